@@ -0,0 +1,320 @@
+//! Compact binary trajectories
+//!
+//! Text and JSON are easy to read but slow to parse on a small controller.
+//! The binary format packs each sample into a fixed number of little-endian
+//! bytes, so a consumer can read it with nothing more than byte offsets.
+//!
+//! A file starts with an 8-byte header:
+//!
+//! | Bytes | Contents                                                           |
+//! |-------|--------------------------------------------------------------------|
+//! | 0–3   | The magic bytes `CMTR`                                             |
+//! | 4     | The format version, currently 1                                    |
+//! | 5     | Flags: bit 0 if samples have a time, bit 1 if they have a velocity |
+//! | 6–7   | Zero                                                               |
+//!
+//! and every sample after it has the same layout:
+//!
+//! | Type       | Contents                                                |
+//! |------------|---------------------------------------------------------|
+//! | `u32`      | Index of the motion the sample is on                    |
+//! | 3 × `f64`  | X, Y and Z                                              |
+//! | `f64`      | Seconds since the start, if the time flag is set        |
+//! | 3 × `f64`  | Velocity along X, Y and Z, if the velocity flag is set  |
+//!
+//! so samples are 28, 36, 52 or 60 bytes long.
+
+use std::io::{self, Read, Write};
+
+use crate::output::Point;
+
+/// The bytes every binary trajectory starts with
+pub const MAGIC: [u8; 4] = *b"CMTR";
+
+/// The version of the format this module reads and writes
+pub const VERSION: u8 = 1;
+
+// Flag bits of the header
+const TIME: u8 = 1;
+const VELOCITY: u8 = 2;
+
+/// Which optional values the samples of a trajectory carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Layout {
+    /// Each sample has the time it's reached
+    pub time: bool,
+    /// Each sample has its velocity along each axis
+    pub velocity: bool,
+}
+
+impl Layout {
+    /// Number of bytes each sample takes
+    pub fn sample_size(self) -> usize {
+        4 + 24 + if self.time { 8 } else { 0 } + if self.velocity { 24 } else { 0 }
+    }
+
+    // The header's flag byte
+    fn flags(self) -> u8 {
+        (if self.time { TIME } else { 0 }) | (if self.velocity { VELOCITY } else { 0 })
+    }
+}
+
+/// One sample of a binary trajectory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// Index of the motion the sample is on
+    pub motion: usize,
+    /// The (x, y, z) position
+    pub position: (f64, f64, f64),
+    /// Seconds since the start of the program, if the trajectory is timed
+    pub time: Option<f64>,
+    /// Velocity along each axis, if the trajectory has them
+    pub velocity: Option<(f64, f64, f64)>,
+}
+
+impl From<&Point> for Sample {
+    fn from(point: &Point) -> Self {
+        Sample {
+            motion: point.motion,
+            position: point.position,
+            time: point.time,
+            velocity: point.dynamics.map(|dynamics| dynamics.velocity),
+        }
+    }
+}
+
+/// Writer of binary trajectories
+#[derive(Debug)]
+pub struct BinaryWriter<W> {
+    writer: W,
+    layout: Layout,
+}
+
+impl<W: Write> BinaryWriter<W> {
+    /// Constructor that writes the header for samples with this layout
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing the header.
+    pub fn new(mut writer: W, layout: Layout) -> io::Result<BinaryWriter<W>> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, layout.flags(), 0, 0])?;
+        Ok(BinaryWriter { writer, layout })
+    }
+
+    /// Function to write one sample
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error if the sample lacks a value the layout
+    /// needs or its motion index doesn't fit in 32 bits, or any error writing it.
+    pub fn write(&mut self, sample: &Sample) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+        let motion = u32::try_from(sample.motion).map_err(|_| invalid("motion index doesn't fit in 32 bits"))?;
+
+        let mut bytes = Vec::with_capacity(self.layout.sample_size());
+        bytes.extend(motion.to_le_bytes());
+        let (x, y, z) = sample.position;
+        bytes.extend([x, y, z].iter().flat_map(|value| value.to_le_bytes()));
+        if self.layout.time {
+            let time = sample.time.ok_or_else(|| invalid("sample has no time"))?;
+            bytes.extend(time.to_le_bytes());
+        }
+        if self.layout.velocity {
+            let (vx, vy, vz) = sample.velocity.ok_or_else(|| invalid("sample has no velocity"))?;
+            bytes.extend([vx, vy, vz].iter().flat_map(|value| value.to_le_bytes()));
+        }
+        self.writer.write_all(&bytes)
+    }
+
+    /// Function to flush the samples written and give the writer back
+    ///
+    /// # Errors
+    ///
+    /// Returns any error flushing the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Function to write points as a binary trajectory
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if a point lacks a value the layout needs,
+/// or any error writing.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::binary::{write_binary, BinaryReader, Layout};
+/// use project_2::motion::Motion;
+/// use project_2::output::geometric_points;
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (2.0, 0.0, 0.0))];
+/// let points = geometric_points(&motions, &Default::default());
+/// let mut bytes = Vec::new();
+/// write_binary(&mut bytes, points, Layout::default()).unwrap();
+/// assert_eq!(bytes.len(), 8 + 4 * 28);
+///
+/// let samples: Vec<_> = BinaryReader::new(bytes.as_slice()).unwrap().collect::<Result<_, _>>().unwrap();
+/// assert_eq!(samples[3].position, (2.0, 0.0, 0.0));
+/// ```
+pub fn write_binary<W: Write>(writer: W, points: impl IntoIterator<Item = Point>, layout: Layout) -> io::Result<()> {
+    let mut writer = BinaryWriter::new(writer, layout)?;
+    for point in points {
+        writer.write(&Sample::from(&point))?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Reader of binary trajectories, iterating over their samples
+#[derive(Debug)]
+pub struct BinaryReader<R> {
+    reader: R,
+    layout: Layout,
+    // Whether an error has ended the samples
+    failed: bool,
+}
+
+impl<R: Read> BinaryReader<R> {
+    /// Constructor that reads and checks the header
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the header isn't one this module
+    /// writes, or any error reading it.
+    pub fn new(mut reader: R) -> io::Result<BinaryReader<R>> {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        if header[..4] != MAGIC {
+            return Err(invalid(String::from("not a binary trajectory")));
+        }
+        if header[4] != VERSION {
+            return Err(invalid(format!("unsupported binary trajectory version {}", header[4])));
+        }
+        let flags = header[5];
+        if flags & !(TIME | VELOCITY) != 0 || header[6..] != [0, 0] {
+            return Err(invalid(String::from("unknown flags in binary trajectory header")));
+        }
+        let layout = Layout {
+            time: flags & TIME != 0,
+            velocity: flags & VELOCITY != 0,
+        };
+        Ok(BinaryReader {
+            reader,
+            layout,
+            failed: false,
+        })
+    }
+
+    /// Which optional values the samples carry
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    // Read the next sample, or `None` at the end of the samples
+    fn read_sample(&mut self) -> io::Result<Option<Sample>> {
+        let mut bytes = vec![0; self.layout.sample_size()];
+        // Only a sample that hasn't started yet can end the trajectory
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "trajectory ends part way through a sample"))
+                }
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut values = bytes[4..].chunks_exact(8).map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()));
+        let mut next = || values.next().expect("the layout sized the sample");
+        let motion = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+        let position = (next(), next(), next());
+        let time = self.layout.time.then(&mut next);
+        let velocity = self.layout.velocity.then(|| (next(), next(), next()));
+        Ok(Some(Sample {
+            motion,
+            position,
+            time,
+            velocity,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for BinaryReader<R> {
+    type Item = io::Result<Sample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.read_sample();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that timed samples with velocities come back exactly as they were written.
+    #[test]
+    fn test_round_trip() {
+        let layout = Layout { time: true, velocity: true };
+        let samples = [
+            Sample {
+                motion: 0,
+                position: (1.0, -2.5, 0.1),
+                time: Some(0.0),
+                velocity: Some((0.0, 0.0, 0.0)),
+            },
+            Sample {
+                motion: 7,
+                position: (1.0 / 3.0, 1e9, -0.0),
+                time: Some(12.25),
+                velocity: Some((10.0, -5.0, 0.5)),
+            },
+        ];
+        let mut writer = BinaryWriter::new(Vec::new(), layout).unwrap();
+        for sample in &samples {
+            writer.write(sample).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+        assert_eq!(bytes[..8], [b'C', b'M', b'T', b'R', 1, 3, 0, 0]);
+        assert_eq!(bytes.len(), 8 + 2 * 60);
+        assert_eq!(bytes[8 + 60..8 + 64], 7u32.to_le_bytes());
+
+        let reader = BinaryReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.layout(), layout);
+        let read: Vec<Sample> = reader.collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, samples);
+
+        // A sample missing a value the layout needs can't be written
+        let mut writer = BinaryWriter::new(Vec::new(), layout).unwrap();
+        let untimed = Sample { time: None, ..samples[0] };
+        assert_eq!(writer.write(&untimed).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// Test that bad headers and cut-off samples are reported.
+    #[test]
+    fn test_invalid() {
+        assert!(BinaryReader::new(&b"CMTX\x01\x00\x00\x00"[..]).is_err());
+        assert!(BinaryReader::new(&b"CMTR\x02\x00\x00\x00"[..]).is_err());
+        assert!(BinaryReader::new(&b"CMTR\x01\x04\x00\x00"[..]).is_err());
+
+        let mut bytes = BinaryWriter::new(Vec::new(), Layout::default()).unwrap().finish().unwrap();
+        assert_eq!(BinaryReader::new(bytes.as_slice()).unwrap().count(), 0);
+        bytes.extend([0; 10]);
+        let mut reader = BinaryReader::new(bytes.as_slice()).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.next().is_none());
+    }
+}
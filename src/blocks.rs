@@ -0,0 +1,344 @@
+//! Loops and subroutines of the command language
+//!
+//! `REPEAT n ... END` and `SUB name ... ENDSUB` / `CALL name` are expanded
+//! before any motion is parsed, by replaying the lines they contain. Each
+//! replayed line keeps the line (and file) it was written on, so errors and
+//! motion attribution still point at the source text.
+//!
+//! Blocks may be nested, and subroutines may call each other (but not
+//! themselves) and may be called before they are defined.
+//!
+//! A few nested loops can make a short program enormous, so the expansion
+//! stops with an error once the replayed lines would take up more than
+//! [`ParseOptions::max_expansion`](crate::parser::ParseOptions::max_expansion)
+//! bytes.
+
+use std::collections::HashMap;
+
+use crate::error::{CmmdError, ParseError, Span};
+use crate::parser::{expect_arguments, strip_comments, tokenize};
+use crate::source::SourceLine;
+
+/// A piece of a program after its block structure is known
+#[derive(Debug, Clone)]
+enum Item {
+    /// An ordinary line, parsed later
+    Line(SourceLine),
+    /// Lines to run `count` times, opened on `line`
+    Repeat {
+        line: SourceLine,
+        span: Span,
+        count: usize,
+        body: Vec<Item>,
+    },
+    /// A call of a named subroutine
+    Call { line: SourceLine, span: Span, name: String },
+}
+
+/// Subroutine bodies by name
+type Subroutines = HashMap<String, Vec<Item>>;
+
+/// A block that has been opened but not yet closed
+struct Frame {
+    line: Option<SourceLine>,
+    span: Span,
+    command: String,
+    kind: FrameKind,
+    items: Vec<Item>,
+}
+
+enum FrameKind {
+    Top,
+    Repeat(usize),
+    Sub(String),
+}
+
+// Parse a repeat count, which has to be a whole number whatever the parse options
+fn repeat_count(line_number: usize, text: &str, span: Span) -> Result<usize, ParseError> {
+    text.parse().map_err(|_| ParseError::InvalidNumber {
+        line: line_number,
+        span,
+        text: text.to_string(),
+    })
+}
+
+// Work out the block structure of a program, collecting subroutines as they are defined
+fn structure(lines: Vec<SourceLine>) -> Result<(Vec<Item>, Subroutines), CmmdError> {
+    let mut subroutines = HashMap::new();
+    let mut stack = vec![Frame {
+        line: None,
+        span: Span(0..0),
+        command: String::new(),
+        kind: FrameKind::Top,
+        items: Vec::new(),
+    }];
+
+    for line in lines {
+        let line_number = line.number;
+        let stripped = strip_comments(&line.text);
+        let tokens = tokenize(&stripped);
+        let keyword = tokens.first().map(|token| token.text).unwrap_or_default();
+
+        match keyword {
+            "REPEAT" | "SUB" => {
+                expect_arguments(line_number, &stripped, &tokens, 1).map_err(|e| line.error(e))?;
+                let kind = if keyword == "REPEAT" {
+                    let count = repeat_count(line_number, tokens[1].text, tokens[1].span());
+                    FrameKind::Repeat(count.map_err(|e| line.error(e))?)
+                } else {
+                    FrameKind::Sub(tokens[1].text.to_string())
+                };
+                let span = Span(tokens[0].start..stripped.trim_end().len());
+                let command = stripped.trim().to_string();
+                stack.push(Frame {
+                    line: Some(line.clone()),
+                    span,
+                    command,
+                    kind,
+                    items: Vec::new(),
+                });
+            }
+            "END" | "ENDSUB" => {
+                // The keyword has to close the innermost block
+                let closes = match stack.last().map(|frame| &frame.kind) {
+                    Some(FrameKind::Repeat(_)) => keyword == "END",
+                    Some(FrameKind::Sub(_)) => keyword == "ENDSUB",
+                    _ => false,
+                };
+                if !closes {
+                    return Err(line.error(ParseError::UnmatchedEnd {
+                        line: line_number,
+                        span: tokens[0].span(),
+                        command: keyword.to_string(),
+                    }));
+                }
+                let frame = stack.pop().expect("an open block");
+                match frame.kind {
+                    FrameKind::Repeat(count) => stack.last_mut().expect("the top level").items.push(Item::Repeat {
+                        line: frame.line.expect("an opened block"),
+                        span: frame.span,
+                        count,
+                        body: frame.items,
+                    }),
+                    FrameKind::Sub(name) => {
+                        subroutines.insert(name, frame.items);
+                    }
+                    FrameKind::Top => unreachable!("the top level is never closed"),
+                }
+            }
+            "CALL" => {
+                expect_arguments(line_number, &stripped, &tokens, 1).map_err(|e| line.error(e))?;
+                let (span, name) = (tokens[1].span(), tokens[1].text.to_string());
+                stack.last_mut().expect("the top level").items.push(Item::Call { line, span, name });
+            }
+            _ => stack.last_mut().expect("the top level").items.push(Item::Line(line)),
+        }
+    }
+
+    // Only the top level may be left open
+    let frame = stack.pop().expect("the top level");
+    match frame.line {
+        None => Ok((frame.items, subroutines)),
+        Some(line) => Err(line.error(ParseError::UnclosedBlock {
+            line: line.number,
+            span: frame.span,
+            command: frame.command,
+        })),
+    }
+}
+
+// What is left of the expansion budget, and the outermost loop or call being replayed, which gets the blame
+struct Budget<'a> {
+    bytes: usize,
+    limit: usize,
+    block: Option<(&'a SourceLine, &'a Span)>,
+}
+
+impl<'a> Budget<'a> {
+    // Spend some of the budget on `line`, or on the block it is replayed by
+    fn spend(&mut self, bytes: usize, line: &SourceLine, span: &Span) -> Result<(), CmmdError> {
+        if bytes > self.bytes {
+            let (line, span) = self.block.unwrap_or((line, span));
+            return Err(line.error(ParseError::ExpansionTooLarge {
+                line: line.number,
+                span: span.clone(),
+                limit: self.limit,
+            }));
+        }
+        self.bytes -= bytes;
+        Ok(())
+    }
+
+    // Replay a block's body, blaming the block if it's the outermost one
+    fn within(
+        &mut self,
+        line: &'a SourceLine,
+        span: &'a Span,
+        replay: impl FnOnce(&mut Self) -> Result<(), CmmdError>,
+    ) -> Result<(), CmmdError> {
+        let outermost = self.block.is_none();
+        if outermost {
+            self.block = Some((line, span));
+        }
+        let result = replay(self);
+        if outermost {
+            self.block = None;
+        }
+        result
+    }
+}
+
+// Replay items into plain lines, following calls
+fn unroll<'a>(
+    items: &'a [Item],
+    subroutines: &'a Subroutines,
+    calls: &mut Vec<&'a str>,
+    lines: &mut Vec<SourceLine>,
+    budget: &mut Budget<'a>,
+) -> Result<(), CmmdError> {
+    for item in items {
+        match item {
+            Item::Line(line) => {
+                // A line takes up its text and its own record
+                let span = Span(0..line.text.trim_end().len());
+                budget.spend(line.text.len() + std::mem::size_of::<SourceLine>(), line, &span)?;
+                lines.push(line.clone());
+            }
+            Item::Repeat { line, span, count, body } => budget.within(line, span, |budget| {
+                for _ in 0..*count {
+                    // Even a pass over an empty body costs something, so empty loops can't run forever
+                    budget.spend(1, line, span)?;
+                    unroll(body, subroutines, calls, lines, budget)?;
+                }
+                Ok(())
+            })?,
+            Item::Call { line, span, name } => {
+                let Some(body) = subroutines.get(name) else {
+                    return Err(line.error(ParseError::UnknownSubroutine {
+                        line: line.number,
+                        span: span.clone(),
+                        name: name.clone(),
+                    }));
+                };
+                if calls.contains(&name.as_str()) {
+                    return Err(line.error(ParseError::RecursiveCall {
+                        line: line.number,
+                        span: span.clone(),
+                        name: name.clone(),
+                    }));
+                }
+                calls.push(name);
+                budget.within(line, span, |budget| unroll(body, subroutines, calls, lines, budget))?;
+                calls.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Function to expand the loops and subroutine calls of a program
+///
+/// # Arguments
+///
+/// * `lines` - The program's lines, included files already spliced in
+/// * `limit` - Most bytes the expanded lines may take up
+///
+/// # Returns
+///
+/// The lines to parse, in the order they run. Block keywords and subroutine
+/// definitions are left out.
+///
+/// # Errors
+///
+/// Returns a parse error for unclosed or mismatched blocks, repeat counts
+/// that aren't whole numbers, calls of unknown or recursive subroutines, and
+/// programs that would grow past `limit`.
+pub(crate) fn expand(lines: Vec<SourceLine>, limit: usize) -> Result<Vec<SourceLine>, CmmdError> {
+    let (items, subroutines) = structure(lines)?;
+    let mut expanded = Vec::new();
+    let mut budget = Budget {
+        bytes: limit,
+        limit,
+        block: None,
+    };
+    unroll(&items, &subroutines, &mut Vec::new(), &mut expanded, &mut budget)?;
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Number the lines of a program from one
+    fn numbered(program: &str) -> Vec<SourceLine> {
+        program.lines().enumerate().map(|(index, line)| SourceLine::new(index + 1, line)).collect()
+    }
+
+    // Expand a program and keep only the line numbers
+    fn line_numbers(program: &str) -> Result<Vec<usize>, ParseError> {
+        match expand(numbered(program), 1 << 20) {
+            Ok(lines) => Ok(lines.into_iter().map(|line| line.number).collect()),
+            Err(CmmdError::Parse(e)) => Err(e),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    /// Test nested loops and calls, including a call before the definition.
+    #[test]
+    fn test_expand() {
+        let program = "\
+CALL hole
+REPEAT 2
+  LIN X1 Y0 Z0
+  REPEAT 2 ; inner
+    CALL hole
+  END
+END
+SUB hole
+  LIN X0 Y0 Z-1
+  LIN X0 Y0 Z0
+ENDSUB";
+        assert_eq!(line_numbers(program).unwrap(), vec![9, 10, 3, 9, 10, 9, 10, 3, 9, 10, 9, 10]);
+    }
+
+    /// Test that broken block structure is reported where it happens.
+    #[test]
+    fn test_block_errors() {
+        assert!(matches!(
+            line_numbers("REPEAT 2\nLIN X1 Y0 Z0"),
+            Err(ParseError::UnclosedBlock { line: 1, .. })
+        ));
+        assert!(matches!(
+            line_numbers("SUB a\nEND"),
+            Err(ParseError::UnmatchedEnd { line: 2, .. })
+        ));
+        assert!(matches!(
+            line_numbers("REPEAT two\nEND"),
+            Err(ParseError::InvalidNumber { line: 1, .. })
+        ));
+        assert!(matches!(
+            line_numbers("CALL missing"),
+            Err(ParseError::UnknownSubroutine { line: 1, .. })
+        ));
+        assert!(matches!(
+            line_numbers("SUB a\nCALL b\nENDSUB\nSUB b\nCALL a\nENDSUB\nCALL a"),
+            Err(ParseError::RecursiveCall { line: 5, .. })
+        ));
+    }
+
+    /// Test that loops can't expand a program without limit, even when their bodies are empty.
+    #[test]
+    fn test_expansion_limit() {
+        let program = "LIN X0 Y0 Z0\nREPEAT 1000\nREPEAT 1000\nREPEAT 1000\nLIN X1 Y0 Z0\nEND\nEND\nEND";
+        assert!(matches!(
+            line_numbers(program),
+            Err(ParseError::ExpansionTooLarge { line: 2, limit: 1048576, .. })
+        ));
+        let program = "REPEAT 1000000000\nREPEAT 1000000000\nEND\nEND";
+        assert!(matches!(line_numbers(program), Err(ParseError::ExpansionTooLarge { line: 1, .. })));
+        // A long program is blamed on its own line
+        let err = expand(numbered("LIN X0 Y0 Z0\nLIN X1 Y0 Z0"), 100).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::ExpansionTooLarge { line: 2, .. })));
+        assert_eq!(expand(numbered("REPEAT 3\nLIN X0 Y0 Z0\nEND"), 1 << 10).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,1470 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+#[cfg(feature = "scripting")]
+use std::sync::Arc;
+
+use project_2::interpolate::{ArcStep, InterpolationOptions};
+use project_2::drawing::DrawingOptions;
+use project_2::heightmap::HeightMap;
+use project_2::kinematics::{Elbow, Kinematics};
+use project_2::machine::MachineConfig;
+use project_2::optimize::Blend;
+use project_2::output::Format;
+use project_2::parser::{Dialect, Extensions, ParseOptions};
+use project_2::planner::PlannerOptions;
+use project_2::post::Flavor;
+use project_2::raster::RasterOptions;
+#[cfg(feature = "scripting")]
+use project_2::script::Script;
+use project_2::stream::{Checkpoint, Framing};
+use project_2::transform::{Axis, Grid, Restart, Transform};
+
+/// Usage text printed when the arguments don't make sense
+pub const USAGE: &str = "\
+Usage: project-2 [command] [options] <filename.cmmd|.txt|.gcode|.nc|.ngc|.tap|.json|.hpgl|.plt|->
+
+Commands:
+    (none)                        Print the interpolated positions
+    plot [-o <file.svg>]          Render the XY path as SVG (to stdout by default)
+    convert <in> [<out>]          Write the program as .cmmd, G-code, .json or HP-GL, by the extension of <out>
+                                  (or -o <out>; G-code to stdout by default)
+            [--flavor <name>]     Write the G-code for a grbl, marlin or linuxcnc controller (raster and import too)
+    raster <image.pgm> [<out>]    Engrave a grayscale image with a laser, line by line, writing the program
+                                  like convert does
+           [--pixel <units>]      Size of each pixel and distance between lines, 0.1 by default
+           [--power <S>]          Laser power for black pixels, 1000 by default (white ones get none)
+           [--feed <units/min>]   Feed rate of the lines, 3000 by default
+           [--overscan <units>]   Run each line on this far past the image with the laser off, 0 by default
+    import <drawing.svg|.hpgl> [<out>]
+                                  Turn the paths of a drawing or plot into a program, writing it like convert does
+           [--depth <z>]          Height to draw or cut at, 0 by default
+           [--clearance <z>]      Height to travel between strokes at, 5 by default
+           [--feed <units/min>]   Feed rate of the strokes, 1000 by default
+           [--power <S>]          Spindle speed or laser power of the strokes, none by default
+    view                          Browse the XY path in the terminal, motion by motion (needs the tui feature)
+    stream --device <port>        Send the program to a GRBL controller (needs the serial feature)
+           [--baud <rate>]        Serial speed, 115200 by default
+           [--checkpoint <file>]  Keep where the job got to in this file, for --resume
+           [--line-numbers]       Number each line, sending it again if the controller asks for it
+           [--checksum]           Number each line and add a *checksum, as Marlin checks them
+           [--flavor <name>]      Write the G-code for a generic, marlin or linuxcnc controller, grbl by default
+                                  (type ! to hold, ~ to resume, + or - or 10-200% to override the feed)
+    play                          Print the timed positions as the machine reaches them, in real time,
+                                  with the same feed hold and override controls as stream
+    serve [--port <port>]         Interpret programs sent over TCP (port 7878 by default)
+          [--bind <address>]      Listen on this address, 127.0.0.1 (this machine only) by default
+    repl                          Interpret commands typed one at a time, printing each motion's positions
+    steps [--tick <s>]            Print when each motor steps, or its steps per tick (needs steps_per_mm)
+    stats                         Print path length, bounding box, motion counts and run time
+    check                         Validate the program without output, exiting with 1 on problems
+    verify --expected <file>      Compare the positions with stored --format json, jsonl or binary output,
+           [--within <units>]     to within this much (0.000001 by default), exiting with 1 on the first difference
+    decode <file.bin|->           Print a --format binary trajectory as text
+    simulate -o <file.stl|.pgm>   Cut the program out of stock and write the surface as an STL mesh or PGM image
+             --tool-diameter <d>  Diameter of the flat-bottomed tool
+             [--cell <units>]     Width of each cell of the stock, a tenth of the tool by default
+             [--stock-top <z>]    Height of the top of the stock, 0 by default
+    diff <old> <new>              List the motions removed, added or moved between two versions of a program,
+           [--within <units>]     ignoring any within this much (0.001 by default), exiting with 1 if any
+
+Options:
+    --plot <file.png>             Also draw the XY path as a PNG (needs the png feature)
+    --strict                      Reject unparsable numbers
+    --lathe                       Read X as a diameter, forbid Y and arc in XZ, as lathes do
+    --units mm|inch               Units of programs that don't say (mm or the machine's by default)
+    --wcs <n>=<x>,<y>,<z>         Define work offset n (in mm) for USE WCS n / G54-G59
+    --tools <file.toml>           Tool table for TOOL n
+    --machine <file.toml>         Machine profile: travel, speed and acceleration limits
+    --enforce-limits              Stop instead of warning when the program leaves the machine's travel
+    --height-map <file.toml>      Probed stock heights to add to the Z of every position
+    --script <file.rhai>          Rhai hooks that change each motion and printed position (needs the scripting feature)
+    --extension <ext>=<dialect>   Also accept files ending in .ext, read as cmmd, gcode, json or hpgl
+    --force                       Read files with any extension (as cmmd unless listed)
+    --format <name>               Output format: text, json, jsonl or binary (packed samples)
+    --timed                       Print timestamped positions
+    --sample-rate <Hz>            Print timed positions once per servo period, at this rate
+    --dynamics                    Add speed, velocity and acceleration (units/s, units/s²) to timed positions
+    --kinematics <name>           Print motor positions instead of cartesian ones: cartesian, corexy or scara
+    --arms <inner>,<outer>        Link lengths of a SCARA arm
+    --elbow left|right            Which way a SCARA arm's elbow bends (right by default)
+    --parallel                    Interpolate untimed positions on every CPU core
+    --watch                       Run again whenever the program file changes, until stopped
+    --progress                    Show a progress bar with the line reached and the time left on stderr
+    --low-memory                  Parse and print one line at a time (no INCLUDE, REPEAT, SUB or COMP)
+    --accel <units/s²>            Plan timing with an acceleration limit
+    --junction-deviation <units>  How far corners may be rounded off at speed
+    --rapid-rate <mm/min>         Speed of RAP/G0 moves (5000 or the machine's by default)
+    --pause-time <s>              Count each PAUSE/M0/M1 as this long in run times (the machine's, or 0)
+    --step <units>                Spacing of linear motion points
+    --angle-step <deg>            Angle between arc points
+    --tolerance <units>           Largest distance between arc or spline chords and the true curve
+    --translate <x>,<y>,<z>       Move the whole program by this much
+    --rotate-z <deg>              Turn the whole program counter-clockwise about Z
+    --scale <factor>              Scale the whole program about the origin
+    --mirror x|y|z                Mirror the whole program across an axis (repeatable)
+    --array <n>x<m>               Repeat the program n times along X and m times along Y
+    --pitch <dx>,<dy>             Distance between the copies --array makes
+    --simplify <units>            Merge runs of straight cuts into fewer ones within this distance
+    --fit-arcs <units>            Replace runs of straight cuts that follow a circle with arcs
+    --fillet <radius>             Round off corners between straight cuts with arcs of this radius
+    --fillet-deviation <units>    Round off corners with the largest arcs this close to the corner
+    --reorder                     Cut separate parts of the program in the order with the least rapid travel
+    --safe-z <z>                  Lift to this height between parts instead of dragging through the work
+    --start-line <n>              Pick the program up at line n, travelling there over the top of the part
+    --start-motion <n>            Pick the program up at motion n (counted from zero) instead
+    --resume <file>               Pick the program up where a stream --checkpoint file says it got to";
+
+/// What the command line asked to do with the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Print the interpolated positions
+    Run,
+    /// Render the XY path as SVG, to a file or to stdout
+    Plot { output: Option<String> },
+    /// Write the program in another language, to a file or as G-code to stdout
+    Convert { output: Option<String>, dialect: Dialect },
+    /// Engrave a grayscale image, writing the program to a file or as G-code to stdout
+    Raster {
+        output: Option<String>,
+        dialect: Dialect,
+        raster: RasterOptions,
+    },
+    /// Turn the paths of a drawing into a program, writing it to a file or as G-code to stdout
+    Import {
+        output: Option<String>,
+        dialect: Dialect,
+        drawing: DrawingOptions,
+    },
+    /// Browse the XY path in the terminal
+    View,
+    /// Send the program as G-code to a GRBL controller on a serial port
+    Stream {
+        device: String,
+        baud: u32,
+        checkpoint: Option<String>,
+        framing: Framing,
+    },
+    /// Print the timed positions in real time, holding and overriding the feed as stdin says
+    Play,
+    /// Interpret programs sent over TCP instead of reading a file
+    Serve { address: IpAddr, port: u16 },
+    /// Interpret commands typed one at a time instead of reading a file
+    Repl,
+    /// Print when each motor steps, or how many steps it makes in each tick
+    Steps { tick: Option<f64> },
+    /// Print path metrics instead of positions
+    Stats,
+    /// Validate the program without printing positions
+    Check,
+    /// Compare the positions with stored output instead of printing them
+    Verify { expected: String, within: f64 },
+    /// Print a binary trajectory as text instead of reading a program
+    Decode,
+    /// Compare the program with an older version of it instead of printing positions
+    Diff { old: String, within: f64 },
+    /// Cut the program out of stock and write the surface, as an STL mesh or else a PGM image
+    Simulate {
+        output: String,
+        mesh: bool,
+        diameter: f64,
+        cell: f64,
+        top: f64,
+    },
+}
+
+/// Everything the command line asked for
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// What to do with the program
+    pub command: Command,
+    /// Path of the program to run, `-` for stdin, or `None` if none was given
+    pub file_path: Option<String>,
+    /// Accepted file extensions and the dialect each is read as
+    pub extensions: Extensions,
+    /// Read files whose extension isn't accepted instead of rejecting them
+    pub force: bool,
+    /// TOML tool table to load before parsing
+    pub tools: Option<String>,
+    /// Machine profile given with `--machine`
+    pub machine: Option<MachineConfig>,
+    /// Refuse to output programs that leave the machine's travel
+    pub enforce_limits: bool,
+    /// Probed stock heights given with `--height-map`
+    pub height_map: Option<HeightMap>,
+    /// Rhai hooks given with `--script`
+    #[cfg(feature = "scripting")]
+    pub script: Option<Arc<Script>>,
+    /// PNG file to draw the XY path into
+    pub png: Option<String>,
+    /// How the program is parsed
+    pub parse: ParseOptions,
+    /// How motions are split into positions
+    pub interpolation: InterpolationOptions,
+    /// How the output is printed
+    pub format: Format,
+    /// Print timestamped positions instead of geometry
+    pub timed: bool,
+    /// Resample the timed positions at this many per second
+    pub sample_rate: Option<f64>,
+    /// Add velocities and accelerations to the timed positions
+    pub dynamics: bool,
+    /// How the printed positions are converted into motor coordinates
+    pub kinematics: Kinematics,
+    /// Interpolate untimed positions on several threads
+    pub parallel: bool,
+    /// Show a progress bar while positions are written or the program streams
+    pub progress: bool,
+    /// Run again whenever the program file changes
+    pub watch: bool,
+    /// Parse, interpolate and print one motion at a time
+    pub low_memory: bool,
+    /// Plan timing with this acceleration limit instead of jumping to the feed rate
+    pub acceleration: Option<f64>,
+    /// How far the planner may round off corners to keep speed through them
+    pub junction_deviation: f64,
+    /// Where to put the program: mirrored, scaled, turned, then moved
+    pub transform: Option<Transform>,
+    /// Copies of the program to make, once it's transformed
+    pub array: Option<Grid>,
+    /// Merge runs of straight cuts within this distance, once arcs are fitted
+    pub simplify: Option<f64>,
+    /// Replace runs of straight cuts that follow a circle with arcs, before simplifying
+    pub fit_arcs: Option<f64>,
+    /// Round off corners between straight cuts with arcs, after simplifying
+    pub fillet: Option<Blend>,
+    /// Cut the separate parts of the program in the order with the least rapid travel, once it's cleaned up
+    pub reorder: bool,
+    /// Lift to this height between parts that aren't connected, once they're in order
+    pub safe_z: Option<f64>,
+    /// Where to pick the program up, once everything else has been done to it
+    pub restart: Option<Restart>,
+    /// Controller that written G-code is meant for
+    pub flavor: Flavor,
+    /// The checkpoint given with `--resume`, which the restart comes from
+    pub resume: Option<Checkpoint>,
+}
+
+// Parse text as a number, turning down `inf` and `nan`, which no option can use
+fn finite(text: &str) -> Option<f64> {
+    text.trim().parse::<f64>().ok().filter(|number| number.is_finite())
+}
+
+// Parse the value following a flag as a number
+fn number(flag: &str, value: Option<&String>) -> Result<f64, String> {
+    value
+        .and_then(|value| finite(value))
+        .ok_or_else(|| format!("{} needs a number", flag))
+}
+
+// Parse the value following a flag as a positive number
+fn positive(flag: &str, value: Option<&String>) -> Result<f64, String> {
+    match value.and_then(|value| finite(value)) {
+        Some(number) if number > 0.0 => Ok(number),
+        _ => Err(format!("{} needs a positive number", flag)),
+    }
+}
+
+/// Function to parse the command-line arguments (without the program name)
+///
+/// # Errors
+///
+/// Returns a message describing the first argument that doesn't make sense.
+pub fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut parse = ParseOptions::default();
+    let mut interpolation = InterpolationOptions::default();
+    let mut format = Format::default();
+    let mut timed = false;
+    let mut sample_rate = None;
+    let mut dynamics = false;
+    let mut kinematics = Kinematics::default();
+    let mut arms = None;
+    let mut elbow = None;
+    let mut parallel = false;
+    let mut progress = false;
+    let mut watch = false;
+    let mut low_memory = false;
+    let mut acceleration = None;
+    let mut junction_deviation = PlannerOptions::default().junction_deviation;
+    let mut translate = None;
+    let mut rotate = None;
+    let mut scale = None;
+    let mut mirrors = Vec::new();
+    let mut array = None;
+    let mut pitch = None;
+    let mut simplify = None;
+    let mut fit_arcs = None;
+    let mut fillet = None;
+    let mut reorder = false;
+    let mut safe_z = None;
+    let mut output = None;
+    let mut png = None;
+    let mut device = None;
+    let mut baud = None;
+    let mut port = None;
+    let mut bind = None;
+    let mut extensions = Extensions::default();
+    let mut force = false;
+    let mut tools = None;
+    let mut machine = None;
+    let mut height_map = None;
+    let mut script = None;
+    let mut tick = None;
+    let mut expected = None;
+    let mut within = None;
+    let mut diameter = None;
+    let mut cell = None;
+    let mut stock_top = None;
+    let mut pixel = None;
+    let mut power = None;
+    let mut feed = None;
+    let mut overscan = None;
+    let mut depth = None;
+    let mut clearance = None;
+    let mut restart = None;
+    let mut resume = None;
+    let mut checkpoint = None;
+    let mut framing = None;
+    let mut enforce_limits = false;
+    let mut flavor = None;
+    // Flags that take precedence over the machine profile
+    let mut units = None;
+    let mut rapid_rate = None;
+    let mut pause_time = None;
+    let mut paths = Vec::new();
+
+    // The first argument may name a command
+    let (mut command, args) = match args.first().map(String::as_str) {
+        Some("plot") => (Command::Plot { output: None }, &args[1..]),
+        Some("convert") => (
+            Command::Convert {
+                output: None,
+                dialect: Dialect::Gcode,
+            },
+            &args[1..],
+        ),
+        Some("raster") => (
+            Command::Raster {
+                output: None,
+                dialect: Dialect::Gcode,
+                raster: RasterOptions::default(),
+            },
+            &args[1..],
+        ),
+        Some("import") => (
+            Command::Import {
+                output: None,
+                dialect: Dialect::Gcode,
+                drawing: DrawingOptions::default(),
+            },
+            &args[1..],
+        ),
+        Some("view") => (Command::View, &args[1..]),
+        Some("stream") => (
+            Command::Stream {
+                device: String::new(),
+                baud: 115_200,
+                checkpoint: None,
+                framing: Framing::Plain,
+            },
+            &args[1..],
+        ),
+        Some("serve") => (
+            Command::Serve {
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 7878,
+            },
+            &args[1..],
+        ),
+        Some("play") => (Command::Play, &args[1..]),
+        Some("repl") => (Command::Repl, &args[1..]),
+        Some("steps") => (Command::Steps { tick: None }, &args[1..]),
+        Some("stats") => (Command::Stats, &args[1..]),
+        Some("check") => (Command::Check, &args[1..]),
+        Some("verify") => (
+            Command::Verify {
+                expected: String::new(),
+                within: 1e-6,
+            },
+            &args[1..],
+        ),
+        Some("decode") => (Command::Decode, &args[1..]),
+        Some("simulate") => (
+            Command::Simulate {
+                output: String::new(),
+                mesh: true,
+                diameter: 0.0,
+                cell: 0.0,
+                top: 0.0,
+            },
+            &args[1..],
+        ),
+        Some("diff") => (
+            Command::Diff {
+                old: String::new(),
+                within: 0.001,
+            },
+            &args[1..],
+        ),
+        _ => (Command::Run, args),
+    };
+
+    // Separate option flags from the file path
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(rest.next().ok_or("--output needs a file name")?.clone()),
+            "--plot" => png = Some(rest.next().ok_or("--plot needs a file name")?.clone()),
+            "--checkpoint" => checkpoint = Some(rest.next().ok_or("--checkpoint needs a file name")?.clone()),
+            // Checksums need the lines numbered too
+            "--line-numbers" => framing = framing.or(Some(Framing::Numbered)),
+            "--checksum" => framing = Some(Framing::Checksummed),
+            "--device" => device = Some(rest.next().ok_or("--device needs a serial port name")?.clone()),
+            "--baud" => match rest.next().and_then(|value| value.parse::<u32>().ok()) {
+                Some(rate) if rate > 0 => baud = Some(rate),
+                _ => return Err(format!("{} needs a positive whole number", arg)),
+            },
+            "--port" => match rest.next().and_then(|value| value.parse::<u16>().ok()) {
+                Some(number) => port = Some(number),
+                None => return Err(format!("{} needs a port number", arg)),
+            },
+            "--bind" => match rest.next().and_then(|value| value.parse::<IpAddr>().ok()) {
+                Some(address) => bind = Some(address),
+                None => return Err(format!("{} needs an IP address", arg)),
+            },
+            "--strict" => parse.strict = true,
+            "--lathe" => parse.lathe = true,
+            "--wcs" => {
+                let value = rest.next().ok_or("--wcs needs <n>=<x>,<y>,<z>")?;
+                let offset = value.split_once('=').and_then(|(number, coordinates)| {
+                    let number = number.parse::<u32>().ok()?;
+                    let coordinates: Option<Vec<f64>> =
+                        coordinates.split(',').map(finite).collect();
+                    let coordinates = coordinates?;
+                    match coordinates[..] {
+                        [x, y, z] => Some((number, (x, y, z))),
+                        _ => None,
+                    }
+                });
+                let (number, offset) = offset.ok_or_else(|| format!("--wcs expects <n>=<x>,<y>,<z>, got {}", value))?;
+                parse.work_offsets.insert(number, offset);
+            }
+            "--tools" => tools = Some(rest.next().ok_or("--tools needs a file name")?.clone()),
+            "--machine" => machine = Some(rest.next().ok_or("--machine needs a file name")?.clone()),
+            "--enforce-limits" => enforce_limits = true,
+            "--expected" => expected = Some(rest.next().ok_or("--expected needs a file name")?.clone()),
+            "--within" => within = Some(positive(arg, rest.next())?),
+            "--tick" => tick = Some(positive("--tick", rest.next())?),
+            "--tool-diameter" => diameter = Some(positive(arg, rest.next())?),
+            "--cell" => cell = Some(positive(arg, rest.next())?),
+            "--stock-top" => stock_top = Some(number(arg, rest.next())?),
+            "--pixel" => pixel = Some(positive(arg, rest.next())?),
+            "--power" => power = Some(positive(arg, rest.next())?),
+            "--feed" => feed = Some(positive(arg, rest.next())?),
+            "--overscan" => match number(arg, rest.next())? {
+                distance if distance >= 0.0 => overscan = Some(distance),
+                _ => return Err(String::from("--overscan can't be negative")),
+            },
+            "--depth" => depth = Some(number(arg, rest.next())?),
+            "--clearance" => clearance = Some(number(arg, rest.next())?),
+            "--flavor" => flavor = Some(rest.next().ok_or("--flavor needs a controller name")?.parse()?),
+            "--height-map" => height_map = Some(rest.next().ok_or("--height-map needs a file name")?.clone()),
+            "--script" => script = Some(rest.next().ok_or("--script needs a file name")?.clone()),
+            "--units" => units = Some(rest.next().ok_or("--units needs mm or inch")?.parse()?),
+            "--extension" => {
+                let value = rest.next().ok_or("--extension needs an <ext>=<dialect> pair")?;
+                let (extension, dialect) = value
+                    .split_once('=')
+                    .ok_or_else(|| format!("--extension expects <ext>=<dialect>, got {}", value))?;
+                extensions.insert(extension, dialect.parse::<Dialect>()?);
+            }
+            "--force" => force = true,
+            "--format" => format = rest.next().ok_or("--format needs a format name")?.parse()?,
+            "--timed" => timed = true,
+            "--kinematics" => kinematics = rest.next().ok_or("--kinematics needs cartesian, corexy or scara")?.parse()?,
+            "--arms" => {
+                let value = rest.next().ok_or("--arms needs <inner>,<outer>")?;
+                let lengths: Option<Vec<f64>> = value.split(',').map(finite).collect();
+                match lengths.as_deref() {
+                    Some(&[inner, outer]) if inner > 0.0 && outer > 0.0 => arms = Some((inner, outer)),
+                    _ => return Err(format!("--arms expects two positive lengths <inner>,<outer>, got {}", value)),
+                }
+            }
+            "--elbow" => elbow = Some(rest.next().ok_or("--elbow needs left or right")?.parse::<Elbow>()?),
+            "--parallel" => parallel = true,
+            "--progress" => progress = true,
+            "--watch" => watch = true,
+            "--low-memory" => low_memory = true,
+            "--dynamics" => {
+                dynamics = true;
+                timed = true;
+            }
+            "--sample-rate" => {
+                sample_rate = Some(positive(arg, rest.next())?);
+                timed = true;
+            }
+            "--accel" => {
+                acceleration = Some(positive(arg, rest.next())?);
+                timed = true;
+            }
+            "--junction-deviation" => match rest.next().and_then(|value| finite(value)) {
+                Some(deviation) if deviation >= 0.0 => junction_deviation = deviation,
+                _ => return Err(format!("{} needs a number of zero or more", arg)),
+            },
+            "--rapid-rate" => rapid_rate = Some(positive(arg, rest.next())?),
+            "--pause-time" => pause_time = Some(positive(arg, rest.next())?),
+            "--step" => interpolation.linear_step = positive(arg, rest.next())?,
+            "--angle-step" => interpolation.arc_step = ArcStep::Angle(positive(arg, rest.next())?),
+            "--tolerance" => interpolation.arc_step = ArcStep::ChordTolerance(positive(arg, rest.next())?),
+            "--start-line" | "--start-motion" => {
+                let number = rest.next().and_then(|value| value.parse::<usize>().ok());
+                let number = number.ok_or_else(|| format!("{} needs a whole number", arg))?;
+                let from = if arg == "--start-line" { Restart::Line(number) } else { Restart::Motion(number) };
+                if restart.replace(from).is_some() {
+                    return Err(String::from("Give only one of --start-line, --start-motion and --resume"));
+                }
+            }
+            "--resume" => {
+                let path = rest.next().ok_or("--resume needs a checkpoint file")?;
+                let checkpoint =
+                    Checkpoint::load(Path::new(path)).map_err(|e| format!("Error reading checkpoint {}: {}", path, e))?;
+                if restart.replace(Restart::Motion(checkpoint.motion)).is_some() {
+                    return Err(String::from("Give only one of --start-line, --start-motion and --resume"));
+                }
+                resume = Some(checkpoint);
+            }
+            "--translate" => {
+                let value = rest.next().ok_or("--translate needs <x>,<y>,<z>")?;
+                let coordinates: Option<Vec<f64>> = value.split(',').map(finite).collect();
+                match coordinates.as_deref() {
+                    Some(&[x, y, z]) => translate = Some((x, y, z)),
+                    _ => return Err(format!("--translate expects <x>,<y>,<z>, got {}", value)),
+                }
+            }
+            "--rotate-z" => rotate = Some(number(arg, rest.next())?),
+            "--scale" => scale = Some(positive(arg, rest.next())?),
+            "--mirror" => mirrors.push(rest.next().ok_or("--mirror needs x, y or z")?.parse::<Axis>()?),
+            "--array" => {
+                let value = rest.next().ok_or("--array needs <n>x<m>")?;
+                let counts = value
+                    .split_once(['x', 'X'])
+                    .and_then(|(columns, rows)| Some((columns.parse::<usize>().ok()?, rows.parse::<usize>().ok()?)));
+                match counts {
+                    Some((columns, rows)) if columns > 0 && rows > 0 => array = Some((columns, rows)),
+                    _ => return Err(format!("--array expects <n>x<m> with positive counts, got {}", value)),
+                }
+            }
+            "--pitch" => {
+                let value = rest.next().ok_or("--pitch needs <dx>,<dy>")?;
+                let distances: Option<Vec<f64>> = value.split(',').map(finite).collect();
+                match distances.as_deref() {
+                    Some(&[dx, dy]) => pitch = Some((dx, dy)),
+                    _ => return Err(format!("--pitch expects <dx>,<dy>, got {}", value)),
+                }
+            }
+            "--simplify" => simplify = Some(positive(arg, rest.next())?),
+            "--fit-arcs" => fit_arcs = Some(positive(arg, rest.next())?),
+            "--fillet" => fillet = Some(Blend::Radius(positive(arg, rest.next())?)),
+            "--fillet-deviation" => fillet = Some(Blend::Deviation(positive(arg, rest.next())?)),
+            "--reorder" => reorder = true,
+            "--safe-z" => safe_z = Some(number(arg, rest.next())?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    // Check if the correct number of arguments is provided
+    match &mut command {
+        // Programs arrive over the network instead
+        Command::Serve {
+            address,
+            port: serve_port,
+        } => {
+            if !paths.is_empty() {
+                return Err(String::from("serve doesn't take a program file"));
+            }
+            *address = bind.unwrap_or(*address);
+            *serve_port = port.unwrap_or(*serve_port);
+        }
+        _ if port.is_some() => return Err(String::from("--port only applies to serve")),
+        _ if bind.is_some() => return Err(String::from("--bind only applies to serve")),
+        // Commands are typed instead
+        Command::Repl if !paths.is_empty() => return Err(String::from("repl doesn't take a program file")),
+        // Controls are typed instead
+        Command::Play if matches!(paths.last().map(String::as_str), None | Some("-")) => {
+            return Err(String::from("play needs a program file, since it reads controls from stdin"));
+        }
+        // The old version comes first, and the new one is read like any other program
+        Command::Diff { old, .. } => {
+            if paths.len() != 2 {
+                return Err(String::from("diff expects the old and the new program files"));
+            }
+            *old = paths.remove(0);
+        }
+        // The file to write may come after the program instead of with -o
+        Command::Convert { .. } if paths.len() == 2 => {
+            if output.is_some() {
+                return Err(String::from("convert takes its output file after the program or with -o, not both"));
+            }
+            output = paths.pop();
+        }
+        // Likewise for the program an image is engraved with
+        Command::Raster { .. } if paths.len() == 2 => {
+            if output.is_some() {
+                return Err(String::from("raster takes its output file after the image or with -o, not both"));
+            }
+            output = paths.pop();
+        }
+        // Pixels can't be told apart from commands on a terminal
+        Command::Raster { .. } if paths.is_empty() => return Err(String::from("raster needs an image file")),
+        // And a drawing's program likewise
+        Command::Import { .. } if paths.len() == 2 => {
+            if output.is_some() {
+                return Err(String::from("import takes its output file after the drawing or with -o, not both"));
+            }
+            output = paths.pop();
+        }
+        // No file means the program is piped in, which main checks
+        _ if paths.len() > 1 => return Err(String::from("Expected exactly one program file")),
+        _ => {}
+    }
+
+    // Binary output is only written for positions, and only read back as text
+    match command {
+        Command::Decode if format != Format::Text => return Err(String::from("decode only prints text")),
+        Command::Play if format != Format::Text => return Err(String::from("play only prints text")),
+        Command::Run | Command::Decode => {}
+        _ if format == Format::Binary => return Err(String::from("--format binary only applies to printing positions")),
+        _ => {}
+    }
+
+    // Watching needs a file to watch, and a command that finishes
+    if watch {
+        if matches!(
+            command,
+            Command::Stream { .. } | Command::Serve { .. } | Command::Repl | Command::Play | Command::View
+        ) {
+            return Err(String::from("--watch doesn't apply to stream, serve, repl, play or view"));
+        }
+        if matches!(paths.last().map(String::as_str), None | Some("-")) {
+            return Err(String::from("--watch needs a program file"));
+        }
+    }
+
+    // Only some commands write to a file
+    match &mut command {
+        Command::Plot { output: command_output } => *command_output = output,
+        // The language to write is told by the output file's extension
+        Command::Convert {
+            output: command_output,
+            dialect,
+        }
+        | Command::Raster {
+            output: command_output,
+            dialect,
+            ..
+        }
+        | Command::Import {
+            output: command_output,
+            dialect,
+            ..
+        } => {
+            if let Some(path) = &output {
+                *dialect = extensions.dialect_of(path).ok_or_else(|| {
+                    let names = extensions.names().iter().map(|name| format!(".{}", name)).collect::<Vec<_>>();
+                    let names = names.join(", ");
+                    format!("Can't tell what to write {} as; give it one of these extensions: {}", path, names)
+                })?;
+            }
+            *command_output = output;
+        }
+        // The surface is written as a mesh or an image, by the output file's extension
+        Command::Simulate {
+            output: command_output,
+            mesh,
+            diameter: command_diameter,
+            cell: command_cell,
+            top,
+        } => {
+            let path = output.ok_or("simulate needs -o <file.stl> or -o <file.pgm>")?;
+            let extension = Path::new(&path).extension().and_then(|extension| extension.to_str());
+            *mesh = match extension.map(str::to_ascii_lowercase).as_deref() {
+                Some("stl") => true,
+                Some("pgm") => false,
+                _ => return Err(format!("Can't tell what to write {} as; give it a .stl or .pgm extension", path)),
+            };
+            *command_output = path;
+            *command_diameter = diameter.ok_or("simulate needs --tool-diameter")?;
+            *command_cell = cell.unwrap_or(*command_diameter / 10.0);
+            *top = stock_top.unwrap_or(*top);
+        }
+        _ if output.is_some() => {
+            return Err(String::from("--output only applies to plot, convert, raster, import and simulate"))
+        }
+        _ if diameter.is_some() || cell.is_some() || stock_top.is_some() => {
+            return Err(String::from("--tool-diameter, --cell and --stock-top only apply to simulate"))
+        }
+        _ => {}
+    }
+
+    // Only raster engraves images, and only import draws drawings
+    match &mut command {
+        Command::Raster { raster, .. } => {
+            raster.pixel = pixel.unwrap_or(raster.pixel);
+            raster.power = power.unwrap_or(raster.power);
+            raster.feed = feed.unwrap_or(raster.feed);
+            raster.overscan = overscan.unwrap_or(raster.overscan);
+        }
+        _ if pixel.is_some() || overscan.is_some() => {
+            return Err(String::from("--pixel and --overscan only apply to raster"))
+        }
+        Command::Import { drawing, .. } => {
+            drawing.depth = depth.unwrap_or(drawing.depth);
+            drawing.clearance = clearance.unwrap_or(drawing.clearance);
+            drawing.feed = feed.unwrap_or(drawing.feed);
+            drawing.power = power.or(drawing.power);
+        }
+        _ if power.is_some() || feed.is_some() => {
+            return Err(String::from("--power and --feed only apply to raster and import"))
+        }
+        _ if depth.is_some() || clearance.is_some() => {
+            return Err(String::from("--depth and --clearance only apply to import"))
+        }
+        _ => {}
+    }
+    if let Command::Import { drawing, .. } = &command {
+        if drawing.clearance < drawing.depth {
+            return Err(String::from("--clearance can't be below --depth"));
+        }
+    }
+
+    // Only written G-code is meant for a particular controller
+    match &command {
+        Command::Convert { dialect, .. } | Command::Raster { dialect, .. } | Command::Import { dialect, .. }
+            if flavor.is_some() && *dialect != Dialect::Gcode =>
+        {
+            return Err(String::from("--flavor only applies to G-code output"))
+        }
+        Command::Convert { .. } | Command::Raster { .. } | Command::Import { .. } | Command::Stream { .. } => {}
+        _ if flavor.is_some() => {
+            return Err(String::from("--flavor only applies to convert, raster, import and stream"))
+        }
+        _ => {}
+    }
+
+    // Only verifying and diffing have something to compare with
+    match &mut command {
+        Command::Verify {
+            expected: command_expected,
+            within: command_within,
+        } => {
+            *command_expected = expected.ok_or("verify needs an --expected output file")?;
+            *command_within = within.unwrap_or(*command_within);
+        }
+        _ if expected.is_some() => return Err(String::from("--expected only applies to verify")),
+        Command::Diff {
+            within: command_within, ..
+        } => *command_within = within.unwrap_or(*command_within),
+        _ if within.is_some() => return Err(String::from("--within only applies to verify and diff")),
+        _ => {}
+    }
+
+    // Only streaming talks to a serial port
+    match &mut command {
+        Command::Stream {
+            device: stream_device,
+            baud: stream_baud,
+            checkpoint: stream_checkpoint,
+            framing: stream_framing,
+        } => {
+            *stream_device = device.ok_or("stream needs --device")?;
+            *stream_checkpoint = checkpoint;
+            *stream_baud = baud.unwrap_or(*stream_baud);
+            *stream_framing = framing.unwrap_or(*stream_framing);
+        }
+        _ if device.is_some() || baud.is_some() || checkpoint.is_some() => {
+            return Err(String::from("--device, --baud and --checkpoint only apply to stream"))
+        }
+        _ if framing.is_some() => return Err(String::from("--line-numbers and --checksum only apply to stream")),
+        _ => {}
+    }
+
+    // The machine profile fills in whatever the flags didn't set
+    let machine = match machine {
+        Some(path) => Some(
+            MachineConfig::load(Path::new(&path))
+                .map_err(|e| format!("Error reading machine profile {}: {}", path, e))?,
+        ),
+        None => None,
+    };
+    let height_map = match height_map {
+        Some(path) => Some(
+            HeightMap::load(Path::new(&path)).map_err(|e| format!("Error reading height map {}: {}", path, e))?,
+        ),
+        None => None,
+    };
+    // Hooks run on the whole program before anything else, and on every printed position
+    if script.is_some()
+        && (low_memory || matches!(command, Command::Serve { .. } | Command::Repl))
+    {
+        return Err(String::from("--script doesn't apply to serve, repl or --low-memory"));
+    }
+    #[cfg(feature = "scripting")]
+    let script = match script {
+        Some(path) => Some(Arc::new(
+            Script::load(Path::new(&path)).map_err(|e| format!("Error reading script {}: {}", path, e))?,
+        )),
+        None => None,
+    };
+    #[cfg(not(feature = "scripting"))]
+    if script.is_some() {
+        return Err(String::from("--script needs the scripting feature: cargo build --features scripting"));
+    }
+    // Steps are counted with the machine's resolution, which only the profile gives
+    match &mut command {
+        Command::Steps { tick: command_tick } => {
+            let resolved = machine.as_ref().is_some_and(|machine| machine.steps_per_mm().is_some());
+            if !resolved {
+                return Err(String::from("steps needs a --machine profile with steps_per_mm on every axis"));
+            }
+            *command_tick = tick;
+        }
+        _ if tick.is_some() => return Err(String::from("--tick only applies to steps")),
+        _ => {}
+    }
+    if enforce_limits && machine.is_none() {
+        return Err(String::from("--enforce-limits needs a --machine profile"));
+    }
+    // The steps of the transform always apply in the same order
+    let transform = if translate.is_some() || rotate.is_some() || scale.is_some() || !mirrors.is_empty() {
+        let mirrored = mirrors.into_iter().fold(Transform::default(), Transform::mirror);
+        let scaled = mirrored.scale(scale.unwrap_or(1.0));
+        Some(scaled.rotate_z(rotate.unwrap_or(0.0)).translate(translate.unwrap_or((0.0, 0.0, 0.0))))
+    } else {
+        None
+    };
+    // Copies need to know how far apart to go, unless there's only one along an axis
+    let array = match (array, pitch) {
+        (Some((columns, rows)), Some(pitch)) => Some(Grid { columns, rows, pitch }),
+        (Some((1, 1)), None) => None,
+        (Some(_), None) => return Err(String::from("--array needs a --pitch")),
+        (None, Some(_)) => return Err(String::from("--pitch only applies to --array")),
+        (None, None) => None,
+    };
+    // A SCARA arm needs its link lengths, which mean nothing to other machines
+    let kinematics = match (kinematics, arms) {
+        (Kinematics::Scara { .. }, Some((inner, outer))) => Kinematics::Scara {
+            inner,
+            outer,
+            elbow: elbow.unwrap_or_default(),
+        },
+        (Kinematics::Scara { .. }, None) => return Err(String::from("--kinematics scara needs --arms")),
+        (_, Some(_)) => return Err(String::from("--arms only applies to --kinematics scara")),
+        (_, None) if elbow.is_some() => return Err(String::from("--elbow only applies to --kinematics scara")),
+        (kinematics, None) => kinematics,
+    };
+    // Everything else needs the whole program at once
+    if low_memory
+        && (command != Command::Run
+            || timed
+            || parallel
+            || enforce_limits
+            || png.is_some()
+            || transform.is_some()
+            || array.is_some()
+            || simplify.is_some()
+            || fit_arcs.is_some()
+            || fillet.is_some()
+            || reorder
+            || safe_z.is_some()
+            || restart.is_some()
+            || progress
+            || matches!(kinematics, Kinematics::Scara { .. })
+            || format == Format::Json
+            || format == Format::Binary)
+    {
+        return Err(String::from("--low-memory only prints untimed positions as text or jsonl"));
+    }
+    // Each typed motion is printed before the next is known, as with --low-memory
+    if command == Command::Repl
+        && (timed
+            || transform.is_some()
+            || array.is_some()
+            || simplify.is_some()
+            || fit_arcs.is_some()
+            || fillet.is_some()
+            || reorder
+            || safe_z.is_some()
+            || restart.is_some()
+            || progress
+            || format == Format::Json
+            || format == Format::Binary)
+    {
+        return Err(String::from("repl only prints untimed positions as text or jsonl"));
+    }
+    // Both versions are compared as written
+    if matches!(command, Command::Diff { .. })
+        && (timed
+            || transform.is_some()
+            || array.is_some()
+            || simplify.is_some()
+            || fit_arcs.is_some()
+            || fillet.is_some()
+            || reorder
+            || safe_z.is_some()
+            || restart.is_some()
+            || resume.is_some()
+            || script.is_some()
+            || format != Format::Text)
+    {
+        return Err(String::from("diff compares programs as written, and only prints text"));
+    }
+    let profile = machine.clone().unwrap_or_default();
+    parse.units = units.or(profile.units).unwrap_or_default();
+    interpolation.rapid_rate = rapid_rate.or(profile.rapid_rate).unwrap_or(interpolation.rapid_rate);
+    interpolation.pause_time = pause_time.or(profile.pause_time).unwrap_or(interpolation.pause_time);
+
+    // The streamer is written for GRBL, so that's what it sends to unless told otherwise
+    let flavor = match (flavor, &command) {
+        (Some(flavor), _) => flavor,
+        (None, Command::Stream { .. }) => Flavor::Grbl,
+        (None, _) => Flavor::Generic,
+    };
+
+    Ok(Options {
+        command,
+        file_path: paths.pop(),
+        extensions,
+        force,
+        tools,
+        machine,
+        enforce_limits,
+        height_map,
+        #[cfg(feature = "scripting")]
+        script,
+        png,
+        parse,
+        interpolation,
+        format,
+        timed,
+        sample_rate,
+        dynamics,
+        kinematics,
+        parallel,
+        progress,
+        watch,
+        low_memory,
+        acceleration,
+        junction_deviation,
+        transform,
+        array,
+        simplify,
+        fit_arcs,
+        fillet,
+        reorder,
+        safe_z,
+        restart,
+        flavor,
+        resume,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use project_2::parser::Units;
+
+    // Turn string literals into owned arguments
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// Test that resolution flags end up in the interpolation options.
+    #[test]
+    fn test_resolution_flags() {
+        let options = parse_args(&args(&["--step", "0.5", "--angle-step", "2", "part.cmmd"])).unwrap();
+        assert_eq!(options.file_path.as_deref(), Some("part.cmmd"));
+        assert_eq!(options.interpolation.linear_step, 0.5);
+        assert_eq!(options.interpolation.arc_step, ArcStep::Angle(2.0));
+
+        assert!(parse_args(&args(&["--step", "-1", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--step", "0", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--tolerance", "0", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--accel", "100", "part.cmmd"])).unwrap().timed);
+        let options = parse_args(&args(&["--sample-rate", "1000", "part.cmmd"])).unwrap();
+        assert!(options.timed && options.sample_rate == Some(1000.0));
+        assert!(parse_args(&args(&["--sample-rate", "0", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--dynamics", "part.cmmd"])).unwrap().timed);
+        assert!(parse_args(&args(&["--parallel", "part.cmmd"])).unwrap().parallel);
+        assert!(parse_args(&args(&["--progress", "part.cmmd"])).unwrap().progress);
+        assert!(parse_args(&args(&["--progress", "--low-memory", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["plot", "--watch", "-o", "part.svg", "part.cmmd"])).unwrap().watch);
+        assert!(parse_args(&args(&["--watch", "-"])).is_err());
+        assert!(parse_args(&args(&["serve", "--watch"])).is_err());
+        assert!(parse_args(&args(&["--script", "hooks.rhai", "--low-memory", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["repl", "--script", "hooks.rhai"])).is_err());
+        assert!(parse_args(&args(&["--low-memory", "--format", "jsonl", "part.nc"])).unwrap().low_memory);
+        assert!(parse_args(&args(&["--low-memory", "--timed", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["stats", "--low-memory", "part.cmmd"])).is_err());
+        let options = parse_args(&args(&["--fillet-deviation", "0.05", "part.cmmd"])).unwrap();
+        assert_eq!(options.fillet, Some(Blend::Deviation(0.05)));
+        let options = parse_args(&args(&["--start-line", "120", "part.cmmd"])).unwrap();
+        assert_eq!(options.restart, Some(Restart::Line(120)));
+        assert!(parse_args(&args(&["--start-line", "3", "--start-motion", "4", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--fillet", "0", "part.cmmd"])).is_err());
+        assert_eq!(parse_args(&args(&["--simplify", "0.01", "part.nc"])).unwrap().simplify, Some(0.01));
+        assert!(parse_args(&args(&["--low-memory", "--simplify", "0.01", "part.nc"])).is_err());
+        assert_eq!(parse_args(&args(&["--fit-arcs", "0.02", "part.nc"])).unwrap().fit_arcs, Some(0.02));
+        assert!(parse_args(&args(&["--reorder", "part.nc"])).unwrap().reorder);
+        assert!(parse_args(&args(&["repl", "--reorder"])).is_err());
+        assert_eq!(parse_args(&args(&["--safe-z", "5", "part.nc"])).unwrap().safe_z, Some(5.0));
+        assert!(parse_args(&args(&["--safe-z", "high", "part.nc"])).is_err());
+        // Infinities and NaN parse as floats, but no option can use them
+        let bad = [("--safe-z", "nan"), ("--step", "inf"), ("--tolerance", "NaN"), ("--translate", "1,inf,0")];
+        for (flag, value) in bad {
+            assert!(parse_args(&args(&[flag, value, "part.nc"])).is_err(), "{} {}", flag, value);
+        }
+        assert!(parse_args(&args(&["--step"])).is_err());
+        let options = parse_args(&args(&["--rapid-rate", "3000", "part.cmmd"])).unwrap();
+        assert_eq!(options.interpolation.rapid_rate, 3000.0);
+        assert!(parse_args(&args(&["a.cmmd", "b.cmmd"])).is_err());
+    }
+
+    /// Test the plot command and its output file.
+    #[test]
+    fn test_plot_command() {
+        let options = parse_args(&args(&["plot", "part.cmmd", "-o", "part.svg"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Plot {
+                output: Some(String::from("part.svg"))
+            }
+        );
+        assert_eq!(parse_args(&args(&["part.cmmd"])).unwrap().command, Command::Run);
+        assert!(parse_args(&args(&["part.cmmd", "-o", "part.svg"])).is_err());
+    }
+
+    /// Test the simulate command, its output file and its tool.
+    #[test]
+    fn test_simulate_command() {
+        let options = parse_args(&args(&["simulate", "--tool-diameter", "6", "-o", "part.STL", "part.nc"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Simulate {
+                output: String::from("part.STL"),
+                mesh: true,
+                diameter: 6.0,
+                cell: 0.6,
+                top: 0.0,
+            }
+        );
+        let options = parse_args(&args(&[
+            "simulate", "--tool-diameter", "3", "--cell", "0.1", "--stock-top", "-2", "-o", "part.pgm", "part.nc",
+        ]))
+        .unwrap();
+        let Command::Simulate { mesh, cell, top, .. } = options.command else { panic!("expected simulate") };
+        assert!(!mesh && cell == 0.1 && top == -2.0);
+
+        // It needs a tool and a file it knows how to write, and its flags mean nothing elsewhere
+        assert!(parse_args(&args(&["simulate", "-o", "part.stl", "part.nc"])).is_err());
+        assert!(parse_args(&args(&["simulate", "--tool-diameter", "6", "-o", "part.obj", "part.nc"])).is_err());
+        assert!(parse_args(&args(&["simulate", "--tool-diameter", "6", "part.nc"])).is_err());
+        assert!(parse_args(&args(&["--tool-diameter", "6", "part.nc"])).is_err());
+    }
+
+    /// Test the stats command.
+    #[test]
+    fn test_stats_command() {
+        let options = parse_args(&args(&["stats", "--rapid-rate", "3000", "part.cmmd"])).unwrap();
+        assert_eq!(options.command, Command::Stats);
+        assert_eq!(options.file_path.as_deref(), Some("part.cmmd"));
+        assert!(parse_args(&args(&["stats", "part.cmmd", "-o", "stats.txt"])).is_err());
+        assert_eq!(parse_args(&args(&["check", "part.cmmd"])).unwrap().command, Command::Check);
+        assert_eq!(parse_args(&args(&["view", "part.cmmd"])).unwrap().command, Command::View);
+    }
+
+    /// Test the convert command.
+    #[test]
+    fn test_convert_command() {
+        let options = parse_args(&args(&["convert", "part.cmmd"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Convert {
+                output: None,
+                dialect: Dialect::Gcode
+            }
+        );
+        let options = parse_args(&args(&["convert", "-o", "part.nc", "part.cmmd"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Convert {
+                output: Some(String::from("part.nc")),
+                dialect: Dialect::Gcode
+            }
+        );
+        // The output can follow the program, and its extension picks the language
+        let options = parse_args(&args(&["convert", "part.nc", "part.json"])).unwrap();
+        assert_eq!(options.file_path.as_deref(), Some("part.nc"));
+        assert_eq!(
+            options.command,
+            Command::Convert {
+                output: Some(String::from("part.json")),
+                dialect: Dialect::Json
+            }
+        );
+        let options = parse_args(&args(&["convert", "part.nc", "part.cmmd"])).unwrap();
+        assert!(matches!(options.command, Command::Convert { dialect: Dialect::Cmmd, .. }));
+        assert!(parse_args(&args(&["convert", "part.nc", "part.svg"])).is_err());
+        assert!(parse_args(&args(&["convert", "-o", "a.nc", "part.cmmd", "b.nc"])).is_err());
+    }
+
+    /// Test the raster command, which writes a program like convert does.
+    #[test]
+    fn test_raster_command() {
+        let options = parse_args(&args(&["raster", "logo.pgm"])).unwrap();
+        assert_eq!(options.file_path.as_deref(), Some("logo.pgm"));
+        assert_eq!(
+            options.command,
+            Command::Raster {
+                output: None,
+                dialect: Dialect::Gcode,
+                raster: RasterOptions::default(),
+            }
+        );
+        let options = parse_args(&args(&[
+            "raster", "--pixel", "0.2", "--power", "255", "--feed", "6000", "--overscan", "3", "logo.pgm", "logo.cmmd",
+        ]))
+        .unwrap();
+        let raster = RasterOptions {
+            pixel: 0.2,
+            power: 255.0,
+            feed: 6000.0,
+            overscan: 3.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.command,
+            Command::Raster {
+                output: Some(String::from("logo.cmmd")),
+                dialect: Dialect::Cmmd,
+                raster,
+            }
+        );
+        assert!(parse_args(&args(&["raster"])).is_err());
+        assert!(parse_args(&args(&["raster", "--pixel", "0", "logo.pgm"])).is_err());
+        assert!(parse_args(&args(&["raster", "--overscan", "-1", "logo.pgm"])).is_err());
+        assert!(parse_args(&args(&["raster", "logo.pgm", "logo.svg"])).is_err());
+        assert!(parse_args(&args(&["--power", "255", "part.cmmd"])).is_err());
+    }
+
+    /// Test the import command, which writes a program like convert does.
+    #[test]
+    fn test_import_command() {
+        let options = parse_args(&args(&[
+            "import", "--depth", "-0.5", "--clearance", "2", "--feed", "800", "logo.svg", "-o", "logo.nc",
+        ]))
+        .unwrap();
+        let drawing = DrawingOptions {
+            depth: -0.5,
+            clearance: 2.0,
+            feed: 800.0,
+            power: None,
+        };
+        assert_eq!(
+            options.command,
+            Command::Import {
+                output: Some(String::from("logo.nc")),
+                dialect: Dialect::Gcode,
+                drawing,
+            }
+        );
+        assert!(parse_args(&args(&["import", "--depth", "3", "--clearance", "2", "logo.svg"])).is_err());
+        assert!(parse_args(&args(&["import", "--pixel", "0.1", "logo.svg"])).is_err());
+        assert!(parse_args(&args(&["--depth", "-1", "part.cmmd"])).is_err());
+    }
+
+    /// Test that --flavor picks the controller G-code is written for, and only applies to G-code output.
+    #[test]
+    fn test_flavor() {
+        let options = parse_args(&args(&["convert", "--flavor", "GRBL", "part.cmmd", "part.nc"])).unwrap();
+        assert_eq!(options.flavor, Flavor::Grbl);
+        assert_eq!(parse_args(&args(&["convert", "part.cmmd"])).unwrap().flavor, Flavor::Generic);
+        assert!(parse_args(&args(&["convert", "--flavor", "fanuc", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["convert", "--flavor", "marlin", "part.nc", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["stats", "--flavor", "linuxcnc", "part.cmmd"])).is_err());
+        let stream = |extra: &[&str]| parse_args(&args(&[&["stream", "--device", "/dev/ttyUSB0"], extra].concat()));
+        assert_eq!(stream(&["part.cmmd"]).unwrap().flavor, Flavor::Grbl);
+        assert_eq!(stream(&["--flavor", "marlin", "part.cmmd"]).unwrap().flavor, Flavor::Marlin);
+    }
+
+    /// Test the stream command and its serial port settings.
+    #[test]
+    fn test_stream_command() {
+        let options = parse_args(&args(&["stream", "--device", "/dev/ttyUSB0", "part.cmmd"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Stream {
+                device: String::from("/dev/ttyUSB0"),
+                baud: 115_200,
+                checkpoint: None,
+                framing: Framing::Plain,
+            }
+        );
+        let options = parse_args(&args(&["stream", "--device", "COM3", "--baud", "9600", "part.cmmd"])).unwrap();
+        assert!(matches!(options.command, Command::Stream { baud: 9600, .. }));
+
+        assert!(parse_args(&args(&["stream", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--device", "COM3", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--checkpoint", "job.toml", "part.cmmd"])).is_err());
+
+        // Checksums need line numbers, whichever flag comes first
+        for flags in [["--line-numbers", "--checksum"], ["--checksum", "--line-numbers"]] {
+            let options = parse_args(&args(&["stream", "--device", "COM3", flags[0], flags[1], "part.cmmd"])).unwrap();
+            assert!(matches!(options.command, Command::Stream { framing: Framing::Checksummed, .. }));
+        }
+        assert!(parse_args(&args(&["convert", "--line-numbers", "part.cmmd"])).is_err());
+    }
+
+    /// Test that resuming restarts at the checkpoint's motion, in place of another start.
+    #[test]
+    fn test_resume_flag() {
+        let path = std::env::temp_dir().join("project-2-cli-checkpoint.toml");
+        std::fs::write(&path, "motions = 40\nmotion = 12\nposition = [1.0, 2.0, 0.0]\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = parse_args(&args(&["--resume", path, "part.cmmd"])).unwrap();
+        assert_eq!(options.restart, Some(Restart::Motion(12)));
+        assert_eq!(options.resume.unwrap().motions, 40);
+        assert!(parse_args(&args(&["--resume", path, "--start-line", "3", "part.cmmd"])).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse_args(&args(&["--resume", path, "part.cmmd"])).is_err());
+    }
+
+    /// Test the serve command, which takes an address and port instead of a file.
+    #[test]
+    fn test_serve_command() {
+        let local = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            parse_args(&args(&["serve"])).unwrap().command,
+            Command::Serve {
+                address: local,
+                port: 7878
+            }
+        );
+        let options = parse_args(&args(&["serve", "--port", "9000", "--step", "0.5"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Serve {
+                address: local,
+                port: 9000
+            }
+        );
+        let options = parse_args(&args(&["serve", "--bind", "0.0.0.0"])).unwrap();
+        assert_eq!(
+            options.command,
+            Command::Serve {
+                address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                port: 7878
+            }
+        );
+        assert!(parse_args(&args(&["serve", "--bind", "everywhere"])).is_err());
+        assert!(parse_args(&args(&["serve", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--port", "9000", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--bind", "0.0.0.0", "part.cmmd"])).is_err());
+    }
+
+    /// Test the repl command, which reads commands as they are typed.
+    #[test]
+    fn test_repl_command() {
+        let options = parse_args(&args(&["repl", "--format", "jsonl"])).unwrap();
+        assert_eq!(options.command, Command::Repl);
+        assert!(parse_args(&args(&["repl", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["repl", "--timed"])).is_err());
+        assert!(parse_args(&args(&["repl", "--format", "json"])).is_err());
+    }
+
+    /// Test the play command, which needs a file since stdin carries its controls.
+    #[test]
+    fn test_play_command() {
+        let options = parse_args(&args(&["play", "--accel", "500", "part.cmmd"])).unwrap();
+        assert_eq!(options.command, Command::Play);
+        assert!(parse_args(&args(&["play"])).is_err());
+        assert!(parse_args(&args(&["play", "-"])).is_err());
+        assert!(parse_args(&args(&["play", "--format", "jsonl", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["play", "--watch", "part.cmmd"])).is_err());
+    }
+
+    /// Test that the program file may be left out or given as `-`.
+    #[test]
+    fn test_stdin_program() {
+        assert_eq!(parse_args(&args(&["--strict"])).unwrap().file_path, None);
+        assert_eq!(parse_args(&args(&["plot", "-"])).unwrap().file_path.as_deref(), Some("-"));
+    }
+
+    /// Test extending the accepted extensions.
+    #[test]
+    fn test_extension_flags() {
+        let options = parse_args(&args(&["--extension", ".prg=gcode", "--force", "part.prg"])).unwrap();
+        assert_eq!(options.extensions.dialect_of("part.prg"), Some(Dialect::Gcode));
+        assert!(options.force);
+        assert!(parse_args(&args(&["--extension", "prg", "part.prg"])).is_err());
+        assert!(parse_args(&args(&["--extension", "prg=basic", "part.prg"])).is_err());
+    }
+
+    /// Test the default units flag.
+    #[test]
+    fn test_units_flag() {
+        let options = parse_args(&args(&["--units", "inch", "part.cmmd"])).unwrap();
+        assert_eq!(options.parse.units, Units::Inches);
+        assert!(parse_args(&args(&["--units", "furlongs", "part.cmmd"])).is_err());
+    }
+
+    /// Test that a machine profile sets defaults the flags can override.
+    #[test]
+    fn test_machine_flag() {
+        let path = std::env::temp_dir().join("project-2-cli-machine.toml");
+        std::fs::write(&path, "units = \"inch\"\nrapid_rate = 8000\npause_time = 20\n[x]\nmax = 300\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = parse_args(&args(&["--machine", path, "part.cmmd"])).unwrap();
+        assert_eq!(options.parse.units, Units::Inches);
+        assert_eq!(options.interpolation.rapid_rate, 8000.0);
+        assert_eq!(options.interpolation.pause_time, 20.0);
+        assert_eq!(options.machine.unwrap().x.max, Some(300.0));
+
+        let options = parse_args(&args(&["--units", "mm", "--machine", path, "part.cmmd"])).unwrap();
+        assert_eq!(options.parse.units, Units::Millimeters);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse_args(&args(&["--machine", "missing.toml", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--enforce-limits", "part.cmmd"])).is_err());
+    }
+
+    /// Test that steps need the machine's resolution and take a tick.
+    #[test]
+    fn test_steps_command() {
+        let path = std::env::temp_dir().join("project-2-cli-steps.toml");
+        std::fs::write(&path, "[x]\nsteps_per_mm = 80\n[y]\nsteps_per_mm = 80\n[z]\nsteps_per_mm = 400\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = parse_args(&args(&["steps", "--machine", path, "--tick", "0.001", "part.cmmd"])).unwrap();
+        assert_eq!(options.command, Command::Steps { tick: Some(0.001) });
+        assert!(parse_args(&args(&["--machine", path, "--tick", "0.001", "part.cmmd"])).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse_args(&args(&["steps", "part.cmmd"])).is_err());
+    }
+
+    /// Test that a height map is loaded, and that a broken one is reported.
+    #[test]
+    fn test_height_map_flag() {
+        let path = std::env::temp_dir().join("project-2-cli-height-map.toml");
+        std::fs::write(&path, "origin = [0, 0]\nspacing = [10, 10]\nz = [[0.1, 0.3]]\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let options = parse_args(&args(&["--height-map", path, "part.cmmd"])).unwrap();
+        assert_eq!(options.height_map.unwrap().apply((5.0, 0.0, -1.0)), (5.0, 0.0, -0.8));
+
+        std::fs::write(path, "origin = [0, 0]\nspacing = [10, 10]\nz = [[0.1], [0.2, 0.3]]\n").unwrap();
+        assert!(parse_args(&args(&["--height-map", path, "part.cmmd"])).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Test defining work offsets on the command line.
+    #[test]
+    fn test_wcs_flag() {
+        let options = parse_args(&args(&["--wcs", "1=10,20,-5", "part.cmmd"])).unwrap();
+        assert_eq!(options.parse.work_offsets.get(&1), Some(&(10.0, 20.0, -5.0)));
+        assert!(parse_args(&args(&["--wcs", "1=10,20", "part.cmmd"])).is_err());
+    }
+
+    /// Test that --kinematics picks how positions are converted for the motors.
+    #[test]
+    fn test_kinematics_flag() {
+        assert_eq!(parse_args(&args(&["part.cmmd"])).unwrap().kinematics, Kinematics::Cartesian);
+        let options = parse_args(&args(&["--kinematics", "corexy", "part.cmmd"])).unwrap();
+        assert_eq!(options.kinematics, Kinematics::CoreXy);
+        assert!(parse_args(&args(&["--kinematics", "polar", "part.cmmd"])).is_err());
+
+        let options = parse_args(&args(&["--kinematics", "scara", "--arms", "200,150", "--elbow", "left", "part.cmmd"]));
+        let expected = Kinematics::Scara {
+            inner: 200.0,
+            outer: 150.0,
+            elbow: Elbow::Left,
+        };
+        assert_eq!(options.unwrap().kinematics, expected);
+        assert!(parse_args(&args(&["--kinematics", "scara", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--arms", "200,150", "part.cmmd"])).is_err());
+    }
+
+    /// Test that the transform flags apply in a fixed order, whatever order they're given in.
+    #[test]
+    fn test_transform_flags() {
+        let options = parse_args(&args(&[
+            "--translate", "10,0,0", "--rotate-z", "90", "--mirror", "x", "--scale", "2", "part.cmmd",
+        ]))
+        .unwrap();
+        let (x, y, z) = options.transform.unwrap().point((1.0, 0.0, 0.0));
+        assert!((x - 10.0).abs() < 1e-9 && (y + 2.0).abs() < 1e-9 && z == 0.0);
+
+        assert!(parse_args(&args(&["part.cmmd"])).unwrap().transform.is_none());
+        assert!(parse_args(&args(&["--translate", "1,2", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--mirror", "w", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--low-memory", "--rotate-z", "45", "part.cmmd"])).is_err());
+    }
+
+    /// Test that arrays need a pitch and positive counts.
+    #[test]
+    fn test_array_flags() {
+        let options = parse_args(&args(&["--array", "3x2", "--pitch", "50,40", "part.cmmd"])).unwrap();
+        assert_eq!(
+            options.array,
+            Some(Grid {
+                columns: 3,
+                rows: 2,
+                pitch: (50.0, 40.0)
+            })
+        );
+        assert!(parse_args(&args(&["--array", "3x2", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--array", "0x2", "--pitch", "1,1", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--pitch", "1,1", "part.cmmd"])).is_err());
+    }
+
+    /// Test the output format flag.
+    #[test]
+    fn test_format_flag() {
+        let options = parse_args(&args(&["--format", "jsonl", "part.cmmd"])).unwrap();
+        assert_eq!(options.format, Format::JsonLines);
+        assert!(parse_args(&args(&["--format", "yaml", "part.cmmd"])).is_err());
+        let options = parse_args(&args(&["--format", "binary", "--timed", "part.cmmd"])).unwrap();
+        assert_eq!(options.format, Format::Binary);
+        assert!(parse_args(&args(&["stats", "--format", "binary", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--format", "binary", "--low-memory", "part.cmmd"])).is_err());
+        assert_eq!(parse_args(&args(&["decode", "path.bin"])).unwrap().command, Command::Decode);
+        assert!(parse_args(&args(&["decode", "--format", "json", "path.bin"])).is_err());
+    }
+
+    /// Test that verify needs a file to compare with, and takes a tolerance.
+    #[test]
+    fn test_verify_command() {
+        let options = parse_args(&args(&["verify", "--expected", "golden.jsonl", "--timed", "part.cmmd"])).unwrap();
+        let expected = String::from("golden.jsonl");
+        assert_eq!(options.command, Command::Verify { expected, within: 1e-6 });
+        let options = parse_args(&args(&["verify", "--expected", "out.bin", "--within", "0.01", "part.cmmd"])).unwrap();
+        assert!(matches!(options.command, Command::Verify { within: 0.01, .. }));
+        assert!(parse_args(&args(&["verify", "part.cmmd"])).is_err());
+        assert!(parse_args(&args(&["--expected", "golden.bin", "part.cmmd"])).is_err());
+    }
+
+    /// Test that diff takes the old and the new program, in that order.
+    #[test]
+    fn test_diff_command() {
+        let options = parse_args(&args(&["diff", "old.nc", "new.nc", "--within", "0.01"])).unwrap();
+        let old = String::from("old.nc");
+        assert_eq!(options.command, Command::Diff { old, within: 0.01 });
+        assert_eq!(options.file_path.as_deref(), Some("new.nc"));
+        assert!(parse_args(&args(&["diff", "new.nc"])).is_err());
+        assert!(parse_args(&args(&["diff", "--simplify", "0.1", "old.nc", "new.nc"])).is_err());
+        assert!(parse_args(&args(&["--within", "0.01", "part.cmmd"])).is_err());
+    }
+}
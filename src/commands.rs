@@ -0,0 +1,196 @@
+//! Custom commands registered by library users
+//!
+//! The `.cmmd` language can be extended without forking the parser: a
+//! [`CommandHandler`] registered under a new command word in
+//! [`ParseOptions::commands`] is called for every line starting with that
+//! word. It gets the rest of the line's words and a [`Context`] onto the
+//! interpreter, through which it can read and change the position, the modal
+//! feed rate and power, and resolve coordinates the way built-in commands do.
+//! It can give back one motion, which is then transformed, stamped with the
+//! feed rate and power and attributed to its line like any other, and the
+//! interpreter moves on to where that motion ends.
+//!
+//! Built-in commands always take precedence, so a handler can only add new
+//! words. Plugins distributed as crates can offer a function that registers
+//! their commands:
+//!
+//! ```
+//! use project_2::commands::Commands;
+//!
+//! pub fn register(commands: &mut Commands) {
+//!     // ...
+//! # let _ = commands;
+//! }
+//! ```
+//!
+//! [`ParseOptions::commands`]: crate::parser::ParseOptions::commands
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::machine::MachineState;
+use crate::motion::Motion;
+use crate::parser::State;
+
+type Point = (f64, f64, f64);
+
+/// What a custom command can see and change of the interpreter
+pub struct Context<'a> {
+    pub(crate) line: usize,
+    pub(crate) state: &'a mut State,
+}
+
+impl Context<'_> {
+    /// One-based number of the line being run
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// Where the tool is, in machine coordinates and millimeters
+    pub fn position(&self) -> Point {
+        self.state.position
+    }
+
+    /// Function to move the tool without a motion, such as after probing
+    pub fn set_position(&mut self, position: Point) {
+        self.state.position = position;
+    }
+
+    /// Function to resolve programmed coordinates into machine coordinates
+    ///
+    /// Coordinates are read in the program's units, as absolute or incremental
+    /// according to `ABS` and `INC`, and from the active work offset and tool
+    /// length. Axes left out stay where they are.
+    pub fn target(&self, x: Option<f64>, y: Option<f64>, z: Option<f64>) -> Point {
+        let scale = self.state.units.scale();
+        let length = |value: Option<f64>| value.map(|value| value * scale);
+        self.state.target(length(x), length(y), length(z))
+    }
+
+    /// The modal feed rate and power, which can be changed
+    pub fn machine(&mut self) -> &mut MachineState {
+        &mut self.state.machine
+    }
+}
+
+/// A handler for a custom command word
+///
+/// Closures with the same signature as [`CommandHandler::run`] are handlers.
+pub trait CommandHandler: Send + Sync {
+    /// Function to run a line starting with the command's word
+    ///
+    /// # Arguments
+    ///
+    /// * `arguments` - The words after the command, with comments removed
+    /// * `context` - The interpreter the line runs in
+    ///
+    /// # Returns
+    ///
+    /// The motion the line makes, in machine coordinates and starting from
+    /// [`Context::position`], or `None` if it only changes the state.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message saying what's wrong with the line, which is reported
+    /// with its line number and the command's columns.
+    fn run(&self, arguments: &[&str], context: &mut Context) -> Result<Option<Motion>, String>;
+}
+
+impl<F> CommandHandler for F
+where
+    F: Fn(&[&str], &mut Context) -> Result<Option<Motion>, String> + Send + Sync,
+{
+    fn run(&self, arguments: &[&str], context: &mut Context) -> Result<Option<Motion>, String> {
+        self(arguments, context)
+    }
+}
+
+/// Custom commands by word
+#[derive(Clone, Default)]
+pub struct Commands {
+    handlers: BTreeMap<String, Arc<dyn CommandHandler>>,
+}
+
+impl Commands {
+    /// Function to register a handler for a command word, replacing any registered before
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::commands::{Commands, Context};
+    /// use project_2::motion::Motion;
+    /// use project_2::parser::{read_reader, ParseOptions};
+    ///
+    /// // `LIFT h` raises the tool by h
+    /// let mut commands = Commands::default();
+    /// commands.register("LIFT", |arguments: &[&str], context: &mut Context| {
+    ///     let height: f64 = arguments.first().and_then(|h| h.parse().ok()).ok_or("expected a height")?;
+    ///     let start = context.position();
+    ///     Ok(Some(Motion::new_rapid(start, (start.0, start.1, start.2 + height))))
+    /// });
+    /// let options = ParseOptions { commands, ..Default::default() };
+    /// let motions = read_reader("LIN X5 Y0 Z0\nLIFT 2\n".as_bytes(), &options).unwrap();
+    /// assert_eq!(motions.len(), 2);
+    /// assert!(read_reader("LIFT\n".as_bytes(), &options).is_err());
+    /// ```
+    pub fn register(&mut self, word: &str, handler: impl CommandHandler + 'static) {
+        self.handlers.insert(word.to_string(), Arc::new(handler));
+    }
+
+    /// The handler registered for a command word, if any
+    pub fn get(&self, word: &str) -> Option<&dyn CommandHandler> {
+        self.handlers.get(word).map(|handler| handler.as_ref())
+    }
+
+    /// The registered command words, in order
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+}
+
+// Handlers can't be printed, so just list their words
+impl fmt::Debug for Commands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.words()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{CmmdError, ParseError};
+    use crate::parser::{read_reader, ParseOptions};
+
+    /// Test that a custom command can read coordinates, change the modal state and make a motion.
+    #[test]
+    fn test_custom_command() {
+        // `PLUNGE z` feeds straight down to z at a third of the feed rate, and keeps that feed rate
+        let mut commands = Commands::default();
+        commands.register("PLUNGE", |arguments: &[&str], context: &mut Context| {
+            let [depth] = arguments else {
+                return Err(format!("expected a depth, found {} words", arguments.len()));
+            };
+            let depth = depth.parse().map_err(|_| format!("invalid depth `{}`", depth))?;
+            let machine = context.machine();
+            machine.feed = machine.feed.map(|feed| feed / 3.0);
+            Ok(Some(Motion::new_linear(context.position(), context.target(None, None, Some(depth)))))
+        });
+        let options = ParseOptions { commands, ..Default::default() };
+        assert_eq!(format!("{:?}", options.commands), "{\"PLUNGE\"}");
+
+        let program = "UNITS INCH\nF30\nLIN X1 Y1 Z0\nPLUNGE -0.5 ; down\nLIN X2 Y1 Z-0.5\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+        assert_eq!(motions[1].line(), Some(4));
+        let Motion::Linear(plunge) = &motions[1] else { panic!("expected a line") };
+        assert_eq!((plunge.start, plunge.end), ((25.4, 25.4, 0.0), (25.4, 25.4, -12.7)));
+        assert!((motions[1].feed().unwrap() - 254.0).abs() < 1e-9);
+        assert_eq!(motions[2].feed(), motions[1].feed());
+
+        let err = read_reader("PLUNGE\n".as_bytes(), &options).unwrap_err();
+        let CmmdError::Parse(ParseError::CommandFailed { line: 1, message, .. }) = err else {
+            panic!("expected the handler's error, got {:?}", err)
+        };
+        assert_eq!(message, "expected a depth, found 0 words");
+    }
+}
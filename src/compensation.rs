@@ -0,0 +1,658 @@
+//! Cutter radius compensation
+//!
+//! Programs written to part dimensions describe the edge of the part, but the
+//! tool's center has to run half a tool diameter away from it. With
+//! `COMP LEFT` or `COMP RIGHT` (like G41/G42) the interpreter shifts the
+//! programmed path to the left or right of the direction of travel by the
+//! radius of the active tool, until `COMP OFF` (G40):
+//!
+//! - lines move sideways, and arcs grow or shrink around the same center
+//! - where the offset path turns away from the part (an outside corner), an
+//!   arc around the programmed corner joins the two offset segments
+//! - where it turns into the part (an inside corner), both segments are
+//!   trimmed back to the point where they cross
+//! - the first line after `COMP LEFT`/`COMP RIGHT` ramps from the
+//!   uncompensated position onto the offset path, and the first line after
+//!   `COMP OFF` ramps back off it
+//!
+//! Compensation only acts in the XY plane. Moves that only change Z keep the
+//! tool where it is in XY. Bézier curves and splines are never offset; the
+//! parser refuses them while compensation is on.
+
+use crate::error::CmmdError;
+use crate::motion::Motion;
+use crate::post::arc_move;
+use crate::transform::ends;
+
+/// Which side of the programmed path the tool runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    /// Left of the direction of travel (G41)
+    Left,
+    /// Right of the direction of travel (G42)
+    Right,
+}
+
+/// Compensation in effect for a motion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compensation {
+    /// Which side of the path the tool runs on
+    pub side: Side,
+    /// How far the tool's center runs from the path, half the tool diameter
+    pub radius: f64,
+}
+
+// Distances below this are treated as zero
+const EPSILON: f64 = 1e-9;
+
+type Point = (f64, f64, f64);
+
+/// The geometry of a segment being compensated
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Shape {
+    Line,
+    Arc {
+        center: (f64, f64),
+        radius: f64,
+        clockwise: bool,
+        // Whether the original arc ramped Z
+        helical: bool,
+    },
+    Curve {
+        // Index of the programmed curve, which is passed through unchanged
+        motion: usize,
+        // Unit directions of travel at the start and the end, in XY
+        tangents: ((f64, f64), (f64, f64)),
+    },
+    Event {
+        // Index of the programmed event, such as a tool change, which happens wherever the tool is
+        motion: usize,
+    },
+}
+
+/// A motion in the geometric form compensation works with
+#[derive(Debug, Clone)]
+struct Segment {
+    start: Point,
+    end: Point,
+    shape: Shape,
+    // Whether the segment is a rapid move
+    rapid: bool,
+    feed: Option<f64>,
+    power: Option<f64>,
+    line: Option<usize>,
+    compensation: Option<Compensation>,
+}
+
+impl Segment {
+    // Whether the segment moves in XY at all
+    fn moves_in_xy(&self) -> bool {
+        match self.shape {
+            Shape::Line => xy_distance(self.start, self.end) > EPSILON,
+            Shape::Arc { .. } | Shape::Curve { .. } => true,
+            Shape::Event { .. } => false,
+        }
+    }
+
+    // Unit direction of travel at the start or the end of the segment
+    fn tangent(&self, at_end: bool) -> (f64, f64) {
+        match self.shape {
+            Shape::Line => normalize((self.end.0 - self.start.0, self.end.1 - self.start.1)),
+            Shape::Arc { center, clockwise, .. } => {
+                let point = if at_end { self.end } else { self.start };
+                let radial = normalize((point.0 - center.0, point.1 - center.1));
+                if clockwise {
+                    (radial.1, -radial.0)
+                } else {
+                    (-radial.1, radial.0)
+                }
+            }
+            Shape::Curve { tangents, .. } => {
+                if at_end {
+                    tangents.1
+                } else {
+                    tangents.0
+                }
+            }
+            Shape::Event { .. } => (0.0, 0.0),
+        }
+    }
+
+    // Convert back into a motion, looking curves up in the programmed motions
+    fn into_motion(self, motions: &[Motion]) -> Motion {
+        let motion = match self.shape {
+            Shape::Line if self.rapid => Motion::new_rapid(self.start, self.end),
+            Shape::Line => Motion::new_linear(self.start, self.end),
+            Shape::Arc {
+                center,
+                clockwise,
+                helical,
+                ..
+            } => {
+                let offset = (center.0 - self.start.0, center.1 - self.start.1);
+                let mut motion = Motion::new_arc_from_offset(self.start, self.end, offset, clockwise);
+                // Flat arcs stay at whatever height the tool is at
+                if let (Motion::Rotational(arc), false) = (&mut motion, helical) {
+                    arc.z = None;
+                }
+                motion
+            }
+            Shape::Curve { motion, .. } => motions[motion].clone(),
+            Shape::Event { motion } => match &motions[motion] {
+                Motion::ToolChange(tool_change) => Motion::new_tool_change(tool_change.tool, self.end),
+                Motion::Aux(aux_command) => Motion::new_aux(aux_command.code.clone(), self.end),
+                Motion::Pause(pause) => Motion::new_pause(pause.optional, self.end),
+                event => event.clone(),
+            },
+        };
+        let motion = motion.with_feed(self.feed).with_power(self.power);
+        match self.line {
+            Some(line) => motion.with_line(line),
+            None => motion,
+        }
+    }
+}
+
+// Distance between two points in XY
+fn xy_distance(a: Point, b: Point) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+// Scale a vector to unit length
+fn normalize(v: (f64, f64)) -> (f64, f64) {
+    let length = v.0.hypot(v.1);
+    if length < EPSILON {
+        (0.0, 0.0)
+    } else {
+        (v.0 / length, v.1 / length)
+    }
+}
+
+// Z component of the cross product of two vectors; positive for a left turn
+fn cross(a: (f64, f64), b: (f64, f64)) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+// Turn motions into segments, tracking where the tool is
+fn segments(motions: &[Motion], compensation: &[Option<Compensation>]) -> Vec<Segment> {
+    let mut z = 0.0;
+    // Directions a curve leaves and arrives in, from points just inside its ends
+    let tangents = |point: &dyn Fn(f64) -> Point, start: Point, end: Point| {
+        let (after, before) = (point(1e-6), point(1.0 - 1e-6));
+        let fallback = normalize((end.0 - start.0, end.1 - start.1));
+        let direction = |from: Point, to: Point| {
+            let direction = normalize((to.0 - from.0, to.1 - from.1));
+            if direction == (0.0, 0.0) {
+                fallback
+            } else {
+                direction
+            }
+        };
+        (direction(start, after), direction(before, end))
+    };
+    motions
+        .iter()
+        .zip(compensation)
+        .enumerate()
+        .map(|(index, (motion, compensation))| {
+            let segment = match motion {
+                Motion::Linear(linear) => Segment {
+                    start: linear.start,
+                    end: linear.end,
+                    shape: Shape::Line,
+                    rapid: linear.rapid,
+                    feed: linear.feed,
+                    power: linear.power,
+                    line: linear.line,
+                    compensation: *compensation,
+                },
+                Motion::Rotational(rotational) => {
+                    let arc = arc_move(rotational, z);
+                    Segment {
+                        start: arc.start,
+                        end: arc.end,
+                        shape: Shape::Arc {
+                            center: rotational.center,
+                            radius: rotational.radius,
+                            clockwise: arc.clockwise,
+                            helical: rotational.z.is_some(),
+                        },
+                        rapid: false,
+                        feed: rotational.feed,
+                        power: rotational.power,
+                        line: rotational.line,
+                        compensation: *compensation,
+                    }
+                }
+                // Curves can't be offset, so they always run as programmed
+                Motion::Bezier(bezier) => Segment {
+                    start: bezier.start,
+                    end: bezier.end,
+                    shape: Shape::Curve {
+                        motion: index,
+                        tangents: tangents(&|t| bezier.point(t), bezier.start, bezier.end),
+                    },
+                    rapid: false,
+                    feed: bezier.feed,
+                    power: bezier.power,
+                    line: bezier.line,
+                    compensation: None,
+                },
+                Motion::Spline(spline) => {
+                    let (start, end) = (spline.start(), spline.end());
+                    Segment {
+                        start,
+                        end,
+                        shape: Shape::Curve {
+                            motion: index,
+                            tangents: tangents(&|t| spline.point(t), start, end),
+                        },
+                        rapid: false,
+                        feed: spline.feed,
+                        power: spline.power,
+                        line: spline.line,
+                        compensation: None,
+                    }
+                }
+                // Which way to offset a tilted tool depends on its direction, so those run as programmed too
+                Motion::Oriented(oriented) => {
+                    let (start, end) = (oriented.start, oriented.end);
+                    let along = |t: f64| {
+                        let between = |from: f64, to: f64| from + (to - from) * t;
+                        (between(start.0, end.0), between(start.1, end.1), between(start.2, end.2))
+                    };
+                    Segment {
+                        start,
+                        end,
+                        shape: Shape::Curve {
+                            motion: index,
+                            tangents: tangents(&along, start, end),
+                        },
+                        rapid: oriented.rapid,
+                        feed: oriented.feed,
+                        power: oriented.power,
+                        line: oriented.line,
+                        compensation: None,
+                    }
+                }
+                // Events don't move, so they stay with the tool like moves that only change Z
+                Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => {
+                    let position = ends(motion, z).0;
+                    Segment {
+                        start: position,
+                        end: position,
+                        shape: Shape::Event { motion: index },
+                        rapid: false,
+                        feed: None,
+                        power: None,
+                        line: motion.line(),
+                        compensation: None,
+                    }
+                }
+            };
+            z = segment.end.2;
+            segment
+        })
+        .collect()
+}
+
+// Shift a segment to the compensated side
+fn offset(segment: &mut Segment, compensation: Compensation) -> Result<(), CmmdError> {
+    let distance = match compensation.side {
+        Side::Left => compensation.radius,
+        Side::Right => -compensation.radius,
+    };
+    match &mut segment.shape {
+        Shape::Line => {
+            let tangent = segment.tangent(false);
+            let normal = (-tangent.1 * distance, tangent.0 * distance);
+            segment.start = (segment.start.0 + normal.0, segment.start.1 + normal.1, segment.start.2);
+            segment.end = (segment.end.0 + normal.0, segment.end.1 + normal.1, segment.end.2);
+        }
+        Shape::Arc {
+            center,
+            radius,
+            clockwise,
+            ..
+        } => {
+            // The left of a counter-clockwise arc is towards its center
+            let new_radius = if *clockwise { *radius + distance } else { *radius - distance };
+            if new_radius < EPSILON {
+                return Err(CmmdError::ToolTooLarge {
+                    line: segment.line.unwrap_or_default(),
+                });
+            }
+            let scale = new_radius / *radius;
+            let center = *center;
+            let move_point = |p: Point| (center.0 + (p.0 - center.0) * scale, center.1 + (p.1 - center.1) * scale, p.2);
+            segment.start = move_point(segment.start);
+            segment.end = move_point(segment.end);
+            *radius = new_radius;
+        }
+        // Curves are never compensated, see `segments`
+        Shape::Curve { .. } | Shape::Event { .. } => {}
+    }
+    Ok(())
+}
+
+// Points where the full lines or circles of two segments cross, in XY
+fn intersections(a: &Segment, b: &Segment) -> Vec<(f64, f64)> {
+    // Where a line through `p` along `d` meets a circle
+    fn line_circle(p: Point, d: (f64, f64), center: (f64, f64), radius: f64) -> Vec<(f64, f64)> {
+        let f = (p.0 - center.0, p.1 - center.1);
+        let b = f.0 * d.0 + f.1 * d.1;
+        let c = f.0 * f.0 + f.1 * f.1 - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return Vec::new();
+        }
+        let root = discriminant.sqrt();
+        [-b - root, -b + root].iter().map(|t| (p.0 + d.0 * t, p.1 + d.1 * t)).collect()
+    }
+
+    match (a.shape, b.shape) {
+        (Shape::Line, Shape::Line) => {
+            let (da, db) = (a.tangent(false), b.tangent(false));
+            let denominator = cross(da, db);
+            if denominator.abs() < EPSILON {
+                return Vec::new();
+            }
+            let t = cross((b.start.0 - a.start.0, b.start.1 - a.start.1), db) / denominator;
+            vec![(a.start.0 + da.0 * t, a.start.1 + da.1 * t)]
+        }
+        (Shape::Line, Shape::Arc { center, radius, .. }) => line_circle(a.start, a.tangent(false), center, radius),
+        (Shape::Arc { center, radius, .. }, Shape::Line) => line_circle(b.start, b.tangent(false), center, radius),
+        (
+            Shape::Arc {
+                center: c1, radius: r1, ..
+            },
+            Shape::Arc {
+                center: c2, radius: r2, ..
+            },
+        ) => {
+            let d = (c2.0 - c1.0).hypot(c2.1 - c1.1);
+            if d < EPSILON || d > r1 + r2 || d < (r1 - r2).abs() {
+                return Vec::new();
+            }
+            let along = (r1 * r1 - r2 * r2 + d * d) / (2.0 * d);
+            let across = (r1 * r1 - along * along).max(0.0).sqrt();
+            let unit = ((c2.0 - c1.0) / d, (c2.1 - c1.1) / d);
+            let base = (c1.0 + unit.0 * along, c1.1 + unit.1 * along);
+            vec![
+                (base.0 - unit.1 * across, base.1 + unit.0 * across),
+                (base.0 + unit.1 * across, base.1 - unit.0 * across),
+            ]
+        }
+        // Curves never sit next to compensated segments, see `segments`
+        (Shape::Curve { .. } | Shape::Event { .. }, _) | (_, Shape::Curve { .. } | Shape::Event { .. }) => Vec::new(),
+    }
+}
+
+// Join two consecutive offset segments around the programmed corner between them
+//
+// Returns an arc to insert for outside corners; inside corners are trimmed in place.
+fn join(
+    before: &mut Segment,
+    after: &mut Segment,
+    corner: Point,
+    compensation: Compensation,
+) -> Result<Option<Segment>, CmmdError> {
+    if xy_distance(before.end, after.start) < EPSILON {
+        return Ok(None);
+    }
+
+    // Turning towards the tool's side means the offset segments overlap
+    let turn = cross(before.tangent(true), after.tangent(false));
+    let inside = match compensation.side {
+        Side::Left => turn > EPSILON,
+        Side::Right => turn < -EPSILON,
+    };
+
+    if inside {
+        let closest = intersections(before, after).into_iter().min_by(|a, b| {
+            let distance = |p: &(f64, f64)| (p.0 - corner.0).hypot(p.1 - corner.1);
+            distance(a).total_cmp(&distance(b))
+        });
+        if let Some((x, y)) = closest {
+            // A line trimmed past its start means the tool doesn't fit
+            let reversed = |segment: &Segment, start: Point, end: Point| {
+                segment.shape == Shape::Line && {
+                    let original = segment.tangent(false);
+                    (end.0 - start.0) * original.0 + (end.1 - start.1) * original.1 < -EPSILON
+                }
+            };
+            let trimmed_before = (x, y, before.end.2);
+            let trimmed_after = (x, y, after.start.2);
+            if reversed(before, before.start, trimmed_before) || reversed(after, trimmed_after, after.end) {
+                return Err(CmmdError::ToolTooLarge {
+                    line: after.line.unwrap_or_default(),
+                });
+            }
+            before.end = trimmed_before;
+            after.start = trimmed_after;
+            return Ok(None);
+        }
+    }
+
+    // Go around the outside of the corner, the way the tool edge would
+    let joint = Segment {
+        start: before.end,
+        end: after.start,
+        shape: Shape::Arc {
+            center: (corner.0, corner.1),
+            radius: compensation.radius,
+            clockwise: compensation.side == Side::Left,
+            helical: false,
+        },
+        rapid: false,
+        feed: after.feed,
+        power: after.power,
+        line: after.line,
+        compensation: Some(compensation),
+    };
+    Ok(Some(joint))
+}
+
+/// Function to apply cutter radius compensation to a program
+///
+/// # Arguments
+///
+/// * `motions` - The programmed motions
+/// * `compensation` - The compensation in effect for each motion, if any
+///
+/// # Returns
+///
+/// The motions the tool's center follows. Corners may add extra arcs, so
+/// there can be more motions than were programmed.
+///
+/// # Errors
+///
+/// Returns `CmmdError::ToolTooLarge` if the tool doesn't fit inside an arc or
+/// between two segments of the path.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::compensation::{apply, Compensation, Side};
+/// use project_2::motion::Motion;
+///
+/// // Cut along the bottom edge of a part that lies above Y = 0
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0))];
+/// let compensation = Compensation { side: Side::Right, radius: 3.0 };
+/// let compensated = apply(&motions, &[Some(compensation)]).unwrap();
+/// let Motion::Linear(line) = &compensated[0] else { unreachable!() };
+/// assert_eq!(line.end, (10.0, -3.0, 0.0));
+/// ```
+pub fn apply(motions: &[Motion], compensation: &[Option<Compensation>]) -> Result<Vec<Motion>, CmmdError> {
+    let original = segments(motions, compensation);
+    let mut compensated = original.clone();
+    for segment in &mut compensated {
+        if let (Some(compensation), true) = (segment.compensation, segment.moves_in_xy()) {
+            offset(segment, compensation)?;
+        }
+    }
+
+    let mut output: Vec<Segment> = Vec::with_capacity(compensated.len());
+    // Index in `output` of the last segment that moved in XY, and the compensation it had
+    let mut previous: Option<(usize, Option<Compensation>)> = None;
+    // Lines that ramp from where the tool is, moved to their start once every corner is joined
+    let mut ramps: Vec<(usize, Point)> = Vec::new();
+
+    for (index, mut segment) in compensated.into_iter().enumerate() {
+        let programmed = &original[index];
+
+        if !segment.moves_in_xy() {
+            // Z-only moves stay wherever the tool already is in XY
+            if let Some(last) = output.last() {
+                segment.start = (last.end.0, last.end.1, segment.start.2);
+                segment.end = (last.end.0, last.end.1, segment.end.2);
+            }
+            output.push(segment);
+            continue;
+        }
+
+        match (previous, segment.compensation) {
+            // Consecutive compensated segments on the same side are joined at the corner
+            (Some((last, Some(before))), Some(after)) if before == after => {
+                let corner = programmed.start;
+                let (head, tail) = output.split_at_mut(last + 1);
+                let joint = join(&mut head[last], &mut segment, corner, after)?;
+                // Z-only moves in between follow the trimmed corner
+                for z_move in tail.iter_mut() {
+                    z_move.start = (head[last].end.0, head[last].end.1, z_move.start.2);
+                    z_move.end = (head[last].end.0, head[last].end.1, z_move.end.2);
+                }
+                output.extend(joint);
+            }
+            // Lines ramp onto and off the offset path from where the tool is
+            // Corners are joined on the fully offset line, so its start moves only afterwards
+            (Some(_), _) if segment.shape == Shape::Line && segment.compensation != previous.unwrap().1 => {
+                let last = output.last().expect("a previous segment").end;
+                ramps.push((output.len(), (last.0, last.1, segment.start.2)));
+            }
+            (None, Some(_)) if segment.shape == Shape::Line => ramps.push((output.len(), programmed.start)),
+            _ => {}
+        }
+
+        previous = Some((output.len(), segment.compensation));
+        output.push(segment);
+    }
+    for (index, start) in ramps {
+        output[index].start = start;
+    }
+
+    Ok(output.into_iter().map(|segment| segment.into_motion(motions)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEFT: Option<Compensation> = Some(Compensation {
+        side: Side::Left,
+        radius: 1.0,
+    });
+
+    // End points of the compensated motions
+    fn ends(motions: &[Motion]) -> Vec<(f64, f64)> {
+        motions
+            .iter()
+            .map(|motion| match motion {
+                Motion::Linear(linear) => (linear.end.0, linear.end.1),
+                Motion::Rotational(rotational) => {
+                    let arc = arc_move(rotational, 0.0);
+                    (arc.end.0, arc.end.1)
+                }
+                Motion::Bezier(bezier) => (bezier.end.0, bezier.end.1),
+                Motion::Spline(spline) => (spline.end().0, spline.end().1),
+                Motion::Oriented(oriented) => (oriented.end.0, oriented.end.1),
+                Motion::ToolChange(tool_change) => (tool_change.position.0, tool_change.position.1),
+                Motion::Aux(aux_command) => (aux_command.position.0, aux_command.position.1),
+                Motion::Pause(pause) => (pause.position.0, pause.position.1),
+            })
+            .map(|(x, y)| ((x * 1e6).round() / 1e6 + 0.0, (y * 1e6).round() / 1e6 + 0.0))
+            .collect()
+    }
+
+    /// Test a square cut clockwise with the tool outside of it, plus ramps on and off.
+    #[test]
+    fn test_outside_corners() {
+        // Clockwise around a 10x10 square with the tool on the left is outside the square
+        let corners = [(0.0, 0.0), (0.0, 10.0), (10.0, 10.0), (10.0, 0.0), (0.0, 0.0)];
+        let mut motions = vec![Motion::new_linear((-5.0, 0.0, 0.0), (0.0, 0.0, 0.0))];
+        let mut compensation = vec![None];
+        for pair in corners.windows(2) {
+            motions.push(Motion::new_linear((pair[0].0, pair[0].1, 0.0), (pair[1].0, pair[1].1, 0.0)));
+            compensation.push(LEFT);
+        }
+        motions.push(Motion::new_linear((0.0, 0.0, 0.0), (-5.0, 0.0, 0.0)));
+        compensation.push(None);
+
+        let compensated = apply(&motions, &compensation).unwrap();
+        assert_eq!(
+            ends(&compensated),
+            vec![
+                (0.0, 0.0),
+                (-1.0, 10.0), // up the left side
+                (0.0, 11.0),  // around the corner
+                (10.0, 11.0),
+                (11.0, 10.0),
+                (11.0, 0.0),
+                (10.0, -1.0),
+                (0.0, -1.0),
+                (-5.0, 0.0), // ramp off
+            ]
+        );
+        let Motion::Linear(ramp_on) = &compensated[1] else { panic!("expected a line") };
+        assert_eq!(ramp_on.start, (0.0, 0.0, 0.0));
+    }
+
+    /// Test an inside corner, an arc shrinking around its center and a tool too large for an arc.
+    #[test]
+    fn test_inside_corner_and_arcs() {
+        // Turning left with the tool on the left trims both lines back
+        let motions = vec![
+            Motion::new_linear((-5.0, 0.0, 0.0), (0.0, 0.0, 0.0)),
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, 10.0, 0.0)),
+        ];
+        let compensated = apply(&motions, &[LEFT, LEFT, LEFT]).unwrap();
+        assert_eq!(ends(&compensated), vec![(0.0, 1.0), (9.0, 1.0), (9.0, 10.0)]);
+
+        // A counter-clockwise arc with the tool on the left shrinks
+        let arc = Motion::new_arc_from_offset((5.0, 0.0, 0.0), (0.0, 5.0, 0.0), (-5.0, 0.0), false);
+        let compensated = apply(std::slice::from_ref(&arc), &[LEFT]).unwrap();
+        let Motion::Rotational(rotational) = &compensated[0] else { panic!("expected an arc") };
+        assert!((rotational.radius - 4.0).abs() < 1e-9);
+        assert_eq!(rotational.z, Some((0.0, 0.0)));
+
+        let large = Some(Compensation {
+            side: Side::Left,
+            radius: 6.0,
+        });
+        assert!(matches!(apply(&[arc], &[large]), Err(CmmdError::ToolTooLarge { .. })));
+    }
+
+    /// Test that a line ramping on still meets the next line exactly at the offset corner.
+    #[test]
+    fn test_ramp_on_corner() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, 10.0, 0.0)),
+        ];
+        for previous in [None, Some(Motion::new_linear((-5.0, 0.0, 0.0), (0.0, 0.0, 0.0)))] {
+            let mut compensation = vec![LEFT, LEFT];
+            let mut programmed = motions.clone();
+            if let Some(previous) = previous {
+                programmed.insert(0, previous);
+                compensation.insert(0, None);
+            }
+            let compensated = apply(&programmed, &compensation).unwrap();
+            let skip = compensated.len() - 2;
+            let Motion::Linear(ramp_on) = &compensated[skip] else { panic!("expected a line") };
+            assert_eq!(ramp_on.start, (0.0, 0.0, 0.0));
+            assert_eq!(ramp_on.end, (9.0, 1.0, 0.0));
+            assert_eq!(ends(&compensated[skip..]), vec![(9.0, 1.0), (9.0, 10.0)]);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! Machining cycles
+//!
+//! A cycle is a whole sequence of moves written as one command, the way
+//! canned cycles are on a controller. The functions here build those moves
+//! from the cycle's dimensions as plain `LIN` and `RAP` motions, so that
+//! timing, validation and output treat them like any others: cutting moves
+//! run at the feed rate and positioning moves at the rapid rate.
+//!
+//! - [`drill`] plunges a hole in one go, or in pecks that break the chip
+//! - [`Pocket`] clears a rectangular pocket layer by layer, in rows or
+//!   rings
+//! - [`Thread`] mills a thread inside a hole or around a boss in helical
+//!   turns
+
+use crate::motion::Motion;
+
+type Point = (f64, f64, f64);
+
+/// How far the tool backs off between pecks to break the chip, in millimeters
+pub const CHIP_BREAK: f64 = 0.5;
+
+// Moves through a series of points, cutting or rapid, skipping any that go nowhere
+fn moves(from: Point, through: &[(Point, bool)]) -> Vec<Motion> {
+    let mut motions = Vec::with_capacity(through.len());
+    let mut at = from;
+    for &(to, rapid) in through {
+        let distance = ((to.0 - at.0).powi(2) + (to.1 - at.1).powi(2) + (to.2 - at.2).powi(2)).sqrt();
+        if distance < 1e-9 {
+            continue;
+        }
+        motions.push(if rapid {
+            Motion::new_rapid(at, to)
+        } else {
+            Motion::new_linear(at, to)
+        });
+        at = to;
+    }
+    motions
+}
+
+/// Function to drill a hole along Z
+///
+/// The tool rises to the retract height if it's below it, crosses over to
+/// the hole at rapid, drops to the retract height and feeds down to the
+/// bottom. With a peck depth it feeds down that much deeper each time and
+/// backs off by [`CHIP_BREAK`] at rapid between pecks, to break the chip,
+/// before carrying on from there. Once at the bottom it returns at rapid to
+/// the height it started at, or the retract height if that's higher, so it
+/// clears whatever it crossed over on the way in.
+///
+/// # Arguments
+///
+/// * `from` - Where the tool is when the cycle starts
+/// * `hole` - X and Y of the hole
+/// * `bottom` - Height of the bottom of the hole
+/// * `retract` - Height the tool feeds down from, just above the stock
+/// * `peck` - How deep each peck goes, or `None` to drill in one go
+///
+/// # Returns
+///
+/// The cycle's motions, or `None` if the bottom isn't below the retract
+/// height or the peck depth isn't positive.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::cycles::drill;
+///
+/// // A hole to Z = -6 at (10, 5) in pecks of 3, from 10 above the stock
+/// let motions = drill((0.0, 0.0, 10.0), (10.0, 5.0), -6.0, 1.0, Some(3.0)).unwrap();
+/// // Across, down to the retract height, three pecks with two chip breaks, and back up
+/// assert_eq!(motions.len(), 8);
+/// assert!(motions[0].is_rapid() && !motions[2].is_rapid() && motions[3].is_rapid());
+/// assert_eq!(drill((0.0, 0.0, 10.0), (10.0, 5.0), 2.0, 1.0, None), None);
+/// ```
+pub fn drill(from: Point, hole: (f64, f64), bottom: f64, retract: f64, peck: Option<f64>) -> Option<Vec<Motion>> {
+    if bottom >= retract || peck.is_some_and(|peck| peck <= 0.0) {
+        return None;
+    }
+    let clearance = from.2.max(retract);
+    let at = |z: f64| (hole.0, hole.1, z);
+
+    let mut through = vec![
+        ((from.0, from.1, clearance), true),
+        (at(clearance), true),
+        (at(retract), true),
+    ];
+    let step = peck.unwrap_or(retract - bottom);
+    let mut depth = retract;
+    while depth > bottom {
+        // Each peck goes a whole step deeper than the last, but no deeper than the hole
+        depth = (depth - step).max(bottom);
+        through.push((at(depth), false));
+        if depth > bottom {
+            through.push((at(depth + CHIP_BREAK.min(step)), true));
+        }
+    }
+    through.push((at(clearance), true));
+    Some(moves(from, &through))
+}
+
+/// How a pocket is cleared at each depth
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clearing {
+    /// Rows back and forth along X, then once around the walls to clean them up
+    #[default]
+    Zigzag,
+    /// Rings from the middle out to the walls
+    Spiral,
+}
+
+/// A rectangular pocket, cleared down from the stock to its floor
+///
+/// The tool's center stays its radius inside the walls, so the pocket comes
+/// out the size given.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pocket {
+    /// X and Y of the corner with the lowest X and Y
+    pub corner: (f64, f64),
+    /// Width along X and height along Y
+    pub size: (f64, f64),
+    /// Height of the pocket's floor
+    pub bottom: f64,
+    /// Height the tool feeds down from, just above the stock
+    pub top: f64,
+    /// How far apart the rows or rings are
+    pub stepover: f64,
+    /// How much deeper each layer goes than the one before
+    pub stepdown: f64,
+    /// Whether each layer is cut in rows or rings
+    pub clearing: Clearing,
+}
+
+impl Pocket {
+    /// Function to build the moves that clear the pocket
+    ///
+    /// The tool rises to the top of the pocket if it's below it and crosses
+    /// over at rapid. Each layer then starts with a plunge at the feed rate,
+    /// from the top to a stepdown deeper than the last layer, and ends with a
+    /// rapid back up to the top. The last layer is on the floor. Once done the
+    /// tool returns at rapid to the height it started at, or the top if that's
+    /// higher.
+    ///
+    /// Rows and rings are spread evenly, no further apart than the stepover.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Where the tool is when the cycle starts
+    /// * `radius` - Radius of the tool
+    ///
+    /// # Returns
+    ///
+    /// The cycle's motions, or `None` if the tool doesn't fit in the pocket,
+    /// the floor isn't below the top, or the stepover or stepdown isn't positive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::cycles::{Clearing, Pocket};
+    ///
+    /// // A 20 by 10 pocket 3 deep, in layers of 2, with a 6 mm tool
+    /// let pocket = Pocket {
+    ///     corner: (0.0, 0.0),
+    ///     size: (20.0, 10.0),
+    ///     bottom: -3.0,
+    ///     top: 1.0,
+    ///     stepover: 2.0,
+    ///     stepdown: 2.0,
+    ///     clearing: Clearing::Zigzag,
+    /// };
+    /// let motions = pocket.motions((0.0, 0.0, 10.0), 3.0).unwrap();
+    /// // The first cut plunges at the corner, a radius in from both walls
+    /// assert!(motions[1].is_rapid() && !motions[2].is_rapid());
+    /// assert_eq!(motions[2], project_2::motion::Motion::new_linear((3.0, 3.0, 1.0), (3.0, 3.0, -1.0)));
+    /// assert_eq!(pocket.motions((0.0, 0.0, 10.0), 6.0), None);
+    /// ```
+    pub fn motions(&self, from: Point, radius: f64) -> Option<Vec<Motion>> {
+        let (x0, y0) = (self.corner.0 + radius, self.corner.1 + radius);
+        let (x1, y1) = (self.corner.0 + self.size.0 - radius, self.corner.1 + self.size.1 - radius);
+        if !(x1 >= x0 && y1 >= y0 && self.bottom < self.top && self.stepover > 0.0 && self.stepdown > 0.0) {
+            return None;
+        }
+        let path = match self.clearing {
+            Clearing::Zigzag => zigzag((x0, y0), (x1, y1), self.stepover),
+            Clearing::Spiral => spiral((x0, y0), (x1, y1), self.stepover),
+        };
+        let (start, end) = (path[0], path[path.len() - 1]);
+        let clearance = from.2.max(self.top);
+
+        let mut through = vec![
+            ((from.0, from.1, clearance), true),
+            ((start.0, start.1, clearance), true),
+        ];
+        let mut depth = self.top;
+        while depth > self.bottom {
+            depth = (depth - self.stepdown).max(self.bottom);
+            through.push(((start.0, start.1, self.top), true));
+            through.extend(path.iter().map(|&(x, y)| ((x, y, depth), false)));
+            through.push(((end.0, end.1, self.top), true));
+        }
+        through.push(((end.0, end.1, clearance), true));
+        Some(moves(from, &through))
+    }
+}
+
+// Values from `from` to `to`, both included, evenly spaced no more than `step` apart
+fn spaced(from: f64, to: f64, step: f64) -> Vec<f64> {
+    let count = ((to - from).abs() / step).ceil() as usize;
+    if count == 0 {
+        return vec![from];
+    }
+    (0..=count).map(|i| from + (to - from) * i as f64 / count as f64).collect()
+}
+
+// Rows back and forth between two corners, then once around the outside from where the rows end
+fn zigzag(low: (f64, f64), high: (f64, f64), stepover: f64) -> Vec<(f64, f64)> {
+    let mut path = Vec::new();
+    for (row, y) in spaced(low.1, high.1, stepover).into_iter().enumerate() {
+        if row % 2 == 0 {
+            path.extend([(low.0, y), (high.0, y)]);
+        } else {
+            path.extend([(high.0, y), (low.0, y)]);
+        }
+    }
+    // The rows leave cusps on the walls they turn at, which going around takes off
+    let (end, other) = if path[path.len() - 1].0 == high.0 { (high.0, low.0) } else { (low.0, high.0) };
+    path.extend([(end, low.1), (other, low.1), (other, high.1), (end, high.1)]);
+    path
+}
+
+// Rings between two corners, from the middle out
+fn spiral(low: (f64, f64), high: (f64, f64), stepover: f64) -> Vec<(f64, f64)> {
+    let middle = (high.0 - low.0).min(high.1 - low.1) / 2.0;
+    let mut path = Vec::new();
+    for inset in spaced(middle, 0.0, stepover) {
+        let (x0, y0, x1, y1) = (low.0 + inset, low.1 + inset, high.0 - inset, high.1 - inset);
+        // The innermost ring is a line when the pocket isn't square, which the tool runs along and back
+        path.extend([(x0, y0), (x1, y0), (x1, y1), (x0, y1), (x0, y0)]);
+    }
+    path
+}
+
+/// A thread milled with helical turns, inside a hole or around a boss
+///
+/// The thread is cut from its bottom up in one pass, one full turn per pitch,
+/// so a right-hand thread turns counter-clockwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thread {
+    /// X and Y of the axis of the hole or boss
+    pub center: (f64, f64),
+    /// Diameter the edge of the tool reaches: the major diameter of an
+    /// internal thread, or the minor diameter of an external one
+    pub diameter: f64,
+    /// How far the thread rises in one turn
+    pub pitch: f64,
+    /// Height of the bottom of the thread
+    pub bottom: f64,
+    /// Height the tool feeds down from, just above the stock
+    pub top: f64,
+    /// Whether the thread is cut around a boss instead of inside a hole
+    pub external: bool,
+    /// Whether the thread is left-hand, turning clockwise as it rises
+    pub left_hand: bool,
+}
+
+impl Thread {
+    /// Function to build the moves that mill the thread
+    ///
+    /// The tool rises to the top if it's below it and crosses over at rapid to
+    /// where it leads in from: the axis for an internal thread, or a tool
+    /// diameter outside the path for an external one. It feeds down to the
+    /// bottom there and across onto the path, turns up the helix until it's
+    /// at or above the top, and feeds back to where it led in from before
+    /// returning at rapid to the height it started at, or the top if that's
+    /// higher.
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Where the tool is when the cycle starts
+    /// * `radius` - Radius of the tool
+    ///
+    /// # Returns
+    ///
+    /// The cycle's motions, or `None` if the tool doesn't fit in the hole, the
+    /// bottom isn't below the top, or the diameter or pitch isn't positive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::cycles::Thread;
+    /// use project_2::motion::Motion;
+    ///
+    /// // An M10 × 1.5 thread 6 deep with a 6 mm thread mill
+    /// let thread = Thread {
+    ///     center: (0.0, 0.0),
+    ///     diameter: 10.0,
+    ///     pitch: 1.5,
+    ///     bottom: -6.0,
+    ///     top: 1.0,
+    ///     external: false,
+    ///     left_hand: false,
+    /// };
+    /// let motions = thread.motions((0.0, 0.0, 10.0), 3.0).unwrap();
+    /// // Five turns take the tool from the bottom to the top
+    /// let turns: Vec<_> = motions.iter().filter(|motion| matches!(motion, Motion::Rotational(_))).collect();
+    /// assert_eq!(turns.len(), 5);
+    /// assert_eq!(thread.motions((0.0, 0.0, 10.0), 5.0), None);
+    /// ```
+    pub fn motions(&self, from: Point, radius: f64) -> Option<Vec<Motion>> {
+        let path = if self.external { self.diameter / 2.0 + radius } else { self.diameter / 2.0 - radius };
+        if !(path > 0.0 && self.diameter > 0.0 && self.pitch > 0.0 && self.bottom < self.top) {
+            return None;
+        }
+        let (x, y) = self.center;
+        let lead = if self.external { (x + path + 2.0 * radius, y) } else { (x, y) };
+        let clearance = from.2.max(self.top);
+        // A whole number of turns that only misses the top by rounding needs no extra turn
+        let turns = ((self.top - self.bottom) / self.pitch - 1e-9).ceil() as usize;
+        let end = self.bottom + turns as f64 * self.pitch;
+
+        let mut motions = moves(
+            from,
+            &[
+                ((from.0, from.1, clearance), true),
+                ((lead.0, lead.1, clearance), true),
+                ((lead.0, lead.1, self.top), true),
+                ((lead.0, lead.1, self.bottom), false),
+                ((x + path, y, self.bottom), false),
+            ],
+        );
+        // Right-hand threads turn counter-clockwise as they rise
+        motions.extend((0..turns).map(|turn| {
+            let z = self.bottom + turn as f64 * self.pitch;
+            Motion::new_arc_from_offset((x + path, y, z), (x + path, y, z + self.pitch), (-path, 0.0), self.left_hand)
+        }));
+        motions.extend(moves(
+            (x + path, y, end),
+            &[((lead.0, lead.1, end), false), ((lead.0, lead.1, clearance.max(end)), true)],
+        ));
+        Some(motions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpolate::helical_motion_calculate;
+    use crate::motion::{LinearMotion, RotationalMotion};
+    use crate::transform;
+
+    /// Test that pecks go a step deeper each time, breaking the chip between them, and that the last is short.
+    #[test]
+    fn test_pecks() {
+        let motions = drill((0.0, 0.0, 5.0), (0.0, 0.0), -5.0, 2.0, Some(3.0)).unwrap();
+        let ends: Vec<(f64, bool)> = motions
+            .iter()
+            .map(|motion| {
+                let Motion::Linear(line) = motion else { panic!("expected a line") };
+                (line.end.2, line.rapid)
+            })
+            .collect();
+        assert_eq!(
+            ends,
+            vec![
+                (2.0, true),
+                (-1.0, false),
+                (-0.5, true),
+                (-4.0, false),
+                (-3.5, true),
+                (-5.0, false),
+                (5.0, true),
+            ]
+        );
+
+        // Without pecks the tool rises from below the retract height first, and goes back up to it
+        let motions = drill((20.0, 0.0, -1.0), (0.0, 0.0), -5.0, 2.0, None).unwrap();
+        assert_eq!(motions.len(), 4);
+        assert_eq!(motions[0], Motion::new_rapid((20.0, 0.0, -1.0), (20.0, 0.0, 2.0)));
+        assert_eq!(motions[3], Motion::new_rapid((0.0, 0.0, -5.0), (0.0, 0.0, 2.0)));
+    }
+
+    /// Test that both clearings stay inside the walls, finish on them, and cut each layer in turn.
+    #[test]
+    fn test_pocket() {
+        let mut pocket = Pocket {
+            corner: (10.0, 20.0),
+            size: (12.0, 8.0),
+            bottom: -5.0,
+            top: 0.0,
+            stepover: 1.5,
+            stepdown: 2.0,
+            clearing: Clearing::Zigzag,
+        };
+        // Zigzags end where the walls meet after the top row, spirals on the outer ring where they started it
+        for (clearing, end) in [(Clearing::Zigzag, (21.0, 27.0, -5.0)), (Clearing::Spiral, (11.0, 21.0, -5.0))] {
+            pocket.clearing = clearing;
+            let motions = pocket.motions((0.0, 0.0, 5.0), 1.0).unwrap();
+            let cuts: Vec<&LinearMotion> = motions
+                .iter()
+                .filter_map(|motion| match motion {
+                    Motion::Linear(line) if !line.rapid => Some(line),
+                    _ => None,
+                })
+                .collect();
+            assert!(cuts.iter().all(|line| (11.0..=21.0).contains(&line.end.0) && (21.0..=27.0).contains(&line.end.1)));
+            // Three plunges, to -2, -4 and the floor
+            let plunges: Vec<f64> =
+                cuts.iter().filter(|line| line.start.2 != line.end.2).map(|line| line.end.2).collect();
+            assert_eq!(plunges, vec![-2.0, -4.0, -5.0]);
+            assert_eq!(cuts[cuts.len() - 1].end, end);
+        }
+
+        // Rows are no more than the stepover apart
+        pocket.clearing = Clearing::Zigzag;
+        pocket.stepdown = 5.0;
+        let motions = pocket.motions((0.0, 0.0, 5.0), 1.0).unwrap();
+        let rows = motions
+            .iter()
+            .filter(|motion| {
+                matches!(motion, Motion::Linear(line) if line.start.1 == line.end.1 && line.start.2 == line.end.2)
+            })
+            .count();
+        // Five rows 1.5 apart across the 6 between the walls, and the two walls along X
+        assert_eq!(rows, 7);
+    }
+
+    /// Test that a thread's turns follow one helix in the right direction, outside the boss for external threads.
+    #[test]
+    fn test_thread() {
+        let mut thread = Thread {
+            center: (5.0, 5.0),
+            diameter: 20.0,
+            pitch: 2.0,
+            bottom: -4.0,
+            top: 0.5,
+            external: true,
+            left_hand: false,
+        };
+        let motions = thread.motions((0.0, 0.0, 5.0), 2.0).unwrap();
+        let turns: Vec<&RotationalMotion> = motions
+            .iter()
+            .filter_map(|motion| match motion {
+                Motion::Rotational(arc) => Some(arc),
+                _ => None,
+            })
+            .collect();
+        // Three turns rise from the bottom to 2 past it each, ending above the top
+        let heights: Vec<(f64, f64)> = turns.iter().map(|arc| arc.z.unwrap()).collect();
+        assert_eq!(heights, vec![(-4.0, -2.0), (-2.0, 0.0), (0.0, 2.0)]);
+        assert!(turns.iter().all(|arc| arc.center == (5.0, 5.0) && arc.radius == 12.0));
+        // Led in from a tool diameter outside the path, and back out there
+        assert_eq!(transform::ends(&motions[2], 5.0).1, (21.0, 5.0, -4.0));
+        assert_eq!(transform::ends(&motions[motions.len() - 2], 0.0).1, (21.0, 5.0, 2.0));
+
+        // Right- and left-hand threads turn opposite ways
+        let direction = |thread: &Thread| {
+            let motions = thread.motions((0.0, 0.0, 5.0), 2.0).unwrap();
+            let arc = motions.iter().find(|motion| matches!(motion, Motion::Rotational(_)));
+            let Some(Motion::Rotational(arc)) = arc else {
+                panic!("expected an arc")
+            };
+            // Counter-clockwise from the start, at 0°, goes up Y first
+            helical_motion_calculate(arc.clone())[1].1 > 5.0
+        };
+        assert!(direction(&thread));
+        thread.left_hand = true;
+        assert!(!direction(&thread));
+    }
+}
@@ -0,0 +1,481 @@
+//! Differences between two versions of a program
+//!
+//! When a CAM revision regenerates a program, the text can change everywhere
+//! while the path barely moves. [`diff`] compares the paths instead: it lines
+//! up the motions of both versions, reports the ones only one version has and
+//! the ones that moved, and measures how far apart the two paths get overall.
+//!
+//! Distances are measured between interpolated positions, refined along the
+//! straight pieces between them, so they're as accurate as the interpolation
+//! options are fine.
+
+use std::fmt;
+use std::mem::{discriminant, Discriminant};
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::output::geometric_points;
+
+// Largest number of cells in the table that lines up the changed middle of
+// two programs, beyond which their motions are paired in order instead
+const MAX_TABLE: usize = 16_000_000;
+
+/// How one motion differs between the old and the new version of a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// A motion only the old version has
+    Removed {
+        /// Index of the motion in the old version
+        motion: usize,
+        /// Its line in the old version, if known
+        line: Option<usize>,
+    },
+    /// A motion only the new version has
+    Added {
+        /// Index of the motion in the new version
+        motion: usize,
+        /// Its line in the new version, if known
+        line: Option<usize>,
+    },
+    /// A motion both versions have, in different places
+    Moved {
+        /// Index of the motion in the old version
+        old: usize,
+        /// Index of the motion in the new version
+        new: usize,
+        /// Its line in the old version, if known
+        old_line: Option<usize>,
+        /// Its line in the new version, if known
+        new_line: Option<usize>,
+        /// The furthest either path gets from the other
+        deviation: f64,
+    },
+}
+
+// Describe a motion by its index, and its line if it has one
+fn motion_name(motion: usize, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("motion {} (line {})", motion, line),
+        None => format!("motion {}", motion),
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Removed { motion, line } => write!(f, "- removed {}", motion_name(*motion, *line)),
+            Change::Added { motion, line } => write!(f, "+ added {}", motion_name(*motion, *line)),
+            Change::Moved {
+                old,
+                new,
+                old_line,
+                new_line,
+                deviation,
+            } => write!(
+                f,
+                "~ moved {} to {} by up to {:.3}",
+                motion_name(*old, *old_line),
+                motion_name(*new, *new_line),
+                deviation
+            ),
+        }
+    }
+}
+
+/// The differences between two versions of a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramDiff {
+    /// Motions that were removed, added or moved, in program order
+    pub changes: Vec<Change>,
+    /// Number of motions that are the same in both versions
+    pub unchanged: usize,
+    /// The furthest either whole path gets from the other
+    pub deviation: f64,
+    /// Where that furthest position is, or `None` if either path is empty
+    pub at: Option<(f64, f64, f64)>,
+}
+
+impl ProgramDiff {
+    /// Whether the two versions follow the same path
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+// Squared distance between two positions
+fn distance_squared(a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)
+}
+
+// Distance from a position to the straight piece between two others
+fn segment_distance(p: (f64, f64, f64), a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+    let ab = (b.0 - a.0, b.1 - a.1, b.2 - a.2);
+    let length_squared = ab.0 * ab.0 + ab.1 * ab.1 + ab.2 * ab.2;
+    if length_squared == 0.0 {
+        return distance_squared(p, a).sqrt();
+    }
+    let t = (((p.0 - a.0) * ab.0 + (p.1 - a.1) * ab.1 + (p.2 - a.2) * ab.2) / length_squared).clamp(0.0, 1.0);
+    distance_squared(p, (a.0 + t * ab.0, a.1 + t * ab.1, a.2 + t * ab.2)).sqrt()
+}
+
+// One coordinate of a position
+fn coordinate(position: (f64, f64, f64), axis: usize) -> f64 {
+    match axis {
+        0 => position.0,
+        1 => position.1,
+        _ => position.2,
+    }
+}
+
+// Nearest-position lookups over the positions of a path, kept in a k-d tree
+struct PathIndex {
+    // The positions of the path, in order
+    positions: Vec<(f64, f64, f64)>,
+    // Which motion each position is on, since only positions on the same motion are joined
+    motions: Vec<usize>,
+    // Indices of the positions, arranged so each range's middle splits it
+    tree: Vec<usize>,
+}
+
+impl PathIndex {
+    fn new(motion_positions: &[Vec<(f64, f64, f64)>]) -> PathIndex {
+        let positions = motion_positions.concat();
+        let motions = motion_positions.iter().enumerate().flat_map(|(i, p)| std::iter::repeat_n(i, p.len())).collect();
+        let mut tree: Vec<usize> = (0..positions.len()).collect();
+        build(&mut tree, &positions, 0);
+        PathIndex {
+            positions,
+            motions,
+            tree,
+        }
+    }
+
+    // Index of the position nearest to this one, if there are any
+    fn nearest(&self, position: (f64, f64, f64)) -> Option<usize> {
+        let mut best = None;
+        self.search(&self.tree, 0, position, &mut best);
+        best.map(|(index, _)| index)
+    }
+
+    fn search(&self, tree: &[usize], depth: usize, position: (f64, f64, f64), best: &mut Option<(usize, f64)>) {
+        if tree.is_empty() {
+            return;
+        }
+        let middle = tree.len() / 2;
+        let index = tree[middle];
+        let distance = distance_squared(position, self.positions[index]);
+        if best.is_none_or(|(_, nearest)| distance < nearest) {
+            *best = Some((index, distance));
+        }
+
+        // Search the side the position is on first, and the other only if it could be nearer
+        let axis = depth % 3;
+        let offset = coordinate(position, axis) - coordinate(self.positions[index], axis);
+        let (near, far) = if offset < 0.0 {
+            (&tree[..middle], &tree[middle + 1..])
+        } else {
+            (&tree[middle + 1..], &tree[..middle])
+        };
+        self.search(near, depth + 1, position, best);
+        if best.is_none_or(|(_, nearest)| offset * offset < nearest) {
+            self.search(far, depth + 1, position, best);
+        }
+    }
+
+    // Distance from a position to the path, or infinity if the path is empty
+    fn distance(&self, position: (f64, f64, f64)) -> f64 {
+        let Some(index) = self.nearest(position) else {
+            return f64::INFINITY;
+        };
+        // The path runs straight on from the nearest position to the ones either side on its motion
+        let mut distance = distance_squared(position, self.positions[index]).sqrt();
+        for neighbour in [index.wrapping_sub(1), index + 1] {
+            if neighbour < self.positions.len() && self.motions[neighbour] == self.motions[index] {
+                distance = distance.min(segment_distance(position, self.positions[index], self.positions[neighbour]));
+            }
+        }
+        distance
+    }
+}
+
+// Arrange indices into a k-d tree, splitting on X, Y and Z in turn
+fn build(tree: &mut [usize], positions: &[(f64, f64, f64)], depth: usize) {
+    if tree.len() <= 1 {
+        return;
+    }
+    let middle = tree.len() / 2;
+    let axis = depth % 3;
+    tree.select_nth_unstable_by(middle, |&a, &b| {
+        coordinate(positions[a], axis).total_cmp(&coordinate(positions[b], axis))
+    });
+    let (before, after) = tree.split_at_mut(middle);
+    build(before, positions, depth + 1);
+    build(&mut after[1..], positions, depth + 1);
+}
+
+// The furthest any position of one path is from the other path, and where it is
+fn furthest(from: &[(f64, f64, f64)], to: &PathIndex) -> Option<(f64, (f64, f64, f64))> {
+    from.iter()
+        .map(|&position| (to.distance(position), position))
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+// The furthest either path gets from the other, given the positions of each
+// of their motions, or `None` if either is empty
+fn deviation(old: &[Vec<(f64, f64, f64)>], new: &[Vec<(f64, f64, f64)>]) -> Option<(f64, (f64, f64, f64))> {
+    let (old_index, new_index) = (PathIndex::new(old), PathIndex::new(new));
+    if old_index.positions.is_empty() || new_index.positions.is_empty() {
+        return None;
+    }
+    let forward = furthest(&old_index.positions, &new_index)?;
+    let backward = furthest(&new_index.positions, &old_index)?;
+    Some(if backward.0 > forward.0 { backward } else { forward })
+}
+
+// The furthest two motions' positions get from each other, checking the
+// common case of matching positions first
+fn motion_deviation(old: &[(f64, f64, f64)], new: &[(f64, f64, f64)], tolerance: f64) -> f64 {
+    if old.len() == new.len() {
+        let pointwise = old.iter().zip(new).map(|(&a, &b)| distance_squared(a, b).sqrt()).fold(0.0, f64::max);
+        if pointwise <= tolerance {
+            return pointwise;
+        }
+    }
+    deviation(&[old.to_vec()], &[new.to_vec()]).map_or(f64::INFINITY, |(distance, _)| distance)
+}
+
+// What two motions must share to be lined up: their kind, whether they're
+// rapid, and where they start and end, rounded to the tolerance
+type Key = (Discriminant<Motion>, bool, [i64; 6]);
+
+fn key(motion: &Motion, positions: &[(f64, f64, f64)], tolerance: f64) -> Key {
+    let round = |value: f64| (value / tolerance).round() as i64;
+    let (start, end) = match (positions.first(), positions.last()) {
+        (Some(&start), Some(&end)) => (start, end),
+        _ => ((0.0, 0.0, 0.0), (0.0, 0.0, 0.0)),
+    };
+    let ends = [start.0, start.1, start.2, end.0, end.1, end.2].map(round);
+    (discriminant(motion), motion.is_rapid(), ends)
+}
+
+// One step of lining up two lists of motions
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Both(usize, usize),
+    Old(usize),
+    New(usize),
+}
+
+// Line up two lists of keys along their longest common subsequence, after
+// taking off the prefix and suffix they share
+fn align(old: &[Key], new: &[Key]) -> Vec<Step> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let (rows, columns) = (old_middle.len(), new_middle.len());
+
+    let mut steps: Vec<Step> = (0..prefix).map(|i| Step::Both(i, i)).collect();
+    if (rows + 1).saturating_mul(columns + 1) <= MAX_TABLE {
+        // Lengths of the longest common subsequences of every pair of suffixes
+        let width = columns + 1;
+        let mut table = vec![0u32; (rows + 1) * width];
+        for i in (0..rows).rev() {
+            for j in (0..columns).rev() {
+                table[i * width + j] = if old_middle[i] == new_middle[j] {
+                    table[(i + 1) * width + j + 1] + 1
+                } else {
+                    table[(i + 1) * width + j].max(table[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < rows || j < columns {
+            if i < rows && j < columns && old_middle[i] == new_middle[j] {
+                steps.push(Step::Both(prefix + i, prefix + j));
+                (i, j) = (i + 1, j + 1);
+            } else if j == columns || (i < rows && table[(i + 1) * width + j] >= table[i * width + j + 1]) {
+                steps.push(Step::Old(prefix + i));
+                i += 1;
+            } else {
+                steps.push(Step::New(prefix + j));
+                j += 1;
+            }
+        }
+    } else {
+        // Too large to line up, so everything in the middle is a change
+        steps.extend((prefix..prefix + rows).map(Step::Old));
+        steps.extend((prefix..prefix + columns).map(Step::New));
+    }
+    steps.extend((0..suffix).map(|k| Step::Both(old.len() - suffix + k, new.len() - suffix + k)));
+    steps
+}
+
+// The positions of each motion of a program
+fn positions_by_motion(motions: &[Motion], options: &InterpolationOptions) -> Vec<Vec<(f64, f64, f64)>> {
+    let mut positions = vec![Vec::new(); motions.len()];
+    for point in geometric_points(motions, options) {
+        positions[point.motion].push(point.position);
+    }
+    positions
+}
+
+/// Function to compare the paths of two versions of a program
+///
+/// Motions are lined up by kind and by where they start and end, keeping as
+/// many in order as possible. Motions that line up but follow different paths,
+/// and removed and added motions of the same kind between the same lined-up
+/// motions, are reported as moved, unless they stay within `tolerance` of
+/// each other.
+///
+/// # Arguments
+///
+/// * `old` - The motions of the old version
+/// * `new` - The motions of the new version
+/// * `options` - Interpolation options, which set how finely paths are compared
+/// * `tolerance` - The furthest apart two motions can be and still be the same
+///
+/// # Returns
+///
+/// The changes, and the furthest the two whole paths get from each other.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::diff::{diff, Change};
+/// use project_2::motion::Motion;
+///
+/// let old = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_line(1),
+///     Motion::new_linear((10.0, 0.0, 0.0), (10.0, 10.0, 0.0)).with_line(2),
+/// ];
+/// let new = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_line(1),
+///     Motion::new_linear((10.0, 0.0, 0.0), (10.5, 10.0, 0.0)).with_line(2),
+/// ];
+/// let differences = diff(&old, &new, &Default::default(), 0.001);
+/// assert_eq!(differences.unchanged, 1);
+/// assert!(matches!(differences.changes[..], [Change::Moved { old: 1, new: 1, .. }]));
+/// assert!((differences.deviation - 0.5).abs() < 1e-9);
+/// ```
+pub fn diff(old: &[Motion], new: &[Motion], options: &InterpolationOptions, tolerance: f64) -> ProgramDiff {
+    let old_positions = positions_by_motion(old, options);
+    let new_positions = positions_by_motion(new, options);
+    // Rounding to a tolerance of zero would divide by zero
+    let grid = tolerance.max(1e-9);
+    let old_keys: Vec<Key> = old.iter().zip(&old_positions).map(|(m, p)| key(m, p, grid)).collect();
+    let new_keys: Vec<Key> = new.iter().zip(&new_positions).map(|(m, p)| key(m, p, grid)).collect();
+
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    let mut compare = |changes: &mut Vec<Change>, i: usize, j: usize| {
+        let deviation = motion_deviation(&old_positions[i], &new_positions[j], tolerance);
+        if deviation <= tolerance {
+            unchanged += 1;
+        } else {
+            changes.push(Change::Moved {
+                old: i,
+                new: j,
+                old_line: old[i].line(),
+                new_line: new[j].line(),
+                deviation,
+            });
+        }
+    };
+
+    // Motions removed and added between the same lined-up motions are paired
+    // in order while their kinds match
+    let steps = align(&old_keys, &new_keys);
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for step in steps.into_iter().map(Some).chain([None]) {
+        match step {
+            Some(Step::Old(i)) => removed.push(i),
+            Some(Step::New(j)) => added.push(j),
+            Some(Step::Both(..)) | None => {
+                let pairs = removed.iter().zip(&added).take_while(|&(&i, &j)| old_keys[i].0 == new_keys[j].0).count();
+                for (&i, &j) in removed.iter().zip(&added).take(pairs) {
+                    compare(&mut changes, i, j);
+                }
+                changes.extend(removed[pairs..].iter().map(|&i| Change::Removed { motion: i, line: old[i].line() }));
+                changes.extend(added[pairs..].iter().map(|&j| Change::Added { motion: j, line: new[j].line() }));
+                removed.clear();
+                added.clear();
+                if let Some(Step::Both(i, j)) = step {
+                    compare(&mut changes, i, j);
+                }
+            }
+        }
+    }
+
+    let furthest = deviation(&old_positions, &new_positions);
+    ProgramDiff {
+        changes,
+        unchanged,
+        deviation: furthest.map_or(0.0, |(distance, _)| distance),
+        at: furthest.map(|(_, position)| position),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(side: f64) -> Vec<Motion> {
+        vec![
+            Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((0.0, 0.0, 0.0), (side, 0.0, 0.0)).with_line(2),
+            Motion::new_linear((side, 0.0, 0.0), (side, side, 0.0)).with_line(3),
+            Motion::new_linear((side, side, 0.0), (0.0, side, 0.0)).with_line(4),
+            Motion::new_linear((0.0, side, 0.0), (0.0, 0.0, 0.0)).with_line(5),
+        ]
+    }
+
+    /// Test that identical programs, and ones within the tolerance, have no differences.
+    #[test]
+    fn test_unchanged() {
+        let options = InterpolationOptions::default();
+        let same = diff(&square(10.0), &square(10.0), &options, 0.001);
+        assert!(same.is_empty());
+        assert_eq!((same.unchanged, same.deviation), (5, 0.0));
+
+        let close = diff(&square(10.0), &square(10.0005), &options, 0.001);
+        assert!(close.is_empty());
+        assert!(close.deviation > 0.0 && close.deviation <= 0.001);
+    }
+
+    /// Test that removed, added and moved motions are each found, and the largest deviation measured.
+    #[test]
+    fn test_changes() {
+        let options = InterpolationOptions::default();
+        let old = square(10.0);
+
+        // Drop a side and add a pass around the outside
+        let mut new = old.clone();
+        new.remove(3);
+        new.push(Motion::new_linear((0.0, 0.0, 0.0), (-2.0, 0.0, 0.0)).with_line(9));
+        let differences = diff(&old, &new, &options, 0.001);
+        assert_eq!(
+            differences.changes,
+            [
+                Change::Removed { motion: 3, line: Some(4) },
+                Change::Added { motion: 4, line: Some(9) },
+            ]
+        );
+        // The middle of the removed side is furthest from the new path, as near as its positions get
+        assert!((4.5..=5.0).contains(&differences.deviation));
+        assert!(matches!(differences.at, Some((_, y, _)) if y == 10.0));
+
+        // Bow one side out by a millimeter
+        let mut new = old.clone();
+        new[2] = Motion::new_linear((10.0, 0.0, 0.0), (11.0, 10.0, 0.0)).with_line(3);
+        let differences = diff(&old, &new, &options, 0.001);
+        assert_eq!(differences.unchanged, 4);
+        match &differences.changes[..] {
+            [change @ Change::Moved { deviation, .. }] => {
+                assert!((deviation - 1.0).abs() < 1e-9);
+                assert_eq!(change.to_string(), "~ moved motion 2 (line 3) to motion 2 (line 3) by up to 1.000");
+            }
+            changes => panic!("expected one moved motion, got {:?}", changes),
+        }
+    }
+}
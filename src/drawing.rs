@@ -0,0 +1,191 @@
+//! Flat drawings turned into programs
+//!
+//! Plotter and laser work is usually designed as a drawing: strokes of
+//! lines, curves and circular arcs in the plane, with the pen lifted between
+//! them. A [`Drawing`] holds those strokes as they were read, such as from an
+//! SVG file with [`read_svg`](crate::svg::read_svg), and
+//! [`Drawing::motions`] turns them into a program that draws or cuts each
+//! stroke at one depth, rising to a clearance height to travel between them.
+//! Arcs stay arcs and curves stay Bézier curves, so nothing is lost to
+//! flattening until the program is interpolated.
+
+use crate::motion::Motion;
+use crate::transform::approach;
+
+type Point = (f64, f64, f64);
+
+/// One piece of a stroke, running on from where the piece before it ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment {
+    /// A straight line to a point
+    Line((f64, f64)),
+    /// A cubic Bézier curve through two control points to the last point
+    Bezier((f64, f64), (f64, f64), (f64, f64)),
+    /// A circular arc around `center` to `end`, which is a full circle if it ends where it starts
+    Arc {
+        center: (f64, f64),
+        clockwise: bool,
+        end: (f64, f64),
+    },
+}
+
+/// A path drawn without lifting the pen
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stroke {
+    /// Where the stroke starts
+    pub start: (f64, f64),
+    /// The pieces of the stroke, in the order they're drawn
+    pub segments: Vec<Segment>,
+}
+
+impl Stroke {
+    /// Where the stroke ends
+    pub fn end(&self) -> (f64, f64) {
+        match self.segments.last() {
+            Some(Segment::Line(end) | Segment::Bezier(_, _, end) | Segment::Arc { end, .. }) => *end,
+            None => self.start,
+        }
+    }
+}
+
+/// The strokes of a drawing, in the order they're drawn
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Drawing {
+    pub strokes: Vec<Stroke>,
+}
+
+/// Options controlling how a drawing becomes a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DrawingOptions {
+    /// Height every stroke is drawn or cut at
+    pub depth: f64,
+    /// Height the tool rises to between strokes
+    pub clearance: f64,
+    /// Feed rate of the strokes and of plunging to the depth, in units per minute
+    pub feed: f64,
+    /// Spindle speed or laser power of the strokes, if they need one
+    pub power: Option<f64>,
+}
+
+impl Default for DrawingOptions {
+    fn default() -> Self {
+        DrawingOptions {
+            depth: 0.0,
+            clearance: 5.0,
+            feed: 1000.0,
+            power: None,
+        }
+    }
+}
+
+impl Drawing {
+    /// Function to turn the drawing into a program
+    ///
+    /// From the origin, the tool rises to the clearance height and crosses
+    /// over to each stroke with rapids, feeds down to the depth, and draws the
+    /// stroke. It rises again after the last one. Lines become straight cuts,
+    /// curves Bézier motions and arcs rotational motions, all at the depth.
+    /// Strokes with nothing to draw are left out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::drawing::{Drawing, DrawingOptions, Segment, Stroke};
+    /// use project_2::motion::Motion;
+    ///
+    /// let stroke = Stroke { start: (10.0, 0.0), segments: vec![Segment::Line((20.0, 0.0))] };
+    /// let drawing = Drawing { strokes: vec![stroke] };
+    /// let options = DrawingOptions { depth: -1.0, clearance: 2.0, feed: 300.0, power: None };
+    /// let motions = drawing.motions(&options);
+    /// assert_eq!(
+    ///     motions,
+    ///     vec![
+    ///         Motion::new_rapid((0.0, 0.0, 0.0), (0.0, 0.0, 2.0)),
+    ///         Motion::new_rapid((0.0, 0.0, 2.0), (10.0, 0.0, 2.0)),
+    ///         Motion::new_linear((10.0, 0.0, 2.0), (10.0, 0.0, -1.0)).with_feed(Some(300.0)),
+    ///         Motion::new_linear((10.0, 0.0, -1.0), (20.0, 0.0, -1.0)).with_feed(Some(300.0)),
+    ///         Motion::new_rapid((20.0, 0.0, -1.0), (20.0, 0.0, 2.0)),
+    ///     ]
+    /// );
+    /// ```
+    pub fn motions(&self, options: &DrawingOptions) -> Vec<Motion> {
+        let z = options.depth;
+        let cut = |motion: Motion| motion.with_feed(Some(options.feed)).with_power(options.power);
+
+        let mut motions = Vec::new();
+        let mut position: Point = (0.0, 0.0, 0.0);
+        for stroke in self.strokes.iter().filter(|stroke| !stroke.segments.is_empty()) {
+            let (x, y) = stroke.start;
+            let above = (x, y, options.clearance);
+            motions.extend(approach(position, above, options.clearance));
+            let start = (x, y, z);
+            if above != start {
+                motions.push(cut(Motion::new_linear(above, start)));
+            }
+            position = start;
+
+            for segment in &stroke.segments {
+                let (motion, end) = match *segment {
+                    Segment::Line((x, y)) => {
+                        let end = (x, y, z);
+                        (Motion::new_linear(position, end), end)
+                    }
+                    Segment::Bezier((x1, y1), (x2, y2), (x, y)) => {
+                        let end = (x, y, z);
+                        (Motion::new_bezier(position, (x1, y1, z), (x2, y2, z), end), end)
+                    }
+                    Segment::Arc {
+                        center,
+                        clockwise,
+                        end: (x, y),
+                    } => {
+                        let end = (x, y, z);
+                        let offset = (center.0 - position.0, center.1 - position.1);
+                        (Motion::new_arc_from_offset(position, end, offset, clockwise), end)
+                    }
+                };
+                motions.push(cut(motion));
+                position = end;
+            }
+        }
+        // Leave the tool clear of the work
+        if position.2 < options.clearance && !motions.is_empty() {
+            let above = (position.0, position.1, options.clearance);
+            motions.push(Motion::new_rapid(position, above));
+        }
+        motions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::ends;
+
+    /// Test that arcs and curves are drawn at the depth, and strokes without segments are skipped.
+    #[test]
+    fn test_arcs_and_curves() {
+        let drawing = Drawing {
+            strokes: vec![
+                Stroke { start: (5.0, 5.0), segments: Vec::new() },
+                Stroke {
+                    start: (10.0, 0.0),
+                    segments: vec![
+                        Segment::Arc { center: (0.0, 0.0), clockwise: false, end: (0.0, 10.0) },
+                        Segment::Bezier((0.0, 15.0), (5.0, 20.0), (10.0, 20.0)),
+                    ],
+                },
+            ],
+        };
+        let options = DrawingOptions { depth: 0.0, clearance: 0.0, feed: 600.0, power: Some(800.0) };
+        let motions = drawing.motions(&options);
+        assert_eq!(motions.len(), 3);
+        assert_eq!(motions[0], Motion::new_rapid((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)));
+        let Motion::Rotational(arc) = &motions[1] else { panic!("expected an arc") };
+        assert_eq!((arc.center, arc.radius), ((0.0, 0.0), 10.0));
+        let (start, end) = ends(&motions[1], 0.0);
+        assert!((start.0 - 10.0).abs() < 1e-9 && (end.1 - 10.0).abs() < 1e-9);
+        assert!(matches!(&motions[2], Motion::Bezier(curve) if curve.end == (10.0, 20.0, 0.0)));
+        assert!(motions[1..].iter().all(|motion| motion.power() == Some(800.0) && motion.feed() == Some(600.0)));
+    }
+}
@@ -0,0 +1,483 @@
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::binary::Sample;
+
+/// Column span of the offending text within a line
+///
+/// Columns are zero-based byte offsets into the line, with `start` inclusive
+/// and `end` exclusive. They are displayed one-based, the way editors count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span(pub Range<usize>);
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.0.start + 1, self.0.end.max(self.0.start + 1))
+    }
+}
+
+/// Error raised while parsing a single line of a program
+///
+/// Every variant carries the one-based line number and the column span of the
+/// text that caused the failure, so callers can point at the exact problem.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ParseError {
+    /// The first word of the line is not a known command
+    #[error("line {line}, column {span}: unknown command `{command}`")]
+    UnknownCommand { line: usize, span: Span, command: String },
+
+    /// A G-code the G-code reader doesn't handle, which would move the tool other than the path says
+    #[error("line {line}, column {span}: `{code}` is not supported")]
+    UnsupportedGCode { line: usize, span: Span, code: String },
+
+    /// The command was given fewer arguments than it needs
+    #[error("line {line}, column {span}: `{command}` expects {expected} arguments, found {found}")]
+    MissingArguments {
+        line: usize,
+        span: Span,
+        command: String,
+        expected: usize,
+        found: usize,
+    },
+
+    /// An argument's letter is not valid for the command
+    #[error("line {line}, column {span}: unexpected word `{word}` for `{command}`")]
+    UnexpectedWord {
+        line: usize,
+        span: Span,
+        command: String,
+        word: String,
+    },
+
+    /// An argument could not be parsed as a number (strict mode only)
+    #[error("line {line}, column {span}: invalid number `{text}`")]
+    InvalidNumber { line: usize, span: Span, text: String },
+
+    /// A `REPEAT` or `SUB` block has no matching `END` or `ENDSUB`
+    #[error("line {line}, column {span}: `{command}` is never closed")]
+    UnclosedBlock { line: usize, span: Span, command: String },
+
+    /// An `END` or `ENDSUB` doesn't close the innermost open block
+    #[error("line {line}, column {span}: `{command}` without a matching block")]
+    UnmatchedEnd { line: usize, span: Span, command: String },
+
+    /// A `CALL` names a subroutine that is never defined
+    #[error("line {line}, column {span}: unknown subroutine `{name}`")]
+    UnknownSubroutine { line: usize, span: Span, name: String },
+
+    /// A subroutine calls itself, directly or through other subroutines
+    #[error("line {line}, column {span}: subroutine `{name}` calls itself")]
+    RecursiveCall { line: usize, span: Span, name: String },
+
+    /// Loops and subroutine calls that would make the program too large to hold
+    #[error("line {line}, column {span}: the program grows past {limit} bytes once its loops and calls are expanded")]
+    ExpansionTooLarge { line: usize, span: Span, limit: usize },
+
+    /// `USE WCS` names a work offset that was never defined
+    #[error("line {line}, column {span}: work offset {number} is not defined")]
+    UnknownWorkOffset { line: usize, span: Span, number: u32 },
+
+    /// `TOOL` names a tool that isn't in the tool table
+    #[error("line {line}, column {span}: tool {number} is not in the tool table")]
+    UnknownTool { line: usize, span: Span, number: u32 },
+
+    /// `COMP LEFT`, `COMP RIGHT`, `POCKET` or `THREAD` without a tool whose diameter is known
+    #[error("line {line}, column {span}: `{command}` needs a tool with a diameter")]
+    NoToolDiameter { line: usize, span: Span, command: String },
+
+    /// Cutter compensation and a plane other than XY are used together
+    #[error("line {line}, column {span}: cutter compensation only works in the XY plane")]
+    CompensationPlane { line: usize, span: Span },
+
+    /// A Bézier curve or spline while cutter compensation is on, which can't offset it
+    #[error("line {line}, column {span}: cutter compensation can't offset Bézier curves or splines")]
+    CompensatedCurve { line: usize, span: Span },
+
+    /// The control points, weights and knots of a spline don't fit together
+    #[error("line {line}, column {span}: {error}")]
+    InvalidSpline { line: usize, span: Span, error: SplineError },
+
+    /// `ROTATE`, `SCALE` or `MIRROR` would turn an arc out of the planes arcs can lie in, or stretch it
+    #[error("line {line}, column {span}: the active ROTATE, SCALE or MIRROR would tilt this arc out of the XY, XZ and YZ planes or stretch it into an ellipse")]
+    TransformedArc { line: usize, span: Span },
+
+    /// A G2/G3 arc without its center's offset along either axis of its plane
+    #[error("line {line}, column {span}: arcs need the center's offset from the start along the axes of their plane, as I, J or K")]
+    MissingArcCenter { line: usize, span: Span },
+
+    /// A G2/G3 arc whose end point is a different distance from the center than its start point
+    #[error("line {line}, column {span}: the arc starts {start_radius} from its center but ends {end_radius} from it")]
+    ArcEndOffCircle {
+        line: usize,
+        span: Span,
+        start_radius: f64,
+        end_radius: f64,
+    },
+
+    /// An arc given by its center and angles that doesn't start where the tool is
+    #[error("line {line}, column {span}: the arc starts at {start:?}, but the tool is at {position:?}")]
+    ArcStartOffCircle {
+        line: usize,
+        span: Span,
+        position: (f64, f64),
+        start: (f64, f64),
+    },
+
+    /// An arc given by its radius whose end points are too far apart for it, or are the same point
+    #[error("line {line}, column {span}: an arc of radius {radius} can't join points {chord} apart")]
+    ArcRadius {
+        line: usize,
+        span: Span,
+        radius: f64,
+        chord: f64,
+    },
+
+    /// A three-point arc whose points lie on one line, so no circle passes through them
+    #[error("line {line}, column {span}: the three points of the arc lie on one line")]
+    CollinearArc { line: usize, span: Span },
+
+    /// A Y coordinate in a lathe program, which has no Y axis
+    #[error("line {line}, column {span}: lathes have no Y axis")]
+    LatheY { line: usize, span: Span },
+
+    /// A custom command's handler turned its line down
+    #[error("line {line}, column {span}: `{command}` failed: {message}")]
+    CommandFailed {
+        line: usize,
+        span: Span,
+        command: String,
+        message: String,
+    },
+
+    /// A drilling or other cycle whose dimensions don't make a cycle
+    #[error("line {line}, column {span}: `{command}` {reason}")]
+    InvalidCycle {
+        line: usize,
+        span: Span,
+        command: String,
+        reason: String,
+    },
+}
+
+impl ParseError {
+    /// One-based line number the error occurred on
+    pub fn line(&self) -> usize {
+        match self {
+            ParseError::UnknownCommand { line, .. }
+            | ParseError::UnsupportedGCode { line, .. }
+            | ParseError::MissingArguments { line, .. }
+            | ParseError::UnexpectedWord { line, .. }
+            | ParseError::InvalidNumber { line, .. }
+            | ParseError::UnclosedBlock { line, .. }
+            | ParseError::UnmatchedEnd { line, .. }
+            | ParseError::UnknownSubroutine { line, .. }
+            | ParseError::RecursiveCall { line, .. }
+            | ParseError::ExpansionTooLarge { line, .. }
+            | ParseError::UnknownWorkOffset { line, .. }
+            | ParseError::UnknownTool { line, .. }
+            | ParseError::NoToolDiameter { line, .. }
+            | ParseError::CompensationPlane { line, .. }
+            | ParseError::CompensatedCurve { line, .. }
+            | ParseError::InvalidSpline { line, .. }
+            | ParseError::TransformedArc { line, .. }
+            | ParseError::MissingArcCenter { line, .. }
+            | ParseError::ArcEndOffCircle { line, .. }
+            | ParseError::ArcStartOffCircle { line, .. }
+            | ParseError::ArcRadius { line, .. }
+            | ParseError::CollinearArc { line, .. }
+            | ParseError::LatheY { line, .. }
+            | ParseError::CommandFailed { line, .. }
+            | ParseError::InvalidCycle { line, .. } => *line,
+        }
+    }
+
+    /// Column span of the offending text
+    pub fn span(&self) -> &Span {
+        match self {
+            ParseError::UnknownCommand { span, .. }
+            | ParseError::UnsupportedGCode { span, .. }
+            | ParseError::MissingArguments { span, .. }
+            | ParseError::UnexpectedWord { span, .. }
+            | ParseError::InvalidNumber { span, .. }
+            | ParseError::UnclosedBlock { span, .. }
+            | ParseError::UnmatchedEnd { span, .. }
+            | ParseError::UnknownSubroutine { span, .. }
+            | ParseError::RecursiveCall { span, .. }
+            | ParseError::ExpansionTooLarge { span, .. }
+            | ParseError::UnknownWorkOffset { span, .. }
+            | ParseError::UnknownTool { span, .. }
+            | ParseError::NoToolDiameter { span, .. }
+            | ParseError::CompensationPlane { span, .. }
+            | ParseError::CompensatedCurve { span, .. }
+            | ParseError::InvalidSpline { span, .. }
+            | ParseError::TransformedArc { span, .. }
+            | ParseError::MissingArcCenter { span, .. }
+            | ParseError::ArcEndOffCircle { span, .. }
+            | ParseError::ArcStartOffCircle { span, .. }
+            | ParseError::ArcRadius { span, .. }
+            | ParseError::CollinearArc { span, .. }
+            | ParseError::LatheY { span, .. }
+            | ParseError::CommandFailed { span, .. }
+            | ParseError::InvalidCycle { span, .. } => span,
+        }
+    }
+}
+
+/// Why a spline's control points, weights and knots don't describe a curve
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SplineError {
+    /// Splines need a degree of at least one
+    #[error("a spline's degree must be at least 1")]
+    ZeroDegree,
+
+    /// There are fewer control points than the degree needs
+    #[error("a degree {degree} spline needs at least {} control points, found {found}", degree + 1)]
+    TooFewPoints { degree: usize, found: usize },
+
+    /// The number of weights doesn't match the number of control points
+    #[error("expected {expected} weights, one per control point, found {found}")]
+    WeightCount { expected: usize, found: usize },
+
+    /// A weight is zero or negative
+    #[error("weights must be positive, found {weight}")]
+    NonPositiveWeight { weight: f64 },
+
+    /// The number of knots doesn't match the control points and degree
+    #[error("expected {expected} knots, found {found}")]
+    KnotCount { expected: usize, found: usize },
+
+    /// The knots decrease somewhere, or leave the curve no room to run
+    #[error("knots must never decrease, and must grow across the curve")]
+    InvalidKnots,
+}
+
+/// Top-level error type for reading and interpreting programs
+#[derive(Debug, Error)]
+pub enum CmmdError {
+    /// The program could not be read from disk
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The program text is malformed
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+
+    /// A program written as JSON is malformed
+    #[error("invalid JSON program: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// A motion needs a feed rate for timing but none was programmed
+    #[error("motion {motion} has no feed rate; program one with an F command")]
+    MissingFeedRate { motion: usize },
+
+    /// A file pulled in with `INCLUDE` is malformed
+    #[error("{}: {error}", file.display())]
+    Included { file: PathBuf, error: ParseError },
+
+    /// A file pulled in with `INCLUDE` could not be read
+    #[error("can't include {}: {source}", file.display())]
+    Include { file: PathBuf, source: io::Error },
+
+    /// A file ends up including itself
+    #[error("{} includes itself", file.display())]
+    IncludeCycle { file: PathBuf },
+
+    /// `INCLUDE` was used where files can't be read, such as a program sent over the network
+    #[error("line {line}: INCLUDE isn't allowed here")]
+    IncludeNotAllowed { line: usize },
+
+    /// Cutter compensation can't fit the tool inside an arc or corner of the path
+    #[error("line {line}: the tool is too large for the compensated path")]
+    ToolTooLarge { line: usize },
+
+    /// A command that needs the whole program turned up while reading it one line at a time
+    #[error("line {line}: {command} needs the whole program, so it can't be read one line at a time")]
+    NotStreamable { line: usize, command: String },
+}
+
+/// Error type for streaming a program to a controller
+#[derive(Debug, Error)]
+pub enum StreamError {
+    /// The connection to the controller failed
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The controller rejected a line
+    #[error("controller rejected line {line} `{text}`: {message}")]
+    Rejected { line: usize, text: String, message: String },
+
+    /// The controller stopped answering before the program finished
+    #[error("controller disconnected after line {line}")]
+    Disconnected { line: usize },
+}
+
+/// Error type for reading configuration files such as tool tables
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// The file could not be read
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The file isn't valid TOML or doesn't have the expected fields
+    #[error("invalid configuration: {0}")]
+    Toml(#[from] toml::de::Error),
+
+    /// A tool table entry isn't numbered with a whole number
+    #[error("invalid tool number `{0}`")]
+    InvalidToolNumber(String),
+
+    /// The axis remapping sends one program axis to several output axes
+    #[error("invalid axis remapping: program axis {0} is used more than once")]
+    RepeatedAxis(char),
+
+    /// A height map's grid can't be read between its probe points
+    #[error("invalid height map: {0}")]
+    InvalidHeightMap(String),
+}
+
+/// Error type for loading and running user scripts
+#[derive(Debug, Error)]
+pub enum ScriptError {
+    /// The script file could not be read
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// The script isn't valid, or failed while running its top level
+    #[error("{0}")]
+    Compile(String),
+
+    /// A hook failed while running
+    #[error("`{hook}` failed: {message}")]
+    Run { hook: &'static str, message: String },
+
+    /// A hook gave back something it isn't allowed to
+    #[error("`{hook}` returned {found}, expected {expected}")]
+    BadReturn {
+        hook: &'static str,
+        found: String,
+        expected: &'static str,
+    },
+}
+
+/// A motion that takes an axis past the machine's travel
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{}motion {motion} takes {axis} to {position:.3}, past its {bound} limit of {limit}", line.map(|line| format!("line {}: ", line)).unwrap_or_default())]
+pub struct LimitViolation {
+    /// Index of the motion, counted from zero
+    pub motion: usize,
+    /// Line of the program the motion came from, if known
+    pub line: Option<usize>,
+    /// The axis that goes too far: 'X', 'Y' or 'Z'
+    pub axis: char,
+    /// `"min"` or `"max"`, whichever limit is exceeded
+    pub bound: &'static str,
+    /// The configured limit, in millimeters
+    pub limit: f64,
+    /// The furthest the motion goes past it, in millimeters
+    pub position: f64,
+}
+
+/// A point the machine's arm can't reach, too far from its base or too close to it
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{}motion {motion} goes to ({:.3}, {:.3}), out of the arm's reach", line.map(|line| format!("line {}: ", line)).unwrap_or_default(), position.0, position.1)]
+pub struct Unreachable {
+    /// Index of the motion, counted from zero
+    pub motion: usize,
+    /// Line of the program the motion came from, if known
+    pub line: Option<usize>,
+    /// The first point of the motion out of reach, in millimeters
+    pub position: (f64, f64, f64),
+}
+
+/// A motion that takes the tool into one of the machine's keep-out zones
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{}motion {motion} enters {zone} at ({:.3}, {:.3}, {:.3})", line.map(|line| format!("line {}: ", line)).unwrap_or_default(), position.0, position.1, position.2)]
+pub struct Intrusion {
+    /// Index of the motion, counted from zero
+    pub motion: usize,
+    /// Line of the program the motion came from, if known
+    pub line: Option<usize>,
+    /// The zone's name, or `keep-out zone n` counting from one for zones without one
+    pub zone: String,
+    /// Where the motion first enters the zone, in millimeters
+    pub position: (f64, f64, f64),
+}
+
+/// An arc that a transform would turn out of the XY, XZ and YZ planes, or stretch into an ellipse
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{}motion {motion} is an arc the transform would tilt out of the XY, XZ and YZ planes or stretch into an ellipse", line.map(|line| format!("line {}: ", line)).unwrap_or_default())]
+pub struct DistortedArc {
+    /// Index of the motion, counted from zero
+    pub motion: usize,
+    /// Line of the program the motion came from, if known
+    pub line: Option<usize>,
+}
+
+/// A motion that doesn't start where the previous one ended
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("{}motion {motion} starts at {to:?}, but the previous motion ended at {from:?}", line.map(|line| format!("line {}: ", line)).unwrap_or_default())]
+pub struct Discontinuity {
+    /// Index of the motion, counted from zero
+    pub motion: usize,
+    /// Line of the program the motion came from, if known
+    pub line: Option<usize>,
+    /// Where the previous motion ended
+    pub from: (f64, f64, f64),
+    /// Where the motion starts
+    pub to: (f64, f64, f64),
+}
+
+/// The first sample of a program's output that doesn't match the expected output
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// Index of the sample, counted from zero
+    pub sample: usize,
+    /// Index of the motion the sample is on, if known
+    pub motion: Option<usize>,
+    /// Line of the program the motion came from, if known
+    pub line: Option<usize>,
+    /// The expected sample, or `None` if the output goes on past the expected end
+    pub expected: Option<Sample>,
+    /// The sample output, or `None` if the output ends early
+    pub actual: Option<Sample>,
+}
+
+// A sample's position, with its time if it has one
+fn describe(sample: &Sample) -> String {
+    let (x, y, z) = sample.position;
+    match sample.time {
+        Some(time) => format!("({:.6}, {:.6}, {:.6}) at {:.6}s", x, y, z, time),
+        None => format!("({:.6}, {:.6}, {:.6})", x, y, z),
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}: ", line)?;
+        }
+        write!(f, "sample {}", self.sample)?;
+        if let Some(motion) = self.motion {
+            write!(f, " (motion {})", motion)?;
+        }
+        match (&self.actual, &self.expected) {
+            (Some(actual), Some(expected)) => {
+                write!(f, " is {}, expected {}", describe(actual), describe(expected))?;
+                // Velocities only matter when the positions and times match
+                if let (Some(velocity), Some(expected)) = (actual.velocity, expected.velocity) {
+                    write!(f, ", moving at {:?}, expected {:?}", velocity, expected)?;
+                }
+                Ok(())
+            }
+            (Some(actual), None) => write!(f, " at {} is past the end of the expected output", describe(actual)),
+            (None, Some(expected)) => write!(f, ", expected {}, is missing from the output", describe(expected)),
+            (None, None) => write!(f, " differs"),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
@@ -0,0 +1,970 @@
+//! RS-274 (G-code) front-end
+//!
+//! Reads `G0`/`G1`/`G2`/`G3` moves into the same [`Motion`] values the
+//! `.cmmd` parser produces, so the rest of the pipeline does not care which
+//! language a program was written in; `G0` moves become rapids. `F` words set the feed rate of the
+//! motions that follow and `S` words their spindle speed or laser power.
+//! `G90`/`G91` switch between absolute and incremental coordinates, and
+//! `G20`/`G21` between inches and millimeters. `G54`-`G59` select work
+//! offsets 1-6 from [`ParseOptions::work_offsets`] (offsets that aren't listed
+//! are zero). Motions are always returned in millimeters, in machine
+//! coordinates. `G17`, `G18` and `G19` put the arcs that follow in the XY, XZ
+//! or YZ plane, with their centers given by `I`/`J`, `I`/`K` or `J`/`K`. `G5`
+//! draws a cubic Bézier curve in XY, with `I`/`J` the offset of its first
+//! control point from the start and `P`/`Q` that of its second from the end.
+//! `G5.2` starts a NURBS curve at the tool's position, with `L` its order
+//! (degree + 1, 4 by default); the coordinates on it and on the lines after it
+//! are control points, weighted by `P` (1 by default), until `G5.3` ends the
+//! curve. `A`, `B` and `C` words on `G0` and `G1` lines turn the rotary axes,
+//! in degrees, and `I`, `J` and `K` words on them point the tool along that
+//! direction, like G43.5. `T` words select a tool and `M6` changes to it,
+//! which puts a [`Motion::ToolChange`] among the motions. `G43` adds the length
+//! of tool `H` (the selected tool without an `H` word) from
+//! [`ParseOptions::tools`] to the Z words that follow, and `G49` takes it off
+//! again. `M7`, `M8`, `M9` and `M100`-`M199` (see [`is_aux_code`]) become
+//! [`Motion::Aux`] events, after any tool change and before any move on the
+//! line. `M0` and `M1` become [`Motion::Pause`] events after the line's move.
+//! Other words that don't affect the path (other M-codes, line numbers)
+//! are accepted and ignored. `G40`, `G80` and `G94` are accepted too, since
+//! they ask for what the reader does anyway, but any other G-code, such as a
+//! canned cycle or `G28`, is an error: its axis words would otherwise run as
+//! an ordinary move.
+//!
+//! With [`ParseOptions::lathe`] set, X words are diameters, Y words are
+//! rejected and arcs start out in the XZ plane, as on a lathe. Arc center
+//! offsets stay radii.
+//!
+//! Arcs are converted with [`Motion::new_arc_from_offset`], the same way
+//! `.cmmd` arcs written in I/J form are, or with
+//! [`Motion::new_arc_from_radius`] if they give an `R` word instead of their
+//! center. An arc without either, whose end point is further than
+//! [`ARC_TOLERANCE`] (or 0.1% of the radius) off the circle through its
+//! start, or whose radius can't reach its end point, is an error rather than
+//! a guess.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead};
+
+use crate::error::{CmmdError, ParseError, Span};
+use crate::machine::MachineState;
+use crate::motion::{Motion, Plane, Rotary};
+use crate::parser::{DistanceMode, ParseOptions, Units};
+
+/// Function to tell whether an M-code is an auxiliary command that is passed on rather than ignored
+///
+/// These are the coolant codes `M7`, `M8` and `M9` and the user-defined
+/// `M100`-`M199`.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::gcode::is_aux_code;
+///
+/// assert!(is_aux_code(8) && is_aux_code(101));
+/// assert!(!is_aux_code(3));
+/// ```
+pub fn is_aux_code(code: u32) -> bool {
+    matches!(code, 7..=9 | 100..=199)
+}
+
+/// How far, in millimeters, an arc's end point may be off the circle through its start
+pub const ARC_TOLERANCE: f64 = 0.002;
+
+/// A single G-code word such as `G1` or `X-2.5`
+#[derive(Debug, Clone, Copy)]
+struct Word<'a> {
+    letter: char,
+    number: &'a str,
+    span: (usize, usize),
+}
+
+impl Word<'_> {
+    // Span covering this word
+    fn span(&self) -> Span {
+        Span(self.span.0..self.span.1)
+    }
+
+    // Parse the number of the word, falling back to 0.0 outside of strict mode
+    fn value(&self, line_number: usize, options: &ParseOptions) -> Result<f64, ParseError> {
+        match self.number.parse::<f64>() {
+            // Strict mode also turns down `inf`, `nan` and numbers too large to be finite
+            Ok(value) if !options.strict || value.is_finite() => Ok(value),
+            _ if !options.strict => Ok(0.0),
+            _ => Err(ParseError::InvalidNumber {
+                line: line_number,
+                span: self.span(),
+                text: format!("{}{}", self.letter, self.number),
+            }),
+        }
+    }
+}
+
+// Split a line into words, dropping `( ... )` and `;` comments
+//
+// G-code doesn't require spaces between words, so `G1X5Y2` is three words.
+fn words(line: &str) -> Vec<Word<'_>> {
+    let mut words = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == ';' {
+            break;
+        } else if c == '(' {
+            // Skip to the end of the inline comment
+            for (_, c) in chars.by_ref() {
+                if c == ')' {
+                    break;
+                }
+            }
+        } else if c.is_ascii_alphabetic() {
+            // Collect the number that follows the letter
+            let start = i + c.len_utf8();
+            let mut end = start;
+            while let Some(&(j, d)) = chars.peek() {
+                if d.is_ascii_digit() || d == '.' || d == '-' || d == '+' {
+                    end = j + d.len_utf8();
+                    chars.next();
+                } else if d == ' ' || d == '\t' {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            words.push(Word {
+                letter: c.to_ascii_uppercase(),
+                number: line[start..end].trim(),
+                span: (i, end.max(start)),
+            });
+        }
+    }
+    words
+}
+
+/// Modal state carried from one G-code line to the next
+#[derive(Debug, Clone)]
+struct State {
+    // Active motion mode (0, 1, 2 or 3), if any
+    motion_mode: Option<u32>,
+    // Current tool position
+    position: (f64, f64, f64),
+    // Feed rate and power set by the last F and S words
+    machine: MachineState,
+    // Set by G90 and G91
+    distance: DistanceMode,
+    // Set by G20 and G21
+    units: Units,
+    // Offset of the work coordinate system selected by G54-G59
+    work_offset: (f64, f64, f64),
+    // Arc plane set by G17, G18 and G19
+    plane: Plane,
+    // A G5.2 spline still being read
+    spline: Option<SplineBlock>,
+    // Angles of the rotary axes, once an A, B or C word has turned one
+    rotary: Option<Rotary>,
+    // Extruder position, once an E word has moved it
+    extrusion: Option<f64>,
+    // Whether E words are relative, set by M83 and G91 and cleared by M82 and G90
+    relative_extrusion: bool,
+    // Direction the tool points in, once I, J and K words on a G0 or G1 line have tilted it
+    tool_vector: Option<(f64, f64, f64)>,
+    // Tool selected by the last T word
+    tool: Option<u32>,
+    // Tool length added to Z by G43, until G49
+    tool_length: f64,
+    // Tool change and auxiliary commands of the current line, which come before any move on it
+    events: Vec<Motion>,
+    // Whether the current line stops after its move, and if so whether the stop is optional (M1)
+    pause: Option<bool>,
+}
+
+/// Control points of a `G5.2` spline, gathered until `G5.3`
+#[derive(Debug, Clone)]
+struct SplineBlock {
+    // Degree + 1, from the L word
+    order: usize,
+    controls: Vec<(f64, f64, f64)>,
+    weights: Vec<f64>,
+}
+
+// Check that an arc ends on the circle through its start, around the center at `offset` from the start
+//
+// The points are in the arc's plane, with only their first two coordinates counting.
+pub(crate) fn check_arc_end(
+    line_number: usize,
+    span: Span,
+    start: (f64, f64, f64),
+    end: (f64, f64, f64),
+    offset: (f64, f64, f64),
+) -> Result<(), ParseError> {
+    let start_radius = offset.0.hypot(offset.1);
+    let end_radius = (end.0 - start.0 - offset.0).hypot(end.1 - start.1 - offset.1);
+    if start_radius == 0.0 || (end_radius - start_radius).abs() > ARC_TOLERANCE.max(start_radius * 1e-3) {
+        return Err(ParseError::ArcEndOffCircle {
+            line: line_number,
+            span,
+            start_radius,
+            end_radius,
+        });
+    }
+    Ok(())
+}
+
+/// Function to parse a single line of G-code
+///
+/// # Arguments
+///
+/// * `line_number` - The one-based line number, used for error reporting
+/// * `line` - The text of the line
+/// * `state` - Modal state, updated by the line
+/// * `options` - Parsing options
+///
+/// # Errors
+///
+/// Returns a `ParseError` for unsupported motion codes, or in strict mode for
+/// words whose number can't be parsed.
+fn parse_line(
+    line_number: usize,
+    line: &str,
+    state: &mut State,
+    options: &ParseOptions,
+) -> Result<Option<Motion>, ParseError> {
+    let mut target = (None, None, None);
+    // Rotary axis angles from A, B and C words, in degrees
+    let mut turn = (None, None, None);
+    let mut offset = (0.0, 0.0, 0.0);
+    // Which of I, J and K were given, as 1.0 or 0.0 so they can be put in the arc's plane like the offset
+    let mut given = (0.0, 0.0, 0.0);
+    // Whether I, J or K was given, which on G0 and G1 lines is the tool's direction
+    let mut tilted = false;
+    // Offset of a G5 curve's second control point from its end
+    let mut end_offset = (0.0, 0.0);
+    // Radius of an arc given in R form instead of by its center
+    let mut radius = None;
+    let mut feed = None;
+    let mut has_axis = false;
+    // G5.2 starts a spline and G5.3 ends it; P gives control point weights and L the order
+    let mut spline_start = false;
+    let mut spline_end = None;
+    let mut weight = None;
+    let mut order = None;
+    // G43 applies a tool length, from the tool an H word names if there is one
+    let mut length_offset = false;
+    let mut length_tool = None;
+    let mut tool_change = false;
+    let mut aux = Vec::new();
+    // Extruder position or distance from the E word, and whether G92 sets it instead of moving
+    let mut extrude = None;
+    let mut set_position = false;
+    let mut speed = None;
+
+    for word in words(line) {
+        match word.letter {
+            'G' => {
+                let code = word.value(line_number, options)?;
+                match code as u32 {
+                    // Motion modes
+                    mode @ (0..=3 | 5) if code.fract() == 0.0 => state.motion_mode = Some(mode),
+                    // NURBS blocks
+                    5 if (code.fract() - 0.2).abs() < 1e-9 => spline_start = true,
+                    5 if (code.fract() - 0.3).abs() < 1e-9 => spline_end = Some(word.span()),
+                    // Distance modes
+                    90 if code.fract() == 0.0 => {
+                        state.distance = DistanceMode::Absolute;
+                        state.relative_extrusion = false;
+                    }
+                    91 if code.fract() == 0.0 => {
+                        state.distance = DistanceMode::Incremental;
+                        state.relative_extrusion = true;
+                    }
+                    92 if code.fract() == 0.0 => set_position = true,
+                    // Units
+                    20 if code.fract() == 0.0 => state.units = Units::Inches,
+                    21 if code.fract() == 0.0 => state.units = Units::Millimeters,
+                    // Arc planes
+                    17 if code.fract() == 0.0 => state.plane = Plane::Xy,
+                    18 if code.fract() == 0.0 => state.plane = Plane::Xz,
+                    19 if code.fract() == 0.0 => state.plane = Plane::Yz,
+                    // Tool length offsets
+                    43 if code.fract() == 0.0 => length_offset = true,
+                    49 if code.fract() == 0.0 => state.tool_length = 0.0,
+                    // Work coordinate systems
+                    system @ 54..=59 if code.fract() == 0.0 => {
+                        let number = system - 53;
+                        state.work_offset = options.work_offsets.get(&number).copied().unwrap_or_default();
+                    }
+                    // No cutter compensation, no canned cycle and feed rates per minute, which is all the reader does
+                    40 | 80 | 94 if code.fract() == 0.0 => {}
+                    // Anything else would move the tool some other way than the path says, so it can't be ignored
+                    _ => {
+                        return Err(ParseError::UnsupportedGCode {
+                            line: line_number,
+                            span: word.span(),
+                            code: format!("G{}", word.number),
+                        })
+                    }
+                }
+            }
+            'X' => {
+                target.0 = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'Y' if options.lathe => {
+                return Err(ParseError::LatheY {
+                    line: line_number,
+                    span: word.span(),
+                })
+            }
+            'Y' => {
+                target.1 = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'Z' => {
+                target.2 = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'A' => {
+                turn.0 = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'B' => {
+                turn.1 = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'C' => {
+                turn.2 = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'E' => {
+                extrude = Some(word.value(line_number, options)?);
+                has_axis = true;
+            }
+            'I' => {
+                offset.0 = word.value(line_number, options)?;
+                given.0 = 1.0;
+                tilted = true;
+            }
+            'J' => {
+                offset.1 = word.value(line_number, options)?;
+                given.1 = 1.0;
+                tilted = true;
+            }
+            'K' => {
+                offset.2 = word.value(line_number, options)?;
+                given.2 = 1.0;
+                tilted = true;
+            }
+            'P' => {
+                end_offset.0 = word.value(line_number, options)?;
+                weight = Some(end_offset.0);
+            }
+            'L' => order = Some(word.value(line_number, options)?),
+            'R' => radius = Some(word.value(line_number, options)?),
+            'Q' => end_offset.1 = word.value(line_number, options)?,
+            'F' => feed = Some(word.value(line_number, options)?),
+            'S' => speed = Some(word.value(line_number, options)?),
+            'T' => state.tool = Some(word.value(line_number, options)? as u32),
+            'H' => length_tool = Some(word.value(line_number, options)? as u32),
+            'M' => match word.value(line_number, options)? {
+                6.0 => tool_change = true,
+                0.0 => state.pause = Some(false),
+                1.0 => state.pause = Some(true),
+                // Extruder distance modes
+                82.0 => state.relative_extrusion = false,
+                83.0 => state.relative_extrusion = true,
+                code if code.fract() == 0.0 && is_aux_code(code as u32) => aux.push(code as u32),
+                _ => {}
+            },
+            // Other M-codes, line numbers etc.
+            _ => {}
+        }
+    }
+
+    // Lengths are converted to millimeters once the line's G20/G21 is known
+    let scale = state.units.scale();
+    // On a lathe X is a diameter, so the tool moves half as far from the axis
+    let diameter = if options.lathe { 0.5 } else { 1.0 };
+    let target = (target.0.map(|x| x * scale * diameter), target.1.map(|y| y * scale), target.2.map(|z| z * scale));
+    let offset = (offset.0 * scale, offset.1 * scale, offset.2 * scale);
+    let end_offset = (end_offset.0 * scale, end_offset.1 * scale);
+    let radius = radius.map(|radius| radius * scale);
+    if let Some(feed) = feed {
+        state.machine.feed = Some(feed * scale);
+    }
+    // S words of M100-M199 are their own settings, such as the temperatures and fan speeds slicers set
+    if !aux.iter().any(|code| (100..=199).contains(code)) {
+        state.machine.power = speed.or(state.machine.power);
+    }
+    // Tools the table doesn't list have no length
+    if length_offset {
+        let tool = length_tool.or(state.tool).and_then(|number| options.tools.get(number));
+        state.tool_length = tool.map_or(0.0, |tool| tool.length);
+    }
+    // These happen where the tool is before the line moves it: the tool change, coolant, then user codes
+    if tool_change {
+        state.events.push(Motion::new_tool_change(state.tool.unwrap_or(0), state.position));
+    }
+    aux.sort_unstable();
+    state.events.extend(aux.into_iter().map(|code| Motion::new_aux(format!("M{}", code), state.position)));
+
+    // The tool's position is the first control point of a spline
+    if spline_start {
+        let order = order.map_or(4, |order| order as usize);
+        state.spline = Some(SplineBlock {
+            order,
+            controls: vec![state.position],
+            weights: vec![1.0],
+        });
+    }
+
+    // G92 says where the extruder is without moving it
+    if set_position {
+        if let Some(position) = extrude {
+            state.extrusion = Some(position * scale);
+        }
+        return Ok(None);
+    }
+
+    // Lines without coordinates don't move the tool
+    if !has_axis && spline_end.is_none() {
+        return Ok(None);
+    }
+
+    let start = state.position;
+    let axis = |value: Option<f64>, current: f64, offset: f64| match (value, state.distance) {
+        (Some(value), DistanceMode::Absolute) => value + offset,
+        (Some(value), DistanceMode::Incremental) => current + value,
+        (None, _) => current,
+    };
+    let work_offset = state.work_offset;
+    let end = (
+        axis(target.0, start.0, work_offset.0),
+        axis(target.1, start.1, work_offset.1),
+        axis(target.2, start.2, work_offset.2 + state.tool_length),
+    );
+    state.position = end;
+
+    // Inside a G5.2 block, coordinates are control points rather than moves
+    if let Some(block) = &mut state.spline {
+        if has_axis {
+            block.controls.push(end);
+            block.weights.push(weight.unwrap_or(1.0));
+        }
+        if spline_end.is_none() {
+            return Ok(None);
+        }
+    }
+    if let Some(span) = spline_end {
+        let Some(SplineBlock { order, controls, weights }) = state.spline.take() else {
+            return Ok(None);
+        };
+        // Too few points for the order lower the degree, the way controllers do
+        let degree = order.saturating_sub(1).min(controls.len().saturating_sub(1));
+        let curve = Motion::new_spline(controls, weights, None, degree).map_err(|error| ParseError::InvalidSpline {
+            line: line_number,
+            span,
+            error,
+        })?;
+        return Ok(Some(state.machine.apply(curve, None)));
+    }
+
+    // G0 and G1 turn the rotary axes along with the move; once one has turned, every move carries their angles
+    if turn != (None, None, None) && state.rotary.is_none() {
+        state.rotary = Some(Rotary::default());
+    }
+    let rotary = state.rotary.map(|from| {
+        let angle = |value: Option<f64>, current: f64| axis(value, current, 0.0);
+        let to = Rotary {
+            a: angle(turn.0, from.a),
+            b: angle(turn.1, from.b),
+            c: angle(turn.2, from.c),
+        };
+        (from, to)
+    });
+    // E words move the extruder along with the move; once one has, every G0 and G1 carries its position
+    let extrusion = state.extrusion.or(extrude.map(|_| 0.0)).map(|from| {
+        let to = match extrude {
+            Some(distance) if state.relative_extrusion => from + distance * scale,
+            Some(position) => position * scale,
+            None => from,
+        };
+        (from, to)
+    });
+    if let Some((_, to)) = extrusion {
+        state.extrusion = Some(to);
+    }
+    let turned = |motion: Motion| {
+        let motion = match rotary {
+            Some((from, to)) => motion.with_rotary(from, to),
+            None => motion,
+        };
+        match extrusion {
+            Some((from, to)) => motion.with_extrusion(from, to),
+            None => motion,
+        }
+    };
+
+    match state.motion_mode {
+        // I, J and K on G0 and G1 tilt the tool, like G43.5; once tilted, every move carries its direction
+        Some(0 | 1) if tilted || state.tool_vector.is_some() => {
+            let from = state.tool_vector.unwrap_or((0.0, 0.0, 1.0));
+            let Motion::Oriented(mut oriented_motion) =
+                Motion::new_oriented(start, end, from, if tilted { offset } else { from })
+            else {
+                unreachable!("new_oriented makes oriented motions")
+            };
+            oriented_motion.rapid = state.motion_mode == Some(0);
+            state.tool_vector = Some(oriented_motion.orientation.1);
+            Ok(Some(state.machine.apply(Motion::Oriented(oriented_motion), None)))
+        }
+        Some(0 | 1) => {
+            if let Some((_, to)) = rotary {
+                state.rotary = Some(to);
+            }
+            let motion = if state.motion_mode == Some(0) {
+                Motion::new_rapid(start, end)
+            } else {
+                Motion::new_linear(start, end)
+            };
+            Ok(Some(state.machine.apply(turned(motion), None)))
+        }
+        // G5 curves are always in XY, whatever the plane
+        Some(5) => {
+            let curve = Motion::new_bezier_from_offsets(start, end, (offset.0, offset.1), end_offset);
+            Ok(Some(state.machine.apply(curve, None)))
+        }
+        // G2 is clockwise, G3 counter-clockwise; G18 is seen from +Y, the back of the XZ plane
+        Some(mode) => {
+            let plane = state.plane;
+            let clockwise = (mode == 2) != (plane == Plane::Xz);
+            let (start, end, offset) = (plane.coordinates(start), plane.coordinates(end), plane.coordinates(offset));
+            let span = || Span(0..line.trim_end().len());
+            // An arc needs its center, and its end point must lie on the circle through its start
+            let given = plane.coordinates(given);
+            if let (0.0, 0.0, Some(radius)) = (given.0, given.1, radius) {
+                // Or else its radius, which picks the shorter arc if positive and the longer if negative
+                let arc = Motion::new_arc_from_radius(start, end, radius, clockwise).ok_or_else(|| {
+                    ParseError::ArcRadius {
+                        line: line_number,
+                        span: span(),
+                        radius,
+                        chord: (end.0 - start.0).hypot(end.1 - start.1),
+                    }
+                })?;
+                return Ok(Some(state.machine.apply(arc.with_plane(plane), None)));
+            }
+            if given.0 == 0.0 && given.1 == 0.0 {
+                return Err(ParseError::MissingArcCenter {
+                    line: line_number,
+                    span: span(),
+                });
+            }
+            check_arc_end(line_number, span(), start, end, offset)?;
+            let arc = Motion::new_arc_from_offset(start, end, (offset.0, offset.1), clockwise);
+            Ok(Some(state.machine.apply(arc.with_plane(plane), None)))
+        }
+        None => Err(ParseError::UnknownCommand {
+            line: line_number,
+            span: Span(0..line.trim_end().len()),
+            command: line.trim().to_string(),
+        }),
+    }
+}
+
+/// Function to read motions from G-code coming from any buffered reader
+///
+/// # Errors
+///
+/// Returns `CmmdError::Io` if reading fails, and `CmmdError::Parse` for the
+/// first line that can't be interpreted.
+pub fn read_reader<R: BufRead>(reader: R, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    stream_reader(reader, options).collect()
+}
+
+/// Iterator over the motions of a G-code program, parsed one line at a time
+///
+/// Made by [`stream_reader`]. G-code has no blocks, so any program can be read
+/// this way while holding only the current line in memory. The stream ends
+/// after the first error.
+pub struct GcodeStream<'a, R> {
+    lines: io::Lines<R>,
+    number: usize,
+    state: State,
+    options: &'a ParseOptions,
+    failed: bool,
+    // Motions of a line with events, given out one at a time
+    pending: VecDeque<Motion>,
+}
+
+impl<R: BufRead> GcodeStream<'_, R> {
+    // Parse lines until one produces a motion
+    fn next_motion(&mut self) -> Result<Option<Motion>, CmmdError> {
+        while self.pending.is_empty() {
+            let Some(line) = self.lines.next() else {
+                return Ok(None);
+            };
+            let line = line?;
+            self.number += 1;
+            let motion = parse_line(self.number, &line, &mut self.state, self.options)?;
+            // Program stops come last, once the line's move has run
+            let pause = self.state.pause.take().map(|optional| Motion::new_pause(optional, self.state.position));
+            let number = self.number;
+            let motions = self.state.events.drain(..).chain(motion).chain(pause);
+            self.pending.extend(motions.map(|motion| motion.with_line(number)));
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl<R: BufRead> Iterator for GcodeStream<'_, R> {
+    type Item = Result<Motion, CmmdError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_motion();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+/// Function to parse G-code lazily from any buffered reader
+///
+/// Gives the same motions as [`read_reader`], parsing each line only when its
+/// motion is asked for.
+pub fn stream_reader<R: BufRead>(reader: R, options: &ParseOptions) -> GcodeStream<'_, R> {
+    GcodeStream {
+        lines: reader.lines(),
+        number: 0,
+        state: State {
+            motion_mode: None,
+            position: (0.0, 0.0, 0.0),
+            machine: MachineState::default(),
+            distance: DistanceMode::Absolute,
+            units: options.units,
+            work_offset: (0.0, 0.0, 0.0),
+            // Lathes turn in the XZ plane
+            plane: if options.lathe { Plane::Xz } else { Plane::Xy },
+            spline: None,
+            rotary: None,
+            extrusion: None,
+            relative_extrusion: false,
+            tool_vector: None,
+            tool: None,
+            tool_length: 0.0,
+            events: Vec::new(),
+            pause: None,
+        },
+        options,
+        failed: false,
+        pending: VecDeque::new(),
+    }
+}
+
+/// Function to read motions from a G-code file
+///
+/// # Arguments
+///
+/// * `file_path` - The path to the G-code file
+/// * `options` - Parsing options
+///
+/// # Errors
+///
+/// Returns `CmmdError::Io` if the file can't be read, and `CmmdError::Parse`
+/// for the first line that can't be interpreted.
+///
+/// # Examples
+///
+/// ```no_run
+/// use project_2::gcode;
+/// use project_2::parser::ParseOptions;
+///
+/// let motions = gcode::read_file("part.nc", &ParseOptions::default()).unwrap();
+/// println!("{} motions", motions.len());
+/// ```
+pub fn read_file(file_path: &str, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    let file = File::open(file_path)?;
+    read_reader(io::BufReader::new(file), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::Tool;
+
+    /// Test linear moves with modal G codes and omitted axes.
+    #[test]
+    fn test_linear_moves() {
+        let program = "%\nG21 G90 (setup)\nG0 X5 Y0\nX5 Y5 ; still G0\nG1Z-1 F100\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(
+            motions,
+            vec![
+                Motion::new_rapid((0.0, 0.0, 0.0), (5.0, 0.0, 0.0)).with_line(3),
+                Motion::new_rapid((5.0, 0.0, 0.0), (5.0, 5.0, 0.0)).with_line(4),
+                Motion::new_linear((5.0, 5.0, 0.0), (5.0, 5.0, -1.0))
+                    .with_feed(Some(100.0))
+                    .with_line(5),
+            ]
+        );
+    }
+
+    // Interpolate an arc and return its first and last points
+    fn arc_ends(motion: &Motion) -> ((f64, f64), (f64, f64)) {
+        let Motion::Rotational(arc) = motion else {
+            panic!("expected an arc");
+        };
+        let positions = crate::interpolate::rotational_motion_calculate(arc.clone());
+        (positions[0], positions[positions.len() - 1])
+    }
+
+    /// Test that G91 makes coordinates relative until G90.
+    #[test]
+    fn test_incremental_moves() {
+        let program = "G1 X1 Y1\nG91 X2 Z-1\nG90 X0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let ends: Vec<_> = motions
+            .iter()
+            .map(|motion| match motion {
+                Motion::Linear(linear) => linear.end,
+                _ => panic!("expected a line"),
+            })
+            .collect();
+        assert_eq!(ends, vec![(1.0, 1.0, 0.0), (3.0, 1.0, -1.0), (0.0, 1.0, -1.0)]);
+    }
+
+    /// Test that G20 inch coordinates and feeds come out in millimeters.
+    #[test]
+    fn test_inch_units() {
+        let program = "G20 G1 X1 F10\nG21 X1\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(
+            motions[0],
+            Motion::new_linear((0.0, 0.0, 0.0), (25.4, 0.0, 0.0)).with_feed(Some(254.0)).with_line(1)
+        );
+        assert_eq!(
+            motions[1],
+            Motion::new_linear((25.4, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(254.0)).with_line(2)
+        );
+    }
+
+    /// Test that a G5.2/G5.3 block becomes one spline starting at the tool.
+    #[test]
+    fn test_nurbs_block() {
+        let program = "G0 X0 Y0 Z1\nG5.2 X0 Y10 P2 L3\nX10 Y10\nX10 Y0\nG5.3\nG1 X0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(motions.len(), 3);
+        let Motion::Spline(curve) = &motions[1] else { panic!("expected a spline") };
+        assert_eq!(curve.controls.first(), Some(&(0.0, 0.0, 1.0)));
+        assert_eq!(curve.controls.last(), Some(&(10.0, 0.0, 1.0)));
+        assert_eq!((curve.weights.clone(), curve.degree), (vec![1.0, 2.0, 1.0, 1.0], 2));
+        assert_eq!(curve.line, Some(5));
+        let Motion::Linear(line) = &motions[2] else { panic!("expected a line") };
+        assert_eq!(line.start, (10.0, 0.0, 1.0));
+    }
+
+    /// Test that M6 changes to the tool the T word selected before the line moves, and that G43 and G49
+    /// add and remove its length.
+    #[test]
+    fn test_tool_changes() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(2, Tool { length: 30.0, diameter: 6.0 });
+        let program = "G0 X5 Z10\nT2 M6 G43 Z5\nG1 Z0\nG49 Z0\nM6 T9\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+        assert_eq!(motions.len(), 6);
+        assert_eq!(motions[1], Motion::new_tool_change(2, (5.0, 0.0, 10.0)).with_line(2));
+        assert_eq!(motions[2], Motion::new_rapid((5.0, 0.0, 10.0), (5.0, 0.0, 35.0)).with_line(2));
+        let Motion::Linear(cut) = &motions[3] else { panic!("expected a line") };
+        assert_eq!(cut.end, (5.0, 0.0, 30.0));
+        let Motion::Linear(back) = &motions[4] else { panic!("expected a line") };
+        assert_eq!(back.end, (5.0, 0.0, 0.0));
+        assert_eq!(motions[5], Motion::new_tool_change(9, (5.0, 0.0, 0.0)).with_line(5));
+    }
+
+    /// Test that coolant and user-defined M-codes come after a tool change and before the line's move.
+    #[test]
+    fn test_aux_commands() {
+        let program = "G0 X5\nM101 M8 T1 M6 G1 X10\nM9 M3\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(
+            motions[1..4],
+            [
+                Motion::new_tool_change(1, (5.0, 0.0, 0.0)).with_line(2),
+                Motion::new_aux("M8", (5.0, 0.0, 0.0)).with_line(2),
+                Motion::new_aux("M101", (5.0, 0.0, 0.0)).with_line(2),
+            ]
+        );
+        assert!(matches!(motions[4], Motion::Linear(_)));
+        assert_eq!(motions[5], Motion::new_aux("M9", (10.0, 0.0, 0.0)).with_line(3));
+        assert_eq!(motions.len(), 6);
+    }
+
+    /// Test that E words move the extruder, absolutely or after M83 relatively, and that G92 resets it.
+    #[test]
+    fn test_extrusion() {
+        let program = "G1 X10 E2 F1200\nG1 E1.5\nG92 E0\nM83\nG1 X20 E0.5\nG0 X0\nM104 S200\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let extrusion: Vec<_> = motions
+            .iter()
+            .map(|motion| match motion {
+                Motion::Linear(line) => line.extrusion,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            extrusion,
+            vec![Some((0.0, 2.0)), Some((2.0, 1.5)), Some((0.0, 0.5)), Some((0.5, 0.5)), None]
+        );
+        // Temperatures aren't laser power
+        assert_eq!(motions[4], Motion::new_aux("M104", (0.0, 0.0, 0.0)).with_line(7));
+        assert_eq!(motions[3].power(), None);
+    }
+
+    /// Test that M0 and M1 stop the program once their line's move has run.
+    #[test]
+    fn test_pauses() {
+        let motions = read_reader("G1 X5 F100 M0\nM01\n".as_bytes(), &ParseOptions::default()).unwrap();
+        assert!(matches!(motions[0], Motion::Linear(_)));
+        assert_eq!(motions[1], Motion::new_pause(false, (5.0, 0.0, 0.0)).with_line(1));
+        assert_eq!(motions[2], Motion::new_pause(true, (5.0, 0.0, 0.0)).with_line(2));
+    }
+
+    /// Test that G55 applies work offset 2.
+    #[test]
+    fn test_work_offsets() {
+        let mut options = ParseOptions::default();
+        options.work_offsets.insert(2, (10.0, 0.0, -1.0));
+        let motions = read_reader("G55 G1 X1 Y1 Z0\nG54 X1\n".as_bytes(), &options).unwrap();
+        let Motion::Linear(first) = &motions[0] else { panic!("expected a line") };
+        assert_eq!(first.end, (11.0, 1.0, -1.0));
+        let Motion::Linear(second) = &motions[1] else { panic!("expected a line") };
+        assert_eq!(second.end, (1.0, 1.0, -1.0));
+    }
+
+    /// Test that lathe programs read X as a diameter, arc in XZ and reject Y.
+    #[test]
+    fn test_lathe() {
+        let options = ParseOptions {
+            lathe: true,
+            ..Default::default()
+        };
+        let motions = read_reader("G0 X40 Z2\nG1 Z-10\nG2 X40 Z-20 I0 K-5\n".as_bytes(), &options).unwrap();
+        let Motion::Linear(first) = &motions[0] else { panic!("expected a line") };
+        assert_eq!(first.end, (20.0, 0.0, 2.0));
+        let Motion::Rotational(arc) = &motions[2] else { panic!("expected an arc") };
+        assert_eq!((arc.plane, arc.center), (Plane::Xz, (20.0, -15.0)));
+
+        let err = read_reader("G1 X10 Y5".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::LatheY { line: 1, .. })));
+    }
+
+    /// Test that an arc with a Z change becomes a helix.
+    #[test]
+    fn test_helical_arc() {
+        let program = "G0 X10 Y0 Z0\nG3 X10 Y0 Z-2 I-10 J0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let Motion::Rotational(arc) = &motions[1] else {
+            panic!("expected an arc");
+        };
+        assert_eq!(arc.z, Some((0.0, -2.0)));
+        assert!((arc.sweep - 360.0).abs() < 1e-9);
+    }
+
+    /// Test that G2/G3 arcs start and end where the program says.
+    #[test]
+    fn test_arcs() {
+        let program = "G0 X10 Y0\nG2 X0 Y-10 I-10 J0\nG3 X-10 Y0 I0 J10\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+
+        let Motion::Rotational(arc) = &motions[1] else {
+            panic!("expected an arc");
+        };
+        assert_eq!(arc.center, (0.0, 0.0));
+        assert_eq!(arc.radius, 10.0);
+
+        let (first, last) = arc_ends(&motions[1]);
+        assert!((first.0 - 10.0).abs() < 1e-9 && first.1.abs() < 1e-9);
+        assert!(last.0.abs() < 1e-9 && (last.1 + 10.0).abs() < 1e-9);
+
+        let (first, last) = arc_ends(&motions[2]);
+        assert!(first.0.abs() < 1e-9 && (first.1 + 10.0).abs() < 1e-9);
+        assert!((last.0 + 10.0).abs() < 1e-9 && last.1.abs() < 1e-9);
+    }
+
+    /// Test arcs given by a radius, shorter or longer than half a turn by its sign.
+    #[test]
+    fn test_radius_arcs() {
+        let program = "G1 X10 Y0\nG2 X0 Y-10 R10\nG3 X10 Y0 R-10\nG2 X0 Y10 R4.9999\n";
+        let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+        let CmmdError::Parse(ParseError::ArcRadius { line: 4, radius, chord, .. }) = err else {
+            panic!("expected the radius to be too small");
+        };
+        assert_eq!((radius, chord), (4.9999, 200.0_f64.sqrt()));
+
+        let motions = read_reader(program.rsplit_once("G2").unwrap().0.as_bytes(), &ParseOptions::default()).unwrap();
+        for (motion, center, sweep) in [(&motions[1], (0.0, 0.0), -90.0), (&motions[2], (10.0, -10.0), 270.0)] {
+            let Motion::Rotational(arc) = motion else { panic!("expected an arc") };
+            assert!((arc.center.0 - center.0).abs() < 1e-9 && (arc.center.1 - center.1).abs() < 1e-9);
+            assert!((arc.sweep - sweep).abs() < 1e-9);
+        }
+        // The long way round from (0, -10) to (10, 0) dips down to Y-20
+        let (first, last) = arc_ends(&motions[2]);
+        assert!(first.0.abs() < 1e-9 && (last.0 - 10.0).abs() < 1e-9 && last.1.abs() < 1e-9);
+        let Motion::Rotational(arc) = &motions[2] else { unreachable!() };
+        let points = crate::interpolate::rotational_motion_calculate(arc.clone());
+        assert!(points.iter().any(|point| (point.1 + 20.0).abs() < 1e-3));
+    }
+
+    /// Test that arcs without a center, or ending off their circle, are rejected.
+    #[test]
+    fn test_bad_arcs() {
+        let err = read_reader("G1 X10 Y0\nG2 X0 Y-10\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::MissingArcCenter { line: 2, .. })));
+        // K is no help to an arc in XY
+        let err = read_reader("G1 X10 Y0\nG2 X0 Y-10 K-10\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::MissingArcCenter { line: 2, .. })));
+        let err = read_reader("G1 X10 Y0\nG2 X0 Y-12 I-10\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        let CmmdError::Parse(ParseError::ArcEndOffCircle { start_radius, end_radius, .. }) = err else {
+            panic!("expected the arc to be off its circle");
+        };
+        assert_eq!((start_radius, end_radius), (10.0, 12.0));
+        // Rounding in the last digits is fine
+        assert!(read_reader("G1 X10 Y0\nG2 X0 Y-10.001 I-10\n".as_bytes(), &ParseOptions::default()).is_ok());
+    }
+
+    /// Test that coordinates without a motion mode are rejected.
+    #[test]
+    fn test_missing_motion_mode() {
+        let err = read_reader("X1 Y2\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnknownCommand { line: 1, .. })));
+    }
+
+    /// Test that strict mode rejects numbers that aren't finite.
+    #[test]
+    fn test_strict_numbers() {
+        let strict = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        // Words only take digits, but enough of them still overflow to infinity
+        let program = format!("G1 X1{} Y0\n", "0".repeat(400));
+        let err = read_reader(program.as_bytes(), &strict).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::InvalidNumber { line: 1, .. })));
+        assert!(read_reader(program.as_bytes(), &ParseOptions::default()).is_ok());
+    }
+
+    /// Test that G-codes the reader doesn't handle are rejected rather than run as ordinary moves.
+    #[test]
+    fn test_unsupported_codes() {
+        let program = "G21 G90 G17 G40 G49 G80 G94\nG1 X1 Y0 Z0 F100\nG81 X10 Y0 Z-5 R2\n";
+        let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+        let CmmdError::Parse(ParseError::UnsupportedGCode { line: 3, span, code }) = err else {
+            panic!("expected G81 to be rejected")
+        };
+        assert_eq!((span, code.as_str()), (Span(0..3), "G81"));
+        let err = read_reader("G0 X5 Y5\nG28 X0 Y0\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnsupportedGCode { line: 2, .. })));
+        let err = read_reader("G1 X5 Y5 F100\nG41 D1\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnsupportedGCode { line: 2, .. })));
+    }
+}
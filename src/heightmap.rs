@@ -0,0 +1,142 @@
+//! Height maps for levelling the Z axis over uneven stock
+//!
+//! Boards for PCB milling and plates for engraving are never quite flat, and
+//! cuts only a tenth of a millimeter deep go too deep in one corner and miss
+//! the other. Probing the stock on a grid gives how far the surface sits above
+//! or below Z = 0 at each point; [`HeightMap`] adds that to the Z of every
+//! interpolated position, reading between probe points bilinearly. It is
+//! written in TOML:
+//!
+//! ```toml
+//! origin = [0, 0]     # X and Y of the first probe point
+//! spacing = [10, 10]  # distance between probe points along X and Y
+//! z = [
+//!     [0.00, 0.05, 0.12],   # the row at the origin's Y, along X
+//!     [0.02, 0.08, 0.15],   # the next row, one spacing further along Y
+//! ]
+//! ```
+//!
+//! Positions outside the grid use the nearest edge. Straight moves only get
+//! positions every `--step`, so the step should be no larger than the spacing
+//! for the cut to follow the surface between probe points.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::ConfigError;
+
+type Point = (f64, f64, f64);
+
+/// A grid of probed surface heights, in program units
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeightMap {
+    /// X and Y of the first probe point
+    pub origin: (f64, f64),
+    /// Distance between probe points along X and along Y
+    pub spacing: (f64, f64),
+    /// Heights by row along Y, each row running along X
+    pub z: Vec<Vec<f64>>,
+}
+
+impl HeightMap {
+    /// Function to read a height map from TOML text
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Toml` if the text isn't a valid height map, and
+    /// `ConfigError::InvalidHeightMap` if the grid is empty or ragged or its
+    /// spacing isn't positive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::heightmap::HeightMap;
+    ///
+    /// let map = HeightMap::from_toml("origin = [0, 0]\nspacing = [10, 10]\nz = [[0, 0.2], [0.4, 0.6]]").unwrap();
+    /// assert!((map.height(5.0, 5.0) - 0.3).abs() < 1e-12);
+    /// ```
+    pub fn from_toml(text: &str) -> Result<HeightMap, ConfigError> {
+        let map: HeightMap = toml::from_str(text)?;
+        let columns = map.z.first().map_or(0, Vec::len);
+        if columns == 0 {
+            return Err(ConfigError::InvalidHeightMap(String::from("the grid has no probe points")));
+        }
+        if map.z.iter().any(|row| row.len() != columns) {
+            return Err(ConfigError::InvalidHeightMap(String::from("every row needs as many probe points")));
+        }
+        if !(map.spacing.0 > 0.0 && map.spacing.1 > 0.0) {
+            return Err(ConfigError::InvalidHeightMap(String::from("the spacing needs to be positive")));
+        }
+        Ok(map)
+    }
+
+    /// Function to read a height map from a TOML file
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Io` if the file can't be read, and the errors of
+    /// [`HeightMap::from_toml`] otherwise.
+    pub fn load(path: &Path) -> Result<HeightMap, ConfigError> {
+        HeightMap::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// Function to find the height of the surface at a point, between the probe points around it
+    pub fn height(&self, x: f64, y: f64) -> f64 {
+        // Where the point falls along one axis: the probe point before it and how far on to the next
+        let cell = |position: f64, origin: f64, spacing: f64, count: usize| {
+            let last = count.saturating_sub(1);
+            let along = ((position - origin) / spacing).clamp(0.0, last as f64);
+            let index = (along.floor() as usize).min(last.saturating_sub(1));
+            (index, (index + 1).min(last), along - index as f64)
+        };
+        let (column, next_column, u) = cell(x, self.origin.0, self.spacing.0, self.z[0].len());
+        let (row, next_row, v) = cell(y, self.origin.1, self.spacing.1, self.z.len());
+
+        let near = self.z[row][column] * (1.0 - u) + self.z[row][next_column] * u;
+        let far = self.z[next_row][column] * (1.0 - u) + self.z[next_row][next_column] * u;
+        near * (1.0 - v) + far * v
+    }
+
+    /// Function to move a position up or down to follow the surface
+    pub fn apply(&self, (x, y, z): Point) -> Point {
+        (x, y, z + self.height(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that heights are exact at probe points, bilinear between them and held past the edges.
+    #[test]
+    fn test_height() {
+        let map = HeightMap::from_toml("origin = [10, 20]\nspacing = [5, 10]\nz = [[0, 1, 2], [1, 3, 5]]").unwrap();
+        assert_eq!(map.height(15.0, 30.0), 3.0);
+        assert_eq!(map.height(20.0, 20.0), 2.0);
+        assert!((map.height(12.5, 25.0) - 1.25).abs() < 1e-12);
+        // Beyond the grid, the nearest edge
+        assert_eq!(map.height(0.0, 0.0), 0.0);
+        assert_eq!(map.height(100.0, 100.0), 5.0);
+        assert_eq!(map.apply((20.0, 30.0, -0.5)), (20.0, 30.0, 4.5));
+
+        // A single probe point raises everything by the same amount
+        let flat = HeightMap::from_toml("origin = [0, 0]\nspacing = [1, 1]\nz = [[0.25]]").unwrap();
+        assert_eq!(flat.height(-3.0, 7.0), 0.25);
+    }
+
+    /// Test that empty, ragged and badly spaced grids are rejected.
+    #[test]
+    fn test_invalid() {
+        for text in [
+            "origin = [0, 0]\nspacing = [1, 1]\nz = []",
+            "origin = [0, 0]\nspacing = [1, 1]\nz = [[0, 1], [2]]",
+            "origin = [0, 0]\nspacing = [0, 1]\nz = [[0, 1]]",
+        ] {
+            assert!(matches!(HeightMap::from_toml(text), Err(ConfigError::InvalidHeightMap(_))));
+        }
+        assert!(matches!(HeightMap::from_toml("origin = [0, 0]\nz = [[0]]"), Err(ConfigError::Toml(_))));
+    }
+}
@@ -0,0 +1,311 @@
+//! HP-GL import and export for pen plotters
+//!
+//! HP-GL is the language of Hewlett-Packard's pen plotters and the many
+//! plotters and vinyl cutters that copied them. A plot is a series of
+//! two-letter instructions: `PU` lifts the pen and `PD` lowers it, either
+//! followed by points to go to, `PA` and `PR` make those points absolute or
+//! relative, and `AA`, `AR` and `CI` draw arcs and circles. Coordinates are in
+//! plotter units of 0.025 mm.
+//!
+//! [`read_hpgl`] reads a plot into a [`Drawing`], which becomes a program
+//! like an SVG drawing does, and [`write_hpgl`] writes a program's
+//! interpolated path back out as a plot, so old plotters can draw anything
+//! the tool reads.
+
+use std::io::{self, Read, Write};
+
+use crate::drawing::{Drawing, Segment, Stroke};
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::trajectory::Trajectory;
+
+/// Plotter units in a millimeter
+pub const UNITS_PER_MM: f64 = 40.0;
+
+// The error for a plot that can't be read
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// The pen, and the stroke it's drawing
+struct Plotter {
+    strokes: Vec<Stroke>,
+    stroke: Option<Stroke>,
+    // Where the pen is, in millimeters
+    position: (f64, f64),
+    down: bool,
+    absolute: bool,
+}
+
+impl Plotter {
+    // Finish the stroke being drawn, if any
+    fn lift(&mut self) {
+        self.strokes.extend(self.stroke.take());
+    }
+
+    // Draw a segment from where the pen is, starting a stroke there if need be
+    fn draw(&mut self, segment: Segment) {
+        let start = self.position;
+        self.stroke.get_or_insert_with(|| Stroke { start, segments: Vec::new() }).segments.push(segment);
+    }
+
+    // Go to each point in turn, drawing if the pen is down
+    fn go(&mut self, instruction: &str, values: &[f64]) -> io::Result<()> {
+        if !values.len().is_multiple_of(2) {
+            return Err(invalid(format!("{} needs pairs of coordinates", instruction)));
+        }
+        for pair in values.chunks_exact(2) {
+            let (x, y) = (pair[0] / UNITS_PER_MM, pair[1] / UNITS_PER_MM);
+            let to = if self.absolute { (x, y) } else { (self.position.0 + x, self.position.1 + y) };
+            if self.down {
+                self.draw(Segment::Line(to));
+            } else {
+                self.lift();
+            }
+            self.position = to;
+        }
+        Ok(())
+    }
+
+    // Go round `center` by `sweep` degrees, counter-clockwise if positive
+    fn arc(&mut self, center: (f64, f64), sweep: f64) {
+        let (dx, dy) = (self.position.0 - center.0, self.position.1 - center.1);
+        // A turn or more is a full circle, which ends where it starts
+        let (sin, cos) = if sweep.abs() >= 360.0 { (0.0, 1.0) } else { sweep.to_radians().sin_cos() };
+        let end = (center.0 + dx * cos - dy * sin, center.1 + dx * sin + dy * cos);
+        if sweep == 0.0 {
+            return;
+        }
+        if self.down {
+            self.draw(Segment::Arc { center, clockwise: sweep < 0.0, end });
+        } else {
+            self.lift();
+        }
+        self.position = end;
+    }
+}
+
+/// Function to read an HP-GL plot
+///
+/// Each run of drawing with the pen down becomes a stroke. `PU`, `PD`, `PA`
+/// and `PR` move the pen, `AA` and `AR` draw arcs around an absolute or
+/// relative center (counter-clockwise for positive angles), `CI` draws a
+/// circle around the pen, which then stays where it was, and `IN` starts over.
+/// Coordinates are read as plotter units, 40 to the millimeter; scaling with
+/// `SC`, and everything else that doesn't move the pen, such as picking pens
+/// and writing labels, is ignored.
+///
+/// # Errors
+///
+/// Returns any error from reading, or `InvalidData` if an instruction that
+/// moves the pen has a number missing.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::drawing::Segment;
+/// use project_2::hpgl::read_hpgl;
+///
+/// let drawing = read_hpgl("IN;SP1;PU400,0;PD800,0;AA400,0,90;PU;".as_bytes()).unwrap();
+/// let stroke = &drawing.strokes[0];
+/// assert_eq!(stroke.start, (10.0, 0.0));
+/// assert_eq!(stroke.segments[0], Segment::Line((20.0, 0.0)));
+/// assert!(matches!(stroke.segments[1], Segment::Arc { center: (10.0, 0.0), clockwise: false, .. }));
+/// ```
+pub fn read_hpgl<R: Read>(mut reader: R) -> io::Result<Drawing> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let text = String::from_utf8_lossy(&bytes);
+
+    let mut plotter = Plotter {
+        strokes: Vec::new(),
+        stroke: None,
+        position: (0.0, 0.0),
+        down: false,
+        absolute: true,
+    };
+    let mut rest = text.as_ref();
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic()) {
+        rest = &rest[start..];
+        let Some(instruction) = rest.get(..2).filter(|name| name.chars().all(|c| c.is_ascii_alphabetic())) else {
+            break;
+        };
+        let instruction = instruction.to_ascii_uppercase();
+        rest = &rest[2..];
+        // Labels run on to the end-of-text character, whatever is in them
+        if instruction == "LB" {
+            rest = rest.split_once('\u{3}').map_or("", |(_, after)| after);
+            continue;
+        }
+        // The parameters run up to the next instruction
+        let end = rest.find(|c: char| c.is_ascii_alphabetic() || c == ';').unwrap_or(rest.len());
+        let parameters = &rest[..end];
+        rest = &rest[end..];
+        let values = parameters
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid(format!("{} has a malformed number in {}", instruction, parameters.trim())))?;
+        let needs = |count: usize| {
+            if values.len() < count {
+                Err(invalid(format!("{} needs at least {} numbers", instruction, count)))
+            } else {
+                Ok(())
+            }
+        };
+
+        match instruction.as_str() {
+            "IN" => {
+                plotter.lift();
+                (plotter.position, plotter.down, plotter.absolute) = ((0.0, 0.0), false, true);
+            }
+            "PU" => {
+                plotter.down = false;
+                plotter.lift();
+                plotter.go(&instruction, &values)?;
+            }
+            "PD" => {
+                plotter.down = true;
+                plotter.go(&instruction, &values)?;
+            }
+            "PA" => {
+                plotter.absolute = true;
+                plotter.go(&instruction, &values)?;
+            }
+            "PR" => {
+                plotter.absolute = false;
+                plotter.go(&instruction, &values)?;
+            }
+            "AA" | "AR" => {
+                needs(3)?;
+                let (x, y) = (values[0] / UNITS_PER_MM, values[1] / UNITS_PER_MM);
+                let center = match instruction.as_str() {
+                    "AA" => (x, y),
+                    _ => (plotter.position.0 + x, plotter.position.1 + y),
+                };
+                plotter.arc(center, values[2]);
+            }
+            "CI" => {
+                needs(1)?;
+                let center = plotter.position;
+                let radius = values[0].abs() / UNITS_PER_MM;
+                // The circle is drawn on its own, from its rightmost point
+                plotter.lift();
+                let start = (center.0 + radius, center.1);
+                plotter.strokes.push(Stroke {
+                    start,
+                    segments: vec![Segment::Arc { center, clockwise: false, end: start }],
+                });
+            }
+            _ => {}
+        }
+    }
+    plotter.lift();
+    Ok(Drawing { strokes: plotter.strokes })
+}
+
+/// Function to write a program's interpolated path as an HP-GL plot
+///
+/// The pen is down for cutting motions and up for rapid moves, and is lifted
+/// to go to wherever a motion starts away from the pen. Arcs and curves are
+/// split into points as for any other output and drawn as short lines, and
+/// straight cuts go straight to their end; heights are left out, since a
+/// plotter only has X and Y. Coordinates are rounded to whole plotter units.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::hpgl::write_hpgl;
+/// use project_2::motion::Motion;
+///
+/// let motions = vec![
+///     Motion::new_rapid((0.0, 0.0, 5.0), (10.0, 0.0, 5.0)),
+///     Motion::new_linear((10.0, 0.0, 0.0), (20.0, 0.0, 0.0)),
+/// ];
+/// let mut output = Vec::new();
+/// write_hpgl(&mut output, &motions, &Default::default()).unwrap();
+/// let plot = String::from_utf8(output).unwrap();
+/// assert_eq!(plot, "IN;\nSP1;\nPU400,0;\nPD800,0;\nPU;\nSP0;\n");
+/// ```
+pub fn write_hpgl<W: Write>(mut writer: W, motions: &[Motion], options: &InterpolationOptions) -> io::Result<()> {
+    writeln!(writer, "IN;")?;
+    writeln!(writer, "SP1;")?;
+
+    let units = |(x, y, _): (f64, f64, f64)| ((x * UNITS_PER_MM).round() as i64, (y * UNITS_PER_MM).round() as i64);
+    let mut pen = (0, 0);
+    // The points the pen is down for, written as one PD once the pen lifts
+    let mut drawn: Vec<(i64, i64)> = Vec::new();
+    let flush = |writer: &mut W, drawn: &mut Vec<(i64, i64)>| -> io::Result<()> {
+        if drawn.is_empty() {
+            return Ok(());
+        }
+        let points: Vec<String> = drawn.drain(..).map(|(x, y)| format!("{},{}", x, y)).collect();
+        writeln!(writer, "PD{};", points.join(","))
+    };
+    let mut points = Trajectory::new(motions, options).points().peekable();
+    let mut previous = None;
+    while let Some(point) = points.next() {
+        let at = units(point.position);
+        let first = previous != Some(point.motion);
+        let last = points.peek().is_none_or(|next| next.motion != point.motion);
+        previous = Some(point.motion);
+        let motion = &motions[point.motion];
+        // Travel, and the start of a motion away from the pen, go straight there with the pen up
+        if motion.is_rapid() || (first && at != pen) {
+            if (last || first) && at != pen {
+                flush(&mut writer, &mut drawn)?;
+                writeln!(writer, "PU{},{};", at.0, at.1)?;
+                pen = at;
+            }
+        } else if at != pen && (last || !matches!(motion, Motion::Linear(_))) {
+            // Straight cuts only need their end
+            drawn.push(at);
+            pen = at;
+        }
+    }
+    flush(&mut writer, &mut drawn)?;
+    writeln!(writer, "PU;")?;
+    writeln!(writer, "SP0;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::DrawingOptions;
+
+    /// Test relative moves, circles, labels and starting over.
+    #[test]
+    fn test_read_plot() {
+        let plot = "IN;SP1;PA;PU0,0;PD;PR400,0 0,400;LBhello; PD;\u{3}PU;CI200;PA0,0;IN;PD400,400;";
+        let drawing = read_hpgl(plot.as_bytes()).unwrap();
+        assert_eq!(drawing.strokes.len(), 3);
+        assert_eq!(drawing.strokes[0].segments, vec![Segment::Line((10.0, 0.0)), Segment::Line((10.0, 10.0))]);
+        // The circle goes round the pen from its right, and the pen stays in the middle
+        assert_eq!(
+            drawing.strokes[1],
+            Stroke {
+                start: (15.0, 10.0),
+                segments: vec![Segment::Arc { center: (10.0, 10.0), clockwise: false, end: (15.0, 10.0) }],
+            }
+        );
+        assert_eq!(drawing.strokes[2].start, (0.0, 0.0));
+
+        assert!(read_hpgl("PD10;".as_bytes()).is_err());
+        assert!(read_hpgl("AA10,10;".as_bytes()).is_err());
+        assert!(read_hpgl("PD1x,0;".as_bytes()).is_err());
+    }
+
+    /// Test that a plot comes back the same after being written out and read again.
+    #[test]
+    fn test_round_trip() {
+        let plot = "IN;PU400,400;PD800,400,800,800;PU0,0;PD0,40;PU;";
+        let drawing = read_hpgl(plot.as_bytes()).unwrap();
+        let options = DrawingOptions { clearance: 0.0, ..Default::default() };
+        let mut output = Vec::new();
+        write_hpgl(&mut output, &drawing.motions(&options), &InterpolationOptions::default()).unwrap();
+        let written = String::from_utf8(output).unwrap();
+        assert_eq!(read_hpgl(written.as_bytes()).unwrap(), drawing);
+        assert!(written.contains("PU400,400;\nPD800,400,800,800;\nPU0,0;\n"));
+    }
+}
@@ -0,0 +1,816 @@
+pub use crate::kernel::ArcStep;
+use crate::kernel::{arc_steps, linear_steps, ArcSteps, LinearSteps};
+use crate::motion::{BezierMotion, LinearMotion, OrientedMotion, Plane, RotationalMotion, SplineMotion};
+
+/// Options controlling how motions are split into positions
+#[derive(Debug, Clone)]
+pub struct InterpolationOptions {
+    /// Largest distance, along any single axis, between linear motion points; positive and finite
+    pub linear_step: f64,
+    /// How the angular step between arc points is chosen
+    pub arc_step: ArcStep,
+    /// Feed rate rapid moves run at, in millimeters per minute
+    pub rapid_rate: f64,
+    /// Seconds each pause is expected to last, counted in run times; 0 leaves pauses out
+    pub pause_time: f64,
+}
+
+impl Default for InterpolationOptions {
+    fn default() -> Self {
+        InterpolationOptions {
+            linear_step: 1.0,
+            arc_step: ArcStep::default(),
+            rapid_rate: 5000.0,
+            pause_time: 0.0,
+        }
+    }
+}
+
+/// Function to calculate positions for linear motion
+///
+/// Given a start position and an end position, this function calculates
+/// intermediate positions in a straight line between the start and end points.
+/// The positions are calculated with one-unit increments.
+///
+/// # Arguments
+///
+/// * `start` - The starting position (x, y, z)
+/// * `end` - The ending position (x, y, z)
+///
+/// # Returns
+///
+/// A vector of strings containing the calculated positions.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::linear_motion_calculate;
+///
+/// let positions = linear_motion_calculate((0.0, 0.0, 0.0), (3.0, 4.0, 5.0));
+/// assert_eq!(positions.first().unwrap(), "0.00, 0.00, 0.00");
+/// assert_eq!(positions.last().unwrap(), "3.00, 4.00, 5.00");
+/// ```
+pub fn linear_motion_calculate(start: (f64, f64, f64), end: (f64, f64, f64)) -> Vec<String> {
+    linear_motion_calculate_with_options(start, end, &InterpolationOptions::default())
+}
+
+/// Function to calculate positions for linear motion with explicit options
+///
+/// Behaves like [`linear_motion_calculate`], but uses `options.linear_step`
+/// instead of one-unit increments.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::{linear_motion_calculate_with_options, InterpolationOptions};
+///
+/// let options = InterpolationOptions { linear_step: 0.5, ..Default::default() };
+/// let positions = linear_motion_calculate_with_options((0.0, 0.0, 0.0), (2.0, 0.0, 0.0), &options);
+/// assert_eq!(positions.len(), 6);
+/// ```
+pub fn linear_motion_calculate_with_options(
+    start: (f64, f64, f64),
+    end: (f64, f64, f64),
+    options: &InterpolationOptions,
+) -> Vec<String> {
+    linear_positions(start, end, options)
+        .into_iter()
+        .map(|(x, y, z)| format!("{:.2}, {:.2}, {:.2}", x, y, z))
+        .collect()
+}
+
+/// Function to calculate the numeric positions of a linear motion
+///
+/// The same positions [`linear_motion_calculate_with_options`] formats as
+/// text, for callers that need to do further math with them.
+pub fn linear_positions(
+    start: (f64, f64, f64),
+    end: (f64, f64, f64),
+    options: &InterpolationOptions,
+) -> Vec<(f64, f64, f64)> {
+    linear_points(start, end, options).collect()
+}
+
+/// Iterator over the positions of a linear motion, made by [`linear_points`]
+pub type LinearPoints = LinearSteps;
+
+/// Function to lazily calculate the positions of a linear motion
+///
+/// Yields the same positions as [`linear_positions`], one at a time, so long
+/// motions don't have to be held in memory.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::linear_points;
+///
+/// let mut points = linear_points((0.0, 0.0, 0.0), (2.0, 0.0, 0.0), &Default::default());
+/// assert_eq!(points.len(), 4);
+/// assert_eq!(points.next(), Some((0.0, 0.0, 0.0)));
+/// assert_eq!(points.last(), Some((2.0, 0.0, 0.0)));
+/// ```
+pub fn linear_points(start: (f64, f64, f64), end: (f64, f64, f64), options: &InterpolationOptions) -> LinearPoints {
+    stepped_points(start, end, 0.0, options)
+}
+
+/// Function to lazily calculate the positions of a linear motion, counting its rotary axes
+///
+/// Like [`linear_points`], except that a degree turned by a rotary axis counts
+/// as one unit of travel, so moves that mostly or only turn the rotary axes
+/// still get a position every `linear_step` degrees. The rotary angles at
+/// position `i` of `n` are [`LinearMotion::rotary_at`] `i / (n - 1)`.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::linear_motion_points;
+/// use project_2::motion::{Motion, Rotary};
+///
+/// // A quarter turn of the A axis without moving X, Y or Z
+/// let turn = Rotary { a: 90.0, ..Default::default() };
+/// let motion = Motion::new_linear((0.0, 0.0, 0.0), (0.0, 0.0, 0.0)).with_rotary(Rotary::default(), turn);
+/// let Motion::Linear(line) = motion else { unreachable!() };
+/// assert_eq!(linear_motion_points(&line, &Default::default()).len(), 92);
+/// ```
+pub fn linear_motion_points(linear_motion: &LinearMotion, options: &InterpolationOptions) -> LinearPoints {
+    let turned = linear_motion.rotary.map_or(0.0, |(start, end)| start.largest_change(&end));
+    stepped_points(linear_motion.start, linear_motion.end, turned, options)
+}
+
+/// Function to lazily calculate the positions of a move that tilts the tool
+///
+/// Like [`linear_motion_points`], with each degree the tool turns counting as
+/// one unit of travel. The tool's direction at position `i` of `n` is
+/// [`OrientedMotion::orientation_at`] `i / (n - 1)`.
+pub fn oriented_points(oriented_motion: &OrientedMotion, options: &InterpolationOptions) -> LinearPoints {
+    stepped_points(oriented_motion.start, oriented_motion.end, oriented_motion.turned(), options)
+}
+
+// Split a straight move into steps of at most `linear_step` along any axis, rotary ones included
+fn stepped_points(
+    start: (f64, f64, f64),
+    end: (f64, f64, f64),
+    turned: f64,
+    options: &InterpolationOptions,
+) -> LinearPoints {
+    linear_steps(start, end, options.linear_step, turned)
+}
+
+// Split the arc of a rotational motion into steps, in the coordinates of its plane
+fn arc_sweep(rotational_motion: &RotationalMotion, options: &InterpolationOptions) -> ArcSteps {
+    let steps = arc_steps(
+        rotational_motion.center,
+        rotational_motion.radius,
+        rotational_motion.start_angle,
+        rotational_motion.sweep,
+        rotational_motion.z.unwrap_or((0.0, 0.0)),
+        options.arc_step,
+    );
+    // Arcs given by their end point stop exactly there, like lines do
+    match rotational_motion.end {
+        Some(end) => steps.with_end(end),
+        None => steps,
+    }
+}
+
+/// Function to calculate positions for rotational motion
+///
+/// Given the parameters of a rotational motion (center, radius, start angle
+/// and signed sweep in degrees), this function calculates the positions
+/// along the arc from the start angle through the sweep. The
+/// angular step is `5.0 / radius` degrees, so the spacing between points stays
+/// roughly constant along the arc.
+///
+/// # Arguments
+///
+/// * `rotational_motion` - A struct containing the parameters of the rotational motion.
+///
+/// # Returns
+///
+/// A vector of tuples containing the calculated (x, y) positions. Arcs in
+/// the XZ or YZ plane give coordinates in that plane.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::rotational_motion_calculate;
+/// use project_2::motion::{Plane, RotationalMotion};
+///
+/// let rotational_motion = RotationalMotion {
+///     center: (0.0, 0.0),
+///     radius: 5.0,
+///     start_angle: 0.0,
+///     sweep: 90.0,
+///     z: None,
+///     end: None,
+///     plane: Plane::Xy,
+///     feed: None,
+///     power: None,
+///     line: None,
+/// };
+/// let positions = rotational_motion_calculate(rotational_motion);
+/// assert_eq!(positions.len(), 91); // One position per degree
+/// assert_eq!(positions[0], (5.00, 0.00)); // Check first position
+/// ```
+pub fn rotational_motion_calculate(rotational_motion: RotationalMotion) -> Vec<(f64, f64)> {
+    rotational_motion_calculate_with_options(rotational_motion, &InterpolationOptions::default())
+}
+
+/// Function to calculate positions for rotational motion with explicit options
+///
+/// Behaves like [`rotational_motion_calculate`], but lets the caller choose how
+/// the arc is split into points.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::{rotational_motion_calculate_with_options, ArcStep, InterpolationOptions};
+/// use project_2::motion::{Plane, RotationalMotion};
+///
+/// let rotational_motion = RotationalMotion {
+///     center: (0.0, 0.0),
+///     radius: 100.0,
+///     start_angle: 0.0,
+///     sweep: 90.0,
+///     z: None,
+///     end: None,
+///     plane: Plane::Xy,
+///     feed: None,
+///     power: None,
+///     line: None,
+/// };
+/// let options = InterpolationOptions { arc_step: ArcStep::ChordTolerance(0.01), ..Default::default() };
+/// let positions = rotational_motion_calculate_with_options(rotational_motion, &options);
+/// assert_eq!(positions.len(), 57); // Enough chords to stay within 0.01 of the arc
+/// ```
+pub fn rotational_motion_calculate_with_options(
+    rotational_motion: RotationalMotion,
+    options: &InterpolationOptions,
+) -> Vec<(f64, f64)> {
+    arc_sweep(&rotational_motion, options).map(|(x, y, _)| (x, y)).collect()
+}
+
+/// Function to calculate positions for helical motion
+///
+/// Samples the arc exactly like [`rotational_motion_calculate`], and ramps Z
+/// linearly from the start height to the end height across the angular sweep.
+/// Arcs without a Z range stay at Z = 0. Arcs in the XZ or YZ plane ramp along
+/// the axis normal to their plane instead, and come out as (x, y, z) points all
+/// the same.
+///
+/// # Arguments
+///
+/// * `rotational_motion` - A struct containing the parameters of the rotational motion.
+///
+/// # Returns
+///
+/// A vector of tuples containing the calculated (x, y, z) positions.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::helical_motion_calculate;
+/// use project_2::motion::{Plane, RotationalMotion};
+///
+/// let rotational_motion = RotationalMotion {
+///     center: (0.0, 0.0),
+///     radius: 5.0,
+///     start_angle: 0.0,
+///     sweep: 720.0,
+///     z: Some((0.0, -2.0)),
+///     end: None,
+///     plane: Plane::Xy,
+///     feed: None,
+///     power: None,
+///     line: None,
+/// };
+/// let positions = helical_motion_calculate(rotational_motion);
+/// assert_eq!(positions[0].2, 0.0); // Starts at the top
+/// assert_eq!(positions[positions.len() - 1].2, -2.0); // Ends two turns lower
+/// ```
+pub fn helical_motion_calculate(rotational_motion: RotationalMotion) -> Vec<(f64, f64, f64)> {
+    helical_motion_calculate_with_options(rotational_motion, &InterpolationOptions::default())
+}
+
+/// Function to calculate positions for helical motion with explicit options
+///
+/// Behaves like [`helical_motion_calculate`], but lets the caller choose how
+/// the arc is split into points.
+pub fn helical_motion_calculate_with_options(
+    rotational_motion: RotationalMotion,
+    options: &InterpolationOptions,
+) -> Vec<(f64, f64, f64)> {
+    helical_points(&rotational_motion, options).collect()
+}
+
+/// Iterator over the positions of an arc, made by [`helical_points`]
+#[derive(Debug, Clone)]
+pub struct ArcPoints {
+    // The positions in the coordinates of the arc's plane
+    steps: ArcSteps,
+    plane: Plane,
+}
+
+impl Iterator for ArcPoints {
+    type Item = (f64, f64, f64);
+
+    // Arcs in the XZ and YZ planes are mapped back onto the machine's axes
+    fn next(&mut self) -> Option<Self::Item> {
+        self.steps.next().map(|point| self.plane.point(point))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.steps.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.steps.nth(n).map(|point| self.plane.point(point))
+    }
+
+    fn last(self) -> Option<Self::Item> {
+        let plane = self.plane;
+        self.steps.last().map(|point| plane.point(point))
+    }
+}
+
+impl ExactSizeIterator for ArcPoints {}
+
+/// Function to lazily calculate the positions of an arc or helix
+///
+/// Yields the same positions as [`helical_motion_calculate_with_options`],
+/// one at a time, so arcs with a fine step don't have to be held in memory.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::helical_points;
+/// use project_2::motion::Motion;
+///
+/// let Motion::Rotational(arc) = Motion::new_helical((0.0, 0.0), 5.0, 0.0, 90.0, (0.0, -1.0)) else {
+///     unreachable!()
+/// };
+/// let mut points = helical_points(&arc, &Default::default());
+/// assert_eq!(points.len(), 91);
+/// assert_eq!(points.next(), Some((5.0, 0.0, 0.0)));
+/// ```
+pub fn helical_points(rotational_motion: &RotationalMotion, options: &InterpolationOptions) -> ArcPoints {
+    ArcPoints {
+        steps: arc_sweep(rotational_motion, options),
+        plane: rotational_motion.plane,
+    }
+}
+
+/// Iterator over the positions of a Bézier curve, made by [`bezier_points`]
+#[derive(Debug, Clone)]
+pub struct BezierPoints {
+    curve: BezierMotion,
+    // Distance along the curve at evenly spaced parameters
+    lengths: Vec<f64>,
+    // Index of the next position, and of the last one
+    next: usize,
+    num_steps: usize,
+}
+
+impl BezierPoints {
+    // The parameter at which the curve has covered `distance`, found by
+    // interpolating between the measured chords
+    fn parameter(&self, distance: f64) -> f64 {
+        let chords = self.lengths.len() - 1;
+        let index = self.lengths.partition_point(|&length| length < distance).clamp(1, chords);
+        let (before, after) = (self.lengths[index - 1], self.lengths[index]);
+        let fraction = if after > before { (distance - before) / (after - before) } else { 0.0 };
+        (index as f64 - 1.0 + fraction) / chords as f64
+    }
+}
+
+impl Iterator for BezierPoints {
+    type Item = (f64, f64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.num_steps {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+
+        // The ends are exact, everything in between is spaced evenly along the curve
+        let t = match i {
+            0 => 0.0,
+            _ if i == self.num_steps => 1.0,
+            _ => {
+                let total = self.lengths[self.lengths.len() - 1];
+                self.parameter(total * i as f64 / self.num_steps as f64)
+            }
+        };
+        Some(self.curve.point(t))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.num_steps + 1).saturating_sub(self.next);
+        (remaining, Some(remaining))
+    }
+
+    // Skipping ahead is just a matter of moving the index
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.next = self.next.saturating_add(n);
+        self.next()
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next = self.next.max(self.num_steps);
+        self.next()
+    }
+}
+
+impl ExactSizeIterator for BezierPoints {}
+
+/// Function to lazily calculate the positions of a cubic Bézier curve
+///
+/// Points are spaced evenly along the curve rather than in its parameter, so
+/// they don't bunch up where the control points pull the curve tight. They
+/// are at most `options.linear_step` apart, measured along the curve.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::bezier_points;
+/// use project_2::motion::Motion;
+///
+/// let Motion::Bezier(curve) = Motion::new_bezier((0.0, 0.0, 0.0), (0.0, 4.0, 0.0), (4.0, 4.0, 0.0), (4.0, 0.0, 0.0)) else {
+///     unreachable!()
+/// };
+/// let points: Vec<_> = bezier_points(&curve, &Default::default()).collect();
+/// assert_eq!(points.first(), Some(&(0.0, 0.0, 0.0)));
+/// assert_eq!(points.last(), Some(&(4.0, 0.0, 0.0)));
+/// ```
+pub fn bezier_points(bezier_motion: &BezierMotion, options: &InterpolationOptions) -> BezierPoints {
+    // Measure the curve chord by chord
+    let mut lengths = Vec::with_capacity(BezierMotion::CHORDS + 1);
+    let mut total = 0.0;
+    let mut previous = bezier_motion.start;
+    lengths.push(0.0);
+    for i in 1..=BezierMotion::CHORDS {
+        let point = bezier_motion.point(i as f64 / BezierMotion::CHORDS as f64);
+        total += ((point.0 - previous.0).powi(2) + (point.1 - previous.1).powi(2) + (point.2 - previous.2).powi(2)).sqrt();
+        lengths.push(total);
+        previous = point;
+    }
+
+    BezierPoints {
+        curve: bezier_motion.clone(),
+        lengths,
+        next: 0,
+        num_steps: ((total / options.linear_step).ceil() as usize).max(1),
+    }
+}
+
+/// Iterator over the positions of a NURBS curve, made by [`spline_points`]
+#[derive(Debug, Clone)]
+pub struct SplinePoints {
+    // Splines are sampled adaptively, so the points are worked out up front
+    points: std::vec::IntoIter<(f64, f64, f64)>,
+}
+
+impl Iterator for SplinePoints {
+    type Item = (f64, f64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.points.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.points.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.points.nth(n)
+    }
+
+    fn last(self) -> Option<Self::Item> {
+        self.points.last()
+    }
+}
+
+impl ExactSizeIterator for SplinePoints {}
+
+// How far spline chords may stray from the curve unless a chord tolerance is given
+const SPLINE_TOLERANCE: f64 = 0.01;
+
+// Halvings of a single knot span before sampling gives up on the tolerance
+const MAX_DEPTH: u32 = 16;
+
+/// Function to calculate the positions of a NURBS curve to a tolerance
+///
+/// The curve is split wherever a chord would stray from it by more than the
+/// chord tolerance, [`ArcStep::ChordTolerance`] if one is set and 0.01
+/// otherwise, or would be longer than `options.linear_step`. Every knot is a
+/// split point too, so short pieces of the curve aren't skipped over. Tight
+/// bends get more points than straight stretches.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::{spline_points, ArcStep, InterpolationOptions};
+/// use project_2::motion::Motion;
+///
+/// // A rational quadratic tracing a quarter circle of radius 10
+/// let controls = vec![(10.0, 0.0, 0.0), (10.0, 10.0, 0.0), (0.0, 10.0, 0.0)];
+/// let weights = vec![1.0, std::f64::consts::FRAC_1_SQRT_2, 1.0];
+/// let Motion::Spline(curve) = Motion::new_spline(controls, weights, None, 2).unwrap() else {
+///     unreachable!()
+/// };
+/// let options = InterpolationOptions { linear_step: 100.0, arc_step: ArcStep::ChordTolerance(0.1), ..Default::default() };
+/// let points: Vec<_> = spline_points(&curve, &options).collect();
+/// assert_eq!(points.first(), Some(&(10.0, 0.0, 0.0)));
+/// assert!(points.len() < 20);
+/// ```
+pub fn spline_points(spline_motion: &SplineMotion, options: &InterpolationOptions) -> SplinePoints {
+    let tolerance = match options.arc_step {
+        ArcStep::ChordTolerance(tolerance) => tolerance,
+        _ => SPLINE_TOLERANCE,
+    };
+
+    // Split between (t, point) pairs until every chord is close and short enough
+    fn split(
+        spline_motion: &SplineMotion,
+        from: (f64, (f64, f64, f64)),
+        to: (f64, (f64, f64, f64)),
+        limits: (f64, f64),
+        depth: u32,
+        points: &mut Vec<(f64, f64, f64)>,
+    ) {
+        let (tolerance, step) = limits;
+        let chord = (to.1 .0 - from.1 .0, to.1 .1 - from.1 .1, to.1 .2 - from.1 .2);
+        let length = (chord.0 * chord.0 + chord.1 * chord.1 + chord.2 * chord.2).sqrt();
+        // Distance of the curve from the chord, checked at its quarters so S-bends show up
+        let deviation = |t: f64| {
+            let point = spline_motion.point(t);
+            let offset = (point.0 - from.1 .0, point.1 - from.1 .1, point.2 - from.1 .2);
+            if length < 1e-12 {
+                return (offset.0 * offset.0 + offset.1 * offset.1 + offset.2 * offset.2).sqrt();
+            }
+            let cross = (
+                offset.1 * chord.2 - offset.2 * chord.1,
+                offset.2 * chord.0 - offset.0 * chord.2,
+                offset.0 * chord.1 - offset.1 * chord.0,
+            );
+            (cross.0 * cross.0 + cross.1 * cross.1 + cross.2 * cross.2).sqrt() / length
+        };
+        let quarters = [0.25, 0.5, 0.75].map(|fraction| from.0 + (to.0 - from.0) * fraction);
+        let close = quarters.iter().all(|&t| deviation(t) <= tolerance);
+        if depth >= MAX_DEPTH || (close && length <= step) {
+            points.push(to.1);
+            return;
+        }
+        let middle = (quarters[1], spline_motion.point(quarters[1]));
+        split(spline_motion, from, middle, limits, depth + 1, points);
+        split(spline_motion, middle, to, limits, depth + 1, points);
+    }
+
+    // Start from the knots, as parameters from 0 to 1 across the curve
+    let (low, high) = (spline_motion.knots[spline_motion.degree], spline_motion.knots[spline_motion.controls.len()]);
+    let mut breaks: Vec<f64> = spline_motion
+        .knots
+        .iter()
+        .filter(|&&knot| knot > low && knot < high)
+        .map(|&knot| (knot - low) / (high - low))
+        .collect();
+    breaks.dedup();
+    breaks.push(1.0);
+
+    let mut points = vec![spline_motion.start()];
+    let mut from = (0.0, spline_motion.start());
+    for t in breaks {
+        let to = (t, spline_motion.point(t));
+        split(spline_motion, from, to, (tolerance, options.linear_step), 0, &mut points);
+        from = to;
+    }
+    SplinePoints {
+        points: points.into_iter(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Import necessary items from the parent module
+    use super::*;
+    use crate::motion::Motion;
+
+    /// Test the `linear_motion_calculate` function.
+    #[test]
+    fn test_linear_motion_calculate() {
+        // Test linear motion calculation function
+        let start = (0.0, 0.0, 0.0);
+        let end = (3.0, 4.0, 5.0);
+        let positions = linear_motion_calculate(start, end);
+        assert_eq!(positions.len(), 7); // Adjusted for inclusive start and end points
+        assert_eq!(positions[0], "0.00, 0.00, 0.00"); // Adjusted start position
+        assert_eq!(positions[6], "3.00, 4.00, 5.00"); // Check last position
+    }
+
+    /// Test the `rotational_motion_calculate` function.
+    #[test]
+    fn test_rotational_motion_calculate() {
+        // Test rotational motion calculation function
+        let rotational_motion = RotationalMotion {
+            center: (0.0, 0.0),
+            radius: 5.0,
+            start_angle: 0.0,
+            sweep: 90.0,
+            z: None,
+            end: None,
+            plane: Plane::Xy,
+            feed: None,
+            power: None,
+            line: None,
+        };
+        let positions = rotational_motion_calculate(rotational_motion);
+        assert_eq!(positions.len(), 91); // One position per degree at radius 5
+        assert_eq!(positions[0], (5.00, 0.00)); // Check first position
+        assert!((positions[90].0 - 0.00).abs() < 1e-9); // Check last position
+        assert!((positions[90].1 - 5.00).abs() < 1e-9);
+    }
+
+    /// Test a partial arc that doesn't start on the +X axis.
+    #[test]
+    fn test_rotational_motion_start_angle() {
+        let rotational_motion = RotationalMotion {
+            center: (1.0, 1.0),
+            radius: 2.0,
+            start_angle: 90.0,
+            sweep: 90.0,
+            z: None,
+            end: None,
+            plane: Plane::Xy,
+            feed: None,
+            power: None,
+            line: None,
+        };
+        let positions = rotational_motion_calculate(rotational_motion);
+        assert!((positions[0].0 - 1.0).abs() < 1e-9); // Starts straight above the center
+        assert!((positions[0].1 - 3.0).abs() < 1e-9);
+        let last = positions[positions.len() - 1];
+        assert!((last.0 + 1.0).abs() < 1e-9); // Ends to the left of the center
+        assert!((last.1 - 1.0).abs() < 1e-9);
+    }
+
+    /// Test that helical motion ramps Z evenly across the sweep.
+    #[test]
+    fn test_helical_motion_calculate() {
+        let rotational_motion = RotationalMotion {
+            center: (0.0, 0.0),
+            radius: 5.0,
+            start_angle: 0.0,
+            sweep: 360.0,
+            z: Some((1.0, -1.0)),
+            end: None,
+            plane: Plane::Xy,
+            feed: None,
+            power: None,
+            line: None,
+        };
+        let positions = helical_motion_calculate(rotational_motion);
+        assert_eq!(positions.len(), 361);
+        assert_eq!(positions[0], (5.0, 0.0, 1.0));
+        assert!((positions[180].0 + 5.0).abs() < 1e-9); // Half a turn in...
+        assert!(positions[180].2.abs() < 1e-9); // ...is halfway down
+        assert_eq!(positions[360].2, -1.0);
+    }
+
+    /// Test that arcs given by their end point end exactly on it, as lines do.
+    #[test]
+    fn test_arc_end_points() {
+        let (start, end) = ((1.0, 0.0, 0.0), (0.6, 0.8, -0.3));
+        let arcs = [
+            Motion::new_arc_from_offset(start, end, (-1.0, 0.0), false),
+            Motion::new_arc_from_radius(start, end, 1.0, false).unwrap(),
+            Motion::new_arc_through(start, (0.8, 0.6, 0.0), end).unwrap(),
+            Motion::new_arc_from_offset(start, end, (-1.0, 0.0), false).with_plane(Plane::Xz),
+        ];
+        for arc in arcs {
+            let Motion::Rotational(arc) = arc else { panic!("expected an arc") };
+            let plane = arc.plane;
+            assert_eq!(helical_points(&arc, &Default::default()).last(), Some(plane.point(end)));
+            let options = InterpolationOptions {
+                arc_step: ArcStep::ChordTolerance(1e-4),
+                ..Default::default()
+            };
+            assert_eq!(helical_points(&arc, &options).last(), Some(plane.point(end)));
+        }
+    }
+
+    /// Test that the chord tolerance bounds the deviation from the true arc.
+    #[test]
+    fn test_chord_tolerance() {
+        let options = InterpolationOptions {
+            arc_step: ArcStep::ChordTolerance(0.01),
+            ..Default::default()
+        };
+        for radius in [0.5, 5.0, 500.0] {
+            let rotational_motion = RotationalMotion {
+                center: (0.0, 0.0),
+                radius,
+                start_angle: 0.0,
+                sweep: 90.0,
+                z: None,
+                end: None,
+                plane: Plane::Xy,
+                feed: None,
+                power: None,
+                line: None,
+            };
+            let positions = rotational_motion_calculate_with_options(rotational_motion, &options);
+            // The midpoint of each chord is where it strays furthest from the arc
+            for pair in positions.windows(2) {
+                let mid = ((pair[0].0 + pair[1].0) / 2.0, (pair[0].1 + pair[1].1) / 2.0);
+                assert!(radius - mid.0.hypot(mid.1) <= 0.01 + 1e-12);
+            }
+        }
+
+        // Larger arcs need more points, tiny arcs fewer
+        let count = |radius| {
+            let rotational_motion = RotationalMotion {
+                center: (0.0, 0.0),
+                radius,
+                start_angle: 0.0,
+                sweep: 90.0,
+                z: None,
+                end: None,
+                plane: Plane::Xy,
+                feed: None,
+                power: None,
+                line: None,
+            };
+            rotational_motion_calculate_with_options(rotational_motion, &options).len()
+        };
+        assert!(count(500.0) > count(5.0));
+        assert_eq!(count(0.005), 2);
+    }
+
+    /// Test configurable linear and angular resolution.
+    #[test]
+    fn test_configurable_resolution() {
+        let options = InterpolationOptions {
+            linear_step: 0.25,
+            arc_step: ArcStep::Angle(10.0),
+            ..Default::default()
+        };
+        let positions = linear_motion_calculate_with_options((0.0, 0.0, 0.0), (0.0, 2.0, 0.0), &options);
+        assert_eq!(positions.len(), 10);
+        assert_eq!(positions[9], "0.00, 2.00, 0.00");
+
+        let rotational_motion = RotationalMotion {
+            center: (0.0, 0.0),
+            radius: 5.0,
+            start_angle: 0.0,
+            sweep: 90.0,
+            z: None,
+            end: None,
+            plane: Plane::Xy,
+            feed: None,
+            power: None,
+            line: None,
+        };
+        let positions = rotational_motion_calculate_with_options(rotational_motion, &options);
+        assert_eq!(positions.len(), 10); // 0, 10, ..., 90 degrees
+    }
+
+    /// Test that Bézier points are evenly spaced along the curve.
+    #[test]
+    fn test_bezier_spacing() {
+        // Control points bunched near the start make the parameter crawl there
+        let Motion::Bezier(curve) =
+            Motion::new_bezier((0.0, 0.0, 0.0), (0.1, 0.0, 0.0), (0.2, 0.0, 0.0), (10.0, 0.0, 0.0))
+        else {
+            unreachable!()
+        };
+        let points: Vec<_> = bezier_points(&curve, &InterpolationOptions::default()).collect();
+        assert_eq!(points.len(), 11);
+        for (i, point) in points.iter().enumerate() {
+            assert!((point.0 - i as f64).abs() < 1e-3, "{:?}", points);
+        }
+    }
+
+    /// Test that spline chords stay within the tolerance and bunch up in tight bends.
+    #[test]
+    fn test_spline_tolerance() {
+        // A quarter circle of radius 10 as a rational quadratic
+        let controls = vec![(10.0, 0.0, 0.0), (10.0, 10.0, 0.0), (0.0, 10.0, 0.0)];
+        let weights = vec![1.0, std::f64::consts::FRAC_1_SQRT_2, 1.0];
+        let Motion::Spline(curve) = Motion::new_spline(controls, weights, None, 2).unwrap() else {
+            unreachable!()
+        };
+        let options = InterpolationOptions {
+            linear_step: 100.0,
+            arc_step: ArcStep::ChordTolerance(0.01),
+            ..Default::default()
+        };
+        let points: Vec<_> = spline_points(&curve, &options).collect();
+        assert_eq!(points.last(), Some(&(0.0, 10.0, 0.0)));
+        for pair in points.windows(2) {
+            // The chord's midpoint is where it strays furthest from the circle
+            let middle = ((pair[0].0 + pair[1].0) / 2.0, (pair[0].1 + pair[1].1) / 2.0);
+            assert!(10.0 - middle.0.hypot(middle.1) <= 0.01 + 1e-9);
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Interpolation math for embedded motion controllers
+//!
+//! The straight-line and arc generators at the heart of [`interpolate`](crate::interpolate),
+//! written against `core` alone so the same code can run in `no_std`
+//! firmware, such as on a Cortex-M motion controller. Nothing here allocates:
+//! each generator is an iterator that works out its next position on demand,
+//! so it needs neither the standard library nor an allocator. Build the crate
+//! with `default-features = false` to get just this module.
+//!
+//! Without the standard library, the trigonometry comes from `libm`, which
+//! can differ from the platform's in the last bit.
+
+use core::f64::consts::PI;
+
+/// A position along the X, Y and Z axes, or the two axes of an arc's plane and its normal
+pub type Point = (f64, f64, f64);
+
+// Floating point functions from the standard library when there is one, otherwise from libm
+#[cfg(feature = "std")]
+mod float {
+    pub fn abs(x: f64) -> f64 {
+        x.abs()
+    }
+    pub fn ceil(x: f64) -> f64 {
+        x.ceil()
+    }
+    pub fn round(x: f64) -> f64 {
+        x.round()
+    }
+    pub fn sin(x: f64) -> f64 {
+        x.sin()
+    }
+    pub fn cos(x: f64) -> f64 {
+        x.cos()
+    }
+    pub fn acos(x: f64) -> f64 {
+        x.acos()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+mod float {
+    pub use libm::{acos, ceil, cos, fabs as abs, round, sin};
+}
+
+/// How the angular step between arc points is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ArcStep {
+    /// A step of `5.0 / radius` degrees, keeping point spacing roughly constant
+    #[default]
+    RadiusScaled,
+    /// A fixed step, in degrees
+    Angle(f64),
+    /// The largest step whose chords stay within this distance of the true arc
+    ChordTolerance(f64),
+}
+
+impl ArcStep {
+    /// Function to calculate the angular step, in radians, between points on an arc of this radius
+    pub fn angle(self, radius: f64) -> f64 {
+        match self {
+            ArcStep::RadiusScaled => (5.0 / radius).to_radians(),
+            ArcStep::Angle(degrees) => degrees.to_radians(),
+            ArcStep::ChordTolerance(tolerance) => {
+                // A chord spanning angle θ deviates from the arc by r(1 - cos(θ/2))
+                if tolerance >= radius {
+                    PI
+                } else {
+                    2.0 * float::acos(1.0 - tolerance / radius)
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over evenly spaced positions along a straight line, made by [`linear_steps`]
+#[derive(Debug, Clone)]
+pub struct LinearSteps {
+    // Where the line starts and ends
+    start: Point,
+    end: Point,
+    // Index of the next position, and of the last one
+    next: usize,
+    num_steps: usize,
+}
+
+impl Iterator for LinearSteps {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.num_steps {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+
+        // The last position is the end point itself, whatever rounding the steps before it pick up
+        if i == self.num_steps {
+            return Some(self.end);
+        }
+        let fraction = i as f64 / self.num_steps as f64;
+        Some((
+            self.start.0 + (self.end.0 - self.start.0) * fraction,
+            self.start.1 + (self.end.1 - self.start.1) * fraction,
+            self.start.2 + (self.end.2 - self.start.2) * fraction,
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.num_steps.saturating_add(1).saturating_sub(self.next);
+        (remaining, Some(remaining))
+    }
+
+    // Skipping ahead is just a matter of moving the index
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.next = self.next.saturating_add(n);
+        self.next()
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next = self.next.max(self.num_steps);
+        self.next()
+    }
+}
+
+impl ExactSizeIterator for LinearSteps {}
+
+/// Function to split a straight line into steps of at most `max_step` along any axis
+///
+/// Both ends are included exactly, and there is always at least one step.
+///
+/// # Arguments
+///
+/// * `start` - Where the line starts
+/// * `end` - Where the line ends
+/// * `max_step` - Largest change along any axis from one position to the next,
+///   which has to be positive and finite
+/// * `turned` - How far any other axis moving along with the line turns, such
+///   as a rotary axis in degrees, which is split up the same way
+///
+/// # Examples
+///
+/// ```
+/// use project_2::kernel::linear_steps;
+///
+/// let mut steps = linear_steps((0.0, 0.0, 0.0), (2.0, 1.0, 0.0), 1.0, 0.0);
+/// assert_eq!(steps.len(), 4);
+/// assert_eq!(steps.nth(1), Some((2.0 / 3.0, 1.0 / 3.0, 0.0)));
+/// ```
+pub fn linear_steps(start: Point, end: Point, max_step: f64, turned: f64) -> LinearSteps {
+    // Determine the maximum magnitude of change along any axis
+    let max_delta = float::abs(end.0 - start.0)
+        .max(float::abs(end.1 - start.1))
+        .max(float::abs(end.2 - start.2))
+        .max(turned);
+
+    // Determine the number of steps
+    let num_steps = float::ceil(float::abs(max_delta) / max_step + 1.0) as usize;
+
+    // Positions for each step, including the start and end points
+    LinearSteps {
+        start,
+        end,
+        next: 0,
+        num_steps,
+    }
+}
+
+/// Iterator over positions along an arc or helix, made by [`arc_steps`]
+///
+/// Positions are in the coordinates of the arc's plane, with the height along
+/// its normal last.
+#[derive(Debug, Clone)]
+pub struct ArcSteps {
+    // The circle the arc lies on
+    center: (f64, f64),
+    radius: f64,
+    // Where the sweep starts, how far it goes and where it ends, in radians
+    start_angle: f64,
+    sweep: f64,
+    end_angle: f64,
+    // Heights along the normal at the start and the end
+    z: (f64, f64),
+    // Whether the sweep is a whole number of turns, so it ends where it starts
+    closed: bool,
+    // The exact last position in the plane, if the arc was given one
+    end: Option<(f64, f64)>,
+    // Index of the next position, and of the last one
+    next: usize,
+    num_steps: usize,
+}
+
+impl Iterator for ArcSteps {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.num_steps {
+            return None;
+        }
+        let i = self.next;
+        self.next += 1;
+
+        // The sweep is split into whole steps, and the last position is exactly on the stop angle and end
+        // height rather than a sum that rounding leaves short or long. A full circle closes on its first point
+        // rather than on one rounded a turn later.
+        let (angle, z) = if i == self.num_steps {
+            if let Some((x, y)) = self.end {
+                return Some((x, y, self.z.1));
+            }
+            (if self.closed { self.start_angle } else { self.end_angle }, self.z.1)
+        } else {
+            let (i, n) = (i as f64, self.num_steps as f64);
+            (self.start_angle + self.sweep * i / n, self.z.0 + (self.z.1 - self.z.0) * i / n)
+        };
+        Some((
+            self.center.0 + self.radius * float::cos(angle),
+            self.center.1 + self.radius * float::sin(angle),
+            z,
+        ))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.num_steps.saturating_add(1).saturating_sub(self.next);
+        (remaining, Some(remaining))
+    }
+
+    // Skipping ahead is just a matter of moving the index
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.next = self.next.saturating_add(n);
+        self.next()
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next = self.next.max(self.num_steps);
+        self.next()
+    }
+}
+
+impl ExactSizeIterator for ArcSteps {}
+
+impl ArcSteps {
+    /// End the arc exactly on `end`, a point in its plane, as [`linear_steps`] ends on its end point
+    ///
+    /// An arc given by its end point stops there, rather than where its stop
+    /// angle puts it after rounding.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::kernel::{arc_steps, ArcStep};
+    ///
+    /// let steps = arc_steps((0.0, 0.0), 1.0, 0.0, 53.13, (0.0, 0.0), ArcStep::Angle(10.0));
+    /// assert_eq!(steps.with_end((0.6, 0.8)).last(), Some((0.6, 0.8, 0.0)));
+    /// ```
+    pub fn with_end(mut self, end: (f64, f64)) -> Self {
+        self.end = Some(end);
+        self
+    }
+}
+
+/// Function to split an arc or helix into steps
+///
+/// The angles follow the convention of [`RotationalMotion`](crate::motion::RotationalMotion): the arc
+/// starts at `start_angle`, measured counter-clockwise from the first axis,
+/// and turns through `sweep`, counter-clockwise if it is positive and
+/// clockwise if it is negative. Height ramps evenly from the first value of
+/// `z` to the second along the way.
+///
+/// # Arguments
+///
+/// * `center` - Center of the circle the arc lies on
+/// * `radius` - Radius of the circle
+/// * `start_angle` - Angle the arc starts at, in degrees
+/// * `sweep` - Angle the arc turns through, in degrees
+/// * `z` - Heights along the normal at the start and the end
+/// * `arc_step` - How the angle between positions is chosen
+///
+/// # Examples
+///
+/// ```
+/// use project_2::kernel::{arc_steps, ArcStep};
+///
+/// let mut steps = arc_steps((0.0, 0.0), 5.0, 0.0, 90.0, (0.0, -1.0), ArcStep::Angle(45.0));
+/// assert_eq!(steps.len(), 3);
+/// assert_eq!(steps.next(), Some((5.0, 0.0, 0.0)));
+/// assert_eq!(steps.last().map(|(_, _, z)| z), Some(-1.0));
+/// ```
+pub fn arc_steps(
+    center: (f64, f64),
+    radius: f64,
+    start_angle: f64,
+    sweep: f64,
+    z: (f64, f64),
+    arc_step: ArcStep,
+) -> ArcSteps {
+    // Define constant for degree to radian conversion
+    const DEG_TO_RAD: f64 = PI / 180.0;
+
+    // A sweep of a whole number of turns, such as 360 degrees, draws full circles
+    let turns = sweep / 360.0;
+    let closed = turns != 0.0 && float::abs(turns - float::round(turns)) < 1e-12;
+
+    // The angles are given in degrees
+    let end_angle = DEG_TO_RAD * (start_angle + sweep);
+    let start_angle = DEG_TO_RAD * start_angle;
+    let sweep = DEG_TO_RAD * sweep;
+
+    // Split the sweep into whole steps, with at least one for a helix that only changes height
+    let num_steps = float::ceil(float::abs(sweep) / arc_step.angle(radius)) as usize;
+    let num_steps = if z.0 != z.1 { num_steps.max(1) } else { num_steps };
+    ArcSteps {
+        center,
+        radius,
+        start_angle,
+        sweep,
+        end_angle,
+        z,
+        closed,
+        end: None,
+        next: 0,
+        num_steps,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that lines end exactly where they should, whatever the step.
+    #[test]
+    fn test_linear_steps() {
+        let steps = linear_steps((1.0, 2.0, 3.0), (4.0, -2.0, 3.0), 0.3, 0.0);
+        assert_eq!(steps.len(), 16);
+        assert_eq!(steps.last(), Some((4.0, -2.0, 3.0)));
+        // Steps that don't add up to the line in floating point still end on it
+        for step in [0.1, 0.07, 0.3] {
+            assert_eq!(linear_steps((0.1, 0.2, 0.7), (0.3, 0.9, 0.1), step, 0.0).last(), Some((0.3, 0.9, 0.1)));
+        }
+        // Turning an axis without moving still gets steps
+        assert_eq!(linear_steps((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), 1.0, 10.0).len(), 12);
+        // A step of zero is refused where options are read, but counting its steps still doesn't overflow
+        assert_eq!(linear_steps((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), 0.0, 0.0).size_hint().0, usize::MAX);
+    }
+
+    /// Test that arcs go the right way round and stay on their circle.
+    #[test]
+    fn test_arc_steps() {
+        let steps = arc_steps((1.0, 1.0), 2.0, 0.0, -90.0, (0.0, 0.0), ArcStep::ChordTolerance(0.01));
+        let mut count = 0;
+        for (x, y, _) in steps.clone() {
+            assert!(((x - 1.0).hypot(y - 1.0) - 2.0).abs() < 1e-12);
+            count += 1;
+        }
+        assert_eq!(count, steps.len());
+        // A negative sweep turns clockwise, from +X down to -Y
+        let second = steps.clone().nth(1).unwrap();
+        assert!(second.1 < 1.0);
+        let end = steps.last().unwrap();
+        assert!((end.0 - 1.0).abs() < 1e-12 && (end.1 + 1.0).abs() < 1e-12);
+    }
+
+    /// Test that arcs end exactly on their stop angle and end height, whatever the step.
+    #[test]
+    fn test_arc_ends() {
+        for (sweep, step) in [(90.1, 0.7), (-90.1, 0.7), (90.1, 13.0), (-90.1, 0.01)] {
+            let steps = arc_steps((0.1, 0.2), 3.3, 10.0, sweep, (0.1, 0.3), ArcStep::Angle(step));
+            let stop = (10.0 + sweep).to_radians();
+            let end = (0.1 + 3.3 * stop.cos(), 0.2 + 3.3 * stop.sin(), 0.3);
+            assert_eq!(steps.last(), Some(end));
+        }
+        // A helix that only changes height still gets from one end to the other
+        let steps: Vec<_> = arc_steps((0.0, 0.0), 1.0, 0.0, 0.0, (0.0, -1.0), ArcStep::Angle(1.0)).collect();
+        assert_eq!(steps, vec![(1.0, 0.0, 0.0), (1.0, 0.0, -1.0)]);
+    }
+
+    /// Test that a full circle ends exactly on its first point, with helices still rising along the way.
+    #[test]
+    fn test_full_circle() {
+        for sweep in [360.0, -360.0, 720.0] {
+            let steps = arc_steps((3.0, -2.0), 7.0, 33.0, sweep, (0.0, -2.0), ArcStep::Angle(7.0));
+            let first = steps.clone().next().unwrap();
+            let last = steps.last().unwrap();
+            assert_eq!((last.0, last.1, last.2), (first.0, first.1, -2.0));
+        }
+        // Anything short of a turn stays open
+        let last = arc_steps((0.0, 0.0), 1.0, 0.0, 359.0, (0.0, 0.0), ArcStep::Angle(1.0)).last().unwrap();
+        assert!(last.1 < 0.0);
+    }
+}
@@ -0,0 +1,183 @@
+//! Converting positions into the coordinates of a machine's motors
+//!
+//! Programs and their interpolated positions are always cartesian, and on
+//! most machines each motor drives one axis, so that's also what the motors
+//! see. Other machines move the tool with several motors at once: on a CoreXY
+//! gantry two belts share the X and Y carriages, so both motors turn for a
+//! move along either axis. A SCARA arm swings two links about vertical
+//! joints, so its motors turn through angles rather than move along axes.
+//! [`Kinematics`] turns cartesian positions into the positions of those
+//! motors, so trajectories can be fed to the motors directly.
+//!
+//! Timing is worked out from the cartesian path, as the controller plans it,
+//! and the conversion only changes where each sample says the motors are.
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+type Point = (f64, f64, f64);
+
+/// How the machine's motors move the tool
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Kinematics {
+    /// One motor per axis, so motor positions are the cartesian ones
+    #[default]
+    Cartesian,
+    /// A CoreXY gantry, whose A and B motors each drive both X and Y
+    ///
+    /// A turns by X + Y and B by X - Y; Z has a motor of its own.
+    CoreXy,
+    /// A SCARA arm with its shoulder at the origin, whose motors give joint angles
+    ///
+    /// The shoulder angle is measured counter-clockwise from +X to the inner
+    /// link, and the elbow angle from the inner link to the outer one, both in
+    /// degrees; Z moves the tool up and down as usual. The tool can only reach
+    /// the ring between `inner - outer` and `inner + outer` from the shoulder.
+    Scara {
+        /// Length of the link from the shoulder to the elbow
+        inner: f64,
+        /// Length of the link from the elbow to the tool
+        outer: f64,
+        /// Which way the elbow bends, since most points can be reached either way
+        elbow: Elbow,
+    },
+}
+
+/// Which side of the line from a SCARA arm's shoulder to the tool its elbow is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Elbow {
+    /// On the left looking out from the shoulder, with a negative elbow angle
+    Left,
+    /// On the right looking out from the shoulder, with a positive elbow angle
+    #[default]
+    Right,
+}
+
+impl FromStr for Elbow {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "left" => Ok(Elbow::Left),
+            "right" => Ok(Elbow::Right),
+            _ => Err(format!("Unknown elbow {}, expected left or right", name)),
+        }
+    }
+}
+
+impl FromStr for Kinematics {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "cartesian" => Ok(Kinematics::Cartesian),
+            "corexy" => Ok(Kinematics::CoreXy),
+            // The arm's lengths are given separately
+            "scara" => Ok(Kinematics::Scara {
+                inner: 0.0,
+                outer: 0.0,
+                elbow: Elbow::default(),
+            }),
+            _ => Err(format!("Unknown kinematics {}, expected cartesian, corexy or scara", name)),
+        }
+    }
+}
+
+impl Kinematics {
+    /// Function to find where the motors are when the tool is at a cartesian position
+    ///
+    /// # Returns
+    ///
+    /// The positions of the motors: X, Y and Z for a cartesian machine, A, B
+    /// and Z for CoreXY, and the shoulder angle, elbow angle and Z for SCARA.
+    /// Returns `None` if the arm can't reach the position.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::kinematics::{Elbow, Kinematics};
+    ///
+    /// assert_eq!(Kinematics::CoreXy.motors((10.0, 4.0, -1.0)), Some((14.0, 6.0, -1.0)));
+    ///
+    /// let arm = Kinematics::Scara { inner: 100.0, outer: 100.0, elbow: Elbow::Right };
+    /// let (shoulder, elbow, _) = arm.motors((100.0, 100.0, 0.0)).unwrap();
+    /// assert!(shoulder.abs() < 1e-9 && (elbow - 90.0).abs() < 1e-9);
+    /// assert_eq!(arm.motors((250.0, 0.0, 0.0)), None);
+    /// ```
+    pub fn motors(&self, (x, y, z): Point) -> Option<Point> {
+        match *self {
+            Kinematics::Cartesian => Some((x, y, z)),
+            Kinematics::CoreXy => Some((x + y, x - y, z)),
+            Kinematics::Scara { inner, outer, elbow } => {
+                // The elbow angle follows from the distance to the tool, by the law of cosines
+                let cos = (x * x + y * y - inner * inner - outer * outer) / (2.0 * inner * outer);
+                if cos.abs() > 1.0 + 1e-9 {
+                    return None;
+                }
+                let bend = match elbow {
+                    Elbow::Left => -cos.clamp(-1.0, 1.0).acos(),
+                    Elbow::Right => cos.clamp(-1.0, 1.0).acos(),
+                };
+                // The shoulder points at the tool, less the angle the bent elbow adds
+                let shoulder = y.atan2(x) - (outer * bend.sin()).atan2(inner + outer * bend.cos());
+                // Keep the shoulder angle between -180° and 180°
+                let shoulder = (shoulder + PI).rem_euclid(2.0 * PI) - PI;
+                Some((shoulder.to_degrees(), bend.to_degrees(), z))
+            }
+        }
+    }
+
+    /// Function to find the cartesian position of the tool from where the motors are
+    ///
+    /// This undoes [`Kinematics::motors`].
+    pub fn cartesian(&self, (first, second, z): Point) -> Point {
+        match *self {
+            Kinematics::Cartesian => (first, second, z),
+            Kinematics::CoreXy => ((first + second) / 2.0, (first - second) / 2.0, z),
+            Kinematics::Scara { inner, outer, .. } => {
+                let (shoulder, reach) = (first.to_radians(), (first + second).to_radians());
+                (inner * shoulder.cos() + outer * reach.cos(), inner * shoulder.sin() + outer * reach.sin(), z)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that CoreXY motor positions convert back to the cartesian ones and that names parse.
+    #[test]
+    fn test_corexy() {
+        let position = (12.5, -3.0, 7.0);
+        assert_eq!(Kinematics::CoreXy.cartesian(Kinematics::CoreXy.motors(position).unwrap()), position);
+        // A move along X alone turns both motors the same way, one along Y turns them apart
+        assert_eq!(Kinematics::CoreXy.motors((1.0, 0.0, 0.0)), Some((1.0, 1.0, 0.0)));
+        assert_eq!(Kinematics::CoreXy.motors((0.0, 1.0, 0.0)), Some((1.0, -1.0, 0.0)));
+        assert_eq!(Kinematics::Cartesian.motors(position), Some(position));
+
+        assert_eq!("CoreXY".parse::<Kinematics>(), Ok(Kinematics::CoreXy));
+        assert!("delta".parse::<Kinematics>().is_err());
+    }
+
+    /// Test that SCARA joint angles lead back to the same point with either elbow, and that the reach is checked.
+    #[test]
+    fn test_scara() {
+        for elbow in [Elbow::Left, Elbow::Right] {
+            let arm = Kinematics::Scara {
+                inner: 200.0,
+                outer: 150.0,
+                elbow,
+            };
+            for position in [(300.0, 50.0, -2.0), (-120.0, 180.0, 0.0), (60.0, -40.0, 5.0)] {
+                let joints = arm.motors(position).unwrap();
+                assert_eq!(joints.1 > 0.0, elbow == Elbow::Right);
+                let (x, y, z) = arm.cartesian(joints);
+                assert!((x - position.0).abs() < 1e-9 && (y - position.1).abs() < 1e-9 && z == position.2);
+            }
+            // Past the outstretched arm, and inside the circle the folded arm can't reach
+            assert_eq!(arm.motors((351.0, 0.0, 0.0)), None);
+            assert_eq!(arm.motors((0.0, 49.0, 0.0)), None);
+        }
+    }
+}
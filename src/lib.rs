@@ -0,0 +1,154 @@
+//! Parser and interpolator for `.cmmd` motion programs.
+//!
+//! The library exposes the same pipeline the `project-2` binary uses, so other
+//! projects can embed the interpreter without shelling out to the CLI:
+//!
+//! - [`parser`] reads command files into a list of [`Motion`]s
+//! - [`binary`] writes and reads compact binary trajectories
+//! - [`commands`] lets library users add their own commands to the language
+//! - [`compensation`] offsets the path by the tool radius
+//! - [`cycles`] builds the moves of drilling and other cycles
+//! - [`gcode`] reads G-code files into the same [`Motion`]s
+//! - [`diff`] compares the paths of two versions of a program
+//! - [`drawing`] turns flat drawings, such as SVG paths, into programs
+//! - [`error`] describes what went wrong and where
+//! - [`machine`] tracks the modal feed rate and power, and reads machine profiles
+//! - [`motion`] defines the motion types
+//! - [`heightmap`] follows probed stock heights in Z
+//! - [`hpgl`] reads and writes HP-GL plots for pen plotters
+//! - [`interpolate`] turns each motion into a sequence of positions
+//! - [`kernel`] holds the line and arc math underneath, which builds without
+//!   the standard library for embedded controllers
+//! - [`kinematics`] converts positions into motor coordinates for machines like CoreXY
+//! - [`trajectory`] walks a program's positions lazily and times them using
+//!   each motion's feed rate
+//! - [`planner`] times them with acceleration limits instead
+//! - [`playback`] plays them back in real time, with feed hold and feed override
+//! - [`observer`] calls back as each motion and position is interpolated
+//! - [`optimize`] rewrites the path to run better, such as rounding off corners
+//!   or cutting the parts of a program in a shorter order
+//! - [`output`] writes programs and their points as JSON
+//! - [`progress`] draws progress bars with the time left
+//! - [`pulses`] times the step pulses of each motor
+//! - [`program`] holds a parsed program, which serde can store and read back, and converts it between languages
+//! - [`post`] writes programs back out as G-code or `.cmmd`
+//! - [`raster`] turns grayscale images into laser raster engraving programs
+//! - [`server`] interprets programs sent over TCP
+//! - [`simulate`] cuts a program out of a block of stock to show the finished part
+//! - [`spline`] evaluates NURBS curves and builds their knot vectors
+//! - [`stats`] measures path length, extent and run time
+//! - [`stream`] sends programs to a GRBL controller
+//! - [`svg`] renders the path as an SVG backplot
+//! - [`tools`] reads tool tables
+//! - [`transform`] moves, turns, scales and mirrors whole programs
+//! - [`verify`] compares a program's output with stored output
+//! - [`validate`] checks programs against the machine before they run
+//! - `script` runs Rhai hooks over motions and points (with the `scripting` feature)
+//! - `png` draws the same backplot as a PNG image (with the `png` feature)
+//! - `tui` browses the backplot in the terminal (with the `tui` feature)
+//! - `wasm` binds the parser and interpolator for JavaScript (with the `wasm` feature)
+//!
+//! Everything but [`kernel`] needs the standard library, through the default
+//! `std` feature. With `default-features = false` the crate is `no_std`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod binary;
+#[cfg(feature = "std")]
+mod blocks;
+#[cfg(feature = "std")]
+pub mod commands;
+#[cfg(feature = "std")]
+pub mod compensation;
+#[cfg(feature = "std")]
+pub mod cycles;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod drawing;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod gcode;
+#[cfg(feature = "std")]
+pub mod heightmap;
+#[cfg(feature = "std")]
+pub mod hpgl;
+#[cfg(feature = "std")]
+pub mod interpolate;
+pub mod kernel;
+#[cfg(feature = "std")]
+pub mod kinematics;
+#[cfg(feature = "std")]
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod motion;
+#[cfg(feature = "std")]
+pub mod observer;
+#[cfg(feature = "std")]
+pub mod optimize;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "std")]
+pub mod parser;
+#[cfg(feature = "std")]
+pub mod planner;
+#[cfg(feature = "std")]
+pub mod playback;
+#[cfg(feature = "png")]
+pub mod png;
+#[cfg(feature = "std")]
+pub mod post;
+#[cfg(feature = "std")]
+pub mod program;
+#[cfg(feature = "std")]
+pub mod raster;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod pulses;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod simulate;
+#[cfg(feature = "std")]
+mod source;
+#[cfg(feature = "std")]
+pub mod spline;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod svg;
+#[cfg(feature = "std")]
+pub mod tools;
+#[cfg(feature = "std")]
+pub mod trajectory;
+#[cfg(feature = "std")]
+pub mod transform;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "std")]
+pub use error::{
+    CmmdError, ConfigError, Discontinuity, DistortedArc, Divergence, LimitViolation, ParseError, SplineError,
+    StreamError, Unreachable,
+};
+#[cfg(feature = "std")]
+pub use interpolate::{
+    helical_motion_calculate, linear_motion_calculate, rotational_motion_calculate, ArcStep, InterpolationOptions,
+};
+#[cfg(feature = "std")]
+pub use motion::{LinearMotion, Motion, RotationalMotion};
+#[cfg(feature = "std")]
+pub use parser::{read_file, read_program, read_program_from, Dialect, DistanceMode, Extensions, Units};
@@ -0,0 +1,489 @@
+//! Machine state and machine profiles
+//!
+//! Feed rate and spindle speed (or laser power) are modal: once a program sets
+//! them they apply to every motion that follows until they are set again. Both
+//! front-ends keep them in a [`MachineState`] and stamp each motion with the
+//! values in effect when it was programmed, so everything downstream (timing,
+//! planning, G-code output) reads them straight off the motion.
+//!
+//! A [`MachineConfig`] describes the machine itself. It is written in TOML,
+//! with a table per axis; every key is optional:
+//!
+//! ```toml
+//! units = "mm"        # units of programs that don't say, "mm" or "inch"
+//! rapid_rate = 8000   # mm/min
+//! pause_time = 30     # seconds each PAUSE/M0/M1 is expected to last, in run times
+//!
+//! [x]
+//! min = 0             # travel limits, mm in machine coordinates
+//! max = 300
+//! max_velocity = 8000     # mm/min
+//! max_acceleration = 800  # mm/s²
+//! steps_per_mm = 80
+//!
+//! [remap]
+//! x = "-Y"            # output X is the program's Y, reversed
+//! y = "X"             # axes left out keep their own coordinate
+//!
+//! [[keep_out]]        # somewhere the tool must never go, such as a vise
+//! name = "vise"
+//! shape = "box"
+//! min = [0, 0, -20]
+//! max = [150, 40, 15]
+//!
+//! [[keep_out]]
+//! name = "clamp"
+//! shape = "cylinder"
+//! center = [200, 80]
+//! radius = 12
+//! bottom = -10
+//! top = 25
+//! ```
+
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::ConfigError;
+use crate::motion::Motion;
+use crate::parser::Units;
+use crate::planner::PlannerOptions;
+use crate::transform::Axis;
+
+type Point = (f64, f64, f64);
+
+/// Settings that carry over from one motion to the next
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MachineState {
+    /// Feed rate set by the last `F` command, in millimeters per minute
+    pub feed: Option<f64>,
+    /// Spindle speed or laser power set by the last `S` command
+    pub power: Option<f64>,
+}
+
+impl MachineState {
+    /// Function to stamp a motion with the settings in effect
+    ///
+    /// Rapid moves don't get a feed rate, since they run at the machine's
+    /// rapid rate.
+    ///
+    /// # Arguments
+    ///
+    /// * `motion` - The motion just programmed
+    /// * `feed` - A feed rate given on the motion's own line, which applies to
+    ///   that motion only and leaves the modal feed rate alone
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::machine::MachineState;
+    /// use project_2::motion::Motion;
+    ///
+    /// let state = MachineState { feed: Some(600.0), power: None };
+    /// let motion = Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0));
+    /// assert_eq!(state.apply(motion.clone(), None).feed(), Some(600.0));
+    /// assert_eq!(state.apply(motion, Some(100.0)).feed(), Some(100.0));
+    /// ```
+    pub fn apply(&self, motion: Motion, feed: Option<f64>) -> Motion {
+        let feed = if motion.is_rapid() { None } else { feed.or(self.feed) };
+        motion.with_feed(feed).with_power(self.power)
+    }
+}
+
+/// Limits and drive settings of one axis
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AxisConfig {
+    /// Lowest position the axis can reach, in millimeters
+    pub min: Option<f64>,
+    /// Highest position the axis can reach, in millimeters
+    pub max: Option<f64>,
+    /// Fastest the axis can move, in millimeters per minute
+    pub max_velocity: Option<f64>,
+    /// Hardest the axis can accelerate, in millimeters per second squared
+    pub max_acceleration: Option<f64>,
+    /// Motor steps per millimeter of travel, used by the `steps` command
+    pub steps_per_mm: Option<f64>,
+}
+
+/// The program axis an output axis follows, and whether it runs the other way
+///
+/// Written as the axis letter, with a `-` in front to reverse it: `"-Y"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct AxisSource {
+    /// The program axis whose coordinate is used
+    pub axis: Axis,
+    /// Whether the coordinate's sign is flipped
+    pub reversed: bool,
+}
+
+impl TryFrom<String> for AxisSource {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let (reversed, name) = match text.trim() {
+            name if name.starts_with('-') => (true, &name[1..]),
+            name => (false, name.strip_prefix('+').unwrap_or(name)),
+        };
+        Ok(AxisSource {
+            axis: name.parse()?,
+            reversed,
+        })
+    }
+}
+
+/// How the axes of every output position are swapped or reversed, for machines wired differently from the program
+///
+/// Each output axis follows a program axis; axes that aren't listed follow
+/// their own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AxisMap {
+    /// The program axis the output X follows
+    pub x: Option<AxisSource>,
+    /// The program axis the output Y follows
+    pub y: Option<AxisSource>,
+    /// The program axis the output Z follows
+    pub z: Option<AxisSource>,
+}
+
+impl AxisMap {
+    // The source of each output axis, filling in the ones left out
+    fn sources(&self) -> [AxisSource; 3] {
+        let own = |axis| AxisSource { axis, reversed: false };
+        [
+            self.x.unwrap_or(own(Axis::X)),
+            self.y.unwrap_or(own(Axis::Y)),
+            self.z.unwrap_or(own(Axis::Z)),
+        ]
+    }
+
+    /// Function to swap and reverse the axes of a position
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::machine::MachineConfig;
+    ///
+    /// let machine = MachineConfig::from_toml("[remap]\nx = \"-Y\"\ny = \"X\"").unwrap();
+    /// assert_eq!(machine.remap.apply((1.0, 2.0, 3.0)), (-2.0, 1.0, 3.0));
+    /// ```
+    pub fn apply(&self, (x, y, z): Point) -> Point {
+        let [first, second, third] = self.sources().map(|source| {
+            let coordinate = match source.axis {
+                Axis::X => x,
+                Axis::Y => y,
+                Axis::Z => z,
+            };
+            if source.reversed {
+                -coordinate
+            } else {
+                coordinate
+            }
+        });
+        (first, second, third)
+    }
+
+    // Check that no program axis drives two output axes, which would leave another one out
+    fn check(&self) -> Result<(), ConfigError> {
+        let axes = self.sources().map(|source| source.axis);
+        for (index, axis) in axes.iter().enumerate() {
+            if axes[..index].contains(axis) {
+                let letter = match axis {
+                    Axis::X => 'X',
+                    Axis::Y => 'Y',
+                    Axis::Z => 'Z',
+                };
+                return Err(ConfigError::RepeatedAxis(letter));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A volume the tool must stay out of, such as a vise or a clamp, in program coordinates
+///
+/// The zone is where the tip of the tool may not go, so it should take in the
+/// tool's radius around whatever it protects.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "shape", rename_all = "lowercase", deny_unknown_fields)]
+pub enum KeepOut {
+    /// A box lined up with the axes
+    Box {
+        /// What the zone protects, used in messages
+        name: Option<String>,
+        /// The corner with the lowest X, Y and Z
+        min: Point,
+        /// The corner with the highest X, Y and Z
+        max: Point,
+    },
+    /// An upright cylinder, standing along Z
+    Cylinder {
+        /// What the zone protects, used in messages
+        name: Option<String>,
+        /// X and Y of the cylinder's axis
+        center: (f64, f64),
+        /// Radius of the cylinder
+        radius: f64,
+        /// Height of the bottom of the cylinder
+        bottom: f64,
+        /// Height of the top of the cylinder
+        top: f64,
+    },
+}
+
+impl KeepOut {
+    /// The zone's name, if the profile gives it one
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            KeepOut::Box { name, .. } | KeepOut::Cylinder { name, .. } => name.as_deref(),
+        }
+    }
+
+    /// Function to find where a straight move first enters the zone
+    ///
+    /// Points on the surface of the zone count as inside it.
+    ///
+    /// # Returns
+    ///
+    /// How far along the move it enters the zone, from 0 at `from` to 1 at
+    /// `to`, or `None` if it stays out.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::machine::KeepOut;
+    ///
+    /// let clamp = KeepOut::Cylinder { name: None, center: (0.0, 0.0), radius: 5.0, bottom: 0.0, top: 10.0 };
+    /// assert_eq!(clamp.entry((-10.0, 0.0, 2.0), (10.0, 0.0, 2.0)), Some(0.25));
+    /// assert_eq!(clamp.entry((-10.0, 0.0, 12.0), (10.0, 0.0, 12.0)), None);
+    /// ```
+    pub fn entry(&self, from: Point, to: Point) -> Option<f64> {
+        // The part of the move between two values along one axis, narrowing `(enter, leave)`
+        let between = |(enter, leave): (f64, f64), start: f64, end: f64, low: f64, high: f64| {
+            let delta = end - start;
+            if delta.abs() < 1e-12 {
+                return (low..=high).contains(&start).then_some((enter, leave));
+            }
+            let (a, b) = ((low - start) / delta, (high - start) / delta);
+            let (enter, leave) = (enter.max(a.min(b)), leave.min(a.max(b)));
+            (enter <= leave).then_some((enter, leave))
+        };
+        let (enter, _) = match *self {
+            KeepOut::Box { min, max, .. } => {
+                let span = between((0.0, 1.0), from.0, to.0, min.0, max.0)?;
+                let span = between(span, from.1, to.1, min.1, max.1)?;
+                between(span, from.2, to.2, min.2, max.2)?
+            }
+            KeepOut::Cylinder {
+                center,
+                radius,
+                bottom,
+                top,
+                ..
+            } => {
+                let (enter, leave) = between((0.0, 1.0), from.2, to.2, bottom, top)?;
+                // Within the radius of the axis between the roots of a quadratic in the fraction along
+                let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+                let (wx, wy) = (center.0 - from.0, center.1 - from.1);
+                let gap = wx * wx + wy * wy - radius * radius;
+                let length2 = dx * dx + dy * dy;
+                if length2 < 1e-24 {
+                    (gap <= 0.0).then_some((enter, leave))?
+                } else {
+                    let along = wx * dx + wy * dy;
+                    let discriminant = along * along - length2 * gap;
+                    if discriminant < 0.0 {
+                        return None;
+                    }
+                    let root = discriminant.sqrt();
+                    let (enter, leave) = (enter.max((along - root) / length2), leave.min((along + root) / length2));
+                    (enter <= leave).then_some((enter, leave))?
+                }
+            }
+        };
+        Some(enter)
+    }
+}
+
+/// A machine profile, loaded with `--machine`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    /// Units of programs that don't choose their own
+    pub units: Option<Units>,
+    /// Feed rate of rapid moves, in millimeters per minute
+    pub rapid_rate: Option<f64>,
+    /// Seconds each pause is expected to last, for run times
+    pub pause_time: Option<f64>,
+    /// The X axis
+    pub x: AxisConfig,
+    /// The Y axis
+    pub y: AxisConfig,
+    /// The Z axis
+    pub z: AxisConfig,
+    /// How output positions are swapped and reversed from the program's axes
+    ///
+    /// Travel limits stay in the program's axes.
+    pub remap: AxisMap,
+    /// Volumes the tool must stay out of, in the program's axes like travel limits
+    pub keep_out: Vec<KeepOut>,
+}
+
+impl MachineConfig {
+    /// Function to read a machine profile from TOML text
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Toml` if the text isn't a valid profile, including
+    /// keys the profile doesn't know, and `ConfigError::RepeatedAxis` if the
+    /// remapping uses a program axis twice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::machine::MachineConfig;
+    ///
+    /// let machine = MachineConfig::from_toml("rapid_rate = 6000\n[z]\nmin = -80\nmax = 0").unwrap();
+    /// assert_eq!(machine.rapid_rate, Some(6000.0));
+    /// assert_eq!(machine.z.min, Some(-80.0));
+    /// ```
+    pub fn from_toml(text: &str) -> Result<MachineConfig, ConfigError> {
+        let machine: MachineConfig = toml::from_str(text)?;
+        machine.remap.check()?;
+        Ok(machine)
+    }
+
+    /// Function to read a machine profile from a TOML file
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Io` if the file can't be read, and the errors of
+    /// [`MachineConfig::from_toml`] otherwise.
+    pub fn load(path: &Path) -> Result<MachineConfig, ConfigError> {
+        MachineConfig::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// The steps per millimeter of the X, Y and Z motors, if every axis has them
+    pub fn steps_per_mm(&self) -> Option<[f64; 3]> {
+        Some([self.x.steps_per_mm?, self.y.steps_per_mm?, self.z.steps_per_mm?])
+    }
+
+    /// The X, Y and Z axes, in that order
+    pub fn axes(&self) -> [&AxisConfig; 3] {
+        [&self.x, &self.y, &self.z]
+    }
+
+    // One setting of every axis, with `unset` standing in for axes that don't
+    // give it, or `None` if no axis does
+    fn per_axis(&self, setting: impl Fn(&AxisConfig) -> Option<f64>, unset: f64) -> Option<(f64, f64, f64)> {
+        let [x, y, z] = self.axes().map(&setting);
+        if x.is_none() && y.is_none() && z.is_none() {
+            return None;
+        }
+        Some((x.unwrap_or(unset), y.unwrap_or(unset), z.unwrap_or(unset)))
+    }
+
+    /// Function to build planner options from the axis velocity and acceleration limits
+    ///
+    /// # Returns
+    ///
+    /// The planner options, or `None` if the profile doesn't limit velocity or
+    /// acceleration, in which case there is nothing to plan with. Axes without
+    /// an acceleration limit use the planner's default one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::machine::MachineConfig;
+    ///
+    /// let machine = MachineConfig::from_toml("[x]\nmax_acceleration = 200\nmax_velocity = 6000").unwrap();
+    /// let planner = machine.planner_options().unwrap();
+    /// assert_eq!(planner.axis_acceleration, Some((200.0, 500.0, 500.0)));
+    /// assert_eq!(planner.axis_velocity, Some((100.0, f64::INFINITY, f64::INFINITY)));
+    /// ```
+    pub fn planner_options(&self) -> Option<PlannerOptions> {
+        let defaults = PlannerOptions::default();
+        // Axes without a limit of their own accelerate at the planner's default
+        let axis_acceleration = self.per_axis(|axis| axis.max_acceleration, defaults.max_acceleration);
+        // The planner works in units per second
+        let axis_velocity = self.per_axis(|axis| axis.max_velocity.map(|velocity| velocity / 60.0), f64::INFINITY);
+        if axis_acceleration.is_none() && axis_velocity.is_none() {
+            return None;
+        }
+
+        // Moving along several axes at once can accelerate harder than any one
+        // of them, up to the length of the vector of axis limits, so only the
+        // axis limits themselves should bind
+        let max_acceleration = match axis_acceleration {
+            Some((x, y, z)) => (x * x + y * y + z * z).sqrt(),
+            None => defaults.max_acceleration,
+        };
+        Some(PlannerOptions {
+            max_acceleration,
+            axis_acceleration,
+            axis_velocity,
+            ..defaults
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test reading a full profile and rejecting unknown keys.
+    #[test]
+    fn test_from_toml() {
+        let text = "units = \"inch\"\nrapid_rate = 8000\n[x]\nmin = 0\nmax = 300\nsteps_per_mm = 80\n[z]\nmax_acceleration = 50\n";
+        let machine = MachineConfig::from_toml(text).unwrap();
+        assert_eq!(machine.units, Some(Units::Inches));
+        assert_eq!((machine.x.min, machine.x.max, machine.x.steps_per_mm), (Some(0.0), Some(300.0), Some(80.0)));
+        assert_eq!(machine.y, AxisConfig::default());
+
+        let planner = machine.planner_options().unwrap();
+        assert_eq!(planner.axis_acceleration, Some((500.0, 500.0, 50.0)));
+        assert_eq!(planner.acceleration_along((0.0, 0.0, 1.0)), 50.0);
+        assert_eq!(planner.axis_velocity, None);
+        assert_eq!(MachineConfig::default().planner_options().map(|planner| planner.max_acceleration), None);
+
+        assert!(matches!(MachineConfig::from_toml("[x]\nlimit = 3"), Err(ConfigError::Toml(_))));
+    }
+
+    /// Test that remapped axes swap and reverse, and that each program axis is used once.
+    #[test]
+    fn test_remap() {
+        let machine = MachineConfig::from_toml("[remap]\nx = \"+Z\"\ny = \"-Y\"\nz = \"-X\"\n").unwrap();
+        assert_eq!(machine.remap.apply((1.0, 2.0, 3.0)), (3.0, -2.0, -1.0));
+        assert_eq!(MachineConfig::default().remap.apply((1.0, 2.0, 3.0)), (1.0, 2.0, 3.0));
+
+        let err = MachineConfig::from_toml("[remap]\nx = \"Y\"").unwrap_err();
+        assert!(matches!(err, ConfigError::RepeatedAxis('Y')));
+        assert!(matches!(MachineConfig::from_toml("[remap]\nx = \"W\""), Err(ConfigError::Toml(_))));
+    }
+
+    /// Test reading keep-out zones and finding where moves enter a box.
+    #[test]
+    fn test_keep_out() {
+        let text = "[[keep_out]]\nname = \"vise\"\nshape = \"box\"\nmin = [0, 0, -20]\nmax = [100, 40, 10]\n\
+                    [[keep_out]]\nshape = \"cylinder\"\ncenter = [200, 80]\nradius = 12\nbottom = -10\ntop = 25\n";
+        let machine = MachineConfig::from_toml(text).unwrap();
+        assert_eq!(machine.keep_out.len(), 2);
+        let vise = &machine.keep_out[0];
+        assert_eq!((vise.name(), machine.keep_out[1].name()), (Some("vise"), None));
+
+        // A plunge into the top of the box, a pass over it and one beside it
+        assert_eq!(vise.entry((50.0, 20.0, 30.0), (50.0, 20.0, -10.0)), Some(0.5));
+        assert_eq!(vise.entry((-50.0, 20.0, 11.0), (150.0, 20.0, 11.0)), None);
+        assert_eq!(vise.entry((-50.0, 50.0, 0.0), (150.0, 50.0, 0.0)), None);
+        // A diagonal that clips a corner in XY
+        assert_eq!(vise.entry((-10.0, 30.0, 0.0), (10.0, 50.0, 0.0)), Some(0.5));
+
+        // Shapes it doesn't know, and keys a shape doesn't take, are refused
+        assert!(MachineConfig::from_toml("[[keep_out]]\nshape = \"cone\"").is_err());
+        let text = "[[keep_out]]\nshape = \"box\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]\nradius = 3";
+        assert!(MachineConfig::from_toml(text).is_err());
+    }
+}
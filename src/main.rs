@@ -1,345 +1,1390 @@
 use std::env;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::net::{IpAddr, TcpListener};
 use std::path::Path;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-// Define a struct to represent linear motion
-struct LinearMotion {
-    start: (f64, f64, f64),
-    end: (f64, f64, f64),
-}
+use serde::Serialize;
 
-// Define a struct to represent rotational motion
-#[derive(Debug)]
-struct RotationalMotion {
-    center: (f64, f64),
-    radius: f64,
-    clockwise: bool,
-    stop_angle: f64, // Added stop_angle field
-}
+use project_2::binary::{write_binary, BinaryReader, Layout, Sample};
+use project_2::diff::{diff, Change};
+use project_2::hpgl::read_hpgl;
+use project_2::interpolate::InterpolationOptions;
+use project_2::machine::MachineConfig;
+use project_2::motion::Motion;
+use project_2::optimize::{fillet_corners, fit_arcs, reorder, safe_travel, simplify};
+use project_2::output::{write_json, write_json_motion, Format, Point};
+use project_2::error::CmmdError;
+use project_2::parser::{read_program, read_program_from, stream_program_from, Dialect, Interpreter, ParseOptions};
+use project_2::planner::{plan, PlannerOptions};
+use project_2::playback::{Control, Playback};
+use project_2::post::{write_cmmd_with_tools, write_gcode_for};
+use project_2::program::Program;
+use project_2::raster::{raster, Image};
+use project_2::progress::Progress;
+use project_2::pulses::{step_counts, step_events};
+use project_2::server::{serve, ServerOptions};
+use project_2::simulate::{simulate, MAX_CELLS};
+use project_2::stats::{program_stats, Bounds, Stats};
+use project_2::stream::{marked_gcode_lines, Checkpoint, Framing};
+use project_2::svg::{read_svg, write_svg};
+use project_2::tools::ToolTable;
+use project_2::trajectory::{
+    dynamics, extrusion_at, motion_points, resample, rotary_at, timed_positions, Dynamics, TimedPosition, Trajectory,
+};
+use project_2::transform::{array, restart, transform, Restart};
+use project_2::validate::{continuity, keep_out, reachable, soft_limits};
+use project_2::verify::{first_divergence, read_expected};
 
-// Define an enum to represent different types of motion
-enum Motion {
-    Linear(LinearMotion),
-    Rotational(RotationalMotion),
-}
+mod cli;
 
-impl Motion {
-    // Constructor for linear motion
-    fn new_linear(start: (f64, f64, f64), end: (f64, f64, f64)) -> Self {
-        Motion::Linear(LinearMotion { start, end })
+use cli::Command;
+
+fn main() {
+    // Command-line arguments
+    let args: Vec<String> = env::args().collect();
+
+    // Parse the flags and the program path
+    let mut options = match cli::parse_args(&args[1..]) {
+        Ok(options) => options,
+        // Arguments that don't make sense are a usage error, like in most command-line tools
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+    // Each run happens in a fresh process, so a broken edit can't stop the watching
+    if options.watch {
+        watch(&args, options.file_path.as_deref().expect("--watch needs a program file"));
+        return;
     }
+    // Load the tool table before any program refers to it
+    if let Some(path) = &options.tools {
+        match ToolTable::load(Path::new(path)) {
+            Ok(tools) => options.parse.tools = tools,
+            Err(e) => {
+                eprintln!("Error reading tool table {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    let interpolation = &options.interpolation;
 
-    // Constructor for rotational motion
-    fn new_rotational(center: (f64, f64), radius: f64, clockwise: bool, stop_angle: f64) -> Self {
-        Motion::Rotational(RotationalMotion {
-            center,
-            radius,
-            clockwise,
-            stop_angle, // Added stop_angle initialization
-        })
+    // Serve programs over the network instead of reading a file
+    if let Command::Serve { address, port } = options.command {
+        run_server(address, port, &options);
+        return;
+    }
+    // Or take commands as they are typed
+    if options.command == Command::Repl {
+        if let Err(e) = run_repl(&options) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
     }
-}
 
-/// Function to read motions from a file
-///
-/// This function reads motions from a file specified by the given file path.
-/// The file should contain commands in the following format:
-/// - "LIN (x1, y1, z1) to (x2, y2, z2)" for linear motion
-/// - "CW (x, y) radius r stop_angle a" or "CCW (x, y) radius r stop_angle a" for rotational motion
-///
-/// # Arguments
-///
-/// * `file_path` - The path to the file containing motion commands
-///
-/// # Returns
-///
-/// A Result containing a vector of Motion enums if successful, or an IO error otherwise.
-///
-/// # Errors
-///
-/// This function can return an IO error if it fails to open or read from the file.
-///
-/// # Examples
-///
-/// ```
-/// let result = read_file("test.cmmd");
-/// assert!(result.is_ok()); // Check if reading succeeds
-/// let motions = result.unwrap();
-/// assert_eq!(motions.len(), 8); // Check number of motions read
-/// // Add more specific checks if needed
-/// ```
-fn read_file(file_path: &str) -> io::Result<Vec<Motion>> {
-    // Open the file
-    let file = File::open(file_path)?;
-    // Create a buffered reader
-    let reader = io::BufReader::new(file);
-    // Initialize a vector to store motions
-    let mut motions = Vec::new();
-    let mut prev_start = (0.0, 0.0, 0.0);
-
-    // Iterate through each line in the file
-    for line in reader.lines() {
-        // Read the line and handle any potential I/O errors
-        let line = line?;
-        // Split the line into parts using whitespace as delimiter
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
-
-        // Check if there are at least 3 parts (to avoid panics)
-        if parts.len() < 3 {
-            println!("Invalid command format: {}", line);
-            continue;
+    // Or print a binary trajectory back out
+    if options.command == Command::Decode {
+        let result = match options.file_path.as_deref() {
+            Some(path) if path != "-" => File::open(path).and_then(|file| decode(io::BufReader::new(file))),
+            _ => decode(io::stdin().lock()),
+        };
+        match result {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                eprintln!("Error decoding trajectory: {}", e);
+                process::exit(1);
+            }
+            Ok(()) => {}
         }
+        return;
+    }
 
-        // Check if the command is "LIN"
-        if parts[0] == "LIN" {
-            // Parse start and end points from the parts
-            let start = (
-                parts[1][1..].parse().unwrap_or(0.0), // Parse X coordinate
-                parts[2][1..].parse().unwrap_or(0.0), // Parse Y coordinate
-                parts[3][1..].parse().unwrap_or(0.0), // Parse Z coordinate
-            );
-            motions.push(Motion::new_linear(prev_start, start)); // Use previous start point as end point
-            prev_start = start; // Update previous start point
-        } else if parts[0] == "CW" || parts[0] == "CCW" {
-            // Ensure that the CW or CCW command has at least 5 parts
-            if parts.len() < 5 {
-                println!("Invalid command format: {}", line);
-                continue;
+    // Or engrave an image, writing the program the way convert does
+    if let Command::Raster { output, dialect, raster: engraving } = &options.command {
+        let image = match options.file_path.as_deref() {
+            Some(path) if path != "-" => Image::load(Path::new(path)),
+            _ => Image::read_pgm(io::stdin().lock()),
+        };
+        match image {
+            Ok(image) => write_program(raster(&image, engraving), output.as_deref(), *dialect, &options),
+            Err(e) => {
+                eprintln!("Error reading image: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Or draw a drawing's paths, likewise
+    if let Command::Import { output, dialect, drawing: placement } = &options.command {
+        // Drawings are SVG unless their extension says they're HP-GL plots
+        let drawing = match options.file_path.as_deref() {
+            Some(path) if options.extensions.dialect_of(path) == Some(Dialect::Hpgl) => File::open(path).and_then(read_hpgl),
+            Some(path) if path != "-" => File::open(path).and_then(read_svg),
+            _ => read_svg(io::stdin().lock()),
+        };
+        match drawing {
+            Ok(drawing) => write_program(drawing.motions(placement), output.as_deref(), *dialect, &options),
+            Err(e) => {
+                eprintln!("Error reading drawing: {}", e);
+                process::exit(1);
             }
+        }
+        return;
+    }
 
-            // Parse parameters for rotational motion
-            let center = (
-                parts[1][1..].parse().unwrap_or(0.0), // Parse X coordinate
-                parts[2][1..].parse().unwrap_or(0.0), // Parse Y coordinate
+    // Work out where the program comes from and which language it is in
+    let (file_path, dialect) = match options.file_path.as_deref() {
+        Some("-") => (None, Dialect::Cmmd),
+        // Pick the parser from the file extension
+        Some(file_path) => match program_dialect(&options, file_path) {
+            Some(dialect) => (Some(file_path), dialect),
+            None => process::exit(1),
+        },
+        // Without a file the program has to be piped in
+        None if !io::stdin().is_terminal() => (None, Dialect::Cmmd),
+        None => {
+            eprintln!("Expected exactly one program file");
+            eprintln!("{}", cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    // Print huge programs as they are parsed instead of reading them whole
+    if options.low_memory {
+        let result = match file_path {
+            Some(file_path) => File::open(file_path).map_err(CmmdError::from).and_then(|file| {
+                print_streamed(stream_program_from(io::BufReader::new(file), dialect, &options.parse), &options)
+            }),
+            None => print_streamed(stream_program_from(io::stdin().lock(), dialect, &options.parse), &options),
+        };
+        match result {
+            // Readers like `head` may stop early, which is fine
+            Err(CmmdError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                eprintln!("Error reading file: {}", e);
+                process::exit(1);
+            }
+            Ok(()) => {}
+        }
+        return;
+    }
+
+    // Attempt to read motions from the file, or from stdin
+    let result = match file_path {
+        Some(file_path) => read_program(file_path, dialect, &options.parse),
+        None => read_stdin(&options.parse),
+    };
+    let motions = match result {
+        Ok(motions) => motions,
+        Err(e) => {
+            eprintln!("Error reading file: {}", e);
+            process::exit(1);
+        }
+    };
+    // Compare the program with its old version instead of printing positions
+    if let Command::Diff { old, within } = &options.command {
+        let Some(old_dialect) = program_dialect(&options, old) else {
+            process::exit(1);
+        };
+        let old_motions = match read_program(old, old_dialect, &options.parse) {
+            Ok(motions) => motions,
+            Err(e) => {
+                eprintln!("Error reading file {}: {}", old, e);
+                process::exit(1);
+            }
+        };
+        let differences = diff(&old_motions, &motions, interpolation, *within);
+        let (mut removed, mut added, mut moved) = (0, 0, 0);
+        for change in &differences.changes {
+            println!("{}", change);
+            match change {
+                Change::Removed { .. } => removed += 1,
+                Change::Added { .. } => added += 1,
+                Change::Moved { .. } => moved += 1,
+            }
+        }
+        println!("{} unchanged, {} removed, {} added, {} moved", differences.unchanged, removed, added, moved);
+        if let Some((x, y, z)) = differences.at.filter(|_| differences.deviation > 0.0) {
+            println!("The paths are up to {:.3} apart, at ({:.3}, {:.3}, {:.3})", differences.deviation, x, y, z);
+        }
+        // Like diff(1), differences are a failure for scripts to check
+        if !differences.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+    // Let the user's script adjust the program as it was written
+    let motions = script_motions(&options, motions);
+    // Put the program where it was asked to go
+    let motions = match &options.transform {
+        Some(placement) => match transform(&motions, placement) {
+            Ok(motions) => motions,
+            Err(e) => {
+                eprintln!("Error transforming program: {}", e);
+                process::exit(1);
+            }
+        },
+        None => motions,
+    };
+    let motions = match &options.array {
+        Some(grid) => array(&motions, grid),
+        None => motions,
+    };
+    // Clean up and smooth the path before anything looks at it
+    let motions = match options.fit_arcs {
+        Some(tolerance) => fit_arcs(&motions, tolerance),
+        None => motions,
+    };
+    let motions = match options.simplify {
+        Some(tolerance) => simplify(&motions, tolerance),
+        None => motions,
+    };
+    let motions = match options.fillet {
+        Some(blend) => fillet_corners(&motions, blend),
+        None => motions,
+    };
+    // Then put the separate parts in the order with the least travel between them
+    let motions = if options.reorder {
+        let (motions, reordering) = reorder(&motions);
+        if reordering.after < reordering.before {
+            eprintln!(
+                "Reordered {} cutting sequences: {:.3} of rapid travel instead of {:.3}, {:.3} less",
+                reordering.sequences,
+                reordering.after,
+                reordering.before,
+                reordering.before - reordering.after
             );
-            let radius = parts[3][1..].parse().unwrap_or(0.0); // Parse radius
-            let stop_angle = parts[4][1..].parse().unwrap_or(0.0); // Parse stop angle
-            // Create a new rotational motion and push it to the vector
-            motions.push(Motion::new_rotational(center, radius, parts[0] == "CW", stop_angle));
         } else {
-            // Handle unrecognized command
-            println!("Invalid command: {}", line);
+            eprintln!(
+                "Kept the order of {} cutting sequences: none has less than {:.3} of rapid travel",
+                reordering.sequences, reordering.before
+            );
+        }
+        motions
+    } else {
+        motions
+    };
+    // And lift the tool over the work between them
+    let motions = match options.safe_z {
+        Some(safe_z) => {
+            let (motions, replaced) = safe_travel(&motions, safe_z);
+            if replaced > 0 {
+                eprintln!("Lifted {} travel moves to Z {:.3}", replaced, safe_z);
+            }
+            motions
+        }
+        None => motions,
+    };
+    // A checkpoint only makes sense for the program it was written for
+    if let Some(checkpoint) = &options.resume {
+        if checkpoint.motions != motions.len() {
+            eprintln!(
+                "Error resuming program: the checkpoint is for a program of {} motions, not {}",
+                checkpoint.motions,
+                motions.len()
+            );
+            process::exit(1);
         }
     }
+    // Pick an interrupted job up part way through, keeping the whole program to number checkpoints by
+    let program = motions;
+    let motions = match options.restart {
+        Some(from) => match restart(&program, from) {
+            Some(motions) => motions,
+            None => {
+                let at = match from {
+                    Restart::Line(line) => format!("line {}", line),
+                    Restart::Motion(index) => format!("motion {}", index),
+                };
+                eprintln!("Error restarting program: nothing to run from {} on", at);
+                process::exit(1);
+            }
+        },
+        None => program.clone(),
+    };
 
-    // Return the vector of motions
-    Ok(motions)
-}
+    // Check the program against the machine's travel before it goes anywhere
+    let violations = match &options.machine {
+        Some(machine) => soft_limits(&motions, machine, interpolation),
+        None => Vec::new(),
+    };
+    for violation in &violations {
+        eprintln!("Soft limit: {}", violation);
+    }
+    // Nor into the vise, clamps or anything else the profile fences off
+    let intrusions = match &options.machine {
+        Some(machine) => keep_out(&motions, machine, interpolation),
+        None => Vec::new(),
+    };
+    for intrusion in &intrusions {
+        eprintln!("Keep-out: {}", intrusion);
+    }
+    // An arm can't be told to go somewhere it can't reach
+    let unreachable = reachable(&motions, &options.kinematics, interpolation);
+    for motion in &unreachable {
+        eprintln!("Unreachable: {}", motion);
+    }
 
-fn main() {
-    // Command-line arguments
-    let args: Vec<String> = env::args().collect();
+    // A dry run stops here, failing if anything is wrong with the program
+    if let Command::Check = options.command {
+        let gaps = continuity(&motions);
+        for gap in &gaps {
+            eprintln!("Gap: {}", gap);
+        }
+        if !violations.is_empty() || !intrusions.is_empty() || !gaps.is_empty() || !unreachable.is_empty() {
+            process::exit(1);
+        }
+        println!("OK: {} motions", motions.len());
+        return;
+    }
+    if options.enforce_limits && !violations.is_empty() {
+        eprintln!("Refusing to continue: the program leaves the machine's travel");
+        process::exit(1);
+    }
+    if options.enforce_limits && !intrusions.is_empty() {
+        eprintln!("Refusing to continue: the program enters a keep-out zone");
+        process::exit(1);
+    }
+    let outputs_positions =
+        matches!(options.command, Command::Run | Command::Play | Command::Steps { .. } | Command::Verify { .. });
+    if !unreachable.is_empty() && outputs_positions {
+        eprintln!("Refusing to continue: the program leaves the arm's reach");
+        process::exit(1);
+    }
+
+    // Draw a PNG backplot alongside the normal output
+    if let Some(path) = &options.png {
+        draw_png(path, &motions, interpolation);
+    }
+
+    // Render a backplot instead of printing positions
+    if let Command::Plot { output } = &options.command {
+        let result = match output {
+            Some(path) => File::create(path).and_then(|file| write_svg(BufWriter::new(file), &motions, interpolation)),
+            None => with_stdout(|out| write_svg(out, &motions, interpolation)),
+        };
+        if let Err(e) = result {
+            eprintln!("Error writing plot: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
 
-    // Check if the correct number of arguments is provided
-    if args.len() != 2 {
-        println!("Usage: {} <filename.cmmd>", args[0]);
+    // Cut the program out of stock instead of printing positions
+    if let Command::Simulate {
+        output,
+        mesh,
+        diameter,
+        cell,
+        top,
+    } = &options.command
+    {
+        let Some(stock) = simulate(&motions, interpolation, diameter / 2.0, *top, *cell) else {
+            eprintln!(
+                "Error simulating program: there's nothing to cut, or the stock needs more than {} cells",
+                MAX_CELLS
+            );
+            eprintln!("Pass a larger --cell to simulate a large part.");
+            process::exit(1);
+        };
+        let result = File::create(output).and_then(|file| {
+            if *mesh {
+                stock.write_stl(BufWriter::new(file))
+            } else {
+                stock.write_pgm(BufWriter::new(file))
+            }
+        });
+        if let Err(e) = result {
+            eprintln!("Error writing simulation: {}", e);
+            process::exit(1);
+        }
         return;
     }
 
-    // Extract file path from command-line arguments
-    let file_path = &args[1];
-    // Extract file extension
-    let extension = Path::new(file_path)
-        .extension()
-        .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default();
+    // Browse the path in the terminal instead of printing positions
+    if let Command::View = options.command {
+        view_path(&motions, interpolation);
+        return;
+    }
+
+    // Write the program in another language instead of printing positions
+    if let Command::Convert { output, dialect } = &options.command {
+        write_program(motions, output.as_deref(), *dialect, &options);
+        return;
+    }
 
-    // Check if the file extension is correct
-    if extension != "cmmd" {
-        println!("Invalid file extension. The file must have a .cmmd extension.");
+    // Summarize the program instead of printing positions
+    if let Command::Stats = options.command {
+        print_stats(&program_stats(&motions, interpolation), options.format);
         return;
     }
 
-    // Attempt to read motions from the file
-    match read_file(file_path) {
-        Ok(motions) => {
-            // Process each motion
-            for motion in motions {
-                match motion {
-                    // Handle linear motion
-                    Motion::Linear(linear_motion) => {
-                        println!("LIN {:?} to {:?}", linear_motion.start, linear_motion.end);
-                        // Calculate and print the positions for linear motion
-                        let positions = linear_motion_calculate(linear_motion.start, linear_motion.end);
-                        for position in positions {
-                            println!("{}", position);
-                        }
+    // Compare the positions with stored output instead of printing them
+    if let Command::Verify { expected, within } = &options.command {
+        let expected = match File::open(expected).and_then(read_expected) {
+            Ok(samples) => samples,
+            Err(e) => {
+                eprintln!("Error reading expected output {}: {}", expected, e);
+                process::exit(1);
+            }
+        };
+        let actual = output_points(&motions, &options);
+        if let Some(divergence) = first_divergence(&motions, actual.iter().map(Sample::from), expected, *within) {
+            eprintln!("Diverged: {}", divergence);
+            process::exit(1);
+        }
+        println!("OK: {} samples match", actual.len());
+        return;
+    }
+
+    // Send the program to a controller instead of printing positions
+    if let Command::Stream {
+        device,
+        baud,
+        checkpoint,
+        framing,
+    } = &options.command
+    {
+        // Lines are traced back to the motions of the whole program, which checkpoints count
+        let first = options.restart.and_then(|from| from.find(&program)).unwrap_or(0);
+        let approach = motions.len() + first - program.len();
+        let lines = match marked_gcode_lines(&motions, options.flavor) {
+            Ok(lines) => lines,
+            Err(e) => {
+                eprintln!("Error writing program: {}", e);
+                process::exit(1);
+            }
+        };
+        let lines: Vec<(usize, String)> = lines
+            .into_iter()
+            .map(|(index, line)| (program.len().min(first + index.saturating_sub(approach)), line))
+            .collect();
+        // The checkpoint is written whenever the motion to pick up at moves on
+        let mut saved = None;
+        let mut progress = Progress::new(&program, interpolation);
+        stream_to_device(device, *baud, *framing, &lines, |acknowledged| {
+            if options.progress {
+                let motion = lines.get(acknowledged).map_or(program.len(), |line| line.0);
+                let _ = progress.update(&mut io::stderr(), motion);
+            } else {
+                eprint!("\rsent {}/{} lines", acknowledged, lines.len());
+            }
+            if let Some(path) = checkpoint {
+                let progress = Checkpoint::after(&program, &lines, acknowledged);
+                if saved != Some(progress.motion) {
+                    saved = Some(progress.motion);
+                    if let Err(e) = progress.save(Path::new(path)) {
+                        eprintln!("\nError writing checkpoint {}: {}", path, e);
                     }
-                    // Handle rotational motion
-                    Motion::Rotational(rotational_motion) => {
-                        println!("Rotational Motion: {:?}", rotational_motion);
-                        // Calculate and print the positions for rotational motion
-                        let positions = rotational_motion_calculate(rotational_motion);
-                        for (x, y) in positions {
-                            println!("{:.2}, {:.2}", x, y);
-                        }
+                }
+            }
+        });
+        return;
+    }
+
+    // Print the positions as the machine would reach them, taking controls as it goes
+    if let Command::Play = options.command {
+        play(&motions, &options);
+        return;
+    }
+
+    // The progress bar follows the motions as their positions are written
+    let mut progress = options.progress.then(|| Progress::new(&motions, interpolation));
+    let mut reached = |motion: usize| {
+        if let Some(progress) = &mut progress {
+            // A bar that can't be drawn isn't worth stopping the output for
+            let _ = progress.update(&mut io::stderr(), motion);
+        }
+    };
+
+    // Packed samples would only garble a terminal
+    if options.format == Format::Binary && io::stdout().is_terminal() {
+        eprintln!("Refusing to write binary output to a terminal; redirect it to a file");
+        process::exit(1);
+    }
+
+    // Output can run to millions of lines, so stdout is locked once and buffered
+    let result = with_stdout(|out| {
+        if let Command::Steps { tick } = options.command {
+            // Steps are counted in motor coordinates, along the timed path
+            let mut samples = timed_samples(&motions, &options);
+            for sample in &mut samples {
+                sample.position = motors(&options, sample.motion, sample.position);
+            }
+            let steps_per_mm = options.machine.as_ref().and_then(MachineConfig::steps_per_mm);
+            let events = step_events(&samples, steps_per_mm.expect("steps needs steps_per_mm on every axis"));
+            match tick {
+                Some(tick) => print_steps(out, &step_counts(&events, tick), options.format, |out, count| {
+                    let [x, y, z] = count.steps;
+                    writeln!(out, "{:.6} {} {} {}", count.time, x, y, z)
+                }),
+                None => print_steps(out, &events, options.format, |out, step| {
+                    writeln!(out, "{:.6} {} {}", step.time, step.motor, if step.forward { '+' } else { '-' })
+                }),
+            }
+        } else if options.timed {
+            // Samples are timed along the cartesian path, then placed in motor coordinates
+            let mut samples = timed_samples(&motions, &options);
+            if let Some(rate) = options.sample_rate {
+                samples = resample(&samples, rate);
+            }
+            for sample in &mut samples {
+                sample.position = motors(&options, sample.motion, sample.position);
+            }
+            // Velocities are worked out in the same coordinates the positions are printed in
+            let states = options.dynamics.then(|| dynamics(&samples));
+            if options.format == Format::Text {
+                print_timed(out, &samples, states.as_deref(), reached)
+            } else {
+                let points = samples.into_iter().enumerate().map(|(index, sample)| {
+                    reached(sample.motion);
+                    Point {
+                        dynamics: states.as_ref().map(|states| states[index]),
+                        ..Point::from(sample)
                     }
+                });
+                match options.format {
+                    Format::Binary => write_binary(out, points, Layout { time: true, velocity: options.dynamics }),
+                    format => write_json(out, &motions, points, format),
                 }
             }
+        } else {
+            // Untimed points are worked out as they are written, or all at
+            // once across every core with --parallel
+            let trajectory = Trajectory::new(&motions, interpolation);
+            let points: Box<dyn Iterator<Item = Point>> = if options.parallel {
+                let threads = thread::available_parallelism().map_or(1, usize::from);
+                Box::new(trajectory.par_points(threads).into_iter())
+            } else {
+                Box::new(trajectory.points())
+            };
+            let points = points.map(|point| {
+                reached(point.motion);
+                Point {
+                    position: motors(&options, point.motion, point.position),
+                    ..point
+                }
+            });
+            match options.format {
+                Format::Text => print_geometry(out, &motions, points),
+                Format::Binary => write_binary(out, points, Layout::default()),
+                format => write_json(out, &motions, points, format),
+            }
         }
-        Err(e) => println!("Error reading file: {}", e),
+    });
+    match result {
+        // Readers like `head` may stop early, which is fine
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("Error writing output: {}", e);
+            process::exit(1);
+        }
+        Ok(()) => {}
+    }
+    if let Some(progress) = &mut progress {
+        let _ = progress.finish(&mut io::stderr());
     }
 }
 
-
-/// Function to calculate positions for linear motion
-///
-/// Given a start position and an end position, this function calculates
-/// intermediate positions in a straight line between the start and end points.
-/// The positions are calculated with one-unit increments.
+/// Function to convert a position on a motion into motor coordinates, once it's known to be reachable
 ///
-/// # Arguments
+/// The height map is followed first, in program axes, and the machine
+/// profile's axis remapping is applied to the motor axes. The script's
+/// `point` hook sees the result last, as it's about to be printed.
+fn motors(options: &cli::Options, motion: usize, position: (f64, f64, f64)) -> (f64, f64, f64) {
+    let position = options.height_map.as_ref().map_or(position, |map| map.apply(position));
+    let position = options.kinematics.motors(position).expect("unreachable positions are refused before output");
+    let position = options.machine.as_ref().map_or(position, |machine| machine.remap.apply(position));
+    script_point(options, motion, position)
+}
+
+/// Function to run the script's `motion` hook over the program, if there's a script
+#[cfg(feature = "scripting")]
+fn script_motions(options: &cli::Options, motions: Vec<Motion>) -> Vec<Motion> {
+    let Some(script) = &options.script else {
+        return motions;
+    };
+    script.motions(&motions).unwrap_or_else(|e| {
+        eprintln!("Error running script: {}", e);
+        process::exit(1);
+    })
+}
+
+#[cfg(not(feature = "scripting"))]
+fn script_motions(_: &cli::Options, motions: Vec<Motion>) -> Vec<Motion> {
+    motions
+}
+
+/// Function to run the script's `point` hook on a printed position, if there's a script
+#[cfg(feature = "scripting")]
+fn script_point(options: &cli::Options, motion: usize, position: (f64, f64, f64)) -> (f64, f64, f64) {
+    let Some(script) = &options.script else {
+        return position;
+    };
+    script.point(motion, position).unwrap_or_else(|e| {
+        eprintln!("Error running script: {}", e);
+        process::exit(1);
+    })
+}
+
+#[cfg(not(feature = "scripting"))]
+fn script_point(_: &cli::Options, _: usize, position: (f64, f64, f64)) -> (f64, f64, f64) {
+    position
+}
+
+/// Function to work out every position the program prints, in motor coordinates
+fn output_points(motions: &[Motion], options: &cli::Options) -> Vec<Point> {
+    if !options.timed {
+        return Trajectory::new(motions, &options.interpolation)
+            .points()
+            .map(|point| Point {
+                position: motors(options, point.motion, point.position),
+                ..point
+            })
+            .collect();
+    }
+    // Timed the same way as printed, with velocities if they are asked for
+    let mut samples = timed_samples(motions, options);
+    if let Some(rate) = options.sample_rate {
+        samples = resample(&samples, rate);
+    }
+    for sample in &mut samples {
+        sample.position = motors(options, sample.motion, sample.position);
+    }
+    let states = options.dynamics.then(|| dynamics(&samples));
+    samples
+        .into_iter()
+        .enumerate()
+        .map(|(index, sample)| Point {
+            dynamics: states.as_ref().map(|states| states[index]),
+            ..Point::from(sample)
+        })
+        .collect()
+}
+
+/// Function to print step events or counts, one per line as text or as JSON
+fn print_steps<T: Serialize>(
+    out: &mut impl Write,
+    records: &[T],
+    format: Format,
+    text: impl Fn(&mut dyn Write, &T) -> io::Result<()>,
+) -> io::Result<()> {
+    match format {
+        Format::Text => records.iter().try_for_each(|record| text(out, record)),
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, records)?;
+            writeln!(out)
+        }
+        Format::JsonLines => records.iter().try_for_each(|record| {
+            serde_json::to_writer(&mut *out, record)?;
+            writeln!(out)
+        }),
+        Format::Binary => unreachable!("binary output is refused for steps"),
+    }
+}
+
+/// Function to write to stdout through a buffer, flushing it at the end
+fn with_stdout(write: impl FnOnce(&mut BufWriter<io::StdoutLock<'static>>) -> io::Result<()>) -> io::Result<()> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    write(&mut out)?;
+    out.flush()
+}
+
+/// Function to print each sample of a binary trajectory as a line of text
 ///
-/// * `start` - The starting position (x, y, z)
-/// * `end` - The ending position (x, y, z)
+/// Lines hold the motion index, the time if there is one, the position and
+/// the velocity if there is one, separated by commas, with every value
+/// printed in full.
+fn decode(reader: impl io::Read) -> io::Result<()> {
+    let samples = BinaryReader::new(reader)?;
+    with_stdout(|out| {
+        for sample in samples {
+            let sample = sample?;
+            write!(out, "{}", sample.motion)?;
+            if let Some(time) = sample.time {
+                write!(out, ", {}", time)?;
+            }
+            let (x, y, z) = sample.position;
+            write!(out, ", {}, {}, {}", x, y, z)?;
+            if let Some((vx, vy, vz)) = sample.velocity {
+                write!(out, ", {}, {}, {}", vx, vy, vz)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    })
+}
+
+/// Function to pick the dialect of a program file from its extension
 ///
 /// # Returns
 ///
-/// A vector of strings containing the calculated positions.
+/// The dialect, or `None` once the user has been told the extension isn't accepted.
+fn program_dialect(options: &cli::Options, file_path: &str) -> Option<Dialect> {
+    match options.extensions.dialect_of(file_path) {
+        Some(dialect) => Some(dialect),
+        // Forced files with unknown extensions are read as .cmmd
+        None if options.force => Some(Dialect::Cmmd),
+        None => {
+            let names = options.extensions.names().iter().map(|name| format!(".{}", name)).collect::<Vec<_>>();
+            eprintln!("Invalid file extension. The file must have one of these extensions: {}", names.join(", "));
+            eprintln!("Pass --extension <ext>=<dialect> to accept another, or --force to read it anyway.");
+            None
+        }
+    }
+}
+
+/// Function to read a `.cmmd` program piped into stdin
+fn read_stdin(options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    read_program_from(io::stdin().lock(), Dialect::Cmmd, options)
+}
+
+/// Function to draw a PNG backplot, exiting if it can't be drawn
+#[cfg(feature = "png")]
+fn draw_png(path: &str, motions: &[Motion], interpolation: &InterpolationOptions) {
+    if let Err(e) = project_2::png::write_png(std::path::Path::new(path), motions, interpolation) {
+        eprintln!("Error drawing plot: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Function to draw a PNG backplot, which this build can't do
+#[cfg(not(feature = "png"))]
+fn draw_png(_path: &str, _motions: &[Motion], _interpolation: &InterpolationOptions) {
+    eprintln!("PNG plots need the png feature: cargo build --features png");
+    process::exit(1);
+}
+
+/// Function to browse the XY path in the terminal, exiting if the terminal can't be used
+#[cfg(feature = "tui")]
+fn view_path(motions: &[Motion], interpolation: &InterpolationOptions) {
+    if let Err(e) = project_2::tui::view(motions, interpolation) {
+        eprintln!("Error showing the path: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Function to browse the XY path in the terminal, which this build can't do
+#[cfg(not(feature = "tui"))]
+fn view_path(_motions: &[Motion], _interpolation: &InterpolationOptions) {
+    eprintln!("The terminal viewer needs the tui feature: cargo build --features tui");
+    process::exit(1);
+}
+
+/// Function to stream G-code lines to a GRBL controller on a serial port, exiting on failure
 ///
-/// # Examples
+/// Lines are marked as `framing` says. `acknowledged` is called with how many
+/// lines the controller has acknowledged so far.
+/// Feed hold and override controls typed on stdin are sent to the controller
+/// as they come, ahead of the lines still waiting to go.
+#[cfg(feature = "serial")]
+fn stream_to_device(
+    device: &str,
+    baud: u32,
+    framing: Framing,
+    lines: &[(usize, String)],
+    mut acknowledged: impl FnMut(usize),
+) {
+    use std::time::Duration;
+
+    use project_2::playback::realtime_bytes;
+    use project_2::stream::Stop;
+
+    let mut port = match serialport::new(device, baud).timeout(Duration::from_secs(1)).open() {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("Error opening {}: {}", device, e);
+            process::exit(1);
+        }
+    };
+
+    // Opening the port resets GRBL, so wake it up and drop its start-up banner
+    let _ = port.write_all(b"\r\n\r\n");
+    std::thread::sleep(Duration::from_secs(2));
+    let _ = port.clear(serialport::ClearBuffer::Input);
+
+    // Controls typed while the program runs go straight to GRBL, and anything else answers the prompts below
+    let mut controls = match port.try_clone() {
+        Ok(controls) => controls,
+        Err(e) => {
+            eprintln!("Error opening {}: {}", device, e);
+            process::exit(1);
+        }
+    };
+    let input = stdin_lines();
+    let (answer, answers) = mpsc::channel();
+    thread::spawn(move || {
+        for line in input {
+            match line.parse::<Control>() {
+                Ok(control) => {
+                    if let Err(e) = controls.write_all(&realtime_bytes(control)) {
+                        eprintln!("\nError sending {}: {}", line.trim(), e);
+                    }
+                }
+                Err(_) if answer.send(line).is_err() => break,
+                Err(_) => {}
+            }
+        }
+    });
+    let wait = || answers.recv().map(|_| ()).map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof));
+
+    let text: Vec<String> = lines.iter().map(|(_, line)| line.clone()).collect();
+    // GRBL can't change tools, so whoever runs the machine does it when the program gets there
+    let on_stop = |stop: &Stop| {
+        eprintln!();
+        match stop {
+            Stop::ToolChange(tool) => {
+                eprintln!("Change to tool {}, then press Enter", tool);
+                wait()
+            }
+            Stop::Aux(code) => {
+                eprintln!("{}", code);
+                Ok(())
+            }
+            Stop::Pause { optional } => {
+                let kind = if *optional { "Optional stop" } else { "Paused" };
+                eprintln!("{}, press Enter to carry on", kind);
+                wait()
+            }
+        }
+    };
+    let result = project_2::stream::stream_with_stops(port, &text, framing, |sent, _| acknowledged(sent), on_stop);
+    eprintln!();
+    if let Err(e) = result {
+        eprintln!("Error streaming program: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Function to stream to a controller, which this build can't do
+#[cfg(not(feature = "serial"))]
+fn stream_to_device(
+    _device: &str,
+    _baud: u32,
+    _framing: Framing,
+    _lines: &[(usize, String)],
+    _acknowledged: impl FnMut(usize),
+) {
+    eprintln!("Streaming needs the serial feature: cargo build --features serial");
+    process::exit(1);
+}
+
+/// Function to run the program again whenever its file changes, until interrupted
 ///
-/// ```
-/// let start = (0.0, 0.0, 0.0);
-/// let end = (3.0, 4.0, 5.0);
-/// let positions = linear_motion_calculate(start, end);
-/// assert_eq!(positions.len(), 7); // Adjusted for inclusive start and end points
-/// assert_eq!(positions[0], "0.00, 0.00, 0.00"); // Adjusted start position
-/// assert_eq!(positions[6], "3.00, 4.00, 5.00"); // Check last position
-/// ```
-fn linear_motion_calculate(start: (f64, f64, f64), end: (f64, f64, f64)) -> Vec<String> {
-    // Calculate the total change in each dimension
-    let dx = end.0 - start.0;
-    let dy = end.1 - start.1;
-    let dz = end.2 - start.2;
-
-    // Determine the maximum magnitude of change
-    let max_delta = dx.abs().max(dy.abs()).max(dz.abs());
-
-    // Determine the number of steps
-    let num_steps = (max_delta.abs() + 1.0).ceil() as usize;
-
-    // Print the delta steps for debugging
-    println!("Delta steps: dx={}, dy={}, dz={}", dx, dy, dz);
-    // Print the number of steps for debugging
-    println!("Number of steps: {}", num_steps);
-
-    // Calculate step increments for each dimension
-    let dx_step = if num_steps != 0 { dx / num_steps as f64 } else { 0.0 };
-    let dy_step = if num_steps != 0 { dy / num_steps as f64 } else { 0.0 };
-    let dz_step = if num_steps != 0 { dz / num_steps as f64 } else { 0.0 };
-
-    // Generate positions for each step
-    let mut positions = Vec::new();
-    for i in 1..=num_steps {
-        let x = start.0 + dx_step * i as f64;
-        let y = start.1 + dy_step * i as f64;
-        let z = start.2 + dz_step * i as f64;
-        positions.push(format!("{:.2}, {:.2}, {:.2}", x, y, z));
-    }
-
-    positions
-}
-
-/// Function to calculate positions for rotational motion
+/// The file's modification time is checked a few times a second, and each run
+/// is this same executable with the same arguments, less `--watch`, so it
+/// reads the tool table and machine profile afresh too.
+fn watch(args: &[String], path: &str) {
+    let executable = match env::current_exe() {
+        Ok(executable) => executable,
+        Err(e) => {
+            eprintln!("Error finding this program to run it again: {}", e);
+            process::exit(1);
+        }
+    };
+    let arguments: Vec<&String> = args[1..].iter().filter(|arg| *arg != "--watch").collect();
+
+    let mut file = WatchedFile::new(path);
+    loop {
+        if file.changed() {
+            if let Err(e) = process::Command::new(&executable).args(&arguments).status() {
+                eprintln!("Error running {}: {}", executable.display(), e);
+                process::exit(1);
+            }
+            eprintln!("Watching {} for changes (Ctrl-C to stop)", path);
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+/// A file checked for changes by its modification time
+struct WatchedFile<'a> {
+    path: &'a str,
+    // When the file was last seen to change, if it has been seen at all
+    seen: Option<SystemTime>,
+}
+
+impl<'a> WatchedFile<'a> {
+    /// Constructor for a file that hasn't been looked at yet, so the first check finds it changed
+    fn new(path: &'a str) -> Self {
+        WatchedFile { path, seen: None }
+    }
+
+    /// Function to tell whether the file has changed since the last check
+    ///
+    /// A file that's missing for a moment is being saved, so it doesn't count
+    /// as a change until it comes back.
+    fn changed(&mut self) -> bool {
+        let modified = fs::metadata(self.path).and_then(|metadata| metadata.modified()).ok();
+        if modified.is_some() && modified != self.seen {
+            self.seen = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Function to interpret programs sent over TCP until the server fails
+fn run_server(address: IpAddr, port: u16, options: &cli::Options) {
+    let listener = match TcpListener::bind((address, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Error listening on {}:{}: {}", address, port, e);
+            process::exit(1);
+        }
+    };
+    eprintln!("Listening on {}:{}", address, port);
+
+    // Clients get the server's tighter limit on how far loops may expand a program
+    let defaults = ServerOptions::default();
+    let server = ServerOptions {
+        parse: ParseOptions {
+            max_expansion: defaults.parse.max_expansion,
+            ..options.parse.clone()
+        },
+        interpolation: options.interpolation.clone(),
+        ..defaults
+    };
+    if let Err(e) = serve(listener, &server) {
+        eprintln!("Error accepting connections: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Function to interpret commands typed one at a time until the input ends
 ///
-/// Given the parameters of a rotational motion (center, radius, clockwise,
-/// stop angle), this function calculates the positions along the arc of the
-/// rotation. Positions are calculated at 5-degree intervals.
+/// Each motion's positions are printed as soon as its line is entered. Lines
+/// that can't be parsed, and motions outside the machine's travel (with
+/// `--enforce-limits`) or the arm's reach, are reported and skipped, but the
+/// position and modes they set stay set, as on a controller.
+fn run_repl(options: &cli::Options) -> io::Result<()> {
+    let interactive = io::stdin().is_terminal();
+    let mut interpreter = Interpreter::new(&options.parse);
+    let mut index = 0;
+    let mut current_z = 0.0;
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            eprint!("> ");
+        }
+        let Some(line) = lines.next() else { break };
+        // A cycle's motions are checked, and skipped, together
+        let motions = match interpreter.line(&line?) {
+            Ok(motions) if motions.is_empty() => continue,
+            Ok(motions) => motions,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+
+        let motions = motions.as_slice();
+        let violations = match &options.machine {
+            Some(machine) => soft_limits(motions, machine, &options.interpolation),
+            None => Vec::new(),
+        };
+        for violation in &violations {
+            eprintln!("Soft limit: {}", violation);
+        }
+        let unreachable = reachable(motions, &options.kinematics, &options.interpolation);
+        for motion in &unreachable {
+            eprintln!("Unreachable: {}", motion);
+        }
+        if !unreachable.is_empty() || (options.enforce_limits && !violations.is_empty()) {
+            continue;
+        }
+
+        let mut out = io::stdout().lock();
+        for motion in motions {
+            print_streamed_motion(&mut out, index, motion, &mut current_z, options)?;
+            index += 1;
+        }
+        out.flush()?;
+    }
+    if interactive {
+        eprintln!();
+    }
+    Ok(())
+}
+
+/// How often playback looks for controls and prints the positions reached
+const PLAYBACK_TICK: Duration = Duration::from_millis(10);
+
+/// Function to print a program's timed positions as the machine would reach them, exiting on failure
 ///
-/// # Arguments
+/// Each position is printed with the time it was reached since playback
+/// started. Controls typed on stdin, one per line, hold and resume the feed or
+/// override it, and the positions still to come are retimed to match.
+fn play(motions: &[Motion], options: &cli::Options) {
+    let mut playback = Playback::new(motions, timed_samples(motions, options));
+    let input = stdin_lines();
+    let mut out = io::stdout().lock();
+    let mut last = Instant::now();
+    while !playback.is_finished() {
+        for line in input.try_iter() {
+            match line.parse::<Control>() {
+                Ok(control) => {
+                    playback.control(control);
+                    let feed = playback.feed();
+                    if feed.held {
+                        eprintln!("Held, type ~ to resume");
+                    } else {
+                        eprintln!("Feed {}%, {:.1}s left", feed.percent, playback.time_left());
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        thread::sleep(PLAYBACK_TICK);
+        let now = Instant::now();
+        let reached = playback.advance(now.duration_since(last).as_secs_f64());
+        last = now;
+        // Each position goes out as soon as it's reached, not when a buffer fills
+        if let Err(e) = print_timed(&mut out, &reached, None, |_| {}).and_then(|_| out.flush()) {
+            eprintln!("Error writing output: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Function to read lines from stdin on another thread, as they are typed
 ///
-/// * `rotational_motion` - A struct containing the parameters of the rotational motion.
+/// The thread stops at the end of stdin, or once nothing is receiving.
+fn stdin_lines() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+/// Function to write a program in a language, to a file or else to stdout, exiting on failure
 ///
-/// # Returns
+/// G-code is written for the controller given with `--flavor`, and `.cmmd`
+/// without the lengths of the tools in `--tools`, which reading it back adds.
+fn write_program(motions: Vec<Motion>, output: Option<&str>, dialect: Dialect, options: &cli::Options) {
+    let program = Program::new(motions);
+    let write = |out: &mut dyn Write| match dialect {
+        Dialect::Gcode => write_gcode_for(out, &program.motions, options.flavor),
+        Dialect::Cmmd => write_cmmd_with_tools(out, &program.motions, &options.parse.tools),
+        _ => program.write(out, dialect),
+    };
+    let result = match output {
+        Some(path) => File::create(path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write(&mut out)?;
+            out.flush()
+        }),
+        None => with_stdout(|out| write(out)),
+    };
+    if let Err(e) = result {
+        eprintln!("Error writing program: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Function to time a program, exiting if it can't be timed
 ///
-/// A vector of tuples containing the calculated (x, y) positions.
+/// Velocity is ramped if an acceleration limit was given or the machine
+/// profile limits its axes, otherwise the tool jumps straight to each motion's
+/// feed rate.
+fn timed_samples(motions: &[Motion], options: &cli::Options) -> Vec<TimedPosition> {
+    // The machine profile's limits, with --accel in place of its overall limit
+    let mut planner = options.machine.as_ref().and_then(MachineConfig::planner_options);
+    if let Some(max_acceleration) = options.acceleration {
+        planner.get_or_insert_with(PlannerOptions::default).max_acceleration = max_acceleration;
+    }
+    let samples = match planner {
+        Some(planner) => {
+            let planner = PlannerOptions {
+                junction_deviation: options.junction_deviation,
+                ..planner
+            };
+            plan(motions, &options.interpolation, &planner)
+        }
+        None => timed_positions(motions, &options.interpolation),
+    };
+    match samples {
+        Ok(samples) => samples,
+        Err(e) => {
+            eprintln!("Error timing program: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Function to print a program's metrics, as text or as one JSON object
+fn print_stats(stats: &Stats, format: Format) {
+    if format != Format::Text {
+        match serde_json::to_string(stats) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Error writing output: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    println!("Length: {:.3} ({:.3} cutting, {:.3} rapid)", stats.length, stats.cutting, stats.rapid);
+    if let Some(Bounds { min, max }) = stats.bounds {
+        println!(
+            "Bounds: X {:.3} to {:.3}, Y {:.3} to {:.3}, Z {:.3} to {:.3}",
+            min.0, max.0, min.1, max.1, min.2, max.2
+        );
+    }
+    let counts = stats.motions;
+    println!(
+        "Motions: {} linear, {} rapid, {} arc, {} helical, {} bezier, {} spline, {} oriented",
+        counts.linear, counts.rapid, counts.arc, counts.helical, counts.bezier, counts.spline, counts.oriented
+    );
+    // Whole minutes and seconds read better than thousands of seconds
+    let minutes = (stats.time / 60.0).floor();
+    println!("Run time: {}m {:.1}s", minutes, stats.time - minutes * 60.0);
+    if stats.extruded != 0.0 {
+        println!("Extruded: {:.3}", stats.extruded);
+    }
+    if counts.tool_changes > 0 {
+        println!("Tool changes: {}", counts.tool_changes);
+    }
+    if counts.aux > 0 {
+        println!("Auxiliary commands: {}", counts.aux);
+    }
+    if counts.pauses > 0 {
+        println!("Pauses: {}", counts.pauses);
+    }
+    if stats.unfed > 0 {
+        println!("({} motions have no feed rate and aren't counted in the run time)", stats.unfed);
+    }
+}
+
+/// Function to print a program's motions and positions as they are parsed
 ///
-/// # Examples
+/// Only one motion is held at a time, so memory stays bounded however large
+/// the program is. Output already printed stays printed if a later line turns
+/// out to be malformed.
+fn print_streamed(motions: impl Iterator<Item = Result<Motion, CmmdError>>, options: &cli::Options) -> Result<(), CmmdError> {
+    let mut out = BufWriter::new(io::stdout().lock());
+    // Flat arcs are placed at the height the previous motion ended at
+    let mut current_z = 0.0;
+    for (index, motion) in motions.enumerate() {
+        print_streamed_motion(&mut out, index, &motion?, &mut current_z, options)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Function to print one motion and its positions, in text or as JSON Lines
 ///
-/// ```
-/// let rotational_motion = RotationalMotion {
-///     center: (0.0, 0.0),
-///     radius: 5.0,
-///     clockwise: true,
-///     stop_angle: 90.0,
-/// };
-/// let positions = rotational_motion_calculate(rotational_motion);
-/// assert_eq!(positions.len(), 21); // Adjusted expected number of positions
-/// assert_eq!(positions[0], (5.00, 0.00)); // Check first position
-/// assert_eq!(positions[20], (0.00, 5.00)); // Check last position
-/// ```
-fn rotational_motion_calculate(rotational_motion: RotationalMotion) -> Vec<(f64, f64)> {
-    // Define constants for full circle and degree to radian conversion
-    const FULL_CIRCLE: f64 = std::f64::consts::PI * 2.0;
-    const DEG_TO_RAD: f64 = std::f64::consts::PI / 180.0;
-
-    // Determine the step angle based on the radius
-    let step_angle = 5.0 / rotational_motion.radius;
-
-    // Calculate the start and end angles based on the direction of rotation
-    let (start_angle, end_angle) = if rotational_motion.clockwise {
-        (0.0, rotational_motion.stop_angle)
+/// `current_z` is the height the previous motion ended at, and is moved on to
+/// where this one ends.
+fn print_streamed_motion<W: Write>(
+    out: &mut W,
+    index: usize,
+    motion: &Motion,
+    current_z: &mut f64,
+    options: &cli::Options,
+) -> io::Result<()> {
+    let positions =
+        motion_points(motion, *current_z, &options.interpolation).inspect(|position| *current_z = position.2);
+    let count = positions.len();
+    let points = positions.enumerate().map(|(step, position)| Point {
+        motion: index,
+        position: motors(options, index, position),
+        time: None,
+        power: motion.power(),
+        rotary: rotary_at(motion, step, count),
+        extrusion: extrusion_at(motion, step, count),
+        dynamics: None,
+    });
+    if options.format == Format::Text {
+        print_motion(out, motion, points)
     } else {
-        (FULL_CIRCLE, FULL_CIRCLE - rotational_motion.stop_angle)
-    };
+        write_json_motion(out, index, motion, points)
+    }
+}
 
-    // Generate positions at 5-degree intervals
-    let mut positions = Vec::new();
-    let mut angle = start_angle;
-    while angle <= end_angle {
-        let x = rotational_motion.center.0 + rotational_motion.radius * angle.cos();
-        let y = rotational_motion.center.1 + rotational_motion.radius * angle.sin();
-        positions.push((x, y));
-        angle += DEG_TO_RAD * step_angle;
+/// Function to print timestamped positions, with the rotary angles, extruder position and power where programmed
+///
+/// `reached` is called with the motion of each position as it is printed.
+fn print_timed<W: Write>(
+    out: &mut W,
+    samples: &[TimedPosition],
+    states: Option<&[Dynamics]>,
+    mut reached: impl FnMut(usize),
+) -> io::Result<()> {
+    for (index, sample) in samples.iter().enumerate() {
+        reached(sample.motion);
+        let (x, y, z) = sample.position;
+        write!(out, "{:.3}, {:.2}, {:.2}, {:.2}", sample.time, x, y, z)?;
+        if let Some(rotary) = sample.rotary {
+            write!(out, ", {:.2}, {:.2}, {:.2}", rotary.a, rotary.b, rotary.c)?;
+        }
+        if let Some(extrusion) = sample.extrusion {
+            write!(out, ", {:.3}", extrusion)?;
+        }
+        // Speed and velocity, then acceleration, each overall and per axis
+        if let Some(state) = states.map(|states| states[index]) {
+            let (vx, vy, vz) = state.velocity;
+            let (ax, ay, az) = state.axis_acceleration;
+            write!(out, ", {:.2}, {:.2}, {:.2}, {:.2}", state.speed, vx, vy, vz)?;
+            write!(out, ", {:.2}, {:.2}, {:.2}, {:.2}", state.acceleration, ax, ay, az)?;
+        }
+        match sample.power {
+            Some(power) => writeln!(out, ", {}", power)?,
+            None => writeln!(out)?,
+        }
     }
+    Ok(())
+}
 
-    positions
+/// Function to print each motion followed by its interpolated positions
+fn print_geometry<W: Write>(out: &mut W, motions: &[Motion], points: impl IntoIterator<Item = Point>) -> io::Result<()> {
+    let mut points = points.into_iter().peekable();
+    // Process each motion
+    for (index, motion) in motions.iter().enumerate() {
+        let positions = std::iter::from_fn(|| points.next_if(|point| point.motion == index));
+        print_motion(out, motion, positions)?;
+    }
+    Ok(())
+}
+
+/// Function to print a motion followed by its interpolated positions
+fn print_motion<W: Write>(
+    out: &mut W,
+    motion: &Motion,
+    points: impl Iterator<Item = Point>,
+) -> io::Result<()> {
+    // Arcs without a Z range lie flat in XY and print just X and Y
+    let flat = match motion {
+        // Handle linear motion
+        Motion::Linear(linear_motion) => {
+            let command = if linear_motion.rapid { "RAP" } else { "LIN" };
+            write!(out, "{} {:?} to {:?}", command, linear_motion.start, linear_motion.end)?;
+            if let Some((from, to)) = linear_motion.rotary {
+                write!(out, ", turning {:?} to {:?}", (from.a, from.b, from.c), (to.a, to.b, to.c))?;
+            }
+            if let Some((from, to)) = linear_motion.extrusion {
+                write!(out, ", extruding {:?} to {:?}", from, to)?;
+            }
+            writeln!(out)?;
+            false
+        }
+        // Handle helical motion
+        Motion::Rotational(rotational_motion) if rotational_motion.z.is_some() => {
+            writeln!(out, "Helical Motion: {:?}", rotational_motion)?;
+            false
+        }
+        // Handle rotational motion
+        Motion::Rotational(rotational_motion) => {
+            writeln!(out, "Rotational Motion: {:?}", rotational_motion)?;
+            true
+        }
+        // Handle Bézier curves
+        Motion::Bezier(bezier_motion) => {
+            writeln!(
+                out,
+                "BEZ {:?} via {:?}, {:?} to {:?}",
+                bezier_motion.start, bezier_motion.control1, bezier_motion.control2, bezier_motion.end
+            )?;
+            false
+        }
+        // Handle NURBS curves
+        Motion::Spline(spline_motion) => {
+            writeln!(
+                out,
+                "NURBS degree {} through {:?}",
+                spline_motion.degree, spline_motion.controls
+            )?;
+            false
+        }
+        // Handle moves that tilt the tool
+        Motion::Oriented(oriented_motion) => {
+            let command = if oriented_motion.rapid { "RAP" } else { "LIN" };
+            writeln!(
+                out,
+                "{} {:?} to {:?}, tilting {:?} to {:?}",
+                command,
+                oriented_motion.start,
+                oriented_motion.end,
+                oriented_motion.orientation.0,
+                oriented_motion.orientation.1
+            )?;
+            false
+        }
+        // Handle tool changes, which have no positions
+        Motion::ToolChange(tool_change) => {
+            writeln!(out, "TOOL {} at {:?}", tool_change.tool, tool_change.position)?;
+            false
+        }
+        Motion::Aux(aux_command) => {
+            writeln!(out, "{} at {:?}", aux_command.code, aux_command.position)?;
+            false
+        }
+        Motion::Pause(pause) => {
+            let optional = if pause.optional { " (optional)" } else { "" };
+            writeln!(out, "PAUSE{} at {:?}", optional, pause.position)?;
+            false
+        }
+    };
+    // Print the positions the motion produced, with the rotary angles and extruder where they move
+    for point in points {
+        let (x, y, z) = point.position;
+        if flat {
+            writeln!(out, "{:.2}, {:.2}", x, y)?;
+            continue;
+        }
+        write!(out, "{:.2}, {:.2}, {:.2}", x, y, z)?;
+        if let Some(rotary) = point.rotary {
+            write!(out, ", {:.2}, {:.2}, {:.2}", rotary.a, rotary.b, rotary.c)?;
+        }
+        if let Some(extrusion) = point.extrusion {
+            write!(out, ", {:.3}", extrusion)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    // Import necessary items from the parent module
     use super::*;
 
-    /// Test the `linear_motion_calculate` function.
+    /// Test that a watched file counts as changed when first seen and whenever it's saved, but not while it's missing.
     #[test]
-    fn test_linear_motion_calculate() {
-        // Test linear motion calculation function
-        let start = (0.0, 0.0, 0.0);
-        let end = (3.0, 4.0, 5.0);
-        let positions = linear_motion_calculate(start, end);
-        assert_eq!(positions.len(), 7); // Adjusted for inclusive start and end points
-        assert_eq!(positions[0], "0.00, 0.00, 0.00"); // Adjusted start position
-        assert_eq!(positions[6], "3.00, 4.00, 5.00"); // Check last position
-    }
+    fn test_watched_file() {
+        let path = std::env::temp_dir().join(format!("project-2-watch-{}.cmmd", process::id()));
+        fs::write(&path, "LIN X0 Y0 Z0\n").unwrap();
+        let mut file = WatchedFile::new(path.to_str().unwrap());
+        assert!(file.changed());
+        assert!(!file.changed());
 
-    /// Test the `rotational_motion_calculate` function.
-    #[test]
-    fn test_rotational_motion_calculate() {
-        // Test rotational motion calculation function
-        let rotational_motion = RotationalMotion {
-            center: (0.0, 0.0),
-            radius: 5.0,
-            clockwise: true,
-            stop_angle: 90.0,
-        };
-        let positions = rotational_motion_calculate(rotational_motion);
-        assert_eq!(positions.len(), 21); // Adjusted expected number of positions
-        assert_eq!(positions[0], (5.00, 0.00)); // Check first position
-        assert_eq!(positions[20], (0.00, 5.00)); // Check last position
-    }
-
-   /// Test the `read_file` function.
-#[test]
-fn test_read_file() {
-    // Test reading motions from a file
-    let result = read_file("test.cmmd");
-    assert!(result.is_ok()); // Check if reading succeeds
-    let motions = result.unwrap();
-    assert_eq!(motions.len(), 8); // Check number of motions read
-    // Add more specific checks if needed
-    }
-}
\ No newline at end of file
+        // Saving moves the modification time on
+        let saved = SystemTime::now() + Duration::from_secs(10);
+        File::options().write(true).open(&path).unwrap().set_modified(saved).unwrap();
+        assert!(file.changed());
+        assert!(!file.changed());
+
+        // An editor replacing the file removes it for a moment
+        fs::remove_file(&path).unwrap();
+        assert!(!file.changed());
+        fs::write(&path, "LIN X1 Y0 Z0\n").unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(saved + Duration::from_secs(10)).unwrap();
+        assert!(file.changed());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,999 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::SplineError;
+use crate::spline;
+
+// Define a struct to represent linear motion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinearMotion {
+    pub start: (f64, f64, f64),
+    pub end: (f64, f64, f64),
+    #[serde(default)]
+    pub rapid: bool, // Positioning move at the machine's rapid rate instead of a cut
+    pub feed: Option<f64>, // Feed rate in units per minute, if one was programmed
+    pub power: Option<f64>, // Spindle speed or laser power, if one was programmed
+    pub line: Option<usize>, // Line of the program the motion came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotary: Option<(Rotary, Rotary)>, // Rotary axis angles at the start and end, on machines that have them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extrusion: Option<(f64, f64)>, // Extruder (E axis) position at the start and end, on 3D printers
+}
+
+impl LinearMotion {
+    /// The rotary axis angles a `fraction` of the way along the motion, if it has rotary axes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::{Motion, Rotary};
+    ///
+    /// let turn = Rotary { a: 90.0, ..Default::default() };
+    /// let motion = Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_rotary(Rotary::default(), turn);
+    /// let Motion::Linear(line) = motion else { unreachable!() };
+    /// assert_eq!(line.rotary_at(0.5), Some(Rotary { a: 45.0, b: 0.0, c: 0.0 }));
+    /// ```
+    pub fn rotary_at(&self, fraction: f64) -> Option<Rotary> {
+        let (start, end) = self.rotary?;
+        let along = |from: f64, to: f64| from + (to - from) * fraction;
+        Some(Rotary {
+            a: along(start.a, end.a),
+            b: along(start.b, end.b),
+            c: along(start.c, end.c),
+        })
+    }
+
+    /// The extruder position a `fraction` of the way along the motion, if it has an extruder
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let motion = Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_extrusion(2.0, 3.0);
+    /// let Motion::Linear(line) = motion else { unreachable!() };
+    /// assert_eq!(line.extrusion_at(0.5), Some(2.5));
+    /// ```
+    pub fn extrusion_at(&self, fraction: f64) -> Option<f64> {
+        let (start, end) = self.extrusion?;
+        Some(start + (end - start) * fraction)
+    }
+
+    /// How far the extruder moves along the motion, negative for a retraction, or 0 without an extruder
+    pub fn extruded(&self) -> f64 {
+        self.extrusion.map_or(0.0, |(start, end)| end - start)
+    }
+}
+
+/// Angles of a machine's rotary axes, in degrees
+///
+/// A turns about X, B about Y and C about Z. Machines without one of them
+/// simply leave it at zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rotary {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl Rotary {
+    /// The largest angle any axis turns through on the way to `other`
+    pub fn largest_change(&self, other: &Rotary) -> f64 {
+        (other.a - self.a).abs().max((other.b - self.b).abs()).max((other.c - self.c).abs())
+    }
+
+    /// The A and C angles that point a tool along `vector`, on a machine that tilts about X and then turns about Z
+    ///
+    /// A is the tilt away from straight down the Z axis and C the direction
+    /// of that tilt, so a vertical tool has both at zero. C is arbitrary for
+    /// a vertical tool, and is taken as zero.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Rotary;
+    ///
+    /// let angles = Rotary::from_tool_vector((1.0, 0.0, 1.0));
+    /// assert!((angles.a - 45.0).abs() < 1e-9 && (angles.c - 90.0).abs() < 1e-9);
+    /// ```
+    pub fn from_tool_vector(vector: (f64, f64, f64)) -> Rotary {
+        let (i, j, k) = unit(vector);
+        let tilt = i.hypot(j);
+        Rotary {
+            a: tilt.atan2(k).to_degrees(),
+            b: 0.0,
+            c: if tilt < 1e-12 { 0.0 } else { i.atan2(-j).to_degrees() },
+        }
+    }
+}
+
+// `vector` scaled to unit length, or straight up the Z axis if it has none
+fn unit((i, j, k): (f64, f64, f64)) -> (f64, f64, f64) {
+    let length = (i * i + j * j + k * k).sqrt();
+    if length < 1e-12 {
+        (0.0, 0.0, 1.0)
+    } else {
+        (i / length, j / length, k / length)
+    }
+}
+
+/// A straight move that tilts the tool on the way, for 5-axis machines
+///
+/// `orientation` holds the direction the tool points along (from the tip
+/// towards the spindle) at the start and at the end, as unit vectors. In
+/// between the direction turns evenly along the great circle between them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrientedMotion {
+    pub start: (f64, f64, f64),
+    pub end: (f64, f64, f64),
+    pub orientation: ((f64, f64, f64), (f64, f64, f64)),
+    #[serde(default)]
+    pub rapid: bool, // Positioning move at the machine's rapid rate instead of a cut
+    pub feed: Option<f64>, // Feed rate in units per minute, if one was programmed
+    pub power: Option<f64>, // Spindle speed or laser power, if one was programmed
+    pub line: Option<usize>, // Line of the program the motion came from
+}
+
+impl OrientedMotion {
+    /// The angle the tool turns through along the motion, in degrees
+    pub fn turned(&self) -> f64 {
+        let (from, to) = self.orientation;
+        let dot = from.0 * to.0 + from.1 * to.1 + from.2 * to.2;
+        dot.clamp(-1.0, 1.0).acos().to_degrees()
+    }
+
+    /// The direction of the tool a `fraction` of the way along the motion
+    ///
+    /// The direction is interpolated spherically, so it turns at a steady
+    /// rate and stays a unit vector. Opposite directions have no single
+    /// great circle between them and turn through the X axis.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::{Motion, OrientedMotion};
+    ///
+    /// let motion = Motion::new_oriented((0.0, 0.0, 0.0), (10.0, 0.0, 0.0), (0.0, 0.0, 1.0), (1.0, 0.0, 0.0));
+    /// let Motion::Oriented(motion) = motion else { unreachable!() };
+    /// let (i, _, k) = motion.orientation_at(0.5);
+    /// assert!((i - 0.5f64.sqrt()).abs() < 1e-9 && (k - 0.5f64.sqrt()).abs() < 1e-9);
+    /// ```
+    pub fn orientation_at(&self, fraction: f64) -> (f64, f64, f64) {
+        let (from, to) = self.orientation;
+        let angle = self.turned().to_radians();
+        if angle < 1e-9 {
+            return from;
+        }
+        // A half turn has no plane of its own, so pick one through X (or Y if the tool lies along X)
+        let sin = angle.sin();
+        let along = if sin < 1e-9 {
+            let axis = if from.0.abs() < 0.9 { (1.0, 0.0, 0.0) } else { (0.0, 1.0, 0.0) };
+            let dot = from.0 * axis.0 + from.1 * axis.1 + from.2 * axis.2;
+            unit((axis.0 - from.0 * dot, axis.1 - from.1 * dot, axis.2 - from.2 * dot))
+        } else {
+            let dot = angle.cos();
+            ((to.0 - from.0 * dot) / sin, (to.1 - from.1 * dot) / sin, (to.2 - from.2 * dot) / sin)
+        };
+        // Turn from the start direction towards the perpendicular `along`
+        let (cos, sin) = ((angle * fraction).cos(), (angle * fraction).sin());
+        unit((from.0 * cos + along.0 * sin, from.1 * cos + along.1 * sin, from.2 * cos + along.2 * sin))
+    }
+}
+
+/// Plane an arc lies in, selected with `PLANE` (G17/G18/G19 in G-code)
+///
+/// Arc centers and angles are given in the plane's own coordinates: the
+/// first axis points right and the second up, so angles run from the first
+/// axis towards the second. The remaining axis is the one helices ramp along.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Plane {
+    /// X right and Y up, seen from above
+    #[default]
+    Xy,
+    /// X right and Z up, seen from the front
+    Xz,
+    /// Y right and Z up, seen from the right
+    Yz,
+}
+
+impl Plane {
+    /// Map coordinates in the plane and along its normal axis to an (x, y, z) point
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Plane;
+    ///
+    /// assert_eq!(Plane::Xz.point((1.0, 2.0, 3.0)), (1.0, 3.0, 2.0));
+    /// assert_eq!(Plane::Xz.coordinates((1.0, 3.0, 2.0)), (1.0, 2.0, 3.0));
+    /// ```
+    pub fn point(self, (first, second, normal): (f64, f64, f64)) -> (f64, f64, f64) {
+        match self {
+            Plane::Xy => (first, second, normal),
+            Plane::Xz => (first, normal, second),
+            Plane::Yz => (normal, first, second),
+        }
+    }
+
+    /// Split an (x, y, z) point into coordinates in the plane and along its normal axis
+    pub fn coordinates(self, (x, y, z): (f64, f64, f64)) -> (f64, f64, f64) {
+        match self {
+            Plane::Xy => (x, y, z),
+            Plane::Xz => (x, z, y),
+            Plane::Yz => (y, z, x),
+        }
+    }
+}
+
+// Define a struct to represent rotational motion
+//
+// Angles are in degrees, measured counter-clockwise from the first axis of the
+// plane toward the second (from +X toward +Y for XY arcs), and the sweep is
+// signed: positive sweeps run counter-clockwise and negative ones clockwise.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RotationalMotion {
+    pub center: (f64, f64),
+    pub radius: f64,
+    pub start_angle: f64, // Angle the arc begins at, in degrees
+    pub sweep: f64, // Angle the arc turns through, in degrees, negative for clockwise arcs
+    pub z: Option<(f64, f64)>, // Position along the plane's normal (Z for XY arcs) at the start and stop angles, for helical motion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<(f64, f64)>, // End point in the plane exactly as programmed, for arcs given by one
+    #[serde(default)]
+    pub plane: Plane, // Plane the center and angles are given in
+    pub feed: Option<f64>, // Feed rate in units per minute, if one was programmed
+    pub power: Option<f64>, // Spindle speed or laser power, if one was programmed
+    pub line: Option<usize>, // Line of the program the motion came from
+}
+
+impl RotationalMotion {
+    /// The angle the arc ends at, in degrees
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let Motion::Rotational(arc) = Motion::new_rotational((0.0, 0.0), 5.0, 90.0, -180.0) else { unreachable!() };
+    /// assert_eq!(arc.stop_angle(), -90.0);
+    /// assert!(arc.is_clockwise());
+    /// ```
+    pub fn stop_angle(&self) -> f64 {
+        self.start_angle + self.sweep
+    }
+
+    /// Whether the tool travels clockwise as seen in the arc's plane
+    pub fn is_clockwise(&self) -> bool {
+        self.sweep < 0.0
+    }
+}
+
+/// A cubic Bézier curve, drawn with `BEZ` (G5 in G-code)
+///
+/// The curve leaves `start` heading toward `control1` and arrives at `end`
+/// coming from `control2`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BezierMotion {
+    pub start: (f64, f64, f64),
+    pub control1: (f64, f64, f64),
+    pub control2: (f64, f64, f64),
+    pub end: (f64, f64, f64),
+    pub feed: Option<f64>, // Feed rate in units per minute, if one was programmed
+    pub power: Option<f64>, // Spindle speed or laser power, if one was programmed
+    pub line: Option<usize>, // Line of the program the motion came from
+}
+
+impl BezierMotion {
+    // Number of chords the curve is measured with
+    pub(crate) const CHORDS: usize = 256;
+
+    /// The point of the curve at parameter `t`, from 0 at the start to 1 at the end
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::BezierMotion;
+    ///
+    /// let curve = BezierMotion {
+    ///     start: (0.0, 0.0, 0.0),
+    ///     control1: (0.0, 4.0, 0.0),
+    ///     control2: (4.0, 4.0, 0.0),
+    ///     end: (4.0, 0.0, 0.0),
+    ///     feed: None,
+    ///     power: None,
+    ///     line: None,
+    /// };
+    /// assert_eq!(curve.point(0.5), (2.0, 3.0, 0.0));
+    /// ```
+    pub fn point(&self, t: f64) -> (f64, f64, f64) {
+        // Bernstein weights of the four points
+        let u = 1.0 - t;
+        let weights = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+        let points = [self.start, self.control1, self.control2, self.end];
+        let mut point = (0.0, 0.0, 0.0);
+        for (weight, (x, y, z)) in weights.into_iter().zip(points) {
+            point.0 += weight * x;
+            point.1 += weight * y;
+            point.2 += weight * z;
+        }
+        point
+    }
+
+    /// The length of the curve, measured along 256 chords
+    pub fn length(&self) -> f64 {
+        let mut length = 0.0;
+        let mut previous = self.start;
+        for i in 1..=Self::CHORDS {
+            let point = self.point(i as f64 / Self::CHORDS as f64);
+            length += ((point.0 - previous.0).powi(2) + (point.1 - previous.1).powi(2) + (point.2 - previous.2).powi(2))
+                .sqrt();
+            previous = point;
+        }
+        length
+    }
+}
+
+/// A NURBS curve, drawn with `NURBS` (G5.2/G5.3 in G-code)
+///
+/// Build one with [`Motion::new_spline`], which checks that the control
+/// points, weights and knots fit together; the methods below assume they do.
+/// Deserializing checks them the same way.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SplineFields")]
+pub struct SplineMotion {
+    pub controls: Vec<(f64, f64, f64)>,
+    pub weights: Vec<f64>, // One per control point; heavier points pull the curve closer
+    pub knots: Vec<f64>, // `controls.len() + degree + 1` of them, never decreasing
+    pub degree: usize,
+    pub feed: Option<f64>, // Feed rate in units per minute, if one was programmed
+    pub power: Option<f64>, // Spindle speed or laser power, if one was programmed
+    pub line: Option<usize>, // Line of the program the motion came from
+}
+
+// The fields of a spline as they are read, before they're checked to fit together
+#[derive(Deserialize)]
+struct SplineFields {
+    controls: Vec<(f64, f64, f64)>,
+    weights: Vec<f64>,
+    knots: Vec<f64>,
+    degree: usize,
+    feed: Option<f64>,
+    power: Option<f64>,
+    line: Option<usize>,
+}
+
+impl TryFrom<SplineFields> for SplineMotion {
+    type Error = SplineError;
+
+    fn try_from(fields: SplineFields) -> Result<Self, Self::Error> {
+        spline::check(&fields.controls, &fields.weights, &fields.knots, fields.degree)?;
+        Ok(SplineMotion {
+            controls: fields.controls,
+            weights: fields.weights,
+            knots: fields.knots,
+            degree: fields.degree,
+            feed: fields.feed,
+            power: fields.power,
+            line: fields.line,
+        })
+    }
+}
+
+impl SplineMotion {
+    /// The point of the curve at `t`, from 0 at the start to 1 at the end
+    ///
+    /// `t` is spread evenly over the knots the curve runs across, not over
+    /// its length.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let controls = vec![(0.0, 0.0, 0.0), (2.0, 2.0, 0.0), (4.0, 0.0, 0.0)];
+    /// let Motion::Spline(curve) = Motion::new_spline(controls, vec![1.0; 3], None, 2).unwrap() else {
+    ///     unreachable!()
+    /// };
+    /// assert_eq!(curve.point(0.5), (2.0, 1.0, 0.0));
+    /// ```
+    pub fn point(&self, t: f64) -> (f64, f64, f64) {
+        spline::evaluate(&self.controls, &self.weights, &self.knots, self.degree, t)
+    }
+
+    /// Where the curve starts, the first control point unless the knots aren't clamped
+    pub fn start(&self) -> (f64, f64, f64) {
+        self.point(0.0)
+    }
+
+    /// Where the curve ends, the last control point unless the knots aren't clamped
+    pub fn end(&self) -> (f64, f64, f64) {
+        self.point(1.0)
+    }
+
+    /// The length of the curve, measured along 256 chords per control point
+    pub fn length(&self) -> f64 {
+        let chords = BezierMotion::CHORDS * self.controls.len();
+        let mut length = 0.0;
+        let mut previous = self.start();
+        for i in 1..=chords {
+            let point = self.point(i as f64 / chords as f64);
+            length += ((point.0 - previous.0).powi(2) + (point.1 - previous.1).powi(2) + (point.2 - previous.2).powi(2))
+                .sqrt();
+            previous = point;
+        }
+        length
+    }
+}
+
+/// A tool change between the motions before and after it
+///
+/// The tool doesn't move, so the change has no positions of its own:
+/// `position` is just where the tool waits while it's changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolChange {
+    pub tool: u32, // Number of the tool put in; 0 takes the tool out
+    pub position: (f64, f64, f64), // Where the tool is while it's changed
+    pub line: Option<usize>, // Line of the program the change came from
+}
+
+/// An auxiliary command, such as coolant on or off, that runs between two motions
+///
+/// Like a tool change it doesn't move the tool. The command is kept as it was
+/// written (`M8`, or `M101 P2` for a user-defined code), so it can be passed on
+/// to the machine.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuxCommand {
+    pub code: String, // The command as written, M-code first
+    pub position: (f64, f64, f64), // Where the tool is when the command runs
+    pub line: Option<usize>, // Line of the program the command came from
+}
+
+/// A stop in the program until whoever runs the machine carries on
+///
+/// `M1` stops are optional: controllers only stop for them with their
+/// optional stop switch on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pause {
+    pub optional: bool, // Whether this is an optional stop (M1) rather than a pause (PAUSE or M0)
+    pub position: (f64, f64, f64), // Where the tool waits
+    pub line: Option<usize>, // Line of the program the pause came from
+}
+
+// Define an enum to represent different types of motion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Motion {
+    Linear(LinearMotion),
+    Rotational(RotationalMotion),
+    Bezier(BezierMotion),
+    Spline(SplineMotion),
+    Oriented(OrientedMotion),
+    #[serde(rename = "tool_change")]
+    ToolChange(ToolChange),
+    Aux(AuxCommand),
+    Pause(Pause),
+}
+
+impl Motion {
+    // Constructor for linear motion
+    pub fn new_linear(start: (f64, f64, f64), end: (f64, f64, f64)) -> Self {
+        Motion::Linear(LinearMotion {
+            start,
+            end,
+            rapid: false,
+            feed: None,
+            power: None,
+            line: None,
+            rotary: None,
+            extrusion: None,
+        })
+    }
+
+    /// Constructor for a tool change, with the tool waiting at `position`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let change = Motion::new_tool_change(2, (0.0, 0.0, 50.0));
+    /// assert!(change.is_event());
+    /// assert_eq!(change.feed(), None);
+    /// ```
+    pub fn new_tool_change(tool: u32, position: (f64, f64, f64)) -> Self {
+        Motion::ToolChange(ToolChange {
+            tool,
+            position,
+            line: None,
+        })
+    }
+
+    /// Constructor for an auxiliary command such as `M8`, run with the tool at `position`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let coolant = Motion::new_aux("M8", (0.0, 0.0, 5.0));
+    /// assert!(coolant.is_event());
+    /// ```
+    pub fn new_aux(code: impl Into<String>, position: (f64, f64, f64)) -> Self {
+        Motion::Aux(AuxCommand {
+            code: code.into(),
+            position,
+            line: None,
+        })
+    }
+
+    /// Constructor for a pause, or an optional stop, with the tool waiting at `position`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let pause = Motion::new_pause(false, (0.0, 0.0, 5.0));
+    /// assert!(pause.is_event());
+    /// ```
+    pub fn new_pause(optional: bool, position: (f64, f64, f64)) -> Self {
+        Motion::Pause(Pause {
+            optional,
+            position,
+            line: None,
+        })
+    }
+
+    /// Whether the motion is an event between moves, such as a tool change, that doesn't move the tool
+    pub fn is_event(&self) -> bool {
+        matches!(self, Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_))
+    }
+
+    // Constructor for a rapid positioning move, which runs at the machine's rapid rate
+    pub fn new_rapid(start: (f64, f64, f64), end: (f64, f64, f64)) -> Self {
+        Motion::Linear(LinearMotion {
+            start,
+            end,
+            rapid: true,
+            feed: None,
+            power: None,
+            line: None,
+            rotary: None,
+            extrusion: None,
+        })
+    }
+
+    /// Constructor for a straight move that turns the tool from one direction to another
+    ///
+    /// The directions don't need to be unit vectors; they are scaled to unit
+    /// length, and a zero vector is read as straight up the Z axis.
+    pub fn new_oriented(
+        start: (f64, f64, f64),
+        end: (f64, f64, f64),
+        from: (f64, f64, f64),
+        to: (f64, f64, f64),
+    ) -> Self {
+        Motion::Oriented(OrientedMotion {
+            start,
+            end,
+            orientation: (unit(from), unit(to)),
+            rapid: false,
+            feed: None,
+            power: None,
+            line: None,
+        })
+    }
+
+    /// Constructor for a cubic Bézier curve through two control points
+    pub fn new_bezier(
+        start: (f64, f64, f64),
+        control1: (f64, f64, f64),
+        control2: (f64, f64, f64),
+        end: (f64, f64, f64),
+    ) -> Self {
+        Motion::Bezier(BezierMotion {
+            start,
+            control1,
+            control2,
+            end,
+            feed: None,
+            power: None,
+            line: None,
+        })
+    }
+
+    /// Constructor for a Bézier curve given the way G5 describes it
+    ///
+    /// The first control point is `start + offset1` and the second `end +
+    /// offset2`, in XY. Their heights are a third and two thirds of the way
+    /// from the start height to the end height, so Z rises evenly along the
+    /// curve's parameter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let curve = Motion::new_bezier_from_offsets((0.0, 0.0, 0.0), (10.0, 0.0, 3.0), (0.0, 5.0), (0.0, 5.0));
+    /// let Motion::Bezier(curve) = curve else { unreachable!() };
+    /// assert_eq!(curve.control1, (0.0, 5.0, 1.0));
+    /// assert_eq!(curve.control2, (10.0, 5.0, 2.0));
+    /// ```
+    pub fn new_bezier_from_offsets(
+        start: (f64, f64, f64),
+        end: (f64, f64, f64),
+        offset1: (f64, f64),
+        offset2: (f64, f64),
+    ) -> Self {
+        let rise = end.2 - start.2;
+        Motion::new_bezier(
+            start,
+            (start.0 + offset1.0, start.1 + offset1.1, start.2 + rise / 3.0),
+            (end.0 + offset2.0, end.1 + offset2.1, start.2 + rise * 2.0 / 3.0),
+            end,
+        )
+    }
+
+    /// Constructor for a NURBS curve
+    ///
+    /// # Arguments
+    ///
+    /// * `controls` - The control points
+    /// * `weights` - The weight of each control point, 1 for a plain B-spline
+    /// * `knots` - The knot vector, or `None` for a clamped, uniform one that
+    ///   starts at the first control point and ends at the last
+    /// * `degree` - The degree of the curve's pieces, 3 for cubic
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SplineError` if the control points, weights and knots don't
+    /// describe a curve of that degree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let controls = vec![(0.0, 0.0, 0.0), (0.0, 5.0, 0.0), (5.0, 5.0, 0.0), (5.0, 0.0, 0.0), (10.0, 0.0, 0.0)];
+    /// let Motion::Spline(curve) = Motion::new_spline(controls, vec![1.0; 5], None, 3).unwrap() else {
+    ///     unreachable!()
+    /// };
+    /// assert_eq!(curve.end(), (10.0, 0.0, 0.0));
+    /// assert!(Motion::new_spline(vec![(0.0, 0.0, 0.0)], vec![1.0], None, 3).is_err());
+    /// ```
+    pub fn new_spline(
+        controls: Vec<(f64, f64, f64)>,
+        weights: Vec<f64>,
+        knots: Option<Vec<f64>>,
+        degree: usize,
+    ) -> Result<Self, SplineError> {
+        let knots = knots.unwrap_or_else(|| spline::clamped_knots(controls.len(), degree));
+        spline::check(&controls, &weights, &knots, degree)?;
+        Ok(Motion::Spline(SplineMotion {
+            controls,
+            weights,
+            knots,
+            degree,
+            feed: None,
+            power: None,
+            line: None,
+        }))
+    }
+
+    /// Whether the motion is a rapid positioning move rather than a cut
+    pub fn is_rapid(&self) -> bool {
+        matches!(
+            self,
+            Motion::Linear(LinearMotion { rapid: true, .. }) | Motion::Oriented(OrientedMotion { rapid: true, .. })
+        )
+    }
+
+    // Constructor for rotational motion, turning `sweep` degrees from `start_angle`
+    pub fn new_rotational(center: (f64, f64), radius: f64, start_angle: f64, sweep: f64) -> Self {
+        Motion::Rotational(RotationalMotion {
+            center,
+            radius,
+            start_angle,
+            sweep,
+            z: None,
+            end: None,
+            plane: Plane::Xy,
+            feed: None,
+            power: None,
+            line: None,
+        })
+    }
+
+    // Constructor for helical motion, ramping Z linearly across the arc
+    pub fn new_helical(center: (f64, f64), radius: f64, start_angle: f64, sweep: f64, z: (f64, f64)) -> Self {
+        Motion::Rotational(RotationalMotion {
+            center,
+            radius,
+            start_angle,
+            sweep,
+            z: Some(z),
+            end: None,
+            plane: Plane::Xy,
+            feed: None,
+            power: None,
+            line: None,
+        })
+    }
+
+    /// Set the feed rate of the motion, in units per minute
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let motion = Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(300.0));
+    /// assert_eq!(motion.feed(), Some(300.0));
+    /// ```
+    pub fn with_feed(mut self, feed: Option<f64>) -> Self {
+        match &mut self {
+            Motion::Linear(linear_motion) => linear_motion.feed = feed,
+            Motion::Rotational(rotational_motion) => rotational_motion.feed = feed,
+            Motion::Bezier(bezier_motion) => bezier_motion.feed = feed,
+            Motion::Spline(spline_motion) => spline_motion.feed = feed,
+            Motion::Oriented(oriented_motion) => oriented_motion.feed = feed,
+            // Events don't move, so they have no feed rate
+            Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => {}
+        }
+        self
+    }
+
+    /// The feed rate of the motion, in units per minute
+    pub fn feed(&self) -> Option<f64> {
+        match self {
+            Motion::Linear(linear_motion) => linear_motion.feed,
+            Motion::Rotational(rotational_motion) => rotational_motion.feed,
+            Motion::Bezier(bezier_motion) => bezier_motion.feed,
+            Motion::Spline(spline_motion) => spline_motion.feed,
+            Motion::Oriented(oriented_motion) => oriented_motion.feed,
+            Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => None,
+        }
+    }
+
+    /// Set the spindle speed or laser power the motion runs at
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let motion = Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_power(Some(800.0));
+    /// assert_eq!(motion.power(), Some(800.0));
+    /// ```
+    pub fn with_power(mut self, power: Option<f64>) -> Self {
+        match &mut self {
+            Motion::Linear(linear_motion) => linear_motion.power = power,
+            Motion::Rotational(rotational_motion) => rotational_motion.power = power,
+            Motion::Bezier(bezier_motion) => bezier_motion.power = power,
+            Motion::Spline(spline_motion) => spline_motion.power = power,
+            Motion::Oriented(oriented_motion) => oriented_motion.power = power,
+            Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => {}
+        }
+        self
+    }
+
+    /// The spindle speed or laser power of the motion
+    pub fn power(&self) -> Option<f64> {
+        match self {
+            Motion::Linear(linear_motion) => linear_motion.power,
+            Motion::Rotational(rotational_motion) => rotational_motion.power,
+            Motion::Bezier(bezier_motion) => bezier_motion.power,
+            Motion::Spline(spline_motion) => spline_motion.power,
+            Motion::Oriented(oriented_motion) => oriented_motion.power,
+            Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => None,
+        }
+    }
+
+    /// Set the line of the program the motion came from
+    pub fn with_line(mut self, line: usize) -> Self {
+        match &mut self {
+            Motion::Linear(linear_motion) => linear_motion.line = Some(line),
+            Motion::Rotational(rotational_motion) => rotational_motion.line = Some(line),
+            Motion::Bezier(bezier_motion) => bezier_motion.line = Some(line),
+            Motion::Spline(spline_motion) => spline_motion.line = Some(line),
+            Motion::Oriented(oriented_motion) => oriented_motion.line = Some(line),
+            Motion::ToolChange(tool_change) => tool_change.line = Some(line),
+            Motion::Aux(aux_command) => aux_command.line = Some(line),
+            Motion::Pause(pause) => pause.line = Some(line),
+        }
+        self
+    }
+
+    /// Put an arc in another plane, leaving linear motions as they are
+    ///
+    /// The arc's center, angles and Z range are then read as coordinates in
+    /// `plane`, see [`Plane`].
+    pub fn with_plane(mut self, plane: Plane) -> Self {
+        if let Motion::Rotational(rotational_motion) = &mut self {
+            rotational_motion.plane = plane;
+        }
+        self
+    }
+
+    /// Turn the rotary axes from `start` to `end` along a linear motion, leaving other motions as they are
+    pub fn with_rotary(mut self, start: Rotary, end: Rotary) -> Self {
+        if let Motion::Linear(linear_motion) = &mut self {
+            linear_motion.rotary = Some((start, end));
+        }
+        self
+    }
+
+    /// Move the extruder from `start` to `end` along a linear motion, leaving other motions as they are
+    pub fn with_extrusion(mut self, start: f64, end: f64) -> Self {
+        if let Motion::Linear(linear_motion) = &mut self {
+            linear_motion.extrusion = Some((start, end));
+        }
+        self
+    }
+
+    /// The line of the program the motion came from, if known
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            Motion::Linear(linear_motion) => linear_motion.line,
+            Motion::Rotational(rotational_motion) => rotational_motion.line,
+            Motion::Bezier(bezier_motion) => bezier_motion.line,
+            Motion::Spline(spline_motion) => spline_motion.line,
+            Motion::Oriented(oriented_motion) => oriented_motion.line,
+            Motion::ToolChange(tool_change) => tool_change.line,
+            Motion::Aux(aux_command) => aux_command.line,
+            Motion::Pause(pause) => pause.line,
+        }
+    }
+
+    /// Constructor for an arc given by its end points and center offset
+    ///
+    /// This is how G2/G3 describe arcs: the tool moves from `start` to `end`
+    /// around a center found at `start + offset`. The radius and the swept
+    /// angle are computed from those points. If `end` coincides with `start`
+    /// the arc is a full circle. Z ramps from the start to the end height, so
+    /// arcs whose end point changes Z become helices.
+    ///
+    /// The end point is taken to be on the circle through `start`, and is kept
+    /// so the arc ends exactly there rather than where rounding of its stop
+    /// angle would put it. Both parsers reject an end point off the circle,
+    /// and a zero offset, before they get here.
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The (x, y, z) point the arc starts at
+    /// * `end` - The (x, y, z) point the arc ends at
+    /// * `offset` - The (i, j) offset from `start` to the center
+    /// * `clockwise` - Whether the tool travels clockwise around the center
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let arc = Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, -10.0, -1.0), (-10.0, 0.0), true);
+    /// let Motion::Rotational(arc) = arc else { unreachable!() };
+    /// assert_eq!(arc.center, (0.0, 0.0));
+    /// assert_eq!(arc.radius, 10.0);
+    /// assert_eq!((arc.start_angle, arc.sweep), (0.0, -90.0));
+    /// assert_eq!(arc.z, Some((0.0, -1.0)));
+    /// ```
+    pub fn new_arc_from_offset(
+        start: (f64, f64, f64),
+        end: (f64, f64, f64),
+        offset: (f64, f64),
+        clockwise: bool,
+    ) -> Self {
+        let start_z = start.2;
+
+        // Center and radius follow directly from the offset
+        let center = (start.0 + offset.0, start.1 + offset.1);
+        let radius = offset.0.hypot(offset.1);
+
+        // Angles of the start and end points as seen from the center
+        let start_angle = (start.1 - center.1).atan2(start.0 - center.0);
+        let end_angle = (end.1 - center.1).atan2(end.0 - center.0);
+
+        // Clockwise travel runs toward decreasing angles, counter-clockwise toward increasing ones
+        let sweep = if clockwise {
+            -normalize_sweep(start_angle - end_angle)
+        } else {
+            normalize_sweep(end_angle - start_angle)
+        };
+        let mut arc =
+            Motion::new_helical(center, radius, start_angle.to_degrees(), sweep.to_degrees(), (start_z, end.2));
+        if let Motion::Rotational(rotational_motion) = &mut arc {
+            rotational_motion.end = Some((end.0, end.1));
+        }
+        arc
+    }
+
+    /// Constructor for an arc given by its end points and radius
+    ///
+    /// This is the `R` form of G2/G3: of the two arcs of `radius` that join
+    /// `start` to `end` in the direction of travel, a positive radius picks
+    /// the one of at most half a turn and a negative radius the longer one.
+    /// Z ramps from the start to the end height as it does for
+    /// [`Motion::new_arc_from_offset`].
+    ///
+    /// Returns `None` if the end points coincide, since the radius can't say
+    /// where a full circle's center is, or if they are further apart than the
+    /// circle is wide. Points apart by up to 0.1% more than the diameter are
+    /// taken to be a half circle, as rounding often leaves them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let arc = Motion::new_arc_from_radius((10.0, 0.0, 0.0), (0.0, -10.0, 0.0), 10.0, true).unwrap();
+    /// let Motion::Rotational(arc) = arc else { unreachable!() };
+    /// assert!(arc.center.0.abs() < 1e-12 && arc.center.1.abs() < 1e-12);
+    /// assert_eq!(Motion::new_arc_from_radius((0.0, 0.0, 0.0), (30.0, 0.0, 0.0), 10.0, true), None);
+    /// ```
+    pub fn new_arc_from_radius(
+        start: (f64, f64, f64),
+        end: (f64, f64, f64),
+        radius: f64,
+        clockwise: bool,
+    ) -> Option<Self> {
+        let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+        let chord = dx.hypot(dy);
+        if chord == 0.0 || chord > 2.0 * radius.abs() * (1.0 + 1e-3) {
+            return None;
+        }
+        // The center is on the chord's perpendicular bisector, this far from the chord
+        let height = (radius * radius - chord * chord / 4.0).max(0.0).sqrt();
+        // Right of the chord for clockwise arcs of up to half a turn, left for counter-clockwise ones, and the
+        // other way round for the longer arcs
+        let side = if clockwise == (radius > 0.0) { -1.0 } else { 1.0 };
+        let offset = (dx / 2.0 - side * height * dy / chord, dy / 2.0 + side * height * dx / chord);
+        Some(Motion::new_arc_from_offset(start, end, offset, clockwise))
+    }
+
+    /// Constructor for an arc through three points
+    ///
+    /// The tool moves from `start` through `through` to `end` along the one
+    /// circle that passes through all three, turning whichever way visits them
+    /// in that order. Only the X and Y of `through` matter: Z ramps from the
+    /// start to the end height as it does for [`Motion::new_arc_from_offset`].
+    ///
+    /// Returns `None` if the points lie on one line, or two of them coincide,
+    /// since then no circle or direction follows from them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    ///
+    /// let arc = Motion::new_arc_through((10.0, 0.0, 0.0), (0.0, 10.0, 0.0), (-10.0, 0.0, 0.0)).unwrap();
+    /// assert_eq!(arc, Motion::new_arc_from_offset((10.0, 0.0, 0.0), (-10.0, 0.0, 0.0), (-10.0, 0.0), false));
+    /// assert_eq!(Motion::new_arc_through((0.0, 0.0, 0.0), (1.0, 1.0, 0.0), (2.0, 2.0, 0.0)), None);
+    /// ```
+    pub fn new_arc_through(start: (f64, f64, f64), through: (f64, f64, f64), end: (f64, f64, f64)) -> Option<Self> {
+        // Work from the start point, so the center comes out as the offset G2/G3 use
+        let (bx, by) = (through.0 - start.0, through.1 - start.1);
+        let (cx, cy) = (end.0 - start.0, end.1 - start.1);
+        let cross = bx * cy - by * cx;
+        // The cross product is the sine of the angle at the start, scaled by the two distances
+        if cross.abs() <= 1e-9 * bx.hypot(by) * cx.hypot(cy) || cross == 0.0 {
+            return None;
+        }
+        let (b2, c2) = (bx * bx + by * by, cx * cx + cy * cy);
+        let offset = ((cy * b2 - by * c2) / (2.0 * cross), (bx * c2 - cx * b2) / (2.0 * cross));
+        // Points visited counter-clockwise have a positive cross product
+        Some(Motion::new_arc_from_offset(start, end, offset, cross < 0.0))
+    }
+}
+
+// Normalize a sweep angle in radians into (0, 2π]
+fn normalize_sweep(angle: f64) -> f64 {
+    let angle = angle.rem_euclid(2.0 * PI);
+    if angle <= 1e-12 {
+        2.0 * PI
+    } else {
+        angle
+    }
+}
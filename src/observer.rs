@@ -0,0 +1,233 @@
+//! Callbacks as a program is interpolated
+//!
+//! An [`Observer`] is told about each motion as interpolation starts and
+//! finishes it, and about each position in between, so an embedder can drive
+//! a display, a motion controller or a log as the program runs instead of
+//! collecting every position first. [`Trajectory::observe`] and
+//! [`Trajectory::observe_timed`] walk a parsed program; [`observe_stream`]
+//! walks one as it is parsed, holding a single motion at a time.
+//!
+//! [`Trajectory::observe`]: crate::trajectory::Trajectory::observe
+//! [`Trajectory::observe_timed`]: crate::trajectory::Trajectory::observe_timed
+
+use crate::error::CmmdError;
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::output::Point;
+use crate::trajectory::{extrusion_at, motion_points, rotary_at};
+
+/// Callbacks for the events of interpolating a program, all of which do nothing by default
+///
+/// Closures taking a [`Point`] are observers that only look at positions.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::observer::Observer;
+/// use project_2::output::Point;
+/// use project_2::trajectory::Trajectory;
+///
+/// // Count the positions of each motion
+/// #[derive(Default)]
+/// struct Counter(Vec<usize>);
+///
+/// impl Observer for Counter {
+///     fn on_motion_start(&mut self, _: usize, _: &Motion) {
+///         self.0.push(0);
+///     }
+///     fn on_point(&mut self, _: &Point) {
+///         *self.0.last_mut().unwrap() += 1;
+///     }
+/// }
+///
+/// let motions = vec![
+///     Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)),
+///     Motion::new_linear((0.0, 0.0, 0.0), (2.0, 0.0, 0.0)),
+/// ];
+/// let options = Default::default();
+/// let mut counter = Counter::default();
+/// Trajectory::new(&motions, &options).observe(&mut counter);
+/// assert_eq!(counter.0, [7, 4]);
+/// ```
+pub trait Observer {
+    /// Function called before the first position of a motion
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - Index of the motion in the program, counted from zero
+    /// * `motion` - The motion about to be interpolated
+    fn on_motion_start(&mut self, index: usize, motion: &Motion) {
+        let _ = (index, motion);
+    }
+
+    /// Function called for each position, in order
+    fn on_point(&mut self, point: &Point) {
+        let _ = point;
+    }
+
+    /// Function called after the last position of a motion
+    fn on_motion_end(&mut self, index: usize, motion: &Motion) {
+        let _ = (index, motion);
+    }
+
+    /// Function called with the error that stops interpolation, before it's returned
+    fn on_error(&mut self, error: &CmmdError) {
+        let _ = error;
+    }
+}
+
+impl<F: FnMut(&Point)> Observer for F {
+    fn on_point(&mut self, point: &Point) {
+        self(point)
+    }
+}
+
+/// Function to tell an observer about one motion and its untimed positions
+///
+/// `current_z` is the height the previous motion ended at, where flat arcs
+/// are placed, and is moved on to where this one ends.
+pub(crate) fn observe_motion<O: Observer + ?Sized>(
+    index: usize,
+    motion: &Motion,
+    current_z: &mut f64,
+    options: &InterpolationOptions,
+    observer: &mut O,
+) {
+    observer.on_motion_start(index, motion);
+    let positions = motion_points(motion, *current_z, options);
+    let count = positions.len();
+    for (step, position) in positions.enumerate() {
+        *current_z = position.2;
+        observer.on_point(&Point {
+            motion: index,
+            position,
+            time: None,
+            power: motion.power(),
+            rotary: rotary_at(motion, step, count),
+            extrusion: extrusion_at(motion, step, count),
+            dynamics: None,
+        });
+    }
+    observer.on_motion_end(index, motion);
+}
+
+/// Function to interpolate motions as they are parsed, telling an observer about each one
+///
+/// Only one motion is held at a time, so memory stays bounded however large
+/// the program is. Positions are untimed.
+///
+/// # Arguments
+///
+/// * `motions` - The motions, or the errors reading them, such as from
+///   [`stream_program_from`](crate::parser::stream_program_from)
+/// * `options` - Interpolation options
+/// * `observer` - What to tell about each motion and position
+///
+/// # Errors
+///
+/// Returns the first error reading the motions, once the observer has been
+/// told about it and about every motion before it.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::observer::observe_stream;
+/// use project_2::output::Point;
+/// use project_2::parser::{stream_program_from, Dialect, ParseOptions};
+///
+/// let program = "LIN X0 Y0 Z0\nLIN X1 Y0 Z0\nREPEAT 2\n";
+/// let parse = ParseOptions::default();
+/// let mut last = None;
+/// let result = observe_stream(
+///     stream_program_from(program.as_bytes(), Dialect::Cmmd, &parse),
+///     &Default::default(),
+///     &mut |point: &Point| last = Some(point.position),
+/// );
+/// assert!(result.is_err());
+/// assert_eq!(last, Some((1.0, 0.0, 0.0)));
+/// ```
+pub fn observe_stream<O: Observer + ?Sized>(
+    motions: impl IntoIterator<Item = Result<Motion, CmmdError>>,
+    options: &InterpolationOptions,
+    observer: &mut O,
+) -> Result<(), CmmdError> {
+    let mut current_z = 0.0;
+    for (index, motion) in motions.into_iter().enumerate() {
+        match motion {
+            Ok(motion) => observe_motion(index, &motion, &mut current_z, options, observer),
+            Err(error) => {
+                observer.on_error(&error);
+                return Err(error);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trajectory::Trajectory;
+
+    // Record every event in order
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Observer for Recorder {
+        fn on_motion_start(&mut self, index: usize, _: &Motion) {
+            self.0.push(format!("start {}", index));
+        }
+        fn on_point(&mut self, point: &Point) {
+            self.0.push(format!("point {} {:?}", point.motion, point.time));
+        }
+        fn on_motion_end(&mut self, index: usize, _: &Motion) {
+            self.0.push(format!("end {}", index));
+        }
+        fn on_error(&mut self, error: &CmmdError) {
+            self.0.push(format!("error {}", error));
+        }
+    }
+
+    /// Test that each motion's points come between its start and end, timed or not.
+    #[test]
+    fn test_events() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(60.0)),
+            Motion::new_linear((1.0, 0.0, 0.0), (1.0, 1.0, 0.0)).with_feed(Some(60.0)),
+        ];
+        let options = InterpolationOptions { linear_step: 1.0, ..Default::default() };
+        let mut recorder = Recorder::default();
+        Trajectory::new(&motions, &options).observe(&mut recorder);
+        let points = Trajectory::new(&motions, &options).points().count();
+        assert_eq!(recorder.0.len(), points + 4);
+        assert_eq!(recorder.0[..2], ["start 0", "point 0 None"]);
+        assert_eq!(recorder.0.iter().position(|event| event == "end 0"), Some(4));
+        assert_eq!(recorder.0.last().unwrap(), "end 1");
+
+        let mut recorder = Recorder::default();
+        Trajectory::new(&motions, &options).observe_timed(&mut recorder).unwrap();
+        assert_eq!(recorder.0[recorder.0.len() - 2], "point 1 Some(2.0)");
+    }
+
+    /// Test that the observer hears about the error that stops interpolation, after the motions before it.
+    #[test]
+    fn test_error() {
+        let motions = vec![
+            Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)),
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)),
+        ];
+        let options = InterpolationOptions::default();
+        let mut recorder = Recorder::default();
+        let result = Trajectory::new(&motions, &options).observe_timed(&mut recorder);
+        assert!(matches!(result, Err(CmmdError::MissingFeedRate { motion: 1 })));
+        assert!(recorder.0.contains(&String::from("end 0")));
+        assert!(recorder.0.last().unwrap().starts_with("error "));
+        assert!(!recorder.0.contains(&String::from("start 1")));
+
+        let mut recorder = Recorder::default();
+        let stream = motions.into_iter().map(Ok).chain([Err(CmmdError::MissingFeedRate { motion: 2 })]);
+        assert!(observe_stream(stream, &options, &mut recorder).is_err());
+        assert_eq!(recorder.0.iter().filter(|event| event.starts_with("end ")).count(), 2);
+    }
+}
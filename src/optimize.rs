@@ -0,0 +1,946 @@
+//! Path optimization passes
+//!
+//! These passes rewrite a program's motions into a path that runs better on
+//! a real machine while staying close to what was programmed. They work on
+//! parsed motions, so they apply to `.cmmd` and G-code programs alike.
+//!
+//! - [`simplify`] collapses runs of nearly collinear straight cuts, such as
+//!   the micro-segments CAM exports are full of, into fewer longer ones
+//! - [`fit_arcs`] replaces runs of straight cuts that follow a circle with a
+//!   single arc
+//! - [`fillet_corners`] rounds off the sharp corner between two straight
+//!   cuts with a small tangent arc, so the machine doesn't have to stop there
+//! - [`reorder`] cuts the separate parts of a program in the order that
+//!   leaves the least rapid travel between them
+//! - [`safe_travel`] lifts the tool to a safe height between parts that
+//!   aren't connected, instead of dragging it through the work
+
+use std::f64::consts::PI;
+
+use crate::motion::{LinearMotion, Motion};
+use crate::transform::{approach, ends};
+
+// Distances and angles below this are treated as zero
+const EPSILON: f64 = 1e-9;
+
+type Point = (f64, f64, f64);
+
+/// How large the arcs that round off corners are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Blend {
+    /// Every corner gets an arc of this radius
+    Radius(f64),
+    /// Each arc is as large as it can be while passing no further than this
+    /// from the programmed corner, so shallow corners get larger arcs
+    Deviation(f64),
+}
+
+// Direction and length of a line in XY
+fn direction(line: &LinearMotion) -> ((f64, f64), f64) {
+    let (dx, dy) = (line.end.0 - line.start.0, line.end.1 - line.start.1);
+    let length = dx.hypot(dy);
+    if length < EPSILON {
+        ((0.0, 0.0), 0.0)
+    } else {
+        ((dx / length, dy / length), length)
+    }
+}
+
+// Work out the fillet for the corner where `before` meets `after`
+//
+// Returns the point `before` now ends at, the arc, and the point `after` now
+// starts at, or `None` if the corner can't or needn't be rounded.
+fn fillet(before: &LinearMotion, after: &LinearMotion, blend: Blend) -> Option<(Point, Motion, Point)> {
+    // Only cuts that meet and lie flat at the same height are rounded
+    let corner = before.end;
+    let flat = [before.start.2, after.start.2, after.end.2].iter().all(|z| (z - corner.2).abs() < EPSILON);
+    let meet = (after.start.0 - corner.0).hypot(after.start.1 - corner.1) < EPSILON;
+    if before.rapid || after.rapid || !flat || !meet {
+        return None;
+    }
+    let ((d1, length1), (d2, length2)) = (direction(before), direction(after));
+    if length1 < EPSILON || length2 < EPSILON {
+        return None;
+    }
+
+    // How far the path turns; straight runs and complete reversals stay as they are
+    let cross = d1.0 * d2.1 - d1.1 * d2.0;
+    let turn = cross.atan2(d1.0 * d2.0 + d1.1 * d2.1);
+    if turn.abs() < 1e-6 || turn.abs() > PI - 1e-6 {
+        return None;
+    }
+    let half = turn.abs() / 2.0;
+    let mut radius = match blend {
+        Blend::Radius(radius) => radius,
+        // The arc's middle is radius·(1/cos(half) - 1) from the corner
+        Blend::Deviation(deviation) => deviation * half.cos() / (1.0 - half.cos()),
+    };
+
+    // The arc touches each line this far from the corner, which can use up at
+    // most what is left of the first line and half of the second, leaving the
+    // other half for the next corner
+    let mut setback = radius * half.tan();
+    let room = length1.min(length2 / 2.0);
+    if setback > room {
+        setback = room;
+        radius = setback / half.tan();
+    }
+    if radius < EPSILON {
+        return None;
+    }
+
+    let from = (corner.0 - d1.0 * setback, corner.1 - d1.1 * setback, corner.2);
+    let to = (corner.0 + d2.0 * setback, corner.1 + d2.1 * setback, corner.2);
+    // The center lies to the side the path turns towards
+    let side = if cross > 0.0 { 1.0 } else { -1.0 };
+    let offset = (-d1.1 * radius * side, d1.0 * radius * side);
+    let arc = Motion::new_arc_from_offset(from, to, offset, cross < 0.0)
+        .with_feed(after.feed)
+        .with_power(after.power);
+    let arc = match after.line {
+        Some(line) => arc.with_line(line),
+        None => arc,
+    };
+    Some((from, arc, to))
+}
+
+/// Function to round off the corners between straight cuts
+///
+/// Wherever two `LIN` moves meet at an angle in the XY plane, both are
+/// shortened and an arc tangent to both is inserted between them. Machines
+/// can run through the arc at speed instead of stopping at the corner. Arcs
+/// are shrunk where the lines are too short for them: an arc uses up at most
+/// what is left of the line before it and half of the line after it. Rapid
+/// moves, moves that change Z and corners between arcs are left as they are.
+/// The arcs take the feed rate, power and line of the motion after the
+/// corner.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `blend` - How large the arcs are
+///
+/// # Returns
+///
+/// The motions with corners rounded off, which may be more than were given.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::optimize::{fillet_corners, Blend};
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+///     Motion::new_linear((10.0, 0.0, 0.0), (10.0, 10.0, 0.0)),
+/// ];
+/// let rounded = fillet_corners(&motions, Blend::Radius(2.0));
+/// assert_eq!(rounded.len(), 3);
+/// let Motion::Linear(first) = &rounded[0] else { unreachable!() };
+/// assert_eq!(first.end, (8.0, 0.0, 0.0));
+/// ```
+pub fn fillet_corners(motions: &[Motion], blend: Blend) -> Vec<Motion> {
+    let mut rounded: Vec<Motion> = Vec::with_capacity(motions.len());
+    for motion in motions {
+        let mut motion = motion.clone();
+        if let (Some(Motion::Linear(before)), Motion::Linear(after)) = (rounded.last_mut(), &mut motion) {
+            // Corners where the rotary axes turn or the extruder moves are left sharp, since arcs can't do either
+            let turning = turns(before) || turns(after);
+            if let Some((end, arc, start)) = fillet(before, after, blend).filter(|_| !turning) {
+                before.end = end;
+                after.start = start;
+                // A line used up entirely by fillets is dropped
+                if direction(before).1 < EPSILON {
+                    rounded.pop();
+                }
+                rounded.push(arc);
+            }
+        }
+        rounded.push(motion);
+    }
+    rounded
+}
+
+// Distance from `point` to the segment from `start` to `end`
+fn segment_distance(point: Point, start: Point, end: Point) -> f64 {
+    let along = (end.0 - start.0, end.1 - start.1, end.2 - start.2);
+    let offset = (point.0 - start.0, point.1 - start.1, point.2 - start.2);
+    let length = along.0 * along.0 + along.1 * along.1 + along.2 * along.2;
+    // Project onto the segment, staying between its ends
+    let t = if length < EPSILON * EPSILON {
+        0.0
+    } else {
+        ((offset.0 * along.0 + offset.1 * along.1 + offset.2 * along.2) / length).clamp(0.0, 1.0)
+    };
+    let gap = (offset.0 - along.0 * t, offset.1 - along.1 * t, offset.2 - along.2 * t);
+    (gap.0 * gap.0 + gap.1 * gap.1 + gap.2 * gap.2).sqrt()
+}
+
+// Whether a line turns the rotary axes or moves the extruder on the way
+fn turns(line: &LinearMotion) -> bool {
+    line.rotary.is_some_and(|(from, to)| from != to) || line.extruded() != 0.0
+}
+
+// The points of the run of straight cuts starting at motion `start`
+//
+// A run goes on while the lines connect and have the same feed rate, power
+// and rotary angles; point i is where motion `start + i` begins. Returns
+// `None` if the motion isn't a straight cut, or turns the rotary axes or
+// moves the extruder.
+fn linear_run(motions: &[Motion], start: usize) -> Option<Vec<Point>> {
+    let Some(Motion::Linear(first)) = motions.get(start) else {
+        return None;
+    };
+    if first.rapid || turns(first) {
+        return None;
+    }
+    let mut points = vec![first.start, first.end];
+    while let Some(Motion::Linear(next)) = motions.get(start + points.len() - 1) {
+        let previous = points[points.len() - 1];
+        let gap = ((next.start.0 - previous.0).powi(2)
+            + (next.start.1 - previous.1).powi(2)
+            + (next.start.2 - previous.2).powi(2))
+        .sqrt();
+        let same = next.feed == first.feed && next.power == first.power && next.rotary == first.rotary;
+        if next.rapid || gap > EPSILON || !same {
+            break;
+        }
+        points.push(next.end);
+    }
+    Some(points)
+}
+
+// A straight cut between two points, cut the way `original` is
+fn retrace(original: &Motion, start: Point, end: Point) -> Motion {
+    let Motion::Linear(original) = original else {
+        unreachable!("runs only hold linear motions")
+    };
+    Motion::Linear(LinearMotion {
+        start,
+        end,
+        ..original.clone()
+    })
+}
+
+// Indices of the points Douglas–Peucker keeps from a chain
+fn douglas_peucker(points: &[Point], tolerance: f64) -> Vec<usize> {
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    // Spans still to check, kept on a stack so long chains can't overflow the call stack
+    let mut spans = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = spans.pop() {
+        let furthest = (first + 1..last)
+            .map(|i| (i, segment_distance(points[i], points[first], points[last])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = furthest {
+            if distance > tolerance {
+                keep[i] = true;
+                spans.push((first, i));
+                spans.push((i, last));
+            }
+        }
+    }
+    (0..points.len()).filter(|&i| keep[i]).collect()
+}
+
+/// Function to collapse runs of nearly collinear straight cuts
+///
+/// A run is a chain of `LIN` moves, each starting where the last one ended,
+/// with the same feed rate and power. Each run is simplified with the
+/// Douglas–Peucker algorithm: only the points needed to stay within
+/// `tolerance` of every original point are kept, and straight cuts join
+/// them. Every kept line takes the feed rate, power and line number of the
+/// first motion it replaces. Rapid moves, arcs and curves are left as they
+/// are and break runs.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `tolerance` - How far the simplified path may stray from the original
+///
+/// # Returns
+///
+/// The simplified motions, never more than were given.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::optimize::simplify;
+///
+/// // Ten tiny steps along a slightly wobbly line
+/// let points: Vec<_> = (0..=10).map(|i| (i as f64, if i % 2 == 0 { 0.0 } else { 0.001 }, 0.0)).collect();
+/// let motions: Vec<_> = points.windows(2).map(|pair| Motion::new_linear(pair[0], pair[1])).collect();
+/// let simplified = simplify(&motions, 0.01);
+/// assert_eq!(simplified, vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0))]);
+/// ```
+pub fn simplify(motions: &[Motion], tolerance: f64) -> Vec<Motion> {
+    let mut simplified = Vec::with_capacity(motions.len());
+    let mut start = 0;
+    while start < motions.len() {
+        let Some(points) = linear_run(motions, start) else {
+            simplified.push(motions[start].clone());
+            start += 1;
+            continue;
+        };
+        let kept = douglas_peucker(&points, tolerance);
+        for pair in kept.windows(2) {
+            simplified.push(retrace(&motions[start + pair[0]], points[pair[0]], points[pair[1]]));
+        }
+        start += points.len() - 1;
+    }
+    simplified
+}
+
+// The circle through three points in XY, as its center and radius
+fn circumcircle(a: Point, b: Point, c: Point) -> Option<((f64, f64), f64)> {
+    let d = 2.0 * (a.0 * (b.1 - c.1) + b.0 * (c.1 - a.1) + c.0 * (a.1 - b.1));
+    if d.abs() < EPSILON {
+        return None;
+    }
+    let (a2, b2, c2) = (a.0 * a.0 + a.1 * a.1, b.0 * b.0 + b.1 * b.1, c.0 * c.0 + c.1 * c.1);
+    let x = (a2 * (b.1 - c.1) + b2 * (c.1 - a.1) + c2 * (a.1 - b.1)) / d;
+    let y = (a2 * (c.0 - b.0) + b2 * (a.0 - c.0) + c2 * (b.0 - a.0)) / d;
+    Some(((x, y), (a.0 - x).hypot(a.1 - y)))
+}
+
+// The arc a chain of points follows within `tolerance`, as its center and
+// whether it runs clockwise, or `None` if they don't follow one
+fn fit_arc(points: &[Point], tolerance: f64) -> Option<((f64, f64), bool)> {
+    let (first, middle, last) = (points[0], points[points.len() / 2], points[points.len() - 1]);
+    // Arcs are flat, and chains that are practically straight are better left as lines
+    if points.iter().any(|point| (point.2 - first.2).abs() > EPSILON)
+        || segment_distance(middle, first, last) <= tolerance
+    {
+        return None;
+    }
+    let (center, radius) = circumcircle(first, middle, last)?;
+
+    // Every point, and the middle of every line between them, has to be close to the circle
+    let off = |x: f64, y: f64| ((x - center.0).hypot(y - center.1) - radius).abs() > tolerance;
+    if points.iter().any(|point| off(point.0, point.1))
+        || points.windows(2).any(|pair| off((pair[0].0 + pair[1].0) / 2.0, (pair[0].1 + pair[1].1) / 2.0))
+    {
+        return None;
+    }
+
+    // And the chain has to run around the center one way, less than a full turn
+    let mut sweep: f64 = 0.0;
+    for pair in points.windows(2) {
+        let angle = |point: Point| (point.1 - center.1).atan2(point.0 - center.0);
+        let step = (angle(pair[1]) - angle(pair[0]) + PI).rem_euclid(2.0 * PI) - PI;
+        if step * sweep < 0.0 {
+            return None;
+        }
+        sweep += step;
+    }
+    if sweep.abs() >= 2.0 * PI - 1e-6 {
+        return None;
+    }
+    Some((center, sweep < 0.0))
+}
+
+/// Function to replace runs of straight cuts that follow a circle with arcs
+///
+/// Runs of `LIN` moves are found the same way [`simplify`] finds them. Going
+/// along each run, the longest stretch of at least three lines whose points,
+/// and the middles of the lines between them, all lie within `tolerance` of
+/// one circle becomes a single arc. The arc takes the feed rate, power and
+/// line number of the first line it replaces. Stretches that are practically
+/// straight, change Z, or wind a full turn or more stay as lines.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `tolerance` - How far the lines may be from the arc that replaces them
+///
+/// # Returns
+///
+/// The motions with arcs in place of the lines they replace.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::optimize::fit_arcs;
+///
+/// // A half circle of radius 10 drawn with 18 lines
+/// let points: Vec<_> = (0..=18)
+///     .map(|i| {
+///         let angle = (i as f64 * 10.0).to_radians();
+///         (10.0 * angle.cos(), 10.0 * angle.sin(), 0.0)
+///     })
+///     .collect();
+/// let motions: Vec<_> = points.windows(2).map(|pair| Motion::new_linear(pair[0], pair[1])).collect();
+/// let fitted = fit_arcs(&motions, 0.05);
+/// let [Motion::Rotational(arc)] = &fitted[..] else { panic!("expected one arc") };
+/// assert!((arc.radius - 10.0).abs() < 1e-9);
+/// ```
+pub fn fit_arcs(motions: &[Motion], tolerance: f64) -> Vec<Motion> {
+    let mut fitted = Vec::with_capacity(motions.len());
+    let mut start = 0;
+    while start < motions.len() {
+        let Some(points) = linear_run(motions, start) else {
+            fitted.push(motions[start].clone());
+            start += 1;
+            continue;
+        };
+
+        let mut i = 0;
+        while i + 1 < points.len() {
+            // Grow the arc a line at a time for as long as it fits
+            let mut arc = None;
+            for j in i + 3..points.len() {
+                match fit_arc(&points[i..=j], tolerance) {
+                    Some(fit) => arc = Some((j, fit)),
+                    None => break,
+                }
+            }
+            let original = &motions[start + i];
+            match arc {
+                Some((j, (center, clockwise))) => {
+                    let from = points[i];
+                    let offset = (center.0 - from.0, center.1 - from.1);
+                    let motion = Motion::new_arc_from_offset(from, points[j], offset, clockwise)
+                        .with_feed(original.feed())
+                        .with_power(original.power());
+                    fitted.push(match original.line() {
+                        Some(line) => motion.with_line(line),
+                        None => motion,
+                    });
+                    i = j;
+                }
+                None => {
+                    fitted.push(retrace(original, points[i], points[i + 1]));
+                    i += 1;
+                }
+            }
+        }
+        start += points.len() - 1;
+    }
+    fitted
+}
+
+/// How much [`reorder`] cut down the rapid travel of a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reordering {
+    /// How many separate cutting sequences there were to put in order
+    pub sequences: usize,
+    /// Total length of the rapid moves as programmed
+    pub before: f64,
+    /// Total length of the rapid moves in the new order
+    pub after: f64,
+}
+
+// A run of cuts between two rapid moves, and where it starts and ends
+struct Sequence {
+    motions: Vec<Motion>,
+    start: Point,
+    end: Point,
+}
+
+// Distance between two points in XY
+fn xy_distance(a: Point, b: Point) -> f64 {
+    (b.0 - a.0).hypot(b.1 - a.1)
+}
+
+// The order to cut the sequences in, going from `from` and finishing at `to`
+//
+// The tool rises to the same height between every two sequences, so only the
+// distance across in XY depends on the order. Starting with the nearest
+// sequence each time, single sequences are then moved to wherever they
+// shorten the trip most, until none does.
+fn visit(sequences: &[Sequence], from: Point, to: Option<Point>) -> Vec<usize> {
+    let mut order = Vec::with_capacity(sequences.len());
+    let mut left: Vec<usize> = (0..sequences.len()).collect();
+    let mut at = from;
+    while !left.is_empty() {
+        let nearest = (0..left.len())
+            .min_by(|&a, &b| {
+                let distance = |i: usize| xy_distance(at, sequences[left[i]].start);
+                distance(a).total_cmp(&distance(b))
+            })
+            .expect("there are sequences left");
+        let next = left.swap_remove(nearest);
+        at = sequences[next].end;
+        order.push(next);
+    }
+
+    // The distance across between two sequences, with `None` for the ends of the trip
+    let gap = |a: Option<usize>, b: Option<usize>| {
+        let from = a.map_or(from, |a| sequences[a].end);
+        match b.map(|b| sequences[b].start).or(to) {
+            Some(to) => xy_distance(from, to),
+            None => 0.0,
+        }
+    };
+    // Each pass is quadratic in the number of sequences, so give up after a few
+    for _ in 0..100 {
+        let mut improved = false;
+        for i in 0..order.len() {
+            let moved = order[i];
+            let (before, after) = (i.checked_sub(1).map(|i| order[i]), order.get(i + 1).copied());
+            let saved = gap(before, Some(moved)) + gap(Some(moved), after) - gap(before, after);
+            let mut rest = order.clone();
+            rest.remove(i);
+            let mut best = (None, saved - EPSILON);
+            for j in 0..=rest.len() {
+                let (before, after) = (j.checked_sub(1).map(|j| rest[j]), rest.get(j).copied());
+                let added = gap(before, Some(moved)) + gap(Some(moved), after) - gap(before, after);
+                if added < best.1 {
+                    best = (Some(j), added);
+                }
+            }
+            if let (Some(j), _) = best {
+                rest.insert(j, moved);
+                order = rest;
+                improved = true;
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+    order
+}
+
+/// Function to cut the separate parts of a program in the order with the least rapid travel
+///
+/// The cuts between two rapid moves make up a cutting sequence, such as one
+/// pocket or one hole. Sequences are cut whole and in the direction they were
+/// programmed, but the order they're cut in is chosen to make the distance
+/// travelled between them short, much like a travelling salesman's route:
+/// each time the nearest sequence is picked, then single sequences are moved
+/// to wherever in the order they save the most. The rapid moves between
+/// sequences are replaced by ones that rise to the highest Z any motion
+/// starts or ends at, cross over, and drop to where the next sequence starts,
+/// with its power. Rapid moves before the first cut are kept, and the program
+/// still finishes where it did if it ended with rapid moves. A flat arc at
+/// the start of a sequence is given the height it would have been cut at.
+///
+/// If no order is shorter than the programmed one, the program is left as it
+/// is. So are programs that change tools, whose parts must be cut with the
+/// tool they were programmed for.
+///
+/// # Returns
+///
+/// The reordered motions, and how long the rapid moves were before and after.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::optimize::reorder;
+///
+/// // Slots at X = 10, 30 and 20, each reached by lifting and crossing from the one before
+/// let mut motions = Vec::new();
+/// let mut at = (0.0, 0.0, 0.0);
+/// for x in [10.0, 30.0, 20.0] {
+///     motions.push(Motion::new_rapid(at, (at.0, at.1, 5.0)));
+///     motions.push(Motion::new_rapid((at.0, at.1, 5.0), (x, 0.0, 5.0)));
+///     motions.push(Motion::new_linear((x, 0.0, 5.0), (x, 0.0, -1.0)));
+///     motions.push(Motion::new_linear((x, 0.0, -1.0), (x, 5.0, -1.0)));
+///     at = (x, 5.0, -1.0);
+/// }
+/// let (reordered, reordering) = reorder(&motions);
+/// assert_eq!(reordering.sequences, 3);
+/// assert!(reordering.after < reordering.before);
+/// // After the first slot, up, across and straight into the one at X = 20
+/// assert_eq!(reordered[6], motions[10]);
+/// ```
+pub fn reorder(motions: &[Motion]) -> (Vec<Motion>, Reordering) {
+    // How far the rapid moves go, and the highest point of the program
+    let mut before = 0.0;
+    let mut clearance = f64::NEG_INFINITY;
+    let mut z = 0.0;
+    for motion in motions {
+        let (start, end) = ends(motion, z);
+        if motion.is_rapid() {
+            before += xy_distance(start, end).hypot(end.2 - start.2);
+        }
+        clearance = clearance.max(start.2).max(end.2);
+        z = end.2;
+    }
+
+    // Rapid moves before the first cut and after the last stay where they are
+    let first = motions.iter().position(|motion| !motion.is_rapid()).unwrap_or(motions.len());
+    let last = motions.iter().rposition(|motion| !motion.is_rapid()).map_or(first, |last| last + 1);
+    let mut z = 0.0;
+    let mut from = None;
+    let mut kept = 0.0;
+    for motion in &motions[..first] {
+        let (start, end) = ends(motion, z);
+        kept += xy_distance(start, end).hypot(end.2 - start.2);
+        from = Some(end);
+        z = end.2;
+    }
+
+    // Split the cuts between them at every rapid move
+    let mut sequences: Vec<Sequence> = Vec::new();
+    let mut cutting = false;
+    for motion in &motions[first..last] {
+        if motion.is_rapid() {
+            cutting = false;
+            z = ends(motion, z).1 .2;
+            continue;
+        }
+        let mut motion = motion.clone();
+        if !cutting {
+            // A flat arc takes its height from the motion before it, which may move
+            if let Motion::Rotational(rotational_motion) = &mut motion {
+                if rotational_motion.z.is_none() {
+                    rotational_motion.z = Some((z, z));
+                }
+            }
+        }
+        let (start, end) = ends(&motion, z);
+        if cutting {
+            let sequence = sequences.last_mut().expect("a sequence has been started");
+            sequence.motions.push(motion);
+            sequence.end = end;
+        } else {
+            sequences.push(Sequence { motions: vec![motion], start, end });
+            cutting = true;
+        }
+        z = end.2;
+    }
+    let to = motions[last..].last().map(|motion| ends(motion, z).1);
+
+    let unchanged = Reordering { sequences: sequences.len(), before, after: before };
+    if sequences.len() < 2 || motions.iter().any(Motion::is_event) {
+        return (motions.to_vec(), unchanged);
+    }
+    let from = from.unwrap_or(sequences[0].start);
+    let order = visit(&sequences, from, to);
+
+    let mut reordered = motions[..first].to_vec();
+    let mut at = from;
+    for &index in &order {
+        let sequence = &sequences[index];
+        let power = sequence.motions[0].power();
+        reordered.extend(approach(at, sequence.start, clearance).into_iter().map(|motion| motion.with_power(power)));
+        reordered.extend_from_slice(&sequence.motions);
+        at = sequence.end;
+    }
+    if let Some(to) = to {
+        let power = motions[motions.len() - 1].power();
+        reordered.extend(approach(at, to, clearance).into_iter().map(|motion| motion.with_power(power)));
+    }
+
+    // Rapid moves are straight, so their length doesn't depend on the height before them
+    let after = kept
+        + reordered[first..]
+            .iter()
+            .filter(|motion| motion.is_rapid())
+            .map(|motion| {
+                let (start, end) = ends(motion, 0.0);
+                xy_distance(start, end).hypot(end.2 - start.2)
+            })
+            .sum::<f64>();
+    if after >= before - EPSILON {
+        return (motions.to_vec(), unchanged);
+    }
+    (reordered, Reordering { after, ..unchanged })
+}
+
+/// Function to lift the tool over the work between parts of a program that aren't connected
+///
+/// Wherever a cut starts away from where the cut before it ended, the travel
+/// between them is replaced by rapid moves that retract to `safe_z`, cross
+/// over and plunge to where the next cut starts, with its power. The tool
+/// never drops below where it is on the way up, or below where it's going on
+/// the way across. Travel that already crosses at or above `safe_z` and
+/// arrives where the next cut starts is left alone, as are rapid moves before
+/// the first cut and after the last. Events during a replaced travel, such as
+/// tool changes, happen once the tool is up at the safe height.
+///
+/// # Returns
+///
+/// The motions with safe travel, and how many travels were replaced.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::optimize::safe_travel;
+///
+/// // Two slots, the second started straight from the end of the first
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, -1.0), (10.0, 0.0, -1.0)),
+///     Motion::new_linear((20.0, 0.0, -1.0), (30.0, 0.0, -1.0)),
+/// ];
+/// let (safe, replaced) = safe_travel(&motions, 5.0);
+/// assert_eq!(replaced, 1);
+/// assert_eq!(safe[1], Motion::new_rapid((10.0, 0.0, -1.0), (10.0, 0.0, 5.0)));
+/// assert_eq!(safe[2], Motion::new_rapid((10.0, 0.0, 5.0), (20.0, 0.0, 5.0)));
+/// assert_eq!(safe[3], Motion::new_rapid((20.0, 0.0, 5.0), (20.0, 0.0, -1.0)));
+/// ```
+pub fn safe_travel(motions: &[Motion], safe_z: f64) -> (Vec<Motion>, usize) {
+    let travel = |motion: &Motion| matches!(motion, Motion::Linear(LinearMotion { rapid: true, .. }));
+    let mut safe = Vec::with_capacity(motions.len());
+    let mut replaced = 0;
+    // Where the last cut ended, and the travel and events since
+    let mut cut_end: Option<Point> = None;
+    let mut pending: Vec<Motion> = Vec::new();
+    let mut z = 0.0;
+    for motion in motions {
+        if travel(motion) || motion.is_event() {
+            z = ends(motion, z).1 .2;
+            pending.push(motion.clone());
+            continue;
+        }
+        let (start, end) = ends(motion, z);
+        match cut_end {
+            Some(from)
+                if xy_distance(from, start).hypot(start.2 - from.2) > EPSILON
+                    && !crosses_above(from, start, &pending, safe_z) =>
+            {
+                let clearance = safe_z.max(from.2).max(start.2);
+                let power = motion.power();
+                let mut approach = approach(from, start, clearance).into_iter().map(|motion| motion.with_power(power));
+                // Up first, so events happen over the work
+                let top = (from.0, from.1, clearance);
+                if clearance - from.2 > EPSILON {
+                    safe.extend(approach.next());
+                }
+                safe.extend(pending.drain(..).filter(Motion::is_event).map(|event| at_position(event, top)));
+                safe.extend(approach);
+                replaced += 1;
+            }
+            _ => safe.append(&mut pending),
+        }
+        // A flat arc takes its height from the motion before it, which stays where it was
+        safe.push(motion.clone());
+        cut_end = Some(end);
+        z = end.2;
+    }
+    safe.append(&mut pending);
+    (safe, replaced)
+}
+
+// Whether the travel from `from` to `to` is a chain of rapid moves that only moves sideways at or above `safe_z`
+fn crosses_above(from: Point, to: Point, travel: &[Motion], safe_z: f64) -> bool {
+    let mut at = from;
+    for motion in travel.iter().filter(|motion| !motion.is_event()) {
+        let (start, end) = ends(motion, at.2);
+        let joined = xy_distance(at, start).hypot(start.2 - at.2) < EPSILON;
+        let sideways = xy_distance(start, end) > EPSILON;
+        if !joined || (sideways && start.2.min(end.2) < safe_z - EPSILON) {
+            return false;
+        }
+        at = end;
+    }
+    xy_distance(at, to).hypot(to.2 - at.2) < EPSILON
+}
+
+// An event moved to where the tool is when it happens
+fn at_position(mut event: Motion, at: Point) -> Motion {
+    match &mut event {
+        Motion::ToolChange(tool_change) => tool_change.position = at,
+        Motion::Aux(aux_command) => aux_command.position = at,
+        Motion::Pause(pause) => pause.position = at,
+        _ => {}
+    }
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::post::arc_move;
+
+    /// Test rounding the corners of a square with arcs of a given radius.
+    #[test]
+    fn test_square_corners() {
+        let corners = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let motions: Vec<Motion> = corners
+            .windows(2)
+            .map(|pair| Motion::new_linear((pair[0].0, pair[0].1, -1.0), (pair[1].0, pair[1].1, -1.0)))
+            .collect();
+        let rounded = fillet_corners(&motions, Blend::Radius(1.0));
+        assert_eq!(rounded.len(), 5);
+
+        // Left turns are counter-clockwise quarter circles around a point inside the square
+        let Motion::Rotational(arc) = &rounded[1] else { panic!("expected an arc") };
+        assert_eq!((arc.center, arc.radius), ((9.0, 1.0), 1.0));
+        let arc = arc_move(arc, -1.0);
+        assert!(!arc.clockwise && (arc.sweep - 90.0).abs() < 1e-9);
+        assert!((arc.end.0 - 10.0).abs() < 1e-9 && (arc.end.1 - 1.0).abs() < 1e-9);
+        let Motion::Linear(middle) = &rounded[2] else { panic!("expected a line") };
+        assert!((middle.start.1 - 1.0).abs() < 1e-9 && (middle.end.1 - 9.0).abs() < 1e-9);
+    }
+
+    /// Test that a deviation picks the radius, and short lines shrink the arc.
+    #[test]
+    fn test_deviation_and_short_lines() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, -10.0, 0.0)),
+        ];
+        let rounded = fillet_corners(&motions, Blend::Deviation(0.1));
+        let Motion::Rotational(arc) = &rounded[1] else { panic!("expected an arc") };
+        // The middle of the arc passes 0.1 from the corner
+        let middle = arc.radius - (10.0 - arc.center.0).hypot(0.0 - arc.center.1);
+        assert!((middle.abs() - 0.1).abs() < 1e-9);
+        assert!(arc_move(arc, 0.0).clockwise);
+
+        // Half of a 2 long line leaves room for an arc of radius 1 at most
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, 2.0, 0.0)),
+        ];
+        let rounded = fillet_corners(&motions, Blend::Radius(5.0));
+        let Motion::Rotational(arc) = &rounded[1] else { panic!("expected an arc") };
+        assert!((arc.radius - 1.0).abs() < 1e-9);
+
+        // Rapid moves keep their corners
+        let motions = vec![
+            Motion::new_rapid((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, 2.0, 0.0)),
+        ];
+        assert_eq!(fillet_corners(&motions, Blend::Radius(1.0)), motions);
+    }
+
+    /// Test fitting a clockwise arc between two lines, leaving the lines alone.
+    #[test]
+    fn test_fit_arcs() {
+        // Lead in along X, a clockwise quarter circle of radius 5 around (0, -5), then lead out
+        let mut points = vec![(-10.0, 0.0, 0.0)];
+        points.extend((0..=9).map(|i| {
+            let angle = (90.0 - i as f64 * 10.0).to_radians();
+            (5.0 * angle.cos(), -5.0 + 5.0 * angle.sin(), 0.0)
+        }));
+        points.push((5.0, -15.0, 0.0));
+        let motions: Vec<Motion> = points
+            .windows(2)
+            .map(|pair| Motion::new_linear(pair[0], pair[1]).with_feed(Some(500.0)))
+            .collect();
+
+        let fitted = fit_arcs(&motions, 0.05);
+        assert_eq!(fitted.len(), 3);
+        assert_eq!(fitted[0], motions[0]);
+        let Motion::Rotational(arc) = &fitted[1] else { panic!("expected an arc") };
+        assert!((arc.center.0).abs() < 1e-9 && (arc.center.1 + 5.0).abs() < 1e-9);
+        assert_eq!(arc.feed, Some(500.0));
+        let arc = arc_move(arc, 0.0);
+        assert!(arc.clockwise && (arc.sweep - 90.0).abs() < 1e-9);
+        assert_eq!(fitted[2], motions[10]);
+
+        // A tighter tolerance than the chords allow keeps every line
+        assert_eq!(fit_arcs(&motions, 0.001), motions);
+    }
+
+    /// Test that runs break at feed changes and rapids, and real corners are kept.
+    #[test]
+    fn test_simplify_runs() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((1.0, 0.0, 0.0), (2.0, 0.0, 0.0)).with_line(2),
+            Motion::new_linear((2.0, 0.0, 0.0), (2.0, 1.0, 0.0)).with_line(3),
+            Motion::new_linear((2.0, 1.0, 0.0), (2.0, 2.0, 0.0)).with_line(4),
+            // A different feed rate starts a new run
+            Motion::new_linear((2.0, 2.0, 0.0), (2.0, 3.0, 0.0)).with_feed(Some(100.0)),
+            Motion::new_linear((2.0, 3.0, 0.0), (2.0, 4.0, 0.0)).with_feed(Some(100.0)),
+            Motion::new_rapid((2.0, 4.0, 0.0), (2.0, 4.0, 5.0)),
+        ];
+        let simplified = simplify(&motions, 0.1);
+        assert_eq!(
+            simplified,
+            vec![
+                Motion::new_linear((0.0, 0.0, 0.0), (2.0, 0.0, 0.0)).with_line(1),
+                Motion::new_linear((2.0, 0.0, 0.0), (2.0, 2.0, 0.0)).with_line(3),
+                Motion::new_linear((2.0, 2.0, 0.0), (2.0, 4.0, 0.0)).with_feed(Some(100.0)),
+                Motion::new_rapid((2.0, 4.0, 0.0), (2.0, 4.0, 5.0)),
+            ]
+        );
+    }
+
+    /// Test that sequences are cut nearest first, over the top, finishing where the program did.
+    #[test]
+    fn test_reorder() {
+        // Slots at X = 30, 10 and 20, cut in that order, then back home
+        let slot = |x: f64| {
+            vec![
+                Motion::new_rapid((0.0, 0.0, 5.0), (x, 0.0, 5.0)),
+                Motion::new_linear((x, 0.0, 5.0), (x, 0.0, -1.0)).with_power(Some(1000.0)),
+                Motion::new_linear((x, 0.0, -1.0), (x, 4.0, -1.0)).with_power(Some(1000.0)),
+                Motion::new_rapid((x, 4.0, -1.0), (x, 4.0, 5.0)),
+            ]
+        };
+        let mut motions: Vec<Motion> = [30.0, 10.0, 20.0].into_iter().flat_map(slot).collect();
+        // The rapids between slots start where the last one ended
+        for i in [4, 8] {
+            let Motion::Linear(rapid) = &mut motions[i] else { unreachable!() };
+            rapid.start = (if i == 4 { 30.0 } else { 10.0 }, 4.0, 5.0);
+        }
+        motions.push(Motion::new_rapid((20.0, 4.0, 5.0), (0.0, 0.0, 5.0)));
+
+        let (reordered, reordering) = reorder(&motions);
+        assert_eq!(reordering.sequences, 3);
+        assert!(reordering.after < reordering.before - 1.0);
+
+        // Each slot is cut whole, the nearest one next: the rapid to the first slot is kept, so
+        // the trip starts there
+        let cuts: Vec<f64> = reordered
+            .iter()
+            .filter_map(|motion| match motion {
+                Motion::Linear(line) if !line.rapid && line.start.2 > 0.0 => Some(line.start.0),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cuts, vec![30.0, 20.0, 10.0]);
+
+        // Travel between slots goes over the top with the next slot's power, and ends back home
+        for motion in reordered.iter().skip(1).filter(|motion| motion.is_rapid()) {
+            let (start, end) = ends(motion, 0.0);
+            assert!(start.2 == 5.0 || end.2 == 5.0);
+        }
+        for pair in reordered[1..].windows(2).filter(|pair| pair[0].is_rapid() && !pair[1].is_rapid()) {
+            assert_eq!(pair[0].power(), Some(1000.0));
+        }
+        assert_eq!(ends(&reordered[reordered.len() - 1], 0.0).1, (0.0, 0.0, 5.0));
+
+        // Already in the best order, the program stays as it is
+        let (again, reordering) = reorder(&reordered);
+        assert_eq!(again, reordered);
+        assert_eq!(reordering.after, reordering.before);
+
+        // Changing tools between slots keeps them in order
+        motions.insert(4, Motion::new_tool_change(2, (30.0, 4.0, 5.0)));
+        assert_eq!(reorder(&motions).0, motions);
+    }
+
+    /// Test that travel through the work is lifted to the safe height, and travel above it left alone.
+    #[test]
+    fn test_safe_travel() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, -1.0), (10.0, 0.0, -1.0)).with_power(Some(500.0)),
+            // Dragged across at the cutting depth, changing tools on the way
+            Motion::new_rapid((10.0, 0.0, -1.0), (20.0, 0.0, -1.0)),
+            Motion::new_tool_change(2, (20.0, 0.0, -1.0)),
+            Motion::new_linear((20.0, 0.0, -1.0), (30.0, 0.0, -1.0)).with_power(Some(800.0)),
+            // Over the top already
+            Motion::new_rapid((30.0, 0.0, -1.0), (30.0, 0.0, 10.0)),
+            Motion::new_rapid((30.0, 0.0, 10.0), (40.0, 0.0, 10.0)),
+            Motion::new_rapid((40.0, 0.0, 10.0), (40.0, 0.0, -1.0)),
+            Motion::new_linear((40.0, 0.0, -1.0), (50.0, 0.0, -1.0)),
+        ];
+        let (safe, replaced) = safe_travel(&motions, 5.0);
+        assert_eq!(replaced, 1);
+        assert_eq!(
+            safe[1..5],
+            [
+                Motion::new_rapid((10.0, 0.0, -1.0), (10.0, 0.0, 5.0)).with_power(Some(800.0)),
+                Motion::new_tool_change(2, (10.0, 0.0, 5.0)),
+                Motion::new_rapid((10.0, 0.0, 5.0), (20.0, 0.0, 5.0)).with_power(Some(800.0)),
+                Motion::new_rapid((20.0, 0.0, 5.0), (20.0, 0.0, -1.0)).with_power(Some(800.0)),
+            ]
+        );
+        assert_eq!(safe[5..], motions[3..]);
+
+        // Cuts that carry on from each other need no travel
+        let connected = [motions[0].clone(), Motion::new_linear((10.0, 0.0, -1.0), (10.0, 5.0, -1.0))];
+        assert_eq!(safe_travel(&connected, 5.0), (connected.to_vec(), 0));
+    }
+}
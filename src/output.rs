@@ -0,0 +1,262 @@
+//! Structured output formats
+//!
+//! Besides the plain text the CLI has always printed, programs can be written
+//! as JSON (one array) or JSON Lines (one object per line). Every source motion
+//! becomes a `"motion"` object, followed by a `"point"` object for each position
+//! it produced, tagged with the motion's index and source line.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::{Motion, Rotary};
+use crate::trajectory::{Dynamics, TimedPosition, Trajectory};
+
+/// Output formats the CLI can print
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// A single JSON array
+    Json,
+    /// One JSON object per line
+    JsonLines,
+    /// Packed little-endian samples, as written by [`write_binary`](crate::binary::write_binary)
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::JsonLines),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("Unknown format {} (expected text, json, jsonl or binary)", name)),
+        }
+    }
+}
+
+/// A single emitted position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Index of the motion that produced the position
+    pub motion: usize,
+    /// The (x, y, z) position
+    pub position: (f64, f64, f64),
+    /// Seconds since the start of the program, for timed output
+    pub time: Option<f64>,
+    /// Spindle speed or laser power at the position, if one was programmed
+    pub power: Option<f64>,
+    /// Rotary axis angles at the position, for motions that turn them
+    pub rotary: Option<Rotary>,
+    /// Extruder (E axis) position at the position, for motions that carry one
+    pub extrusion: Option<f64>,
+    /// Velocity and acceleration at the position, for timed output that asks for them
+    pub dynamics: Option<Dynamics>,
+}
+
+impl From<TimedPosition> for Point {
+    fn from(sample: TimedPosition) -> Self {
+        Point {
+            motion: sample.motion,
+            position: sample.position,
+            time: Some(sample.time),
+            power: sample.power,
+            rotary: sample.rotary,
+            extrusion: sample.extrusion,
+            dynamics: None,
+        }
+    }
+}
+
+/// Function to calculate the untimed positions of every motion of a program
+///
+/// Arcs without their own Z range are placed at the height the previous
+/// motion ended at.
+pub fn geometric_points(motions: &[Motion], options: &InterpolationOptions) -> Vec<Point> {
+    Trajectory::new(motions, options).points().collect()
+}
+
+// One object of the structured output
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<'a> {
+    Motion {
+        index: usize,
+        #[serde(flatten)]
+        motion: &'a Motion,
+    },
+    Point {
+        motion: usize,
+        line: Option<usize>,
+        position: (f64, f64, f64),
+        #[serde(skip_serializing_if = "Option::is_none")]
+        time: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        power: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rotary: Option<Rotary>,
+        #[serde(rename = "e", skip_serializing_if = "Option::is_none")]
+        extrusion: Option<f64>,
+        #[serde(flatten)]
+        dynamics: Option<Dynamics>,
+    },
+}
+
+// Build the records of one motion: the motion itself followed by its points
+fn motion_records<'a>(index: usize, motion: &'a Motion, points: impl IntoIterator<Item = Point>) -> Vec<Record<'a>> {
+    let mut records = vec![Record::Motion { index, motion }];
+    records.extend(points.into_iter().map(|point| Record::Point {
+        motion: index,
+        line: motion.line(),
+        position: point.position,
+        time: point.time,
+        power: point.power,
+        rotary: point.rotary,
+        extrusion: point.extrusion,
+        dynamics: point.dynamics,
+    }));
+    records
+}
+
+// Build the records for a program lazily, each motion followed by its points
+fn records<'a>(motions: &'a [Motion], points: impl IntoIterator<Item = Point>) -> impl Iterator<Item = Record<'a>> {
+    let mut points = points.into_iter().peekable();
+    motions.iter().enumerate().flat_map(move |(index, motion)| {
+        // The points of a motion follow it, so collect just those
+        let mut own = Vec::new();
+        while let Some(point) = points.next_if(|point| point.motion == index) {
+            own.push(point);
+        }
+        motion_records(index, motion, own)
+    })
+}
+
+/// Function to write one motion and its points as JSON Lines
+///
+/// For output produced a motion at a time: writing each motion of a program
+/// this way in turn gives the same output as [`write_json`] with
+/// `Format::JsonLines`.
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the output
+/// * `index` - Index of the motion in its program, counted from zero
+/// * `motion` - The motion
+/// * `points` - The points the motion produced, in order
+pub fn write_json_motion<W: Write>(
+    mut writer: W,
+    index: usize,
+    motion: &Motion,
+    points: impl IntoIterator<Item = Point>,
+) -> io::Result<()> {
+    for record in motion_records(index, motion, points) {
+        serde_json::to_writer(&mut writer, &record)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Function to write a program and its points as JSON or JSON Lines
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the output
+/// * `motions` - The motions of the program
+/// * `points` - The points the motions produced, in order. JSON Lines are
+///   written as the points arrive, so they can come straight from
+///   [`Trajectory::points`]
+/// * `format` - `Format::Json` or `Format::JsonLines`; any other format is
+///   written as JSON Lines
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::output::{geometric_points, write_json, Format};
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_line(1)];
+/// let points = geometric_points(&motions, &Default::default());
+/// let mut output = Vec::new();
+/// write_json(&mut output, &motions, points, Format::JsonLines).unwrap();
+/// let output = String::from_utf8(output).unwrap();
+/// assert!(output.starts_with(r#"{"type":"motion","index":0,"kind":"linear""#));
+/// assert_eq!(output.lines().count(), 4);
+/// ```
+pub fn write_json<W: Write>(
+    mut writer: W,
+    motions: &[Motion],
+    points: impl IntoIterator<Item = Point>,
+    format: Format,
+) -> io::Result<()> {
+    let records = records(motions, points);
+    if format == Format::Json {
+        // A single array has to be built in full before it is written
+        serde_json::to_writer_pretty(&mut writer, &records.collect::<Vec<_>>())?;
+        writeln!(writer)?;
+    } else {
+        for record in records {
+            serde_json::to_writer(&mut writer, &record)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    /// Test that every record names the motion and line that produced it.
+    #[test]
+    fn test_json_records() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_line(2),
+            Motion::new_rotational((0.0, 0.0), 1.0, 0.0, 90.0).with_line(3),
+        ];
+        let points = geometric_points(&motions, &InterpolationOptions::default());
+        let mut output = Vec::new();
+        write_json(&mut output, &motions, points.iter().copied(), Format::Json).unwrap();
+        let records: Vec<Value> = serde_json::from_slice(&output).unwrap();
+
+        assert_eq!(records.len(), motions.len() + points.len());
+        assert_eq!(records[0]["type"], "motion");
+        assert_eq!(records[0]["end"], serde_json::json!([1.0, 0.0, 0.0]));
+        assert_eq!(records[1]["type"], "point");
+        assert_eq!(records[1]["line"], 2);
+        assert!(records[1].get("time").is_none());
+        assert!(records[1].get("power").is_none());
+
+        let arc = records.iter().position(|record| record["kind"] == "rotational").unwrap();
+        assert_eq!(records[arc]["index"], 1);
+        assert_eq!(records[arc + 1]["motion"], 1);
+        assert_eq!(records[arc + 1]["line"], 3);
+    }
+
+    /// Test that points carry the power of their motion.
+    #[test]
+    fn test_point_power() {
+        let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_power(Some(255.0))];
+        let points = geometric_points(&motions, &InterpolationOptions::default());
+        assert!(points.iter().all(|point| point.power == Some(255.0)));
+
+        let mut output = Vec::new();
+        write_json(&mut output, &motions, points, Format::JsonLines).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.lines().nth(1).unwrap().ends_with(r#""power":255.0}"#));
+    }
+
+    /// Test parsing format names.
+    #[test]
+    fn test_format_names() {
+        assert_eq!("jsonl".parse(), Ok(Format::JsonLines));
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
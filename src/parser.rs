@@ -0,0 +1,2974 @@
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::blocks;
+use crate::commands::{Commands, Context};
+use crate::compensation::{self, Compensation, Side};
+use crate::cycles::{self, Clearing, Pocket, Thread};
+use crate::drawing::DrawingOptions;
+use crate::error::{CmmdError, ParseError, Span};
+use crate::gcode;
+use crate::hpgl;
+use crate::machine::MachineState;
+use crate::motion::{LinearMotion, Motion, OrientedMotion, Plane, Rotary};
+use crate::program::Program;
+use crate::source::{self, SourceLine};
+use crate::tools::{Tool, ToolTable};
+use crate::transform::{self, Axis, Transform};
+
+/// Input languages the interpreter understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// The native `.cmmd` command language
+    Cmmd,
+    /// RS-274 G-code (`.gcode`, `.nc`, `.tap`)
+    Gcode,
+    /// A [`Program`](crate::program::Program) written as JSON (`.json`), already in machine coordinates and millimeters
+    Json,
+    /// An HP-GL pen plot (`.hpgl`, `.plt`), drawn at Z 0 with the pen up as rapids
+    Hpgl,
+}
+
+impl Dialect {
+    /// Detect the dialect of a program from its file extension
+    ///
+    /// Uses the default [`Extensions`] list. Returns `None` for extensions
+    /// that aren't recognized.
+    pub fn from_path(file_path: &str) -> Option<Dialect> {
+        Extensions::default().dialect_of(file_path)
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "cmmd" => Ok(Dialect::Cmmd),
+            "gcode" => Ok(Dialect::Gcode),
+            "json" => Ok(Dialect::Json),
+            "hpgl" => Ok(Dialect::Hpgl),
+            _ => Err(format!("Unknown dialect {}, expected cmmd, gcode, json or hpgl", name)),
+        }
+    }
+}
+
+/// File extensions accepted as programs, and the dialect each one is read as
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::{Dialect, Extensions};
+///
+/// let mut extensions = Extensions::default();
+/// assert_eq!(extensions.dialect_of("part.NC"), Some(Dialect::Gcode));
+/// assert_eq!(extensions.dialect_of("part.prg"), None);
+///
+/// extensions.insert("prg", Dialect::Gcode);
+/// assert_eq!(extensions.dialect_of("part.prg"), Some(Dialect::Gcode));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extensions {
+    // Lowercase extensions without the dot, in the order they were added
+    entries: Vec<(String, Dialect)>,
+}
+
+impl Default for Extensions {
+    fn default() -> Self {
+        let mut extensions = Extensions { entries: Vec::new() };
+        for extension in ["cmmd", "txt"] {
+            extensions.insert(extension, Dialect::Cmmd);
+        }
+        for extension in ["gcode", "nc", "ngc", "tap"] {
+            extensions.insert(extension, Dialect::Gcode);
+        }
+        extensions.insert("json", Dialect::Json);
+        for extension in ["hpgl", "plt"] {
+            extensions.insert(extension, Dialect::Hpgl);
+        }
+        extensions
+    }
+}
+
+impl Extensions {
+    /// Accept an extension (with or without the dot), replacing its dialect if already listed
+    pub fn insert(&mut self, extension: &str, dialect: Dialect) {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        match self.entries.iter_mut().find(|(known, _)| *known == extension) {
+            Some(entry) => entry.1 = dialect,
+            None => self.entries.push((extension, dialect)),
+        }
+    }
+
+    /// Dialect of a file, or `None` if its extension isn't accepted
+    pub fn dialect_of(&self, file_path: &str) -> Option<Dialect> {
+        let extension = Path::new(file_path).extension()?.to_str()?.to_ascii_lowercase();
+        self.entries
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, dialect)| *dialect)
+    }
+
+    /// The accepted extensions, for messages
+    pub fn names(&self) -> Vec<&str> {
+        self.entries.iter().map(|(extension, _)| extension.as_str()).collect()
+    }
+}
+
+/// Length units a program can be written in
+///
+/// Whatever a program uses, parsed motions are always in millimeters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Units {
+    /// Millimeters (`UNITS MM`, like G21)
+    #[default]
+    #[serde(rename = "mm")]
+    Millimeters,
+    /// Inches (`UNITS INCH`, like G20)
+    #[serde(rename = "inch", alias = "in")]
+    Inches,
+}
+
+impl Units {
+    /// Millimeters per unit
+    pub fn scale(self) -> f64 {
+        match self {
+            Units::Millimeters => 1.0,
+            Units::Inches => 25.4,
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "mm" => Ok(Units::Millimeters),
+            "inch" | "in" => Ok(Units::Inches),
+            _ => Err(format!("Unknown units {}, expected mm or inch", name)),
+        }
+    }
+}
+
+/// Options controlling how programs are parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Reject unparsable numbers instead of treating them as 0.0
+    pub strict: bool,
+    /// Let `INCLUDE` read other files (on by default)
+    pub includes: bool,
+    /// Units the program is in until it says otherwise
+    pub units: Units,
+    /// Work offsets known before the program starts, by number, in millimeters
+    ///
+    /// Programs can add to these with `WCS` and pick one with `USE WCS`.
+    pub work_offsets: BTreeMap<u32, (f64, f64, f64)>,
+    /// Tools a program can pick with `TOOL`
+    pub tools: ToolTable,
+    /// Read programs for a lathe: X is a diameter, there is no Y, and arcs are in the XZ plane
+    pub lathe: bool,
+    /// Commands added by the library user, run for words the language doesn't know
+    pub commands: Commands,
+    /// Most memory, in bytes, a program's lines may take up once `REPEAT` and `CALL` are expanded
+    pub max_expansion: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strict: false,
+            includes: true,
+            units: Units::default(),
+            work_offsets: BTreeMap::new(),
+            tools: ToolTable::default(),
+            lathe: false,
+            commands: Commands::default(),
+            max_expansion: 1 << 28,
+        }
+    }
+}
+
+/// A whitespace-separated word of a command line along with its column span
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Token<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) start: usize,
+}
+
+impl Token<'_> {
+    // The one-letter prefix of the token (e.g. 'X' for "X5")
+    fn letter(&self) -> char {
+        self.text.chars().next().unwrap_or_default()
+    }
+
+    // Span covering this token
+    pub(crate) fn span(&self) -> Span {
+        Span(self.start..self.start + self.text.len())
+    }
+
+    // Parse the numeric value that follows the one-letter prefix (e.g. "X5")
+    //
+    // Outside of strict mode anything unparsable is read as 0.0.
+    fn value(&self, line_number: usize, options: &ParseOptions) -> Result<f64, ParseError> {
+        let mut chars = self.text.chars();
+        chars.next();
+        match chars.as_str().parse::<f64>() {
+            // Strict mode also turns down `inf`, `nan` and numbers too large to be finite
+            Ok(value) if !options.strict || value.is_finite() => Ok(value),
+            _ if !options.strict => Ok(0.0),
+            _ => Err(ParseError::InvalidNumber {
+                line: line_number,
+                span: self.span(),
+                text: self.text.to_string(),
+            }),
+        }
+    }
+}
+
+// Whether the text between parentheses is a coordinate tuple like `(10, -2.5, 0)`
+//
+// Tuples hold only numbers, commas and `_` placeholders, which comments
+// practically never do.
+fn is_tuple(inner: &str) -> bool {
+    inner.contains(',')
+        && inner
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_whitespace() || matches!(c, ',' | '.' | '-' | '+' | '_' | 'e' | 'E'))
+}
+
+// Blank out comments, keeping every other character in its column
+//
+// `#` and `;` comment out the rest of the line, and `( ... )` comments out
+// the text in between unless it is a coordinate tuple. Replacing comments
+// with spaces instead of removing them keeps the column spans of errors
+// pointing at the original text.
+pub(crate) fn strip_comments(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '#' | ';' => break,
+            '(' => {
+                // An unclosed parenthesis comments out the rest of the line
+                let Some(close) = rest.find(')') else {
+                    break;
+                };
+                if is_tuple(&rest[1..close]) {
+                    stripped.push_str(&rest[..=close]);
+                } else {
+                    // Keep the byte length so later columns don't move
+                    stripped.extend(std::iter::repeat_n(' ', close + 1));
+                }
+                rest = &rest[close + 1..];
+            }
+            _ => {
+                stripped.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    stripped
+}
+
+// Parse a coordinate tuple such as `(10, 20, 0)` into its numbers
+//
+// The parentheses are optional and the numbers may be separated by commas or
+// spaces. Returns the text of the first item that isn't a number on failure.
+fn parse_tuple(text: &str) -> Result<Vec<f64>, String> {
+    parse_partial_tuple(text)?
+        .into_iter()
+        .map(|number| number.ok_or_else(|| String::from("_")))
+        .collect()
+}
+
+// Parse a coordinate tuple that may leave coordinates out with `_`, such as `(_, 20, _)`
+fn parse_partial_tuple(text: &str) -> Result<Vec<Option<f64>>, String> {
+    text.trim()
+        .trim_start_matches('(')
+        .trim_end_matches(')')
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(|item| match item {
+            "_" => Ok(None),
+            _ => item.parse::<f64>().ok().filter(|number| number.is_finite()).map(Some).ok_or_else(|| item.to_string()),
+        })
+        .collect()
+}
+
+// Split a line into tokens, remembering where each one starts
+pub(crate) fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                tokens.push(Token { text: &line[s..i], start: s });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(Token { text: &line[s..], start: s });
+    }
+    tokens
+}
+
+// Make sure a command has the number of arguments it needs
+pub(crate) fn expect_arguments(line_number: usize, line: &str, tokens: &[Token], expected: usize) -> Result<(), ParseError> {
+    let found = tokens.len() - 1;
+    if found < expected {
+        return Err(ParseError::MissingArguments {
+            line: line_number,
+            span: Span(tokens[0].start..line.trim_end().len()),
+            command: tokens[0].text.to_string(),
+            expected,
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// How the coordinates of a motion's end point are read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DistanceMode {
+    /// Coordinates are positions (`ABS`, like G90)
+    #[default]
+    Absolute,
+    /// Coordinates are offsets from the current position (`INC`, like G91)
+    Incremental,
+}
+
+// Parse the number of a work offset or tool, which has to be a whole number
+fn whole_number(line_number: usize, token: &Token) -> Result<u32, ParseError> {
+    token.text.parse().map_err(|_| ParseError::InvalidNumber {
+        line: line_number,
+        span: token.span(),
+        text: token.text.to_string(),
+    })
+}
+
+// Parse a plain number such as the angle of `ROTATE 30`, which can't be `inf` or `nan`
+fn number(line_number: usize, token: &Token) -> Result<f64, ParseError> {
+    let number = token.text.parse::<f64>().ok().filter(|number| number.is_finite());
+    number.ok_or_else(|| ParseError::InvalidNumber {
+        line: line_number,
+        span: token.span(),
+        text: token.text.to_string(),
+    })
+}
+
+// Read the coordinate tuple starting at `parts[index]`, which may hold spaces
+//
+// Returns its numbers and the index of the first token after it.
+fn tuple_at(line_number: usize, line: &str, parts: &[Token], index: usize) -> Result<(Vec<f64>, usize), ParseError> {
+    read_tuple(line_number, line, parts, index, parse_tuple)
+}
+
+// Read the tuple starting at `parts[index]` like `tuple_at`, with `_` for coordinates left out
+fn partial_tuple_at(
+    line_number: usize,
+    line: &str,
+    parts: &[Token],
+    index: usize,
+) -> Result<(Vec<Option<f64>>, usize), ParseError> {
+    read_tuple(line_number, line, parts, index, parse_partial_tuple)
+}
+
+// Find where the tuple starting at `parts[index]` closes and parse its text with `parse`
+fn read_tuple<T>(
+    line_number: usize,
+    line: &str,
+    parts: &[Token],
+    index: usize,
+    parse: fn(&str) -> Result<Vec<T>, String>,
+) -> Result<(Vec<T>, usize), ParseError> {
+    let start = parts[index].start;
+    let close = line[start..].find(')').map_or(line.len(), |close| start + close + 1);
+    let next = index + parts[index..].iter().take_while(|token| token.start < close).count();
+    let numbers = parse(&line[start..close]).map_err(|text| ParseError::InvalidNumber {
+        line: line_number,
+        span: Span(start..close),
+        text,
+    })?;
+    Ok((numbers, next))
+}
+
+// The signed sweep of an arc from one angle to another, going clockwise or counter-clockwise, in degrees
+//
+// A stop angle more than a turn past the start in the direction of travel adds whole turns, and one on the
+// start, or whole turns behind it, makes a full circle.
+fn sweep_between(start_angle: f64, stop_angle: f64, clockwise: bool) -> f64 {
+    let ahead = if clockwise { start_angle - stop_angle } else { stop_angle - start_angle };
+    let ahead = match ahead.rem_euclid(360.0) {
+        _ if ahead > 0.0 => ahead,
+        0.0 => 360.0,
+        ahead => ahead,
+    };
+    if clockwise {
+        -ahead
+    } else {
+        ahead
+    }
+}
+
+// Motion commands that a line of bare coordinates repeats
+const REPEATABLE: [&str; 5] = ["LIN", "RAP", "CW", "CCW", "BEZ"];
+
+// Whether a line starting with this token is a line of bare coordinates: a tuple, or an X, Y or Z word
+fn is_coordinates(token: &Token) -> bool {
+    let mut chars = token.text.chars();
+    match chars.next() {
+        Some('(') => true,
+        Some('X' | 'Y' | 'Z') => chars.next().is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.')),
+        _ => false,
+    }
+}
+
+// Read the `ABOUT (x, y[, z])` point that may follow a transform command at `parts[index]`
+//
+// Returns the programmed coordinates, with Z left out if only X and Y were given.
+fn about_point(line_number: usize, line: &str, parts: &[Token], index: usize) -> Result<Option<Vec<f64>>, ParseError> {
+    let command = parts[0].text;
+    match parts.get(index) {
+        Some(token) if token.text == "ABOUT" => {
+            expect_arguments(line_number, line, parts, index + 1)?;
+            let (coordinates, next) = tuple_at(line_number, line, parts, index + 1)?;
+            if !matches!(coordinates.len(), 2 | 3) {
+                return Err(ParseError::MissingArguments {
+                    line: line_number,
+                    span: Span(parts[index + 1].start..line.trim_end().len()),
+                    command: command.to_string(),
+                    expected: 2,
+                    found: coordinates.len(),
+                });
+            }
+            match parts.get(next) {
+                Some(token) => Err(ParseError::UnexpectedWord {
+                    line: line_number,
+                    span: token.span(),
+                    command: command.to_string(),
+                    word: token.text.to_string(),
+                }),
+                None => Ok(Some(coordinates)),
+            }
+        }
+        Some(token) => Err(ParseError::UnexpectedWord {
+            line: line_number,
+            span: token.span(),
+            command: command.to_string(),
+            word: token.text.to_string(),
+        }),
+        None => Ok(None),
+    }
+}
+
+type Point = (f64, f64, f64);
+
+/// Interpreter state carried from one line to the next
+#[derive(Debug, Clone, Default)]
+pub(crate) struct State {
+    /// End point of the previous motion, where the next one starts
+    pub(crate) position: (f64, f64, f64),
+    /// Feed rate and power set by `F` and `S` commands
+    pub(crate) machine: MachineState,
+    /// Whether end points are absolute or relative to `position`
+    distance: DistanceMode,
+    /// Units of the numbers in the program; `position` is always in millimeters
+    pub(crate) units: Units,
+    /// Work offsets defined so far, by number
+    work_offsets: BTreeMap<u32, (f64, f64, f64)>,
+    /// Offset of the active work coordinate system from machine coordinates
+    work_offset: (f64, f64, f64),
+    /// The active tool and its number, whose length is added to Z
+    tool: Option<(u32, Tool)>,
+    /// Cutter compensation set by `COMP LEFT` or `COMP RIGHT`
+    compensation: Option<Compensation>,
+    /// Plane arcs are drawn in, set by `PLANE`
+    plane: Plane,
+    /// Scaling set by `SCALE`: a factor per axis and the point it scales about, in machine coordinates
+    scaling: Option<(Point, Point)>,
+    /// Axes flipped by `MIRROR` and the point they flip about, in machine coordinates
+    mirror: Option<(Vec<Axis>, (f64, f64))>,
+    /// Rotation set by `ROTATE`: degrees counter-clockwise about a center in machine coordinates
+    rotation: Option<(f64, (f64, f64))>,
+    /// Where the tool really is after the transform changed, if that isn't where `position` now maps to
+    transformed_from: Option<(f64, f64, f64)>,
+    /// Angles of the rotary axes, once a linear move has turned one
+    rotary: Option<Rotary>,
+    /// Direction the tool points in, once a linear move has tilted it; straight up Z before that
+    tool_vector: Option<Point>,
+    /// Whether X coordinates are diameters, on a lathe
+    diameter: bool,
+    /// The last motion command, which lines of bare coordinates repeat
+    verb: Option<&'static str>,
+}
+
+impl State {
+    // The state a program starts in
+    fn new(options: &ParseOptions) -> Self {
+        State {
+            units: options.units,
+            work_offsets: options.work_offsets.clone(),
+            // Lathes turn in the XZ plane
+            plane: if options.lathe { Plane::Xz } else { Plane::Xy },
+            diameter: options.lathe,
+            ..Default::default()
+        }
+    }
+
+    // Offset from programmed to machine Z: the work offset plus the tool length
+    fn z_offset(&self) -> f64 {
+        self.work_offset.2 + self.tool.map_or(0.0, |(_, tool)| tool.length)
+    }
+
+    // The transform that takes programmed motions to where they really go, if any
+    //
+    // `position` and the motions built from it stay untransformed, so
+    // incremental moves turn along with everything else.
+    //
+    // Motions are scaled, mirrored and then turned, like G51, G51.1 and G68 together.
+    fn transform(&self) -> Option<Transform> {
+        if self.scaling.is_none() && self.mirror.is_none() && self.rotation.is_none() {
+            return None;
+        }
+        let mut transform = Transform::default();
+        if let Some((factors, origin)) = self.scaling {
+            transform = transform
+                .translate((-origin.0, -origin.1, -origin.2))
+                .scale_axes(factors)
+                .translate(origin);
+        }
+        if let Some((axes, center)) = &self.mirror {
+            let flipped = axes
+                .iter()
+                .fold(transform.translate((-center.0, -center.1, 0.0)), |transform, &axis| transform.mirror(axis));
+            transform = flipped.translate((center.0, center.1, 0.0));
+        }
+        if let Some((degrees, center)) = self.rotation {
+            transform = transform
+                .translate((-center.0, -center.1, 0.0))
+                .rotate_z(degrees)
+                .translate((center.0, center.1, 0.0));
+        }
+        Some(transform)
+    }
+
+    // Where the tool really is, which the next move starts from
+    fn actual(&self) -> (f64, f64, f64) {
+        self.transformed_from.unwrap_or_else(|| {
+            self.transform().map_or(self.position, |transform| transform.point(self.position))
+        })
+    }
+
+    // Where the tool really is, in the programmed coordinates the active transform takes to the machine
+    //
+    // Right after the transform changed this isn't `position`, which is where
+    // the new transform would have put the tool.
+    fn programmed_actual(&self) -> (f64, f64, f64) {
+        match (self.transformed_from, self.transform()) {
+            (Some(actual), Some(transform)) => {
+                transform.inverse().map_or(self.position, |inverse| inverse.point(actual))
+            }
+            (Some(actual), None) => actual,
+            (None, _) => self.position,
+        }
+    }
+
+    // Change the transform, remembering where the tool is under the old one
+    fn retransform(&mut self, change: impl FnOnce(&mut Self)) {
+        let actual = self.transform().map_or(self.position, |transform| transform.point(self.position));
+        change(self);
+        if self.transformed_from.is_none() {
+            self.transformed_from = Some(actual);
+        }
+    }
+
+    // Resolve the programmed coordinates of an end point into machine coordinates
+    //
+    // Axes left out stay where they are in either mode. On a lathe X is a
+    // diameter, so the tool moves half as far from the axis.
+    pub(crate) fn target(&self, x: Option<f64>, y: Option<f64>, z: Option<f64>) -> (f64, f64, f64) {
+        let x = x.map(|x| if self.diameter { x / 2.0 } else { x });
+        let axis = |value: Option<f64>, current: f64, offset: f64| match (value, self.distance) {
+            (Some(value), DistanceMode::Absolute) => value + offset,
+            (Some(value), DistanceMode::Incremental) => current + value,
+            (None, _) => current,
+        };
+        (
+            axis(x, self.position.0, self.work_offset.0),
+            axis(y, self.position.1, self.work_offset.1),
+            axis(z, self.position.2, self.z_offset()),
+        )
+    }
+}
+
+/// Function to parse a single line of a program
+///
+/// Blank lines and lines that only change the interpreter state produce
+/// `Ok(None)`. Linear motions start from the current position, which is
+/// updated to the new end point.
+///
+/// # Arguments
+///
+/// * `line_number` - The one-based line number, used for error reporting
+/// * `line` - The text of the line
+/// * `state` - The interpreter state, updated by the line
+/// * `options` - Parsing options
+///
+/// # Errors
+///
+/// Returns a `ParseError` if the command is unknown or is missing arguments,
+/// or in strict mode if an argument is not a valid number.
+fn parse_line(
+    line_number: usize,
+    line: &str,
+    state: &mut State,
+    options: &ParseOptions,
+) -> Result<Option<Motion>, ParseError> {
+    // Comments don't take part in parsing
+    let line = &strip_comments(line);
+
+    // Shorthand for parsing the numeric argument at a given position
+    let value = |token: &Token| token.value(line_number, options);
+    // Lengths are converted to millimeters as they are read
+    let scale = state.units.scale();
+    let length = |token: &Token| value(token).map(|value| value * scale);
+
+    // Split the line into parts using whitespace as delimiter
+    let mut parts = tokenize(line);
+
+    // A line of bare coordinates repeats the last motion command, as G-code's modal moves do
+    let repeated = match (state.verb, parts.first()) {
+        (Some(verb), Some(first)) if is_coordinates(first) && options.commands.get(first.text).is_none() => {
+            parts.insert(0, Token { text: verb, start: first.start });
+            true
+        }
+        _ => false,
+    };
+
+    // Motions can override the feed rate for themselves with an F word after the command
+    let mut feed = None;
+    let name = parts.first().map(|token| token.text);
+    if matches!(name, Some("LIN" | "CW" | "CCW" | "CIRCLE" | "ARC" | "BEZ" | "NURBS")) {
+        let mut index = 1;
+        while index < parts.len() {
+            if parts[index].letter() == 'F' {
+                feed = Some(length(&parts.remove(index))?);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    // Skip blank lines
+    let Some(command) = parts.first() else {
+        return Ok(None);
+    };
+
+    // Lathes have no Y axis to move along
+    if state.diameter && matches!(command.text, "LIN" | "RAP" | "CW" | "CCW" | "BEZ" | "NURBS") {
+        if let Some(token) = parts[1..].iter().find(|token| token.letter() == 'Y') {
+            return Err(ParseError::LatheY {
+                line: line_number,
+                span: token.span(),
+            });
+        }
+    }
+
+    // Check if the command is "LIN", or "RAP" for a rapid positioning move
+    let motion = if command.text == "LIN" || command.text == "RAP" {
+        // Lathe moves give just X and Z
+        let axes = if state.diameter { 2 } else { 3 };
+        // Which coordinate an axis word sets, given that Y was already refused on lathes
+        let axis = |token: &Token| match token.letter() {
+            'X' => Some(0),
+            'Y' => Some(1),
+            'Z' => Some(axes - 1),
+            _ => None,
+        };
+        // Words the move can't take, including an axis given twice, are typos rather than things to skip
+        let unexpected = |token: &Token| ParseError::UnexpectedWord {
+            line: line_number,
+            span: token.span(),
+            command: command.text.to_string(),
+            word: token.text.to_string(),
+        };
+        // The end point is a tuple, where `_` leaves an axis where it is, the X, Y and Z words of a line of
+        // bare coordinates, where the axes left out stay put, or else the first words, each going to the axis
+        // its letter names and otherwise taken in X, Y, Z order
+        let (coordinates, rest) = if parts.get(1).is_some_and(|token| token.text.starts_with('(')) {
+            let (coordinates, next) = partial_tuple_at(line_number, line, &parts, 1)?;
+            if coordinates.len() != axes {
+                return Err(ParseError::MissingArguments {
+                    line: line_number,
+                    span: Span(parts[1].start..line.trim_end().len()),
+                    command: command.text.to_string(),
+                    expected: axes,
+                    found: coordinates.len(),
+                });
+            }
+            (coordinates.into_iter().map(|coordinate| coordinate.map(|value| value * scale)).collect(), next)
+        } else if repeated {
+            let count = parts[1..].iter().take_while(|token| matches!(token.letter(), 'X' | 'Y' | 'Z')).count();
+            let mut coordinates = vec![None; axes];
+            for token in &parts[1..=count] {
+                let slot = &mut coordinates[axis(token).expect("only axis words are counted")];
+                if slot.replace(length(token)?).is_some() {
+                    return Err(unexpected(token));
+                }
+            }
+            (coordinates, count + 1)
+        } else {
+            expect_arguments(line_number, line, &parts, axes)?;
+            let mut coordinates = vec![None; axes];
+            for (index, token) in parts[1..=axes].iter().enumerate() {
+                let slot = &mut coordinates[axis(token).unwrap_or(index)];
+                if slot.replace(length(token)?).is_some() {
+                    return Err(unexpected(token));
+                }
+            }
+            (coordinates, axes + 1)
+        };
+        let end = if state.diameter {
+            state.target(coordinates[0], None, coordinates[1])
+        } else {
+            state.target(coordinates[0], coordinates[1], coordinates[2])
+        };
+        // A, B and C words after the end point turn the rotary axes, in degrees whatever the units
+        let mut turn = (None, None, None);
+        // I, J and K words point the tool along a new direction instead; left out components are zero
+        let mut tilt = None;
+        for token in &parts[rest..] {
+            match token.letter() {
+                // The tool can be pointed by the rotary axes or by its direction, but not both in one program
+                'A' | 'B' | 'C' if state.tool_vector.is_some() || tilt.is_some() => return Err(unexpected(token)),
+                'I' | 'J' | 'K' if state.rotary.is_some() || turn != (None, None, None) => {
+                    return Err(unexpected(token))
+                }
+                'A' => turn.0 = Some(value(token)?),
+                'B' => turn.1 = Some(value(token)?),
+                'C' => turn.2 = Some(value(token)?),
+                'I' => tilt.get_or_insert((0.0, 0.0, 0.0)).0 = value(token)?,
+                'J' => tilt.get_or_insert((0.0, 0.0, 0.0)).1 = value(token)?,
+                'K' => tilt.get_or_insert((0.0, 0.0, 0.0)).2 = value(token)?,
+                // Anything else is a typo, not something to skip over
+                _ => return Err(unexpected(token)),
+            }
+        }
+        let start = state.position;
+        state.position = end; // Update previous end point
+        state.verb = REPEATABLE.into_iter().find(|verb| *verb == command.text);
+        // Once the tool has tilted every linear move carries its direction
+        if tilt.is_some() || state.tool_vector.is_some() {
+            let from = state.tool_vector.unwrap_or((0.0, 0.0, 1.0));
+            let Motion::Oriented(mut oriented_motion) = Motion::new_oriented(start, end, from, tilt.unwrap_or(from))
+            else {
+                unreachable!("new_oriented makes oriented motions")
+            };
+            oriented_motion.rapid = command.text == "RAP";
+            state.tool_vector = Some(oriented_motion.orientation.1);
+            Motion::Oriented(oriented_motion)
+        } else {
+            // Use previous end point as start point
+            let motion = if command.text == "RAP" {
+                Motion::new_rapid(start, end)
+            } else {
+                Motion::new_linear(start, end)
+            };
+            if turn != (None, None, None) && state.rotary.is_none() {
+                state.rotary = Some(Rotary::default());
+            }
+            // Once the rotary axes are in use every linear move carries their angles
+            match state.rotary {
+                Some(from) => {
+                    let axis = |value: Option<f64>, current: f64| match (value, state.distance) {
+                        (Some(value), DistanceMode::Absolute) => value,
+                        (Some(value), DistanceMode::Incremental) => current + value,
+                        (None, _) => current,
+                    };
+                    let to = Rotary {
+                        a: axis(turn.0, from.a),
+                        b: axis(turn.1, from.b),
+                        c: axis(turn.2, from.c),
+                    };
+                    state.rotary = Some(to);
+                    motion.with_rotary(from, to)
+                }
+                None => motion,
+            }
+        }
+    } else if (command.text == "CW" || command.text == "CCW")
+        && (parts[1..].iter().any(|token| matches!(token.letter(), 'I' | 'J' | 'K'))
+            || parts[1..].iter().any(|token| token.letter() == 'R')
+                && parts[1..].iter().all(|token| matches!(token.letter(), 'X' | 'Y' | 'Z' | 'R')))
+    {
+        // Arcs with an I/J/K center offset, or an R radius, run from the current position to an end point
+        let centered = parts[1..].iter().any(|token| matches!(token.letter(), 'I' | 'J' | 'K'));
+        let mut target = (None, None, None);
+        let mut offset = (0.0, 0.0, 0.0);
+        let mut radius = 0.0;
+        for token in &parts[1..] {
+            match token.letter() {
+                'R' if !centered => radius = length(token)?, // Parse radius, negative for the longer arc
+                'X' => target.0 = Some(length(token)?), // Parse X end coordinate
+                'Y' => target.1 = Some(length(token)?), // Parse Y end coordinate
+                'Z' => target.2 = Some(length(token)?), // Parse Z end coordinate
+                'I' => offset.0 = length(token)?, // Parse X center offset
+                'J' => offset.1 = length(token)?, // Parse Y center offset
+                'K' => offset.2 = length(token)?, // Parse Z center offset
+                _ => {
+                    return Err(ParseError::UnexpectedWord {
+                        line: line_number,
+                        span: token.span(),
+                        command: command.text.to_string(),
+                        word: token.text.to_string(),
+                    })
+                }
+            }
+        }
+        let machine_end = state.target(target.0, target.1, target.2);
+        // Only the offsets along the plane's axes locate the center
+        let plane = state.plane;
+        let (start, end, offset) =
+            (plane.coordinates(state.position), plane.coordinates(machine_end), plane.coordinates(offset));
+        let clockwise = command.text == "CW";
+        let span = || Span(command.start..line.trim_end().len());
+        let arc = if centered {
+            // As in G-code, the center can't be the start point, and the end point has to be on the circle
+            if offset.0 == 0.0 && offset.1 == 0.0 {
+                return Err(ParseError::MissingArcCenter {
+                    line: line_number,
+                    span: span(),
+                });
+            }
+            gcode::check_arc_end(line_number, span(), start, end, offset)?;
+            Motion::new_arc_from_offset(start, end, (offset.0, offset.1), clockwise)
+        } else {
+            Motion::new_arc_from_radius(start, end, radius, clockwise).ok_or_else(|| ParseError::ArcRadius {
+                line: line_number,
+                span: span(),
+                radius,
+                chord: (end.0 - start.0).hypot(end.1 - start.1),
+            })?
+        };
+        state.position = machine_end; // The arc ends where the next motion starts
+        state.verb = REPEATABLE.into_iter().find(|verb| *verb == command.text);
+        arc.with_plane(plane)
+    } else if matches!(command.text, "CW" | "CCW" | "CIRCLE") {
+        // Full circles have no stop angle, and turn the way a CW or CCW word among their numbers says
+        let circle = command.text == "CIRCLE";
+        let clockwise = if circle { parts.iter().any(|token| token.text == "CW") } else { command.text == "CW" };
+        let parts: Vec<Token> =
+            parts.iter().copied().filter(|token| !(circle && matches!(token.text, "CW" | "CCW"))).collect();
+        expect_arguments(line_number, line, &parts, if circle { 3 } else { 4 })?;
+        // Arcs given by their center and angles can't be repeated with new coordinates
+        state.verb = None;
+        // Offsets from programmed to machine coordinates, along the plane's axes and its normal
+        let plane = state.plane;
+        let origin = plane.coordinates((state.work_offset.0, state.work_offset.1, state.z_offset()));
+        // On a lathe the center's X is a diameter too
+        let diameter = if state.diameter && plane != Plane::Yz { 0.5 } else { 1.0 };
+        // Parse parameters for rotational motion
+        let center = (
+            length(&parts[1])? * diameter + origin.0, // Parse X coordinate (first axis of the plane)
+            length(&parts[2])? + origin.1, // Parse Y coordinate (second axis of the plane)
+        );
+        let radius = length(&parts[3])?; // Parse radius
+        // A circle needs a size; the direction comes from the command, not the radius's sign
+        if radius <= 0.0 || !radius.is_finite() {
+            return Err(ParseError::InvalidNumber {
+                line: line_number,
+                span: parts[3].span(),
+                text: parts[3].text.to_string(),
+            });
+        }
+        // The arc starts where the tool is, unless a start angle puts it somewhere else on the circle
+        let position = plane.coordinates(state.programmed_actual());
+        let tool_angle = (position.1 - center.1).atan2(position.0 - center.0).to_degrees();
+        let optional_angle = |token: Option<&Token>| token.map_or(Ok(tool_angle), value);
+        let (start_angle, sweep, heights) = if circle {
+            // Circles stop a full turn after they start
+            let sweep = if clockwise { -360.0 } else { 360.0 };
+            (optional_angle(parts.get(4))?, sweep, (parts.get(5), parts.get(6)))
+        } else {
+            let start_angle = optional_angle(parts.get(5))?;
+            let sweep = sweep_between(start_angle, value(&parts[4])?, clockwise);
+            (start_angle, sweep, (parts.get(6), parts.get(7)))
+        };
+        // Either way the tool has to be at the start already, or the arc would begin with a jump
+        let (sin, cos) = start_angle.to_radians().sin_cos();
+        let start = (center.0 + radius * cos, center.1 + radius * sin);
+        let distance = (position.0 - start.0).hypot(position.1 - start.1);
+        if distance > gcode::ARC_TOLERANCE.max(radius * 1e-3) {
+            return Err(ParseError::ArcStartOffCircle {
+                line: line_number,
+                span: Span(parts[1].start..line.trim_end().len()),
+                position: (position.0, position.1),
+                start,
+            });
+        }
+        // Create a new rotational motion, helical if start and end Z are given
+        let motion = match heights {
+            (Some(z_start), Some(z_end)) => {
+                let z = (length(z_start)? + origin.2, length(z_end)? + origin.2);
+                Motion::new_helical(center, radius, start_angle, sweep, z)
+            }
+            // Flat arcs outside the XY plane stay where the tool is along the plane's normal
+            _ if plane != Plane::Xy => {
+                Motion::new_helical(center, radius, start_angle, sweep, (position.2, position.2))
+            }
+            _ => Motion::new_rotational(center, radius, start_angle, sweep),
+        }
+        .with_plane(plane);
+        state.position = transform::ends(&motion, state.position.2).1; // The arc ends where the next motion starts
+        motion
+    } else if command.text == "ARC" {
+        // Three-point arcs run from the current position through one point to another, each a tuple like LIN's
+        let axes = if state.diameter { 2 } else { 3 };
+        let mut points = Vec::with_capacity(2);
+        let mut index = 1;
+        while points.len() < 2 {
+            if !parts.get(index).is_some_and(|token| token.text.starts_with('(')) {
+                return Err(ParseError::MissingArguments {
+                    line: line_number,
+                    span: Span(command.start..line.trim_end().len()),
+                    command: command.text.to_string(),
+                    expected: 2,
+                    found: points.len(),
+                });
+            }
+            let (coordinates, next) = partial_tuple_at(line_number, line, &parts, index)?;
+            if coordinates.len() != axes {
+                return Err(ParseError::MissingArguments {
+                    line: line_number,
+                    span: Span(parts[index].start..parts[next - 1].span().0.end),
+                    command: command.text.to_string(),
+                    expected: axes,
+                    found: coordinates.len(),
+                });
+            }
+            let coordinates: Vec<_> =
+                coordinates.into_iter().map(|coordinate| coordinate.map(|value| value * scale)).collect();
+            points.push(if state.diameter {
+                state.target(coordinates[0], None, coordinates[1])
+            } else {
+                state.target(coordinates[0], coordinates[1], coordinates[2])
+            });
+            index = next;
+        }
+        if let Some(token) = parts.get(index) {
+            return Err(ParseError::UnexpectedWord {
+                line: line_number,
+                span: token.span(),
+                command: command.text.to_string(),
+                word: token.text.to_string(),
+            });
+        }
+        let start = state.position;
+        let (through, end) = (points[0], points[1]);
+        // The points are taken in the plane's axes, with Z ramping along its normal as for I/J arcs
+        let plane = state.plane;
+        let arc = Motion::new_arc_through(plane.coordinates(start), plane.coordinates(through), plane.coordinates(end));
+        let Some(arc) = arc else {
+            return Err(ParseError::CollinearArc {
+                line: line_number,
+                span: Span(parts[1].start..line.trim_end().len()),
+            });
+        };
+        state.position = end; // The arc ends where the next motion starts
+        state.verb = None;
+        arc.with_plane(plane)
+    } else if command.text == "BEZ" {
+        if state.compensation.is_some() {
+            return Err(ParseError::CompensatedCurve {
+                line: line_number,
+                span: command.span(),
+            });
+        }
+        // Cubic curves run from the current position to an end point, bent by two control points
+        let mut target = (None, None, None);
+        let mut first = (0.0, 0.0);
+        let mut second = (0.0, 0.0);
+        for token in &parts[1..] {
+            match token.letter() {
+                'X' => target.0 = Some(length(token)?), // Parse X end coordinate
+                'Y' => target.1 = Some(length(token)?), // Parse Y end coordinate
+                'Z' => target.2 = Some(length(token)?), // Parse Z end coordinate
+                'I' => first.0 = length(token)?, // Parse X offset of the first control point from the start
+                'J' => first.1 = length(token)?, // Parse Y offset of the first control point from the start
+                'P' => second.0 = length(token)?, // Parse X offset of the second control point from the end
+                'Q' => second.1 = length(token)?, // Parse Y offset of the second control point from the end
+                _ => {
+                    return Err(ParseError::UnexpectedWord {
+                        line: line_number,
+                        span: token.span(),
+                        command: command.text.to_string(),
+                        word: token.text.to_string(),
+                    })
+                }
+            }
+        }
+        let start = state.position;
+        let end = state.target(target.0, target.1, target.2);
+        state.position = end; // The curve ends where the next motion starts
+        state.verb = Some("BEZ");
+        Motion::new_bezier_from_offsets(start, end, first, second)
+    } else if command.text == "NURBS" {
+        if state.compensation.is_some() {
+            return Err(ParseError::CompensatedCurve {
+                line: line_number,
+                span: command.span(),
+            });
+        }
+        // Splines start at the current position and run through (x, y, z[, w]) control points
+        state.verb = None;
+        let mut controls = vec![state.position];
+        let mut weights = vec![1.0];
+        let mut knots = None;
+        let mut degree = None;
+        let mut index = 1;
+        while index < parts.len() {
+            let token = parts[index];
+            index += 1;
+            if token.text.starts_with('(') || token.text.starts_with("K(") {
+                // Tuples may hold spaces, so they run to the closing parenthesis rather than the token's end
+                let close = line[token.start..].find(')').map_or(line.len(), |close| token.start + close + 1);
+                let span = Span(token.start..close);
+                while index < parts.len() && parts[index].start < close {
+                    index += 1;
+                }
+                let numbers = parse_tuple(line[token.start..close].trim_start_matches('K')).map_err(|text| {
+                    ParseError::InvalidNumber {
+                        line: line_number,
+                        span: span.clone(),
+                        text,
+                    }
+                })?;
+                if token.letter() == 'K' {
+                    knots = Some(numbers);
+                    continue;
+                }
+                let (x, y, z, weight) = match numbers[..] {
+                    [x, y, z] => (x, y, z, 1.0),
+                    [x, y, z, weight] => (x, y, z, weight),
+                    _ => {
+                        return Err(ParseError::MissingArguments {
+                            line: line_number,
+                            span,
+                            command: command.text.to_string(),
+                            expected: 3,
+                            found: numbers.len(),
+                        })
+                    }
+                };
+                // Each point is read like an end point, so incremental points follow on from the last
+                let point = state.target(Some(x * scale), Some(y * scale), Some(z * scale));
+                state.position = point;
+                controls.push(point);
+                weights.push(weight);
+            } else if token.letter() == 'D' {
+                degree = Some(token.text[1..].parse().map_err(|_| ParseError::InvalidNumber {
+                    line: line_number,
+                    span: token.span(),
+                    text: token.text.to_string(),
+                })?);
+            } else {
+                return Err(ParseError::UnexpectedWord {
+                    line: line_number,
+                    span: token.span(),
+                    command: command.text.to_string(),
+                    word: token.text.to_string(),
+                });
+            }
+        }
+        // Without a degree, curves are cubic unless there are too few points for that
+        let degree = degree.unwrap_or_else(|| controls.len().saturating_sub(1).min(3));
+        let curve = Motion::new_spline(controls, weights, knots, degree).map_err(|error| ParseError::InvalidSpline {
+            line: line_number,
+            span: command.span(),
+            error,
+        })?;
+        if let Motion::Spline(spline_motion) = &curve {
+            state.position = spline_motion.end(); // The curve ends where the next motion starts
+        }
+        curve
+    } else if command.text == "ROTATE" {
+        expect_arguments(line_number, line, &parts, 1)?;
+        // Turn the following motions about Z, about the work origin unless given a center
+        let rotation = if parts[1].text == "OFF" {
+            None
+        } else {
+            let degrees = number(line_number, &parts[1])?;
+            // Only X and Y of the center matter for a turn about Z
+            let center = about_point(line_number, line, &parts, 2)?
+                .map_or((0.0, 0.0), |center| (center[0] * scale, center[1] * scale));
+            Some((degrees, (center.0 + state.work_offset.0, center.1 + state.work_offset.1)))
+        };
+        state.retransform(|state| state.rotation = rotation);
+        return Ok(None);
+    } else if command.text == "MIRROR" {
+        expect_arguments(line_number, line, &parts, 1)?;
+        // Flip X, Y or both in the following motions, about the work origin unless given a point
+        let mirror = if parts[1].text == "OFF" {
+            None
+        } else {
+            let count = parts[1..].iter().take_while(|token| matches!(token.text, "X" | "Y")).count();
+            if count == 0 {
+                return Err(ParseError::UnexpectedWord {
+                    line: line_number,
+                    span: parts[1].span(),
+                    command: command.text.to_string(),
+                    word: parts[1].text.to_string(),
+                });
+            }
+            let mut axes = Vec::new();
+            for token in &parts[1..=count] {
+                let axis = if token.text == "X" { Axis::X } else { Axis::Y };
+                if !axes.contains(&axis) {
+                    axes.push(axis);
+                }
+            }
+            let center = about_point(line_number, line, &parts, count + 1)?
+                .map_or((0.0, 0.0), |center| (center[0] * scale, center[1] * scale));
+            Some((axes, (center.0 + state.work_offset.0, center.1 + state.work_offset.1)))
+        };
+        state.retransform(|state| state.mirror = mirror);
+        return Ok(None);
+    } else if command.text == "SCALE" {
+        expect_arguments(line_number, line, &parts, 1)?;
+        // Scale the following motions by one factor or one per axis, about the work origin unless given a point
+        let scaling = if parts[1].text == "OFF" {
+            None
+        } else {
+            let (factors, next) = if parts[1].text.starts_with('(') {
+                let (factors, next) = tuple_at(line_number, line, &parts, 1)?;
+                let [x, y, z] = factors[..] else {
+                    return Err(ParseError::MissingArguments {
+                        line: line_number,
+                        span: Span(parts[1].start..line.trim_end().len()),
+                        command: command.text.to_string(),
+                        expected: 3,
+                        found: factors.len(),
+                    });
+                };
+                ((x, y, z), next)
+            } else {
+                let factor = number(line_number, &parts[1])?;
+                ((factor, factor, factor), 2)
+            };
+            // Scaling by zero would flatten the path onto a point or a plane
+            if factors.0 == 0.0 || factors.1 == 0.0 || factors.2 == 0.0 {
+                let span = Span(parts[1].start..parts[next - 1].span().0.end);
+                return Err(ParseError::InvalidNumber {
+                    line: line_number,
+                    text: line[span.0.clone()].to_string(),
+                    span,
+                });
+            }
+            let origin = about_point(line_number, line, &parts, next)?.map_or((0.0, 0.0, 0.0), |origin| {
+                (origin[0] * scale, origin[1] * scale, origin.get(2).map_or(0.0, |z| z * scale))
+            });
+            Some((
+                factors,
+                (origin.0 + state.work_offset.0, origin.1 + state.work_offset.1, origin.2 + state.z_offset()),
+            ))
+        };
+        state.retransform(|state| state.scaling = scaling);
+        return Ok(None);
+    } else if (command.text == "ABS" || command.text == "INC") && parts.len() == 1 {
+        // Choose how the end points of the following motions are read
+        state.distance = if command.text == "ABS" {
+            DistanceMode::Absolute
+        } else {
+            DistanceMode::Incremental
+        };
+        return Ok(None);
+    } else if command.text == "UNITS" {
+        expect_arguments(line_number, line, &parts, 1)?;
+        // Numbers after this line are in the new units
+        state.units = parts[1].text.parse().map_err(|_| ParseError::UnexpectedWord {
+            line: line_number,
+            span: parts[1].span(),
+            command: command.text.to_string(),
+            word: parts[1].text.to_string(),
+        })?;
+        return Ok(None);
+    } else if command.text == "WCS" {
+        expect_arguments(line_number, line, &parts, 2)?;
+        // Define a work offset: WCS n (x, y, z)
+        let number = whole_number(line_number, &parts[1])?;
+        let coordinates = parse_tuple(&line[parts[2].start..]).map_err(|text| ParseError::InvalidNumber {
+            line: line_number,
+            span: parts[2].span(),
+            text,
+        })?;
+        let [x, y, z] = coordinates[..] else {
+            return Err(ParseError::MissingArguments {
+                line: line_number,
+                span: Span(parts[2].start..line.trim_end().len()),
+                command: command.text.to_string(),
+                expected: 3,
+                found: coordinates.len(),
+            });
+        };
+        state.work_offsets.insert(number, (x * scale, y * scale, z * scale));
+        return Ok(None);
+    } else if command.text == "USE" && parts.get(1).map(|token| token.text) == Some("WCS") {
+        expect_arguments(line_number, line, &parts, 2)?;
+        // Switch work offsets; 0 means machine coordinates
+        let number = whole_number(line_number, &parts[2])?;
+        state.work_offset = match number {
+            0 => (0.0, 0.0, 0.0),
+            _ => *state.work_offsets.get(&number).ok_or(ParseError::UnknownWorkOffset {
+                line: line_number,
+                span: parts[2].span(),
+                number,
+            })?,
+        };
+        return Ok(None);
+    } else if command.text == "TOOL" || command.text == "M6" {
+        // Pick a tool from the table; 0 means no tool
+        let (number, span) = if command.text == "M6" {
+            // `M6 T3` and `M6 T 3` are G-code's way of writing `TOOL 3`
+            let word = parts.get(1).ok_or(ParseError::MissingArguments {
+                line: line_number,
+                span: command.span(),
+                command: command.text.to_string(),
+                expected: 1,
+                found: 0,
+            })?;
+            let Some(digits) = word.text.strip_prefix('T') else {
+                return Err(ParseError::UnexpectedWord {
+                    line: line_number,
+                    span: word.span(),
+                    command: command.text.to_string(),
+                    word: word.text.to_string(),
+                });
+            };
+            let token = match (digits, parts.get(2)) {
+                ("", Some(token)) => {
+                    expect_arguments(line_number, line, &parts, 2)?;
+                    *token
+                }
+                _ => {
+                    expect_arguments(line_number, line, &parts, 1)?;
+                    Token { text: digits, start: word.start + 1 }
+                }
+            };
+            (whole_number(line_number, &token)?, token.span())
+        } else {
+            expect_arguments(line_number, line, &parts, 1)?;
+            (whole_number(line_number, &parts[1])?, parts[1].span())
+        };
+        state.tool = match (number, options.tools.get(number)) {
+            (0, _) => None,
+            (_, Some(tool)) => Some((number, *tool)),
+            // Like G-code, `M6` takes a tool the table doesn't know as one with no length or diameter
+            (_, None) if command.text == "M6" => Some((number, Tool::default())),
+            (_, None) => return Err(ParseError::UnknownTool { line: line_number, span, number }),
+        };
+        return Ok(Some(Motion::new_tool_change(number, state.actual())));
+    } else if matches!(command.text, "PAUSE" | "M0" | "M1") {
+        // Stop until whoever runs the machine carries on; M1 only with the optional stop switch on
+        return Ok(Some(Motion::new_pause(command.text == "M1", state.actual())));
+    } else if command.text.strip_prefix('M').and_then(|code| code.parse().ok()).is_some_and(gcode::is_aux_code) {
+        // Auxiliary commands are passed on as written, with any words after the code
+        let code = parts.iter().map(|token| token.text).collect::<Vec<_>>().join(" ");
+        return Ok(Some(Motion::new_aux(code, state.actual())));
+    } else if command.text == "PLANE" {
+        expect_arguments(line_number, line, &parts, 1)?;
+        // Choose the plane the following arcs are drawn in
+        let plane = match parts[1].text {
+            "XY" => Plane::Xy,
+            "XZ" => Plane::Xz,
+            "YZ" => Plane::Yz,
+            _ => {
+                return Err(ParseError::UnexpectedWord {
+                    line: line_number,
+                    span: parts[1].span(),
+                    command: command.text.to_string(),
+                    word: parts[1].text.to_string(),
+                })
+            }
+        };
+        if plane != Plane::Xy && state.compensation.is_some() {
+            return Err(ParseError::CompensationPlane {
+                line: line_number,
+                span: command.span(),
+            });
+        }
+        state.plane = plane;
+        return Ok(None);
+    } else if command.text == "COMP" {
+        expect_arguments(line_number, line, &parts, 1)?;
+        // Offset the path by the radius of the active tool, or stop offsetting it
+        let side = match parts[1].text {
+            "LEFT" => Side::Left,
+            "RIGHT" => Side::Right,
+            "OFF" => {
+                state.compensation = None;
+                return Ok(None);
+            }
+            _ => {
+                return Err(ParseError::UnexpectedWord {
+                    line: line_number,
+                    span: parts[1].span(),
+                    command: command.text.to_string(),
+                    word: parts[1].text.to_string(),
+                })
+            }
+        };
+        if state.plane != Plane::Xy {
+            return Err(ParseError::CompensationPlane {
+                line: line_number,
+                span: command.span(),
+            });
+        }
+        let diameter = state.tool.map_or(0.0, |(_, tool)| tool.diameter);
+        if diameter <= 0.0 {
+            return Err(ParseError::NoToolDiameter {
+                line: line_number,
+                span: command.span(),
+                command: format!("{} {}", command.text, parts[1].text),
+            });
+        }
+        state.compensation = Some(Compensation {
+            side,
+            radius: diameter / 2.0,
+        });
+        return Ok(None);
+    } else if command.letter() == 'F' && parts.len() == 1 {
+        // A feed rate applies to every following motion, in millimeters per minute
+        state.machine.feed = Some(length(command)?);
+        return Ok(None);
+    } else if command.letter() == 'S' && parts.len() == 1 {
+        // Spindle speed or laser power also applies to every following motion, unscaled
+        state.machine.power = Some(value(command)?);
+        return Ok(None);
+    } else if let Some(handler) = options.commands.get(command.text) {
+        // Words the language doesn't know may be the library user's own commands
+        let arguments: Vec<&str> = parts[1..].iter().map(|token| token.text).collect();
+        let mut context = Context {
+            line: line_number,
+            state: &mut *state,
+        };
+        let motion = handler.run(&arguments, &mut context).map_err(|message| ParseError::CommandFailed {
+            line: line_number,
+            span: command.span(),
+            command: command.text.to_string(),
+            message,
+        })?;
+        let Some(motion) = motion else {
+            return Ok(None);
+        };
+        // The next motion starts where this one ends
+        state.position = transform::ends(&motion, state.position.2).1;
+        motion
+    } else {
+        // Handle unrecognized command
+        return Err(ParseError::UnknownCommand {
+            line: line_number,
+            span: command.span(),
+            command: command.text.to_string(),
+        });
+    };
+
+    place(line_number, command, state, motion, feed).map(Some)
+}
+
+// Finish a programmed motion: put it where the active transform takes it, from
+// wherever the tool really is, at the feed rate and power in effect
+fn place(
+    line_number: usize,
+    command: &Token,
+    state: &mut State,
+    motion: Motion,
+    feed: Option<f64>,
+) -> Result<Motion, ParseError> {
+    // Put the motion where the active transform takes it
+    let motion = match state.transform() {
+        Some(transform) => transform.motion(&motion).ok_or(ParseError::TransformedArc {
+            line: line_number,
+            span: command.span(),
+        })?,
+        None => motion,
+    };
+    // A line after the transform changed runs from wherever the tool really is
+    let motion = match (state.transformed_from.take(), motion) {
+        (Some(start), Motion::Linear(linear_motion)) => Motion::Linear(LinearMotion { start, ..linear_motion }),
+        (Some(start), Motion::Oriented(oriented_motion)) => {
+            Motion::Oriented(OrientedMotion { start, ..oriented_motion })
+        }
+        (_, motion) => motion,
+    };
+
+    // Motions run at the feed rate and power in effect when they were programmed
+    Ok(state.machine.apply(motion, feed))
+}
+
+// Function to interpret a line that may make several motions
+//
+// Cycles make a whole sequence of moves from one line. Every other line is
+// left to `parse_line`, which makes one motion at most.
+fn interpret_line(
+    line_number: usize,
+    line: &str,
+    state: &mut State,
+    options: &ParseOptions,
+) -> Result<Vec<Motion>, ParseError> {
+    let stripped = strip_comments(line);
+    let parts = tokenize(&stripped);
+    match parts.first().map(|token| token.text) {
+        Some("DRILL" | "PECK") => parse_drill(line_number, &stripped, &parts, state, options),
+        Some("POCKET") => parse_pocket(line_number, &stripped, &parts, state, options),
+        Some("THREAD") => parse_thread(line_number, &stripped, &parts, state, options),
+        _ => Ok(parse_line(line_number, line, state, options)?.into_iter().collect()),
+    }
+}
+
+// Parse a drilling cycle, `DRILL Xx Yy Zz Rr [Ff]` or `PECK Xx Yy Zz Rr Qq [Ff]`
+//
+// X and Y are where the hole is, Z its bottom and R the height the tool feeds
+// down from, all read like the end point of a `LIN`. Q is how deep each peck goes.
+fn parse_drill(
+    line_number: usize,
+    line: &str,
+    parts: &[Token],
+    state: &mut State,
+    options: &ParseOptions,
+) -> Result<Vec<Motion>, ParseError> {
+    let command = &parts[0];
+    let pecks = command.text == "PECK";
+    let [x, y, z, r, feed, q] = if pecks {
+        cycle_words(line_number, parts, ['X', 'Y', 'Z', 'R', 'F', 'Q'], state, options)?
+    } else {
+        let [x, y, z, r, feed] = cycle_words(line_number, parts, ['X', 'Y', 'Z', 'R', 'F'], state, options)?;
+        [x, y, z, r, feed, None]
+    };
+    let (Some(z), Some(r)) = (z, r) else {
+        expect_arguments(line_number, line, parts, if pecks { 3 } else { 2 })?;
+        return Err(invalid_cycle(
+            line_number,
+            command,
+            "needs the bottom of the hole as Z and the height to feed down from as R",
+        ));
+    };
+    if pecks && !q.is_some_and(|q| q > 0.0) {
+        return Err(invalid_cycle(line_number, command, "needs a positive peck depth as Q"));
+    }
+
+    // The retract height is read like Z, so in INC mode both are relative to where the tool is
+    let bottom = state.target(x, y, Some(z));
+    let retract = state.target(None, None, Some(r)).2;
+    let motions = cycles::drill(state.position, (bottom.0, bottom.1), bottom.2, retract, q).ok_or_else(|| {
+        invalid_cycle(line_number, command, "needs the bottom of the hole, Z, below the height to feed down from, R")
+    })?;
+    run_cycle(line_number, command, state, motions, feed)
+}
+
+// Parse a pocket, `POCKET Xx Yy Ww Hh Zz Rr Pp Qq [SPIRAL] [Ff]`
+//
+// X and Y are the pocket's corner with the lowest X and Y, W and H its size
+// along X and Y, Z its floor and R the height the tool feeds down from. P is
+// the stepover and Q the stepdown.
+fn parse_pocket(
+    line_number: usize,
+    line: &str,
+    parts: &[Token],
+    state: &mut State,
+    options: &ParseOptions,
+) -> Result<Vec<Motion>, ParseError> {
+    let command = &parts[0];
+    let spiral = parts[1..].iter().any(|token| token.text == "SPIRAL");
+    let words: Vec<Token> = parts.iter().filter(|token| token.text != "SPIRAL").copied().collect();
+    let letters = ['X', 'Y', 'W', 'H', 'Z', 'R', 'P', 'Q', 'F'];
+    let [x, y, w, h, z, r, p, q, feed] = cycle_words(line_number, &words, letters, state, options)?;
+    let (Some(x), Some(y), Some(w), Some(h), Some(z), Some(r), Some(p), Some(q)) = (x, y, w, h, z, r, p, q) else {
+        expect_arguments(line_number, line, &words, 8)?;
+        return Err(invalid_cycle(
+            line_number,
+            command,
+            "needs the corner as X and Y, the size as W and H, the floor as Z, the height to feed down from as R, \
+             the stepover as P and the stepdown as Q",
+        ));
+    };
+    if w <= 0.0 || h <= 0.0 {
+        return Err(invalid_cycle(line_number, command, "needs a positive width W and height H"));
+    }
+
+    // The corner and floor are read like an end point, and the top like Z
+    let corner = state.target(Some(x), Some(y), Some(z));
+    let pocket = Pocket {
+        corner: (corner.0, corner.1),
+        size: (w, h),
+        bottom: corner.2,
+        top: state.target(None, None, Some(r)).2,
+        stepover: p,
+        stepdown: q,
+        clearing: if spiral { Clearing::Spiral } else { Clearing::Zigzag },
+    };
+    // Without a tool the path would be for a cutter of no size, which no real one is
+    let radius = state.tool.map_or(0.0, |(_, tool)| tool.diameter / 2.0);
+    if radius <= 0.0 {
+        return Err(ParseError::NoToolDiameter {
+            line: line_number,
+            span: command.span(),
+            command: command.text.to_string(),
+        });
+    }
+    let motions = pocket.motions(state.position, radius).ok_or_else(|| {
+        invalid_cycle(
+            line_number,
+            command,
+            "needs room for the tool, the floor Z below R, and a positive stepover P and stepdown Q",
+        )
+    })?;
+    run_cycle(line_number, command, state, motions, feed)
+}
+
+// Parse a milled thread, `THREAD Xx Yy Dd Pp Zz Rr [EXTERNAL] [LEFT] [Ff]`
+//
+// X and Y are the axis of the hole or boss, read like `DRILL`'s, D the
+// diameter the tool's edge reaches and P the pitch. Z is the bottom of the
+// thread and R the height the tool feeds down from.
+fn parse_thread(
+    line_number: usize,
+    line: &str,
+    parts: &[Token],
+    state: &mut State,
+    options: &ParseOptions,
+) -> Result<Vec<Motion>, ParseError> {
+    let command = &parts[0];
+    let keyword = |text: &str| parts[1..].iter().any(|token| token.text == text);
+    let (external, left_hand) = (keyword("EXTERNAL"), keyword("LEFT"));
+    let words: Vec<Token> = parts.iter().filter(|token| !matches!(token.text, "EXTERNAL" | "LEFT")).copied().collect();
+    let letters = ['X', 'Y', 'D', 'P', 'Z', 'R', 'F'];
+    let [x, y, d, p, z, r, feed] = cycle_words(line_number, &words, letters, state, options)?;
+    let (Some(d), Some(p), Some(z), Some(r)) = (d, p, z, r) else {
+        expect_arguments(line_number, line, &words, 4)?;
+        return Err(invalid_cycle(
+            line_number,
+            command,
+            "needs the diameter as D, the pitch as P, the bottom as Z and the height to feed down from as R",
+        ));
+    };
+
+    let bottom = state.target(x, y, Some(z));
+    let thread = Thread {
+        center: (bottom.0, bottom.1),
+        diameter: d,
+        pitch: p,
+        bottom: bottom.2,
+        top: state.target(None, None, Some(r)).2,
+        external,
+        left_hand,
+    };
+    // Without a tool the path would be for a cutter of no size, which no real one is
+    let radius = state.tool.map_or(0.0, |(_, tool)| tool.diameter / 2.0);
+    if radius <= 0.0 {
+        return Err(ParseError::NoToolDiameter {
+            line: line_number,
+            span: command.span(),
+            command: command.text.to_string(),
+        });
+    }
+    let motions = thread.motions(state.position, radius).ok_or_else(|| {
+        invalid_cycle(
+            line_number,
+            command,
+            "needs room for the tool, the bottom Z below R, and a positive diameter D and pitch P",
+        )
+    })?;
+    run_cycle(line_number, command, state, motions, feed)
+}
+
+// Read the words of a cycle into a value for each of `letters`, in millimeters
+//
+// Letters that were left out are `None`.
+fn cycle_words<const N: usize>(
+    line_number: usize,
+    parts: &[Token],
+    letters: [char; N],
+    state: &State,
+    options: &ParseOptions,
+) -> Result<[Option<f64>; N], ParseError> {
+    let command = &parts[0];
+    let mut values = [None; N];
+    for token in &parts[1..] {
+        if state.diameter && token.letter() == 'Y' {
+            return Err(ParseError::LatheY {
+                line: line_number,
+                span: token.span(),
+            });
+        }
+        let Some(index) = letters.iter().position(|&letter| letter == token.letter()) else {
+            return Err(ParseError::UnexpectedWord {
+                line: line_number,
+                span: token.span(),
+                command: command.text.to_string(),
+                word: token.text.to_string(),
+            });
+        };
+        values[index] = Some(token.value(line_number, options)? * state.units.scale());
+    }
+    Ok(values)
+}
+
+// The error for a cycle whose words don't make a cycle
+fn invalid_cycle(line_number: usize, command: &Token, reason: &str) -> ParseError {
+    ParseError::InvalidCycle {
+        line: line_number,
+        span: command.span(),
+        command: command.text.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+// Finish the motions of a cycle like those of any other line, at the cycle's feed rate
+fn run_cycle(
+    line_number: usize,
+    command: &Token,
+    state: &mut State,
+    motions: Vec<Motion>,
+    feed: Option<f64>,
+) -> Result<Vec<Motion>, ParseError> {
+    // Cycles retrace their own path, which compensation has no side of
+    if state.compensation.is_some() {
+        return Err(invalid_cycle(line_number, command, "can't be cut with cutter compensation on"));
+    }
+    // The next motion starts where the cycle leaves the tool
+    if let Some(last) = motions.last() {
+        state.position = transform::ends(last, state.position.2).1;
+    }
+    motions.into_iter().map(|motion| place(line_number, command, state, motion, feed)).collect()
+}
+
+/// Function to read motions from a file
+///
+/// This function reads motions from a file specified by the given file path.
+/// The file should contain one command per line in the following format:
+/// - "LIN Xx Yy Zz" for a linear motion from the previous end point to (x, y, z)
+/// - "RAP Xx Yy Zz" for the same move as a rapid, which positions the tool at
+///   the machine's rapid rate instead of cutting at the feed rate
+/// - "CW Xx Yy Rr Aa [Ss] [Zz1 Zz2]" or "CCW Xx Yy Rr Aa [Ss] [Zz1 Zz2]" for a
+///   rotational motion about (x, y) with radius r, turning clockwise or
+///   counter-clockwise from start angle s to stop angle a, both measured
+///   counter-clockwise from +X, optionally ramping Z from z1 to z2 (a helix).
+///   The arc carries on from the current position, which has to be on the
+///   circle: without s it starts at the current position's angle, and with
+///   it the current position has to be at that angle
+/// - "CW Xx Yy Zz Ii Jj" or "CCW Xx Yy Zz Ii Jj" for an arc from the current
+///   position to (x, y, z) around the center at offset (i, j) from the start,
+///   the way G2/G3 specify arcs
+/// - "BEZ Xx Yy Zz Ii Jj Pp Qq" for a cubic Bézier curve from the current
+///   position to (x, y, z), whose first control point is at offset (i, j)
+///   from the start and second at offset (p, q) from the end, like G5
+/// - "NURBS [Dd] (x1, y1, z1[, w1]) (x2, y2, z2[, w2]) ... [K(k1, k2, ...)]"
+///   for a NURBS curve of degree d (3 by default) from the current position
+///   through the control points, each weighted by w (1 by default), with the
+///   given knots or clamped, uniform ones
+/// - "Ff" on a line of its own to set the feed rate, in units per minute, for
+///   the motions that follow; an `F` word on a `LIN`, `CW`, `CCW`, `BEZ` or `NURBS` line sets
+///   the feed rate of that motion only
+/// - "Ss" on a line of its own to set the spindle speed or laser power for the
+///   motions that follow
+///
+/// `ABS` and `INC` on a line of their own switch between absolute end points
+/// (the default) and end points given as offsets from the current position,
+/// for `LIN` and I/J arcs. Positional arcs describe their circle directly and
+/// are not affected.
+///
+/// `UNITS MM` and `UNITS INCH` set the units of the numbers that follow
+/// (lengths and feed rates, not angles). Motions are always returned in
+/// millimeters.
+///
+/// `WCS n (x, y, z)` defines work offset `n`, and `USE WCS n` makes the
+/// following coordinates relative to it (`USE WCS 0` goes back to machine
+/// coordinates). Motions are always returned in machine coordinates.
+///
+/// `TOOL n` picks tool `n` from [`ParseOptions::tools`] and adds its length
+/// to every programmed Z that follows (`TOOL 0` removes it). It also leaves a
+/// [`Motion::ToolChange`] where the tool is, so the change shows up among the
+/// motions. `M6 Tn` is the same, except that a tool the table doesn't list is
+/// taken as one with no length or diameter, the way G-code would.
+///
+/// `PAUSE` (or `M0`) stops the program until whoever runs the machine carries
+/// on, and `M1` is an optional stop. Both leave a [`Motion::Pause`].
+///
+/// `M7`, `M8` and `M9` (coolant) and the user-defined `M100`-`M199` leave a
+/// [`Motion::Aux`] with the command as written, words after the code included.
+///
+/// `PLANE XY`, `PLANE XZ` and `PLANE YZ` choose the plane the following arcs
+/// lie in. Positional arcs then give their center in that plane's axes (X and
+/// Z for `PLANE XZ`) and ramp along the remaining axis, and I/J/K arcs use the
+/// two offsets along the plane's axes.
+///
+/// With [`ParseOptions::lathe`] set, programs are read the way lathes take
+/// them: `LIN` and `RAP` give just X and Z (`LIN X20 Z-5`), X coordinates are
+/// diameters, so the tool goes half as far from the spindle axis, and arcs
+/// start out in the XZ plane. Y words are rejected. Arc center offsets and
+/// radii stay radii.
+///
+/// `ROTATE a` turns the following motions `a` degrees counter-clockwise about
+/// Z around the work origin, and `ROTATE a ABOUT (x, y)` around (x, y)
+/// instead, like G68. `ROTATE OFF` stops turning them. Incremental moves turn
+/// too, and a `LIN` or `RAP` right after `ROTATE` starts from wherever the
+/// tool really is. Arcs in the XZ and YZ planes can only be turned by
+/// multiples of 90°.
+///
+/// `SCALE f` scales the following motions by `f` about the work origin, and
+/// `SCALE (fx, fy, fz)` by a separate factor along each axis; either can be
+/// followed by `ABOUT (x, y[, z])` to scale about another point, like G51.
+/// `SCALE OFF` stops scaling them. Scaling applies before `ROTATE`, and arcs
+/// have to be scaled evenly along both axes of their plane. Feed rates aren't
+/// scaled.
+///
+/// `MIRROR X`, `MIRROR Y` and `MIRROR X Y` flip the X or Y coordinates of the
+/// following motions about the work origin, or about (x, y) when followed by
+/// `ABOUT (x, y)`, like G51.1. `MIRROR OFF` stops flipping them. Mirrored arcs
+/// run the other way round, so `CW` arcs become counter-clockwise and the path
+/// keeps its shape. Mirroring applies after `SCALE` and before `ROTATE`.
+///
+/// `A`, `B` and `C` words after a `LIN` or `RAP` end point turn the rotary
+/// axes to that many degrees (`LIN X10 Y0 Z0 A90`), or by that many in `INC`
+/// mode. The angles turn evenly along the move, and once one has been given
+/// every following linear move carries all three angles.
+///
+/// `I`, `J` and `K` words after a `LIN` or `RAP` end point tilt the tool on a
+/// 5-axis machine instead, so that by the end of the move it points along
+/// (i, j, k) (`LIN X10 Y0 Z0 I0 J-1 K1`). The direction turns at a steady rate
+/// along the move, and once the tool has tilted every following linear move
+/// carries its direction. A program points the tool either this way or with
+/// the rotary axes, not both.
+///
+/// `COMP LEFT` and `COMP RIGHT` offset the following motions to the left or
+/// right of the programmed path by half the active tool's diameter, and
+/// `COMP OFF` stops offsetting them. See [`crate::compensation`].
+///
+/// `DRILL Xx Yy Zz Rr` drills a hole at (x, y) down to z, feeding down from
+/// the retract height r, and `PECK Xx Yy Zz Rr Qq` drills it in pecks q deep,
+/// backing off between them to break the chip. Both rise to r first if the
+/// tool is below it, and return at rapid to the height they started at, or r
+/// if that's higher. X and Y default to where the tool is, and an `F` word
+/// sets the feed rate of the cycle's cutting moves. See [`crate::cycles`].
+///
+/// `POCKET Xx Yy Ww Hh Zz Rr Pp Qq` clears a w by h pocket whose corner with
+/// the lowest X and Y is at (x, y), down to a floor at z, feeding down from r
+/// in layers q deep, with rows p apart. Adding `SPIRAL` cuts each layer in
+/// rings from the middle out instead of rows. The active tool's center stays
+/// its radius inside the walls, and an `F` word sets the feed rate of the
+/// cutting moves.
+///
+/// `THREAD Xx Yy Dd Pp Zz Rr` mills a thread of pitch p inside a hole at
+/// (x, y), from its bottom at z up to r in helical turns. D is the diameter
+/// the edge of the active tool reaches, the thread's major diameter. Adding
+/// `EXTERNAL` cuts the thread around a boss instead, down to its minor
+/// diameter d, and `LEFT` makes it a left-hand thread.
+///
+/// `#` and `;` start a comment that runs to the end of the line, and text in
+/// `( ... )` is an inline comment.
+///
+/// Lines between `REPEAT n` and `END` run `n` times, and lines between
+/// `SUB name` and `ENDSUB` run wherever `CALL name` appears. Subroutines may be
+/// called before they are defined.
+///
+/// `INCLUDE "path"` splices in another file, found relative to the including
+/// file.
+///
+/// # Arguments
+///
+/// * `file_path` - The path to the file containing motion commands
+///
+/// # Returns
+///
+/// A Result containing a vector of Motion enums if successful, or a `CmmdError` otherwise.
+///
+/// # Errors
+///
+/// This function returns `CmmdError::Io` if it fails to open or read from the
+/// file, and `CmmdError::Parse` for the first malformed line, carrying its line
+/// number and column span.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::read_file;
+///
+/// let result = read_file("test.cmmd");
+/// assert!(result.is_ok()); // Check if reading succeeds
+/// let motions = result.unwrap();
+/// assert_eq!(motions.len(), 8); // Check number of motions read
+/// ```
+pub fn read_file(file_path: &str) -> Result<Vec<Motion>, CmmdError> {
+    read_file_with_options(file_path, &ParseOptions::default())
+}
+
+/// Function to read motions from a file with explicit parsing options
+///
+/// Behaves like [`read_file`], but lets the caller choose options such as
+/// strict number parsing.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::{read_file_with_options, ParseOptions};
+///
+/// let options = ParseOptions { strict: true, ..Default::default() };
+/// let motions = read_file_with_options("test.cmmd", &options).unwrap();
+/// assert_eq!(motions.len(), 8);
+/// ```
+pub fn read_file_with_options(file_path: &str, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    // Open the file
+    let file = File::open(file_path)?;
+    // Included files are found next to the program
+    let path = Path::new(file_path);
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let lines = source::load(io::BufReader::new(file), Some(path), options.includes.then_some(directory))?;
+    parse_source(lines, options)
+}
+
+/// Function to read motions from any buffered reader, such as a socket or stdin
+///
+/// Included files are resolved relative to the current directory.
+///
+/// # Errors
+///
+/// Returns `CmmdError::Io` if reading fails, and `CmmdError::Parse` for the
+/// first malformed line.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::{read_reader, ParseOptions};
+///
+/// let program = "LIN X0 Y0 Z0\nLIN X5 Y0 Z0\n";
+/// let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+/// assert_eq!(motions.len(), 2);
+/// ```
+pub fn read_reader<R: BufRead>(reader: R, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    let lines = source::load(reader, None, options.includes.then_some(Path::new(".")))?;
+    parse_source(lines, options)
+}
+
+// Parse a program's lines once included files are spliced in
+fn parse_source(lines: Vec<SourceLine>, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    // Expand loops and subroutine calls into the lines that actually run
+    let lines = blocks::expand(lines, options.max_expansion)?;
+
+    // Initialize a vector to store motions
+    let mut motions = Vec::new();
+    let mut state = State::new(options);
+
+    // Compensation in effect for each motion
+    let mut compensation = Vec::new();
+
+    // Parse each line, attributing motions to the program line they came from
+    for line in lines {
+        let parsed = interpret_line(line.number, &line.text, &mut state, options).map_err(|e| line.error(e))?;
+        for motion in parsed {
+            motions.push(motion.with_line(line.program_line()));
+            compensation.push(state.compensation);
+        }
+    }
+
+    // Offset the compensated parts of the path once the whole program is known
+    if compensation.iter().any(Option::is_some) {
+        motions = compensation::apply(&motions, &compensation)?;
+    }
+
+    // Return the vector of motions
+    Ok(motions)
+}
+
+/// Function to read motions from a file in the given dialect
+///
+/// Dispatches to the `.cmmd` parser or the G-code front-end. Both produce the
+/// same `Motion` values, so the result can be interpolated the same way. JSON
+/// programs hold those values already, and HP-GL plots are in plotter units
+/// rather than a program's, so the parse options don't apply to either.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::{read_program, Dialect, ParseOptions};
+///
+/// let dialect = Dialect::from_path("test.cmmd").unwrap();
+/// let motions = read_program("test.cmmd", dialect, &ParseOptions::default()).unwrap();
+/// assert_eq!(motions.len(), 8);
+/// ```
+pub fn read_program(file_path: &str, dialect: Dialect, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    match dialect {
+        Dialect::Cmmd => read_file_with_options(file_path, options),
+        Dialect::Gcode => gcode::read_file(file_path, options),
+        Dialect::Json => read_json(io::BufReader::new(File::open(file_path)?)),
+        Dialect::Hpgl => read_plot(File::open(file_path)?),
+    }
+}
+
+/// Function to read motions in the given dialect from any buffered reader
+///
+/// Like [`read_program`], for programs that don't come from a file.
+pub fn read_program_from<R: BufRead>(reader: R, dialect: Dialect, options: &ParseOptions) -> Result<Vec<Motion>, CmmdError> {
+    match dialect {
+        Dialect::Cmmd => read_reader(reader, options),
+        Dialect::Gcode => gcode::read_reader(reader, options),
+        Dialect::Json => read_json(reader),
+        Dialect::Hpgl => read_plot(reader),
+    }
+}
+
+// Read the motions of a program written as JSON
+fn read_json<R: BufRead>(reader: R) -> Result<Vec<Motion>, CmmdError> {
+    let program: Program = serde_json::from_reader(reader)?;
+    Ok(program.motions)
+}
+
+// Read an HP-GL plot as a program drawn on the Z 0 plane, travelling there with the pen up
+fn read_plot<R: io::Read>(reader: R) -> Result<Vec<Motion>, CmmdError> {
+    let drawing = hpgl::read_hpgl(reader)?;
+    Ok(drawing.motions(&DrawingOptions { clearance: 0.0, ..Default::default() }))
+}
+
+/// Interpreter for `.cmmd` lines given one at a time
+///
+/// Keeps the position and modal state (units, distance mode, feed, offsets
+/// and the rest) from one line to the next, as a controller does for manual
+/// data input. Commands that need to see more of the program than the current
+/// line (`INCLUDE`, `REPEAT`, `SUB`, `CALL` and `COMP`) are refused with
+/// `CmmdError::NotStreamable`. A line that fails leaves the interpreter ready
+/// for the next one.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::{Interpreter, ParseOptions};
+///
+/// let options = ParseOptions::default();
+/// let mut interpreter = Interpreter::new(&options);
+/// assert_eq!(interpreter.line("LIN X0 Y0 Z0").unwrap().len(), 1);
+/// assert!(interpreter.line("BOGUS").is_err());
+/// let motions = interpreter.line("LIN X5 Y0 Z0").unwrap();
+/// assert_eq!(motions[0].line(), Some(3));
+/// ```
+pub struct Interpreter<'a> {
+    number: usize,
+    state: State,
+    options: &'a ParseOptions,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Constructor for an interpreter in the state a program starts in
+    pub fn new(options: &'a ParseOptions) -> Interpreter<'a> {
+        Interpreter {
+            number: 0,
+            state: State::new(options),
+            options,
+        }
+    }
+
+    /// Function to interpret the next line
+    ///
+    /// # Returns
+    ///
+    /// The motions the line makes, tagged with its line number: none for
+    /// lines that only change the state, one for most motions and several for
+    /// cycles such as `DRILL`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `CmmdError` if the line can't be parsed or needs the rest of
+    /// the program.
+    pub fn line(&mut self, line: &str) -> Result<Vec<Motion>, CmmdError> {
+        self.number += 1;
+
+        // Blocks and includes are expanded before parsing, which needs every line
+        let stripped = strip_comments(line);
+        if let Some(command) = tokenize(&stripped).first() {
+            if matches!(command.text, "INCLUDE" | "REPEAT" | "END" | "SUB" | "ENDSUB" | "CALL") {
+                return Err(CmmdError::NotStreamable {
+                    line: self.number,
+                    command: command.text.to_string(),
+                });
+            }
+        }
+
+        let motions = interpret_line(self.number, line, &mut self.state, self.options)?;
+        // Compensation offsets each motion by the ones around it
+        if self.state.compensation.take().is_some() {
+            return Err(CmmdError::NotStreamable {
+                line: self.number,
+                command: String::from("COMP"),
+            });
+        }
+        Ok(motions.into_iter().map(|motion| motion.with_line(self.number)).collect())
+    }
+}
+
+/// Iterator over the motions of a `.cmmd` program, parsed one line at a time
+///
+/// Made by [`stream_reader`]. Only the line being parsed is held in memory, so
+/// programs of any size can be read. In exchange, lines are limited as
+/// described for [`Interpreter`]. The stream ends after the first error.
+pub struct CmmdStream<'a, R> {
+    lines: io::Lines<R>,
+    interpreter: Interpreter<'a>,
+    // Motions of the last line not handed out yet, when it made several
+    pending: VecDeque<Motion>,
+    failed: bool,
+}
+
+impl<R: BufRead> CmmdStream<'_, R> {
+    // Parse lines until one produces a motion
+    fn next_motion(&mut self) -> Result<Option<Motion>, CmmdError> {
+        while self.pending.is_empty() {
+            let Some(line) = self.lines.next() else {
+                return Ok(None);
+            };
+            self.pending.extend(self.interpreter.line(&line?)?);
+        }
+        Ok(self.pending.pop_front())
+    }
+}
+
+impl<R: BufRead> Iterator for CmmdStream<'_, R> {
+    type Item = Result<Motion, CmmdError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_motion();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+/// Function to parse a `.cmmd` program lazily from a buffered reader
+///
+/// Gives the same motions as [`read_reader`] for programs without blocks,
+/// includes or compensation, but parses each line only when its motion is
+/// asked for. See [`CmmdStream`].
+///
+/// # Examples
+///
+/// ```
+/// use project_2::parser::{stream_reader, ParseOptions};
+///
+/// let program = "LIN X0 Y0 Z0\nLIN X5 Y0 Z0\nREPEAT 2\n";
+/// let options = ParseOptions::default();
+/// let mut motions = stream_reader(program.as_bytes(), &options);
+/// assert_eq!(motions.next().unwrap().unwrap().line(), Some(1));
+/// assert!(motions.next().unwrap().is_ok());
+/// assert!(motions.next().unwrap().is_err());
+/// assert!(motions.next().is_none());
+/// ```
+pub fn stream_reader<R: BufRead>(reader: R, options: &ParseOptions) -> CmmdStream<'_, R> {
+    CmmdStream {
+        lines: reader.lines(),
+        interpreter: Interpreter::new(options),
+        pending: VecDeque::new(),
+        failed: false,
+    }
+}
+
+/// Iterator over the motions of a program in either dialect, parsed one line at a time
+pub enum ProgramStream<'a, R> {
+    /// A `.cmmd` program
+    Cmmd(CmmdStream<'a, R>),
+    /// A G-code program
+    Gcode(gcode::GcodeStream<'a, R>),
+    /// A JSON program, which has to be read whole before its first motion
+    Json(std::vec::IntoIter<Result<Motion, CmmdError>>),
+    /// An HP-GL plot, which is also read whole
+    Hpgl(std::vec::IntoIter<Result<Motion, CmmdError>>),
+}
+
+impl<R: BufRead> Iterator for ProgramStream<'_, R> {
+    type Item = Result<Motion, CmmdError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ProgramStream::Cmmd(motions) => motions.next(),
+            ProgramStream::Gcode(motions) => motions.next(),
+            ProgramStream::Json(motions) => motions.next(),
+            ProgramStream::Hpgl(motions) => motions.next(),
+        }
+    }
+}
+
+/// Function to parse a program in the given dialect lazily from a buffered reader
+///
+/// Like [`read_program_from`], for programs too large to hold in memory.
+/// G-code is always read this way; `.cmmd` programs are limited as described
+/// for [`CmmdStream`], and JSON programs and HP-GL plots are read whole.
+pub fn stream_program_from<R: BufRead>(reader: R, dialect: Dialect, options: &ParseOptions) -> ProgramStream<'_, R> {
+    match dialect {
+        Dialect::Cmmd => ProgramStream::Cmmd(stream_reader(reader, options)),
+        Dialect::Gcode => ProgramStream::Gcode(gcode::stream_reader(reader, options)),
+        Dialect::Json => {
+            let motions = match read_json(reader) {
+                Ok(motions) => motions.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            };
+            ProgramStream::Json(motions.into_iter())
+        }
+        Dialect::Hpgl => {
+            let motions = match read_plot(reader) {
+                Ok(motions) => motions.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            };
+            ProgramStream::Hpgl(motions.into_iter())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    // Import necessary items from the parent module
+    use super::*;
+
+    /// Test the `read_file` function.
+    #[test]
+    fn test_read_file() {
+        // Test reading motions from a file
+        let result = read_file("test.cmmd");
+        assert!(result.is_ok()); // Check if reading succeeds
+        let motions = result.unwrap();
+        assert_eq!(motions.len(), 8); // Check number of motions read
+    }
+
+    /// Test that errors carry the line number and column span.
+    #[test]
+    fn test_parse_line_errors() {
+        let mut state = State::default();
+        let options = ParseOptions::default();
+        let err = parse_line(3, "  JMP X1 Y2 Z3", &mut state, &options).unwrap_err();
+        assert_eq!(err.line(), 3);
+        assert_eq!(err.span(), &Span(2..5));
+
+        let err = parse_line(7, "LIN X1 Y2", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::MissingArguments { expected: 3, found: 2, .. }));
+        assert_eq!(err.to_string(), "line 7, column 1-9: `LIN` expects 3 arguments, found 2");
+    }
+
+    /// Test that tuples leave axes out with `_`, and lines of bare coordinates repeat the last motion command.
+    #[test]
+    fn test_modal_coordinates() {
+        let program = "F300\nLIN (10, 0, -1)\nLIN (_, 20, _)\nX0\nY5 Z0\nRAP X1 Y1 Z5\n(_, 2, _)\n\
+                       CW X3 Y4 I2 J0\nX5 Y2 I0 J-2\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let ends: Vec<_> = motions.iter().map(|motion| transform::ends(motion, 5.0).1).collect();
+        let expected = [
+            (10.0, 0.0, -1.0),
+            (10.0, 20.0, -1.0),
+            (0.0, 20.0, -1.0),
+            (0.0, 5.0, 0.0),
+            (1.0, 1.0, 5.0),
+            (1.0, 2.0, 5.0),
+            (3.0, 4.0, 5.0),
+            (5.0, 2.0, 5.0),
+        ];
+        assert_eq!(ends.len(), expected.len());
+        for (end, expected) in ends.iter().zip(expected) {
+            assert!((end.0 - expected.0).abs() < 1e-9 && (end.1 - expected.1).abs() < 1e-9 && end.2 == expected.2);
+        }
+        assert!(motions[2..4].iter().all(|motion| motion.feed() == Some(300.0) && !motion.is_rapid()));
+        assert!(motions[4..6].iter().all(Motion::is_rapid));
+        assert!(matches!(&motions[6..], [Motion::Rotational(a), Motion::Rotational(b)] if a.sweep == b.sweep));
+
+        // Nothing to repeat at the start, or after an arc given by its angles, and tuples need every axis
+        for program in ["X5 Y0\n", "CW X5 Y0 R5 A90\nX1\n"] {
+            let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+            assert!(matches!(err, CmmdError::Parse(ParseError::UnknownCommand { .. })));
+        }
+        let err = read_reader("LIN (1, 2)\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::MissingArguments { expected: 3, found: 2, .. })));
+    }
+
+    /// Test that the words of a move set the axis their letter names, whatever order they come in.
+    #[test]
+    fn test_axis_words() {
+        let motions = read_reader("LIN Y5 X3 Z0\nRAP Z2 X1 Y-1\n".as_bytes(), &ParseOptions::default()).unwrap();
+        let ends: Vec<_> = motions.iter().map(|motion| transform::ends(motion, 1.0).1).collect();
+        assert_eq!(ends, [(3.0, 5.0, 0.0), (1.0, -1.0, 2.0)]);
+
+        // Giving an axis twice is an error, not a move to the last of them
+        let twice = [("LIN X1 X2 Z0\n", "X2"), ("LIN Z1 2 3\n", "3"), ("LIN X0 Y0 Z0\nX1 Y1 X2\n", "X2")];
+        for (program, expected) in twice {
+            let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+            assert!(matches!(err, CmmdError::Parse(ParseError::UnexpectedWord { ref word, .. }) if word == expected));
+        }
+    }
+
+    /// Test that strict mode rejects numbers the lenient mode reads as zero.
+    #[test]
+    fn test_strict_numbers() {
+        let mut state = State::default();
+        let lenient = ParseOptions::default();
+        let motion = parse_line(1, "LIN X1 Yabc Z3", &mut state, &lenient).unwrap();
+        assert_eq!(motion, Some(Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 3.0))));
+
+        let strict = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = parse_line(2, "CW X1 Y1 R2.5.0 A90", &mut state, &strict).unwrap_err();
+        assert_eq!(err.span(), &Span(9..15));
+        assert!(matches!(err, ParseError::InvalidNumber { line: 2, .. }));
+
+        // Numbers that parse but aren't finite are no better
+        for word in ["Xinf", "Xnan", "X1e999"] {
+            let err = parse_line(3, &format!("LIN {} Y0 Z0", word), &mut state, &strict).unwrap_err();
+            assert!(matches!(err, ParseError::InvalidNumber { line: 3, ref text, .. } if text == word));
+        }
+        assert!(parse_line(4, "ROTATE inf", &mut state, &lenient).is_err());
+    }
+
+    /// Test arcs given by end point and I/J center offset.
+    #[test]
+    fn test_center_offset_arc() {
+        let mut state = State {
+            position: (5.0, 5.0, 5.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let motion = parse_line(1, "CW X7.5 Y7.5 Z5 I1.25 J1.25 K0", &mut state, &options).unwrap();
+        assert_eq!(
+            motion,
+            Some(Motion::new_arc_from_offset((5.0, 5.0, 5.0), (7.5, 7.5, 5.0), (1.25, 1.25), true))
+        );
+        assert_eq!(state.position, (7.5, 7.5, 5.0));
+
+        let err = parse_line(2, "CCW X1 I1 R2", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedWord { .. }));
+        assert_eq!(err.span(), &Span(10..12));
+    }
+
+    /// Test arcs given by end point and R radius, told apart from arcs given by angles by their words.
+    #[test]
+    fn test_radius_arc() {
+        let mut state = State {
+            position: (10.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let motion = parse_line(1, "CW X0 Y-10 R10", &mut state, &options).unwrap();
+        assert_eq!(motion, Motion::new_arc_from_radius((10.0, 0.0, 0.0), (0.0, -10.0, 0.0), 10.0, true));
+        let Some(Motion::Rotational(arc)) = parse_line(2, "CCW X10 Y0 R-10", &mut state, &options).unwrap() else {
+            panic!("expected an arc")
+        };
+        assert!((arc.center.0 - 10.0).abs() < 1e-9 && (arc.center.1 + 10.0).abs() < 1e-9);
+        assert_eq!(state.position, (10.0, 0.0, 0.0));
+
+        let err = parse_line(3, "CW X40 Y0 R10", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::ArcRadius { line: 3, radius: 10.0, chord: 30.0, .. }));
+        // The arc that failed left the tool where it was, and an angle still makes it an arc given by its center
+        assert_eq!(state.position, (10.0, 0.0, 0.0));
+        let motion = parse_line(4, "CW X7 Y0 R3 A90", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_rotational((7.0, 0.0), 3.0, 0.0, -270.0)));
+    }
+
+    /// Test that I/J arcs need a center away from the start, and an end point on its circle, as in G-code.
+    #[test]
+    fn test_offset_arc_errors() {
+        let mut state = State {
+            position: (0.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let err = parse_line(1, "CW X10 Y0 I3 J0", &mut state, &options).unwrap_err();
+        let ParseError::ArcEndOffCircle { line: 1, start_radius, end_radius, .. } = err else {
+            panic!("expected the arc to end off its circle")
+        };
+        assert_eq!((start_radius, end_radius), (3.0, 7.0));
+        let err = parse_line(2, "CCW X10 Y0 I0 J0", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::MissingArcCenter { line: 2, .. }));
+        // Neither moved the tool, and rounding in the last digits is fine
+        assert_eq!(state.position, (0.0, 0.0, 0.0));
+        assert!(parse_line(3, "CW X6.001 Y0 I3 J0", &mut state, &options).is_ok());
+        assert_eq!(state.position, (6.001, 0.0, 0.0));
+    }
+
+    /// Test NURBS curves with weights, knots and an inline feed rate.
+    #[test]
+    fn test_nurbs() {
+        let mut state = State::default();
+        let options = ParseOptions::default();
+        let line = "NURBS D2 (10, 10, 0, 0.5) (20, 0, 0) K(0, 0, 0, 1, 1, 1) F200";
+        let Some(Motion::Spline(curve)) = parse_line(1, line, &mut state, &options).unwrap() else {
+            panic!("expected a spline")
+        };
+        assert_eq!(curve.controls, vec![(0.0, 0.0, 0.0), (10.0, 10.0, 0.0), (20.0, 0.0, 0.0)]);
+        assert_eq!((curve.weights, curve.degree, curve.feed), (vec![1.0, 0.5, 1.0], 2, Some(200.0)));
+        assert_eq!(state.position, (20.0, 0.0, 0.0));
+
+        // Two points are too few for the knots of a quadratic
+        let err = parse_line(2, "NURBS D2 (30, 0, 0) K(0, 1)", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidSpline { line: 2, .. }));
+        let err = parse_line(3, "NURBS (30, 0)", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::MissingArguments { expected: 3, found: 2, .. }));
+    }
+
+    /// Test Bézier curves given by end point and control point offsets.
+    #[test]
+    fn test_bezier() {
+        let mut state = State {
+            position: (0.0, 0.0, 1.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let motion = parse_line(1, "BEZ X10 Y0 I0 J5 P0 Q5 F300", &mut state, &options).unwrap();
+        let Some(Motion::Bezier(curve)) = motion else { panic!("expected a curve") };
+        assert_eq!((curve.control1, curve.control2), ((0.0, 5.0, 1.0), (10.0, 5.0, 1.0)));
+        assert_eq!((curve.end, curve.feed), ((10.0, 0.0, 1.0), Some(300.0)));
+        assert_eq!(state.position, (10.0, 0.0, 1.0));
+
+        state.compensation = Some(Compensation {
+            side: Side::Left,
+            radius: 1.0,
+        });
+        let err = parse_line(2, "BEZ X0 Y0 I0 J-5", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::CompensatedCurve { line: 2, .. }));
+    }
+
+    /// Test the optional start angle of rotational motions, and which way round they go to the stop angle.
+    #[test]
+    fn test_start_angle() {
+        // The point at an angle on the circle the arcs below are drawn on
+        fn on_circle(degrees: f64) -> (f64, f64, f64) {
+            let (sin, cos) = degrees.to_radians().sin_cos();
+            (1.0 + 3.0 * cos, 2.0 + 3.0 * sin, 0.0)
+        }
+        let mut state = State::default();
+        let options = ParseOptions::default();
+        state.position = on_circle(45.0);
+        let motion = parse_line(1, "CCW X1 Y2 R3 A180 S45", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_rotational((1.0, 2.0), 3.0, 45.0, 135.0)));
+        state.position = on_circle(45.0);
+        let motion = parse_line(2, "CW X1 Y2 R3 A180 S45", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_rotational((1.0, 2.0), 3.0, 45.0, -225.0)));
+        state.position = on_circle(0.0);
+        let motion = parse_line(3, "CW X1 Y2 R3 A-90", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_rotational((1.0, 2.0), 3.0, 0.0, -90.0)));
+        // Stop angles more than a turn ahead add turns, and one on the start is a full circle
+        state.position = on_circle(0.0);
+        let motion = parse_line(4, "CCW X1 Y2 R3 A720 S0 Z0 Z-4", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_helical((1.0, 2.0), 3.0, 0.0, 720.0, (0.0, -4.0))));
+        state.position = on_circle(0.0);
+        let motion = parse_line(5, "CW X1 Y2 R3 A-720 S0 Z0 Z-4", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_helical((1.0, 2.0), 3.0, 0.0, -720.0, (0.0, -4.0))));
+        state.position = on_circle(30.0);
+        let motion = parse_line(6, "CW X1 Y2 R3 A30 S30", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_rotational((1.0, 2.0), 3.0, 30.0, -360.0)));
+    }
+
+    /// Test that arcs given by their center continue from where the tool is, and must start on their circle.
+    #[test]
+    fn test_arc_continues() {
+        let mut state = State {
+            position: (4.0, 2.0, -1.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        // Without a start angle the arc sets off from the tool, and the next motion from the arc's end
+        let motion = parse_line(1, "CCW X1 Y2 R3 A90", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_rotational((1.0, 2.0), 3.0, 0.0, 90.0)));
+        let (x, y, z) = state.position;
+        assert!((x - 1.0).abs() < 1e-9 && (y - 5.0).abs() < 1e-9 && z == -1.0);
+        let Some(Motion::Linear(line)) = parse_line(2, "LIN X1 Y10 Z-1", &mut state, &options).unwrap() else {
+            panic!("expected a line")
+        };
+        assert_eq!(line.start, (x, y, z));
+
+        // A start angle has to be where the tool is, give or take rounding in the last digits
+        state.position = (4.001, 2.0, 0.0);
+        assert!(parse_line(3, "CW X1 Y2 R3 A270 S0", &mut state, &options).is_ok());
+        state.position = (4.0, 2.0, 0.0);
+        let err = parse_line(4, "CW X1 Y2 R3 A180 S90", &mut state, &options).unwrap_err();
+        let ParseError::ArcStartOffCircle { line: 4, position, start, .. } = err else {
+            panic!("expected the arc to start away from the tool")
+        };
+        assert_eq!(position, (4.0, 2.0));
+        assert!((start.0 - 1.0).abs() < 1e-9 && (start.1 - 5.0).abs() < 1e-9);
+        // Without one, the tool has to be on the circle
+        let err = parse_line(5, "CIRCLE X0 Y0 R5", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::ArcStartOffCircle { line: 5, .. }));
+
+        // A circle needs a size, whatever the tool's distance from the center
+        state.position = (0.0, 0.0, 0.0);
+        for radius in ["R0", "R-5", "Rinf", "RNaN"] {
+            let err = parse_line(6, &format!("CIRCLE X0 Y0 {}", radius), &mut state, &options).unwrap_err();
+            assert!(matches!(err, ParseError::InvalidNumber { line: 6, ref text, .. } if text == radius));
+        }
+    }
+
+    /// Test that an arc right after a ROTATE or MIRROR starts where the tool really is, not where the new transform
+    /// would have put it.
+    #[test]
+    fn test_transformed_arc_start() {
+        let starts = |program: &str| {
+            let motions = read_reader(program.as_bytes(), &ParseOptions::default())?;
+            let (start, end) = transform::ends(&motions[1], 0.0);
+            Ok::<_, CmmdError>((start.0.round(), start.1.round(), end.0.round(), end.1.round()))
+        };
+        // The tool at (5, 0) is at -90 degrees of the turned program, so the arc sets off from there
+        let arc = starts("LIN X5 Y0 Z0\nROTATE 90\nCCW X0 Y0 R5 A180\n").unwrap();
+        assert_eq!(arc, (5.0, 0.0, -0.0, -5.0));
+        let arc = starts("LIN X5 Y0 Z0\nROTATE 90\nCCW X0 Y0 R5 A0 S270\n").unwrap();
+        assert_eq!(arc, (5.0, 0.0, 0.0, 5.0));
+        let err = starts("LIN X5 Y0 Z0\nROTATE 90\nCCW X0 Y0 R5 A180 S0\n").unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::ArcStartOffCircle { line: 3, .. })));
+
+        // Mirrored, the tool is at 180 degrees, and the arc turns the other way on the machine
+        let arc = starts("LIN X5 Y0 Z0\nMIRROR X\nCCW X0 Y0 R5 A270\n").unwrap();
+        assert_eq!(arc, (5.0, 0.0, 0.0, -5.0));
+        let err = starts("LIN X5 Y0 Z0\nMIRROR X\nCCW X0 Y0 R5 A270 S0\n").unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::ArcStartOffCircle { line: 3, .. })));
+    }
+
+    /// Test that a feed rate applies to the motions after it.
+    #[test]
+    fn test_feed_rate() {
+        let mut state = State::default();
+        let options = ParseOptions::default();
+        let motion = parse_line(1, "LIN X1 Y0 Z0", &mut state, &options).unwrap().unwrap();
+        assert_eq!(motion.feed(), None);
+        assert_eq!(parse_line(2, "F600", &mut state, &options).unwrap(), None);
+        let motion = parse_line(3, "CW X0 Y0 R1 A90", &mut state, &options).unwrap().unwrap();
+        assert_eq!(motion.feed(), Some(600.0));
+    }
+
+    /// Test that line and inline comments are ignored without moving error columns.
+    #[test]
+    fn test_comments() {
+        let mut state = State::default();
+        let options = ParseOptions::default();
+        assert_eq!(parse_line(1, "# setup", &mut state, &options).unwrap(), None);
+        assert_eq!(parse_line(2, "  ; nothing here", &mut state, &options).unwrap(), None);
+
+        let motion = parse_line(3, "LIN (corner) X1 Y2 Z3 ; first cut", &mut state, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(motion, Motion::new_linear((0.0, 0.0, 0.0), (1.0, 2.0, 3.0)));
+
+        let err = parse_line(4, "(é) BOGUS", &mut state, &options).unwrap_err();
+        assert_eq!(err.span(), &Span(5..10));
+
+        // Coordinate tuples aren't comments
+        assert_eq!(strip_comments("WCS 1 (1, -2.5, 0) (vise)"), "WCS 1 (1, -2.5, 0)       ");
+    }
+
+    /// Test that INC reads end points as offsets until ABS switches back.
+    #[test]
+    fn test_distance_modes() {
+        let mut state = State::default();
+        let options = ParseOptions::default();
+        let program = ["LIN X1 Y1 Z0", "INC", "LIN X2 Y0 Z-1", "CCW X-2 I-1 J0", "ABS", "LIN X0 Y0 Z0"];
+        let mut ends = Vec::new();
+        for line in program {
+            if parse_line(1, line, &mut state, &options).unwrap().is_some() {
+                ends.push(state.position);
+            }
+        }
+        assert_eq!(ends[0], (1.0, 1.0, 0.0));
+        assert_eq!(ends[1], (3.0, 1.0, -1.0));
+        assert_eq!(ends[3], (0.0, 0.0, 0.0));
+        assert_eq!(state.distance, DistanceMode::Absolute);
+    }
+
+    /// Test that inch programs are converted to millimeters.
+    #[test]
+    fn test_units() {
+        let options = ParseOptions {
+            units: Units::Inches,
+            ..Default::default()
+        };
+        let motions = read_reader("F10\nLIN X1 Y0 Z0\nUNITS MM\nLIN X1 Y0 Z0\n".as_bytes(), &options).unwrap();
+        assert_eq!(motions[0], Motion::new_linear((0.0, 0.0, 0.0), (25.4, 0.0, 0.0)).with_feed(Some(254.0)).with_line(2));
+        assert_eq!(motions[1], Motion::new_linear((25.4, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(254.0)).with_line(4));
+
+        let mut state = State::default();
+        assert!(parse_line(1, "UNITS FEET", &mut state, &ParseOptions::default()).is_err());
+    }
+
+    /// Test that work offsets move programmed coordinates into machine coordinates.
+    #[test]
+    fn test_work_offsets() {
+        let mut options = ParseOptions::default();
+        options.work_offsets.insert(2, (100.0, 0.0, 0.0));
+        let program = "WCS 1 (10, 20, -5) ; vise\nUSE WCS 1\nLIN X1 Y1 Z0\nUSE WCS 2\nLIN X5 Y0 Z0\nCW X0 Y0 R5 A90\n\
+                       USE WCS 0\nLIN X0 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+
+        let Motion::Linear(first) = &motions[0] else { panic!("expected a line") };
+        assert_eq!(first.end, (11.0, 21.0, -5.0));
+        let Motion::Rotational(arc) = &motions[2] else { panic!("expected an arc") };
+        assert_eq!(arc.center, (100.0, 0.0));
+        let Motion::Linear(last) = &motions[3] else { panic!("expected a line") };
+        assert_eq!(last.end, (0.0, 0.0, 0.0));
+
+        let err = read_reader("USE WCS 3".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnknownWorkOffset { number: 3, .. })));
+        let err = read_reader("WCS 1 (1, 2)".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::MissingArguments { expected: 3, found: 2, .. })));
+    }
+
+    /// Test turning motions about a center, and starting the next line from where the tool is.
+    #[test]
+    fn test_rotate() {
+        let program = "LIN X10 Y0 Z0\nROTATE 90 ABOUT (10, 0)\nLIN X20 Y0 Z0\nCCW X10 Y10 Z0 I-10 J0\nROTATE OFF\nLIN X0 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert!(crate::validate::continuity(&motions).is_empty());
+
+        let Motion::Linear(turned) = &motions[1] else { panic!("expected a line") };
+        assert!((turned.end.0 - 10.0).abs() < 1e-9 && (turned.end.1 - 10.0).abs() < 1e-9);
+        // The arc ran from (20, 0) to (10, 10) around (10, 0), so it turns to end at (0, 0)
+        let Motion::Rotational(arc) = &motions[2] else { panic!("expected an arc") };
+        let arc = crate::post::arc_move(arc, 0.0);
+        assert!(arc.end.0.abs() < 1e-9 && arc.end.1.abs() < 1e-9 && !arc.clockwise);
+        // Back from where the turned arc ended
+        let Motion::Linear(last) = &motions[3] else { panic!("expected a line") };
+        assert_eq!(last.end, (0.0, 0.0, 0.0));
+
+        let program = "PLANE XZ\nROTATE 45\nCW X10 Y0 Z0 I5 K0\n";
+        let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::TransformedArc { line: 3, .. })));
+        let err = read_reader("ROTATE 45 ABOVE (1, 2)".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnexpectedWord { .. })));
+    }
+
+    /// Test scaling about a point, per axis and together with a rotation.
+    #[test]
+    fn test_scale() {
+        let program = "SCALE 2 ABOUT (10, 0)\nLIN X20 Y5 Z-1\nROTATE 90\nLIN X20 Y5 Z-1\nSCALE OFF\nROTATE OFF\nLIN X20 Y5 Z-1\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let ends: Vec<_> = motions
+            .iter()
+            .map(|motion| match motion {
+                Motion::Linear(line) => (line.end.0.round(), line.end.1.round(), line.end.2.round()),
+                _ => panic!("expected a line"),
+            })
+            .collect();
+        assert_eq!(ends, vec![(30.0, 10.0, -2.0), (-10.0, 30.0, -2.0), (20.0, 5.0, -1.0)]);
+
+        // Arcs grow with an even scale, but can't be stretched into ellipses
+        let program = "SCALE (3, 3, 1)\nLIN X5 Y0 Z0\nCCW X0 Y0 R5 A90\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let Motion::Rotational(arc) = &motions[1] else { panic!("expected an arc") };
+        assert_eq!(arc.radius, 15.0);
+        let program = "SCALE (3, 1, 1)\nLIN X5 Y0 Z0\nCCW X0 Y0 R5 A90\n";
+        let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::TransformedArc { line: 3, .. })));
+        let err = read_reader("SCALE 0".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::InvalidNumber { .. })));
+    }
+
+    /// Test that mirrored arcs flip direction and stay on the mirrored path.
+    #[test]
+    fn test_mirror() {
+        let program = "LIN X10 Y0 Z0\nCW X0 Y-10 Z0 I-10 J0\nMIRROR X ABOUT (5, 0)\nLIN X10 Y0 Z0\nCW X0 Y-10 Z0 I-10 J0\nMIRROR OFF\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+
+        let Motion::Rotational(plain) = &motions[1] else { panic!("expected an arc") };
+        let Motion::Rotational(mirrored) = &motions[3] else { panic!("expected an arc") };
+        let (plain, mirrored) = (crate::post::arc_move(plain, 0.0), crate::post::arc_move(mirrored, 0.0));
+        assert!(plain.clockwise && !mirrored.clockwise);
+        assert!((mirrored.start.0 - 0.0).abs() < 1e-9 && (mirrored.end.0 - 10.0).abs() < 1e-9);
+        assert!((mirrored.end.1 + 10.0).abs() < 1e-9 && (mirrored.sweep - 90.0).abs() < 1e-9);
+
+        let motions = read_reader("MIRROR X Y\nLIN X1 Y2 Z3\n".as_bytes(), &ParseOptions::default()).unwrap();
+        let Motion::Linear(line) = &motions[0] else { panic!("expected a line") };
+        assert_eq!(line.end, (-1.0, -2.0, 3.0));
+        let err = read_reader("MIRROR Z".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnexpectedWord { .. })));
+    }
+
+    /// Test that A/B/C words turn the rotary axes and stay modal.
+    #[test]
+    fn test_rotary() {
+        let program = "LIN X1 Y0 Z0\nLIN X2 Y0 Z0 A90\nINC\nLIN X0 Y0 Z0 C-45 A10\nABS\nCW X0 Y0 R2 A90\nLIN X0 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let rotary = |index: usize| match &motions[index] {
+            Motion::Linear(line) => line.rotary.map(|(from, to)| ((from.a, from.c), (to.a, to.c))),
+            _ => panic!("expected a line"),
+        };
+        assert_eq!(rotary(0), None);
+        assert_eq!(rotary(1), Some(((0.0, 0.0), (90.0, 0.0))));
+        assert_eq!(rotary(2), Some(((90.0, 0.0), (100.0, -45.0))));
+        assert_eq!(rotary(4), Some(((100.0, -45.0), (100.0, -45.0))));
+
+        // Words that are neither a rotary axis nor a tool direction are rejected
+        for program in ["LIN 1 2 3 Q45\n", "RAP X0 Y0 Z0 D5\n"] {
+            let err = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap_err();
+            assert!(matches!(err, CmmdError::Parse(ParseError::UnexpectedWord { line: 1, .. })));
+        }
+    }
+
+    /// Test that I/J/K words on linear moves tilt the tool and stay modal.
+    #[test]
+    fn test_tool_vector() {
+        let program = "LIN X1 Y0 Z0\nLIN X2 Y0 Z0 I1 K1\nRAP X2 Y0 Z5\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert!(matches!(motions[0], Motion::Linear(_)));
+        let Motion::Oriented(tilt) = &motions[1] else { panic!("expected a tilting move") };
+        let half = 0.5f64.sqrt();
+        assert_eq!(tilt.orientation.0, (0.0, 0.0, 1.0));
+        assert!((tilt.orientation.1 .0 - half).abs() < 1e-12 && (tilt.orientation.1 .2 - half).abs() < 1e-12);
+        assert!((tilt.turned() - 45.0).abs() < 1e-9);
+        let Motion::Oriented(lift) = &motions[2] else { panic!("expected a tilting move") };
+        assert!(lift.rapid && lift.orientation.0 == lift.orientation.1);
+
+        let err = read_reader("LIN X0 Y0 Z0 A10\nLIN X0 Y0 Z0 K1\n".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnexpectedWord { line: 2, .. })));
+    }
+
+    /// Test that lathe programs read X as a diameter, arc in XZ and reject Y.
+    #[test]
+    fn test_lathe() {
+        let options = ParseOptions {
+            lathe: true,
+            ..Default::default()
+        };
+        let program = "RAP X40 Z2\nLIN X40 Z-10\nINC\nLIN X10 Z0\nABS\nCW X50 Z-20 I0 K-5\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+        let Motion::Linear(face) = &motions[2] else { panic!("expected a line") };
+        assert_eq!((face.start, face.end), ((20.0, 0.0, -10.0), (25.0, 0.0, -10.0)));
+        let Motion::Rotational(arc) = &motions[3] else { panic!("expected an arc") };
+        assert_eq!((arc.plane, arc.center), (Plane::Xz, (25.0, -15.0)));
+
+        let err = read_reader("LIN X10 Y0 Z0".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::LatheY { line: 1, .. })));
+        let motions = read_reader("LIN Z-10 X40\n".as_bytes(), &options).unwrap();
+        assert_eq!(transform::ends(&motions[0], 1.0).1, (20.0, 0.0, -10.0));
+    }
+
+    /// Test that the active tool's length is added to Z.
+    #[test]
+    fn test_tool_length() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 40.0, diameter: 6.0 });
+        let program = "TOOL 1\nLIN X5 Y0 Z-2\nCW X0 Y0 R5 A90 0 Z0 Z-1\nTOOL 0\nLIN X0 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+
+        assert_eq!(motions[0], Motion::new_tool_change(1, (0.0, 0.0, 0.0)).with_line(1));
+        let Motion::Linear(first) = &motions[1] else { panic!("expected a line") };
+        assert_eq!(first.end, (5.0, 0.0, 38.0));
+        let Motion::Rotational(helix) = &motions[2] else { panic!("expected an arc") };
+        assert_eq!(helix.z, Some((40.0, 39.0)));
+        assert!(matches!(&motions[3], Motion::ToolChange(change) if change.tool == 0 && change.line == Some(4)));
+        let Motion::Linear(last) = &motions[4] else { panic!("expected a line") };
+        assert_eq!(last.end, (0.0, 0.0, 0.0));
+
+        let err = read_reader("TOOL 7".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnknownTool { number: 7, .. })));
+
+        // `M6` takes tools the table doesn't list, with no length
+        let motions = read_reader("M6 T1\nM6 T 7\nLIN X0 Y0 Z1\n".as_bytes(), &options).unwrap();
+        assert_eq!(motions[1], Motion::new_tool_change(7, (0.0, 0.0, 0.0)).with_line(2));
+        let Motion::Linear(last) = &motions[2] else { panic!("expected a line") };
+        assert_eq!(last.end, (0.0, 0.0, 1.0));
+    }
+
+    /// Test that coolant and user-defined M-codes are kept as written, where the tool is.
+    #[test]
+    fn test_aux_commands() {
+        let program = "LIN X5 Y0 Z0\nM8\nM101 P2 # part done\nM9\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(motions[1], Motion::new_aux("M8", (5.0, 0.0, 0.0)).with_line(2));
+        assert_eq!(motions[2], Motion::new_aux("M101 P2", (5.0, 0.0, 0.0)).with_line(3));
+        assert_eq!(motions.len(), 4);
+
+        let err = read_reader("M3".as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::UnknownCommand { .. })));
+    }
+
+    /// Test that PAUSE and M0 stop the program where the tool is, and M1 optionally.
+    #[test]
+    fn test_pauses() {
+        let program = "LIN X5 Y0 Z0\nPAUSE\nM0\nM1\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(motions[1], Motion::new_pause(false, (5.0, 0.0, 0.0)).with_line(2));
+        assert_eq!(motions[2], Motion::new_pause(false, (5.0, 0.0, 0.0)).with_line(3));
+        assert_eq!(motions[3], Motion::new_pause(true, (5.0, 0.0, 0.0)).with_line(4));
+    }
+
+    /// Test that COMP offsets motions by the active tool's radius.
+    #[test]
+    fn test_cutter_compensation() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 0.0, diameter: 6.0 });
+        let program = "TOOL 1\nLIN X-5 Y0 Z0\nCOMP RIGHT\nLIN X0 Y0 Z0\nLIN X10 Y0 Z0\nCOMP OFF\nLIN X15 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+        assert_eq!(motions.len(), 5);
+        let Motion::Linear(cut) = &motions[3] else { panic!("expected a line") };
+        assert_eq!((cut.start, cut.end), ((0.0, -3.0, 0.0), (10.0, -3.0, 0.0)));
+        assert_eq!(cut.line, Some(5));
+
+        let err = read_reader("COMP LEFT".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::NoToolDiameter { line: 1, .. })));
+    }
+
+    /// Test that streaming gives the same motions as reading the whole program, and refuses compensation.
+    #[test]
+    fn test_stream_reader() {
+        let options = ParseOptions::default();
+        let program = std::fs::read_to_string("test.cmmd").unwrap();
+        let streamed: Result<Vec<Motion>, CmmdError> = stream_reader(program.as_bytes(), &options).collect();
+        assert_eq!(streamed.unwrap(), read_reader(program.as_bytes(), &options).unwrap());
+
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 0.0, diameter: 6.0 });
+        let program = "TOOL 1\nLIN X-5 Y0 Z0\nCOMP RIGHT\nLIN X0 Y0 Z0\n";
+        let mut motions = stream_reader(program.as_bytes(), &options);
+        assert!(motions.next().unwrap().unwrap().is_event());
+        assert!(motions.next().unwrap().is_ok());
+        let err = motions.next().unwrap().unwrap_err();
+        assert!(matches!(err, CmmdError::NotStreamable { line: 3, ref command } if command == "COMP"));
+        assert!(motions.next().is_none());
+    }
+
+    /// Test that the interpreter keeps position and modes between lines, and carries on after a bad one.
+    #[test]
+    fn test_interpreter() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 0.0, diameter: 6.0 });
+        let mut interpreter = Interpreter::new(&options);
+        for line in ["F300", "LIN X1 Y1 Z0", "INC", "TOOL 1"] {
+            interpreter.line(line).unwrap();
+        }
+        assert!(interpreter.line("LIN X1 Y").is_err());
+        assert!(matches!(interpreter.line("COMP LEFT"), Err(CmmdError::NotStreamable { line: 6, .. })));
+        let motion = interpreter.line("LIN X1 Y0 Z-1").unwrap().remove(0);
+        assert_eq!(motion.line(), Some(7));
+        assert_eq!(motion.feed(), Some(300.0));
+        let Motion::Linear(line) = motion else { panic!("expected a line") };
+        assert_eq!((line.start, line.end), ((1.0, 1.0, 0.0), (2.0, 1.0, -1.0)));
+    }
+
+    /// Test that an F word on a motion's line overrides the modal feed rate for that motion only.
+    #[test]
+    fn test_feed_override() {
+        let program = "F600\nLIN X1 Y0 Z0\nLIN X2 Y0 F100 Z0\nCW X0 Y0 Z0 I-1 J0 F50\nLIN X0 Y1 Z0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let feeds: Vec<_> = motions.iter().map(Motion::feed).collect();
+        assert_eq!(feeds, vec![Some(600.0), Some(100.0), Some(50.0), Some(600.0)]);
+        let Motion::Linear(line) = &motions[1] else { panic!("expected a line") };
+        assert_eq!(line.end, (2.0, 0.0, 0.0));
+    }
+
+    /// Test that RAP moves are flagged as rapids and don't take the feed rate.
+    #[test]
+    fn test_rapid() {
+        let program = "F300\nRAP X0 Y0 Z5\nLIN X0 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert!(motions[0].is_rapid() && !motions[1].is_rapid());
+        assert_eq!((motions[0].feed(), motions[1].feed()), (None, Some(300.0)));
+
+        let mut output = Vec::new();
+        crate::post::write_gcode(&mut output, &motions).unwrap();
+        assert!(String::from_utf8(output).unwrap().contains("G0 X0 Y0 Z5\nG1 X0 Y0 Z0 F300\n"));
+    }
+
+    /// Test that a peck drilling cycle makes its moves from one line, and the next motion starts above the hole.
+    #[test]
+    fn test_drill() {
+        let program = "F200\nRAP X0 Y0 Z5\nINC\nPECK X10 Y0 Z-9 R-3 Q4 F50\nABS\nLIN X20 Y0 Z5\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(motions.len(), 8);
+        let cycle = &motions[1..7];
+        assert!(cycle.iter().all(|motion| motion.line() == Some(4)));
+        let ends: Vec<_> = cycle.iter().map(|motion| (transform::ends(motion, 0.0).1, motion.feed())).collect();
+        assert_eq!(
+            ends,
+            vec![
+                ((10.0, 0.0, 5.0), None),
+                ((10.0, 0.0, 2.0), None),
+                ((10.0, 0.0, -2.0), Some(50.0)),
+                ((10.0, 0.0, -1.5), None),
+                ((10.0, 0.0, -4.0), Some(50.0)),
+                ((10.0, 0.0, 5.0), None),
+            ]
+        );
+        let Motion::Linear(line) = &motions[7] else { panic!("expected a line") };
+        assert_eq!((line.start, line.feed), ((10.0, 0.0, 5.0), Some(200.0)));
+
+        for bad in ["PECK X0 Y0 Z-5 R2 Q0", "DRILL X0 Y0 Z5 R2", "DRILL X0 Y0 Z-5"] {
+            let err = read_reader(bad.as_bytes(), &ParseOptions::default()).unwrap_err();
+            assert!(matches!(err, CmmdError::Parse(ParseError::InvalidCycle { line: 1, .. })), "{bad}");
+        }
+    }
+
+    /// Test that a pocket keeps the active tool inside its walls, and is refused if the tool doesn't fit.
+    #[test]
+    fn test_pocket() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 0.0, diameter: 4.0 });
+        let program = "TOOL 1\nRAP X0 Y0 Z5\nPOCKET X0 Y0 W10 H12 Z-2 R1 P1.5 Q1 SPIRAL F100\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+        let cuts: Vec<&Motion> = motions[2..].iter().filter(|motion| !motion.is_rapid()).collect();
+        assert!(motions[2..].iter().all(|motion| motion.line() == Some(3)));
+        assert!(cuts.iter().all(|motion| motion.feed() == Some(100.0)));
+        assert!(cuts.iter().all(|motion| {
+            let end = transform::ends(motion, 0.0).1;
+            (2.0..=8.0).contains(&end.0) && (2.0..=10.0).contains(&end.1) && end.2 >= -2.0
+        }));
+
+        let program = "TOOL 1\nPOCKET X0 Y0 W3 H12 Z-2 R1 P1.5 Q1\n";
+        let err = read_reader(program.as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::InvalidCycle { line: 2, .. })));
+
+        // No tool, or tool 0, is no pocket at all
+        for tools in ["", "TOOL 1\nTOOL 0\n"] {
+            let program = format!("{}POCKET X0 Y0 W10 H12 Z-2 R1 P1.5 Q1\n", tools);
+            let err = read_reader(program.as_bytes(), &options).unwrap_err();
+            let CmmdError::Parse(ParseError::NoToolDiameter { command, .. }) = err else {
+                panic!("expected the pocket to need a tool")
+            };
+            assert_eq!(command, "POCKET");
+        }
+    }
+
+    /// Test that a thread is milled around the active tool's radius, and that keywords and units apply.
+    #[test]
+    fn test_thread() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 0.0, diameter: 4.0 });
+        let program = "TOOL 1\nUNITS INCH\nTHREAD X1 Y0 D0.5 P0.05 Z-0.2 R0 LEFT F10\n";
+        let motions = read_reader(program.as_bytes(), &options).unwrap();
+        let turns: Vec<_> = motions
+            .iter()
+            .filter_map(|motion| match motion {
+                Motion::Rotational(arc) => Some(arc),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(turns.len(), 4);
+        assert!(turns.iter().all(|arc| arc.center == (25.4, 0.0) && (arc.radius - 4.35).abs() < 1e-9));
+        assert!(turns.iter().all(|arc| arc.feed == Some(254.0) && arc.line == Some(3)));
+
+        let err = read_reader("TOOL 1\nTHREAD X0 Y0 D3 P1 Z-5 R1\n".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::InvalidCycle { line: 2, .. })));
+        let err = read_reader("THREAD X0 Y0 D10 P1 Z-5 R1\n".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::NoToolDiameter { line: 1, .. })));
+    }
+
+    /// Test that S sets the power of the motions that follow.
+    #[test]
+    fn test_power() {
+        let program = "LIN X1 Y0 Z0\nS800\nLIN X2 Y0 Z0\nCW X0 Y0 R2 A90\nS0\nLIN X0 Y0 Z0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let powers: Vec<_> = motions.iter().map(Motion::power).collect();
+        assert_eq!(powers, vec![None, Some(800.0), Some(800.0), Some(0.0)]);
+    }
+
+    /// Test arcs in the XZ and YZ planes.
+    #[test]
+    fn test_arc_planes() {
+        let program = "PLANE XZ\nLIN X10 Y5 Z0\nCCW X0 Y5 Z10 I-10 K0\nPLANE YZ\nCW X5 Y7 R3 A0\n";
+        let motions = read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+
+        // A quarter circle up from X10 to Z10, staying at Y5
+        let Motion::Rotational(arc) = &motions[1] else { panic!("expected an arc") };
+        assert_eq!(arc.plane, Plane::Xz);
+        let points = crate::interpolate::helical_motion_calculate(arc.clone());
+        assert!(points.iter().all(|point| point.1 == 5.0));
+        let middle = points[points.len() / 2];
+        assert!((middle.0 - middle.2).abs() < 1e-9 && (middle.0.hypot(middle.2) - 10.0).abs() < 1e-9);
+
+        // Positional arcs are centered in the plane and stay at the tool's X
+        let Motion::Rotational(arc) = &motions[2] else { panic!("expected an arc") };
+        assert_eq!((arc.plane, arc.center, arc.z), (Plane::Yz, (5.0, 7.0), Some((0.0, 0.0))));
+
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, Tool { length: 0.0, diameter: 6.0 });
+        let err = read_reader("TOOL 1\nCOMP LEFT\nPLANE XZ".as_bytes(), &options).unwrap_err();
+        assert!(matches!(err, CmmdError::Parse(ParseError::CompensationPlane { line: 3, .. })));
+    }
+
+    /// Test full circles, given as a `CIRCLE` or by a stop angle a turn after the start.
+    #[test]
+    fn test_full_circles() {
+        let mut state = State {
+            position: (10.0, 5.0, 0.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let motion = parse_line(1, "CIRCLE X10 Y0 R5 S90 CW F300", &mut state, &options).unwrap();
+        let circle = Motion::new_rotational((10.0, 0.0), 5.0, 90.0, -360.0).with_feed(Some(300.0));
+        assert_eq!(motion, Some(circle.clone()));
+        let motion = parse_line(2, "CIRCLE X5 Y5 R5 S0 Z0 Z-1", &mut state, &options).unwrap();
+        assert_eq!(motion, Some(Motion::new_helical((5.0, 5.0), 5.0, 0.0, 360.0, (0.0, -1.0))));
+
+        // A clockwise circle starts at its start angle and sets off toward lower angles
+        let Motion::Rotational(arc) = circle else { unreachable!() };
+        let points = crate::interpolate::helical_motion_calculate(arc);
+        assert!((points[0].0 - 10.0).abs() < 1e-12 && (points[0].1 - 5.0).abs() < 1e-12);
+        assert!(points[1].0 > 10.0);
+
+        // Both forms come back to exactly where they started
+        let circles = [("CIRCLE X1 Y1 R2 S30", 30.0), ("CCW X1 Y1 R2 A395 S35", 35.0), ("CW X1 Y1 R2 A360", 0.0)];
+        for (line, start) in circles {
+            // Each one starts on its circle, at its start angle
+            let (sin, cos) = f64::to_radians(start).sin_cos();
+            state.position = (1.0 + 2.0 * cos, 1.0 + 2.0 * sin, 0.0);
+            let Some(Motion::Rotational(arc)) = parse_line(3, line, &mut state, &options).unwrap() else {
+                panic!("expected an arc")
+            };
+            let points = crate::interpolate::helical_motion_calculate(arc);
+            assert_eq!(points.first(), points.last());
+        }
+
+        let err = parse_line(4, "CIRCLE X0 Y0 CW", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::MissingArguments { expected: 3, found: 2, .. }));
+    }
+
+    /// Test arcs through three points, turning whichever way visits them in order.
+    #[test]
+    fn test_three_point_arc() {
+        let mut state = State {
+            position: (10.0, 0.0, 0.0),
+            ..Default::default()
+        };
+        let options = ParseOptions::default();
+        let motion = parse_line(1, "ARC (0, 10, 0) (-10, 0, -2) F200", &mut state, &options).unwrap();
+        let arc = Motion::new_arc_from_offset((10.0, 0.0, 0.0), (-10.0, 0.0, -2.0), (-10.0, 0.0), false);
+        assert_eq!(motion, Some(arc.with_feed(Some(200.0))));
+        assert_eq!(state.position, (-10.0, 0.0, -2.0));
+
+        // Going back through the bottom turns the other way, with `_` and INC as for LIN
+        state.distance = DistanceMode::Incremental;
+        let Some(Motion::Rotational(arc)) = parse_line(2, "ARC (10, -10, _) (20, 0, 2)", &mut state, &options).unwrap()
+        else {
+            panic!("expected an arc")
+        };
+        let points = crate::interpolate::helical_motion_calculate(arc);
+        let middle = points[points.len() / 2];
+        assert!((middle.0.abs() + (middle.1 + 10.0).abs() + (middle.2 + 1.0).abs()) < 1e-9);
+        assert_eq!(state.position, (10.0, 0.0, 0.0));
+
+        let err = parse_line(3, "ARC (1, 1, 0) (2, 2, 0)", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::CollinearArc { line: 3, .. }));
+        let err = parse_line(4, "ARC (1, 1, 0)", &mut state, &options).unwrap_err();
+        assert!(matches!(err, ParseError::MissingArguments { expected: 2, found: 1, .. }));
+        let err = parse_line(5, "ARC (1, 1) (2, 0, 0)", &mut state, &options).unwrap_err();
+        assert_eq!(err.span(), &Span(4..10));
+    }
+
+    /// Test dialect detection from file extensions.
+    #[test]
+    fn test_dialect_from_path() {
+        assert_eq!(Dialect::from_path("part.cmmd"), Some(Dialect::Cmmd));
+        assert_eq!(Dialect::from_path("dir/part.NC"), Some(Dialect::Gcode));
+        assert_eq!(Dialect::from_path("part.tap"), Some(Dialect::Gcode));
+        assert_eq!(Dialect::from_path("part.txt"), Some(Dialect::Cmmd));
+        assert_eq!(Dialect::from_path("part.prg"), None);
+    }
+}
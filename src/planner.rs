@@ -0,0 +1,530 @@
+//! Motion planning with acceleration limits
+//!
+//! Where [`crate::trajectory`] assumes the tool jumps straight to the feed rate,
+//! the planner ramps the velocity up and down following a trapezoidal velocity
+//! profile. It looks ahead across the whole program so the tool only slows
+//! down as much as each corner requires, instead of stopping at the end of
+//! every motion.
+
+use crate::error::CmmdError;
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::trajectory::{distance, extrusion_at, motion_positions, rotary_at, TimedPosition};
+
+/// Options controlling the motion planner
+#[derive(Debug, Clone)]
+pub struct PlannerOptions {
+    /// Largest acceleration and deceleration, in units per second squared
+    pub max_acceleration: f64,
+    /// Largest acceleration of each (x, y, z) axis, in units per second squared
+    ///
+    /// Motions are limited to the acceleration at which no axis exceeds its own
+    /// limit. `None` leaves only `max_acceleration`.
+    pub axis_acceleration: Option<(f64, f64, f64)>,
+    /// Largest velocity of each (x, y, z) axis, in units per second
+    ///
+    /// Motions run no faster than the speed at which no axis exceeds its own
+    /// limit, whatever their feed rate. `None` leaves the feed rate alone.
+    pub axis_velocity: Option<(f64, f64, f64)>,
+    /// How far, in units, the path may be allowed to round off a corner
+    ///
+    /// Larger values allow faster cornering. Zero stops at every corner.
+    pub junction_deviation: f64,
+}
+
+impl Default for PlannerOptions {
+    fn default() -> Self {
+        PlannerOptions {
+            max_acceleration: 500.0,
+            axis_acceleration: None,
+            axis_velocity: None,
+            junction_deviation: 0.01,
+        }
+    }
+}
+
+impl PlannerOptions {
+    /// Largest acceleration along the given unit direction
+    ///
+    /// An axis moving a fraction `u` of the path speed sees a fraction `u` of
+    /// the path acceleration, so each axis limit is scaled up by `1 / |u|`.
+    pub fn acceleration_along(&self, direction: (f64, f64, f64)) -> f64 {
+        let mut acceleration = self.max_acceleration;
+        if let Some(limits) = self.axis_acceleration {
+            for (component, limit) in [(direction.0, limits.0), (direction.1, limits.1), (direction.2, limits.2)] {
+                if component.abs() > 1e-12 {
+                    acceleration = acceleration.min(limit / component.abs());
+                }
+            }
+        }
+        acceleration
+    }
+
+    /// Largest velocity along the given unit direction, or infinity without axis limits
+    pub fn velocity_along(&self, direction: (f64, f64, f64)) -> f64 {
+        let mut velocity = f64::INFINITY;
+        if let Some(limits) = self.axis_velocity {
+            for (component, limit) in [(direction.0, limits.0), (direction.1, limits.1), (direction.2, limits.2)] {
+                if component.abs() > 1e-12 {
+                    velocity = velocity.min(limit / component.abs());
+                }
+            }
+        }
+        velocity
+    }
+}
+
+/// A motion split into positions, with what the planner needs to know about it
+#[derive(Debug, Clone)]
+struct Segment {
+    // The interpolated positions
+    positions: Vec<(f64, f64, f64)>,
+    // Distance along the path to each position, from the previous motion's end
+    travelled: Vec<f64>,
+    // Feed rate in units per second
+    feed: f64,
+    // Acceleration limit for the whole motion
+    acceleration: f64,
+    // Direction of travel at the start and end of the motion
+    entry_direction: Option<(f64, f64, f64)>,
+    exit_direction: Option<(f64, f64, f64)>,
+}
+
+impl Segment {
+    // Total length of the motion
+    fn length(&self) -> f64 {
+        self.travelled.last().copied().unwrap_or(0.0)
+    }
+}
+
+// Unit vector from a to b, if the points are apart
+fn direction(a: (f64, f64, f64), b: (f64, f64, f64)) -> Option<(f64, f64, f64)> {
+    let length = distance(a, b);
+    if length < 1e-12 {
+        return None;
+    }
+    Some(((b.0 - a.0) / length, (b.1 - a.1) / length, (b.2 - a.2) / length))
+}
+
+/// Function to calculate the highest velocity a corner can be taken at
+///
+/// Uses the junction deviation model: the corner is treated as a circular arc
+/// that strays `deviation` from the sharp corner, and the velocity is limited
+/// so the centripetal acceleration on that arc stays within `acceleration`.
+/// Straight continuations aren't limited at all, and reversals must stop.
+///
+/// # Arguments
+///
+/// * `exit` - Unit direction at the end of the first motion
+/// * `entry` - Unit direction at the start of the second motion
+/// * `acceleration` - Acceleration limit through the corner
+/// * `deviation` - The junction deviation
+///
+/// # Examples
+///
+/// ```
+/// use project_2::planner::junction_velocity;
+///
+/// let straight = junction_velocity((1.0, 0.0, 0.0), (1.0, 0.0, 0.0), 100.0, 0.01);
+/// let corner = junction_velocity((1.0, 0.0, 0.0), (0.0, 1.0, 0.0), 100.0, 0.01);
+/// let reverse = junction_velocity((1.0, 0.0, 0.0), (-1.0, 0.0, 0.0), 100.0, 0.01);
+/// assert_eq!(straight, f64::INFINITY);
+/// assert!(corner > 0.0 && corner < 2.0);
+/// assert_eq!(reverse, 0.0);
+/// ```
+pub fn junction_velocity(exit: (f64, f64, f64), entry: (f64, f64, f64), acceleration: f64, deviation: f64) -> f64 {
+    // Cosine of the angle between the incoming and outgoing paths
+    let cos_theta = -(exit.0 * entry.0 + exit.1 * entry.1 + exit.2 * entry.2);
+    if cos_theta < -0.999_999 {
+        return f64::INFINITY;
+    }
+    if cos_theta > 0.999_999 {
+        return 0.0;
+    }
+    let sin_half = ((1.0 - cos_theta) / 2.0).sqrt();
+    (acceleration * deviation * sin_half / (1.0 - sin_half)).sqrt()
+}
+
+/// Trapezoidal velocity profile along a path of known length
+///
+/// The velocity ramps from `entry_velocity` up to `cruise_velocity`, holds it,
+/// then ramps down to `exit_velocity`. Paths too short to reach the requested
+/// cruise velocity get a triangular profile instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrapezoidProfile {
+    /// Length of the path, in units
+    pub length: f64,
+    /// Velocity at the start of the path, in units per second
+    pub entry_velocity: f64,
+    /// Highest velocity reached, in units per second
+    pub cruise_velocity: f64,
+    /// Velocity at the end of the path, in units per second
+    pub exit_velocity: f64,
+    /// Acceleration used for both ramps, in units per second squared
+    pub acceleration: f64,
+}
+
+impl TrapezoidProfile {
+    /// Constructor for a profile
+    ///
+    /// # Arguments
+    ///
+    /// * `length` - Length of the path
+    /// * `entry_velocity` - Velocity at the start of the path
+    /// * `max_velocity` - Velocity to cruise at, if the path is long enough
+    /// * `exit_velocity` - Velocity at the end of the path
+    /// * `acceleration` - Acceleration used for both ramps
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::planner::TrapezoidProfile;
+    ///
+    /// // Accelerating to 10 units/s at 10 units/s² takes 5 units each way
+    /// let profile = TrapezoidProfile::new(20.0, 0.0, 10.0, 0.0, 10.0);
+    /// assert_eq!(profile.cruise_velocity, 10.0);
+    /// assert_eq!(profile.duration(), 3.0);
+    /// ```
+    pub fn new(length: f64, entry_velocity: f64, max_velocity: f64, exit_velocity: f64, acceleration: f64) -> Self {
+        // Distance needed to ramp between the end velocities and the cruise velocity
+        let ramps = (2.0 * max_velocity.powi(2) - entry_velocity.powi(2) - exit_velocity.powi(2)) / (2.0 * acceleration);
+
+        // Short paths peak where the two ramps meet
+        let cruise_velocity = if ramps > length {
+            ((2.0 * acceleration * length + entry_velocity.powi(2) + exit_velocity.powi(2)) / 2.0).sqrt()
+        } else {
+            max_velocity
+        };
+
+        TrapezoidProfile {
+            length,
+            entry_velocity: entry_velocity.min(cruise_velocity),
+            cruise_velocity,
+            exit_velocity: exit_velocity.min(cruise_velocity),
+            acceleration,
+        }
+    }
+
+    // Distance covered while speeding up
+    fn accelerate_distance(&self) -> f64 {
+        (self.cruise_velocity.powi(2) - self.entry_velocity.powi(2)) / (2.0 * self.acceleration)
+    }
+
+    // Distance covered while slowing down
+    fn decelerate_distance(&self) -> f64 {
+        (self.cruise_velocity.powi(2) - self.exit_velocity.powi(2)) / (2.0 * self.acceleration)
+    }
+
+    // Distance covered at the cruise velocity
+    fn cruise_distance(&self) -> f64 {
+        (self.length - self.accelerate_distance() - self.decelerate_distance()).max(0.0)
+    }
+
+    /// Time, in seconds, at which the tool has travelled `distance` along the path
+    pub fn time_at(&self, distance: f64) -> f64 {
+        let a = self.acceleration;
+        let (v0, vc) = (self.entry_velocity, self.cruise_velocity);
+        let accelerate = self.accelerate_distance();
+        let cruise = self.cruise_distance();
+        let accelerate_time = (vc - v0) / a;
+        let cruise_time = if vc > 0.0 { cruise / vc } else { 0.0 };
+
+        let distance = distance.clamp(0.0, self.length);
+        if distance <= accelerate {
+            // Solve s = v0 t + a t² / 2 for t
+            (-v0 + (v0 * v0 + 2.0 * a * distance).sqrt()) / a
+        } else if distance <= accelerate + cruise {
+            accelerate_time + (distance - accelerate) / vc
+        } else {
+            // Count back from the end of the ramp, where the exit velocity is known
+            let v1 = self.exit_velocity;
+            let remaining = self.length - distance;
+            let decelerate_time = (vc - v1) / a;
+            accelerate_time + cruise_time + decelerate_time - ((v1 * v1 + 2.0 * a * remaining).sqrt() - v1) / a
+        }
+    }
+
+    /// Velocity, in units per second, after travelling `distance` along the path
+    pub fn velocity_at(&self, distance: f64) -> f64 {
+        let a = self.acceleration;
+        let accelerate = self.accelerate_distance();
+        let cruise = self.cruise_distance();
+
+        let distance = distance.clamp(0.0, self.length);
+        if distance <= accelerate {
+            (self.entry_velocity.powi(2) + 2.0 * a * distance).sqrt()
+        } else if distance <= accelerate + cruise {
+            self.cruise_velocity
+        } else {
+            let remaining = self.length - distance;
+            (self.exit_velocity.powi(2) + 2.0 * a * remaining).sqrt()
+        }
+    }
+
+    /// Total time, in seconds, to travel the whole path
+    pub fn duration(&self) -> f64 {
+        self.time_at(self.length)
+    }
+}
+
+/// Function to plan timestamped positions for a whole program
+///
+/// The program starts and ends at rest. In between, each junction between two
+/// motions is passed at the highest velocity that:
+///
+/// - the corner allows, per [`junction_velocity`]
+/// - neither motion's feed rate exceeds
+/// - can still be reached from the previous junction, and braked down from
+///   before the next one, within the acceleration limit
+///
+/// Each motion then follows a trapezoidal profile between its junctions. The
+/// positions are the same as [`crate::trajectory::timed_positions`] produces;
+/// only their timing differs.
+///
+/// # Errors
+///
+/// Returns `CmmdError::MissingFeedRate` for the first motion (counted from
+/// zero) other than a rapid move that has no positive feed rate.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::planner::{plan, PlannerOptions};
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (20.0, 0.0, 0.0)).with_feed(Some(600.0))];
+/// let options = PlannerOptions { max_acceleration: 10.0, ..Default::default() };
+/// let samples = plan(&motions, &Default::default(), &options).unwrap();
+/// assert_eq!(samples.last().unwrap().time, 3.0);
+/// ```
+pub fn plan(
+    motions: &[Motion],
+    interpolation: &InterpolationOptions,
+    options: &PlannerOptions,
+) -> Result<Vec<TimedPosition>, CmmdError> {
+    let segments = segments(motions, interpolation, options)?;
+
+    // Velocity at each junction; junction i is where segment i starts
+    let mut junctions = vec![0.0; segments.len() + 1];
+    for i in 1..segments.len() {
+        let (before, after) = (&segments[i - 1], &segments[i]);
+        let corner = match (before.exit_direction, after.entry_direction) {
+            (Some(exit), Some(entry)) => junction_velocity(
+                exit,
+                entry,
+                before.acceleration.min(after.acceleration),
+                options.junction_deviation,
+            ),
+            _ => 0.0,
+        };
+        junctions[i] = corner.min(before.feed).min(after.feed);
+    }
+
+    // Backward pass: make sure every junction can be braked down from in time
+    for i in (0..segments.len()).rev() {
+        let reachable = (junctions[i + 1].powi(2) + 2.0 * segments[i].acceleration * segments[i].length()).sqrt();
+        junctions[i] = junctions[i].min(reachable);
+    }
+
+    // Forward pass: make sure every junction can be accelerated up to in time
+    for i in 0..segments.len() {
+        let reachable = (junctions[i].powi(2) + 2.0 * segments[i].acceleration * segments[i].length()).sqrt();
+        junctions[i + 1] = junctions[i + 1].min(reachable);
+    }
+
+    // Time each segment's positions along its profile
+    let mut samples = Vec::new();
+    let mut start_time = 0.0;
+    for (i, segment) in segments.into_iter().enumerate() {
+        let profile = TrapezoidProfile::new(
+            segment.length(),
+            junctions[i],
+            segment.feed,
+            junctions[i + 1],
+            segment.acceleration,
+        );
+        let count = segment.positions.len();
+        for (step, (position, distance)) in segment.positions.into_iter().zip(segment.travelled).enumerate() {
+            samples.push(TimedPosition {
+                time: start_time + profile.time_at(distance),
+                position,
+                motion: i,
+                power: motions[i].power(),
+                rotary: rotary_at(&motions[i], step, count),
+                extrusion: extrusion_at(&motions[i], step, count),
+            });
+        }
+        start_time += profile.duration();
+        if let Motion::Pause(_) = motions[i] {
+            start_time += interpolation.pause_time;
+        }
+    }
+
+    Ok(samples)
+}
+
+// Split every motion into positions and work out its feed, acceleration and directions
+fn segments(
+    motions: &[Motion],
+    interpolation: &InterpolationOptions,
+    options: &PlannerOptions,
+) -> Result<Vec<Segment>, CmmdError> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut previous: Option<(f64, f64, f64)> = None;
+
+    for (index, motion) in motions.iter().enumerate() {
+        // Feed rates are programmed per minute, samples are timed in seconds
+        let mut feed = match motion.feed() {
+            // Events don't move, but with no directions to blend the machine stops for them
+            _ if motion.is_rapid() || motion.is_event() => interpolation.rapid_rate / 60.0,
+            Some(feed) if feed > 0.0 => feed / 60.0,
+            _ => return Err(CmmdError::MissingFeedRate { motion: index }),
+        };
+
+        let current_z = previous.map_or(0.0, |position| position.2);
+        let positions = motion_positions(motion, current_z, interpolation);
+
+        // Distance along the path to each position, starting from the previous motion's end
+        let mut travelled = Vec::with_capacity(positions.len());
+        let Some(&first) = positions.first() else {
+            segments.push(Segment {
+                positions,
+                travelled: Vec::new(),
+                feed,
+                acceleration: options.max_acceleration,
+                entry_direction: None,
+                exit_direction: None,
+            });
+            continue;
+        };
+        let mut last = previous.unwrap_or(first);
+        let mut total = 0.0;
+        let mut entry_direction = None;
+        let mut exit_direction = None;
+        let mut acceleration = options.max_acceleration;
+        for &position in &positions {
+            if let Some(step) = direction(last, position) {
+                entry_direction.get_or_insert(step);
+                exit_direction = Some(step);
+                acceleration = acceleration.min(options.acceleration_along(step));
+                feed = feed.min(options.velocity_along(step));
+            }
+            total += distance(last, position);
+            travelled.push(total);
+            last = position;
+        }
+        previous = Some(last);
+        // Moves that only feed or retract filament are as long as the filament they move
+        if let Motion::Linear(linear_motion) = motion {
+            let fed = linear_motion.extruded().abs();
+            if total < 1e-12 && fed > 0.0 {
+                let steps = travelled.len().saturating_sub(1).max(1) as f64;
+                for (step, along) in travelled.iter_mut().enumerate() {
+                    *along = fed * step as f64 / steps;
+                }
+            }
+        }
+
+        segments.push(Segment {
+            positions,
+            travelled,
+            feed,
+            acceleration,
+            entry_direction,
+            exit_direction,
+        });
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test a profile long enough to cruise.
+    #[test]
+    fn test_trapezoid() {
+        let profile = TrapezoidProfile::new(20.0, 0.0, 10.0, 0.0, 10.0);
+        assert_eq!(profile.time_at(5.0), 1.0); // End of the acceleration ramp
+        assert_eq!(profile.time_at(15.0), 2.0); // End of the cruise
+        assert_eq!(profile.duration(), 3.0);
+        assert_eq!(profile.velocity_at(2.5), 50.0_f64.sqrt());
+        assert_eq!(profile.velocity_at(10.0), 10.0);
+        assert_eq!(profile.velocity_at(20.0), 0.0);
+    }
+
+    /// Test a profile too short to reach the cruise velocity.
+    #[test]
+    fn test_triangle() {
+        let profile = TrapezoidProfile::new(5.0, 0.0, 10.0, 0.0, 10.0);
+        assert_eq!(profile.cruise_velocity, 50.0_f64.sqrt());
+        assert!((profile.duration() - 2.0 * 0.5_f64.sqrt()).abs() < 1e-12);
+    }
+
+    /// Test that planned samples slow down near the ends of each motion.
+    #[test]
+    fn test_plan() {
+        let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (20.0, 0.0, 0.0)).with_feed(Some(600.0))];
+        let options = PlannerOptions {
+            max_acceleration: 10.0,
+            ..Default::default()
+        };
+        let samples = plan(&motions, &InterpolationOptions::default(), &options).unwrap();
+        let gaps: Vec<f64> = samples.windows(2).map(|pair| pair[1].time - pair[0].time).collect();
+        assert!(gaps[0] > gaps[gaps.len() / 2]);
+        assert!(gaps[gaps.len() - 1] > gaps[gaps.len() / 2]);
+    }
+
+    /// Test that look-ahead only slows down as much as each junction needs.
+    #[test]
+    fn test_look_ahead() {
+        let options = PlannerOptions {
+            max_acceleration: 10.0,
+            ..Default::default()
+        };
+        let interpolation = InterpolationOptions::default();
+        let time = |motions: &[Motion]| plan(motions, &interpolation, &options).unwrap().last().unwrap().time;
+
+        // A straight line split in two takes as long as the whole line
+        let split = [
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(600.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (20.0, 0.0, 0.0)).with_feed(Some(600.0)),
+        ];
+        assert!((time(&split) - 3.0).abs() < 1e-9);
+
+        // A right-angle corner is slower than going straight, but faster than stopping
+        let corner = [
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(600.0)),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, 10.0, 0.0)).with_feed(Some(600.0)),
+        ];
+        let stopping = PlannerOptions {
+            junction_deviation: 0.0,
+            ..options.clone()
+        };
+        let stopped = plan(&corner, &interpolation, &stopping).unwrap().last().unwrap().time;
+        assert!(time(&corner) > 3.0);
+        assert!(time(&corner) < stopped);
+        assert!((stopped - 4.0).abs() < 1e-9); // Two 10 unit moves from rest to rest
+    }
+
+    /// Test that per-axis limits slow motions along the limited axis.
+    #[test]
+    fn test_axis_acceleration() {
+        let options = PlannerOptions {
+            max_acceleration: 100.0,
+            axis_acceleration: Some((100.0, 100.0, 10.0)),
+            ..Default::default()
+        };
+        assert_eq!(options.acceleration_along((1.0, 0.0, 0.0)), 100.0);
+        assert_eq!(options.acceleration_along((0.0, 0.0, -1.0)), 10.0);
+        assert!((options.acceleration_along((0.6, 0.0, 0.8)) - 12.5).abs() < 1e-12);
+        assert_eq!(options.velocity_along((0.0, 0.0, 1.0)), f64::INFINITY);
+
+        let options = PlannerOptions {
+            axis_velocity: Some((50.0, 50.0, 5.0)),
+            ..Default::default()
+        };
+        assert!((options.velocity_along((0.6, 0.0, 0.8)) - 6.25).abs() < 1e-12);
+    }
+}
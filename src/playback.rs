@@ -0,0 +1,379 @@
+//! Playing programs back in real time, with feed hold and feed override
+//!
+//! A [`Playback`] walks through a program's timed samples as wall-clock time
+//! passes. [`Control`]s given while it runs hold the feed, resume it, or scale
+//! it between 10% and 200%. Like on a real controller, the override only
+//! scales cutting moves: rapids still run at full speed unless the feed is
+//! held. Whenever the override changes, the time left to each sample is worked
+//! out again from the remaining program time, so the samples are reached at
+//! the times the new feed rate gives.
+//!
+//! The same controls can be sent to a GRBL controller while a program
+//! streams, as the single-byte real-time commands [`realtime_bytes`] gives.
+
+use std::str::FromStr;
+
+use crate::motion::Motion;
+use crate::trajectory::TimedPosition;
+
+/// The lowest feed override, in percent
+pub const MIN_OVERRIDE: u32 = 10;
+/// The highest feed override, in percent
+pub const MAX_OVERRIDE: u32 = 200;
+/// How far one [`Control::Faster`] or [`Control::Slower`] moves the override, in percent
+pub const OVERRIDE_STEP: u32 = 10;
+
+/// A command given while a program runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// Stop feeding, keeping the place in the program
+    Hold,
+    /// Carry on after a hold
+    Resume,
+    /// Raise the feed override by one step
+    Faster,
+    /// Lower the feed override by one step
+    Slower,
+    /// Set the feed override to a percentage
+    Override(u32),
+}
+
+impl FromStr for Control {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "!" | "hold" => Ok(Control::Hold),
+            "~" | "resume" => Ok(Control::Resume),
+            "+" => Ok(Control::Faster),
+            "-" => Ok(Control::Slower),
+            other => {
+                let percent = other.strip_suffix('%').unwrap_or(other);
+                match percent.parse::<u32>() {
+                    Ok(percent) if (MIN_OVERRIDE..=MAX_OVERRIDE).contains(&percent) => Ok(Control::Override(percent)),
+                    Ok(_) => {
+                        Err(format!("Feed override {} is outside {}-{}%", text.trim(), MIN_OVERRIDE, MAX_OVERRIDE))
+                    }
+                    Err(_) => Err(format!("Unknown control {} (expected !, ~, +, - or a percentage)", text.trim())),
+                }
+            }
+        }
+    }
+}
+
+/// Whether the feed is held and how far it is overridden
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feed {
+    /// Whether the feed is held
+    pub held: bool,
+    /// The feed override, in percent
+    pub percent: u32,
+}
+
+impl Default for Feed {
+    fn default() -> Self {
+        Feed { held: false, percent: 100 }
+    }
+}
+
+impl Feed {
+    /// Function to apply a control to the feed
+    ///
+    /// Overrides are kept between [`MIN_OVERRIDE`] and [`MAX_OVERRIDE`].
+    ///
+    /// # Arguments
+    ///
+    /// * `control` - The control to apply
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::playback::{Control, Feed};
+    ///
+    /// let mut feed = Feed::default();
+    /// feed.apply(Control::Override(195));
+    /// feed.apply(Control::Faster);
+    /// assert_eq!(feed.percent, 200);
+    /// ```
+    pub fn apply(&mut self, control: Control) {
+        match control {
+            Control::Hold => self.held = true,
+            Control::Resume => self.held = false,
+            Control::Faster => self.percent = (self.percent + OVERRIDE_STEP).min(MAX_OVERRIDE),
+            Control::Slower => self.percent = self.percent.saturating_sub(OVERRIDE_STEP).max(MIN_OVERRIDE),
+            Control::Override(percent) => self.percent = percent.clamp(MIN_OVERRIDE, MAX_OVERRIDE),
+        }
+    }
+
+    /// Function to get how fast program time passes for a motion
+    ///
+    /// # Arguments
+    ///
+    /// * `rapid` - Whether the motion is a rapid, which the override doesn't scale
+    ///
+    /// # Returns
+    ///
+    /// Seconds of program time per second of wall-clock time, 0 while the feed is held
+    pub fn rate(&self, rapid: bool) -> f64 {
+        if self.held {
+            0.0
+        } else if rapid {
+            1.0
+        } else {
+            self.percent as f64 / 100.0
+        }
+    }
+}
+
+/// Function to turn a control into GRBL real-time command bytes
+///
+/// GRBL only steps its feed override by 10% and 1%, so a set percentage is
+/// sent as a reset to 100% followed by as many steps as it takes.
+///
+/// # Arguments
+///
+/// * `control` - The control to send
+///
+/// # Examples
+///
+/// ```
+/// use project_2::playback::{realtime_bytes, Control};
+///
+/// assert_eq!(realtime_bytes(Control::Hold), vec![b'!']);
+/// assert_eq!(realtime_bytes(Control::Override(112)), vec![0x90, 0x91, 0x93, 0x93]);
+/// ```
+pub fn realtime_bytes(control: Control) -> Vec<u8> {
+    match control {
+        Control::Hold => vec![b'!'],
+        Control::Resume => vec![b'~'],
+        Control::Faster => vec![0x91],
+        Control::Slower => vec![0x92],
+        Control::Override(percent) => {
+            let percent = percent.clamp(MIN_OVERRIDE, MAX_OVERRIDE);
+            let (coarse, fine) = if percent >= 100 { (0x91, 0x93) } else { (0x92, 0x94) };
+            let difference = percent.abs_diff(100);
+            let mut bytes = vec![0x90];
+            bytes.extend(std::iter::repeat_n(coarse, (difference / 10) as usize));
+            bytes.extend(std::iter::repeat_n(fine, (difference % 10) as usize));
+            bytes
+        }
+    }
+}
+
+/// A timed program being played back in real time
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::InterpolationOptions;
+/// use project_2::motion::Motion;
+/// use project_2::playback::{Control, Playback};
+/// use project_2::trajectory::timed_positions;
+///
+/// let motions = [Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(600.0))];
+/// let options = InterpolationOptions { linear_step: 5.0, ..Default::default() };
+/// let mut playback = Playback::new(&motions, timed_positions(&motions, &options).unwrap());
+///
+/// // At half the feed rate, the 1 second move takes 2
+/// playback.control(Control::Override(50));
+/// assert_eq!(playback.advance(1.0).len(), 2);
+/// assert!((playback.advance(1.0).last().unwrap().time - 2.0).abs() < 1e-9);
+/// assert!(playback.is_finished());
+/// ```
+pub struct Playback<'a> {
+    motions: &'a [Motion],
+    samples: Vec<TimedPosition>,
+    // Index of the next sample to reach
+    next: usize,
+    // Program time reached so far
+    clock: f64,
+    // Wall-clock time played so far
+    elapsed: f64,
+    feed: Feed,
+}
+
+impl<'a> Playback<'a> {
+    /// Function to start playing back a program's timed samples
+    ///
+    /// # Arguments
+    ///
+    /// * `motions` - The motions the samples came from
+    /// * `samples` - The samples, as timed at full feed rate
+    pub fn new(motions: &'a [Motion], samples: Vec<TimedPosition>) -> Self {
+        Playback { motions, samples, next: 0, clock: 0.0, elapsed: 0.0, feed: Feed::default() }
+    }
+
+    /// Function to apply a control to the playback
+    ///
+    /// # Arguments
+    ///
+    /// * `control` - The control to apply
+    pub fn control(&mut self, control: Control) {
+        self.feed.apply(control);
+    }
+
+    /// Function to get the playback's feed hold and override
+    pub fn feed(&self) -> Feed {
+        self.feed
+    }
+
+    /// Function to check whether every sample has been reached
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.samples.len()
+    }
+
+    /// Function to get how fast program time passes on the way to a sample
+    fn rate(&self, sample: &TimedPosition) -> f64 {
+        let rapid = self.motions.get(sample.motion).is_some_and(|motion| motion.is_rapid());
+        self.feed.rate(rapid)
+    }
+
+    /// Function to play the program on by some wall-clock time
+    ///
+    /// # Arguments
+    ///
+    /// * `seconds` - The wall-clock time that has passed
+    ///
+    /// # Returns
+    ///
+    /// The samples reached in that time, with their times changed to the
+    /// wall-clock times they were reached at since the playback started
+    pub fn advance(&mut self, seconds: f64) -> Vec<TimedPosition> {
+        let mut reached = Vec::new();
+        let mut left = seconds;
+        while let Some(sample) = self.samples.get(self.next) {
+            let rate = self.rate(sample);
+            let needed = sample.time - self.clock;
+            if needed <= 0.0 {
+                // Samples at the same time as the last, such as events and arc starts
+                reached.push(TimedPosition { time: self.elapsed, ..*sample });
+                self.next += 1;
+                continue;
+            }
+            if rate <= 0.0 {
+                // Held
+                break;
+            }
+            // A little slack, so samples due right at the end of the time aren't missed to rounding
+            if needed - left * rate > 1e-9 {
+                self.clock += left * rate;
+                break;
+            }
+            self.clock = sample.time;
+            self.elapsed += needed / rate;
+            left = (left - needed / rate).max(0.0);
+            reached.push(TimedPosition { time: self.elapsed, ..*sample });
+            self.next += 1;
+        }
+        self.elapsed += left;
+        reached
+    }
+
+    /// Function to estimate the wall-clock time left at the current feed
+    ///
+    /// # Returns
+    ///
+    /// The seconds left, or infinity while the feed is held
+    pub fn time_left(&self) -> f64 {
+        let mut clock = self.clock;
+        let mut left = 0.0;
+        for sample in &self.samples[self.next.min(self.samples.len())..] {
+            let needed = sample.time - clock;
+            if needed > 0.0 {
+                let rate = self.rate(sample);
+                if rate <= 0.0 {
+                    return f64::INFINITY;
+                }
+                left += needed / rate;
+            }
+            clock = sample.time;
+        }
+        left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpolate::InterpolationOptions;
+    use crate::trajectory::timed_positions;
+
+    /// Test that controls are parsed from what the user types
+    #[test]
+    fn test_parse_controls() {
+        assert_eq!("!".parse(), Ok(Control::Hold));
+        assert_eq!("Hold".parse(), Ok(Control::Hold));
+        assert_eq!("~".parse(), Ok(Control::Resume));
+        assert_eq!("+".parse(), Ok(Control::Faster));
+        assert_eq!("-".parse(), Ok(Control::Slower));
+        assert_eq!("150%".parse(), Ok(Control::Override(150)));
+        assert_eq!(" 10 ".parse(), Ok(Control::Override(10)));
+        assert!("250%".parse::<Control>().is_err());
+        assert!("5".parse::<Control>().is_err());
+        assert!("faster".parse::<Control>().is_err());
+    }
+
+    /// Test that the override stays between 10% and 200%
+    #[test]
+    fn test_feed_limits() {
+        let mut feed = Feed::default();
+        feed.apply(Control::Override(20));
+        feed.apply(Control::Slower);
+        feed.apply(Control::Slower);
+        assert_eq!(feed.percent, 10);
+        feed.apply(Control::Hold);
+        assert_eq!(feed.rate(true), 0.0);
+        feed.apply(Control::Resume);
+        assert_eq!(feed.rate(false), 0.1);
+        assert_eq!(feed.rate(true), 1.0);
+    }
+
+    /// Test that overrides are sent as GRBL's coarse and fine steps
+    #[test]
+    fn test_realtime_bytes() {
+        assert_eq!(realtime_bytes(Control::Resume), vec![b'~']);
+        assert_eq!(realtime_bytes(Control::Override(100)), vec![0x90]);
+        assert_eq!(realtime_bytes(Control::Override(200)), [vec![0x90], vec![0x91; 10]].concat());
+        assert_eq!(realtime_bytes(Control::Override(75)), vec![0x90, 0x92, 0x92, 0x94, 0x94, 0x94, 0x94, 0x94]);
+    }
+
+    /// Test that holding, resuming and overriding retime the remaining samples
+    #[test]
+    fn test_playback() {
+        let motions = [
+            Motion::new_rapid((0.0, 0.0, 0.0), (0.0, 0.0, 5.0)),
+            Motion::new_linear((0.0, 0.0, 5.0), (20.0, 0.0, 5.0)).with_feed(Some(600.0)),
+        ];
+        // 2 rapid steps of 0.5 seconds, then 5 cutting steps of 0.4
+        let options = InterpolationOptions { linear_step: 5.0, rapid_rate: 300.0, ..Default::default() };
+        let samples = timed_positions(&motions, &options).unwrap();
+        let mut playback = Playback::new(&motions, samples);
+        assert!((playback.time_left() - 3.0).abs() < 1e-9);
+
+        // The override doesn't slow the 1 second rapid
+        playback.control(Control::Override(50));
+        let reached = playback.advance(1.0);
+        assert_eq!(reached.last().unwrap().position, (0.0, 0.0, 5.0));
+        assert!((reached.last().unwrap().time - 1.0).abs() < 1e-9);
+        assert!((playback.time_left() - 4.0).abs() < 1e-9);
+
+        // Nothing is reached while held
+        playback.control(Control::Hold);
+        assert!(playback.advance(5.0).is_empty());
+        assert_eq!(playback.time_left(), f64::INFINITY);
+
+        // Each step takes 0.8 seconds at half feed, then 0.4 at full
+        playback.control(Control::Resume);
+        let reached = playback.advance(0.8);
+        assert_eq!(reached.len(), 1);
+        assert_eq!(reached[0].position, (4.0, 0.0, 5.0));
+        assert!((reached[0].time - 6.8).abs() < 1e-9);
+        playback.control(Control::Override(100));
+        assert!((playback.time_left() - 1.6).abs() < 1e-9);
+        let reached = playback.advance(2.0);
+        let times = reached.iter().map(|sample| sample.time);
+        assert!(times.zip([7.2, 7.6, 8.0, 8.4]).all(|(time, expected)| (time - expected).abs() < 1e-9));
+        assert_eq!(reached.len(), 4);
+        assert!(playback.is_finished());
+    }
+}
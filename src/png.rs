@@ -0,0 +1,155 @@
+//! PNG backplot export
+//!
+//! Draws the XY path of a program with `plotters`, including axes with a
+//! scale and markers at the start and end of the path, for users who don't
+//! have an SVG viewer at hand. Only built with the `png` feature.
+
+use std::io;
+use std::path::Path;
+
+use plotters::prelude::*;
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::output::geometric_points;
+
+// Size of the image, in pixels
+const SIZE: (u32, u32) = (900, 900);
+
+// Turn a plotting error into an I/O error
+fn plot_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+/// Function to draw the XY path of a program into a PNG image
+///
+/// Both axes use the same scale, so circles come out round. Linear moves are
+/// blue, arcs red, Bézier curves and splines magenta, and travel moves between disconnected motions grey. The
+/// start of the path is marked with a green circle and the end with a black
+/// cross.
+///
+/// # Arguments
+///
+/// * `path` - The PNG file to write
+/// * `motions` - The motions of the program
+/// * `options` - How motions are split into positions
+///
+/// # Errors
+///
+/// Returns an I/O error if the image can't be drawn or written.
+pub fn write_png(path: &Path, motions: &[Motion], options: &InterpolationOptions) -> io::Result<()> {
+    let points = geometric_points(motions, options);
+
+    // Square bounds around the path, so both axes share a scale
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for point in &points {
+        let (x, y, _) = point.position;
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    if points.is_empty() {
+        (min_x, min_y, max_x, max_y) = (0.0, 0.0, 0.0, 0.0);
+    }
+    let half = ((max_x - min_x).max(max_y - min_y) / 2.0 * 1.05).max(1.0);
+    let center = ((min_x + max_x) / 2.0, (min_y + max_y) / 2.0);
+    let x_range = center.0 - half..center.0 + half;
+    let y_range = center.1 - half..center.1 + half;
+
+    let root = BitMapBackend::new(path, SIZE).into_drawing_area();
+    root.fill(&WHITE).map_err(plot_error)?;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Backplot (XY)", ("sans-serif", 24))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(x_range, y_range)
+        .map_err(plot_error)?;
+    chart
+        .configure_mesh()
+        .x_desc("X")
+        .y_desc("Y")
+        .draw()
+        .map_err(plot_error)?;
+
+    // Draw each motion, preceded by a travel move if it starts somewhere new
+    let travel = RGBColor(127, 127, 127);
+    let mut previous: Option<(f64, f64)> = None;
+    for (index, motion) in motions.iter().enumerate() {
+        let path: Vec<(f64, f64)> = points
+            .iter()
+            .filter(|point| point.motion == index)
+            .map(|point| (point.position.0, point.position.1))
+            .collect();
+        let (Some(&first), Some(&last)) = (path.first(), path.last()) else {
+            continue;
+        };
+        if let Some(previous) = previous {
+            if (previous.0 - first.0).hypot(previous.1 - first.1) > 1e-9 {
+                chart
+                    .draw_series(DashedLineSeries::new(vec![previous, first], 6, 4, travel.stroke_width(1)))
+                    .map_err(plot_error)?;
+            }
+        }
+        let color = match motion {
+            // Rapid moves are drawn like travel moves
+            _ if motion.is_rapid() => {
+                chart
+                    .draw_series(DashedLineSeries::new(path, 6, 4, travel.stroke_width(1)))
+                    .map_err(plot_error)?;
+                previous = Some(last);
+                continue;
+            }
+            Motion::Linear(_) | Motion::Oriented(_) => BLUE,
+            Motion::Rotational(_) => RED,
+            Motion::Bezier(_) | Motion::Spline(_) => MAGENTA,
+            // Events have no path to draw
+            Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => continue,
+        };
+        chart
+            .draw_series(LineSeries::new(path, color.stroke_width(2)))
+            .map_err(plot_error)?;
+        previous = Some(last);
+    }
+
+    // Mark where the path starts and ends
+    if let (Some(first), Some(last)) = (points.first(), points.last()) {
+        let start = (first.position.0, first.position.1);
+        let end = (last.position.0, last.position.1);
+        chart
+            .draw_series([Circle::new(start, 6, GREEN.filled())])
+            .map_err(plot_error)?;
+        chart
+            .draw_series([Cross::new(end, 6, BLACK.stroke_width(2))])
+            .map_err(plot_error)?;
+    }
+
+    root.present().map_err(plot_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that a small program comes out as a PNG image of the expected size.
+    #[test]
+    fn test_write_png() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 10.0, 0.0), (-10.0, 0.0), false),
+        ];
+        let path = std::env::temp_dir().join(format!("project-2-test-{}.png", std::process::id()));
+        write_png(&path, &motions, &InterpolationOptions::default()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The signature, then the IHDR chunk with the width and height
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&bytes[12..16], b"IHDR");
+        let width = u32::from_be_bytes(bytes[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(bytes[20..24].try_into().unwrap());
+        assert_eq!((width, height), SIZE);
+    }
+}
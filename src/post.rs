@@ -0,0 +1,1182 @@
+//! G-code and `.cmmd` post-processors
+//!
+//! Writes a parsed program back out as G-code, so programs can be authored in
+//! the simple `.cmmd` syntax and run on any controller, or as `.cmmd`, so
+//! G-code can be brought into the native language. Arcs stay arcs: they
+//! become `G2`/`G3` moves (or `CW`/`CCW` lines) with `I`/`J` center offsets
+//! instead of being exploded into points.
+//!
+//! G-code can also be written for a particular controller, picked with a
+//! [`Flavor`]: each one gets the start and end blocks, decimals and words it
+//! understands, and anything it can't follow in one move is written as short
+//! lines instead.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::interpolate::{bezier_points, helical_points, spline_points, InterpolationOptions};
+use crate::motion::{Motion, Plane, Rotary, RotationalMotion};
+use crate::spline::clamped_knots;
+use crate::tools::ToolTable;
+use crate::transform::Transform;
+
+/// Controllers G-code can be written for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Flavor {
+    /// Everything the G-code reader understands, curves and tilting moves included
+    #[default]
+    Generic,
+    /// GRBL, on most hobby routers and lasers: three axes, no curves, tool changes or `G5`
+    Grbl,
+    /// Marlin, on most 3D printers: arcs only in XY, `;` comments and an extruder
+    Marlin,
+    /// LinuxCNC: rotary axes, curves, and arcs of several turns written with `P`
+    LinuxCnc,
+}
+
+impl FromStr for Flavor {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "generic" => Ok(Flavor::Generic),
+            "grbl" => Ok(Flavor::Grbl),
+            "marlin" => Ok(Flavor::Marlin),
+            "linuxcnc" => Ok(Flavor::LinuxCnc),
+            _ => Err(format!("Unknown flavor {}, expected generic, grbl, marlin or linuxcnc", name)),
+        }
+    }
+}
+
+impl Flavor {
+    // Name of the controller, for errors
+    fn name(self) -> &'static str {
+        match self {
+            Flavor::Generic => "G-code",
+            Flavor::Grbl => "GRBL",
+            Flavor::Marlin => "Marlin",
+            Flavor::LinuxCnc => "LinuxCNC",
+        }
+    }
+
+    // Decimals written for coordinates, feed rates and the rest
+    fn decimals(self) -> usize {
+        match self {
+            Flavor::Grbl | Flavor::Marlin => 3,
+            Flavor::Generic | Flavor::LinuxCnc => 4,
+        }
+    }
+
+    // Whether G5 curves and G5.2 splines can be written
+    fn curves(self) -> bool {
+        matches!(self, Flavor::Generic | Flavor::LinuxCnc)
+    }
+
+    // Whether arcs can be drawn in the XZ and YZ planes
+    fn planes(self) -> bool {
+        self != Flavor::Marlin
+    }
+
+    // Whether A, B and C words turn rotary axes
+    fn rotary(self) -> bool {
+        matches!(self, Flavor::Generic | Flavor::LinuxCnc)
+    }
+
+    // Whether E words feed an extruder
+    fn extruder(self) -> bool {
+        matches!(self, Flavor::Generic | Flavor::Marlin)
+    }
+
+    // Whether T M6 changes the tool, rather than the operator doing it
+    fn tool_changes(self) -> bool {
+        matches!(self, Flavor::Generic | Flavor::LinuxCnc)
+    }
+
+    // A comment the controller skips
+    fn comment(self, text: &str) -> String {
+        match self {
+            Flavor::Marlin => format!(";{}", text),
+            _ => format!("({})", text),
+        }
+    }
+}
+
+// The error for a motion the controller can't make
+fn unsupported(flavor: Flavor, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} can't {}", flavor.name(), what))
+}
+
+// Format a coordinate with up to four decimals and no trailing zeros
+fn number(value: f64) -> String {
+    rounded(value, 4)
+}
+
+// Format a coordinate with up to `decimals` decimals and no trailing zeros
+fn rounded(value: f64, decimals: usize) -> String {
+    let text = format!("{:.*}", decimals, value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    // Avoid printing negative zero
+    if text == "-0" {
+        String::from("0")
+    } else {
+        text.to_string()
+    }
+}
+
+/// The geometric description of an arc, the way G2/G3 need it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArcMove {
+    /// Where the arc starts
+    pub start: (f64, f64, f64),
+    /// Where the arc ends
+    pub end: (f64, f64, f64),
+    /// Offset from the start point to the center, along the axes of the arc's plane
+    pub offset: (f64, f64),
+    /// Whether the tool travels clockwise as seen in the arc's plane
+    pub clockwise: bool,
+    /// Angle swept, in degrees, always positive
+    pub sweep: f64,
+}
+
+/// Function to work out where an arc starts and ends and which way it turns
+///
+/// Arcs without a Z range stay at `current_z`. The start and end are (x, y, z)
+/// points whatever plane the arc lies in.
+pub fn arc_move(rotational_motion: &RotationalMotion, current_z: f64) -> ArcMove {
+    const FULL_CIRCLE: f64 = 360.0;
+
+    let (start_angle, sweep) = (rotational_motion.start_angle, rotational_motion.sweep);
+    let (z_start, z_end) = rotational_motion.z.unwrap_or((current_z, current_z));
+
+    // Point on the circle at an angle in degrees, in the arc's plane
+    let point = |angle: f64, z: f64| {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        (
+            rotational_motion.center.0 + rotational_motion.radius * cos,
+            rotational_motion.center.1 + rotational_motion.radius * sin,
+            z,
+        )
+    };
+    let start = point(start_angle, z_start);
+    // Full circles end exactly where they start, which is how G2/G3 ask for one
+    let turns = sweep / FULL_CIRCLE;
+    let end = match rotational_motion.end {
+        // Arcs given by their end point end exactly there
+        Some((first, second)) => (first, second, z_end),
+        None if turns != 0.0 && (turns - turns.round()).abs() < 1e-12 => (start.0, start.1, z_end),
+        None => point(start_angle + sweep, z_end),
+    };
+    let plane = rotational_motion.plane;
+
+    ArcMove {
+        start: plane.point(start),
+        end: plane.point(end),
+        offset: (
+            rotational_motion.center.0 - start.0,
+            rotational_motion.center.1 - start.1,
+        ),
+        clockwise: rotational_motion.is_clockwise(),
+        sweep: sweep.abs(),
+    }
+}
+
+// Travel to a point with a rapid move, written with `code`, if the tool isn't already there
+fn travel<W: Write>(
+    writer: &mut W,
+    position: &mut (f64, f64, f64),
+    target: (f64, f64, f64),
+    code: &str,
+    decimals: usize,
+) -> io::Result<()> {
+    let gap =
+        ((target.0 - position.0).powi(2) + (target.1 - position.1).powi(2) + (target.2 - position.2).powi(2)).sqrt();
+    if gap > 1e-6 {
+        writeln!(
+            writer,
+            "{} X{} Y{} Z{}",
+            code,
+            rounded(target.0, decimals),
+            rounded(target.1, decimals),
+            rounded(target.2, decimals)
+        )?;
+        *position = target;
+    }
+    Ok(())
+}
+
+// Write the points of a path after the first as G1 lines, for paths the controller can't follow in one move
+fn write_lines<W: Write>(
+    writer: &mut W,
+    points: impl Iterator<Item = (f64, f64, f64)>,
+    feed_word: &str,
+    decimals: usize,
+) -> io::Result<()> {
+    for (index, point) in points.enumerate().skip(1) {
+        writeln!(
+            writer,
+            "G1 X{} Y{} Z{}{}",
+            rounded(point.0, decimals),
+            rounded(point.1, decimals),
+            rounded(point.2, decimals),
+            if index == 1 { feed_word } else { "" }
+        )?;
+    }
+    Ok(())
+}
+
+// Where each piece of an arc ends, splitting arcs of more than a turn into pieces of at most one turn
+fn arc_pieces(rotational_motion: &RotationalMotion, arc: &ArcMove) -> Vec<(f64, f64, f64)> {
+    let plane = rotational_motion.plane;
+    let pieces = (arc.sweep / 360.0 - 1e-9).ceil().max(1.0) as usize;
+    let direction = if arc.clockwise { -1.0 } else { 1.0 };
+    let start = plane.coordinates(arc.start);
+    let finish = plane.coordinates(arc.end);
+    let center = (start.0 + arc.offset.0, start.1 + arc.offset.1);
+    let start_angle = (start.1 - center.1).atan2(start.0 - center.0).to_degrees();
+    (1..=pieces)
+        .map(|piece| {
+            let fraction = piece as f64 / pieces as f64;
+            if piece == pieces {
+                return arc.end;
+            }
+            let (sin, cos) = (start_angle + direction * arc.sweep * fraction).to_radians().sin_cos();
+            plane.point((
+                center.0 + rotational_motion.radius * cos,
+                center.1 + rotational_motion.radius * sin,
+                start.2 + (finish.2 - start.2) * fraction,
+            ))
+        })
+        .collect()
+}
+
+/// Function to write a program as G-code
+///
+/// The output starts with millimeters, absolute positioning and the XY plane,
+/// and ends with a program end. Feed rates and spindle speeds (or laser
+/// power) are written whenever they change. Arcs in other planes switch to
+/// `G18`/`G19` and give their center with `I`/`K` or `J`/`K`. If a motion
+/// starts away from where the previous one ended, a `G0` travel move is
+/// inserted. Arcs sweeping more than a full turn are split into several
+/// `G2`/`G3` moves, since one move can't describe more than one turn.
+/// Bézier curves that stay at one height become `G5` moves; ones that
+/// change Z are written as short `G1` lines, since `G5` can't move Z.
+/// Flat splines with clamped, uniform knots become `G5.2`/`G5.3` blocks,
+/// and other splines short `G1` lines too. Linear motions that turn the
+/// rotary axes get `A`, `B` and `C` words for the angles that change, and
+/// moves that tilt the tool give its direction at the end with `I`, `J` and
+/// `K` words, like G43.5. Moves that feed the extruder get an absolute `E`
+/// word, with a `G92` first where the extruder was reset between moves.
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the G-code
+/// * `motions` - The motions of the program
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::post::write_gcode;
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(300.0)),
+///     Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 10.0, 0.0), (-10.0, 0.0), false),
+/// ];
+/// let mut output = Vec::new();
+/// write_gcode(&mut output, &motions).unwrap();
+/// let gcode = String::from_utf8(output).unwrap();
+/// assert!(gcode.contains("G1 X10 Y0 Z0 F300\n"));
+/// assert!(gcode.contains("G3 X0 Y10 Z0 I-10 J0\n"));
+/// ```
+pub fn write_gcode<W: Write>(writer: W, motions: &[Motion]) -> io::Result<()> {
+    write_program(writer, motions, Flavor::Generic, false)
+}
+
+/// Function to write a program as G-code for a particular controller
+///
+/// Like [`write_gcode`], with the start and end blocks, number of decimals
+/// and words the controller expects. Curves and splines it can't follow are
+/// written as short `G1` lines, as are arcs outside the XY plane for Marlin,
+/// and LinuxCNC gets arcs of more than a turn as one move with a `P` word.
+/// GRBL and Marlin can't change tools, so a tool change stops the program
+/// with `M0` and a comment saying which tool to fit.
+///
+/// # Errors
+///
+/// Returns an I/O error if writing fails, or an `InvalidInput` error for
+/// motions the controller can't make at all: turning rotary axes on GRBL or
+/// Marlin, feeding an extruder on GRBL or LinuxCNC, and tilting the tool on
+/// anything but [`Flavor::Generic`].
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::post::{write_gcode_for, Flavor};
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 1.0 / 3.0, 0.0)).with_feed(Some(300.0))];
+/// let mut output = Vec::new();
+/// write_gcode_for(&mut output, &motions, Flavor::Marlin).unwrap();
+/// let gcode = String::from_utf8(output).unwrap();
+/// assert!(gcode.starts_with(";generated by project-2\nG21\nG90\nM82\n"));
+/// assert!(gcode.contains("G1 X10 Y0.333 Z0 F300\n"));
+/// assert!(gcode.ends_with("M84\n"));
+/// ```
+pub fn write_gcode_for<W: Write>(writer: W, motions: &[Motion], flavor: Flavor) -> io::Result<()> {
+    write_program(writer, motions, flavor, false)
+}
+
+/// Function to write a program as G-code, with a `(motion n)` comment before the lines of each motion
+///
+/// The comment before the program end is `(end)`, so every line can be traced
+/// back to the motion it came from. The lines are for streaming, so tool
+/// changes are written as `T M6` for every flavor: the streamer stops for them.
+pub(crate) fn write_marked_gcode<W: Write>(writer: W, motions: &[Motion], flavor: Flavor) -> io::Result<()> {
+    write_program(writer, motions, flavor, true)
+}
+
+// Write the G-code of a program for a controller, optionally marking where each motion's lines begin
+fn write_program<W: Write>(mut writer: W, motions: &[Motion], flavor: Flavor, marked: bool) -> io::Result<()> {
+    let decimals = flavor.decimals();
+    let number = |value: f64| rounded(value, decimals);
+
+    // LinuxCNC programs are wrapped in percent signs
+    if flavor == Flavor::LinuxCnc {
+        writeln!(writer, "%")?;
+    }
+    writeln!(writer, "{}", flavor.comment("generated by project-2"))?;
+    match flavor {
+        Flavor::Generic => writeln!(writer, "G21 G90 G17")?,
+        Flavor::Grbl => writeln!(writer, "G21 G90 G17 G94")?,
+        // Marlin has no planes to select, and E words are absolute
+        Flavor::Marlin => writeln!(writer, "G21\nG90\nM82")?,
+        // Clear the compensation, tool length offset and canned cycle a previous program may have left on
+        Flavor::LinuxCnc => writeln!(writer, "G21 G90 G17 G40 G49 G80 G94")?,
+    }
+
+    let mut position = (0.0, 0.0, 0.0);
+    let mut feed = None;
+    let mut power = None;
+    let mut current_plane = Plane::Xy;
+    // Rotary axes start at zero, like the readers assume
+    let mut angles = Rotary::default();
+    let mut extruder = 0.0;
+
+    for (index, motion) in motions.iter().enumerate() {
+        if marked {
+            writeln!(writer, "{}", flavor.comment(&format!("motion {}", index)))?;
+        }
+        // Only mention the feed rate and power when they change
+        let mut feed_word = match motion.feed() {
+            Some(rate) if feed != Some(rate) => {
+                feed = Some(rate);
+                format!(" F{}", number(rate))
+            }
+            _ => String::new(),
+        };
+        if let Some(speed) = motion.power().filter(|&speed| power != Some(speed)) {
+            power = Some(speed);
+            feed_word.push_str(&format!(" S{}", number(speed)));
+        }
+
+        match motion {
+            Motion::Linear(linear_motion) => {
+                travel(&mut writer, &mut position, linear_motion.start, "G0", decimals)?;
+                let end = linear_motion.end;
+                // Only mention the rotary axes whose angle changes
+                let mut turn_words = String::new();
+                if let Some((_, to)) = linear_motion.rotary {
+                    let axes = [('A', to.a, angles.a), ('B', to.b, angles.b), ('C', to.c, angles.c)];
+                    for (letter, angle, current) in axes {
+                        if (angle - current).abs() > 1e-9 {
+                            if !flavor.rotary() {
+                                return Err(unsupported(flavor, "turn rotary axes"));
+                            }
+                            turn_words.push_str(&format!(" {}{}", letter, number(angle)));
+                        }
+                    }
+                    angles = to;
+                }
+                // The extruder is reset with G92 where it doesn't carry on from the last move
+                if let Some((from, to)) = linear_motion.extrusion {
+                    if (to - from).abs() > 1e-9 && !flavor.extruder() {
+                        return Err(unsupported(flavor, "feed an extruder"));
+                    }
+                    if (from - extruder).abs() > 1e-9 && flavor.extruder() {
+                        writeln!(writer, "G92 E{}", number(from))?;
+                    }
+                    if (to - from).abs() > 1e-9 {
+                        turn_words.push_str(&format!(" E{}", number(to)));
+                    }
+                    extruder = to;
+                }
+                writeln!(
+                    writer,
+                    "{} X{} Y{} Z{}{}{}",
+                    if linear_motion.rapid { "G0" } else { "G1" },
+                    number(end.0),
+                    number(end.1),
+                    number(end.2),
+                    turn_words,
+                    feed_word
+                )?;
+                position = end;
+            }
+            Motion::Rotational(rotational_motion) => {
+                let arc = arc_move(rotational_motion, position.2);
+                travel(&mut writer, &mut position, arc.start, "G0", decimals)?;
+
+                let plane = rotational_motion.plane;
+                if !flavor.planes() && plane != Plane::Xy {
+                    // Follow the arc with short lines, keeping the height it would have been drawn at
+                    let z = (plane.coordinates(arc.start).2, plane.coordinates(arc.end).2);
+                    let flattened = RotationalMotion {
+                        z: Some(z),
+                        ..rotational_motion.clone()
+                    };
+                    let points = helical_points(&flattened, &InterpolationOptions::default());
+                    write_lines(&mut writer, points, &feed_word, decimals)?;
+                    position = arc.end;
+                    continue;
+                }
+
+                // Select the arc's plane when it changes
+                if plane != current_plane {
+                    let code = match plane {
+                        Plane::Xy => "G17",
+                        Plane::Xz => "G18",
+                        Plane::Yz => "G19",
+                    };
+                    writeln!(writer, "{}", code)?;
+                    current_plane = plane;
+                }
+                // Center offset words for the plane's two axes
+                let (first, second) = match plane {
+                    Plane::Xy => ('I', 'J'),
+                    Plane::Xz => ('I', 'K'),
+                    Plane::Yz => ('J', 'K'),
+                };
+                // G18 is seen from +Y, the back of the XZ plane, so its directions are mirrored
+                let g2 = arc.clockwise != (plane == Plane::Xz);
+
+                // LinuxCNC makes several turns in one move, given how many with P
+                let mut pieces = arc_pieces(rotational_motion, &arc);
+                let turns = if flavor == Flavor::LinuxCnc && pieces.len() > 1 {
+                    let turns = format!(" P{}", pieces.len());
+                    pieces = vec![arc.end];
+                    turns
+                } else {
+                    String::new()
+                };
+
+                let start = plane.coordinates(arc.start);
+                let center = (start.0 + arc.offset.0, start.1 + arc.offset.1);
+                for (piece, end) in pieces.into_iter().enumerate() {
+                    let here = plane.coordinates(position);
+                    writeln!(
+                        writer,
+                        "{} X{} Y{} Z{} {}{} {}{}{}{}",
+                        if g2 { "G2" } else { "G3" },
+                        number(end.0),
+                        number(end.1),
+                        number(end.2),
+                        first,
+                        number(center.0 - here.0),
+                        second,
+                        number(center.1 - here.1),
+                        turns,
+                        if piece == 0 { feed_word.as_str() } else { "" }
+                    )?;
+                    position = end;
+                }
+            }
+            Motion::Bezier(bezier_motion) => {
+                travel(&mut writer, &mut position, bezier_motion.start, "G0", decimals)?;
+                let z = bezier_motion.start.2;
+                let flat = [bezier_motion.control1, bezier_motion.control2, bezier_motion.end]
+                    .iter()
+                    .all(|point| (point.2 - z).abs() < 1e-9);
+                if flat && flavor.curves() {
+                    // G5 only works in the XY plane
+                    if current_plane != Plane::Xy {
+                        writeln!(writer, "G17")?;
+                        current_plane = Plane::Xy;
+                    }
+                    let (start, end) = (bezier_motion.start, bezier_motion.end);
+                    let (first, second) = (bezier_motion.control1, bezier_motion.control2);
+                    writeln!(
+                        writer,
+                        "G5 X{} Y{} I{} J{} P{} Q{}{}",
+                        number(end.0),
+                        number(end.1),
+                        number(first.0 - start.0),
+                        number(first.1 - start.1),
+                        number(second.0 - end.0),
+                        number(second.1 - end.1),
+                        feed_word
+                    )?;
+                } else {
+                    // G5 can't move Z, so curves that do are written as short lines
+                    let points = bezier_points(bezier_motion, &InterpolationOptions::default());
+                    write_lines(&mut writer, points, &feed_word, decimals)?;
+                }
+                position = bezier_motion.end;
+            }
+            Motion::Spline(spline_motion) => {
+                let start = spline_motion.start();
+                travel(&mut writer, &mut position, start, "G0", decimals)?;
+                // G5.2 starts from the tool's position at weight 1, with clamped, uniform knots, in XY
+                let controls = &spline_motion.controls;
+                let plain = spline_motion.weights[0] == 1.0
+                    && spline_motion.knots == clamped_knots(controls.len(), spline_motion.degree)
+                    && controls.iter().all(|point| (point.2 - start.2).abs() < 1e-9);
+                if plain && flavor.curves() {
+                    if current_plane != Plane::Xy {
+                        writeln!(writer, "G17")?;
+                        current_plane = Plane::Xy;
+                    }
+                    for (index, (point, weight)) in controls.iter().zip(&spline_motion.weights).enumerate().skip(1) {
+                        let (code, order) = match index {
+                            1 => ("G5.2 ", format!(" L{}", spline_motion.degree + 1)),
+                            _ => ("", String::new()),
+                        };
+                        writeln!(
+                            writer,
+                            "{}X{} Y{} P{}{}{}",
+                            code,
+                            number(point.0),
+                            number(point.1),
+                            number(*weight),
+                            order,
+                            if index == 1 { feed_word.as_str() } else { "" }
+                        )?;
+                    }
+                    writeln!(writer, "G5.3")?;
+                } else {
+                    // Anything G5.2 can't describe is written as short lines
+                    let points = spline_points(spline_motion, &InterpolationOptions::default());
+                    write_lines(&mut writer, points, &feed_word, decimals)?;
+                }
+                position = spline_motion.end();
+            }
+            // The controller tilts the tool along the way itself, given where it should point at the end
+            Motion::Oriented(oriented_motion) => {
+                if flavor != Flavor::Generic {
+                    return Err(unsupported(flavor, "tilt the tool"));
+                }
+                travel(&mut writer, &mut position, oriented_motion.start, "G0", decimals)?;
+                let (end, (i, j, k)) = (oriented_motion.end, oriented_motion.orientation.1);
+                writeln!(
+                    writer,
+                    "{} X{} Y{} Z{} I{} J{} K{}{}",
+                    if oriented_motion.rapid { "G0" } else { "G1" },
+                    number(end.0),
+                    number(end.1),
+                    number(end.2),
+                    number(i),
+                    number(j),
+                    number(k),
+                    feed_word
+                )?;
+                position = end;
+            }
+            Motion::ToolChange(tool_change) => {
+                travel(&mut writer, &mut position, tool_change.position, "G0", decimals)?;
+                if flavor.tool_changes() || marked {
+                    writeln!(writer, "T{} M6", tool_change.tool)?;
+                } else {
+                    // Stop for whoever runs the machine to fit the tool
+                    let note = format!("change to tool {}", tool_change.tool);
+                    writeln!(writer, "M0 {}", flavor.comment(&note))?;
+                }
+            }
+            Motion::Aux(aux_command) => {
+                travel(&mut writer, &mut position, aux_command.position, "G0", decimals)?;
+                writeln!(writer, "{}", aux_command.code)?;
+            }
+            Motion::Pause(pause) => {
+                travel(&mut writer, &mut position, pause.position, "G0", decimals)?;
+                writeln!(writer, "{}", if pause.optional { "M1" } else { "M0" })?;
+            }
+        }
+    }
+
+    if marked {
+        writeln!(writer, "{}", flavor.comment("end"))?;
+    }
+    match flavor {
+        // Marlin has no program end: stop the spindle or laser if one ran, and let the motors go
+        Flavor::Marlin if power.is_some() => writeln!(writer, "M5\nM84"),
+        Flavor::Marlin => writeln!(writer, "M84"),
+        Flavor::LinuxCnc => writeln!(writer, "M2\n%"),
+        Flavor::Generic | Flavor::Grbl => writeln!(writer, "M2"),
+    }
+}
+
+/// Function to write a program in the `.cmmd` language
+///
+/// The counterpart of [`write_gcode`] for the native language. Feed rates,
+/// spindle speeds (or laser power) and arc planes are written on lines of
+/// their own whenever they change, and a `RAP` travel move is inserted where a
+/// motion starts away from where the previous one ended. Arcs are written in
+/// I/J form, split into pieces of at most a turn like in [`write_gcode`].
+/// Bézier curves whose height changes evenly become `BEZ` lines and other ones
+/// four-point `NURBS` curves, and splines become `NURBS` curves with their
+/// weights, and their knots unless those are clamped and uniform. Linear
+/// motions that turn the rotary axes get `A`, `B` and `C` words for the angles
+/// that change, and moves that tilt the tool give its direction at the end
+/// with `I`, `J` and `K` words.
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the program
+/// * `motions` - The motions of the program
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::post::write_cmmd;
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(300.0)),
+///     Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 10.0, 0.0), (-10.0, 0.0), false),
+/// ];
+/// let mut output = Vec::new();
+/// write_cmmd(&mut output, &motions).unwrap();
+/// let cmmd = String::from_utf8(output).unwrap();
+/// assert!(cmmd.contains("F300\nLIN X10 Y0 Z0\nCCW X0 Y10 Z0 I-10 J0\n"));
+/// ```
+pub fn write_cmmd<W: Write>(writer: W, motions: &[Motion]) -> io::Result<()> {
+    write_cmmd_with_tools(writer, motions, &ToolTable::default())
+}
+
+/// Function to write motions as a `.cmmd` program that was parsed with a tool table
+///
+/// Parsing adds the length of the tool in use to every programmed Z, so the
+/// length of each tool `tools` lists is taken off again after its `M6`. Reading the
+/// program back with the same table then gives the same motions. Otherwise
+/// this is [`write_cmmd`].
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the program
+/// * `motions` - The motions of the program
+/// * `tools` - The tool table the motions were parsed with
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::post::write_cmmd_with_tools;
+/// use project_2::tools::{Tool, ToolTable};
+///
+/// let mut tools = ToolTable::default();
+/// tools.tools.insert(1, Tool { length: 40.0, diameter: 6.0 });
+/// let motions = vec![
+///     Motion::new_tool_change(1, (0.0, 0.0, 5.0)),
+///     Motion::new_linear((0.0, 0.0, 5.0), (10.0, 0.0, 38.0)),
+/// ];
+/// let mut output = Vec::new();
+/// write_cmmd_with_tools(&mut output, &motions, &tools).unwrap();
+/// assert!(String::from_utf8(output).unwrap().contains("M6 T1\nLIN X10 Y0 Z-2\n"));
+/// ```
+pub fn write_cmmd_with_tools<W: Write>(mut writer: W, motions: &[Motion], tools: &ToolTable) -> io::Result<()> {
+    writeln!(writer, "# generated by project-2")?;
+
+    let mut position = (0.0, 0.0, 0.0);
+    let mut feed = None;
+    let mut power = None;
+    let mut current_plane = Plane::Xy;
+    // Rotary axes start at zero, like the readers assume
+    let mut angles = Rotary::default();
+    // Length of the tool in use, which reading the program back adds to Z
+    let mut length = 0.0;
+
+    for motion in motions {
+        let motion = &Transform::default()
+            .translate((0.0, 0.0, -length))
+            .motion(motion)
+            .expect("moving along Z keeps arcs round");
+
+        // Get the tool to the start first, so the travel move doesn't pick up this motion's feed rate
+        let start = match motion {
+            Motion::Linear(linear_motion) => linear_motion.start,
+            Motion::Rotational(rotational_motion) => arc_move(rotational_motion, position.2).start,
+            Motion::Bezier(bezier_motion) => bezier_motion.start,
+            // A spline starts from the tool's position as its first control point
+            Motion::Spline(spline_motion) => spline_motion.controls[0],
+            Motion::Oriented(oriented_motion) => oriented_motion.start,
+            Motion::ToolChange(tool_change) => tool_change.position,
+            Motion::Aux(aux_command) => aux_command.position,
+            Motion::Pause(pause) => pause.position,
+        };
+        travel(&mut writer, &mut position, start, "RAP", 4)?;
+
+        // Feed rates and power are modal, so only mention them when they change
+        if let Some(rate) = motion.feed().filter(|&rate| feed != Some(rate)) {
+            feed = Some(rate);
+            writeln!(writer, "F{}", number(rate))?;
+        }
+        if let Some(speed) = motion.power().filter(|&speed| power != Some(speed)) {
+            power = Some(speed);
+            writeln!(writer, "S{}", number(speed))?;
+        }
+
+        match motion {
+            Motion::Linear(linear_motion) => {
+                let end = linear_motion.end;
+                // Only mention the rotary axes whose angle changes
+                let mut turn_words = String::new();
+                if let Some((_, to)) = linear_motion.rotary {
+                    let axes = [('A', to.a, angles.a), ('B', to.b, angles.b), ('C', to.c, angles.c)];
+                    for (letter, angle, current) in axes {
+                        if (angle - current).abs() > 1e-9 {
+                            turn_words.push_str(&format!(" {}{}", letter, number(angle)));
+                        }
+                    }
+                    angles = to;
+                }
+                writeln!(
+                    writer,
+                    "{} X{} Y{} Z{}{}",
+                    if linear_motion.rapid { "RAP" } else { "LIN" },
+                    number(end.0),
+                    number(end.1),
+                    number(end.2),
+                    turn_words
+                )?;
+                position = end;
+            }
+            Motion::Rotational(rotational_motion) => {
+                let arc = arc_move(rotational_motion, position.2);
+                let plane = rotational_motion.plane;
+                if plane != current_plane {
+                    let name = match plane {
+                        Plane::Xy => "XY",
+                        Plane::Xz => "XZ",
+                        Plane::Yz => "YZ",
+                    };
+                    writeln!(writer, "PLANE {}", name)?;
+                    current_plane = plane;
+                }
+                // Center offset words for the plane's two axes; unlike G18, PLANE XZ isn't mirrored
+                let (first, second) = match plane {
+                    Plane::Xy => ('I', 'J'),
+                    Plane::Xz => ('I', 'K'),
+                    Plane::Yz => ('J', 'K'),
+                };
+                let start = plane.coordinates(arc.start);
+                let center = (start.0 + arc.offset.0, start.1 + arc.offset.1);
+                for end in arc_pieces(rotational_motion, &arc) {
+                    let here = plane.coordinates(position);
+                    writeln!(
+                        writer,
+                        "{} X{} Y{} Z{} {}{} {}{}",
+                        if arc.clockwise { "CW" } else { "CCW" },
+                        number(end.0),
+                        number(end.1),
+                        number(end.2),
+                        first,
+                        number(center.0 - here.0),
+                        second,
+                        number(center.1 - here.1)
+                    )?;
+                    position = end;
+                }
+            }
+            Motion::Bezier(bezier_motion) => {
+                let (start, end) = (bezier_motion.start, bezier_motion.end);
+                let (first, second) = (bezier_motion.control1, bezier_motion.control2);
+                // BEZ puts the control points a third and two thirds of the way up
+                let rise = end.2 - start.2;
+                let even = (first.2 - start.2 - rise / 3.0).abs() < 1e-9
+                    && (second.2 - start.2 - rise * 2.0 / 3.0).abs() < 1e-9;
+                if even {
+                    writeln!(
+                        writer,
+                        "BEZ X{} Y{} Z{} I{} J{} P{} Q{}",
+                        number(end.0),
+                        number(end.1),
+                        number(end.2),
+                        number(first.0 - start.0),
+                        number(first.1 - start.1),
+                        number(second.0 - end.0),
+                        number(second.1 - end.1)
+                    )?;
+                } else {
+                    // A cubic spline with four control points and clamped knots is the same curve
+                    let points: Vec<String> = [first, second, end].iter().map(|&point| tuple(point, None)).collect();
+                    writeln!(writer, "NURBS D3 {}", points.join(" "))?;
+                }
+                position = end;
+            }
+            Motion::Spline(spline_motion) => {
+                // The first control point has weight 1, and scaling every weight alike doesn't change the curve
+                let scale = spline_motion.weights[0];
+                let points: Vec<String> = spline_motion
+                    .controls
+                    .iter()
+                    .zip(&spline_motion.weights)
+                    .skip(1)
+                    .map(|(&point, weight)| tuple(point, Some(weight / scale)))
+                    .collect();
+                let mut line = format!("NURBS D{} {}", spline_motion.degree, points.join(" "));
+                if spline_motion.knots != clamped_knots(spline_motion.controls.len(), spline_motion.degree) {
+                    let knots: Vec<String> = spline_motion.knots.iter().map(|&knot| number(knot)).collect();
+                    line.push_str(&format!(" K({})", knots.join(", ")));
+                }
+                writeln!(writer, "{}", line)?;
+                position = spline_motion.end();
+            }
+            Motion::Oriented(oriented_motion) => {
+                let (end, (i, j, k)) = (oriented_motion.end, oriented_motion.orientation.1);
+                writeln!(
+                    writer,
+                    "{} X{} Y{} Z{} I{} J{} K{}",
+                    if oriented_motion.rapid { "RAP" } else { "LIN" },
+                    number(end.0),
+                    number(end.1),
+                    number(end.2),
+                    number(i),
+                    number(j),
+                    number(k)
+                )?;
+                position = end;
+            }
+            Motion::ToolChange(tool_change) => {
+                writeln!(writer, "M6 T{}", tool_change.tool)?;
+                // The tool stays put, but the Z that gets it there changes with the length
+                let new_length = tools.get(tool_change.tool).map_or(0.0, |tool| tool.length);
+                position.2 += length - new_length;
+                length = new_length;
+            }
+            Motion::Aux(aux_command) => writeln!(writer, "{}", aux_command.code)?,
+            Motion::Pause(pause) => writeln!(writer, "{}", if pause.optional { "M1" } else { "PAUSE" })?,
+        }
+    }
+    Ok(())
+}
+
+// Format a NURBS control point as `(x, y, z)`, or `(x, y, z, w)` with a weight other than 1
+fn tuple(point: (f64, f64, f64), weight: Option<f64>) -> String {
+    let mut numbers = vec![number(point.0), number(point.1), number(point.2)];
+    if let Some(weight) = weight.filter(|&weight| weight != 1.0) {
+        numbers.push(number(weight));
+    }
+    format!("({})", numbers.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode;
+    use crate::parser::ParseOptions;
+
+    /// Test that a converted program reads back as the same motions.
+    #[test]
+    fn test_round_trip() {
+        let motions = crate::parser::read_file("example.cmmd").unwrap();
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+
+        let path = std::env::temp_dir().join("project-2-post-round-trip.nc");
+        std::fs::write(&path, &output).unwrap();
+        let converted = gcode::read_file(path.to_str().unwrap(), &ParseOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(converted.len(), motions.len());
+        for (original, converted) in motions.iter().zip(&converted) {
+            match (original, converted) {
+                (Motion::Linear(a), Motion::Linear(b)) => assert_eq!(a.end, b.end),
+                (Motion::Rotational(a), Motion::Rotational(b)) => {
+                    let (a, b) = (arc_move(a, 0.0), arc_move(b, 0.0));
+                    assert_eq!(a.clockwise, b.clockwise);
+                    assert!((a.sweep - b.sweep).abs() < 1e-3);
+                    assert!((a.end.0 - b.end.0).abs() < 1e-3 && (a.end.1 - b.end.1).abs() < 1e-3);
+                }
+                _ => panic!("motion kind changed"),
+            }
+        }
+    }
+
+    /// Test that multi-turn helices are split and disconnected arcs get a travel move.
+    #[test]
+    fn test_helix_and_travel() {
+        let motions = vec![Motion::new_helical((0.0, 0.0), 5.0, 0.0, 720.0, (0.0, -2.0))];
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        let gcode = String::from_utf8(output).unwrap();
+        assert!(gcode.contains("G0 X5 Y0 Z0\n"));
+        assert!(gcode.contains("G3 X5 Y0 Z-1 I-5 J0\n"));
+        assert!(gcode.contains("G3 X5 Y0 Z-2 I-5 J0\n"));
+    }
+
+    /// Test that flat splines become G5.2 blocks and read back as the same spline.
+    #[test]
+    fn test_nurbs() {
+        let controls = vec![(0.0, 0.0, 0.0), (0.0, 10.0, 0.0), (10.0, 10.0, 0.0), (10.0, 0.0, 0.0), (20.0, 0.0, 0.0)];
+        let spline = Motion::new_spline(controls, vec![1.0, 1.0, 0.5, 1.0, 1.0], None, 3).unwrap();
+        let mut output = Vec::new();
+        write_gcode(&mut output, std::slice::from_ref(&spline)).unwrap();
+        let gcode = String::from_utf8(output).unwrap();
+        assert!(gcode.contains("G5.2 X0 Y10 P1 L4\nX10 Y10 P0.5\nX10 Y0 P1\nX20 Y0 P1\nG5.3\n"));
+
+        let converted = gcode::read_reader(gcode.as_bytes(), &ParseOptions::default()).unwrap();
+        let (Motion::Spline(a), [Motion::Spline(b)]) = (&spline, converted.as_slice()) else {
+            panic!("expected a spline")
+        };
+        assert_eq!((&a.controls, &a.weights, &a.knots), (&b.controls, &b.weights, &b.knots));
+    }
+
+    /// Test that flat curves become G5 moves and read back as the same curve.
+    #[test]
+    fn test_bezier() {
+        let curve = Motion::new_bezier((0.0, 0.0, 0.0), (0.0, 5.0, 0.0), (10.0, 5.0, 0.0), (10.0, 0.0, 0.0));
+        let mut output = Vec::new();
+        write_gcode(&mut output, std::slice::from_ref(&curve)).unwrap();
+        let gcode = String::from_utf8(output).unwrap();
+        assert!(gcode.contains("G5 X10 Y0 I0 J5 P0 Q5\n"));
+
+        let converted = gcode::read_reader(gcode.as_bytes(), &ParseOptions::default()).unwrap();
+        let (Motion::Bezier(a), [Motion::Bezier(b)]) = (&curve, converted.as_slice()) else {
+            panic!("expected a curve")
+        };
+        assert_eq!((a.control1, a.control2, a.end), (b.control1, b.control2, b.end));
+
+        // Curves that change height can't be G5 moves
+        let ramp = Motion::new_bezier((0.0, 0.0, 0.0), (0.0, 5.0, 0.0), (10.0, 5.0, 0.0), (10.0, 0.0, -1.0));
+        let mut output = Vec::new();
+        write_gcode(&mut output, &[ramp]).unwrap();
+        let gcode = String::from_utf8(output).unwrap();
+        assert!(!gcode.contains("G5") && gcode.contains("G1 X10 Y0 Z-1\n"));
+    }
+
+    /// Test that arcs in the XZ plane are written with G18 and read back the same way.
+    #[test]
+    fn test_xz_plane() {
+        let program = "PLANE XZ\nLIN X10 Y5 Z0\nCCW X0 Y5 Z10 I-10 K0\n";
+        let motions = crate::parser::read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        // Counter-clockwise from the front is clockwise seen from +Y
+        assert!(text.contains("G18\nG2 X0 Y5 Z10 I-10 K0\n"));
+
+        let converted = gcode::read_reader(text.as_bytes(), &ParseOptions::default()).unwrap();
+        let (Motion::Rotational(a), Motion::Rotational(b)) = (&motions[1], &converted[1]) else {
+            panic!("expected arcs")
+        };
+        assert_eq!(a.plane, b.plane);
+        let (a, b) = (arc_move(a, 0.0), arc_move(b, 0.0));
+        assert_eq!(a.clockwise, b.clockwise);
+        assert!((a.sweep - b.sweep).abs() < 1e-6);
+    }
+
+    /// Test that curves written as `.cmmd` read back as the same curves, as BEZ lines where they can be.
+    #[test]
+    fn test_cmmd_curves() {
+        let curve = Motion::new_bezier((0.0, 0.0, 0.0), (0.0, 5.0, -1.0), (10.0, 5.0, -2.0), (10.0, 0.0, -3.0));
+        let bulge = Motion::new_bezier((10.0, 0.0, -3.0), (10.0, 5.0, 0.0), (20.0, 5.0, 0.0), (20.0, 0.0, -3.0));
+        let controls = vec![(20.0, 0.0, -3.0), (20.0, 10.0, 0.0), (30.0, 10.0, 0.0), (30.0, 0.0, 0.0)];
+        let knots = vec![0.0, 0.0, 0.0, 0.25, 1.0, 1.0, 1.0];
+        let spline = Motion::new_spline(controls, vec![2.0, 1.0, 2.0, 2.0], Some(knots), 2).unwrap();
+        let motions = vec![curve, bulge, spline];
+        let mut output = Vec::new();
+        write_cmmd(&mut output, &motions).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("BEZ X10 Y0 Z-3 I0 J5 P0 Q5\n"));
+        assert!(text.contains("NURBS D3 (10, 5, 0) (20, 5, 0) (20, 0, -3)\n"));
+        assert!(text.contains("NURBS D2 (20, 10, 0, 0.5) (30, 10, 0) (30, 0, 0) K(0, 0, 0, 0.25, 1, 1, 1)\n"));
+
+        let converted = crate::parser::read_reader(text.as_bytes(), &ParseOptions::default()).unwrap();
+        for (original, converted) in motions.iter().zip(&converted) {
+            let point = |motion: &Motion, t: f64| match motion {
+                Motion::Bezier(curve) => curve.point(t),
+                Motion::Spline(curve) => curve.point(t),
+                _ => panic!("expected a curve"),
+            };
+            for t in [0.0, 0.3, 0.5, 1.0] {
+                let (a, b) = (point(original, t), point(converted, t));
+                assert!((a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6 && (a.2 - b.2).abs() < 1e-6);
+            }
+        }
+    }
+
+    /// Test that a program parsed with a tool table reads back at the same heights with that table.
+    #[test]
+    fn test_cmmd_tool_lengths() {
+        let mut options = ParseOptions::default();
+        options.tools.tools.insert(1, crate::tools::Tool { length: 40.0, diameter: 6.0 });
+        let program = "LIN X0 Y0 Z5\nTOOL 1\nLIN X5 Y0 Z-2\nCW X0 Y0 R5 A90 0 Z-2 Z-3\nM8\nTOOL 0\nLIN X0 Y0 Z0\n";
+        let motions = crate::parser::read_reader(program.as_bytes(), &options).unwrap();
+        let mut output = Vec::new();
+        write_cmmd_with_tools(&mut output, &motions, &options.tools).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("M6 T1\nLIN X5 Y0 Z-2\n"));
+
+        // Line numbers move with the header, so compare where each motion goes
+        let converted = crate::parser::read_reader(text.as_bytes(), &options).unwrap();
+        let heights = |motions: &[Motion]| -> Vec<(f64, f64, f64)> {
+            motions
+                .iter()
+                .map(|motion| match motion {
+                    Motion::Linear(line) => line.end,
+                    Motion::Rotational(arc) => arc_move(arc, 0.0).end,
+                    Motion::ToolChange(change) => change.position,
+                    Motion::Aux(aux) => aux.position,
+                    _ => panic!("unexpected motion"),
+                })
+                .map(|(x, y, z)| ((x * 1e6).round() / 1e6, (y * 1e6).round() / 1e6, z))
+                .collect()
+        };
+        assert_eq!(heights(&converted), heights(&motions));
+        assert_eq!(heights(&converted)[2], (5.0, 0.0, 38.0));
+    }
+
+    /// Test that the spindle speed is written when it changes.
+    #[test]
+    fn test_power_words() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_power(Some(1000.0)),
+            Motion::new_linear((1.0, 0.0, 0.0), (2.0, 0.0, 0.0)).with_power(Some(1000.0)),
+            Motion::new_linear((2.0, 0.0, 0.0), (3.0, 0.0, 0.0)).with_power(Some(0.0)),
+        ];
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("G1 X1 Y0 Z0 S1000\nG1 X2 Y0 Z0\nG1 X3 Y0 Z0 S0\n"));
+
+        let converted = gcode::read_reader(text.as_bytes(), &ParseOptions::default()).unwrap();
+        let powers: Vec<_> = converted.iter().map(Motion::power).collect();
+        assert_eq!(powers, vec![Some(1000.0), Some(1000.0), Some(0.0)]);
+    }
+
+    /// Test that extruder positions are written as E words, reset with G92, and read back the same way.
+    #[test]
+    fn test_extruder_words() {
+        let program = "G1 X1 E1 F600\nG1 E0.5\nG92 E0\nG1 X2 E1\nG1 X3\n";
+        let motions = gcode::read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("G1 X1 Y0 Z0 E1 F600\nG1 X1 Y0 Z0 E0.5\nG92 E0\nG1 X2 Y0 Z0 E1\nG1 X3 Y0 Z0\n"));
+
+        let converted = gcode::read_reader(text.as_bytes(), &ParseOptions::default()).unwrap();
+        let extrusion = |motions: &[Motion]| -> Vec<_> {
+            motions
+                .iter()
+                .map(|motion| match motion {
+                    Motion::Linear(line) => line.extrusion,
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(extrusion(&converted), extrusion(&motions));
+    }
+
+    /// Test that each flavor gets its own start and end blocks, decimals and arcs.
+    #[test]
+    fn test_flavors() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (5.0, 0.0, 0.0)).with_feed(Some(300.0)).with_power(Some(1000.0)),
+            Motion::new_helical((0.0, 0.0), 5.0, 0.0, 720.0, (0.0, -2.0)),
+            Motion::new_bezier((0.0, 0.0, 0.0), (0.0, 5.0, 0.0), (10.0, 5.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 0.0, 0.0), (-5.0, 0.0), false).with_plane(Plane::Xz),
+            Motion::new_tool_change(2, (0.0, 0.0, 0.0)),
+        ];
+        let write = |flavor| {
+            let mut output = Vec::new();
+            write_gcode_for(&mut output, &motions, flavor).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        // LinuxCNC makes both turns of the helix in one move, and keeps the curve and the tool change
+        let linuxcnc = write(Flavor::LinuxCnc);
+        assert!(linuxcnc.starts_with("%\n(generated by project-2)\nG21 G90 G17 G40 G49 G80 G94\n"));
+        assert!(linuxcnc.contains("G3 X5 Y0 Z-2 I-5 J0 P2\n"));
+        assert!(linuxcnc.contains("G5 X10 Y0") && linuxcnc.contains("G18\n") && linuxcnc.contains("T2 M6\n"));
+        assert!(linuxcnc.ends_with("M2\n%\n"));
+
+        // GRBL splits the helix, draws the curve as lines and stops for the tool change
+        let grbl = write(Flavor::Grbl);
+        assert!(grbl.contains("G3 X5 Y0 Z-1 I-5 J0\nG3 X5 Y0 Z-2 I-5 J0\n"));
+        assert!(!grbl.contains("G5") && grbl.contains("G18\n") && grbl.contains("M0 (change to tool 2)\n"));
+
+        // Marlin also draws the arc outside XY as lines, and turns the laser off at the end
+        let marlin = write(Flavor::Marlin);
+        assert!(!marlin.contains("G18") && !marlin.contains("G5") && !marlin.contains("T2"));
+        assert!(marlin.contains("M0 ;change to tool 2\n") && marlin.ends_with("M5\nM84\n"));
+        let converted = gcode::read_reader(marlin.as_bytes(), &ParseOptions::default()).unwrap();
+        let last = converted.iter().rev().find_map(|motion| match motion {
+            Motion::Linear(line) => Some(line.end),
+            _ => None,
+        });
+        assert!(last.is_some_and(|end| end.0.abs() < 1e-3 && end.2.abs() < 1e-3));
+
+        // The generic flavor is what write_gcode writes
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), write(Flavor::Generic));
+    }
+
+    /// Test that motions a controller can't make are refused rather than written wrong.
+    #[test]
+    fn test_unsupported_words() {
+        let turn = Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0))
+            .with_rotary(Rotary::default(), Rotary { a: 90.0, ..Default::default() });
+        let extrude = Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_extrusion(0.0, 1.0);
+        let tilt = Motion::new_oriented((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 1.0, 1.0));
+        let written = |motion: &Motion, flavor| write_gcode_for(Vec::new(), std::slice::from_ref(motion), flavor);
+        assert!(written(&turn, Flavor::LinuxCnc).is_ok() && written(&turn, Flavor::Grbl).is_err());
+        assert!(written(&extrude, Flavor::Marlin).is_ok() && written(&extrude, Flavor::LinuxCnc).is_err());
+        let error = written(&tilt, Flavor::Marlin).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "Marlin can't tilt the tool");
+    }
+
+    /// Test that rotary axis words are written when they change and read back the same way.
+    #[test]
+    fn test_rotary_words() {
+        let program = "LIN X1 Y0 Z0 A90\nLIN X2 Y0 Z0 C45\nLIN X3 Y0 Z0\n";
+        let motions = crate::parser::read_reader(program.as_bytes(), &ParseOptions::default()).unwrap();
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("G1 X1 Y0 Z0 A90\nG1 X2 Y0 Z0 C45\nG1 X3 Y0 Z0\n"));
+
+        let converted = gcode::read_reader(text.as_bytes(), &ParseOptions::default()).unwrap();
+        let rotary = |motions: &[Motion]| -> Vec<_> {
+            motions
+                .iter()
+                .map(|motion| match motion {
+                    Motion::Linear(line) => line.rotary,
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(rotary(&converted), rotary(&motions));
+    }
+
+    /// Test that moves tilting the tool are written with I/J/K and read back the same way.
+    #[test]
+    fn test_tool_vector_words() {
+        let motions = vec![
+            Motion::new_oriented((0.0, 0.0, 0.0), (10.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, -1.0, 1.0))
+                .with_feed(Some(500.0)),
+            Motion::new_oriented((10.0, 0.0, 0.0), (10.0, 5.0, 0.0), (0.0, -1.0, 1.0), (0.0, -1.0, 1.0)),
+        ];
+        let mut output = Vec::new();
+        write_gcode(&mut output, &motions).unwrap();
+        let text = String::from_utf8(output).unwrap();
+        assert!(text.contains("G1 X10 Y0 Z0 I0 J-0.7071 K0.7071 F500\n"));
+
+        let converted = gcode::read_reader(text.as_bytes(), &ParseOptions::default()).unwrap();
+        assert_eq!(converted.len(), 2);
+        for (a, b) in motions.iter().zip(&converted) {
+            let (Motion::Oriented(a), Motion::Oriented(b)) = (a, b) else { panic!("expected tilting moves") };
+            assert_eq!(a.end, b.end);
+            assert!((a.orientation.1 .1 - b.orientation.1 .1).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Whole programs as data
+//!
+//! A [`Program`] holds the motions a program was parsed into, and like every
+//! motion type it can be serialized and deserialized with serde, so parsed
+//! programs can be stored, edited by other tools and kept as test fixtures in
+//! JSON, YAML or any other format serde supports. Motions are tagged with
+//! their `kind`, as in the `--format json` output. Fields that are left out
+//! take their defaults: no feed rate, power or line, not rapid, no rotary
+//! axes and the XY plane. Splines are checked as they're read, as
+//! [`Motion::new_spline`] checks them.
+//!
+//! Programs are also what converting between languages goes through: any
+//! [`Dialect`] is read into a `Program` and written back out in any other,
+//! keeping arcs as arcs. HP-GL has no arcs to keep, so a plot is written
+//! from the interpolated path.
+
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::CmmdError;
+use crate::hpgl::write_hpgl;
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::parser::{read_program, Dialect, ParseOptions};
+use crate::post::{write_cmmd, write_gcode};
+
+/// A parsed program, in machine coordinates and millimeters
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    /// The motions of the program, in order
+    pub motions: Vec<Motion>,
+}
+
+impl Program {
+    /// Constructor for a program made of these motions
+    pub fn new(motions: Vec<Motion>) -> Program {
+        Program { motions }
+    }
+
+    /// Function to write the program as pretty-printed JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("programs always serialize")
+    }
+
+    /// Function to read a program written as JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns the JSON error if the text isn't a program, including splines
+    /// whose control points, weights and knots don't fit together.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    /// use project_2::program::Program;
+    ///
+    /// let program = Program::from_json(r#"{"motions": [
+    ///     {"kind": "linear", "start": [0, 0, 5], "end": [0, 0, 0], "rapid": true},
+    ///     {"kind": "linear", "start": [0, 0, 0], "end": [10, 0, 0], "feed": 300}
+    /// ]}"#).unwrap();
+    /// assert_eq!(program.motions[1], Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(300.0)));
+    /// assert_eq!(Program::from_json(&program.to_json()).unwrap(), program);
+    /// ```
+    pub fn from_json(text: &str) -> Result<Program, serde_json::Error> {
+        serde_json::from_str(text)
+    }
+
+    /// Function to read a program from a file in the given dialect
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [`read_program`].
+    pub fn read(file_path: &str, dialect: Dialect, options: &ParseOptions) -> Result<Program, CmmdError> {
+        read_program(file_path, dialect, options).map(Program::new)
+    }
+
+    /// Function to write the program in the given dialect
+    ///
+    /// `.cmmd` and G-code are written by [`write_cmmd`] and [`write_gcode`],
+    /// and JSON as by [`Program::to_json`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an I/O error if writing fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::parser::{read_program_from, Dialect, ParseOptions};
+    /// use project_2::program::Program;
+    ///
+    /// let gcode = "G1 X10 Y0 F300\nG3 X0 Y10 I-10 J0\n";
+    /// let motions = read_program_from(gcode.as_bytes(), Dialect::Gcode, &ParseOptions::default()).unwrap();
+    /// let program = Program::new(motions);
+    /// let mut cmmd = Vec::new();
+    /// program.write(&mut cmmd, Dialect::Cmmd).unwrap();
+    /// assert!(String::from_utf8(cmmd).unwrap().contains("CCW X0 Y10 Z0 I-10 J0\n"));
+    /// ```
+    pub fn write<W: Write>(&self, mut writer: W, dialect: Dialect) -> io::Result<()> {
+        match dialect {
+            Dialect::Cmmd => write_cmmd(writer, &self.motions),
+            Dialect::Gcode => write_gcode(writer, &self.motions),
+            Dialect::Json => writeln!(writer, "{}", self.to_json()),
+            Dialect::Hpgl => write_hpgl(writer, &self.motions, &InterpolationOptions::default()),
+        }
+    }
+}
+
+impl From<Vec<Motion>> for Program {
+    fn from(motions: Vec<Motion>) -> Self {
+        Program::new(motions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion::{Plane, Rotary};
+    use crate::parser::{read_program_from, read_reader};
+    use crate::post::arc_move;
+
+    /// Test that every kind of motion comes back the same after a trip through JSON.
+    #[test]
+    fn test_round_trip() {
+        let mut motions = read_reader("F300\nS12000\nLIN X10 Y0 Z0\n".as_bytes(), &ParseOptions::default()).unwrap();
+        let turn = Rotary { b: 45.0, ..Default::default() };
+        let controls = vec![(0.0, 0.0, 0.0), (0.0, 5.0, 0.0), (5.0, 5.0, 0.0), (5.0, 0.0, 0.0)];
+        motions.extend([
+            Motion::new_rapid((10.0, 0.0, 0.0), (10.0, 0.0, 5.0)).with_rotary(Rotary::default(), turn),
+            Motion::new_helical((0.0, 0.0), 5.0, 0.0, -90.0, (5.0, 2.0)).with_plane(Plane::Yz),
+            Motion::new_bezier((0.0, 0.0, 0.0), (1.0, 2.0, 0.0), (3.0, 2.0, 0.0), (4.0, 0.0, 0.0)),
+            Motion::new_spline(controls, vec![1.0, 2.0, 2.0, 1.0], None, 3).unwrap().with_line(7),
+            Motion::new_oriented((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 0.0, 1.0), (0.0, 1.0, 0.0)),
+        ]);
+        let program = Program::from(motions);
+
+        let kinds = ["linear", "rotational", "bezier", "spline", "oriented"];
+        let json = program.to_json();
+        assert!(kinds.iter().all(|kind| json.contains(&format!("\"kind\": \"{}\"", kind))));
+        assert_eq!(Program::from_json(&json).unwrap(), program);
+    }
+
+    /// Test that a program comes back with the same arcs, feeds, power and planes from every language.
+    #[test]
+    fn test_convert() {
+        let text = "F300\nS1000\nLIN X10 Y0 Z0\nCW X0 Y-10 Z-1 I-10 J0\nF150\nPLANE XZ\nCCW X-10 Y-10 Z9 I0 K10\n";
+        let program = Program::new(read_reader(text.as_bytes(), &ParseOptions::default()).unwrap());
+        for dialect in [Dialect::Cmmd, Dialect::Gcode, Dialect::Json] {
+            let mut output = Vec::new();
+            program.write(&mut output, dialect).unwrap();
+            let motions = read_program_from(output.as_slice(), dialect, &ParseOptions::default()).unwrap();
+            assert_eq!(motions.len(), program.motions.len(), "{:?}", dialect);
+            for (original, converted) in program.motions.iter().zip(&motions) {
+                assert_eq!((original.feed(), original.power()), (converted.feed(), converted.power()));
+                match (original, converted) {
+                    (Motion::Linear(a), Motion::Linear(b)) => assert_eq!(a.end, b.end),
+                    (Motion::Rotational(a), Motion::Rotational(b)) => {
+                        assert_eq!((a.plane, a.center, a.radius, a.z), (b.plane, b.center, b.radius, b.z));
+                        assert_eq!(arc_move(a, 0.0).clockwise, arc_move(b, 0.0).clockwise);
+                    }
+                    _ => panic!("{:?} changed the kind of a motion", dialect),
+                }
+            }
+        }
+    }
+
+    /// Test that splines whose parts don't fit together are refused.
+    #[test]
+    fn test_invalid_spline() {
+        let json = r#"{"motions": [
+            {"kind": "spline", "controls": [[0, 0, 0]], "weights": [1], "knots": [], "degree": 3}
+        ]}"#;
+        assert!(Program::from_json(json).is_err());
+        assert!(Program::from_json(r#"{"motions": [{"kind": "circle"}]}"#).is_err());
+    }
+}
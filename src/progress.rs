@@ -0,0 +1,159 @@
+//! Progress bars for long jobs
+//!
+//! Interpolating or streaming a large program can take minutes. A
+//! [`Progress`] bar shows how far through the program the job is, which line
+//! of the source it has reached and how long is left. Both the fraction done
+//! and the time left come from the feed-rate time model of
+//! [`motion_times`], so a long slow cut counts for more than many quick
+//! rapid moves.
+
+use std::io::{self, Write};
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::stats::motion_times;
+
+/// Characters the bar is drawn with
+const WIDTH: usize = 30;
+
+/// A progress bar over the motions of a program
+#[derive(Debug, Clone)]
+pub struct Progress {
+    // Seconds left from the start of each motion to the end of the program,
+    // with the end itself last
+    remaining: Vec<f64>,
+    // Line of the program each motion came from
+    lines: Vec<Option<usize>>,
+    // The motion the bar was last drawn for
+    drawn: Option<usize>,
+}
+
+impl Progress {
+    /// Constructor for a progress bar over a program's motions
+    pub fn new(motions: &[Motion], options: &InterpolationOptions) -> Progress {
+        let mut remaining: Vec<f64> = motion_times(motions, options)
+            .into_iter()
+            .rev()
+            .scan(0.0, |left, time| {
+                *left += time;
+                Some(*left)
+            })
+            .collect();
+        remaining.reverse();
+        remaining.push(0.0);
+        Progress {
+            remaining,
+            lines: motions.iter().map(Motion::line).collect(),
+            drawn: None,
+        }
+    }
+
+    /// Function to describe how far through the program a motion is
+    ///
+    /// # Returns
+    ///
+    /// A bar with the percentage done, the motion's source line and the
+    /// estimated time left, counting the motion itself as still to run, or
+    /// just the full bar once past the last motion. A program the model can't
+    /// time at all is measured in motions instead.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    /// use project_2::progress::Progress;
+    ///
+    /// let motions = vec![
+    ///     Motion::new_linear((0.0, 0.0, 0.0), (0.0, 0.0, 10.0)).with_feed(Some(60.0)).with_line(1),
+    ///     Motion::new_linear((0.0, 0.0, 10.0), (0.0, 0.0, 40.0)).with_feed(Some(60.0)).with_line(2),
+    /// ];
+    /// let progress = Progress::new(&motions, &Default::default());
+    /// assert!(progress.status(1).ends_with(" 25% line 2, 0m 30s left"));
+    /// ```
+    pub fn status(&self, motion: usize) -> String {
+        let motions = self.remaining.len() - 1;
+        let (total, left) = (self.remaining[0], self.remaining[motion.min(motions)]);
+        let done = if total > 0.0 {
+            1.0 - left / total
+        } else if motions > 0 {
+            motion.min(motions) as f64 / motions as f64
+        } else {
+            1.0
+        };
+        let filled = (done * WIDTH as f64).round() as usize;
+        let bar = format!("[{}{}] {:3.0}%", "#".repeat(filled), "-".repeat(WIDTH - filled), done * 100.0);
+        if motion >= motions {
+            return format!("{} done", bar);
+        }
+        let place = match self.lines[motion] {
+            Some(line) => format!("line {}", line),
+            None => format!("motion {}", motion),
+        };
+        // Whole minutes and seconds read better than thousands of seconds
+        let seconds = left.round() as u64;
+        format!("{} {}, {}m {:02}s left", bar, place, seconds / 60, seconds % 60)
+    }
+
+    /// Function to redraw the bar in place when the job moves on to another motion
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing to `out`.
+    pub fn update<W: Write>(&mut self, out: &mut W, motion: usize) -> io::Result<()> {
+        if self.drawn == Some(motion) {
+            return Ok(());
+        }
+        self.drawn = Some(motion);
+        // Clear whatever was left over from a longer status
+        write!(out, "\r{}\x1b[K", self.status(motion))?;
+        out.flush()
+    }
+
+    /// Function to draw the finished bar and move on to a new line
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing to `out`.
+    pub fn finish<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        self.update(out, self.remaining.len() - 1)?;
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that the bar fills by time rather than motions, and that untimed programs count motions.
+    #[test]
+    fn test_status() {
+        // A slow cut and a quick rapid move back
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (100.0, 0.0, 0.0)).with_feed(Some(100.0)).with_line(3),
+            Motion::new_rapid((100.0, 0.0, 0.0), (0.0, 0.0, 0.0)).with_line(4),
+        ];
+        let progress = Progress::new(&motions, &Default::default());
+        assert_eq!(progress.status(0), format!("[{}]   0% line 3, 1m 01s left", "-".repeat(WIDTH)));
+        assert!(progress.status(1).contains(" 98% line 4, 0m 01s left"));
+        assert_eq!(progress.status(2), format!("[{}] 100% done", "#".repeat(WIDTH)));
+
+        let untimed = vec![Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)); 4];
+        let progress = Progress::new(&untimed, &Default::default());
+        assert!(progress.status(1).contains(" 25% motion 1, 0m 00s left"));
+    }
+
+    /// Test that the bar is only redrawn when the motion changes.
+    #[test]
+    fn test_update() {
+        let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(60.0)); 2];
+        let mut progress = Progress::new(&motions, &Default::default());
+        let mut out = Vec::new();
+        for motion in [0, 0, 1, 1, 1] {
+            progress.update(&mut out, motion).unwrap();
+        }
+        progress.finish(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches('\r').count(), 3);
+        assert!(out.ends_with("100% done\x1b[K\n"));
+    }
+}
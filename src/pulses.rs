@@ -0,0 +1,189 @@
+//! Step and direction pulse timetables
+//!
+//! Stepper drivers don't take positions: they take a direction and one pulse
+//! per step. A pulse generator on an FPGA or microcontroller only has to
+//! replay a timetable of those pulses, so the planning can happen here. Each
+//! motor's position is taken as moving in a straight line between timed
+//! samples, and a step falls wherever it crosses the halfway point between
+//! two steps, so the motor is always on the step nearest the planned position.
+//!
+//! Generators that run on a fixed tick instead take the number of steps to
+//! make in each tick, which [`step_counts`] adds up.
+
+use serde::Serialize;
+
+use crate::trajectory::TimedPosition;
+
+/// One step of one motor
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StepEvent {
+    /// Seconds since the start of the program
+    pub time: f64,
+    /// Which motor steps: 0 for X (or A, or the shoulder), 1 for Y, 2 for Z
+    pub motor: usize,
+    /// Whether the step is towards positive positions
+    pub forward: bool,
+}
+
+/// The steps every motor makes in one tick of a pulse generator
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StepCount {
+    /// Seconds since the start of the program that the tick begins at
+    pub time: f64,
+    /// Steps made by each motor during the tick, negative going backwards
+    pub steps: [i64; 3],
+}
+
+// The step a motor is nearest to at a position, in steps
+fn nearest(steps: f64) -> i64 {
+    (steps + 0.5).floor() as i64
+}
+
+/// Function to find when each motor steps along a timed trajectory
+///
+/// The motors start on the step nearest the first sample.
+///
+/// # Arguments
+///
+/// * `samples` - Timed motor positions, in order
+/// * `steps_per_mm` - Steps per unit of travel of each motor
+///
+/// # Returns
+///
+/// Every step of every motor, in order of time.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::pulses::step_events;
+/// use project_2::trajectory::TimedPosition;
+///
+/// let sample = |time, x| TimedPosition {
+///     time,
+///     position: (x, 0.0, 0.0),
+///     motion: 0,
+///     power: None,
+///     rotary: None,
+///     extrusion: None,
+/// };
+/// let steps = step_events(&[sample(0.0, 0.0), sample(1.0, 1.0)], [4.0, 4.0, 4.0]);
+/// let times: Vec<f64> = steps.iter().map(|step| step.time).collect();
+/// assert_eq!(times, [0.125, 0.375, 0.625, 0.875]);
+/// assert!(steps.iter().all(|step| step.motor == 0 && step.forward));
+/// ```
+pub fn step_events(samples: &[TimedPosition], steps_per_mm: [f64; 3]) -> Vec<StepEvent> {
+    let mut events = Vec::new();
+    for pair in samples.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let starts = [from.position.0, from.position.1, from.position.2];
+        let ends = [to.position.0, to.position.1, to.position.2];
+        let first = events.len();
+        for motor in 0..3 {
+            let (start, end) = (starts[motor] * steps_per_mm[motor], ends[motor] * steps_per_mm[motor]);
+            let (before, after) = (nearest(start), nearest(end));
+            // Each step happens as the motor passes halfway to the next one
+            let time_at = |halfway: f64| from.time + (halfway - start) / (end - start) * (to.time - from.time);
+            if after > before {
+                events.extend((before..after).map(|step| StepEvent {
+                    time: time_at(step as f64 + 0.5),
+                    motor,
+                    forward: true,
+                }));
+            } else {
+                events.extend((after..before).rev().map(|step| StepEvent {
+                    time: time_at(step as f64 + 0.5),
+                    motor,
+                    forward: false,
+                }));
+            }
+        }
+        // Interleave the motors' steps between these two samples
+        events[first..].sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+    events
+}
+
+/// Function to add up the steps each motor makes in each tick of a pulse generator
+///
+/// # Arguments
+///
+/// * `events` - Steps in order of time, from [`step_events`]
+/// * `tick` - Length of a tick, in seconds
+///
+/// # Returns
+///
+/// One count per tick from the start of the program to the last step.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::pulses::{step_counts, StepEvent};
+///
+/// let step = |time, forward| StepEvent { time, motor: 1, forward };
+/// let counts = step_counts(&[step(0.1, true), step(0.2, true), step(1.5, false)], 1.0);
+/// assert_eq!(counts[0].steps, [0, 2, 0]);
+/// assert_eq!(counts[1].steps, [0, -1, 0]);
+/// ```
+pub fn step_counts(events: &[StepEvent], tick: f64) -> Vec<StepCount> {
+    let mut counts: Vec<StepCount> = Vec::new();
+    for event in events {
+        let index = (event.time / tick).floor().max(0.0) as usize;
+        while counts.len() <= index {
+            counts.push(StepCount {
+                time: counts.len() as f64 * tick,
+                steps: [0; 3],
+            });
+        }
+        counts[index].steps[event.motor] += if event.forward { 1 } else { -1 };
+    }
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time: f64, position: (f64, f64, f64)) -> TimedPosition {
+        TimedPosition {
+            time,
+            position,
+            motion: 0,
+            power: None,
+            rotary: None,
+            extrusion: None,
+        }
+    }
+
+    /// Test that steps follow each motor there and back, and add up to where it ends.
+    #[test]
+    fn test_step_events() {
+        let samples = [
+            sample(0.0, (0.0, 0.0, 0.0)),
+            sample(1.0, (1.0, -0.5, 0.0)),
+            sample(2.0, (0.0, -0.5, 0.0)),
+        ];
+        let events = step_events(&samples, [10.0, 10.0, 100.0]);
+        let net = |motor| {
+            events
+                .iter()
+                .filter(|step| step.motor == motor)
+                .map(|step| if step.forward { 1 } else { -1 })
+                .sum::<i64>()
+        };
+        assert_eq!((net(0), net(1), net(2)), (0, -5, 0));
+        assert_eq!(events.iter().filter(|step| step.motor == 0).count(), 20);
+        assert!(events.windows(2).all(|pair| pair[0].time <= pair[1].time));
+        // X turns around at the second sample, halfway between its last steps each way
+        let turn = events.iter().position(|step| step.motor == 0 && !step.forward).unwrap();
+        assert!((events[turn].time - 1.05).abs() < 1e-12);
+    }
+
+    /// Test that steps are counted into the tick they fall in.
+    #[test]
+    fn test_step_counts() {
+        let samples = [sample(0.0, (0.0, 0.0, 0.0)), sample(0.01, (0.0, 0.0, 1.0))];
+        let counts = step_counts(&step_events(&samples, [1.0, 1.0, 400.0]), 0.001);
+        assert_eq!(counts.len(), 10);
+        assert!(counts.iter().all(|count| count.steps == [0, 0, 40]));
+    }
+}
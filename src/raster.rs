@@ -0,0 +1,328 @@
+//! Laser raster engraving from grayscale images
+//!
+//! Diode lasers shade an image by burning it line by line, with the power
+//! following how dark each pixel is. [`raster`] turns an [`Image`] into that
+//! program directly: one straight cut along X for every row of pixels, going
+//! back and forth, each split into runs of pixels burnt at the same power.
+//! Black pixels get the full power, white ones none, and the shades between
+//! are in proportion.
+//!
+//! The laser speeds up and slows down at the ends of each line, where it would
+//! burn darker than in the middle. Overscan runs each line on past the image
+//! with the laser off, so the part that's engraved is all at full speed.
+//! Rows that are all white are skipped, and so are the white pixels at either
+//! end of a row.
+//!
+//! Images are read as PGM, the plain grayscale format most image editors can
+//! save (and which [`write_pgm`](crate::simulate::Stock::write_pgm) writes).
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use crate::motion::Motion;
+
+type Point = (f64, f64, f64);
+
+/// A grayscale image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    /// Number of pixels along each row
+    pub width: usize,
+    /// Number of rows
+    pub height: usize,
+    /// Brightness of each pixel, 0 for black and 255 for white, by row from the top of the image
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Function to read a PGM image, in either its binary (`P5`) or text (`P2`) form
+    ///
+    /// Images with more than 256 levels of gray are scaled down to 256.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from reading, or `InvalidData` if the data isn't a PGM image.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::raster::Image;
+    ///
+    /// let image = Image::read_pgm("P2\n# a comment\n3 1\n15\n0 15 5\n".as_bytes()).unwrap();
+    /// assert_eq!((image.width, image.height), (3, 1));
+    /// assert_eq!(image.pixels, vec![0, 255, 85]);
+    /// ```
+    pub fn read_pgm<R: Read>(mut reader: R) -> io::Result<Image> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+        // The header is the magic number, the size and the largest value, separated by whitespace and comments
+        let mut at = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            match data.get(at) {
+                Some(b'#') => {
+                    while data.get(at).is_some_and(|&byte| byte != b'\n') {
+                        at += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => at += 1,
+                Some(_) => {
+                    let start = at;
+                    while data.get(at).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                        at += 1;
+                    }
+                    header.push(String::from_utf8_lossy(&data[start..at]).into_owned());
+                }
+                None => return Err(invalid("PGM header is cut short")),
+            }
+        }
+        let binary = match header[0].as_str() {
+            "P5" => true,
+            "P2" => false,
+            _ => return Err(invalid("not a PGM image")),
+        };
+        let number = |text: &str| text.parse::<usize>().map_err(|_| invalid("PGM header has a malformed number"));
+        let (width, height, max) = (number(&header[1])?, number(&header[2])?, number(&header[3])?);
+        if !(1..=65535).contains(&max) {
+            return Err(invalid("PGM largest value must be 1 to 65535"));
+        }
+        let count = width.checked_mul(height).ok_or_else(|| invalid("PGM image is too large"))?;
+
+        // A single whitespace byte separates the header from binary pixels
+        let body = &data[(at + 1).min(data.len())..];
+        let values: Vec<usize> = if !binary {
+            let text = String::from_utf8_lossy(body);
+            text.split_ascii_whitespace().take(count).map(number).collect::<io::Result<_>>()?
+        } else if max < 256 {
+            body.iter().take(count).map(|&value| value as usize).collect()
+        } else {
+            body.chunks_exact(2).take(count).map(|pair| (pair[0] as usize) << 8 | pair[1] as usize).collect()
+        };
+        if values.len() < count {
+            return Err(invalid("PGM image has fewer pixels than its size says"));
+        }
+        let pixels = values.iter().map(|&value| (value.min(max) * 255 / max) as u8).collect();
+        Ok(Image { width, height, pixels })
+    }
+
+    /// Function to read a PGM image from a file
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from opening or reading the file, or `InvalidData` if
+    /// it isn't a PGM image.
+    pub fn load(path: &Path) -> io::Result<Image> {
+        Image::read_pgm(BufReader::new(File::open(path)?))
+    }
+
+    /// Function to get the brightness of a pixel
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The pixel's column, from the left of the image
+    /// * `row` - The pixel's row, from the top of the image
+    pub fn pixel(&self, column: usize, row: usize) -> u8 {
+        self.pixels[row * self.width + column]
+    }
+}
+
+/// Options controlling how an image is engraved
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterOptions {
+    /// Where the bottom left corner of the image goes, with Z the height to engrave at
+    pub origin: Point,
+    /// Width and height of each pixel, which is also the distance between lines
+    pub pixel: f64,
+    /// Feed rate of every line, in units per minute
+    pub feed: f64,
+    /// Laser power black pixels are burnt at
+    pub power: f64,
+    /// How far each line runs on past the image at either end, with the laser off
+    pub overscan: f64,
+}
+
+impl Default for RasterOptions {
+    fn default() -> Self {
+        RasterOptions {
+            origin: (0.0, 0.0, 0.0),
+            pixel: 0.1,
+            feed: 3000.0,
+            power: 1000.0,
+            overscan: 0.0,
+        }
+    }
+}
+
+/// Function to turn a grayscale image into a back-and-forth raster engraving program
+///
+/// Each row is one line along X, with the bottom row at the origin's Y and the
+/// others above it, a pixel apart. A line is made of straight cuts, one for
+/// each run of pixels that get the same power, which is rounded to a whole
+/// number. Rapids join the lines, starting from the origin.
+///
+/// # Arguments
+///
+/// * `image` - The image to engrave
+/// * `options` - Where and how to engrave it
+///
+/// # Returns
+///
+/// The motions of the program, which is empty if the image is all white.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::raster::{raster, Image, RasterOptions};
+///
+/// // A black pixel and a gray one, above a white row
+/// let image = Image { width: 2, height: 2, pixels: vec![0, 128, 255, 255] };
+/// let motions = raster(&image, &RasterOptions { pixel: 0.5, power: 100.0, ..Default::default() });
+/// assert_eq!(motions.len(), 3);
+/// assert!(motions[0].is_rapid());
+/// assert!(matches!(&motions[1], Motion::Linear(cut) if cut.end == (0.5, 0.75, 0.0)));
+/// assert_eq!(motions[1].power(), Some(100.0));
+/// assert_eq!(motions[2].power(), Some(50.0));
+/// ```
+pub fn raster(image: &Image, options: &RasterOptions) -> Vec<Motion> {
+    let (x0, y0, z) = options.origin;
+    let pixel = options.pixel;
+    let power = |brightness: u8| ((255 - brightness) as f64 / 255.0 * options.power).round();
+    let cut = |start: Point, end: Point, power: f64| {
+        Motion::new_linear(start, end).with_feed(Some(options.feed)).with_power(Some(power))
+    };
+
+    let mut motions = Vec::new();
+    let mut position = options.origin;
+    let mut forward = true;
+    // Rows go up the image from the bottom, which is the lowest Y
+    for row in (0..image.height).rev() {
+        let powers: Vec<f64> = (0..image.width).map(|column| power(image.pixel(column, row))).collect();
+        let (Some(first), Some(last)) =
+            (powers.iter().position(|&power| power > 0.0), powers.iter().rposition(|&power| power > 0.0))
+        else {
+            continue;
+        };
+        let y = y0 + ((image.height - 1 - row) as f64 + 0.5) * pixel;
+        // Edges of the pixels from the first burnt one to the last, in the order the line runs
+        let (columns, sign): (Vec<usize>, f64) = if forward {
+            ((first..=last).collect(), 1.0)
+        } else {
+            ((first..=last).rev().collect(), -1.0)
+        };
+        let edge = |column: usize| x0 + (column + usize::from(!forward)) as f64 * pixel;
+        let start = (edge(columns[0]) - sign * options.overscan, y, z);
+
+        if start != position {
+            motions.push(Motion::new_rapid(position, start));
+        }
+        position = start;
+        if options.overscan > 0.0 {
+            let end = (edge(columns[0]), y, z);
+            motions.push(cut(position, end, 0.0));
+            position = end;
+        }
+        // Runs of pixels burnt at the same power are one cut
+        let mut run = 0;
+        while run < columns.len() {
+            let power = powers[columns[run]];
+            let mut next = run + 1;
+            while next < columns.len() && powers[columns[next]] == power {
+                next += 1;
+            }
+            let end = (edge(columns[next - 1]) + sign * pixel, y, z);
+            motions.push(cut(position, end, power));
+            position = end;
+            run = next;
+        }
+        if options.overscan > 0.0 {
+            let end = (position.0 + sign * options.overscan, y, z);
+            motions.push(cut(position, end, 0.0));
+            position = end;
+        }
+        forward = !forward;
+    }
+    motions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::ends;
+
+    /// Test that binary PGM images are read, including 16-bit ones.
+    #[test]
+    fn test_read_binary_pgm() {
+        let image = Image::read_pgm(&b"P5 2 2 255\n\x00\x40\x80\xff"[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixel(1, 0), 0x40);
+        assert_eq!(image.pixel(0, 1), 0x80);
+
+        let image = Image::read_pgm(&b"P5\n2 1\n65535\n\xff\xff\x80\x00"[..]).unwrap();
+        assert_eq!(image.pixels, vec![255, 127]);
+
+        assert!(Image::read_pgm(&b"P6 1 1 255\n\x00\x00\x00"[..]).is_err());
+        assert!(Image::read_pgm(&b"P5 2 2 255\n\x00"[..]).is_err());
+        assert!(Image::read_pgm(&b"P2 2 two 255\n"[..]).is_err());
+    }
+
+    /// Test that lines go back and forth, skipping white rows and white ends.
+    #[test]
+    fn test_raster_lines() {
+        #[rustfmt::skip]
+        let image = Image {
+            width: 4,
+            height: 3,
+            pixels: vec![
+                255, 0, 0, 255,
+                255, 255, 255, 255,
+                0, 0, 255, 0,
+            ],
+        };
+        let options = RasterOptions { origin: (10.0, 0.0, -1.0), pixel: 1.0, power: 255.0, ..Default::default() };
+        let motions = raster(&image, &options);
+        let lines: Vec<_> =
+            motions.iter().map(|motion| (motion.is_rapid(), ends(motion, 0.0).1, motion.power())).collect();
+        assert_eq!(
+            lines,
+            vec![
+                // The bottom row runs left to right, with a gap burnt at no power
+                (true, (10.0, 0.5, -1.0), None),
+                (false, (12.0, 0.5, -1.0), Some(255.0)),
+                (false, (13.0, 0.5, -1.0), Some(0.0)),
+                (false, (14.0, 0.5, -1.0), Some(255.0)),
+                // The white row is skipped, and the top row runs back
+                (true, (13.0, 2.5, -1.0), None),
+                (false, (11.0, 2.5, -1.0), Some(255.0)),
+            ]
+        );
+        assert!(motions.iter().skip(1).all(|motion| motion.feed() == Some(3000.0) || motion.is_rapid()));
+        assert!(raster(&Image { width: 2, height: 1, pixels: vec![255, 255] }, &options).is_empty());
+    }
+
+    /// Test that overscan runs each line on past the image with the laser off.
+    #[test]
+    fn test_raster_overscan() {
+        let image = Image { width: 2, height: 2, pixels: vec![0, 0, 0, 0] };
+        let options = RasterOptions { pixel: 1.0, overscan: 3.0, ..Default::default() };
+        let motions = raster(&image, &options);
+        let lines: Vec<_> = motions.iter().map(|motion| (ends(motion, 0.0).1, motion.power())).collect();
+        let lines: Vec<_> = lines.into_iter().map(|((x, _, _), power)| (x, power)).collect();
+        assert_eq!(
+            lines,
+            vec![
+                (-3.0, None),
+                (0.0, Some(0.0)),
+                (2.0, Some(1000.0)),
+                (5.0, Some(0.0)),
+                (5.0, None),
+                (2.0, Some(0.0)),
+                (0.0, Some(1000.0)),
+                (-3.0, Some(0.0)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Rhai scripts that adjust programs without recompiling
+//!
+//! A shop's own tweaks, such as nudging cuts over to make up for a worn
+//! fixture, dropping motions or logging what runs, can live in a
+//! [Rhai](https://rhai.rs) script instead of a fork. A script may define
+//! either or both of two hooks:
+//!
+//! ```text
+//! // Called once for each parsed motion, in order
+//! fn motion(m) {
+//!     // m.index, m.kind ("linear", "rotational", "bezier", "spline" or
+//!     // "oriented"), m.rapid, m.line, m.feed, m.power, m.start and m.end
+//!     if m.rapid { return; }             // nothing, or true, keeps the motion
+//!     if m.line == 12 { return false; }  // false drops it
+//!     #{ offset: [0.05, 0.0, 0.0], feed: m.feed * 0.9 }
+//! }
+//!
+//! // Called once for each emitted point
+//! fn point(p) {
+//!     // p.motion, p.x, p.y and p.z
+//!     #{ z: p.z - 0.1 }                  // any of x, y and z replace the point's
+//! }
+//! ```
+//!
+//! A map returned from `motion` can set `feed` and `power`, and move the
+//! motion by `offset`. Missing values stay as they were, and `()` stands for
+//! no feed rate or power. Anything the script prints goes to stderr. Only
+//! built with the `scripting` feature.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+
+use crate::error::ScriptError;
+use crate::motion::Motion;
+use crate::transform::{ends, Transform};
+
+type Point = (f64, f64, f64);
+
+/// A loaded script and the hooks it defines
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    // Which of the hooks the script defines
+    motion: bool,
+    point: bool,
+}
+
+// The engine and the syntax tree are too large to be worth printing
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script")
+            .field("motion", &self.motion)
+            .field("point", &self.point)
+            .finish_non_exhaustive()
+    }
+}
+
+// Turn a position into a Rhai array of three numbers
+fn array((x, y, z): Point) -> Dynamic {
+    Dynamic::from_array(vec![x.into(), y.into(), z.into()])
+}
+
+// Turn an optional number into a Rhai number, or `()` if there isn't one
+fn optional(value: Option<f64>) -> Dynamic {
+    value.map_or(Dynamic::UNIT, Dynamic::from_float)
+}
+
+// Read a number a script gave back, where whole numbers are fine too
+fn number(value: &Dynamic) -> Option<f64> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|value| value as f64))
+}
+
+impl Script {
+    /// Function to read and compile a script, running its top level once
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ScriptError::Io`] if the file can't be read, or
+    /// [`ScriptError::Compile`] if it isn't valid Rhai or its top level fails.
+    pub fn load(path: &Path) -> Result<Script, ScriptError> {
+        Script::new(&fs::read_to_string(path)?)
+    }
+
+    /// Function to compile a script from its text, running its top level once
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ScriptError::Compile`] if the text isn't valid Rhai or its top level fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    /// use project_2::script::Script;
+    ///
+    /// let script = Script::new("fn motion(m) { #{ offset: [1, 0, 0] } }").unwrap();
+    /// let motions = script.motions(&[Motion::new_linear((0.0, 0.0, 0.0), (5.0, 0.0, 0.0))]).unwrap();
+    /// assert_eq!(motions, [Motion::new_linear((1.0, 0.0, 0.0), (6.0, 0.0, 0.0))]);
+    /// ```
+    pub fn new(text: &str) -> Result<Script, ScriptError> {
+        // Printing goes to stderr so it doesn't end up among the positions
+        let mut engine = Engine::new();
+        engine.on_print(|text| eprintln!("{}", text));
+        engine.on_debug(|text, _, position| eprintln!("{}: {}", position, text));
+
+        let ast = engine.compile(text).map_err(|e| ScriptError::Compile(e.to_string()))?;
+        engine.run_ast(&ast).map_err(|e| ScriptError::Compile(e.to_string()))?;
+        let defines = |name: &str| {
+            ast.iter_functions().any(|function| function.name == name && function.params.len() == 1)
+        };
+        let (motion, point) = (defines("motion"), defines("point"));
+        Ok(Script {
+            engine,
+            ast,
+            motion,
+            point,
+        })
+    }
+
+    // Call a hook with one map, without running the top level again
+    fn call(&self, hook: &'static str, argument: Map) -> Result<Dynamic, ScriptError> {
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &self.ast, hook, (argument,))
+            .map_err(|e| ScriptError::Run {
+                hook,
+                message: e.to_string(),
+            })
+    }
+
+    /// Function to run the `motion` hook over every motion of a program
+    ///
+    /// # Returns
+    ///
+    /// The motions the hook kept, as it changed them, or all of them
+    /// unchanged if the script has no `motion` hook.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ScriptError::Run`] if the hook fails, or
+    /// [`ScriptError::BadReturn`] if it gives back anything but nothing, a
+    /// boolean or a map of numbers.
+    pub fn motions(&self, motions: &[Motion]) -> Result<Vec<Motion>, ScriptError> {
+        if !self.motion {
+            return Ok(motions.to_vec());
+        }
+        let bad = |found: String| ScriptError::BadReturn {
+            hook: "motion",
+            found,
+            expected: "nothing, a boolean or a map",
+        };
+
+        let mut kept = Vec::with_capacity(motions.len());
+        // Flat arcs are placed at the height the previous motion ended at
+        let mut current_z = 0.0;
+        for (index, motion) in motions.iter().enumerate() {
+            let (start, end) = ends(motion, current_z);
+            current_z = end.2;
+            let kind = match motion {
+                Motion::Linear(_) => "linear",
+                Motion::Rotational(_) => "rotational",
+                Motion::Bezier(_) => "bezier",
+                Motion::Spline(_) => "spline",
+                Motion::Oriented(_) => "oriented",
+                Motion::ToolChange(_) => "tool_change",
+                Motion::Aux(_) => "aux",
+                Motion::Pause(_) => "pause",
+            };
+            let mut argument = Map::new();
+            argument.insert("index".into(), (index as i64).into());
+            argument.insert("kind".into(), kind.into());
+            argument.insert("rapid".into(), motion.is_rapid().into());
+            argument.insert("line".into(), motion.line().map_or(Dynamic::UNIT, |line| (line as i64).into()));
+            argument.insert("feed".into(), optional(motion.feed()));
+            argument.insert("power".into(), optional(motion.power()));
+            argument.insert("start".into(), array(start));
+            argument.insert("end".into(), array(end));
+
+            let result = self.call("motion", argument)?;
+            if result.is_unit() {
+                kept.push(motion.clone());
+                continue;
+            }
+            if let Ok(keep) = result.as_bool() {
+                if keep {
+                    kept.push(motion.clone());
+                }
+                continue;
+            }
+            let type_name = result.type_name().to_string();
+            let changes = result.try_cast::<Map>().ok_or_else(|| bad(type_name))?;
+
+            // Each change leaves the rest of the motion as it was, and `()` clears a feed rate or power
+            let rate = |name: &str| -> Result<Option<Option<f64>>, ScriptError> {
+                match changes.get(name) {
+                    None => Ok(None),
+                    Some(value) if value.is_unit() => Ok(Some(None)),
+                    Some(value) => match number(value) {
+                        Some(rate) => Ok(Some(Some(rate))),
+                        None => Err(bad(format!("{} {}", name, value))),
+                    },
+                }
+            };
+            let mut motion = motion.clone();
+            if let Some(feed) = rate("feed")? {
+                motion = motion.with_feed(feed);
+            }
+            if let Some(power) = rate("power")? {
+                motion = motion.with_power(power);
+            }
+            if let Some(value) = changes.get("offset") {
+                let offset = value
+                    .clone()
+                    .try_cast::<Array>()
+                    .and_then(|offset| match offset.iter().map(number).collect::<Option<Vec<f64>>>()?[..] {
+                        [x, y, z] => Some((x, y, z)),
+                        _ => None,
+                    })
+                    .ok_or_else(|| bad(format!("offset {}", value)))?;
+                // Moving a motion never bends it, so it can't fail
+                motion = Transform::default().translate(offset).motion(&motion).expect("translations keep arcs round");
+            }
+            kept.push(motion);
+        }
+        Ok(kept)
+    }
+
+    /// Function to run the `point` hook on one emitted point
+    ///
+    /// # Arguments
+    ///
+    /// * `motion` - Index of the motion the point is on
+    /// * `position` - Where the point is
+    ///
+    /// # Returns
+    ///
+    /// Where the hook moved the point to, or `position` if the script has no
+    /// `point` hook.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ScriptError::Run`] if the hook fails, or
+    /// [`ScriptError::BadReturn`] if it gives back anything but nothing or a
+    /// map of numbers.
+    pub fn point(&self, motion: usize, position: Point) -> Result<Point, ScriptError> {
+        if !self.point {
+            return Ok(position);
+        }
+        let mut argument = Map::new();
+        argument.insert("motion".into(), (motion as i64).into());
+        argument.insert("x".into(), position.0.into());
+        argument.insert("y".into(), position.1.into());
+        argument.insert("z".into(), position.2.into());
+
+        let result = self.call("point", argument)?;
+        if result.is_unit() {
+            return Ok(position);
+        }
+        let type_name = result.type_name().to_string();
+        let bad = |found: String| ScriptError::BadReturn {
+            hook: "point",
+            found,
+            expected: "nothing or a map",
+        };
+        let changes = result.try_cast::<Map>().ok_or_else(|| bad(type_name))?;
+        let axis = |name: &str, value: f64| match changes.get(name) {
+            Some(changed) => number(changed).ok_or_else(|| bad(format!("{} {}", name, changed))),
+            None => Ok(value),
+        };
+        Ok((axis("x", position.0)?, axis("y", position.1)?, axis("z", position.2)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that the motion hook can keep, drop and change motions, and is told where each one is.
+    #[test]
+    fn test_motions() {
+        let script = Script::new(
+            r#"
+            fn motion(m) {
+                if m.rapid { return; }
+                if m.line == 3 { return false; }
+                if m.kind != "linear" || m.end[0] != 10.0 { throw "unexpected motion"; }
+                #{ feed: m.feed / 2, offset: [0, 0, -1] }
+            }
+            "#,
+        )
+        .unwrap();
+        let motions = vec![
+            Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(300.0)).with_line(2),
+            Motion::new_linear((10.0, 0.0, 0.0), (10.0, 5.0, 0.0)).with_line(3),
+        ];
+        let kept = script.motions(&motions).unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0], motions[0]);
+        let expected = Motion::new_linear((0.0, 0.0, -1.0), (10.0, 0.0, -1.0)).with_feed(Some(150.0)).with_line(2);
+        assert_eq!(kept[1], expected);
+
+        // A hook that throws is reported, and so is one that gives back nonsense
+        let reversed = [motions[2].clone(), motions[1].clone()];
+        let dropped = Script::new("fn motion(m) { m.line != 3 }").unwrap().motions(&reversed).unwrap();
+        assert_eq!(dropped, [motions[1].clone()]);
+        assert!(script.motions(&motions[2..]).unwrap().is_empty());
+        let throwing = Script::new("fn motion(m) { throw \"no\"; }").unwrap();
+        assert!(matches!(throwing.motions(&motions), Err(ScriptError::Run { hook: "motion", .. })));
+        let nonsense = Script::new("fn motion(m) { 42 }").unwrap();
+        assert!(matches!(nonsense.motions(&motions), Err(ScriptError::BadReturn { hook: "motion", .. })));
+    }
+
+    /// Test that the point hook replaces only the axes it gives back, and that scripts without it leave points alone.
+    #[test]
+    fn test_point() {
+        let script = Script::new("fn point(p) { if p.motion == 1 { #{ z: p.z - 0.5 } } }").unwrap();
+        assert_eq!(script.point(0, (1.0, 2.0, 3.0)).unwrap(), (1.0, 2.0, 3.0));
+        assert_eq!(script.point(1, (1.0, 2.0, 3.0)).unwrap(), (1.0, 2.0, 2.5));
+        assert_eq!(Script::new("let a = 1;").unwrap().point(1, (1.0, 2.0, 3.0)).unwrap(), (1.0, 2.0, 3.0));
+        assert!(matches!(Script::new("fn point(p) {"), Err(ScriptError::Compile(_))));
+    }
+}
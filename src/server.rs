@@ -0,0 +1,395 @@
+//! TCP server for interpreting programs on demand
+//!
+//! A controller board or simulator on the network connects, sends a `.cmmd`
+//! program, and gets the interpolated points back without needing the
+//! interpreter installed locally.
+//!
+//! The protocol is line based. The client sends the program one line at a
+//! time and ends it with a line containing only `.` (or by closing its side
+//! of the connection). The server answers in JSON Lines, the same records
+//! `--format jsonl` prints, followed by a closing record:
+//!
+//! ```text
+//! {"type":"done","motions":2,"points":12}
+//! ```
+//!
+//! or, if the program couldn't be parsed,
+//!
+//! ```text
+//! {"type":"error","message":"line 3, column 1-4: unknown command `BOGUS`","line":3}
+//! ```
+//!
+//! `INCLUDE` is refused, since it would let clients read files on the server.
+//!
+//! The connection stays open after a response, so a client can send several
+//! programs in turn. Each connection is handled on its own thread.
+//!
+//! Programs larger than [`ServerOptions::max_request`] are answered with an
+//! error record and the connection is closed, and clients beyond
+//! [`ServerOptions::max_connections`] get an error record saying the server is
+//! busy. A short program can still ask for a lot: loops are expanded only up
+//! to the parse options' `max_expansion`, which is 16 MiB by default here, and
+//! a program whose points run past [`ServerOptions::max_points`] gets an error
+//! record after the points it was sent, in place of `done`. Between them these
+//! keep a client from exhausting the server's memory or threads.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use serde::Serialize;
+
+use crate::error::CmmdError;
+use crate::interpolate::InterpolationOptions;
+use crate::output::{write_json, Format};
+use crate::parser::{read_reader, ParseOptions};
+use crate::trajectory::Trajectory;
+
+/// Options applied to every program the server receives
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// How programs are parsed
+    pub parse: ParseOptions,
+    /// How motions are split into points
+    pub interpolation: InterpolationOptions,
+    /// Largest program a client may send, in bytes
+    pub max_request: usize,
+    /// Most clients served at once
+    pub max_connections: usize,
+    /// Most points sent back for one program
+    pub max_points: usize,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            parse: ParseOptions {
+                max_expansion: 1 << 24,
+                ..ParseOptions::default()
+            },
+            interpolation: InterpolationOptions::default(),
+            max_request: 1 << 20,
+            max_connections: 16,
+            max_points: 1_000_000,
+        }
+    }
+}
+
+/// Record closing the response to one program
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Reply {
+    Done { motions: usize, points: usize },
+    Error { message: String, line: Option<usize> },
+}
+
+// What a client sent
+enum Request {
+    Program(String),
+    // More than the server accepts, which is left unread
+    TooLarge,
+}
+
+// Read one program of at most `limit` bytes, up to a `.` line or the end of the input
+//
+// Returns `None` if the client closed the connection without sending anything.
+fn read_request<R: BufRead>(reader: &mut R, limit: usize) -> io::Result<Option<Request>> {
+    let mut program = String::new();
+    let mut received = false;
+    loop {
+        // Even a single line can't be longer than what is left, plus room for a `.` line
+        let mut line = Vec::new();
+        let room = (limit - program.len() + 3) as u64;
+        if reader.by_ref().take(room).read_until(b'\n', &mut line)? == 0 {
+            return Ok(received.then_some(Request::Program(program)));
+        }
+        received = true;
+        let line = String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if line.trim() == "." {
+            return Ok(Some(Request::Program(program)));
+        }
+        if program.len() + line.len() > limit {
+            return Ok(Some(Request::TooLarge));
+        }
+        program.push_str(&line);
+    }
+}
+
+/// Function to answer every program sent over one connection
+///
+/// # Arguments
+///
+/// * `connection` - The client connection, usually a `TcpStream`
+/// * `options` - How programs are parsed and interpolated
+///
+/// # Errors
+///
+/// Returns an I/O error if the connection fails. Programs that can't be parsed
+/// are reported to the client instead, and so are programs that are too large,
+/// which also end the connection.
+pub fn handle_connection<S: Read + Write>(connection: S, options: &ServerOptions) -> io::Result<()> {
+    let mut connection = BufReader::new(connection);
+
+    while let Some(request) = read_request(&mut connection, options.max_request)? {
+        let writer = connection.get_mut();
+        // The rest of a program that's too large is never read, so there's no telling where the next one starts
+        let Request::Program(program) = request else {
+            let error = Reply::Error {
+                message: format!("the program is larger than the {} byte limit", options.max_request),
+                line: None,
+            };
+            writeln!(writer, "{}", serde_json::to_string(&error)?)?;
+            return writer.flush();
+        };
+        // Clients mustn't be able to read files on the server
+        let parse = ParseOptions {
+            includes: false,
+            ..options.parse.clone()
+        };
+        match read_reader(program.as_bytes(), &parse) {
+            Ok(motions) => {
+                // Points are counted as they are written, and stop at the limit
+                let trajectory = Trajectory::new(&motions, &options.interpolation);
+                let mut points = trajectory.points();
+                let mut count = 0;
+                let limited = points.by_ref().take(options.max_points).inspect(|_| count += 1);
+                write_json(&mut *writer, &motions, limited, Format::JsonLines)?;
+                let reply = if points.next().is_some() {
+                    Reply::Error {
+                        message: format!("the program makes more than {} points", options.max_points),
+                        line: None,
+                    }
+                } else {
+                    Reply::Done {
+                        motions: motions.len(),
+                        points: count,
+                    }
+                };
+                writeln!(writer, "{}", serde_json::to_string(&reply)?)?;
+            }
+            Err(e) => {
+                let line = match &e {
+                    CmmdError::Parse(parse_error) => Some(parse_error.line()),
+                    CmmdError::IncludeNotAllowed { line } => Some(*line),
+                    _ => None,
+                };
+                let error = Reply::Error {
+                    message: e.to_string(),
+                    line,
+                };
+                writeln!(writer, "{}", serde_json::to_string(&error)?)?;
+            }
+        }
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Function to serve programs to every client that connects
+///
+/// Runs until accepting connections fails. Errors on a single connection,
+/// such as a client going away mid-response, only end that connection. While
+/// `max_connections` clients are being served, any more are sent an error
+/// record and disconnected.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::net::TcpListener;
+/// use project_2::server::{serve, ServerOptions};
+///
+/// let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
+/// serve(listener, &ServerOptions::default()).unwrap();
+/// ```
+pub fn serve(listener: TcpListener, options: &ServerOptions) -> io::Result<()> {
+    // Only this thread takes slots, so checking and taking one can't race
+    let active = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (mut connection, _) = listener.accept()?;
+        if active.load(Ordering::SeqCst) >= options.max_connections {
+            let busy = Reply::Error {
+                message: format!("the server is busy with {} clients, try again later", options.max_connections),
+                line: None,
+            };
+            let _ = writeln!(connection, "{}", serde_json::to_string(&busy)?);
+            continue;
+        }
+        active.fetch_add(1, Ordering::SeqCst);
+        let slot = Slot(Arc::clone(&active));
+        let options = options.clone();
+        thread::spawn(move || {
+            // A client leaving early isn't the server's problem
+            let _ = handle_connection(connection, &options);
+            drop(slot);
+        });
+    }
+}
+
+// A connection's place among the clients being served, given back when its thread ends, however it ends
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::TcpStream;
+
+    /// Test a client sending two programs over one connection.
+    #[test]
+    fn test_serve_programs() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, &ServerOptions::default()));
+
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut responses = BufReader::new(client.try_clone().unwrap());
+
+        client.write_all(b"LIN X0 Y0 Z0\nLIN X2 Y0 Z0\n.\n").unwrap();
+        let mut records = Vec::new();
+        loop {
+            let mut line = String::new();
+            responses.read_line(&mut line).unwrap();
+            let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+            if record["type"] == "done" {
+                assert_eq!(record["motions"], 2);
+                break;
+            }
+            records.push(record);
+        }
+        assert_eq!(records[0]["type"], "motion");
+        assert_eq!(records.last().unwrap()["position"], json!([2.0, 0.0, 0.0]));
+
+        client.write_all(b"LIN X0 Y0 Z0\nBOGUS\n.\n").unwrap();
+        let mut line = String::new();
+        responses.read_line(&mut line).unwrap();
+        let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["type"], "error");
+        assert_eq!(record["line"], 2);
+    }
+
+    // Read one JSON record from a response
+    fn record<R: BufRead>(responses: &mut R) -> serde_json::Value {
+        let mut line = String::new();
+        responses.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    // A connection whose client sends `input`, collecting what the server writes back
+    struct Connection {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Connection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Connection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Test that programs over the size limit are refused and end the connection.
+    #[test]
+    fn test_request_too_large() {
+        let options = ServerOptions {
+            max_request: 32,
+            ..ServerOptions::default()
+        };
+        let mut connection = Connection {
+            input: io::Cursor::new(
+                b"LIN X0 Y0 Z0\nLIN X2 Y0 Z0\n.\nLIN X0 Y0 Z0\nLIN X2 Y0 Z0\nLIN X4 Y0 Z0\n.\nLIN X0 Y0 Z0\n.\n".to_vec(),
+            ),
+            output: Vec::new(),
+        };
+        handle_connection(&mut connection, &options).unwrap();
+
+        // The small program is answered, then the large one ends the conversation
+        let output = String::from_utf8(connection.output).unwrap();
+        let replies: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .filter(|record| record["type"] == "done" || record["type"] == "error")
+            .collect();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["type"], "done");
+        assert_eq!(replies[1]["type"], "error");
+        assert!(replies[1]["message"].as_str().unwrap().contains("32 byte limit"));
+    }
+
+    /// Test that a short program of nested loops, or one line making too many points, gets an error.
+    #[test]
+    fn test_request_too_costly() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = ServerOptions {
+            max_points: 1000,
+            ..ServerOptions::default()
+        };
+        thread::spawn(move || serve(listener, &options));
+
+        let mut client = TcpStream::connect(address).unwrap();
+        let mut responses = BufReader::new(client.try_clone().unwrap());
+        let program = b"REPEAT 999999\nREPEAT 999999\nREPEAT 999999\nLIN X1 Y0 Z0\nEND\nEND\nEND\n.\n";
+        assert!(program.len() < 80);
+        client.write_all(program).unwrap();
+        let reply = record(&mut responses);
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["line"], 1);
+        assert!(reply["message"].as_str().unwrap().contains("16777216 bytes"));
+
+        // The connection is still good, and a line too long to send in points stops at the limit
+        client.write_all(b"LIN X1e15 Y0 Z0\n.\n").unwrap();
+        let mut points = 0;
+        let reply = loop {
+            let reply = record(&mut responses);
+            match reply["type"].as_str() {
+                Some("point") => points += 1,
+                Some("motion") => {}
+                _ => break reply,
+            }
+        };
+        assert_eq!(points, 1000);
+        assert_eq!(reply["type"], "error");
+        assert!(reply["message"].as_str().unwrap().contains("more than 1000 points"));
+    }
+
+    /// Test that clients beyond the connection limit are turned away.
+    #[test]
+    fn test_connection_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let options = ServerOptions {
+            max_connections: 1,
+            ..ServerOptions::default()
+        };
+        thread::spawn(move || serve(listener, &options));
+
+        // Once the first client has been answered, it holds the only slot
+        let mut first = TcpStream::connect(address).unwrap();
+        first.write_all(b"LIN X0 Y0 Z0\n.\n").unwrap();
+        let mut responses = BufReader::new(first.try_clone().unwrap());
+        while record(&mut responses)["type"] != "done" {}
+
+        let second = TcpStream::connect(address).unwrap();
+        let busy = record(&mut BufReader::new(second));
+        assert_eq!(busy["type"], "error");
+        assert!(busy["message"].as_str().unwrap().contains("busy"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Material removal simulation
+//!
+//! Shows what a part will look like once it's cut, without cutting it. The
+//! stock is a heightfield, a grid of square cells over XY that each hold the
+//! height of the surface at their middle. A flat-bottomed cylindrical tool
+//! following the interpolated path lowers every cell it passes over to the
+//! height of its tip, which is all a 2.5D job can do to the stock: cuts
+//! don't undercut, so the surface is still one height at each point.
+//!
+//! The finished surface can be written as a binary STL mesh, a closed solid
+//! that any 3D viewer or slicer opens, or as a PGM depth image with the top
+//! of the stock white and its bottom black.
+
+use std::io::{self, Write};
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::output::geometric_points;
+
+type Point = (f64, f64, f64);
+
+/// The most cells a stock may have, which keeps memory use and output files reasonable
+pub const MAX_CELLS: usize = 1 << 22;
+
+/// A block of stock, as the height of its surface over a grid of cells
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stock {
+    /// X and Y of the corner of the first cell
+    pub origin: (f64, f64),
+    /// Width of each square cell
+    pub cell: f64,
+    /// Number of cells along X
+    pub columns: usize,
+    /// Number of cells along Y
+    pub rows: usize,
+    /// Height of the stock before it's cut
+    pub top: f64,
+    /// Height of the bottom of the stock, which cuts don't go below
+    pub bottom: f64,
+    /// Height of the surface at the middle of each cell, by row along Y, each row running along X
+    pub heights: Vec<f64>,
+}
+
+impl Stock {
+    /// Function to make an uncut block of stock
+    ///
+    /// # Arguments
+    ///
+    /// * `min` - The lowest corner of the block, whose Z is its bottom
+    /// * `max` - The highest corner of the block, whose Z is its top
+    /// * `cell` - Width of each cell of the grid
+    ///
+    /// # Returns
+    ///
+    /// The stock, or `None` if the cell isn't positive, the block is empty, or
+    /// it would need more than [`MAX_CELLS`] cells.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::simulate::Stock;
+    ///
+    /// let stock = Stock::new((0.0, 0.0, -5.0), (10.0, 4.0, 0.0), 0.5).unwrap();
+    /// assert_eq!((stock.columns, stock.rows), (20, 8));
+    /// assert_eq!(stock.height(5.0, 2.0), 0.0);
+    /// assert!(Stock::new((0.0, 0.0, -5.0), (10.0, 4.0, 0.0), 0.0).is_none());
+    /// ```
+    pub fn new(min: Point, max: Point, cell: f64) -> Option<Stock> {
+        if !(cell > 0.0 && max.0 > min.0 && max.1 > min.1 && max.2 > min.2) {
+            return None;
+        }
+        // At least two cells each way, so the surface has an inside and an edge
+        let columns = ((max.0 - min.0) / cell).ceil().max(2.0);
+        let rows = ((max.1 - min.1) / cell).ceil().max(2.0);
+        if columns * rows > MAX_CELLS as f64 {
+            return None;
+        }
+        let (columns, rows) = (columns as usize, rows as usize);
+        Some(Stock {
+            origin: (min.0, min.1),
+            cell,
+            columns,
+            rows,
+            top: max.2,
+            bottom: min.2,
+            heights: vec![max.2; columns * rows],
+        })
+    }
+
+    // X and Y of the middle of a cell
+    fn middle(&self, column: usize, row: usize) -> (f64, f64) {
+        (
+            self.origin.0 + (column as f64 + 0.5) * self.cell,
+            self.origin.1 + (row as f64 + 0.5) * self.cell,
+        )
+    }
+
+    // The cells whose middles lie between two X or Y values, along one axis
+    fn span(&self, from: f64, to: f64, origin: f64, count: usize) -> std::ops::Range<usize> {
+        let first = ((from - origin) / self.cell - 0.5).ceil().max(0.0);
+        let last = ((to - origin) / self.cell - 0.5).floor().min(count as f64 - 1.0);
+        if last < first {
+            0..0
+        } else {
+            first as usize..last as usize + 1
+        }
+    }
+
+    /// Function to find the height of the surface over a point, or the top of the stock outside it
+    pub fn height(&self, x: f64, y: f64) -> f64 {
+        let column = ((x - self.origin.0) / self.cell).floor();
+        let row = ((y - self.origin.1) / self.cell).floor();
+        if column < 0.0 || row < 0.0 || column >= self.columns as f64 || row >= self.rows as f64 {
+            return self.top;
+        }
+        self.heights[row as usize * self.columns + column as usize]
+    }
+
+    /// Function to cut the stock with a tool moving in a straight line
+    ///
+    /// The tool is a flat-bottomed cylinder of the given radius with its tip
+    /// on the line. Every cell whose middle the tool passes over is lowered to
+    /// the lowest height the tip reaches while over it, but not below the
+    /// bottom of the stock.
+    pub fn cut(&mut self, from: Point, to: Point, radius: f64) {
+        if from.2 >= self.top && to.2 >= self.top {
+            return;
+        }
+        let (dx, dy, dz) = (to.0 - from.0, to.1 - from.1, to.2 - from.2);
+        let length2 = dx * dx + dy * dy;
+        let columns = self.span(from.0.min(to.0) - radius, from.0.max(to.0) + radius, self.origin.0, self.columns);
+        let rows = self.span(from.1.min(to.1) - radius, from.1.max(to.1) + radius, self.origin.1, self.rows);
+        for row in rows {
+            for column in columns.clone() {
+                let (x, y) = self.middle(column, row);
+                let (wx, wy) = (x - from.0, y - from.1);
+                let gap = wx * wx + wy * wy - radius * radius;
+
+                // The part of the line where the tool is over the cell, as fractions of the way along it
+                let (t0, t1) = if length2 < 1e-18 {
+                    if gap > 0.0 {
+                        continue;
+                    }
+                    (0.0, 1.0)
+                } else {
+                    let along = wx * dx + wy * dy;
+                    let discriminant = along * along - length2 * gap;
+                    if discriminant < 0.0 {
+                        continue;
+                    }
+                    let root = discriminant.sqrt();
+                    ((along - root) / length2, (along + root) / length2)
+                };
+                let (t0, t1) = (t0.max(0.0), t1.min(1.0));
+                if t0 > t1 {
+                    continue;
+                }
+
+                // The tip is lowest at one end of that part
+                let z = (from.2 + t0 * dz).min(from.2 + t1 * dz).max(self.bottom);
+                let height = &mut self.heights[row * self.columns + column];
+                *height = height.min(z);
+            }
+        }
+    }
+
+    /// Function to write the surface as a PGM depth image
+    ///
+    /// Each cell is one pixel, with +Y up the image. The top of the stock is
+    /// white and its bottom black.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing.
+    pub fn write_pgm<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "P5\n{} {}\n255\n", self.columns, self.rows)?;
+        let depth = self.top - self.bottom;
+        let mut line = Vec::with_capacity(self.columns);
+        for row in (0..self.rows).rev() {
+            line.clear();
+            for height in &self.heights[row * self.columns..(row + 1) * self.columns] {
+                line.push(((height - self.bottom) / depth * 255.0).round().clamp(0.0, 255.0) as u8);
+            }
+            writer.write_all(&line)?;
+        }
+        writer.flush()
+    }
+
+    /// Function to write the stock as a closed binary STL mesh
+    ///
+    /// The surface runs through the middle of every cell, and walls drop from
+    /// its edges to a flat bottom, so the mesh is a solid with the outside of
+    /// every triangle facing out.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error from writing.
+    pub fn write_stl<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let (columns, rows) = (self.columns, self.rows);
+        let surface = |column: usize, row: usize| {
+            let (x, y) = self.middle(column, row);
+            (x, y, self.heights[row * columns + column])
+        };
+        let floor = |point: Point| (point.0, point.1, self.bottom);
+
+        // Points around the edge of the surface, counter-clockwise seen from above
+        let mut edge = Vec::with_capacity(2 * (columns + rows));
+        edge.extend((0..columns).map(|column| surface(column, 0)));
+        edge.extend((1..rows).map(|row| surface(columns - 1, row)));
+        edge.extend((0..columns - 1).rev().map(|column| surface(column, rows - 1)));
+        edge.extend((1..rows - 1).rev().map(|row| surface(0, row)));
+        let (first, last) = (surface(0, 0), surface(columns - 1, rows - 1));
+        let center = ((first.0 + last.0) / 2.0, (first.1 + last.1) / 2.0, self.bottom);
+
+        // The surface, the walls, and the bottom as a fan around its middle
+        let mut triangles = Vec::new();
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let (a, b) = (surface(column, row), surface(column + 1, row));
+                let (c, d) = (surface(column + 1, row + 1), surface(column, row + 1));
+                triangles.push([a, b, c]);
+                triangles.push([a, c, d]);
+            }
+        }
+        if edge.len() > 1 {
+            for (index, &a) in edge.iter().enumerate() {
+                let b = edge[(index + 1) % edge.len()];
+                triangles.push([a, floor(a), floor(b)]);
+                triangles.push([a, floor(b), b]);
+                triangles.push([center, floor(b), floor(a)]);
+            }
+        }
+
+        let mut header = [0u8; 80];
+        let title = b"project-2 simulated stock";
+        header[..title.len()].copy_from_slice(title);
+        writer.write_all(&header)?;
+        writer.write_all(&(triangles.len() as u32).to_le_bytes())?;
+        for [a, b, c] in triangles {
+            let (u, v) = ((b.0 - a.0, b.1 - a.1, b.2 - a.2), (c.0 - a.0, c.1 - a.1, c.2 - a.2));
+            let normal = (u.1 * v.2 - u.2 * v.1, u.2 * v.0 - u.0 * v.2, u.0 * v.1 - u.1 * v.0);
+            let length = (normal.0 * normal.0 + normal.1 * normal.1 + normal.2 * normal.2).sqrt();
+            let normal = if length > 0.0 {
+                (normal.0 / length, normal.1 / length, normal.2 / length)
+            } else {
+                (0.0, 0.0, 0.0)
+            };
+            for point in [normal, a, b, c] {
+                for value in [point.0, point.1, point.2] {
+                    writer.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+            writer.write_all(&[0, 0])?;
+        }
+        writer.flush()
+    }
+}
+
+/// Function to cut a program out of a block of stock
+///
+/// The stock covers the XY extent of the path with room for the tool
+/// around it, has its top at `top` and its bottom a cell below the lowest
+/// point the path reaches, so parts that are cut through still show a floor.
+/// The tool follows the interpolated positions of the program, rapid moves
+/// included, so `options` decides how closely curves are followed.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `options` - How motions are split into positions
+/// * `radius` - Radius of the flat-bottomed tool
+/// * `top` - Height of the top of the stock
+/// * `cell` - Width of each cell of the grid
+///
+/// # Returns
+///
+/// The cut stock, or `None` if the program is empty or the stock would need
+/// more than [`MAX_CELLS`] cells.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::interpolate::InterpolationOptions;
+/// use project_2::motion::Motion;
+/// use project_2::simulate::simulate;
+///
+/// // A slot 1 deep along X, with a tool 2 across
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 1.0), (0.0, 0.0, -1.0)),
+///     Motion::new_linear((0.0, 0.0, -1.0), (10.0, 0.0, -1.0)),
+/// ];
+/// let stock = simulate(&motions, &InterpolationOptions::default(), 1.0, 0.0, 0.1).unwrap();
+/// assert_eq!(stock.height(5.0, 0.05), -1.0);
+/// assert_eq!(stock.height(5.0, 1.5), 0.0);
+/// ```
+pub fn simulate(
+    motions: &[Motion],
+    options: &InterpolationOptions,
+    radius: f64,
+    top: f64,
+    cell: f64,
+) -> Option<Stock> {
+    let points = geometric_points(motions, options);
+    let (mut min, mut max) = ((f64::MAX, f64::MAX, top), (f64::MIN, f64::MIN, top));
+    for point in &points {
+        let (x, y, z) = point.position;
+        min = (min.0.min(x), min.1.min(y), min.2.min(z));
+        max = (max.0.max(x), max.1.max(y), max.2);
+    }
+    if points.is_empty() {
+        return None;
+    }
+    let room = radius + cell;
+    let mut stock = Stock::new(
+        (min.0 - room, min.1 - room, min.2 - cell),
+        (max.0 + room, max.1 + room, top),
+        cell,
+    )?;
+    stock.cut(points[0].position, points[0].position, radius);
+    for pair in points.windows(2) {
+        stock.cut(pair[0].position, pair[1].position, radius);
+    }
+    Some(stock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that a ramp cuts each cell to the lowest height the tip reaches over it, and not below the bottom.
+    #[test]
+    fn test_cut() {
+        let mut stock = Stock::new((0.0, -2.25, -3.0), (10.0, 2.25, 0.0), 0.5).unwrap();
+        // Down from Z = 0 at X = 0 to Z = -5 at X = 10, with a tool 1 across
+        stock.cut((0.0, 0.0, 0.0), (10.0, 0.0, -5.0), 0.5);
+        // Over X = 2.25 the tool reaches X = 2.75, where the tip is at -1.375
+        assert_eq!(stock.height(2.25, 0.0), -1.375);
+        // Further on it would go below the bottom
+        assert_eq!(stock.height(9.75, 0.0), -3.0);
+        // And beside the tool the stock is untouched
+        assert_eq!(stock.height(5.0, 1.25), 0.0);
+
+        // Moves above the stock leave it alone
+        let before = stock.clone();
+        stock.cut((0.0, 0.0, 1.0), (10.0, 0.0, 0.0), 0.5);
+        assert_eq!(stock, before);
+    }
+
+    /// Test that the mesh and image have the right size and shape.
+    #[test]
+    fn test_write() {
+        let mut stock = Stock::new((0.0, 0.0, -1.0), (3.0, 2.0, 0.0), 1.0).unwrap();
+        stock.cut((0.5, 0.5, -1.0), (0.5, 0.5, -1.0), 0.1);
+
+        let mut image = Vec::new();
+        stock.write_pgm(&mut image).unwrap();
+        // The cut cell is black, in the bottom row of the image
+        assert_eq!(image, b"P5\n3 2\n255\n\xff\xff\xff\x00\xff\xff".to_vec());
+
+        let mut mesh = Vec::new();
+        stock.write_stl(&mut mesh).unwrap();
+        // Two triangles for each of the two squares of surface, and three for each of the six edges
+        let count = u32::from_le_bytes(mesh[80..84].try_into().unwrap());
+        assert_eq!(count, 2 * 2 + 3 * 6);
+        assert_eq!(mesh.len(), 84 + 50 * count as usize);
+    }
+}
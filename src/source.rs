@@ -0,0 +1,212 @@
+//! Program source lines and the `INCLUDE` directive
+//!
+//! `INCLUDE "fixtures/clamp.cmmd"` splices another file into the program
+//! where the directive appears, before loops and subroutines are expanded, so
+//! shared setup sequences and subroutine libraries can live in their own
+//! files. Paths are resolved relative to the file doing the including.
+//!
+//! Every line remembers where it came from: errors in an included file name
+//! that file and its own line numbers, while motions from it are attributed to
+//! the `INCLUDE` line of the program that was run.
+
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::{CmmdError, ParseError};
+use crate::parser::{expect_arguments, strip_comments, tokenize};
+
+/// Where an included line came from
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Origin {
+    /// The included file the line is in
+    pub(crate) file: PathBuf,
+    /// The `INCLUDE` line of the program that was run
+    pub(crate) line: usize,
+}
+
+/// A line of a program, numbered from one within its own file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SourceLine {
+    pub(crate) number: usize,
+    pub(crate) text: String,
+    /// `None` for lines of the program itself
+    pub(crate) origin: Option<Rc<Origin>>,
+}
+
+impl SourceLine {
+    /// A line of the program itself
+    #[cfg(test)]
+    pub(crate) fn new(number: usize, text: &str) -> Self {
+        SourceLine {
+            number,
+            text: text.to_string(),
+            origin: None,
+        }
+    }
+
+    /// Line of the program that was run that this line stands for
+    pub(crate) fn program_line(&self) -> usize {
+        self.origin.as_ref().map_or(self.number, |origin| origin.line)
+    }
+
+    /// Turn an error on this line into a program error naming the right file
+    pub(crate) fn error(&self, error: ParseError) -> CmmdError {
+        match &self.origin {
+            Some(origin) => CmmdError::Included {
+                file: origin.file.clone(),
+                error,
+            },
+            None => CmmdError::Parse(error),
+        }
+    }
+}
+
+// The path named by an INCLUDE line, without quotes, if the line is one
+fn include_path(line: &SourceLine) -> Result<Option<String>, CmmdError> {
+    let stripped = strip_comments(&line.text);
+    let tokens = tokenize(&stripped);
+    if tokens.first().map(|token| token.text) != Some("INCLUDE") {
+        return Ok(None);
+    }
+    expect_arguments(line.number, &stripped, &tokens, 1).map_err(|e| line.error(e))?;
+
+    // Take everything after the keyword so quoted paths may contain spaces
+    let path = stripped[tokens[1].start..].trim();
+    let path = path.strip_prefix('"').and_then(|path| path.strip_suffix('"')).unwrap_or(path);
+    Ok(Some(path.to_string()))
+}
+
+// Read lines into `lines`, following includes
+//
+// `stack` holds the canonical paths of the files being read, to catch cycles.
+fn load_into<R: BufRead>(
+    reader: R,
+    directory: Option<&Path>,
+    origin: Option<Rc<Origin>>,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<SourceLine>,
+) -> Result<(), CmmdError> {
+    for (index, text) in reader.lines().enumerate() {
+        let line = SourceLine {
+            number: index + 1,
+            text: text?,
+            origin: origin.clone(),
+        };
+        let Some(path) = include_path(&line)? else {
+            lines.push(line);
+            continue;
+        };
+
+        // Programs without a directory (sent over the network) can't include files
+        let Some(directory) = directory else {
+            return Err(CmmdError::IncludeNotAllowed {
+                line: line.program_line(),
+            });
+        };
+        let file = directory.join(&path);
+        let canonical = file.canonicalize().map_err(|source| CmmdError::Include {
+            file: file.clone(),
+            source,
+        })?;
+        if stack.contains(&canonical) {
+            return Err(CmmdError::IncludeCycle { file });
+        }
+        let reader = File::open(&canonical).map_err(|source| CmmdError::Include {
+            file: file.clone(),
+            source,
+        })?;
+
+        let included = Rc::new(Origin {
+            file: file.clone(),
+            line: line.program_line(),
+        });
+        stack.push(canonical.clone());
+        load_into(
+            io::BufReader::new(reader),
+            canonical.parent(),
+            Some(included),
+            stack,
+            lines,
+        )?;
+        stack.pop();
+    }
+    Ok(())
+}
+
+/// Function to read the lines of a program, splicing in included files
+///
+/// # Arguments
+///
+/// * `reader` - The program text
+/// * `file` - The file the program was read from, if any, so it can't include itself
+/// * `directory` - Where relative include paths start from, or `None` to refuse includes
+///
+/// # Errors
+///
+/// Returns `CmmdError::Io` if reading fails, `CmmdError::Include` if an
+/// included file can't be read, `CmmdError::IncludeCycle` if a file ends up
+/// including itself, and `CmmdError::IncludeNotAllowed` if includes are refused.
+pub(crate) fn load<R: BufRead>(
+    reader: R,
+    file: Option<&Path>,
+    directory: Option<&Path>,
+) -> Result<Vec<SourceLine>, CmmdError> {
+    let mut stack: Vec<PathBuf> = file.and_then(|file| file.canonicalize().ok()).into_iter().collect();
+    let mut lines = Vec::new();
+    load_into(reader, directory, None, &mut stack, &mut lines)?;
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // Write a set of files into a fresh temporary directory
+    fn files(name: &str, contents: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("project-2-include-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        for (path, text) in contents {
+            let path = directory.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        directory
+    }
+
+    /// Test nested includes resolved relative to each including file.
+    #[test]
+    fn test_include() {
+        let directory = files(
+            "nested",
+            &[
+                ("lib/clamp.cmmd", "LIN X1 Y0 Z0\nINCLUDE \"park.cmmd\"\n"),
+                ("lib/park.cmmd", "LIN X0 Y0 Z9\n"),
+            ],
+        );
+        let program = "F300\nINCLUDE \"lib/clamp.cmmd\" ; setup\nLIN X2 Y0 Z0\n";
+        let lines = load(program.as_bytes(), None, Some(&directory)).unwrap();
+
+        let texts: Vec<_> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, vec!["F300", "LIN X1 Y0 Z0", "LIN X0 Y0 Z9", "LIN X2 Y0 Z0"]);
+        let numbers: Vec<_> = lines.iter().map(|line| (line.number, line.program_line())).collect();
+        assert_eq!(numbers, vec![(1, 1), (1, 2), (1, 2), (3, 3)]);
+        assert_eq!(lines[2].origin.as_ref().unwrap().file, directory.join("lib").join("park.cmmd"));
+    }
+
+    /// Test that include cycles, missing files and refused includes are errors.
+    #[test]
+    fn test_include_errors() {
+        let directory = files("cycle", &[("a.cmmd", "INCLUDE b.cmmd\n"), ("b.cmmd", "INCLUDE a.cmmd\n")]);
+        let err = load("INCLUDE a.cmmd".as_bytes(), None, Some(&directory)).unwrap_err();
+        assert!(matches!(err, CmmdError::IncludeCycle { ref file } if file.ends_with("a.cmmd")));
+
+        let err = load("INCLUDE missing.cmmd".as_bytes(), None, Some(&directory)).unwrap_err();
+        assert!(matches!(err, CmmdError::Include { .. }));
+
+        let err = load("LIN X0 Y0 Z0\nINCLUDE a.cmmd".as_bytes(), None, None).unwrap_err();
+        assert!(matches!(err, CmmdError::IncludeNotAllowed { line: 2 }));
+    }
+}
@@ -0,0 +1,183 @@
+//! NURBS curves
+//!
+//! A non-uniform rational B-spline is a chain of polynomial pieces of one
+//! degree, pulled toward each control point in proportion to its weight. The
+//! knot vector says where along the curve's parameter one piece hands over to
+//! the next. With a clamped knot vector, whose first and last knots repeat
+//! `degree + 1` times, the curve starts at the first control point and ends at
+//! the last one. That is almost always what a program wants, so
+//! [`clamped_knots`] builds one with evenly spaced knots in between.
+//!
+//! Curves are evaluated with de Boor's algorithm in homogeneous coordinates,
+//! which handles the weights exactly; a degree 2 curve with the right weights
+//! traces a true circular arc.
+
+use crate::error::SplineError;
+
+type Point = (f64, f64, f64);
+
+/// Function to build a clamped, uniform knot vector
+///
+/// # Arguments
+///
+/// * `count` - The number of control points
+/// * `degree` - The degree of the curve's pieces
+///
+/// # Returns
+///
+/// `count + degree + 1` knots running from 0 to 1, with the first and last
+/// repeated `degree + 1` times and the rest evenly spaced.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::spline::clamped_knots;
+///
+/// assert_eq!(clamped_knots(5, 2), vec![0.0, 0.0, 0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0, 1.0, 1.0]);
+/// ```
+pub fn clamped_knots(count: usize, degree: usize) -> Vec<f64> {
+    let spans = count.saturating_sub(degree).max(1);
+    (0..count + degree + 1)
+        .map(|i| (i.saturating_sub(degree) as f64 / spans as f64).min(1.0))
+        .collect()
+}
+
+/// Function to check that control points, weights and knots describe a curve
+///
+/// # Arguments
+///
+/// * `controls` - The control points
+/// * `weights` - The weight of each control point
+/// * `knots` - The knot vector
+/// * `degree` - The degree of the curve's pieces
+///
+/// # Errors
+///
+/// Returns a `SplineError` saying what doesn't fit.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::error::SplineError;
+/// use project_2::spline::{check, clamped_knots};
+///
+/// let controls = [(0.0, 0.0, 0.0), (1.0, 1.0, 0.0), (2.0, 0.0, 0.0)];
+/// assert!(check(&controls, &[1.0; 3], &clamped_knots(3, 2), 2).is_ok());
+/// assert_eq!(
+///     check(&controls, &[1.0; 3], &clamped_knots(3, 2), 3),
+///     Err(SplineError::TooFewPoints { degree: 3, found: 3 })
+/// );
+/// ```
+pub fn check(controls: &[Point], weights: &[f64], knots: &[f64], degree: usize) -> Result<(), SplineError> {
+    if degree == 0 {
+        return Err(SplineError::ZeroDegree);
+    }
+    if controls.len() <= degree {
+        return Err(SplineError::TooFewPoints {
+            degree,
+            found: controls.len(),
+        });
+    }
+    if weights.len() != controls.len() {
+        return Err(SplineError::WeightCount {
+            expected: controls.len(),
+            found: weights.len(),
+        });
+    }
+    if let Some(&weight) = weights.iter().find(|weight| weight.is_nan() || **weight <= 0.0) {
+        return Err(SplineError::NonPositiveWeight { weight });
+    }
+    if knots.len() != controls.len() + degree + 1 {
+        return Err(SplineError::KnotCount {
+            expected: controls.len() + degree + 1,
+            found: knots.len(),
+        });
+    }
+    // The curve runs from knot `degree` to knot `count`, which must be apart
+    if knots.windows(2).any(|pair| pair[1] < pair[0]) || knots[degree] >= knots[controls.len()] {
+        return Err(SplineError::InvalidKnots);
+    }
+    Ok(())
+}
+
+// Evaluate a curve that passed `check` at `t`, from 0 at its start to 1 at its end
+pub(crate) fn evaluate(controls: &[Point], weights: &[f64], knots: &[f64], degree: usize, t: f64) -> Point {
+    let count = controls.len();
+    let (low, high) = (knots[degree], knots[count]);
+    let u = low + (high - low) * t.clamp(0.0, 1.0);
+
+    // The knot span holding `u`; the very end belongs to the last span that isn't empty
+    let mut span = (knots[..count].partition_point(|&knot| knot <= u) - 1).clamp(degree, count - 1);
+    while span > degree && knots[span] >= knots[span + 1] {
+        span -= 1;
+    }
+
+    // De Boor's algorithm on the weighted points (x·w, y·w, z·w, w)
+    let mut points: Vec<[f64; 4]> = (span - degree..=span)
+        .map(|i| {
+            let (x, y, z) = controls[i];
+            let weight = weights[i];
+            [x * weight, y * weight, z * weight, weight]
+        })
+        .collect();
+    for round in 1..=degree {
+        for j in (round..=degree).rev() {
+            let i = j + span - degree;
+            let width = knots[i + degree + 1 - round] - knots[i];
+            let alpha = if width > 0.0 { (u - knots[i]) / width } else { 0.0 };
+            let before = points[j - 1];
+            for (value, before) in points[j].iter_mut().zip(before) {
+                *value = (1.0 - alpha) * before + alpha * *value;
+            }
+        }
+    }
+    let [x, y, z, weight] = points[degree];
+    (x / weight, y / weight, z / weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that a clamped cubic with four control points is a Bézier curve.
+    #[test]
+    fn test_bezier_equivalence() {
+        let controls = [(0.0, 0.0, 0.0), (0.0, 4.0, 0.0), (4.0, 4.0, 2.0), (4.0, 0.0, 2.0)];
+        let knots = clamped_knots(4, 3);
+        assert_eq!(knots, vec![0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(evaluate(&controls, &[1.0; 4], &knots, 3, 0.0), controls[0]);
+        assert_eq!(evaluate(&controls, &[1.0; 4], &knots, 3, 0.5), (2.0, 3.0, 1.0));
+        assert_eq!(evaluate(&controls, &[1.0; 4], &knots, 3, 1.0), controls[3]);
+    }
+
+    /// Test that weights make a quadratic trace an exact quarter circle.
+    #[test]
+    fn test_rational_circle() {
+        let controls = [(10.0, 0.0, 0.0), (10.0, 10.0, 0.0), (0.0, 10.0, 0.0)];
+        let weights = [1.0, std::f64::consts::FRAC_1_SQRT_2, 1.0];
+        let knots = clamped_knots(3, 2);
+        for step in 0..=10 {
+            let (x, y, _) = evaluate(&controls, &weights, &knots, 2, step as f64 / 10.0);
+            assert!((x.hypot(y) - 10.0).abs() < 1e-9);
+        }
+    }
+
+    /// Test the knots and degrees that don't describe a curve.
+    #[test]
+    fn test_check() {
+        let controls = [(0.0, 0.0, 0.0), (1.0, 1.0, 0.0), (2.0, 0.0, 0.0)];
+        assert_eq!(check(&controls, &[1.0; 3], &clamped_knots(3, 0), 0), Err(SplineError::ZeroDegree));
+        assert_eq!(
+            check(&controls, &[1.0, 0.0, 1.0], &clamped_knots(3, 2), 2),
+            Err(SplineError::NonPositiveWeight { weight: 0.0 })
+        );
+        assert_eq!(
+            check(&controls, &[1.0; 3], &[0.0, 0.0, 1.0, 1.0], 2),
+            Err(SplineError::KnotCount { expected: 6, found: 4 })
+        );
+        assert_eq!(
+            check(&controls, &[1.0; 3], &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0], 2),
+            Err(SplineError::InvalidKnots)
+        );
+    }
+}
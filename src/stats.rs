@@ -0,0 +1,305 @@
+//! Path metrics
+//!
+//! Sums up a program without running it: how far the tool travels, how much
+//! of that is cutting, where it goes and roughly how long it takes. Handy for
+//! quoting jobs and for spotting programs that are obviously wrong.
+
+use serde::Serialize;
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::post::arc_move;
+use crate::trajectory::Trajectory;
+
+/// Number of motions of each kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct MotionCounts {
+    /// Straight cutting moves
+    pub linear: usize,
+    /// Rapid moves
+    pub rapid: usize,
+    /// Arcs that stay at one height along their plane's normal
+    pub arc: usize,
+    /// Arcs that also move along their plane's normal
+    pub helical: usize,
+    /// Cubic Bézier curves
+    pub bezier: usize,
+    /// NURBS curves
+    pub spline: usize,
+    /// Straight cutting moves that tilt the tool
+    pub oriented: usize,
+    /// Tool changes, which don't move the tool
+    pub tool_changes: usize,
+    /// Auxiliary commands such as coolant on and off
+    pub aux: usize,
+    /// Pauses and optional stops
+    pub pauses: usize,
+}
+
+/// The box a program's path fits in
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Bounds {
+    /// Lowest (x, y, z) the tool reaches
+    pub min: (f64, f64, f64),
+    /// Highest (x, y, z) the tool reaches
+    pub max: (f64, f64, f64),
+}
+
+/// Metrics of a whole program
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Stats {
+    /// Length of every motion together, rapid or not
+    pub length: f64,
+    /// Length of the motions that cut
+    pub cutting: f64,
+    /// Length of the rapid moves
+    pub rapid: f64,
+    /// Where the tool goes, or `None` for an empty program
+    pub bounds: Option<Bounds>,
+    /// Number of motions of each kind
+    pub motions: MotionCounts,
+    /// Estimated run time in seconds, at the programmed feed rates, with the expected length of any pauses
+    pub time: f64,
+    /// Cutting motions without a feed rate, which the run time leaves out
+    pub unfed: usize,
+    /// Filament the extruder feeds, less what it retracts
+    pub extruded: f64,
+}
+
+// Length of the true path of a motion, moving `current_z` on to the height it ends at
+fn path_length(motion: &Motion, current_z: &mut f64) -> f64 {
+    let straight = |start: (f64, f64, f64), end: (f64, f64, f64)| {
+        ((end.0 - start.0).powi(2) + (end.1 - start.1).powi(2) + (end.2 - start.2).powi(2)).sqrt()
+    };
+    match motion {
+        Motion::Linear(linear_motion) => {
+            *current_z = linear_motion.end.2;
+            straight(linear_motion.start, linear_motion.end)
+        }
+        Motion::Rotational(rotational_motion) => {
+            let arc = arc_move(rotational_motion, *current_z);
+            // Unrolled, a helix is the hypotenuse of its sweep and its rise
+            let (z_start, z_end) = rotational_motion.z.unwrap_or((*current_z, *current_z));
+            *current_z = arc.end.2;
+            (rotational_motion.radius * arc.sweep.to_radians()).hypot(z_end - z_start)
+        }
+        Motion::Bezier(bezier_motion) => {
+            *current_z = bezier_motion.end.2;
+            bezier_motion.length()
+        }
+        Motion::Spline(spline_motion) => {
+            *current_z = spline_motion.end().2;
+            spline_motion.length()
+        }
+        Motion::Oriented(oriented_motion) => {
+            *current_z = oriented_motion.end.2;
+            straight(oriented_motion.start, oriented_motion.end)
+        }
+        Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => 0.0,
+    }
+}
+
+/// Function to estimate how long each motion of a program takes, in seconds
+///
+/// Motions run at their feed rate from start to finish, as in
+/// [`program_stats`], and cutting motions without a feed rate take no time.
+/// Pauses take [`InterpolationOptions::pause_time`].
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::stats::motion_times;
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (30.0, 40.0, 0.0)).with_feed(Some(600.0)),
+///     Motion::new_linear((30.0, 40.0, 0.0), (0.0, 0.0, 0.0)),
+/// ];
+/// assert_eq!(motion_times(&motions, &Default::default()), vec![5.0, 0.0]);
+/// ```
+pub fn motion_times(motions: &[Motion], options: &InterpolationOptions) -> Vec<f64> {
+    let mut current_z = 0.0;
+    motions
+        .iter()
+        .map(|motion| {
+            if let Motion::Pause(_) = motion {
+                return options.pause_time;
+            }
+            let length = path_length(motion, &mut current_z);
+            let feed = match motion.feed() {
+                _ if motion.is_rapid() => options.rapid_rate,
+                Some(feed) if feed > 0.0 => feed,
+                _ => return 0.0,
+            };
+            // Feed rates are per minute
+            length / feed * 60.0
+        })
+        .collect()
+}
+
+/// Function to measure a program
+///
+/// Lengths are measured along the true path, so arcs count their full
+/// sweep rather than the chords they are split into, and curves are measured
+/// much more finely than they are interpolated. The bounding box does
+/// come from the interpolated points, so it includes the bulge of arcs. Run
+/// time assumes every motion runs at its feed rate from start to finish
+/// (and rapid moves at [`InterpolationOptions::rapid_rate`]), without
+/// acceleration.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `options` - How motions are split into points, and the rapid rate
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::stats::program_stats;
+///
+/// let motions = vec![
+///     Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)),
+///     Motion::new_linear((0.0, 0.0, 0.0), (30.0, 40.0, 0.0)).with_feed(Some(600.0)),
+/// ];
+/// let stats = program_stats(&motions, &Default::default());
+/// assert_eq!((stats.cutting, stats.rapid), (50.0, 5.0));
+/// assert_eq!(stats.motions.linear, 1);
+/// assert_eq!(stats.bounds.unwrap().max, (30.0, 40.0, 5.0));
+/// ```
+pub fn program_stats(motions: &[Motion], options: &InterpolationOptions) -> Stats {
+    let mut stats = Stats::default();
+
+    // Flat arcs without their own height stay where the last motion ended
+    let mut current_z = 0.0;
+    for motion in motions {
+        // Events take no time at the feed rate, and don't need one; pauses take as long as they're expected
+        // to
+        match motion {
+            Motion::ToolChange(_) => stats.motions.tool_changes += 1,
+            Motion::Aux(_) => stats.motions.aux += 1,
+            Motion::Pause(_) => {
+                stats.motions.pauses += 1;
+                stats.time += options.pause_time;
+            }
+            _ => {}
+        }
+        if motion.is_event() {
+            continue;
+        }
+        let length = path_length(motion, &mut current_z);
+        match motion {
+            Motion::Rotational(rotational_motion) => {
+                if rotational_motion.z.is_some_and(|(start, end)| (end - start).abs() > 1e-9) {
+                    stats.motions.helical += 1;
+                } else {
+                    stats.motions.arc += 1;
+                }
+            }
+            Motion::Bezier(_) => stats.motions.bezier += 1,
+            Motion::Spline(_) => stats.motions.spline += 1,
+            _ => {}
+        }
+        stats.length += length;
+        if let Motion::Linear(linear_motion) = motion {
+            stats.extruded += linear_motion.extruded();
+        }
+
+        // Feed rates are per minute, the run time is in seconds
+        let feed = if motion.is_rapid() {
+            stats.motions.rapid += 1;
+            stats.rapid += length;
+            Some(options.rapid_rate)
+        } else {
+            match motion {
+                Motion::Linear(_) => stats.motions.linear += 1,
+                Motion::Oriented(_) => stats.motions.oriented += 1,
+                _ => {}
+            }
+            stats.cutting += length;
+            motion.feed().filter(|feed| *feed > 0.0)
+        };
+        match feed {
+            Some(feed) => stats.time += length / feed * 60.0,
+            None => stats.unfed += 1,
+        }
+    }
+
+    // Grow the box around every point the tool passes through
+    for point in Trajectory::new(motions, options).points() {
+        let (x, y, z) = point.position;
+        let Bounds { min, max } = stats.bounds.unwrap_or(Bounds {
+            min: (x, y, z),
+            max: (x, y, z),
+        });
+        stats.bounds = Some(Bounds {
+            min: (min.0.min(x), min.1.min(y), min.2.min(z)),
+            max: (max.0.max(x), max.1.max(y), max.2.max(z)),
+        });
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test measuring arcs and helices along their sweep, and timing at the rapid rate.
+    #[test]
+    fn test_arc_lengths() {
+        let motions = vec![
+            // A quarter circle of radius 10 and a full helical turn rising 5
+            Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 10.0, 0.0), (-10.0, 0.0), false)
+                .with_feed(Some(100.0)),
+            Motion::new_helical((0.0, 0.0), 10.0, 90.0, 360.0, (0.0, 5.0)),
+            Motion::new_rapid((0.0, 10.0, 5.0), (0.0, 10.0, 55.0)),
+        ];
+        let options = InterpolationOptions {
+            rapid_rate: 3000.0,
+            ..Default::default()
+        };
+        let stats = program_stats(&motions, &options);
+
+        let quarter = 10.0 * std::f64::consts::FRAC_PI_2;
+        let helix = (20.0 * std::f64::consts::PI).hypot(5.0);
+        assert!((stats.cutting - (quarter + helix)).abs() < 1e-9);
+        assert_eq!(stats.rapid, 50.0);
+        assert_eq!(
+            stats.motions,
+            MotionCounts {
+                linear: 0,
+                rapid: 1,
+                arc: 1,
+                helical: 1,
+                bezier: 0,
+                spline: 0,
+                oriented: 0,
+                tool_changes: 0,
+                aux: 0,
+                pauses: 0
+            }
+        );
+        // The helix has no feed rate, so only the arc and the rapid move are timed
+        assert_eq!(stats.unfed, 1);
+        assert!((stats.time - (quarter / 100.0 * 60.0 + 1.0)).abs() < 1e-9);
+        let Bounds { min, max } = stats.bounds.unwrap();
+        assert!((min.0 + 10.0).abs() < 1e-6 && (max.2 - 55.0).abs() < 1e-9);
+    }
+
+    /// Test that pauses are counted, and timed only as long as they're expected to last.
+    #[test]
+    fn test_pauses() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(600.0)),
+            Motion::new_pause(false, (10.0, 0.0, 0.0)),
+            Motion::new_pause(true, (10.0, 0.0, 0.0)),
+        ];
+        let stats = program_stats(&motions, &InterpolationOptions::default());
+        assert_eq!((stats.motions.pauses, stats.time, stats.unfed), (2, 1.0, 0));
+
+        let options = InterpolationOptions { pause_time: 30.0, ..Default::default() };
+        assert_eq!(program_stats(&motions, &options).time, 61.0);
+        assert_eq!(motion_times(&motions, &options), vec![1.0, 30.0, 30.0]);
+    }
+}
@@ -0,0 +1,699 @@
+//! Streaming programs to a GRBL controller
+//!
+//! GRBL acknowledges every line it receives with `ok` or `error:<code>`. The
+//! streamer here uses the simple send-response scheme: it sends one line,
+//! waits for the answer, and only then sends the next, so the controller's
+//! receive buffer can never overflow. Status reports and messages that arrive
+//! in between (`<Idle|...>`, `[MSG:...]`) are skipped.
+//!
+//! The streamer works with anything that can be read and written, so the
+//! command line opens a serial port for it while tests use an in-memory fake.
+//!
+//! While a program streams, a [`Checkpoint`] can be written to disk every so
+//! often, recording the motion to pick the job up at if the computer or the
+//! machine loses power, for `--resume`.
+//!
+//! GRBL can't change tools itself, so [`stream_with_stops`] holds back tool
+//! changes: it waits for the machine to finish the moves before one, and then
+//! for whoever is running it to swap the tool. Auxiliary commands such as
+//! coolant are reported to the caller as they go out, and the user-defined
+//! ones GRBL doesn't know are held back the same way tool changes are. Pauses
+//! and optional stops are held back too, until the caller lets the program
+//! carry on.
+//!
+//! Feed hold and override don't go through the streamer at all: GRBL acts on
+//! them the moment it receives them, so they are written straight to the port
+//! as the bytes [`realtime_bytes`](crate::playback::realtime_bytes) gives.
+//!
+//! Over a noisy link each line can be given an `N` line number and, as
+//! Marlin and other printer firmware expect, a `*` checksum (see [`Framing`]).
+//! A controller that gets a line garbled answers with `Resend: <n>` (or
+//! `rs <n>`) and then `ok`, and the line goes out again.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{ConfigError, StreamError};
+use crate::gcode::is_aux_code;
+use crate::motion::Motion;
+use crate::post::{write_marked_gcode, Flavor};
+use crate::transform::ends;
+
+/// How many acknowledged lines may not have run yet
+///
+/// GRBL acknowledges a line once it is planned, not once it has run, and its
+/// planner holds 15 moves besides the one running. Arcs are planned as many
+/// short moves, so they only ever fill it with fewer lines.
+pub const PLANNER_LINES: usize = 16;
+
+// How many times one line is sent again before giving up on the link
+const RESENDS: usize = 5;
+
+/// How each line is marked on its way to the controller
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Lines go as they are
+    #[default]
+    Plain,
+    /// Lines start with an `N` word numbering them from 1
+    Numbered,
+    /// Lines are numbered and end with a `*` checksum, after an `M110 N0` that resets the controller's count
+    Checksummed,
+}
+
+/// Function to work out the checksum of a line, as Marlin and other printer firmware check it
+///
+/// The checksum is every byte of the line before the `*` XORed together.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::stream::checksum;
+///
+/// assert_eq!(checksum("N0 M110 N0"), 125);
+/// ```
+pub fn checksum(line: &str) -> u8 {
+    line.bytes().fold(0, |sum, byte| sum ^ byte)
+}
+
+// The number of the line a controller asks to have sent again, as in `Resend: 12` or `rs N12`
+fn resend_request(response: &str) -> Option<u64> {
+    let number = response.strip_prefix("Resend:").or_else(|| response.strip_prefix("rs "))?;
+    number.trim().trim_start_matches('N').parse().ok()
+}
+
+/// Function to turn a program into the G-code lines to send to a controller
+///
+/// Comment and blank lines are dropped, since the controller doesn't need them.
+/// Tool changes stay `T M6` lines whatever the flavor, since
+/// [`stream_with_stops`] stops for them itself.
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error for motions the controller can't make, as
+/// [`write_gcode_for`](crate::post::write_gcode_for) does.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::post::Flavor;
+/// use project_2::stream::gcode_lines;
+///
+/// let lines = gcode_lines(&[Motion::new_linear((0.0, 0.0, 0.0), (5.0, 0.0, 0.0))], Flavor::Grbl).unwrap();
+/// assert_eq!(lines, vec!["G21 G90 G17 G94", "G1 X5 Y0 Z0", "M2"]);
+/// ```
+pub fn gcode_lines(motions: &[Motion], flavor: Flavor) -> io::Result<Vec<String>> {
+    Ok(marked_gcode_lines(motions, flavor)?.into_iter().map(|(_, line)| line).collect())
+}
+
+/// Function to turn a program into the G-code lines to send, each with the motion it belongs to
+///
+/// The set-up lines at the start belong to the first motion, and the program
+/// end to none: it is given the number of motions instead.
+///
+/// # Errors
+///
+/// Returns the errors [`gcode_lines`] does.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::post::Flavor;
+/// use project_2::stream::marked_gcode_lines;
+///
+/// let lines = marked_gcode_lines(&[Motion::new_linear((0.0, 0.0, 0.0), (5.0, 0.0, 0.0))], Flavor::Generic).unwrap();
+/// assert_eq!(lines[1], (0, String::from("G1 X5 Y0 Z0")));
+/// assert_eq!(lines[2], (1, String::from("M2")));
+/// ```
+pub fn marked_gcode_lines(motions: &[Motion], flavor: Flavor) -> io::Result<Vec<(usize, String)>> {
+    let mut output = Vec::new();
+    write_marked_gcode(&mut output, motions, flavor)?;
+    let mut motion = 0;
+    let mut lines = Vec::new();
+    for line in String::from_utf8_lossy(&output).lines().map(str::trim) {
+        // Marlin comments start with a semicolon, and LinuxCNC programs are wrapped in percent signs
+        let comment = line.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')).or(line.strip_prefix(';'));
+        if let Some(index) = comment.and_then(|text| text.strip_prefix("motion ")) {
+            motion = index.parse().expect("motion markers are numbered");
+        } else if comment == Some("end") {
+            motion = motions.len();
+        } else if !line.is_empty() && comment.is_none() && line != "%" {
+            lines.push((motion, line.to_string()));
+        }
+    }
+    Ok(lines)
+}
+
+/// Where a streamed job got to, for picking it up again after a crash or power loss
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// How many motions the program has, to catch resuming a different one
+    pub motions: usize,
+    /// The first motion that may not have run yet, where the job picks up
+    pub motion: usize,
+    /// Line of the program that motion came from, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    /// Where the tool was when that motion began
+    pub position: (f64, f64, f64),
+    /// Feed rate in effect for that motion, if one was programmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feed: Option<f64>,
+    /// Spindle speed or laser power in effect for that motion, if one was programmed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power: Option<f64>,
+}
+
+impl Checkpoint {
+    /// Function to work out where a job got to once some of its lines are acknowledged
+    ///
+    /// The last [`PLANNER_LINES`] acknowledged lines may still be waiting in
+    /// the controller, so the checkpoint is the motion of the line before them.
+    ///
+    /// # Arguments
+    ///
+    /// * `motions` - The motions of the program
+    /// * `lines` - The lines being sent, from [`marked_gcode_lines`]
+    /// * `acknowledged` - How many lines the controller has acknowledged
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    /// use project_2::post::Flavor;
+    /// use project_2::stream::{marked_gcode_lines, Checkpoint};
+    ///
+    /// let motions: Vec<Motion> = (0..40)
+    ///     .map(|step| Motion::new_linear((step as f64, 0.0, 0.0), (step as f64 + 1.0, 0.0, 0.0)))
+    ///     .collect();
+    /// let lines = marked_gcode_lines(&motions, Flavor::Grbl).unwrap();
+    /// // The header and 29 motions are acknowledged, but the last 16 may not have run
+    /// let checkpoint = Checkpoint::after(&motions, &lines, 30);
+    /// assert_eq!((checkpoint.motion, checkpoint.position), (13, (13.0, 0.0, 0.0)));
+    /// ```
+    pub fn after(motions: &[Motion], lines: &[(usize, String)], acknowledged: usize) -> Checkpoint {
+        let motion = lines.get(acknowledged.saturating_sub(PLANNER_LINES)).map_or(motions.len(), |line| line.0);
+        // Walk up to the motion, placing flat arcs at the height the one before ended
+        let mut position = (0.0, 0.0, 0.0);
+        for (index, current) in motions.iter().enumerate() {
+            let (start, end) = ends(current, position.2);
+            if index == motion {
+                position = start;
+                break;
+            }
+            position = end;
+        }
+        let current = motions.get(motion);
+        Checkpoint {
+            motions: motions.len(),
+            motion,
+            line: current.and_then(Motion::line),
+            position,
+            feed: current.and_then(Motion::feed),
+            power: current.and_then(Motion::power),
+        }
+    }
+
+    /// Function to read a checkpoint from a TOML file
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Io` if the file can't be read and
+    /// `ConfigError::Toml` if it isn't a checkpoint.
+    pub fn load(path: &Path) -> Result<Checkpoint, ConfigError> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Function to write the checkpoint to a TOML file
+    ///
+    /// The checkpoint is written next to the file and then renamed over it, so
+    /// losing power part way through leaves the previous checkpoint intact.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing or renaming the file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text = toml::to_string(self).map_err(io::Error::other)?;
+        let partial = path.with_extension("partial");
+        fs::write(&partial, text)?;
+        fs::rename(&partial, path)
+    }
+}
+
+// Read the next full line from the controller, waiting out read timeouts
+//
+// Serial ports time out while the controller is busy finishing moves, so a
+// timeout just means "keep waiting". Returns `None` when the connection closes.
+fn read_response<P: Read>(port: &mut BufReader<P>) -> io::Result<Option<String>> {
+    let mut response = String::new();
+    loop {
+        match port.read_line(&mut response) {
+            Ok(0) if response.is_empty() => return Ok(None),
+            Ok(_) if response.ends_with('\n') => return Ok(Some(response.trim().to_string())),
+            // A partial line at the end of the input
+            Ok(0) => return Ok(Some(response.trim().to_string())),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Function to stream G-code lines to a GRBL controller
+///
+/// Each line is sent and acknowledged before the next one goes out.
+///
+/// # Arguments
+///
+/// * `port` - The connection to the controller
+/// * `lines` - The G-code lines to send
+/// * `progress` - Called with the number of lines acknowledged so far and the total
+///
+/// # Errors
+///
+/// Returns `StreamError::Rejected` as soon as the controller answers a line
+/// with an error or alarm, `StreamError::Disconnected` if the connection closes
+/// early, and `StreamError::Io` if it fails.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Cursor, Read, Write};
+/// use project_2::stream::stream;
+///
+/// // A pretend controller that accepts everything
+/// struct Controller(Cursor<&'static [u8]>, Vec<u8>);
+/// impl Read for Controller {
+///     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.read(buf) }
+/// }
+/// impl Write for Controller {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.1.write(buf) }
+///     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// }
+///
+/// let controller = Controller(Cursor::new(b"ok\nok\n"), Vec::new());
+/// let lines = vec![String::from("G1 X5 F300"), String::from("M2")];
+/// stream(controller, &lines, |sent, total| println!("{}/{}", sent, total)).unwrap();
+/// ```
+pub fn stream<P, F>(port: P, lines: &[String], progress: F) -> Result<(), StreamError>
+where
+    P: Read + Write,
+    F: FnMut(usize, usize),
+{
+    stream_with_stops(port, lines, Framing::Plain, progress, |_| Ok(()))
+}
+
+/// A line that [`stream_with_stops`] stops or calls back for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// A change to the given tool
+    ToolChange(u32),
+    /// An auxiliary command such as `M8`, as written
+    Aux(String),
+    /// A pause (`M0`), or an optional stop (`M1`)
+    Pause { optional: bool },
+}
+
+/// Function to find the tool a G-code line changes to, if it changes tools
+///
+/// A line changes tools if it has an `M6` word, to the tool its `T` word
+/// names (0 if it has none).
+///
+/// # Examples
+///
+/// ```
+/// use project_2::stream::tool_change;
+///
+/// assert_eq!(tool_change("T3 M6"), Some(3));
+/// assert_eq!(tool_change("G1 X5 T3"), None);
+/// ```
+pub fn tool_change(line: &str) -> Option<u32> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let changes = words.iter().any(|word| word.eq_ignore_ascii_case("M6") || word.eq_ignore_ascii_case("M06"));
+    changes.then(|| {
+        words
+            .iter()
+            .find_map(|word| word.strip_prefix(['T', 't']).and_then(|number| number.parse().ok()))
+            .unwrap_or(0)
+    })
+}
+
+/// Function to find the auxiliary M-code a G-code line runs, if any
+///
+/// See [`is_aux_code`] for which codes those are.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::stream::aux_code;
+///
+/// assert_eq!(aux_code("M8"), Some(8));
+/// assert_eq!(aux_code("M3 S1000"), None);
+/// ```
+pub fn aux_code(line: &str) -> Option<u32> {
+    line.split_whitespace()
+        .filter_map(|word| word.strip_prefix(['M', 'm']).and_then(|number| number.parse().ok()))
+        .find(|&code| is_aux_code(code))
+}
+
+/// Function to find whether a G-code line stops the program, and if so whether optionally
+///
+/// # Examples
+///
+/// ```
+/// use project_2::stream::pause;
+///
+/// assert_eq!(pause("M0"), Some(false));
+/// assert_eq!(pause("M01"), Some(true));
+/// assert_eq!(pause("M8"), None);
+/// ```
+pub fn pause(line: &str) -> Option<bool> {
+    line.split_whitespace()
+        .filter_map(|word| word.strip_prefix(['M', 'm']).and_then(|number| number.parse::<u32>().ok()))
+        .find_map(|code| match code {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        })
+}
+
+// The connection to the controller, with the number the next line goes out with
+struct Link<P> {
+    port: BufReader<P>,
+    framing: Framing,
+    number: u64,
+}
+
+impl<P: Read + Write> Link<P> {
+    // Write a line as it is, ending it with a newline
+    fn write_line(&mut self, text: &str) -> io::Result<()> {
+        let writer = self.port.get_mut();
+        writer.write_all(text.as_bytes())?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    // Send one line and wait for the controller to acknowledge it, sending it again when asked to
+    //
+    // `index` is the line's place in the program, for errors.
+    fn send(&mut self, index: usize, line: &str) -> Result<(), StreamError> {
+        let framed = match self.framing {
+            Framing::Plain => line.to_string(),
+            Framing::Numbered => format!("N{} {}", self.number, line),
+            Framing::Checksummed => {
+                let numbered = format!("N{} {}", self.number, line);
+                format!("{}*{}", numbered, checksum(&numbered))
+            }
+        };
+        self.write_line(&framed)?;
+        let rejected = |message: String| StreamError::Rejected {
+            line: index + 1,
+            text: line.to_string(),
+            message,
+        };
+
+        // Wait for the acknowledgement, skipping reports and messages
+        let mut resends = 0;
+        let mut resending = false;
+        loop {
+            let Some(response) = read_response(&mut self.port)? else {
+                return Err(StreamError::Disconnected { line: index });
+            };
+            if response == "ok" || response.starts_with("ok ") {
+                // The controller acknowledges a resend request too, and then wants the line again
+                if resending {
+                    resending = false;
+                    self.write_line(&framed)?;
+                    continue;
+                }
+                self.number += 1;
+                return Ok(());
+            } else if response.starts_with("error") || response.starts_with("ALARM") {
+                return Err(rejected(response));
+            } else if let Some(number) = resend_request(&response).filter(|_| self.framing != Framing::Plain) {
+                // Only the line waiting for its answer can be sent again
+                resends += 1;
+                if number != self.number || resends > RESENDS {
+                    return Err(rejected(response));
+                }
+                resending = true;
+            }
+        }
+    }
+}
+
+/// Function to stream G-code lines to a GRBL controller, stopping for tool changes
+///
+/// Works like [`stream`], marking each line as `framing` says, except that lines that change tools (see
+/// [`tool_change`]) aren't sent. In their place goes a `G4 P0` dwell, which
+/// GRBL only acknowledges once every move before it has run, and then
+/// `on_stop` is called with the new tool. Streaming carries on once it returns.
+///
+/// Lines with an auxiliary command (see [`aux_code`]) call `on_stop` too, once
+/// the controller has acknowledged them. GRBL only knows the coolant codes, so
+/// a user-defined code is held back like a tool change, leaving it to `on_stop`.
+/// Pauses and optional stops (see [`pause`]) are held back the same way, and
+/// `on_stop` decides how long to wait.
+///
+/// # Arguments
+///
+/// * `port` - The connection to the controller
+/// * `lines` - The G-code lines to send
+/// * `framing` - How each line is marked on its way
+/// * `progress` - Called with the number of lines acknowledged so far and the total
+/// * `on_stop` - Called with each tool change and auxiliary command as the program gets to it
+///
+/// # Errors
+///
+/// Returns the errors [`stream`] does, and `StreamError::Io` with the error
+/// `on_stop` gives if it fails. A line the controller asks for more than five
+/// times over, or one it asks for out of turn, is `StreamError::Rejected`.
+pub fn stream_with_stops<P, F, S>(
+    port: P,
+    lines: &[String],
+    framing: Framing,
+    mut progress: F,
+    mut on_stop: S,
+) -> Result<(), StreamError>
+where
+    P: Read + Write,
+    F: FnMut(usize, usize),
+    S: FnMut(&Stop) -> io::Result<()>,
+{
+    let mut link = Link {
+        port: BufReader::new(port),
+        framing,
+        number: 1,
+    };
+    // Checksummed lines are counted from where the controller is told to start
+    if framing == Framing::Checksummed {
+        link.number = 0;
+        link.send(0, "M110 N0")?;
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(tool) = tool_change(line) {
+            link.send(index, "G4 P0")?;
+            on_stop(&Stop::ToolChange(tool))?;
+        } else if let Some(optional) = pause(line) {
+            link.send(index, "G4 P0")?;
+            on_stop(&Stop::Pause { optional })?;
+        } else if let Some(code) = aux_code(line) {
+            // GRBL runs coolant itself, once the moves before it have run
+            let sent = if (7..=9).contains(&code) { line.as_str() } else { "G4 P0" };
+            link.send(index, sent)?;
+            on_stop(&Stop::Aux(line.clone()))?;
+        } else {
+            link.send(index, line)?;
+        }
+        progress(index + 1, lines.len());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::motion::Rotary;
+    use std::io::Cursor;
+
+    // In-memory stand-in for a serial port
+    struct FakePort {
+        responses: Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl FakePort {
+        fn new(responses: &str) -> Self {
+            FakePort {
+                responses: Cursor::new(responses.as_bytes().to_vec()),
+                sent: Vec::new(),
+            }
+        }
+    }
+
+    impl Write for &mut FakePort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for &mut FakePort {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.responses.read(buf)
+        }
+    }
+
+    // Turn string literals into owned lines
+    fn lines(list: &[&str]) -> Vec<String> {
+        list.iter().map(|line| line.to_string()).collect()
+    }
+
+    /// Test that every line is sent and acknowledged, skipping status reports.
+    #[test]
+    fn test_stream_ok() {
+        let mut port = FakePort::new("ok\n<Idle|MPos:0,0,0>\n[MSG:hello]\nok\n");
+        let mut reported = Vec::new();
+        stream(&mut port, &lines(&["G1 X1", "G1 X2"]), |sent, total| reported.push((sent, total))).unwrap();
+        assert_eq!(port.sent, b"G1 X1\nG1 X2\n");
+        assert_eq!(reported, vec![(1, 2), (2, 2)]);
+    }
+
+    /// Test that streaming stops at the first rejected line or when the controller goes away.
+    #[test]
+    fn test_stream_errors() {
+        let mut port = FakePort::new("ok\nerror:20\nok\n");
+        let err = stream(&mut port, &lines(&["G1 X1", "G5", "G1 X2"]), |_, _| {}).unwrap_err();
+        assert!(matches!(err, StreamError::Rejected { line: 2, ref message, .. } if message == "error:20"));
+        assert_eq!(port.sent, b"G1 X1\nG5\n");
+
+        let mut port = FakePort::new("ok\n");
+        let err = stream(&mut port, &lines(&["G1 X1", "G1 X2"]), |_, _| {}).unwrap_err();
+        assert!(matches!(err, StreamError::Disconnected { line: 1 }));
+    }
+
+    /// Test that tool changes and pauses wait for the machine to stop and for the caller to carry on.
+    #[test]
+    fn test_stream_stops() {
+        let mut port = FakePort::new("ok\nok\nok\nok\n");
+        let mut confirmed = Vec::new();
+        let program = lines(&["G1 X1", "T2 M6", "G1 X2", "M1"]);
+        let confirm = |stop: &Stop| {
+            confirmed.push(stop.clone());
+            Ok(())
+        };
+        stream_with_stops(&mut port, &program, Framing::Plain, |_, _| {}, confirm).unwrap();
+        assert_eq!(port.sent, b"G1 X1\nG4 P0\nG1 X2\nG4 P0\n");
+        assert_eq!(confirmed, vec![Stop::ToolChange(2), Stop::Pause { optional: true }]);
+
+        let mut port = FakePort::new("ok\nok\n");
+        let refused = |_: &Stop| Err(io::Error::other("no one there"));
+        let err = stream_with_stops(&mut port, &program, Framing::Plain, |_, _| {}, refused).unwrap_err();
+        assert!(matches!(err, StreamError::Io(_)));
+        assert_eq!(port.sent, b"G1 X1\nG4 P0\n");
+    }
+
+    /// Test that coolant goes to the controller and user-defined codes don't, with a callback for each.
+    #[test]
+    fn test_stream_aux() {
+        let mut port = FakePort::new("ok\nok\nok\n");
+        let mut called = Vec::new();
+        let program = lines(&["M8", "M101 P2", "M9"]);
+        let on_stop = |stop: &Stop| {
+            called.push(stop.clone());
+            Ok(())
+        };
+        stream_with_stops(&mut port, &program, Framing::Plain, |_, _| {}, on_stop).unwrap();
+        assert_eq!(port.sent, b"M8\nG4 P0\nM9\n");
+        let codes = ["M8", "M101 P2", "M9"].map(|code| Stop::Aux(code.to_string()));
+        assert_eq!(called, codes);
+    }
+
+    /// Test that numbered lines carry checksums and go out again when the controller asks for them.
+    #[test]
+    fn test_stream_resend() {
+        let mut port = FakePort::new("ok\nError:checksum mismatch, Last Line: 0\nResend: 1\nok\nok\nok\n");
+        let program = lines(&["G1 X1", "G1 X2"]);
+        stream_with_stops(&mut port, &program, Framing::Checksummed, |_, _| {}, |_| Ok(())).unwrap();
+        let sent = "N0 M110 N0*125\nN1 G1 X1*96\nN1 G1 X1*96\nN2 G1 X2*96\n";
+        assert_eq!(String::from_utf8(port.sent).unwrap(), sent);
+
+        // Plain numbers, and a line asked for out of turn or too often
+        let mut port = FakePort::new("ok\nok\n");
+        stream_with_stops(&mut port, &program, Framing::Numbered, |_, _| {}, |_| Ok(())).unwrap();
+        assert_eq!(port.sent, b"N1 G1 X1\nN2 G1 X2\n");
+        let mut port = FakePort::new("rs N5\nok\n");
+        let err = stream_with_stops(&mut port, &program, Framing::Numbered, |_, _| {}, |_| Ok(())).unwrap_err();
+        assert!(matches!(err, StreamError::Rejected { line: 1, ref message, .. } if message == "rs N5"));
+        let mut port = FakePort::new(&"Resend: 1\nok\n".repeat(RESENDS + 1));
+        assert!(stream_with_stops(&mut port, &program, Framing::Numbered, |_, _| {}, |_| Ok(())).is_err());
+    }
+
+    /// Test that the lines sent follow the flavor, keeping tool changes for the streamer to stop at.
+    #[test]
+    fn test_flavored_lines() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0 / 3.0, 0.0, 0.0)),
+            Motion::new_tool_change(2, (1.0 / 3.0, 0.0, 0.0)),
+            Motion::new_bezier((1.0 / 3.0, 0.0, 0.0), (1.0, 1.0, 0.0), (2.0, 1.0, 0.0), (3.0, 0.0, 0.0)),
+        ];
+        let grbl = marked_gcode_lines(&motions, Flavor::Grbl).unwrap();
+        assert_eq!(grbl[0], (0, String::from("G21 G90 G17 G94")));
+        assert_eq!(grbl[1], (0, String::from("G1 X0.333 Y0 Z0")));
+        assert_eq!(grbl[2], (1, String::from("T2 M6")));
+        // GRBL has no G5, so the curve goes out as short lines
+        assert!(grbl[3..grbl.len() - 1].iter().all(|(motion, line)| *motion == 2 && line.starts_with("G1 ")));
+        assert_eq!(grbl.last(), Some(&(3, String::from("M2"))));
+
+        // Marlin's comments and LinuxCNC's percent signs never go out
+        let marlin = gcode_lines(&motions, Flavor::Marlin).unwrap();
+        assert!(marlin.iter().all(|line| !line.starts_with(';')) && marlin.contains(&String::from("M84")));
+        let linuxcnc = marked_gcode_lines(&motions, Flavor::LinuxCnc).unwrap();
+        assert!(linuxcnc.iter().all(|(_, line)| line != "%"));
+        assert!(linuxcnc.iter().any(|(motion, line)| *motion == 2 && line.starts_with("G5 ")));
+
+        let turn = Rotary { a: 90.0, ..Default::default() };
+        let rotary = Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_rotary(Rotary::default(), turn);
+        assert!(gcode_lines(&[rotary], Flavor::Grbl).is_err());
+    }
+
+    /// Test that checkpoints hold back for the planner, survive a round trip to disk and trace lines to motions.
+    #[test]
+    fn test_checkpoint() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(300.0)).with_line(1),
+            Motion::new_rotational((0.0, 0.0), 10.0, 0.0, 90.0).with_power(Some(12000.0)).with_line(2),
+            Motion::new_linear((0.0, 5.0, 0.0), (0.0, 0.0, 0.0)).with_line(3),
+        ];
+        let lines = marked_gcode_lines(&motions, Flavor::Generic).unwrap();
+        // The last motion starts away from the arc, so it travels there first
+        let marks: Vec<usize> = lines.iter().map(|line| line.0).collect();
+        assert_eq!(marks, vec![0, 0, 1, 2, 2, 3]);
+        let text: Vec<String> = lines.iter().map(|line| line.1.clone()).collect();
+        assert_eq!(text, gcode_lines(&motions, Flavor::Generic).unwrap());
+
+        // Nothing is known to have run until the planner has filled up
+        assert_eq!(Checkpoint::after(&motions, &lines, lines.len()).motion, 0);
+        let late = Checkpoint::after(&motions, &lines, PLANNER_LINES + 3);
+        assert_eq!((late.motion, late.line, late.power), (2, Some(3), None));
+
+        let checkpoint = Checkpoint::after(&motions, &lines, PLANNER_LINES + 2);
+        assert_eq!((checkpoint.motion, checkpoint.power), (1, Some(12000.0)));
+        assert_eq!(checkpoint.position, (10.0, 0.0, 0.0));
+        let path = std::env::temp_dir().join("project-2-stream-checkpoint.toml");
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(ConfigError::Io(_))));
+    }
+}
@@ -0,0 +1,731 @@
+//! SVG backplot export and path import
+//!
+//! Renders the XY projection of a program's interpolated path, so it can be
+//! checked by eye before running it. Linear moves, arcs, and the travel moves
+//! that connect motions which don't start where the previous one ended are
+//! each drawn in their own style. Programmed rapid moves are drawn like travel
+//! moves.
+//!
+//! Going the other way, [`read_svg`] reads the `path` elements of a drawing,
+//! such as one designed in Inkscape, into a [`Drawing`] that becomes a
+//! program.
+
+use std::f64::consts::PI;
+use std::io::{self, Read, Write};
+
+use crate::drawing::{Drawing, Segment, Stroke};
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::output::geometric_points;
+
+// Styles for each kind of path
+const STYLE: &str = "\
+polyline { fill: none; stroke-width: 1.5; vector-effect: non-scaling-stroke; stroke-linejoin: round; }
+.linear { stroke: #1f77b4; }
+.arc { stroke: #d62728; }
+.rapid { stroke: #7f7f7f; stroke-dasharray: 4 3; }
+";
+
+// Write a polyline, flipping Y so the plot is the right way up
+fn polyline<W: Write>(writer: &mut W, class: &str, points: &[(f64, f64)]) -> io::Result<()> {
+    write!(writer, r#"  <polyline class="{}" points=""#, class)?;
+    for (i, (x, y)) in points.iter().enumerate() {
+        if i > 0 {
+            write!(writer, " ")?;
+        }
+        // Adding zero turns -0 into 0
+        write!(writer, "{},{}", x, -y + 0.0)?;
+    }
+    writeln!(writer, r#""/>"#)
+}
+
+/// Function to write the XY path of a program as an SVG image
+///
+/// The image uses program units, with a small margin around the path.
+///
+/// # Arguments
+///
+/// * `writer` - Where to write the SVG document
+/// * `motions` - The motions of the program
+/// * `options` - How motions are split into positions
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::svg::write_svg;
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+///     Motion::new_rotational((10.0, 5.0), 5.0, 270.0, 180.0),
+/// ];
+/// let mut output = Vec::new();
+/// write_svg(&mut output, &motions, &Default::default()).unwrap();
+/// let svg = String::from_utf8(output).unwrap();
+/// assert!(svg.contains(r#"class="linear""#));
+/// assert!(svg.contains(r#"class="arc""#));
+/// ```
+pub fn write_svg<W: Write>(mut writer: W, motions: &[Motion], options: &InterpolationOptions) -> io::Result<()> {
+    let points = geometric_points(motions, options);
+
+    // Bounding box of the path, with a margin so strokes at the edge stay visible
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (0.0_f64, 0.0_f64, 0.0_f64, 0.0_f64);
+    for (i, point) in points.iter().enumerate() {
+        let (x, y, _) = point.position;
+        if i == 0 {
+            (min_x, min_y, max_x, max_y) = (x, y, x, y);
+        }
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+    let margin = ((max_x - min_x).max(max_y - min_y) * 0.05).max(1.0);
+    let (width, height) = (max_x - min_x + 2.0 * margin, max_y - min_y + 2.0 * margin);
+
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}mm" height="{}mm">"#,
+        min_x - margin,
+        -max_y - margin,
+        width,
+        height,
+        width,
+        height
+    )?;
+    writeln!(writer, "  <style>\n{}  </style>", STYLE)?;
+
+    // Draw each motion, preceded by a travel move if it starts somewhere new
+    let mut previous: Option<(f64, f64)> = None;
+    for (index, motion) in motions.iter().enumerate() {
+        let path: Vec<(f64, f64)> = points
+            .iter()
+            .filter(|point| point.motion == index)
+            .map(|point| (point.position.0, point.position.1))
+            .collect();
+        let (Some(&first), Some(&last)) = (path.first(), path.last()) else {
+            continue;
+        };
+        if let Some(previous) = previous {
+            if (previous.0 - first.0).hypot(previous.1 - first.1) > 1e-9 {
+                polyline(&mut writer, "rapid", &[previous, first])?;
+            }
+        }
+        let class = match motion {
+            _ if motion.is_rapid() => "rapid",
+            Motion::Linear(_) | Motion::Oriented(_) => "linear",
+            Motion::Rotational(_) => "arc",
+            Motion::Bezier(_) | Motion::Spline(_) => "curve",
+            // Events have no path to draw
+            Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => continue,
+        };
+        polyline(&mut writer, class, &path)?;
+        previous = Some(last);
+    }
+
+    writeln!(writer, "</svg>")
+}
+
+// A 2D affine transform, as the six numbers of an SVG `matrix(a b c d e f)`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Affine([f64; 6]);
+
+impl Affine {
+    const IDENTITY: Affine = Affine([1.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+
+    fn apply(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let [a, b, c, d, e, f] = self.0;
+        (a * x + c * y + e, b * x + d * y + f)
+    }
+
+    // This transform applied after `inner`
+    fn after(&self, inner: &Affine) -> Affine {
+        let [a, b, c, d, e, f] = self.0;
+        let [ia, ib, ic, id, ie, if_] = inner.0;
+        Affine([
+            a * ia + c * ib,
+            b * ia + d * ib,
+            a * ic + c * id,
+            b * ic + d * id,
+            a * ie + c * if_ + e,
+            b * ie + d * if_ + f,
+        ])
+    }
+
+    fn determinant(&self) -> f64 {
+        let [a, b, c, d, _, _] = self.0;
+        a * d - b * c
+    }
+
+    // Whether circles stay circles: turning, moving, mirroring and scaling alike along both axes
+    fn keeps_circles(&self) -> bool {
+        let [a, b, c, d, _, _] = self.0;
+        let scale = a.hypot(b).max(c.hypot(d));
+        (a.hypot(b) - c.hypot(d)).abs() < 1e-9 * scale && (a * c + b * d).abs() < 1e-9 * scale * scale
+    }
+}
+
+// The error for a drawing that can't be read
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// Reads numbers, flags and command letters out of path data and transform lists
+struct Scanner<'a> {
+    text: &'a [u8],
+    at: usize,
+}
+
+impl Scanner<'_> {
+    // Skip whitespace and the commas that may separate numbers
+    fn skip(&mut self) {
+        while self.text.get(self.at).is_some_and(|&byte| byte.is_ascii_whitespace() || byte == b',') {
+            self.at += 1;
+        }
+    }
+
+    fn done(&mut self) -> bool {
+        self.skip();
+        self.at >= self.text.len()
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip();
+        self.text.get(self.at).copied()
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip();
+        let start = self.at;
+        let digits = |scanner: &mut Self| {
+            let from = scanner.at;
+            while scanner.text.get(scanner.at).is_some_and(u8::is_ascii_digit) {
+                scanner.at += 1;
+            }
+            scanner.at > from
+        };
+        if matches!(self.text.get(self.at), Some(b'+' | b'-')) {
+            self.at += 1;
+        }
+        let whole = digits(self);
+        let fraction = self.text.get(self.at) == Some(&b'.') && {
+            self.at += 1;
+            digits(self)
+        };
+        if !whole && !fraction {
+            self.at = start;
+            return None;
+        }
+        // An exponent only counts if digits follow it
+        if matches!(self.text.get(self.at), Some(b'e' | b'E')) {
+            let mark = self.at;
+            self.at += 1;
+            if matches!(self.text.get(self.at), Some(b'+' | b'-')) {
+                self.at += 1;
+            }
+            if !digits(self) {
+                self.at = mark;
+            }
+        }
+        std::str::from_utf8(&self.text[start..self.at]).ok()?.parse().ok()
+    }
+
+    // Arc flags are a single 0 or 1, which may run straight into the next number
+    fn flag(&mut self) -> Option<bool> {
+        let flag = match self.peek()? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.at += 1;
+        Some(flag)
+    }
+
+    fn point(&mut self) -> Option<(f64, f64)> {
+        Some((self.number()?, self.number()?))
+    }
+}
+
+// Parse a `transform` attribute, a list of transforms applied right to left
+fn parse_transform(text: &str) -> io::Result<Affine> {
+    let mut transform = Affine::IDENTITY;
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('(').ok_or_else(|| invalid(format!("malformed transform {}", text)))?;
+        let (arguments, after) = after.split_once(')').ok_or_else(|| invalid(format!("malformed transform {}", text)))?;
+        let mut scanner = Scanner { text: arguments.as_bytes(), at: 0 };
+        let mut values = Vec::new();
+        while let Some(value) = scanner.number() {
+            values.push(value);
+        }
+        let tan = |degrees: f64| degrees.to_radians().tan();
+        let next = match (name.trim(), values.as_slice()) {
+            ("matrix", &[a, b, c, d, e, f]) => Affine([a, b, c, d, e, f]),
+            ("translate", &[x]) => Affine([1.0, 0.0, 0.0, 1.0, x, 0.0]),
+            ("translate", &[x, y]) => Affine([1.0, 0.0, 0.0, 1.0, x, y]),
+            ("scale", &[s]) => Affine([s, 0.0, 0.0, s, 0.0, 0.0]),
+            ("scale", &[x, y]) => Affine([x, 0.0, 0.0, y, 0.0, 0.0]),
+            ("rotate", &[angle] | &[angle, _, _]) => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let (x, y) = match values[1..] {
+                    [x, y] => (x, y),
+                    _ => (0.0, 0.0),
+                };
+                // Turning about (x, y) is turning about the origin, moved there
+                Affine([cos, sin, -sin, cos, x - cos * x + sin * y, y - sin * x - cos * y])
+            }
+            ("skewX", &[angle]) => Affine([1.0, 0.0, tan(angle), 1.0, 0.0, 0.0]),
+            ("skewY", &[angle]) => Affine([1.0, tan(angle), 0.0, 1.0, 0.0, 0.0]),
+            _ => return Err(invalid(format!("malformed transform {}", text))),
+        };
+        transform = transform.after(&next);
+        rest = after.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    Ok(transform)
+}
+
+// A length such as `210mm`, in millimeters; plain numbers are CSS pixels, 96 to the inch
+fn length_mm(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_ascii_alphabetic() || c == '%').unwrap_or(text.len());
+    let value: f64 = text[..split].parse().ok()?;
+    let per_unit = match &text[split..] {
+        "" | "px" => 25.4 / 96.0,
+        "mm" => 1.0,
+        "cm" => 10.0,
+        "in" => 25.4,
+        "pt" => 25.4 / 72.0,
+        "pc" => 25.4 / 6.0,
+        _ => return None,
+    };
+    Some(value * per_unit)
+}
+
+// The name and attributes of a tag, from the text between its angle brackets
+fn tag_parts(text: &str) -> (&str, Vec<(&str, &str)>) {
+    let text = text.trim_end_matches('/');
+    let split = text.find(|c: char| c.is_whitespace()).unwrap_or(text.len());
+    let name = &text[..split];
+    let mut attributes = Vec::new();
+    let mut rest = &text[split..];
+    while let Some(equals) = rest.find('=') {
+        let key = rest[..equals].trim();
+        let value = rest[equals + 1..].trim_start();
+        let Some(quote) = value.chars().next().filter(|&c| c == '"' || c == '\'') else {
+            break;
+        };
+        let Some(end) = value[1..].find(quote) else {
+            break;
+        };
+        attributes.push((key, &value[1..end + 1]));
+        rest = &value[end + 2..];
+    }
+    // Namespaced names like `svg:path` are the same elements
+    (name.rsplit(':').next().unwrap_or(name), attributes)
+}
+
+// How the root element's size and view box place the drawing, in millimeters with Y up
+fn page_transform(attributes: &[(&str, &str)]) -> Affine {
+    let attribute = |key: &str| attributes.iter().find(|(name, _)| *name == key).map(|(_, value)| *value);
+    let view_box: Option<Vec<f64>> = attribute("viewBox").map(|text| {
+        let mut scanner = Scanner { text: text.as_bytes(), at: 0 };
+        std::iter::from_fn(|| scanner.number()).collect()
+    });
+    let width = attribute("width").and_then(length_mm);
+    let height = attribute("height").and_then(length_mm);
+    match view_box.as_deref() {
+        Some(&[min_x, min_y, view_width, view_height]) if view_width > 0.0 && view_height > 0.0 => {
+            // A size without units, or none at all, is in pixels
+            let sx = width.map_or(25.4 / 96.0, |width| width / view_width);
+            let sy = height.map_or(sx, |height| height / view_height);
+            Affine([sx, 0.0, 0.0, -sy, -min_x * sx, (min_y + view_height) * sy])
+        }
+        _ => {
+            let pixel = 25.4 / 96.0;
+            Affine([pixel, 0.0, 0.0, -pixel, 0.0, height.unwrap_or(0.0)])
+        }
+    }
+}
+
+// Elements whose contents are only drawn where something else refers to them
+const UNDRAWN: [&str; 7] = ["defs", "clipPath", "mask", "marker", "pattern", "symbol", "metadata"];
+
+// The strokes of a path's `d` attribute, with `transform` taking them to millimeters
+fn parse_path(data: &str, transform: &Affine) -> io::Result<Vec<Stroke>> {
+    let malformed = || invalid(format!("malformed path data {}", data));
+    let mut scanner = Scanner { text: data.as_bytes(), at: 0 };
+    let mut strokes = Vec::new();
+    let mut stroke: Option<Stroke> = None;
+    let (mut current, mut subpath) = ((0.0, 0.0), (0.0, 0.0));
+    // Control points the smooth curve commands reflect
+    let mut cubic: Option<(f64, f64)> = None;
+    let mut quadratic: Option<(f64, f64)> = None;
+    let mut command = None;
+
+    while !scanner.done() {
+        if let Some(letter) = scanner.peek().filter(u8::is_ascii_alphabetic) {
+            scanner.at += 1;
+            command = Some(letter);
+        }
+        let letter = command.ok_or_else(malformed)?;
+        let relative = letter.is_ascii_lowercase();
+        let offset = |(x, y): (f64, f64)| if relative { (current.0 + x, current.1 + y) } else { (x, y) };
+        let mut segment = None;
+        let (mut next_cubic, mut next_quadratic) = (None, None);
+        match letter.to_ascii_uppercase() {
+            b'M' => {
+                let point = offset(scanner.point().ok_or_else(malformed)?);
+                strokes.extend(stroke.take());
+                stroke = Some(Stroke { start: transform.apply(point), segments: Vec::new() });
+                (current, subpath) = (point, point);
+                // Further pairs after a move are lines
+                command = Some(if relative { b'l' } else { b'L' });
+            }
+            b'Z' => {
+                if current != subpath {
+                    segment = Some(Segment::Line(transform.apply(subpath)));
+                }
+                current = subpath;
+                command = None;
+            }
+            b'L' => {
+                current = offset(scanner.point().ok_or_else(malformed)?);
+                segment = Some(Segment::Line(transform.apply(current)));
+            }
+            b'H' => {
+                let x = scanner.number().ok_or_else(malformed)?;
+                current.0 = if relative { current.0 + x } else { x };
+                segment = Some(Segment::Line(transform.apply(current)));
+            }
+            b'V' => {
+                let y = scanner.number().ok_or_else(malformed)?;
+                current.1 = if relative { current.1 + y } else { y };
+                segment = Some(Segment::Line(transform.apply(current)));
+            }
+            b'C' | b'S' => {
+                let first = match letter.to_ascii_uppercase() {
+                    b'C' => offset(scanner.point().ok_or_else(malformed)?),
+                    // The first control point mirrors the last curve's second one
+                    _ => cubic.map_or(current, |(x, y)| (2.0 * current.0 - x, 2.0 * current.1 - y)),
+                };
+                let second = offset(scanner.point().ok_or_else(malformed)?);
+                let end = offset(scanner.point().ok_or_else(malformed)?);
+                segment = Some(Segment::Bezier(transform.apply(first), transform.apply(second), transform.apply(end)));
+                next_cubic = Some(second);
+                current = end;
+            }
+            b'Q' | b'T' => {
+                let control = match letter.to_ascii_uppercase() {
+                    b'Q' => offset(scanner.point().ok_or_else(malformed)?),
+                    _ => quadratic.map_or(current, |(x, y)| (2.0 * current.0 - x, 2.0 * current.1 - y)),
+                };
+                let end = offset(scanner.point().ok_or_else(malformed)?);
+                // A quadratic curve is the cubic with control points two thirds of the way to its one
+                let toward = |from: (f64, f64)| {
+                    (from.0 + 2.0 / 3.0 * (control.0 - from.0), from.1 + 2.0 / 3.0 * (control.1 - from.1))
+                };
+                segment = Some(Segment::Bezier(
+                    transform.apply(toward(current)),
+                    transform.apply(toward(end)),
+                    transform.apply(end),
+                ));
+                next_quadratic = Some(control);
+                current = end;
+            }
+            b'A' => {
+                let radii = scanner.point().ok_or_else(malformed)?;
+                let rotation = scanner.number().ok_or_else(malformed)?;
+                let large = scanner.flag().ok_or_else(malformed)?;
+                let sweep = scanner.flag().ok_or_else(malformed)?;
+                let end = offset(scanner.point().ok_or_else(malformed)?);
+                let pieces = arc_segments(current, radii, rotation, large, sweep, end, transform);
+                stroke.get_or_insert_with(|| Stroke { start: transform.apply(current), segments: Vec::new() });
+                if let Some(stroke) = &mut stroke {
+                    stroke.segments.extend(pieces);
+                }
+                current = end;
+            }
+            _ => return Err(malformed()),
+        }
+        if let Some(segment) = segment {
+            stroke
+                .get_or_insert_with(|| Stroke { start: transform.apply(subpath), segments: Vec::new() })
+                .segments
+                .push(segment);
+        }
+        // Drawing on after a close starts a new stroke where the last one started
+        if letter.eq_ignore_ascii_case(&b'Z') {
+            strokes.extend(stroke.take());
+            if scanner.peek().is_some_and(|byte| !byte.is_ascii_alphabetic()) {
+                return Err(malformed());
+            }
+        }
+        (cubic, quadratic) = (next_cubic, next_quadratic);
+    }
+    strokes.extend(stroke);
+    Ok(strokes)
+}
+
+// The segments of an SVG elliptical arc: one circular arc if it stays a circle, or else Bézier curves
+fn arc_segments(
+    from: (f64, f64),
+    (rx, ry): (f64, f64),
+    rotation: f64,
+    large: bool,
+    sweep: bool,
+    to: (f64, f64),
+    transform: &Affine,
+) -> Vec<Segment> {
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if from == to {
+        return Vec::new();
+    }
+    if rx < 1e-12 || ry < 1e-12 {
+        return vec![Segment::Line(transform.apply(to))];
+    }
+    // The center, following the SVG specification's endpoint to center conversion
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (dx, dy) = ((from.0 - to.0) / 2.0, (from.1 - to.1) / 2.0);
+    let (x1, y1) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+    // Radii too small to reach are scaled up until they just do
+    let reach = (x1 / rx).powi(2) + (y1 / ry).powi(2);
+    if reach > 1.0 {
+        (rx, ry) = (rx * reach.sqrt(), ry * reach.sqrt());
+    }
+    let numerator = (rx * ry).powi(2) - (rx * y1).powi(2) - (ry * x1).powi(2);
+    let denominator = (rx * y1).powi(2) + (ry * x1).powi(2);
+    let sign = if large == sweep { -1.0 } else { 1.0 };
+    let factor = sign * (numerator / denominator).max(0.0).sqrt();
+    let (cx1, cy1) = (factor * rx * y1 / ry, -factor * ry * x1 / rx);
+    let center = (
+        cos * cx1 - sin * cy1 + (from.0 + to.0) / 2.0,
+        sin * cx1 + cos * cy1 + (from.1 + to.1) / 2.0,
+    );
+
+    if (rx - ry).abs() < 1e-9 * rx && transform.keeps_circles() {
+        // Mirroring the drawing, as turning it the right way up does, turns the arc the other way
+        let clockwise = sweep == (transform.determinant() < 0.0);
+        return vec![Segment::Arc {
+            center: transform.apply(center),
+            clockwise,
+            end: transform.apply(to),
+        }];
+    }
+
+    // Stretched arcs become a Bézier curve for every quarter turn or less of the ellipse
+    let angle = |(ux, uy): (f64, f64)| uy.atan2(ux);
+    let start = angle(((x1 - cx1) / rx, (y1 - cy1) / ry));
+    let mut turn = angle(((-x1 - cx1) / rx, (-y1 - cy1) / ry)) - start;
+    if sweep && turn < 0.0 {
+        turn += 2.0 * PI;
+    } else if !sweep && turn > 0.0 {
+        turn -= 2.0 * PI;
+    }
+    let pieces = (turn.abs() / (PI / 2.0)).ceil().max(1.0) as usize;
+    let step = turn / pieces as f64;
+    let point = |t: f64| {
+        let (x, y) = (rx * t.cos(), ry * t.sin());
+        (center.0 + cos * x - sin * y, center.1 + sin * x + cos * y)
+    };
+    let tangent = |t: f64| {
+        let (x, y) = (-rx * t.sin(), ry * t.cos());
+        (cos * x - sin * y, sin * x + cos * y)
+    };
+    let handle = 4.0 / 3.0 * (step / 4.0).tan();
+    (0..pieces)
+        .map(|piece| {
+            let (t0, t1) = (start + piece as f64 * step, start + (piece + 1) as f64 * step);
+            let (p0, d0, d1) = (point(t0), tangent(t0), tangent(t1));
+            let p1 = if piece + 1 == pieces { to } else { point(t1) };
+            Segment::Bezier(
+                transform.apply((p0.0 + handle * d0.0, p0.1 + handle * d0.1)),
+                transform.apply((p1.0 - handle * d1.0, p1.1 - handle * d1.1)),
+                transform.apply(p1),
+            )
+        })
+        .collect()
+}
+
+/// Function to read the paths of an SVG drawing
+///
+/// Every `path` element becomes one or more strokes, one for each subpath,
+/// in the order they appear; other shapes, text, and anything inside `defs`
+/// and the like are skipped. Lines stay lines and cubic and quadratic curves
+/// become Bézier curves. Elliptical arcs that are circles become circular
+/// arcs, and stretched ones Bézier curves that follow them closely. The
+/// `transform`s of paths and the groups around them are applied.
+///
+/// Coordinates come out in millimeters, worked out from the drawing's
+/// `width`, `height` and `viewBox` (drawings without units are in pixels, 96
+/// to the inch). Y is flipped, so the bottom left of the page is the origin and
+/// the drawing is the right way up.
+///
+/// # Errors
+///
+/// Returns any error from reading, or `InvalidData` if the document has no
+/// `svg` element or has malformed path data or transforms.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::drawing::Segment;
+/// use project_2::svg::read_svg;
+///
+/// let svg = r#"<svg width="100mm" height="50mm" viewBox="0 0 100 50">
+///   <path d="M 10 40 H 30 A 10 10 0 0 1 50 40"/>
+/// </svg>"#;
+/// let drawing = read_svg(svg.as_bytes()).unwrap();
+/// let stroke = &drawing.strokes[0];
+/// assert_eq!(stroke.start, (10.0, 10.0));
+/// assert_eq!(stroke.segments[0], Segment::Line((30.0, 10.0)));
+/// // A half circle over the top, clockwise once the page is the right way up
+/// assert_eq!(stroke.segments[1], Segment::Arc { center: (40.0, 10.0), clockwise: true, end: (50.0, 10.0) });
+/// ```
+pub fn read_svg<R: Read>(mut reader: R) -> io::Result<Drawing> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut drawing = Drawing::default();
+    // The transform of each open element, and whether what's inside it is drawn
+    let mut open: Vec<(Affine, bool)> = Vec::new();
+    let mut found = false;
+    let mut rest = text.as_str();
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        // Comments, declarations and character data hold no elements
+        let skip = [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>"), ("<!", ">")]
+            .into_iter()
+            .find(|(opening, _)| rest.starts_with(opening));
+        if let Some((_, closing)) = skip {
+            rest = rest.find(closing).map_or("", |end| &rest[end + closing.len()..]);
+            continue;
+        }
+        // A tag ends at the first > outside quotes
+        let mut quote = None;
+        let end = rest.char_indices().skip(1).find(|&(_, c)| match quote {
+            Some(q) if c == q => {
+                quote = None;
+                false
+            }
+            Some(_) => false,
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                false
+            }
+            None => c == '>',
+        });
+        let Some((end, _)) = end else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        if tag.starts_with('/') {
+            open.pop();
+            continue;
+        }
+
+        let (name, attributes) = tag_parts(tag);
+        let attribute = |key: &str| attributes.iter().find(|(name, _)| *name == key).map(|(_, value)| *value);
+        let (outer, drawn) = match open.last() {
+            Some(&(transform, drawn)) => (transform, drawn),
+            None if name == "svg" => {
+                found = true;
+                (page_transform(&attributes), true)
+            }
+            None => (Affine::IDENTITY, true),
+        };
+        let transform = match attribute("transform") {
+            Some(text) => outer.after(&parse_transform(text)?),
+            None => outer,
+        };
+        let drawn = drawn && !UNDRAWN.contains(&name);
+        if name == "path" && drawn {
+            if let Some(data) = attribute("d") {
+                drawing.strokes.extend(parse_path(data, &transform)?);
+            }
+        }
+        if !tag.ends_with('/') {
+            open.push((transform, drawn));
+        }
+    }
+    if !found {
+        return Err(invalid("not an SVG drawing"));
+    }
+    Ok(drawing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that disconnected motions are joined by a travel move.
+    #[test]
+    fn test_travel_moves() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+            Motion::new_linear((10.0, 10.0, 0.0), (0.0, 10.0, 0.0)),
+        ];
+        let mut output = Vec::new();
+        write_svg(&mut output, &motions, &InterpolationOptions::default()).unwrap();
+        let svg = String::from_utf8(output).unwrap();
+        assert!(svg.contains(r#"<polyline class="rapid" points="10,0 10,-10"/>"#));
+        assert_eq!(svg.matches(r#"class="linear""#).count(), 2);
+        // The view covers the path plus a margin, with Y flipped
+        assert!(svg.contains(r#"viewBox="-1 -11 12 12""#));
+    }
+
+    /// Test relative and implicit commands, closing, and the transforms of groups.
+    #[test]
+    fn test_read_paths() {
+        let svg = r#"<?xml version="1.0"?>
+<!-- made by hand -->
+<svg xmlns="http://www.w3.org/2000/svg" width="20mm" height="20mm" viewBox="0 0 20 20">
+  <defs><path d="M 0 0 L 5 5"/></defs>
+  <g transform="translate(10, 0)">
+    <svg:path d="m0 10 5 0 v-5z M0 0"/>
+  </g>
+  <path d='M1,1Q 2,2 3,1t2,0' transform="scale(2)"/>
+</svg>"#;
+        let drawing = read_svg(svg.as_bytes()).unwrap();
+        assert_eq!(drawing.strokes.len(), 3);
+        assert_eq!(
+            drawing.strokes[0],
+            Stroke {
+                start: (10.0, 10.0),
+                segments: vec![
+                    Segment::Line((15.0, 10.0)),
+                    Segment::Line((15.0, 15.0)),
+                    Segment::Line((10.0, 10.0)),
+                ],
+            }
+        );
+        // A lone move draws nothing
+        assert!(drawing.strokes[1].segments.is_empty());
+        // The smooth quadratic mirrors the first one's control point, and both are scaled
+        let Segment::Bezier(first, second, end) = drawing.strokes[2].segments[1] else {
+            panic!("expected a curve");
+        };
+        let close = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9;
+        assert!(close(first, (6.0 + 4.0 / 3.0, 20.0 - 2.0 + 4.0 / 3.0)));
+        assert!(close(second, (10.0 - 4.0 / 3.0, 20.0 - 2.0 + 4.0 / 3.0)));
+        assert_eq!(end, (10.0, 18.0));
+    }
+
+    /// Test that stretched arcs become curves ending where the arc does, and that bad data is an error.
+    #[test]
+    fn test_read_arcs_and_errors() {
+        // Pixels without a view box, and flags written straight into the numbers
+        let svg = r#"<svg height="96"><path d="M0 48a48 24 0 1196 0"/></svg>"#;
+        let drawing = read_svg(svg.as_bytes()).unwrap();
+        let segments = &drawing.strokes[0].segments;
+        assert_eq!(segments.len(), 2);
+        let Segment::Bezier(_, _, middle) = segments[0] else { panic!("expected a curve") };
+        assert!((middle.0 - 12.7).abs() < 1e-9 && (middle.1 - (12.7 + 6.35)).abs() < 1e-9);
+        assert!(matches!(segments[1], Segment::Bezier(_, _, end) if end == (25.4, 12.7)));
+
+        assert!(read_svg("<html></html>".as_bytes()).is_err());
+        assert!(read_svg(r#"<svg><path d="M 0 0 L 1"/></svg>"#.as_bytes()).is_err());
+        assert!(read_svg(r#"<svg><path d="10 10"/></svg>"#.as_bytes()).is_err());
+        assert!(read_svg(r#"<svg><g transform="spin(3)"/></svg>"#.as_bytes()).is_err());
+    }
+}
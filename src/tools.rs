@@ -0,0 +1,120 @@
+//! Tool tables
+//!
+//! A tool table lists the cutters a job uses, by number, with the length and
+//! diameter of each. It is written in TOML:
+//!
+//! ```toml
+//! [tool.1]
+//! length = 42.5   # mm from the spindle gauge line to the tip
+//! diameter = 6.0
+//!
+//! [tool.2]
+//! length = 55.0
+//! diameter = 3.175
+//! ```
+//!
+//! `TOOL n` in a program makes tool `n` active: its length is added to every
+//! programmed Z, so programs can be written for the tool tip instead of the
+//! spindle, and its diameter is kept for cutter compensation. `M6 Tn` does the
+//! same in either kind of program.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::ConfigError;
+
+/// One cutter in the tool table, in millimeters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Tool {
+    /// Length offset added to programmed Z while the tool is active
+    #[serde(default)]
+    pub length: f64,
+    /// Cutter diameter, used for cutter compensation
+    #[serde(default)]
+    pub diameter: f64,
+}
+
+/// The cutters available to a program, by tool number
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolTable {
+    /// Tools by number
+    pub tools: BTreeMap<u32, Tool>,
+}
+
+// The table as it is written in TOML, where keys are always strings
+#[derive(Deserialize)]
+struct ToolFile {
+    #[serde(default)]
+    tool: BTreeMap<String, Tool>,
+}
+
+impl ToolTable {
+    /// Function to read a tool table from TOML text
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Toml` if the text isn't a valid tool table, and
+    /// `ConfigError::InvalidToolNumber` for tool numbers that aren't whole numbers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::tools::ToolTable;
+    ///
+    /// let table = ToolTable::from_toml("[tool.3]\nlength = 40\ndiameter = 6").unwrap();
+    /// assert_eq!(table.get(3).unwrap().diameter, 6.0);
+    /// ```
+    pub fn from_toml(text: &str) -> Result<ToolTable, ConfigError> {
+        let file: ToolFile = toml::from_str(text)?;
+        let mut tools = BTreeMap::new();
+        for (number, tool) in file.tool {
+            let number = number.parse().map_err(|_| ConfigError::InvalidToolNumber(number.clone()))?;
+            tools.insert(number, tool);
+        }
+        Ok(ToolTable { tools })
+    }
+
+    /// Function to read a tool table from a TOML file
+    ///
+    /// # Errors
+    ///
+    /// Returns `ConfigError::Io` if the file can't be read, and the errors of
+    /// [`ToolTable::from_toml`] otherwise.
+    pub fn load(path: &Path) -> Result<ToolTable, ConfigError> {
+        ToolTable::from_toml(&fs::read_to_string(path)?)
+    }
+
+    /// The tool with the given number, if the table has it
+    pub fn get(&self, number: u32) -> Option<&Tool> {
+        self.tools.get(&number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test reading a table, with defaults for missing fields.
+    #[test]
+    fn test_from_toml() {
+        let table = ToolTable::from_toml("[tool.1]\nlength = 42.5\n\n[tool.2]\ndiameter = 3.175\n").unwrap();
+        assert_eq!(
+            table.get(1),
+            Some(&Tool {
+                length: 42.5,
+                diameter: 0.0
+            })
+        );
+        assert_eq!(table.get(2).unwrap().diameter, 3.175);
+        assert_eq!(table.get(3), None);
+
+        assert!(matches!(
+            ToolTable::from_toml("[tool.first]\nlength = 1"),
+            Err(ConfigError::InvalidToolNumber(_))
+        ));
+        assert!(matches!(ToolTable::from_toml("[tool.1]\nlength = \"long\""), Err(ConfigError::Toml(_))));
+    }
+}
@@ -0,0 +1,779 @@
+//! Time-parameterized trajectories
+//!
+//! Turns the geometric positions of each motion into timed samples, using the
+//! feed rate of the motion and the distance travelled between samples. That
+//! feed rate is the one the parser's [`MachineState`](crate::machine::MachineState)
+//! had in effect for the motion, including any override on its own line.
+
+use std::iter::Enumerate;
+use std::slice;
+use std::thread;
+
+use serde::Serialize;
+
+use crate::error::CmmdError;
+use crate::interpolate::{
+    bezier_points, helical_points, linear_motion_points, oriented_points, spline_points, ArcPoints, BezierPoints,
+    InterpolationOptions, LinearPoints, SplinePoints,
+};
+use crate::motion::{Motion, Rotary};
+use crate::observer::{observe_motion, Observer};
+use crate::output::Point;
+
+/// A position along with the time the tool reaches it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimedPosition {
+    /// Seconds since the start of the program
+    pub time: f64,
+    /// The (x, y, z) position
+    pub position: (f64, f64, f64),
+    /// Index of the motion the position belongs to
+    pub motion: usize,
+    /// Spindle speed or laser power of that motion, if one was programmed
+    pub power: Option<f64>,
+    /// Rotary axis angles at the position, for motions that turn them
+    pub rotary: Option<Rotary>,
+    /// Extruder (E axis) position at the position, for motions that carry one
+    pub extrusion: Option<f64>,
+}
+
+/// Function to calculate the positions of a single motion
+///
+/// Linear motions are split with [`linear_positions`](crate::interpolate::linear_positions).
+/// Arcs are split with [`helical_motion_calculate_with_options`](crate::interpolate::helical_motion_calculate_with_options); arcs without their own Z range
+/// stay at `current_z`, the height the tool is at when the arc starts.
+///
+/// # Arguments
+///
+/// * `motion` - The motion to split into positions
+/// * `current_z` - The height of the tool before the motion
+/// * `options` - Interpolation options
+pub fn motion_positions(motion: &Motion, current_z: f64, options: &InterpolationOptions) -> Vec<(f64, f64, f64)> {
+    motion_points(motion, current_z, options).collect()
+}
+
+/// Iterator over the positions of a single motion, made by [`motion_points`]
+#[derive(Debug, Clone)]
+pub enum MotionPoints {
+    /// Positions of a linear motion
+    Linear(LinearPoints),
+    /// Positions of an arc or helix
+    Arc(ArcPoints),
+    /// Positions of a Bézier curve
+    Bezier(BezierPoints),
+    /// Positions of a NURBS curve
+    Spline(SplinePoints),
+    /// No positions at all, for events such as tool changes that don't move the tool
+    Event,
+}
+
+impl Iterator for MotionPoints {
+    type Item = (f64, f64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            MotionPoints::Linear(points) => points.next(),
+            MotionPoints::Arc(points) => points.next(),
+            MotionPoints::Bezier(points) => points.next(),
+            MotionPoints::Spline(points) => points.next(),
+            MotionPoints::Event => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            MotionPoints::Linear(points) => points.size_hint(),
+            MotionPoints::Arc(points) => points.size_hint(),
+            MotionPoints::Bezier(points) => points.size_hint(),
+            MotionPoints::Spline(points) => points.size_hint(),
+            MotionPoints::Event => (0, Some(0)),
+        }
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        match self {
+            MotionPoints::Linear(points) => points.nth(n),
+            MotionPoints::Arc(points) => points.nth(n),
+            MotionPoints::Bezier(points) => points.nth(n),
+            MotionPoints::Spline(points) => points.nth(n),
+            MotionPoints::Event => None,
+        }
+    }
+
+    fn last(self) -> Option<Self::Item> {
+        match self {
+            MotionPoints::Linear(points) => points.last(),
+            MotionPoints::Arc(points) => points.last(),
+            MotionPoints::Bezier(points) => points.last(),
+            MotionPoints::Spline(points) => points.last(),
+            MotionPoints::Event => None,
+        }
+    }
+}
+
+impl ExactSizeIterator for MotionPoints {}
+
+/// Function to lazily calculate the positions of a single motion
+///
+/// Yields the same positions as [`motion_positions`], one at a time.
+pub fn motion_points(motion: &Motion, current_z: f64, options: &InterpolationOptions) -> MotionPoints {
+    match motion {
+        Motion::Linear(linear_motion) => MotionPoints::Linear(linear_motion_points(linear_motion, options)),
+        Motion::Rotational(rotational_motion) => {
+            let mut rotational_motion = rotational_motion.clone();
+            rotational_motion.z.get_or_insert((current_z, current_z));
+            MotionPoints::Arc(helical_points(&rotational_motion, options))
+        }
+        Motion::Bezier(bezier_motion) => MotionPoints::Bezier(bezier_points(bezier_motion, options)),
+        Motion::Spline(spline_motion) => MotionPoints::Spline(spline_points(spline_motion, options)),
+        Motion::Oriented(oriented_motion) => MotionPoints::Linear(oriented_points(oriented_motion, options)),
+        Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => MotionPoints::Event,
+    }
+}
+
+/// Function to find the rotary axis angles at one of a motion's positions
+///
+/// Linear positions are evenly spaced, so the angles are spread evenly over
+/// them too. Only linear motions turn the rotary axes; motions that tilt the
+/// tool give the A and C angles of its direction, see
+/// [`Rotary::from_tool_vector`].
+///
+/// # Arguments
+///
+/// * `motion` - The motion
+/// * `index` - Which of its positions, counted from zero
+/// * `count` - How many positions the motion has
+pub fn rotary_at(motion: &Motion, index: usize, count: usize) -> Option<Rotary> {
+    let fraction = if count > 1 { index as f64 / (count - 1) as f64 } else { 1.0 };
+    match motion {
+        Motion::Linear(linear_motion) => linear_motion.rotary_at(fraction),
+        Motion::Oriented(oriented_motion) => Some(Rotary::from_tool_vector(oriented_motion.orientation_at(fraction))),
+        _ => None,
+    }
+}
+
+/// Function to find the extruder position at one of a motion's positions
+///
+/// Like the rotary angles in [`rotary_at`], the extruder moves evenly over a
+/// linear motion's positions. Other motions don't move it.
+///
+/// # Arguments
+///
+/// * `motion` - The motion
+/// * `index` - Which of its positions, counted from zero
+/// * `count` - How many positions the motion has
+pub fn extrusion_at(motion: &Motion, index: usize, count: usize) -> Option<f64> {
+    let fraction = if count > 1 { index as f64 / (count - 1) as f64 } else { 1.0 };
+    match motion {
+        Motion::Linear(linear_motion) => linear_motion.extrusion_at(fraction),
+        _ => None,
+    }
+}
+
+/// A program's path, walked one position at a time
+///
+/// Interpolating a long program up front can take a lot of memory; a
+/// trajectory only works out each position as it is asked for.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::trajectory::Trajectory;
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (2.0, 0.0, 0.0)),
+///     Motion::new_linear((2.0, 0.0, 0.0), (2.0, 2.0, 0.0)),
+/// ];
+/// let options = Default::default();
+/// let trajectory = Trajectory::new(&motions, &options);
+/// let last = trajectory.points().last().unwrap();
+/// assert_eq!((last.motion, last.position), (1, (2.0, 2.0, 0.0)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Trajectory<'a> {
+    motions: &'a [Motion],
+    options: &'a InterpolationOptions,
+}
+
+impl<'a> Trajectory<'a> {
+    /// Constructor for the trajectory of a program
+    pub fn new(motions: &'a [Motion], options: &'a InterpolationOptions) -> Self {
+        Trajectory { motions, options }
+    }
+
+    /// Function to iterate over the untimed positions of every motion
+    ///
+    /// Arcs without their own Z range are placed at the height the previous
+    /// motion ended at, as in [`geometric_points`](crate::output::geometric_points).
+    pub fn points(&self) -> Points<'a> {
+        Points {
+            motions: self.motions.iter().enumerate(),
+            options: self.options,
+            current: None,
+            current_z: 0.0,
+        }
+    }
+
+    /// Function to interpolate every motion, telling an observer about each one and its untimed positions
+    ///
+    /// The positions are the ones [`Trajectory::points`] gives, and none of
+    /// them are kept.
+    pub fn observe<O: Observer + ?Sized>(&self, observer: &mut O) {
+        let mut current_z = 0.0;
+        for (index, motion) in self.motions.iter().enumerate() {
+            observe_motion(index, motion, &mut current_z, self.options, observer);
+        }
+    }
+
+    /// Function to interpolate and time every motion, telling an observer about each one and its positions
+    ///
+    /// The positions are timed as in [`timed_positions`], and none of them are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns `CmmdError::MissingFeedRate` for the first motion other than a
+    /// rapid move that has no positive feed rate, once the observer has been
+    /// told about it and about every motion before it.
+    pub fn observe_timed<O: Observer + ?Sized>(&self, observer: &mut O) -> Result<(), CmmdError> {
+        let options = self.options;
+        // The sample before, which the next one is timed from
+        let mut previous: Option<TimedPosition> = None;
+        // Time spent paused since then
+        let mut paused = 0.0;
+
+        for (index, motion) in self.motions.iter().enumerate() {
+            // Feed rates are programmed per minute, samples are timed in seconds
+            let feed = match motion.feed() {
+                // Events have no positions to time, so any rate will do
+                _ if motion.is_rapid() || motion.is_event() => options.rapid_rate / 60.0,
+                Some(feed) if feed > 0.0 => feed / 60.0,
+                _ => {
+                    let error = CmmdError::MissingFeedRate { motion: index };
+                    observer.on_error(&error);
+                    return Err(error);
+                }
+            };
+
+            observer.on_motion_start(index, motion);
+            if let Motion::Pause(_) = motion {
+                paused += options.pause_time;
+            }
+            let current_z = previous.map_or(0.0, |sample| sample.position.2);
+            let positions = motion_points(motion, current_z, options);
+            let count = positions.len();
+            for (step, position) in positions.enumerate() {
+                let rotary = rotary_at(motion, step, count);
+                let extrusion = extrusion_at(motion, step, count);
+                // Time advances by the distance travelled from the previous sample, or the angle
+                // turned when only the rotary axes move (feed rates are then in degrees), or the
+                // filament fed when only the extruder moves, as it does to retract
+                let time = match previous {
+                    Some(previous) => {
+                        let travelled = match (distance(previous.position, position), previous.rotary, rotary) {
+                            (travelled, Some(from), Some(to)) if travelled < 1e-12 && from != to => {
+                                from.largest_change(&to)
+                            }
+                            (travelled, _, _) if travelled < 1e-12 => match (previous.extrusion, extrusion) {
+                                (Some(from), Some(to)) => (to - from).abs(),
+                                _ => travelled,
+                            },
+                            (travelled, _, _) => travelled,
+                        };
+                        previous.time + paused + travelled / feed
+                    }
+                    None => paused,
+                };
+                paused = 0.0;
+                let sample = TimedPosition {
+                    time,
+                    position,
+                    motion: index,
+                    power: motion.power(),
+                    rotary,
+                    extrusion,
+                };
+                observer.on_point(&Point::from(sample));
+                previous = Some(sample);
+            }
+            observer.on_motion_end(index, motion);
+        }
+        Ok(())
+    }
+
+    /// Function to calculate the untimed positions of every motion on several threads
+    ///
+    /// The motions are split into one run per thread, interpolated at the same
+    /// time and stitched back together in program order, so the result is the
+    /// same as collecting [`Trajectory::points`]. Only the height each motion
+    /// starts at has to be worked out in order, which doesn't need any of the
+    /// positions in between.
+    ///
+    /// # Arguments
+    ///
+    /// * `threads` - How many threads to split the work over; 0 is taken as 1
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::motion::Motion;
+    /// use project_2::trajectory::Trajectory;
+    ///
+    /// let motions = vec![
+    ///     Motion::new_linear((0.0, 0.0, 0.0), (5.0, 0.0, 0.0)),
+    ///     Motion::new_rotational((0.0, 0.0), 5.0, 0.0, 90.0),
+    /// ];
+    /// let options = Default::default();
+    /// let trajectory = Trajectory::new(&motions, &options);
+    /// assert_eq!(trajectory.par_points(2), trajectory.points().collect::<Vec<_>>());
+    /// ```
+    pub fn par_points(&self, threads: usize) -> Vec<Point> {
+        let options = self.options;
+
+        // Height of the tool before each motion, for arcs without their own
+        let mut current_z = 0.0;
+        let heights: Vec<f64> = self
+            .motions
+            .iter()
+            .map(|motion| {
+                let start = current_z;
+                if let Some(end) = motion_points(motion, current_z, options).last() {
+                    current_z = end.2;
+                }
+                start
+            })
+            .collect();
+
+        // Interpolate each run on its own thread, then join them up in order
+        let run_length = self.motions.len().div_ceil(threads.max(1)).max(1);
+        let runs: Vec<Vec<Point>> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .motions
+                .chunks(run_length)
+                .zip(heights.chunks(run_length))
+                .enumerate()
+                .map(|(run, (motions, heights))| {
+                    scope.spawn(move || {
+                        let mut points = Vec::new();
+                        for (offset, (motion, &z)) in motions.iter().zip(heights).enumerate() {
+                            let index = run * run_length + offset;
+                            let positions = motion_points(motion, z, options);
+                            let count = positions.len();
+                            points.extend(positions.enumerate().map(|(step, position)| Point {
+                                motion: index,
+                                position,
+                                time: None,
+                                power: motion.power(),
+                                rotary: rotary_at(motion, step, count),
+                                extrusion: extrusion_at(motion, step, count),
+                                dynamics: None,
+                            }));
+                        }
+                        points
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("interpolation thread panicked"))
+                .collect()
+        });
+        runs.concat()
+    }
+}
+
+/// Iterator over the positions of a program, made by [`Trajectory::points`]
+#[derive(Debug, Clone)]
+pub struct Points<'a> {
+    motions: Enumerate<slice::Iter<'a, Motion>>,
+    options: &'a InterpolationOptions,
+    // The motion being walked, with its index and how many positions it has
+    current: Option<(usize, &'a Motion, MotionPoints, usize)>,
+    // Height of the last position, where flat arcs are placed
+    current_z: f64,
+}
+
+impl Iterator for Points<'_> {
+    type Item = Point;
+
+    fn next(&mut self) -> Option<Point> {
+        loop {
+            // Move on to the next motion once this one runs out
+            if let Some((index, motion, positions, count)) = &mut self.current {
+                if let Some(position) = positions.next() {
+                    self.current_z = position.2;
+                    return Some(Point {
+                        motion: *index,
+                        position,
+                        time: None,
+                        power: motion.power(),
+                        rotary: rotary_at(motion, *count - positions.len() - 1, *count),
+                        extrusion: extrusion_at(motion, *count - positions.len() - 1, *count),
+                        dynamics: None,
+                    });
+                }
+            }
+            let (index, motion) = self.motions.next()?;
+            let positions = motion_points(motion, self.current_z, self.options);
+            let count = positions.len();
+            self.current = Some((index, motion, positions, count));
+        }
+    }
+}
+
+/// Function to calculate timestamped positions for a whole program
+///
+/// Each sample is reached after travelling the straight-line distance from the
+/// previous sample at the feed rate of the motion it belongs to, or at
+/// [`InterpolationOptions::rapid_rate`] for rapid moves. The first sample is
+/// at time zero.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program, in order
+/// * `options` - Interpolation options
+///
+/// # Errors
+///
+/// Returns `CmmdError::MissingFeedRate` for the first motion (counted from
+/// zero) other than a rapid move that has no positive feed rate.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::trajectory::timed_positions;
+///
+/// // 10 units at 600 units/min takes one second
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(600.0))];
+/// let samples = timed_positions(&motions, &Default::default()).unwrap();
+/// assert_eq!(samples.last().unwrap().time, 1.0);
+/// ```
+pub fn timed_positions(motions: &[Motion], options: &InterpolationOptions) -> Result<Vec<TimedPosition>, CmmdError> {
+    let mut samples: Vec<TimedPosition> = Vec::new();
+    Trajectory::new(motions, options).observe_timed(&mut |point: &Point| {
+        samples.push(TimedPosition {
+            time: point.time.expect("timed points have a time"),
+            position: point.position,
+            motion: point.motion,
+            power: point.power,
+            rotary: point.rotary,
+            extrusion: point.extrusion,
+        })
+    })?;
+    Ok(samples)
+}
+
+/// How fast the tool is moving and speeding up at a timed sample, per axis and overall
+///
+/// Velocities are in units per second and accelerations in units per second
+/// squared, whatever the feed rates were programmed in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Dynamics {
+    /// Speed along the path
+    pub speed: f64,
+    /// Velocity along each axis
+    pub velocity: (f64, f64, f64),
+    /// Size of the acceleration
+    pub acceleration: f64,
+    /// Acceleration along each axis
+    pub axis_acceleration: (f64, f64, f64),
+}
+
+/// Function to find the velocity and acceleration at every sample of a timed trajectory
+///
+/// Each sample gets the velocity over the step leading up to it, which is
+/// the true velocity halfway along that step. Its acceleration is the change
+/// from the previous step's velocity, over the time between the middles of
+/// the two steps. The first sample is at rest, and steps that take no time,
+/// such as where one motion hands over to the next, keep the velocity they
+/// started with.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::trajectory::{dynamics, timed_positions};
+///
+/// // 10 units at 600 units/min is 10 units/s, reached in the first step
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (0.0, 10.0, 0.0)).with_feed(Some(600.0))];
+/// let samples = timed_positions(&motions, &Default::default()).unwrap();
+/// let dynamics = dynamics(&samples);
+/// assert!((dynamics.last().unwrap().speed - 10.0).abs() < 1e-9);
+/// assert!((dynamics.last().unwrap().velocity.1 - 10.0).abs() < 1e-9);
+/// ```
+pub fn dynamics(samples: &[TimedPosition]) -> Vec<Dynamics> {
+    let mut states = Vec::with_capacity(samples.len());
+    let mut previous = Dynamics::default();
+    // How long the previous step took, none at the start where the tool is at rest
+    let mut previous_elapsed = 0.0;
+    for (index, sample) in samples.iter().enumerate() {
+        if index > 0 {
+            let before = &samples[index - 1];
+            let elapsed = sample.time - before.time;
+            if elapsed > 1e-12 {
+                let rate = |from: f64, to: f64, time: f64| (to - from) / time;
+                let (from, to) = (before.position, sample.position);
+                let velocity = (rate(from.0, to.0, elapsed), rate(from.1, to.1, elapsed), rate(from.2, to.2, elapsed));
+                let (was, now, between) = (previous.velocity, velocity, (previous_elapsed + elapsed) / 2.0);
+                let axis_acceleration = (
+                    rate(was.0, now.0, between),
+                    rate(was.1, now.1, between),
+                    rate(was.2, now.2, between),
+                );
+                previous_elapsed = elapsed;
+                previous = Dynamics {
+                    speed: distance((0.0, 0.0, 0.0), velocity),
+                    velocity,
+                    acceleration: distance((0.0, 0.0, 0.0), axis_acceleration),
+                    axis_acceleration,
+                };
+            } else {
+                previous.acceleration = 0.0;
+                previous.axis_acceleration = (0.0, 0.0, 0.0);
+            }
+        }
+        states.push(previous);
+    }
+    states
+}
+
+/// Function to resample a timed trajectory at a fixed rate, as servo controllers take it
+///
+/// Positions, rotary angles and extruder positions are read off the straight line between the
+/// samples on either side; each new sample belongs to the motion, and has the
+/// power, of the sample it is heading towards.
+///
+/// # Arguments
+///
+/// * `samples` - Timed positions, in order of time
+/// * `rate` - Samples per second
+///
+/// # Returns
+///
+/// One sample every `1 / rate` seconds from time zero, the last one at or
+/// just after the end of the trajectory and holding its final position.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::trajectory::{resample, timed_positions};
+///
+/// // One second of travel at 4 Hz gives 5 samples, 2.5 units apart
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_feed(Some(600.0))];
+/// let samples = resample(&timed_positions(&motions, &Default::default()).unwrap(), 4.0);
+/// assert_eq!(samples.len(), 5);
+/// assert_eq!(samples[1].position, (2.5, 0.0, 0.0));
+/// ```
+pub fn resample(samples: &[TimedPosition], rate: f64) -> Vec<TimedPosition> {
+    let Some(last) = samples.last() else {
+        return Vec::new();
+    };
+    // Stop a hair early so rounding doesn't add a sample past an end on the dot
+    let count = (last.time * rate - 1e-9).ceil().max(0.0) as usize + 1;
+    let mut next = 0;
+    (0..count)
+        .map(|period| {
+            let time = period as f64 / rate;
+            // The first sample at or after this time
+            while next < samples.len() - 1 && samples[next].time < time {
+                next += 1;
+            }
+            let to = samples[next];
+            let from = samples[next.saturating_sub(1)];
+            let span = to.time - from.time;
+            let fraction = if span > 0.0 { ((time - from.time) / span).clamp(0.0, 1.0) } else { 1.0 };
+            let lerp = |a: f64, b: f64| a + (b - a) * fraction;
+            let rotary = match (from.rotary, to.rotary) {
+                (Some(a), Some(b)) => Some(Rotary {
+                    a: lerp(a.a, b.a),
+                    b: lerp(a.b, b.b),
+                    c: lerp(a.c, b.c),
+                }),
+                (_, rotary) => rotary,
+            };
+            let extrusion = match (from.extrusion, to.extrusion) {
+                (Some(a), Some(b)) => Some(lerp(a, b)),
+                (_, extrusion) => extrusion,
+            };
+            TimedPosition {
+                time,
+                position: (
+                    lerp(from.position.0, to.position.0),
+                    lerp(from.position.1, to.position.1),
+                    lerp(from.position.2, to.position.2),
+                ),
+                motion: to.motion,
+                power: to.power,
+                rotary,
+                extrusion,
+            }
+        })
+        .collect()
+}
+
+// Straight-line distance between two points
+pub(crate) fn distance(a: (f64, f64, f64), b: (f64, f64, f64)) -> f64 {
+    ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2) + (b.2 - a.2).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test timing across linear and arc motions with different feeds.
+    #[test]
+    fn test_timed_positions() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (6.0, 0.0, 0.0)).with_feed(Some(60.0)),
+            Motion::new_rotational((0.0, 0.0), 6.0, 0.0, 180.0).with_feed(Some(120.0)),
+        ];
+        let samples = timed_positions(&motions, &InterpolationOptions::default()).unwrap();
+
+        // 6 units at 1 unit/s
+        let line_end = samples.iter().position(|sample| sample.time >= 6.0 - 1e-9).unwrap();
+        assert_eq!(samples[line_end].position, (6.0, 0.0, 0.0));
+
+        // Half a circle of radius 6 at 2 units/s, measured along the chords
+        let total = samples.last().unwrap().time;
+        assert!((total - 6.0 - 6.0 * std::f64::consts::PI / 2.0).abs() < 1e-3);
+        assert!(samples.windows(2).all(|pair| pair[1].time >= pair[0].time));
+    }
+
+    /// Test that the lazy points match the positions of each motion, with flat arcs at the last height.
+    #[test]
+    fn test_points() {
+        let motions = vec![
+            Motion::new_linear((6.0, 0.0, 0.0), (6.0, 0.0, -2.0)),
+            Motion::new_rotational((0.0, 0.0), 6.0, 0.0, 90.0).with_power(Some(100.0)),
+        ];
+        let options = InterpolationOptions::default();
+        let points: Vec<Point> = Trajectory::new(&motions, &options).points().collect();
+
+        let mut expected = motion_positions(&motions[0], 0.0, &options);
+        expected.extend(motion_positions(&motions[1], -2.0, &options));
+        assert_eq!(points.iter().map(|point| point.position).collect::<Vec<_>>(), expected);
+        assert!(points.iter().all(|point| (point.motion == 1) == (point.power == Some(100.0))));
+        assert_eq!(points.last().unwrap().position.2, -2.0);
+    }
+
+    /// Test that parallel interpolation gives the same points in the same order.
+    #[test]
+    fn test_par_points() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, -1.0)),
+            Motion::new_rotational((0.0, 0.0), 10.0, 0.0, 180.0),
+            Motion::new_linear((-10.0, 0.0, -1.0), (-10.0, 0.0, 3.0)),
+            Motion::new_rotational((0.0, 0.0), 10.0, -180.0, 90.0),
+            Motion::new_rapid((0.0, 10.0, 3.0), (0.0, 0.0, 3.0)),
+        ];
+        let options = InterpolationOptions::default();
+        let trajectory = Trajectory::new(&motions, &options);
+        let expected: Vec<Point> = trajectory.points().collect();
+        for threads in [0, 1, 2, 3, 8] {
+            assert_eq!(trajectory.par_points(threads), expected);
+        }
+    }
+
+    /// Test that resampled positions are evenly timed, follow the path and hold at the end.
+    #[test]
+    fn test_resample() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (6.0, 0.0, 0.0)).with_feed(Some(60.0)),
+            Motion::new_linear((6.0, 0.0, 0.0), (6.0, 3.0, 0.0)).with_feed(Some(180.0)),
+        ];
+        let samples = timed_positions(&motions, &InterpolationOptions::default()).unwrap();
+
+        // Seven seconds at 2 Hz, with the end on a period
+        let resampled = resample(&samples, 2.0);
+        assert_eq!(resampled.len(), 15);
+        assert!(resampled.iter().enumerate().all(|(period, sample)| sample.time == period as f64 / 2.0));
+        assert_eq!(resampled[13].position, (6.0, 1.5, 0.0));
+        assert_eq!(resampled[13].motion, 1);
+        assert_eq!(resampled[14].position, (6.0, 3.0, 0.0));
+
+        // An end between periods is held until the next one
+        let resampled = resample(&samples, 0.4);
+        assert_eq!(resampled.len(), 4);
+        assert_eq!((resampled[3].time, resampled[3].position), (7.5, (6.0, 3.0, 0.0)));
+        assert!(resample(&[], 1000.0).is_empty());
+    }
+
+    /// Test that velocities and accelerations follow a planned move that speeds up and slows down.
+    #[test]
+    fn test_dynamics() {
+        let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (30.0, 40.0, 0.0)).with_feed(Some(3000.0))];
+        let options = InterpolationOptions {
+            linear_step: 0.5,
+            ..Default::default()
+        };
+        let planner = crate::planner::PlannerOptions {
+            max_acceleration: 100.0,
+            ..Default::default()
+        };
+        let samples = crate::planner::plan(&motions, &options, &planner).unwrap();
+        let states = dynamics(&samples);
+        assert_eq!(states.len(), samples.len());
+        assert_eq!(states[0], Dynamics::default());
+        // Never faster than the feed rate, along the line from start to end
+        let fastest = states.iter().map(|state| state.speed).fold(0.0, f64::max);
+        assert!(fastest <= 50.0 + 1e-6 && fastest > 40.0);
+        assert!(states.iter().all(|state| (state.velocity.0 * 4.0 - state.velocity.1 * 3.0).abs() < 1e-6));
+        // Accelerating and braking within the limit
+        assert!(states.iter().all(|state| state.acceleration <= 100.0 + 1e-6));
+        assert!(states.iter().any(|state| state.axis_acceleration.1 < 0.0));
+    }
+
+    /// Test that motions without a feed rate can't be timed.
+    #[test]
+    fn test_missing_feed_rate() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(60.0)),
+            Motion::new_linear((1.0, 0.0, 0.0), (2.0, 0.0, 0.0)),
+        ];
+        let err = timed_positions(&motions, &InterpolationOptions::default()).unwrap_err();
+        assert!(matches!(err, CmmdError::MissingFeedRate { motion: 1 }));
+    }
+
+    /// Test that rapid moves run at the rapid rate without a feed rate.
+    #[test]
+    fn test_rapid_rate() {
+        let motions = vec![Motion::new_rapid((0.0, 0.0, 0.0), (100.0, 0.0, 0.0))];
+        let options = InterpolationOptions {
+            rapid_rate: 6000.0,
+            ..Default::default()
+        };
+        let samples = timed_positions(&motions, &options).unwrap();
+        assert!((samples.last().unwrap().time - 1.0).abs() < 1e-9);
+    }
+
+    /// Test that events such as tool changes need no feed rate and take no time, planned or not.
+    #[test]
+    fn test_events() {
+        let motions = vec![
+            Motion::new_tool_change(1, (0.0, 0.0, 0.0)),
+            Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_feed(Some(60.0)),
+            Motion::new_tool_change(2, (1.0, 0.0, 0.0)),
+            Motion::new_linear((1.0, 0.0, 0.0), (2.0, 0.0, 0.0)).with_feed(Some(60.0)),
+        ];
+        let samples = timed_positions(&motions, &InterpolationOptions::default()).unwrap();
+        assert!(samples.iter().all(|sample| sample.motion % 2 == 1));
+        assert!((samples.last().unwrap().time - 2.0).abs() < 1e-9);
+
+        let options = crate::planner::PlannerOptions { max_acceleration: 10.0, ..Default::default() };
+        let planned = crate::planner::plan(&motions, &InterpolationOptions::default(), &options).unwrap();
+        assert_eq!(planned.len(), samples.len());
+        // The machine stops for the tool change, so it takes longer than going straight through
+        let straight = [motions[1].clone(), motions[3].clone()];
+        let through = crate::planner::plan(&straight, &InterpolationOptions::default(), &options).unwrap();
+        assert!(planned.last().unwrap().time > through.last().unwrap().time);
+
+        // Pauses hold up everything after them by the time they're expected to take
+        let mut paused = motions.clone();
+        paused[2] = Motion::new_pause(false, (1.0, 0.0, 0.0));
+        let options = InterpolationOptions { pause_time: 10.0, ..Default::default() };
+        let samples = timed_positions(&paused, &options).unwrap();
+        assert!((samples.last().unwrap().time - 12.0).abs() < 1e-9);
+    }
+}
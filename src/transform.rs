@@ -0,0 +1,707 @@
+//! Placing a whole program somewhere else
+//!
+//! A [`Transform`] moves, turns, scales and mirrors every motion of a program,
+//! so a part can be put anywhere on the table without editing it. Each step
+//! is added after the ones before it, so
+//! `Transform::default().scale(2.0).translate((10.0, 0.0, 0.0))` doubles the
+//! program's size and then moves it 10 along X.
+//!
+//! Motions keep their kind: lines stay lines, Bézier curves and splines move
+//! their control points, and arcs move their center and angles. Mirroring
+//! reverses an arc's direction, as it does on the machine. Arcs lie in the XY,
+//! XZ or YZ plane, so an XZ or YZ arc can only be turned about Z by a multiple
+//! of 90°, which carries it into one of those planes again. Arcs also have to
+//! be scaled by the same factor along both axes of their plane, or they would
+//! become ellipses.
+//!
+//! [`array`] repeats a whole program on a grid, for cutting many identical
+//! parts from one sheet, and [`restart`] cuts the start off a program to pick
+//! up an interrupted job part way through.
+
+use std::str::FromStr;
+
+use crate::error::DistortedArc;
+use crate::motion::{
+    AuxCommand, BezierMotion, LinearMotion, Motion, OrientedMotion, Pause, Plane, RotationalMotion, SplineMotion,
+    ToolChange,
+};
+use crate::post::arc_move;
+
+const EPSILON: f64 = 1e-9;
+
+type Point = (f64, f64, f64);
+
+/// An axis to mirror across
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+impl FromStr for Axis {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "x" => Ok(Axis::X),
+            "y" => Ok(Axis::Y),
+            "z" => Ok(Axis::Z),
+            _ => Err(format!("Unknown axis {}, expected x, y or z", name)),
+        }
+    }
+}
+
+/// A move, turn, scale and mirror of the whole program, in any combination
+///
+/// The default transform leaves everything where it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    // Rows of the matrix applied to each point, before the offset is added
+    matrix: [[f64; 3]; 3],
+    offset: Point,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            offset: (0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Transform {
+    // Follow this transform with a linear map given by its rows
+    fn then(self, rows: [[f64; 3]; 3]) -> Self {
+        let apply = |(x, y, z): Point| {
+            let row = |r: [f64; 3]| r[0] * x + r[1] * y + r[2] * z;
+            (row(rows[0]), row(rows[1]), row(rows[2]))
+        };
+        // Map each column of the current matrix, then the offset
+        let columns = [0, 1, 2].map(|i| apply((self.matrix[0][i], self.matrix[1][i], self.matrix[2][i])));
+        let matrix = [
+            [columns[0].0, columns[1].0, columns[2].0],
+            [columns[0].1, columns[1].1, columns[2].1],
+            [columns[0].2, columns[1].2, columns[2].2],
+        ];
+        Transform {
+            matrix,
+            offset: apply(self.offset),
+        }
+    }
+
+    /// Move everything by `offset`
+    pub fn translate(self, offset: Point) -> Self {
+        Transform {
+            offset: (self.offset.0 + offset.0, self.offset.1 + offset.1, self.offset.2 + offset.2),
+            ..self
+        }
+    }
+
+    /// Turn everything counter-clockwise about the Z axis, in degrees
+    pub fn rotate_z(self, degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        self.then([[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    /// Scale everything by `factor` about the origin
+    pub fn scale(self, factor: f64) -> Self {
+        self.scale_axes((factor, factor, factor))
+    }
+
+    /// Scale everything about the origin by a separate factor along each axis
+    pub fn scale_axes(self, factors: Point) -> Self {
+        self.then([[factors.0, 0.0, 0.0], [0.0, factors.1, 0.0], [0.0, 0.0, factors.2]])
+    }
+
+    /// Mirror everything across the plane through the origin where `axis` is zero
+    pub fn mirror(self, axis: Axis) -> Self {
+        let mut rows = Transform::default().matrix;
+        let index = match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        };
+        rows[index][index] = -1.0;
+        self.then(rows)
+    }
+
+    /// Where the transform takes a point
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::transform::{Axis, Transform};
+    ///
+    /// let transform = Transform::default().mirror(Axis::X).translate((10.0, 0.0, 5.0));
+    /// assert_eq!(transform.point((1.0, 2.0, 3.0)), (9.0, 2.0, 8.0));
+    /// ```
+    pub fn point(&self, point: Point) -> Point {
+        let (x, y, z) = self.vector(point);
+        (x + self.offset.0, y + self.offset.1, z + self.offset.2)
+    }
+
+    /// The transform that undoes this one, or `None` if it flattens everything onto a plane
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use project_2::transform::Transform;
+    ///
+    /// let transform = Transform::default().rotate_z(90.0).scale(2.0).translate((10.0, 0.0, 0.0));
+    /// let back = transform.inverse().unwrap().point(transform.point((1.0, 2.0, 3.0)));
+    /// assert!((back.0 - 1.0).abs() < 1e-12 && (back.1 - 2.0).abs() < 1e-12 && (back.2 - 3.0).abs() < 1e-12);
+    /// assert_eq!(Transform::default().scale(0.0).inverse(), None);
+    /// ```
+    pub fn inverse(&self) -> Option<Self> {
+        let m = self.matrix;
+        // Cofactors of the matrix, transposed, over its determinant
+        let cofactor = |r: usize, c: usize| {
+            let (r1, r2, c1, c2) = ((r + 1) % 3, (r + 2) % 3, (c + 1) % 3, (c + 2) % 3);
+            m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+        };
+        let determinant: f64 = (0..3).map(|c| m[0][c] * cofactor(0, c)).sum();
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let matrix = [0, 1, 2].map(|r| [0, 1, 2].map(|c| cofactor(c, r) / determinant));
+        let inverse = Transform {
+            matrix,
+            offset: (0.0, 0.0, 0.0),
+        };
+        // Undo the offset, then the matrix
+        let (x, y, z) = inverse.vector(self.offset);
+        Some(inverse.translate((-x, -y, -z)))
+    }
+
+    // Where the transform takes a direction, which the offset doesn't move
+    fn vector(&self, (x, y, z): Point) -> Point {
+        let row = |r: [f64; 3]| r[0] * x + r[1] * y + r[2] * z;
+        (row(self.matrix[0]), row(self.matrix[1]), row(self.matrix[2]))
+    }
+
+    /// Function to transform one motion
+    ///
+    /// # Returns
+    ///
+    /// The motion in its new place, or `None` for an arc the transform would
+    /// turn out of the XY, XZ and YZ planes or stretch into an ellipse.
+    pub fn motion(&self, motion: &Motion) -> Option<Motion> {
+        let transformed = match motion {
+            Motion::Linear(linear_motion) => Motion::Linear(LinearMotion {
+                start: self.point(linear_motion.start),
+                end: self.point(linear_motion.end),
+                ..linear_motion.clone()
+            }),
+            Motion::Rotational(rotational_motion) => Motion::Rotational(self.arc(rotational_motion)?),
+            Motion::Bezier(bezier_motion) => Motion::Bezier(BezierMotion {
+                start: self.point(bezier_motion.start),
+                control1: self.point(bezier_motion.control1),
+                control2: self.point(bezier_motion.control2),
+                end: self.point(bezier_motion.end),
+                ..bezier_motion.clone()
+            }),
+            // Splines, rational ones too, follow their control points
+            Motion::Spline(spline_motion) => Motion::Spline(SplineMotion {
+                controls: spline_motion.controls.iter().map(|&control| self.point(control)).collect(),
+                ..spline_motion.clone()
+            }),
+            // The tool's direction turns and flips with the program, staying a unit vector
+            Motion::Oriented(oriented_motion) => {
+                let direction = |vector: Point| {
+                    let (x, y, z) = self.vector(vector);
+                    let length = x.hypot(y).hypot(z);
+                    (x / length, y / length, z / length)
+                };
+                let (from, to) = oriented_motion.orientation;
+                Motion::Oriented(OrientedMotion {
+                    start: self.point(oriented_motion.start),
+                    end: self.point(oriented_motion.end),
+                    orientation: (direction(from), direction(to)),
+                    ..oriented_motion.clone()
+                })
+            }
+            Motion::ToolChange(tool_change) => Motion::ToolChange(ToolChange {
+                position: self.point(tool_change.position),
+                ..tool_change.clone()
+            }),
+            Motion::Aux(aux_command) => Motion::Aux(AuxCommand {
+                position: self.point(aux_command.position),
+                ..aux_command.clone()
+            }),
+            Motion::Pause(pause) => Motion::Pause(Pause {
+                position: self.point(pause.position),
+                ..pause.clone()
+            }),
+        };
+        Some(transformed)
+    }
+
+    // Transform an arc, unless it would stop being a circle in one of the planes arcs lie in
+    fn arc(&self, arc: &RotationalMotion) -> Option<RotationalMotion> {
+        // Where the arc's plane ends up, which has to stay square and evenly scaled
+        let first = self.vector(arc.plane.point((1.0, 0.0, 0.0)));
+        let second = self.vector(arc.plane.point((0.0, 1.0, 0.0)));
+        let scale = first.0.hypot(first.1).hypot(first.2);
+        let dot = first.0 * second.0 + first.1 * second.1 + first.2 * second.2;
+        let stretched = (second.0.hypot(second.1).hypot(second.2) - scale).abs() > EPSILON * scale;
+        if stretched || dot.abs() > EPSILON * scale * scale {
+            return None;
+        }
+        let plane = [Plane::Xy, Plane::Xz, Plane::Yz].into_iter().find(|plane| {
+            plane.coordinates(first).2.abs() < EPSILON * scale && plane.coordinates(second).2.abs() < EPSILON * scale
+        })?;
+
+        // The plane's axes turn by some angle, and a mirror also reverses the angles
+        let (a11, a21, _) = plane.coordinates(first);
+        let (a12, a22, _) = plane.coordinates(second);
+        let turn = a21.atan2(a11).to_degrees();
+        let mirrored = a11 * a22 - a12 * a21 < 0.0;
+
+        // The start angle turns with the plane, and a mirror also turns the sweep the other way round
+        let (start_angle, sweep) = if mirrored {
+            (turn - arc.start_angle, -arc.sweep)
+        } else {
+            (turn + arc.start_angle, arc.sweep)
+        };
+
+        // The center and end move like any point, and the height along the normal with them
+        let moved = |(first, second): (f64, f64), normal: f64| {
+            plane.coordinates(self.point(arc.plane.point((first, second, normal))))
+        };
+        let (x, y, _) = moved(arc.center, 0.0);
+        Some(RotationalMotion {
+            center: (x, y),
+            radius: arc.radius * scale,
+            start_angle,
+            sweep,
+            z: arc.z.map(|(start, stop)| (moved(arc.center, start).2, moved(arc.center, stop).2)),
+            end: arc.end.map(|end| {
+                let (x, y, _) = moved(end, 0.0);
+                (x, y)
+            }),
+            plane,
+            ..arc.clone()
+        })
+    }
+}
+
+/// Function to transform every motion of a program
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `transform` - Where to put them
+///
+/// # Errors
+///
+/// Returns a `DistortedArc` for the first arc the transform would turn out of
+/// the XY, XZ and YZ planes or stretch into an ellipse.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::transform::{transform, Transform};
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0))];
+/// let moved = transform(&motions, &Transform::default().rotate_z(90.0).translate((5.0, 5.0, 0.0))).unwrap();
+/// let Motion::Linear(line) = &moved[0] else { unreachable!() };
+/// assert!((line.end.0 - 5.0).abs() < 1e-9 && (line.end.1 - 15.0).abs() < 1e-9);
+/// ```
+pub fn transform(motions: &[Motion], transform: &Transform) -> Result<Vec<Motion>, DistortedArc> {
+    motions
+        .iter()
+        .enumerate()
+        .map(|(index, motion)| {
+            transform.motion(motion).ok_or(DistortedArc {
+                motion: index,
+                line: motion.line(),
+            })
+        })
+        .collect()
+}
+
+/// A grid of copies of a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Grid {
+    /// Number of copies along X
+    pub columns: usize,
+    /// Number of copies along Y
+    pub rows: usize,
+    /// Distance between neighbouring copies along X and Y
+    pub pitch: (f64, f64),
+}
+
+// Where a motion starts and ends, with flat arcs at the height the last one ended
+pub(crate) fn ends(motion: &Motion, current_z: f64) -> (Point, Point) {
+    match motion {
+        Motion::Linear(linear_motion) => (linear_motion.start, linear_motion.end),
+        Motion::Rotational(rotational_motion) => {
+            let arc = arc_move(rotational_motion, current_z);
+            (arc.start, arc.end)
+        }
+        Motion::Bezier(bezier_motion) => (bezier_motion.start, bezier_motion.end),
+        Motion::Spline(spline_motion) => (spline_motion.start(), spline_motion.end()),
+        Motion::Oriented(oriented_motion) => (oriented_motion.start, oriented_motion.end),
+        Motion::ToolChange(tool_change) => (tool_change.position, tool_change.position),
+        Motion::Aux(aux_command) => (aux_command.position, aux_command.position),
+        Motion::Pause(pause) => (pause.position, pause.position),
+    }
+}
+
+// Rapid moves lifting from one point to a clearance height, crossing over and dropping to another
+pub(crate) fn approach(from: Point, to: Point, clearance: f64) -> Vec<Motion> {
+    let waypoints = [from, (from.0, from.1, clearance), (to.0, to.1, clearance), to];
+    waypoints
+        .windows(2)
+        .filter(|pair| {
+            let (a, b) = (pair[0], pair[1]);
+            (b.0 - a.0).hypot(b.1 - a.1).hypot(b.2 - a.2) > EPSILON
+        })
+        .map(|pair| Motion::new_rapid(pair[0], pair[1]))
+        .collect()
+}
+
+/// Function to repeat a program on a grid
+///
+/// The first copy stays where the program is, and the others are moved by
+/// whole multiples of the pitch. Copies run along the first row in +X, back
+/// along the next row in -X, and so on, so the tool never crosses the whole
+/// sheet between copies. Between two copies the tool rises with rapid moves
+/// to the highest Z any motion starts or ends at, crosses over, and drops to
+/// where the next copy starts.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+/// * `grid` - How many copies to make and how far apart
+///
+/// # Returns
+///
+/// The copies joined by their travel moves, or no motions for an empty grid.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::transform::{array, Grid};
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0))];
+/// let grid = Grid { columns: 3, rows: 1, pitch: (20.0, 0.0) };
+/// // Three cuts, each joined to the next by a rapid move
+/// assert_eq!(array(&motions, &grid).len(), 5);
+/// ```
+pub fn array(motions: &[Motion], grid: &Grid) -> Vec<Motion> {
+    // The highest point of the program, and where it starts and ends
+    let mut clearance = f64::NEG_INFINITY;
+    let mut first = None;
+    let mut last = (0.0, 0.0, 0.0);
+    for motion in motions {
+        let (start, end) = ends(motion, last.2);
+        clearance = clearance.max(start.2).max(end.2);
+        first.get_or_insert(start);
+        last = end;
+    }
+    let Some(first) = first else {
+        return Vec::new();
+    };
+
+    let mut arrayed = Vec::with_capacity(motions.len() * grid.columns * grid.rows);
+    let mut previous: Option<Point> = None;
+    for row in 0..grid.rows {
+        for step in 0..grid.columns {
+            // Every other row runs backwards
+            let column = if row % 2 == 0 { step } else { grid.columns - 1 - step };
+            let offset = (column as f64 * grid.pitch.0, row as f64 * grid.pitch.1, 0.0);
+            let start = (first.0 + offset.0, first.1 + offset.1, first.2);
+
+            // Lift, cross over and drop to where this copy starts
+            if let Some(from) = previous {
+                arrayed.extend(approach(from, start, clearance));
+            }
+
+            let copy = Transform::default().translate(offset);
+            arrayed.extend(
+                motions
+                    .iter()
+                    .map(|motion| copy.motion(motion).expect("moving an arc keeps it in its plane")),
+            );
+            previous = Some((last.0 + offset.0, last.1 + offset.1, last.2));
+        }
+    }
+    arrayed
+}
+
+/// Where to pick a program up again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restart {
+    /// At the first motion from this line of the program or a later one
+    Line(usize),
+    /// At this motion, counted from zero
+    Motion(usize),
+}
+
+impl Restart {
+    /// Function to find the index of the motion a program picks up at, if there is one
+    pub fn find(&self, motions: &[Motion]) -> Option<usize> {
+        match *self {
+            Restart::Line(line) => motions.iter().position(|motion| motion.line().is_some_and(|at| at >= line)),
+            Restart::Motion(index) if index < motions.len() => Some(index),
+            Restart::Motion(_) => None,
+        }
+    }
+}
+
+/// Function to drop the start of a program, to carry on from part way through
+///
+/// Each motion already carries the feed rate, power, tool offset and
+/// coordinates that were in effect for it, so only the tool's position has to
+/// be brought up to date. The program begins with rapid moves from above
+/// where the job stopped, at the end of the motion before the restart, and at
+/// the highest Z any motion starts or ends at: across, and down to where the
+/// restart begins, with the power of the first motion so a spindle is running
+/// before it cuts. Starting at that height means the tool doesn't have to be
+/// moved back into the cut by hand. A flat arc at the restart is given the
+/// height it would have been cut at.
+///
+/// Tool changes and coolant aren't carried by the motions, so the last tool
+/// change before the restart and the coolant left on (`M7`, `M8`) are made
+/// again above where the job stopped, before the tool moves. User-defined
+/// M-codes aren't repeated, since what state they leave is unknown.
+///
+/// # Returns
+///
+/// The rest of the program, or `None` if there's no motion at or after the
+/// restart.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::transform::{restart, Restart};
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)).with_line(1),
+///     Motion::new_linear((10.0, 0.0, 0.0), (10.0, 10.0, 0.0)).with_line(2),
+/// ];
+/// let rest = restart(&motions, Restart::Line(2)).unwrap();
+/// assert_eq!(rest.last(), motions.last());
+/// assert!(restart(&motions, Restart::Motion(2)).is_none());
+/// ```
+pub fn restart(motions: &[Motion], from: Restart) -> Option<Vec<Motion>> {
+    let first = from.find(motions)?;
+
+    // Where the tool was before the restart, and the highest point of the program
+    let mut clearance = f64::NEG_INFINITY;
+    let mut last = (0.0, 0.0, 0.0);
+    let mut before = None;
+    // The tool and the coolant the skipped motions left in place
+    let mut tool = None;
+    let mut coolant: Vec<(u32, &AuxCommand)> = Vec::new();
+    for (index, motion) in motions.iter().enumerate() {
+        if index == first {
+            before = Some(last);
+        }
+        match motion {
+            Motion::ToolChange(tool_change) if index < first => tool = Some(tool_change),
+            Motion::Aux(aux_command) if index < first => {
+                let code = aux_command.code.split_whitespace().next().and_then(|word| word[1..].parse().ok());
+                match code {
+                    Some(9) => coolant.clear(),
+                    Some(code @ (7 | 8)) => {
+                        coolant.retain(|&(on, _)| on != code);
+                        coolant.push((code, aux_command));
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        let (start, end) = ends(motion, last.2);
+        clearance = clearance.max(start.2).max(end.2);
+        last = end;
+    }
+    let before = before.expect("the restart is within the program");
+
+    // A flat arc takes its height from the motion before it, which is gone
+    let mut resumed = motions[first].clone();
+    if let Motion::Rotational(rotational_motion) = &mut resumed {
+        if rotational_motion.z.is_none() {
+            rotational_motion.z = Some((before.2, before.2));
+        }
+    }
+    let (start, _) = ends(&resumed, before.2);
+
+    let mut rest = Vec::new();
+    if first > 0 {
+        let above = (before.0, before.1, clearance);
+        if let Some(tool_change) = tool {
+            rest.push(Motion::ToolChange(ToolChange {
+                position: above,
+                ..tool_change.clone()
+            }));
+        }
+        rest.extend(coolant.into_iter().map(|(_, aux_command)| {
+            Motion::Aux(AuxCommand {
+                position: above,
+                ..aux_command.clone()
+            })
+        }));
+        let power = resumed.power();
+        rest.extend(approach(above, start, clearance).into_iter().map(|motion| motion.with_power(power)));
+    }
+    rest.push(resumed);
+    rest.extend_from_slice(&motions[first + 1..]);
+    Some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Point, expected: Point) {
+        let distance = (actual.0 - expected.0).hypot(actual.1 - expected.1).hypot(actual.2 - expected.2);
+        assert!(distance < 1e-9, "{:?} != {:?}", actual, expected);
+    }
+
+    /// Test that turned and mirrored arcs start, end and run the way their transformed points do.
+    #[test]
+    fn test_arcs() {
+        // A counter-clockwise quarter circle from (10, 0) to (0, 10), rising 2
+        let arc = Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 10.0, 2.0), (-10.0, 0.0), false);
+        let Motion::Rotational(original) = &arc else { unreachable!() };
+        let original = arc_move(original, 0.0);
+
+        let transforms = [
+            Transform::default().rotate_z(30.0).translate((5.0, -5.0, 1.0)),
+            Transform::default().mirror(Axis::X).scale(0.5),
+            Transform::default().mirror(Axis::Z),
+        ];
+        for transform in transforms {
+            let Some(Motion::Rotational(moved)) = transform.motion(&arc) else { panic!("expected an arc") };
+            let moved = arc_move(&moved, 0.0);
+            assert_close(moved.start, transform.point(original.start));
+            assert_close(moved.end, transform.point(original.end));
+            assert!((moved.sweep - 90.0).abs() < 1e-9);
+        }
+
+        // Mirroring reverses the direction, turning doesn't
+        let direction = |transform: Transform| match transform.motion(&arc) {
+            Some(Motion::Rotational(moved)) => arc_move(&moved, 0.0).clockwise,
+            _ => panic!("expected an arc"),
+        };
+        assert!(!original.clockwise);
+        assert!(!direction(Transform::default().rotate_z(135.0)));
+        assert!(direction(Transform::default().mirror(Axis::Y)));
+    }
+
+    /// Test that copies snake along the rows, joined by lifts over the program.
+    #[test]
+    fn test_array() {
+        // Plunge and cut a line, ending below the start
+        let motions = vec![
+            Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)),
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, -1.0)),
+        ];
+        let grid = Grid {
+            columns: 2,
+            rows: 2,
+            pitch: (20.0, 30.0),
+        };
+        let arrayed = array(&motions, &grid);
+        // Four copies, and a rise and a crossing between each two; each copy starts at the clearance
+        assert_eq!(arrayed.len(), 4 * 2 + 3 * 2);
+
+        // Where each cut starts: the second row runs backwards
+        let cuts: Vec<Point> = arrayed
+            .iter()
+            .filter_map(|motion| match motion {
+                Motion::Linear(line) if !line.rapid => Some(line.start),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cuts, vec![(0.0, 0.0, 0.0), (20.0, 0.0, 0.0), (20.0, 30.0, 0.0), (0.0, 30.0, 0.0)]);
+
+        // The first crossing rises from the end of the cut to the top of the program
+        assert_eq!(arrayed[2], Motion::new_rapid((10.0, 0.0, -1.0), (10.0, 0.0, 5.0)));
+        assert_eq!(arrayed[3], Motion::new_rapid((10.0, 0.0, 5.0), (20.0, 0.0, 5.0)));
+        assert!(crate::validate::continuity(&arrayed).is_empty());
+
+        assert!(array(&motions, &Grid { columns: 0, ..grid }).is_empty());
+    }
+
+    /// Test that a restarted program climbs over to where it picks up, with flat arcs kept at their height.
+    #[test]
+    fn test_restart() {
+        let motions = vec![
+            Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, -1.0)).with_line(2),
+            Motion::new_rotational((0.0, 0.0), 10.0, 0.0, 90.0).with_power(Some(800.0)).with_line(3),
+            Motion::new_linear((0.0, 10.0, -1.0), (0.0, 0.0, -1.0)).with_line(4),
+        ];
+        let rest = restart(&motions, Restart::Line(3)).unwrap();
+        // Down from above the end of line 2, since the arc starts right below
+        assert!(rest[0].is_rapid() && rest[0].power() == Some(800.0));
+        assert_close(ends(&rest[0], 0.0).0, (10.0, 0.0, 5.0));
+        assert_close(ends(&rest[0], 0.0).1, (10.0, 0.0, -1.0));
+        let Motion::Rotational(arc) = &rest[1] else { panic!("expected the arc") };
+        assert_eq!(arc.z, Some((-1.0, -1.0)));
+        assert_eq!(rest.len(), 3);
+        assert!(crate::validate::continuity(&rest[..2]).is_empty());
+
+        // Counting motions instead, from the end of the arc
+        let rest = restart(&motions, Restart::Motion(3)).unwrap();
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0], Motion::new_rapid((0.0, 10.0, 5.0), (0.0, 10.0, -1.0)));
+
+        // The tool and the coolant still on come back before the travel, at the top
+        let mut events = vec![
+            Motion::new_tool_change(1, (0.0, 0.0, 5.0)).with_line(1),
+            Motion::new_aux("M8", (0.0, 0.0, 5.0)),
+            Motion::new_aux("M101 P2", (0.0, 0.0, 5.0)),
+            Motion::new_tool_change(2, (0.0, 0.0, 5.0)),
+            Motion::new_aux("M9", (0.0, 0.0, 5.0)),
+            Motion::new_aux("M7", (0.0, 0.0, 5.0)),
+        ];
+        events.extend(motions.iter().cloned());
+        let rest = restart(&events, Restart::Line(3)).unwrap();
+        assert_eq!(rest[0], Motion::new_tool_change(2, (10.0, 0.0, 5.0)));
+        assert_eq!(rest[1], Motion::new_aux("M7", (10.0, 0.0, 5.0)));
+        assert_eq!(rest[2], Motion::new_rapid((10.0, 0.0, 5.0), (10.0, 0.0, -1.0)).with_power(Some(800.0)));
+        assert_eq!(rest.len(), 5);
+
+        // Restarting at the beginning adds no travel
+        assert_eq!(restart(&motions, Restart::Motion(0)).unwrap(), motions);
+        assert_eq!(restart(&motions, Restart::Line(0)).unwrap(), motions);
+        assert!(restart(&motions, Restart::Line(5)).is_none());
+    }
+
+    /// Test turning XZ arcs into YZ arcs, and refusing to tilt or stretch them.
+    #[test]
+    fn test_arc_planes() {
+        let Motion::Rotational(mut arc) = Motion::new_arc_from_offset((10.0, 0.0, 0.0), (0.0, 10.0, 0.0), (-10.0, 0.0), true)
+        else {
+            unreachable!()
+        };
+        arc.plane = Plane::Xz;
+        arc.z = Some((3.0, 3.0));
+        let arc = Motion::Rotational(arc);
+        let Motion::Rotational(original) = &arc else { unreachable!() };
+        let original = arc_move(original, 0.0);
+
+        let transform = Transform::default().rotate_z(90.0);
+        let Some(Motion::Rotational(moved)) = transform.motion(&arc) else { panic!("expected an arc") };
+        assert_eq!(moved.plane, Plane::Yz);
+        let moved = arc_move(&moved, 0.0);
+        assert_close(moved.start, transform.point(original.start));
+        assert_close(moved.end, transform.point(original.end));
+
+        let tilted = super::transform(std::slice::from_ref(&arc), &Transform::default().rotate_z(45.0));
+        assert_eq!(tilted, Err(DistortedArc { motion: 0, line: None }));
+        // Stretching along the plane's normal is fine, along one of its axes isn't
+        assert!(Transform::default().scale_axes((1.0, 3.0, 1.0)).motion(&arc).is_some());
+        assert!(Transform::default().scale_axes((1.0, 1.0, 3.0)).motion(&arc).is_none());
+    }
+}
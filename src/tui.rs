@@ -0,0 +1,288 @@
+//! Terminal backplot viewer
+//!
+//! Draws the XY path of a program in the terminal with braille characters,
+//! for previewing paths over SSH where there's no image viewer at hand. The
+//! view can be zoomed and panned, and a cursor steps through the motions,
+//! highlighting one at a time and showing the line of the program it came
+//! from. Only built with the `tui` feature.
+//!
+//! - arrows or `h` `j` `k` `l` pan
+//! - `+` and `-` zoom in and out
+//! - `n` and `p`, or Tab and Shift-Tab, move the cursor to the next or previous motion
+//! - Home and End move the cursor to the first or last motion
+//! - `c` centers the view on the end of the cursor's motion
+//! - `r` fits the whole path in the view again
+//! - `q` or Esc quits
+
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::Color;
+use ratatui::symbols::Marker;
+use ratatui::widgets::canvas::{Canvas, Line, Points};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+
+use crate::interpolate::InterpolationOptions;
+use crate::motion::Motion;
+use crate::output::geometric_points;
+
+// Fraction of the view one key press pans by
+const PAN: f64 = 0.2;
+
+// How much one key press zooms by
+const ZOOM: f64 = 1.5;
+
+/// The state of the viewer: the path, the part of it in view and the motion the cursor is on
+#[derive(Debug, Clone)]
+pub struct Viewer {
+    // XY positions of each motion
+    paths: Vec<Vec<(f64, f64)>>,
+    // Color each motion is drawn in
+    colors: Vec<Color>,
+    // Line of the program each motion came from
+    lines: Vec<Option<usize>>,
+    // Middle of the whole path, and half the larger of its width and height
+    fit: ((f64, f64), f64),
+    // Middle of the view
+    center: (f64, f64),
+    // How many times larger than the whole path things are drawn
+    zoom: f64,
+    // The motion the cursor is on
+    selected: usize,
+}
+
+impl Viewer {
+    /// Constructor for a viewer showing the whole path, with the cursor on the first motion
+    pub fn new(motions: &[Motion], options: &InterpolationOptions) -> Viewer {
+        let mut paths = vec![Vec::new(); motions.len()];
+        for point in geometric_points(motions, options) {
+            paths[point.motion].push((point.position.0, point.position.1));
+        }
+        // Colored like the SVG and PNG backplots
+        let colors = motions
+            .iter()
+            .map(|motion| match motion {
+                _ if motion.is_rapid() => Color::DarkGray,
+                Motion::Linear(_) | Motion::Oriented(_) => Color::LightBlue,
+                Motion::Rotational(_) => Color::LightRed,
+                Motion::Bezier(_) | Motion::Spline(_) => Color::LightMagenta,
+                // Events have no path, so their color is never seen
+                Motion::ToolChange(_) | Motion::Aux(_) | Motion::Pause(_) => Color::Reset,
+            })
+            .collect();
+
+        let (mut min, mut max) = ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN));
+        for &(x, y) in paths.iter().flatten() {
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+        }
+        if min.0 > max.0 {
+            (min, max) = ((0.0, 0.0), (0.0, 0.0));
+        }
+        let middle = ((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0);
+        let half = ((max.0 - min.0).max(max.1 - min.1) / 2.0 * 1.05).max(1.0);
+
+        Viewer {
+            paths,
+            colors,
+            lines: motions.iter().map(Motion::line).collect(),
+            fit: (middle, half),
+            center: middle,
+            zoom: 1.0,
+            selected: 0,
+        }
+    }
+
+    // Half the width and height of the smaller side of the view, in program units
+    fn half(&self) -> f64 {
+        self.fit.1 / self.zoom
+    }
+
+    /// Function to find the part of the path in view on a canvas of braille cells
+    ///
+    /// A braille cell is two dots wide and four high, about the shape of a
+    /// terminal cell, so both axes share a scale and circles come out round.
+    ///
+    /// # Returns
+    ///
+    /// The X range and the Y range in view.
+    pub fn bounds(&self, columns: u16, rows: u16) -> ([f64; 2], [f64; 2]) {
+        let (width, height) = (f64::from(columns.max(1)) * 2.0, f64::from(rows.max(1)) * 4.0);
+        let (half_x, half_y) = if width >= height {
+            (self.half() * width / height, self.half())
+        } else {
+            (self.half(), self.half() * height / width)
+        };
+        (
+            [self.center.0 - half_x, self.center.0 + half_x],
+            [self.center.1 - half_y, self.center.1 + half_y],
+        )
+    }
+
+    /// Function to act on a key press
+    ///
+    /// # Returns
+    ///
+    /// `false` once the viewer should close.
+    pub fn key(&mut self, code: KeyCode) -> bool {
+        let last = self.paths.len().saturating_sub(1);
+        let step = self.half() * PAN;
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Left | KeyCode::Char('h') => self.center.0 -= step,
+            KeyCode::Right | KeyCode::Char('l') => self.center.0 += step,
+            KeyCode::Down | KeyCode::Char('j') => self.center.1 -= step,
+            KeyCode::Up | KeyCode::Char('k') => self.center.1 += step,
+            KeyCode::Char('+') | KeyCode::Char('=') => self.zoom *= ZOOM,
+            KeyCode::Char('-') => self.zoom /= ZOOM,
+            KeyCode::Char('n') | KeyCode::Tab => self.selected = (self.selected + 1).min(last),
+            KeyCode::Char('p') | KeyCode::BackTab => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = last,
+            KeyCode::Char('c') => {
+                if let Some(&(x, y)) = self.paths.get(self.selected).and_then(|path| path.last()) {
+                    self.center = (x, y);
+                }
+            }
+            KeyCode::Char('r') => (self.center, self.zoom) = (self.fit.0, 1.0),
+            _ => {}
+        }
+        true
+    }
+
+    /// Function to describe where the cursor and the view are
+    pub fn status(&self) -> String {
+        if self.paths.is_empty() {
+            return String::from("no motions | q quit");
+        }
+        let place = match self.lines[self.selected] {
+            Some(line) => format!("line {}", line),
+            None => String::from("no line"),
+        };
+        format!(
+            "motion {} of {}, {} | zoom {:.1}x | arrows pan, +/- zoom, n/p motion, c center, r reset, q quit",
+            self.selected + 1,
+            self.paths.len(),
+            place,
+            self.zoom
+        )
+    }
+
+    /// Function to draw the path and the status line into a frame
+    pub fn draw(&self, frame: &mut Frame) {
+        let [plot, status] = Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+        let (x_bounds, y_bounds) = self.bounds(plot.width.saturating_sub(2), plot.height.saturating_sub(2));
+
+        let canvas = Canvas::default()
+            .block(Block::bordered().title(" Backplot (XY) "))
+            .marker(Marker::Braille)
+            .x_bounds(x_bounds)
+            .y_bounds(y_bounds)
+            .paint(|ctx| {
+                // Each motion, preceded by a travel move if it starts somewhere new
+                let mut previous: Option<(f64, f64)> = None;
+                for (path, &color) in self.paths.iter().zip(&self.colors) {
+                    let (Some(&first), Some(&last)) = (path.first(), path.last()) else {
+                        continue;
+                    };
+                    if let Some(previous) = previous.filter(|previous| previous != &first) {
+                        ctx.draw(&Line::new(previous.0, previous.1, first.0, first.1, Color::DarkGray));
+                    }
+                    for pair in path.windows(2) {
+                        ctx.draw(&Line::new(pair[0].0, pair[0].1, pair[1].0, pair[1].1, color));
+                    }
+                    previous = Some(last);
+                }
+
+                // The cursor's motion on top of the rest, with its end points marked
+                ctx.layer();
+                if let Some(path) = self.paths.get(self.selected) {
+                    for pair in path.windows(2) {
+                        ctx.draw(&Line::new(pair[0].0, pair[0].1, pair[1].0, pair[1].1, Color::Yellow));
+                    }
+                    if let (Some(&first), Some(&last)) = (path.first(), path.last()) {
+                        ctx.draw(&Points::new(&[first, last], Color::White));
+                    }
+                }
+            });
+        frame.render_widget(canvas, plot);
+        frame.render_widget(Paragraph::new(self.status()), status);
+    }
+}
+
+/// Function to browse the XY path of a program in the terminal until the user quits
+///
+/// # Errors
+///
+/// Returns an I/O error if the terminal can't be set up, drawn to or read from.
+pub fn view(motions: &[Motion], options: &InterpolationOptions) -> io::Result<()> {
+    let mut viewer = Viewer::new(motions, options);
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| loop {
+        terminal.draw(|frame| viewer.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            // Some terminals report releases too
+            if key.kind == KeyEventKind::Press && !viewer.key(key.code) {
+                return Ok(());
+            }
+        }
+    })();
+    // Put the terminal back the way it was, even after an error
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Vec<Motion> {
+        vec![
+            Motion::new_rapid((0.0, 0.0, 5.0), (0.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((0.0, 0.0, 0.0), (20.0, 0.0, 0.0)).with_line(2),
+            Motion::new_linear((20.0, 0.0, 0.0), (20.0, 10.0, 0.0)).with_line(3),
+        ]
+    }
+
+    /// Test that the view fits the path, keeps both axes at one scale and follows zooming and panning.
+    #[test]
+    fn test_bounds() {
+        let mut viewer = Viewer::new(&square(), &Default::default());
+        // 40 columns by 20 rows is 80 by 80 dots
+        let (x, y) = viewer.bounds(40, 20);
+        assert!((x[0] + 0.5).abs() < 1e-9 && (x[1] - 20.5).abs() < 1e-9);
+        assert!((y[0] + 5.5).abs() < 1e-9 && (y[1] - 15.5).abs() < 1e-9);
+        // Twice as many columns shows twice as much X
+        let (x, _) = viewer.bounds(80, 20);
+        assert!((x[1] - x[0] - 42.0).abs() < 1e-9);
+
+        viewer.key(KeyCode::Char('+'));
+        viewer.key(KeyCode::Right);
+        let (x, _) = viewer.bounds(40, 20);
+        assert!((x[1] - x[0] - 14.0).abs() < 1e-9);
+        assert!(((x[0] + x[1]) / 2.0 - 11.4).abs() < 1e-9);
+        viewer.key(KeyCode::Char('r'));
+        assert_eq!(viewer.bounds(40, 20).0, [-0.5, 20.5]);
+    }
+
+    /// Test that the cursor steps through the motions without running off either end, and that q quits.
+    #[test]
+    fn test_keys() {
+        let mut viewer = Viewer::new(&square(), &Default::default());
+        assert!(viewer.status().starts_with("motion 1 of 3, line 1 |"));
+        for code in [KeyCode::Char('n'), KeyCode::Tab, KeyCode::Tab] {
+            assert!(viewer.key(code));
+        }
+        assert!(viewer.status().starts_with("motion 3 of 3, line 3 |"));
+        viewer.key(KeyCode::Char('c'));
+        assert_eq!(viewer.center, (20.0, 10.0));
+        viewer.key(KeyCode::Home);
+        viewer.key(KeyCode::Char('p'));
+        assert_eq!(viewer.selected, 0);
+        assert!(!viewer.key(KeyCode::Char('q')));
+
+        assert_eq!(Viewer::new(&[], &Default::default()).status(), "no motions | q quit");
+    }
+}
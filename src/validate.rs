@@ -0,0 +1,328 @@
+//! Validation passes
+//!
+//! Checks run on a parsed program before it is sent anywhere, so mistakes are
+//! caught at the desk instead of on the machine.
+
+use crate::error::{Discontinuity, Intrusion, LimitViolation, Unreachable};
+use crate::interpolate::InterpolationOptions;
+use crate::kinematics::Kinematics;
+use crate::machine::MachineConfig;
+use crate::motion::Motion;
+use crate::post::arc_move;
+use crate::trajectory::Trajectory;
+
+/// Function to check every interpolated point against the machine's travel limits
+///
+/// Each motion is reported at most once per axis and bound, at the point
+/// where it goes furthest past the limit. Axes without limits in the profile
+/// aren't checked.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program, in machine coordinates
+/// * `machine` - The machine profile with the travel limits
+/// * `options` - How motions are split into points
+///
+/// # Returns
+///
+/// The violations in program order, empty if the program stays in bounds.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::machine::MachineConfig;
+/// use project_2::motion::Motion;
+/// use project_2::validate::soft_limits;
+///
+/// let machine = MachineConfig::from_toml("[x]\nmin = 0\nmax = 100").unwrap();
+/// let motions = vec![Motion::new_linear((50.0, 0.0, 0.0), (120.0, 0.0, 0.0)).with_line(3)];
+/// let violations = soft_limits(&motions, &machine, &Default::default());
+/// assert_eq!(violations.len(), 1);
+/// assert_eq!(violations[0].to_string(), "line 3: motion 0 takes X to 120.000, past its max limit of 100");
+/// ```
+pub fn soft_limits(motions: &[Motion], machine: &MachineConfig, options: &InterpolationOptions) -> Vec<LimitViolation> {
+    let mut violations: Vec<LimitViolation> = Vec::new();
+    for point in Trajectory::new(motions, options).points() {
+        let (x, y, z) = point.position;
+        for ((axis, position), config) in [('X', x), ('Y', y), ('Z', z)].into_iter().zip(machine.axes()) {
+            // How far past each bound the point is, positive when it's out of bounds
+            let bounds = [
+                ("min", config.min, config.min.map(|min| min - position)),
+                ("max", config.max, config.max.map(|max| position - max)),
+            ];
+            for (bound, limit, excess) in bounds {
+                let (Some(limit), Some(excess)) = (limit, excess) else {
+                    continue;
+                };
+                if excess <= 1e-9 {
+                    continue;
+                }
+                // Keep only the worst point of each motion
+                let existing = violations.iter_mut().find(|violation| {
+                    violation.motion == point.motion && violation.axis == axis && violation.bound == bound
+                });
+                match existing {
+                    Some(violation) if (violation.position - limit).abs() < excess => violation.position = position,
+                    Some(_) => {}
+                    None => violations.push(LimitViolation {
+                        motion: point.motion,
+                        line: motions[point.motion].line(),
+                        axis,
+                        bound,
+                        limit,
+                        position,
+                    }),
+                }
+            }
+        }
+    }
+    violations
+}
+
+/// Function to find motions that take the tool into the machine's keep-out zones
+///
+/// The tool is followed along the straight lines between interpolated
+/// points, so a move that passes through a zone between two points is
+/// caught too. Each motion is reported at most once per zone, where it first
+/// enters it.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program, in machine coordinates
+/// * `machine` - The machine profile with the keep-out zones
+/// * `options` - How motions are split into points
+///
+/// # Returns
+///
+/// The intrusions in program order, empty if the program keeps out of every zone.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::machine::MachineConfig;
+/// use project_2::motion::Motion;
+/// use project_2::validate::keep_out;
+///
+/// let text = "[[keep_out]]\nname = \"clamp\"\nshape = \"cylinder\"\n\
+///             center = [50, 0]\nradius = 5\nbottom = -10\ntop = 10";
+/// let machine = MachineConfig::from_toml(text).unwrap();
+/// let motions = vec![Motion::new_rapid((0.0, 0.0, 5.0), (100.0, 0.0, 5.0)).with_line(4)];
+/// let intrusions = keep_out(&motions, &machine, &Default::default());
+/// assert_eq!(intrusions.len(), 1);
+/// assert_eq!(intrusions[0].to_string(), "line 4: motion 0 enters clamp at (45.000, 0.000, 5.000)");
+/// ```
+pub fn keep_out(motions: &[Motion], machine: &MachineConfig, options: &InterpolationOptions) -> Vec<Intrusion> {
+    let mut intrusions: Vec<Intrusion> = Vec::new();
+    // Skip interpolating the whole program when there's nothing to keep out of
+    if machine.keep_out.is_empty() {
+        return intrusions;
+    }
+    let mut previous: Option<(f64, f64, f64)> = None;
+    for point in Trajectory::new(motions, options).points() {
+        let to = point.position;
+        let from = previous.unwrap_or(to);
+        previous = Some(to);
+        for (index, zone) in machine.keep_out.iter().enumerate() {
+            let Some(along) = zone.entry(from, to) else {
+                continue;
+            };
+            let name = match zone.name() {
+                Some(name) => name.to_string(),
+                None => format!("keep-out zone {}", index + 1),
+            };
+            // Keep only where each motion first enters each zone
+            if intrusions.iter().any(|intrusion| intrusion.motion == point.motion && intrusion.zone == name) {
+                continue;
+            }
+            intrusions.push(Intrusion {
+                motion: point.motion,
+                line: motions[point.motion].line(),
+                zone: name,
+                position: (
+                    from.0 + (to.0 - from.0) * along,
+                    from.1 + (to.1 - from.1) * along,
+                    from.2 + (to.2 - from.2) * along,
+                ),
+            });
+        }
+    }
+    intrusions
+}
+
+/// Function to find motions that take the tool somewhere the machine's arm can't reach
+///
+/// Each motion is reported at most once, at its first point out of reach.
+/// Machines that move along their axes reach everywhere, so only arms like
+/// SCARA can fail this check.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program, in machine coordinates
+/// * `kinematics` - How the machine's motors move the tool
+/// * `options` - How motions are split into points
+///
+/// # Returns
+///
+/// The unreachable motions in program order, empty if the arm reaches every point.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::kinematics::{Elbow, Kinematics};
+/// use project_2::motion::Motion;
+/// use project_2::validate::reachable;
+///
+/// let arm = Kinematics::Scara { inner: 120.0, outer: 40.0, elbow: Elbow::Right };
+/// let motions = vec![Motion::new_linear((100.0, 0.0, 0.0), (0.0, 100.0, 0.0)).with_line(2)];
+/// // The straight line passes 71 from the shoulder, closer than the folded arm's 80
+/// let unreachable = reachable(&motions, &arm, &Default::default());
+/// assert_eq!(unreachable.len(), 1);
+/// assert_eq!(unreachable[0].line, Some(2));
+/// ```
+pub fn reachable(motions: &[Motion], kinematics: &Kinematics, options: &InterpolationOptions) -> Vec<Unreachable> {
+    let mut unreachable: Vec<Unreachable> = Vec::new();
+    // Skip interpolating the whole program when every point is reachable anyway
+    if !matches!(kinematics, Kinematics::Scara { .. }) {
+        return unreachable;
+    }
+    for point in Trajectory::new(motions, options).points() {
+        if kinematics.motors(point.position).is_some() {
+            continue;
+        }
+        if unreachable.last().map(|motion| motion.motion) != Some(point.motion) {
+            unreachable.push(Unreachable {
+                motion: point.motion,
+                line: motions[point.motion].line(),
+                position: point.position,
+            });
+        }
+    }
+    unreachable
+}
+
+/// Function to find motions that don't start where the previous one ended
+///
+/// The parser always continues from the current position, so gaps point at
+/// programs assembled by hand or by other tools, which would jump the tool
+/// across them. Arcs without a Z range are taken to start at the height the
+/// previous motion ended at.
+///
+/// # Arguments
+///
+/// * `motions` - The motions of the program
+///
+/// # Returns
+///
+/// The gaps in program order, empty if the path is continuous.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::motion::Motion;
+/// use project_2::validate::continuity;
+///
+/// let motions = vec![
+///     Motion::new_linear((0.0, 0.0, 0.0), (10.0, 0.0, 0.0)),
+///     Motion::new_linear((10.0, 5.0, 0.0), (0.0, 5.0, 0.0)).with_line(2),
+/// ];
+/// let gaps = continuity(&motions);
+/// assert_eq!(gaps.len(), 1);
+/// assert_eq!((gaps[0].motion, gaps[0].line), (1, Some(2)));
+/// ```
+pub fn continuity(motions: &[Motion]) -> Vec<Discontinuity> {
+    let mut gaps: Vec<Discontinuity> = Vec::new();
+    let mut previous: Option<(f64, f64, f64)> = None;
+    for (index, motion) in motions.iter().enumerate() {
+        let (start, end) = match motion {
+            Motion::Linear(linear_motion) => (linear_motion.start, linear_motion.end),
+            Motion::Rotational(rotational_motion) => {
+                let arc = arc_move(rotational_motion, previous.map_or(0.0, |position| position.2));
+                (arc.start, arc.end)
+            }
+            Motion::Bezier(bezier_motion) => (bezier_motion.start, bezier_motion.end),
+            Motion::Spline(spline_motion) => (spline_motion.start(), spline_motion.end()),
+            Motion::Oriented(oriented_motion) => (oriented_motion.start, oriented_motion.end),
+            Motion::ToolChange(tool_change) => (tool_change.position, tool_change.position),
+            Motion::Aux(aux_command) => (aux_command.position, aux_command.position),
+            Motion::Pause(pause) => (pause.position, pause.position),
+        };
+        if let Some(from) = previous {
+            let gap = ((start.0 - from.0).powi(2) + (start.1 - from.1).powi(2) + (start.2 - from.2).powi(2)).sqrt();
+            if gap > 1e-6 {
+                gaps.push(Discontinuity {
+                    motion: index,
+                    line: motion.line(),
+                    from,
+                    to: start,
+                });
+            }
+        }
+        previous = Some(end);
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that arcs are checked along their whole sweep, not just at their ends.
+    #[test]
+    fn test_arc_bulge() {
+        let machine = MachineConfig::from_toml("[y]\nmax = 8\n[z]\nmin = -5").unwrap();
+        // A half circle from (10, 0) to (-10, 0) through (0, 10)
+        let motions = vec![
+            Motion::new_arc_from_offset((10.0, 0.0, 0.0), (-10.0, 0.0, 0.0), (-10.0, 0.0), false).with_line(1),
+            Motion::new_linear((-10.0, 0.0, 0.0), (-10.0, 0.0, -4.0)).with_line(2),
+        ];
+        let violations = soft_limits(&motions, &machine, &InterpolationOptions::default());
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            (violations[0].motion, violations[0].axis, violations[0].bound),
+            (0, 'Y', "max")
+        );
+        assert!((violations[0].position - 10.0).abs() < 1e-6);
+    }
+
+    /// Test that moves passing through a zone between points are caught, once per motion and zone.
+    #[test]
+    fn test_keep_out_between_points() {
+        let text = "[[keep_out]]\nshape = \"box\"\nmin = [40, -1, -5]\nmax = [41, 1, 5]\n\
+                    [[keep_out]]\nname = \"clamp\"\nshape = \"cylinder\"\n\
+                    center = [0, 20]\nradius = 2\nbottom = 0\ntop = 5";
+        let machine = MachineConfig::from_toml(text).unwrap();
+        // Points every 25 along X step right over the thin box
+        let options = InterpolationOptions {
+            linear_step: 25.0,
+            ..InterpolationOptions::default()
+        };
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (100.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((100.0, 0.0, 0.0), (0.0, 0.0, 0.0)).with_line(2),
+            // Over the top of the clamp, then down beside it
+            Motion::new_rapid((0.0, 0.0, 0.0), (0.0, 20.0, 6.0)).with_line(3),
+            Motion::new_linear((0.0, 20.0, 6.0), (3.0, 20.0, 0.0)).with_line(4),
+        ];
+        let intrusions = keep_out(&motions, &machine, &options);
+        let found: Vec<_> = intrusions.iter().map(|intrusion| (intrusion.line, intrusion.zone.as_str())).collect();
+        assert_eq!(found, vec![(Some(1), "keep-out zone 1"), (Some(2), "keep-out zone 1"), (Some(4), "clamp")]);
+        assert!((intrusions[0].position.0 - 40.0).abs() < 1e-9);
+        assert!((intrusions[1].position.0 - 41.0).abs() < 1e-9);
+    }
+
+    /// Test that arcs joining their neighbours aren't reported as gaps.
+    #[test]
+    fn test_continuous_arcs() {
+        let motions = vec![
+            Motion::new_linear((0.0, 0.0, 2.0), (10.0, 0.0, 2.0)),
+            Motion::new_arc_from_offset((10.0, 0.0, 2.0), (0.0, 10.0, 2.0), (-10.0, 0.0), false),
+            Motion::new_linear((0.0, 10.0, 2.0), (0.0, 0.0, 2.0)),
+        ];
+        assert!(continuity(&motions).is_empty());
+
+        // Without the arc the tool would jump from (10, 0) to (0, 10)
+        let gaps = continuity(&[motions[0].clone(), motions[2].clone()]);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!((gaps[0].from, gaps[0].to), ((10.0, 0.0, 2.0), (0.0, 10.0, 2.0)));
+    }
+}
@@ -0,0 +1,215 @@
+//! Regression checks against stored output
+//!
+//! A program's output can be kept as a golden file, written once with
+//! `--format jsonl`, `--format json` or `--format binary`, and checked again
+//! later: [`read_expected`] reads the samples back from any of the three, and
+//! [`first_divergence`] compares them with what the program gives now, within
+//! a tolerance. That catches changes to a program as well as changes to the
+//! interpolator itself.
+
+use std::io::{self, Read};
+
+use serde_json::Value;
+
+use crate::binary::{BinaryReader, Sample, MAGIC};
+use crate::error::Divergence;
+use crate::motion::Motion;
+
+// Read three numbers from a JSON array
+fn triple(value: &Value) -> Option<(f64, f64, f64)> {
+    match value.as_array()?.as_slice() {
+        [x, y, z] => Some((x.as_f64()?, y.as_f64()?, z.as_f64()?)),
+        _ => None,
+    }
+}
+
+// Turn a JSON record into a sample, if it's a point record
+fn json_sample(record: &Value) -> io::Result<Option<Sample>> {
+    if record["type"] != "point" {
+        return Ok(None);
+    }
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid point record {}", record));
+    Ok(Some(Sample {
+        motion: record["motion"].as_u64().ok_or_else(invalid)? as usize,
+        position: triple(&record["position"]).ok_or_else(invalid)?,
+        time: record["time"].as_f64(),
+        velocity: triple(&record["velocity"]),
+    }))
+}
+
+/// Function to read the samples of stored output
+///
+/// The format is worked out from the content: binary output starts with its
+/// magic bytes, a JSON array with `[`, and anything else is read as JSON
+/// Lines. Only point records are kept from JSON.
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if the output can't be read as any of the
+/// three, or any error reading it.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::verify::read_expected;
+///
+/// let output = r#"{"type":"motion","index":0,"kind":"linear"}
+/// {"type":"point","motion":0,"line":1,"position":[1.0,2.0,3.0],"time":0.5}
+/// "#;
+/// let samples = read_expected(output.as_bytes()).unwrap();
+/// assert_eq!((samples[0].position, samples[0].time), ((1.0, 2.0, 3.0), Some(0.5)));
+/// ```
+pub fn read_expected<R: Read>(mut reader: R) -> io::Result<Vec<Sample>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if bytes.starts_with(&MAGIC) {
+        return BinaryReader::new(bytes.as_slice())?.collect();
+    }
+
+    let text = String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let records: Vec<Value> = if text.trim_start().starts_with('[') {
+        serde_json::from_str(&text)?
+    } else {
+        text.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?
+    };
+    let mut samples = Vec::new();
+    for record in &records {
+        samples.extend(json_sample(record)?);
+    }
+    Ok(samples)
+}
+
+// Whether two samples are within the tolerance of each other, in position,
+// and in time and velocity where both have them
+fn matches(actual: &Sample, expected: &Sample, tolerance: f64) -> bool {
+    let close = |a: (f64, f64, f64), b: (f64, f64, f64)| {
+        (a.0 - b.0).abs() <= tolerance && (a.1 - b.1).abs() <= tolerance && (a.2 - b.2).abs() <= tolerance
+    };
+    let times = match (actual.time, expected.time) {
+        (Some(actual), Some(expected)) => (actual - expected).abs() <= tolerance,
+        _ => true,
+    };
+    let velocities = match (actual.velocity, expected.velocity) {
+        (Some(actual), Some(expected)) => close(actual, expected),
+        _ => true,
+    };
+    close(actual.position, expected.position) && times && velocities
+}
+
+/// Function to find the first sample that differs from the expected output
+///
+/// Samples are compared in order, each value within `tolerance`. Times and
+/// velocities are only compared when both samples have them, and the motion
+/// indices aren't compared at all, so merging or splitting motions without
+/// moving the path isn't a difference.
+///
+/// # Arguments
+///
+/// * `motions` - The program's motions, to find the line of each sample
+/// * `actual` - The samples the program gives now
+/// * `expected` - The samples it gave before
+/// * `tolerance` - The largest difference in any value that still matches
+///
+/// # Returns
+///
+/// The first sample that differs, or is only in one of the two, or `None`
+/// if they all match.
+///
+/// # Examples
+///
+/// ```
+/// use project_2::binary::Sample;
+/// use project_2::motion::Motion;
+/// use project_2::verify::first_divergence;
+///
+/// let motions = vec![Motion::new_linear((0.0, 0.0, 0.0), (1.0, 0.0, 0.0)).with_line(4)];
+/// let sample = |x| Sample { motion: 0, position: (x, 0.0, 0.0), time: None, velocity: None };
+/// let actual = [sample(0.0), sample(1.0)];
+/// assert!(first_divergence(&motions, actual, [sample(0.0), sample(1.0000001)], 1e-6).is_none());
+/// let divergence = first_divergence(&motions, actual, [sample(0.0), sample(1.1)], 1e-6).unwrap();
+/// assert_eq!((divergence.sample, divergence.line), (1, Some(4)));
+/// ```
+pub fn first_divergence(
+    motions: &[Motion],
+    actual: impl IntoIterator<Item = Sample>,
+    expected: impl IntoIterator<Item = Sample>,
+    tolerance: f64,
+) -> Option<Divergence> {
+    let (mut actual, mut expected) = (actual.into_iter(), expected.into_iter());
+    let mut sample = 0;
+    loop {
+        let (next, wanted) = (actual.next(), expected.next());
+        let same = match (&next, &wanted) {
+            (Some(next), Some(wanted)) => matches(next, wanted, tolerance),
+            (None, None) => return None,
+            _ => false,
+        };
+        if !same {
+            // Attribute the sample to the motion the program is on, or was expected to be on
+            let motion = next.or(wanted).map(|sample| sample.motion);
+            return Some(Divergence {
+                sample,
+                motion,
+                line: motion.and_then(|motion| motions.get(motion)).and_then(Motion::line),
+                expected: wanted,
+                actual: next,
+            });
+        }
+        sample += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::write_binary;
+    use crate::output::{geometric_points, write_json, Format};
+
+    fn motions() -> Vec<Motion> {
+        vec![
+            Motion::new_linear((0.0, 0.0, 0.0), (2.0, 0.0, 0.0)).with_line(1),
+            Motion::new_linear((2.0, 0.0, 0.0), (2.0, 2.0, 0.0)).with_line(2),
+        ]
+    }
+
+    /// Test that output written in each format reads back as the same samples.
+    #[test]
+    fn test_read_expected() {
+        let motions = motions();
+        let points = geometric_points(&motions, &Default::default());
+        let samples: Vec<Sample> = points.iter().map(Sample::from).collect();
+        for format in [Format::Json, Format::JsonLines, Format::Binary] {
+            let mut output = Vec::new();
+            match format {
+                Format::Binary => write_binary(&mut output, points.clone(), Default::default()).unwrap(),
+                format => write_json(&mut output, &motions, points.clone(), format).unwrap(),
+            }
+            assert_eq!(read_expected(output.as_slice()).unwrap(), samples, "{:?}", format);
+        }
+        assert!(read_expected(&b"{\"type\":\"point\",\"motion\":0}\n"[..]).is_err());
+    }
+
+    /// Test that the first difference is found and traced to its line, including output that ends early or late.
+    #[test]
+    fn test_first_divergence() {
+        let motions = motions();
+        let samples: Vec<Sample> = geometric_points(&motions, &Default::default()).iter().map(Sample::from).collect();
+        assert!(first_divergence(&motions, samples.clone(), samples.clone(), 0.0).is_none());
+
+        let mut moved = samples.clone();
+        let last = moved.len() - 1;
+        moved[last].position.1 += 0.01;
+        let divergence = first_divergence(&motions, moved.clone(), samples.clone(), 0.001).unwrap();
+        assert_eq!((divergence.sample, divergence.motion, divergence.line), (last, Some(1), Some(2)));
+        assert!(first_divergence(&motions, moved, samples.clone(), 0.1).is_none());
+
+        let short = first_divergence(&motions, samples[..2].to_vec(), samples.clone(), 0.0).unwrap();
+        assert_eq!((short.sample, short.actual, short.line), (2, None, Some(1)));
+        assert!(short.to_string().ends_with("is missing from the output"));
+        let long = first_divergence(&motions, samples.clone(), samples[..2].to_vec(), 0.0).unwrap();
+        assert!(long.expected.is_none());
+    }
+}
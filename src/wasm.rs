@@ -0,0 +1,202 @@
+//! WebAssembly bindings for browser-based viewers
+//!
+//! Exposes the parser and interpolator to JavaScript through `wasm-bindgen`,
+//! so a visualizer in the browser draws exactly the points the CLI prints.
+//! Only built with the `wasm` feature. The library isn't a `cdylib` by
+//! default, since that can't be built without the standard library, so build
+//! one and generate the JavaScript glue with `wasm-bindgen`:
+//!
+//! ```text
+//! cargo rustc --lib --release --crate-type cdylib --features wasm --target wasm32-unknown-unknown
+//! wasm-bindgen --target web --out-dir pkg target/wasm32-unknown-unknown/release/project_2.wasm
+//! ```
+//!
+//! and then:
+//!
+//! ```js
+//! import init, { parse, interpolate, Options } from "./pkg/project_2.js";
+//!
+//! await init();
+//! const program = parse("LIN X0 Y0 Z0\nLIN X10 Y0 Z0\n");
+//! const motions = JSON.parse(program.motions());
+//! const options = new Options();
+//! options.step = 0.5;
+//! const points = interpolate(program, options); // Float64Array of x, y, z, x, y, z, ...
+//! ```
+//!
+//! `INCLUDE` is refused, since there are no files to read in a browser.
+
+use js_sys::Float64Array;
+use wasm_bindgen::prelude::*;
+
+use crate::interpolate::{ArcStep, InterpolationOptions};
+use crate::motion::Motion;
+use crate::parser::{read_reader, ParseOptions};
+use crate::trajectory::Trajectory;
+
+/// A parsed program, held on the WebAssembly side
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct Program {
+    motions: Vec<Motion>,
+}
+
+#[wasm_bindgen]
+impl Program {
+    /// Number of motions in the program
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.motions.len()
+    }
+
+    /// The motions as a JSON array, in the form `--format json` prints them
+    pub fn motions(&self) -> String {
+        serde_json::to_string(&self.motions).expect("motions always serialize")
+    }
+}
+
+/// How motions are split into points, with the CLI's defaults
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Largest distance, along any single axis, between linear motion points
+    pub step: f64,
+    /// Fixed angle between arc points, in degrees
+    pub angle_step: Option<f64>,
+    /// Largest distance between arc chords and the true arc, used instead of `angle_step`
+    pub tolerance: Option<f64>,
+}
+
+#[wasm_bindgen]
+impl Options {
+    /// Constructor for the default options
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Options {
+        Options::default()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            step: InterpolationOptions::default().linear_step,
+            angle_step: None,
+            tolerance: None,
+        }
+    }
+}
+
+impl TryFrom<Options> for InterpolationOptions {
+    type Error = String;
+
+    // Steps and tolerances set from JavaScript have to be positive and finite, as on the command line
+    fn try_from(options: Options) -> Result<Self, Self::Error> {
+        let check = |name: &str, value: f64| {
+            if value > 0.0 && value.is_finite() {
+                Ok(value)
+            } else {
+                Err(format!("{} needs a positive number", name))
+            }
+        };
+        let arc_step = match (options.tolerance, options.angle_step) {
+            (Some(tolerance), _) => ArcStep::ChordTolerance(check("tolerance", tolerance)?),
+            (None, Some(angle)) => ArcStep::Angle(check("angle_step", angle)?),
+            (None, None) => ArcStep::RadiusScaled,
+        };
+        Ok(InterpolationOptions {
+            linear_step: check("step", options.step)?,
+            arc_step,
+            ..Default::default()
+        })
+    }
+}
+
+// The points of every motion, flattened into x, y, z triples
+fn flat_points(motions: &[Motion], options: &InterpolationOptions) -> Vec<f64> {
+    Trajectory::new(motions, options)
+        .points()
+        .flat_map(|point| [point.position.0, point.position.1, point.position.2])
+        .collect()
+}
+
+/// Function to parse the text of a `.cmmd` program
+///
+/// # Errors
+///
+/// Throws the parse error's message if the program can't be parsed.
+#[wasm_bindgen]
+pub fn parse(text: &str) -> Result<Program, JsError> {
+    let options = ParseOptions {
+        includes: false,
+        ..Default::default()
+    };
+    match read_reader(text.as_bytes(), &options) {
+        Ok(motions) => Ok(Program { motions }),
+        Err(e) => Err(JsError::new(&e.to_string())),
+    }
+}
+
+/// Function to interpolate a parsed program
+///
+/// # Returns
+///
+/// The untimed points of every motion in order, flattened into x, y, z triples.
+///
+/// # Errors
+///
+/// Throws if a step or tolerance in the options isn't a positive number.
+#[wasm_bindgen]
+pub fn interpolate(program: &Program, options: &Options) -> Result<Float64Array, JsError> {
+    let options = InterpolationOptions::try_from(*options).map_err(|e| JsError::new(&e))?;
+    Ok(Float64Array::from(flat_points(&program.motions, &options).as_slice()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test that points are flattened into triples in order, using the options given.
+    #[test]
+    fn test_flat_points() {
+        let program = Program {
+            motions: read_reader("F600\nLIN X2 Y0 Z1\n".as_bytes(), &ParseOptions::default()).unwrap(),
+        };
+        assert_eq!(program.length(), 1);
+        assert!(program.motions().starts_with("[{"));
+
+        let options = Options { step: 0.5, ..Options::new() };
+        let points = flat_points(&program.motions, &options.try_into().unwrap());
+        // Six points half a unit or less apart along the move
+        assert_eq!(points.len(), 18);
+        assert_eq!(points[..3], [0.0, 0.0, 0.0]);
+        assert_eq!(points[15..], [2.0, 0.0, 1.0]);
+    }
+
+    /// Test that a tolerance takes precedence over a fixed angle step.
+    #[test]
+    fn test_options() {
+        let options = Options {
+            angle_step: Some(2.0),
+            tolerance: Some(0.01),
+            ..Options::new()
+        };
+        assert_eq!(InterpolationOptions::try_from(options).unwrap().arc_step, ArcStep::ChordTolerance(0.01));
+        let options = Options { tolerance: None, ..options };
+        assert_eq!(InterpolationOptions::try_from(options).unwrap().arc_step, ArcStep::Angle(2.0));
+    }
+
+    /// Test that steps and tolerances that aren't positive and finite are refused.
+    #[test]
+    fn test_bad_options() {
+        for options in [
+            Options { step: 0.0, ..Options::new() },
+            Options { step: f64::NAN, ..Options::new() },
+            Options { angle_step: Some(-2.0), ..Options::new() },
+            Options { tolerance: Some(f64::INFINITY), ..Options::new() },
+        ] {
+            assert!(InterpolationOptions::try_from(options).is_err(), "{:?}", options);
+        }
+        let err = InterpolationOptions::try_from(Options { step: 0.0, ..Options::new() }).unwrap_err();
+        assert_eq!(err, "step needs a positive number");
+    }
+}
@@ -0,0 +1,108 @@
+//! Exit codes and error output of the command-line tool
+//!
+//! Scripts and CI jobs rely on these: usage errors exit with 2, anything else
+//! that goes wrong with 1, and in both cases the message goes to stderr.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// Run the tool with these arguments
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_project-2")).args(args).output().unwrap()
+}
+
+// Write a program to a file of its own, named after the test using it
+fn program(name: &str, text: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("project-2-{}-{}", std::process::id(), name));
+    fs::write(&path, text).unwrap();
+    path
+}
+
+/// Test that check succeeds on a good program and fails on bad input, with the reason on stderr.
+#[test]
+fn test_check_exit_codes() {
+    let good = program("check.cmmd", "LIN X0 Y0 Z0\nLIN X1 Y0 Z0\n");
+    let output = run(&["check", good.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // A file the tool doesn't know how to read
+    let unknown = program("check.xyz", "LIN X0 Y0 Z0\n");
+    let output = run(&["check", unknown.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid file extension"));
+
+    // Flags that don't exist
+    let output = run(&["check", "--bogus", good.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown option --bogus"));
+
+    // A program that doesn't parse
+    let broken = program("broken.cmmd", "LIN X0 Y0 Z0\nBOGUS\n");
+    let output = run(&["check", broken.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    for path in [good, unknown, broken] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+/// Test that verify fails on output that differs, files it can't read and bad flags, with the reason on stderr.
+#[test]
+fn test_verify_exit_codes() {
+    let good = program("verify.cmmd", "LIN X0 Y0 Z0\nLIN X1 Y0 Z0\n");
+    let golden = std::env::temp_dir().join(format!("project-2-{}-golden.jsonl", std::process::id()));
+    fs::write(&golden, run(&["--format", "jsonl", good.to_str().unwrap()]).stdout).unwrap();
+    let output = run(&["verify", "--expected", golden.to_str().unwrap(), good.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // A program that no longer gives the stored output
+    let changed = program("changed.cmmd", "LIN X0 Y0 Z0\nLIN X2 Y0 Z0\n");
+    let output = run(&["verify", "--expected", golden.to_str().unwrap(), changed.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Diverged"));
+
+    // A program file the tool doesn't know how to read
+    let unknown = program("verify.xyz", "LIN X0 Y0 Z0\n");
+    let output = run(&["verify", "--expected", golden.to_str().unwrap(), unknown.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+
+    // Stored output that isn't there
+    let output = run(&["verify", "--expected", "missing.jsonl", good.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+
+    // Flags that don't exist, or no stored output to compare with
+    for args in [vec!["verify", "--bogus"], vec!["verify"]] {
+        let output = run(&[&args[..], &[good.to_str().unwrap()]].concat());
+        assert_eq!(output.status.code(), Some(2));
+        assert!(output.stdout.is_empty());
+    }
+
+    for path in [good, golden, changed, unknown] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+/// Test that diff fails when either version can't be read, rather than reporting no differences.
+#[test]
+fn test_diff_exit_codes() {
+    let old = program("old.cmmd", "LIN X0 Y0 Z0\nLIN X1 Y0 Z0\n");
+    let output = run(&["diff", old.to_str().unwrap(), old.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    // Either version may have an extension the tool doesn't know
+    let unknown = program("old.xyz", "LIN X0 Y0 Z0\n");
+    for (old, new) in [(&unknown, &old), (&old, &unknown)] {
+        let output = run(&["diff", old.to_str().unwrap(), new.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid file extension"));
+    }
+
+    for path in [old, unknown] {
+        fs::remove_file(path).unwrap();
+    }
+}